@@ -144,3 +144,8 @@ pub(crate) fn pop_off() {
         intr_on();
     }
 }
+
+/// Number of locks currently held on this cpu, i.e. the depth of push_off() nesting.
+pub fn locks_held() -> usize {
+    mycpu().noff as usize
+}
@@ -4,6 +4,7 @@ cfg_if::cfg_if! {
     if #[cfg(all(target_os = "none", feature = "ticket"))] {
         extern crate alloc;
         mod interrupt;
+        pub use interrupt::locks_held;
         pub mod mcslock;
         pub mod rwlock;
         pub use {rwlock::*, mcslock::*};
@@ -12,6 +13,7 @@ cfg_if::cfg_if! {
     } else if #[cfg(target_os = "none")] {
         extern crate alloc;
         mod interrupt;
+        pub use interrupt::locks_held;
         pub mod mcslock;
         pub mod rwlock;
         pub use {rwlock::*, mcslock::*};
@@ -1,42 +1,33 @@
-use std::{
-    env,
-    fs::{self, File},
-    io::{Result, Write},
-    path::{Path, PathBuf},
-};
+use std::{env, fs, path::PathBuf};
 
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
 
-    println!("cargo:rerun-if-changed=../fat.img");
-    insert_fs_img().unwrap();
-
     let ld = &PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("linker.ld");
     fs::write(ld, LINKER).unwrap();
     println!("cargo:rustc-link-arg=-T{}", ld.display());
+
+    // 编译时嵌入内核的 initramfs，是 newc 格式的 cpio 包
+    println!("cargo:rerun-if-env-changed=INITRAMFS");
+    let initramfs = &PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("initramfs.S");
+    let incbin = match env::var("INITRAMFS") {
+        Ok(path) if !path.is_empty() => {
+            let path = fs::canonicalize(&path).expect("INITRAMFS does not exist");
+            println!("cargo:rerun-if-changed={}", path.display());
+            format!("    .incbin \"{}\"\n", path.display())
+        }
+        _ => String::new(),
+    };
+    fs::write(initramfs, INITRAMFS_HEAD.to_owned() + &incbin + "initramfs_end:\n").unwrap();
 }
 
-fn insert_fs_img() -> Result<()> {
-    let mut f = File::create("src/fs.S").unwrap();
-    if !Path::new(IMG_PATH).exists() {
-        return Ok(());
-    }
-    writeln!(
-        f,
-        r#"
+const INITRAMFS_HEAD: &str = "\
     .section .data
-    .global img_start
-    .global img_end
     .align 12
-img_start:
-    .incbin "{}"
-img_end:"#,
-        IMG_PATH,
-    )?;
-    Ok(())
-}
-
-const IMG_PATH: &str = "../fat.img";
+    .global initramfs_start
+    .global initramfs_end
+initramfs_start:
+";
 
 const LINKER: &str = "\
 OUTPUT_ARCH(riscv)
@@ -1,3 +1,6 @@
+use crate::syscall::ErrorNo;
+use crate::sysctl::{SysctlValue, Tunable};
+use alloc::string::String;
 use core::fmt::Arguments;
 use log::*;
 
@@ -55,6 +58,20 @@ pub fn init_logger(level: LogLevel) -> Result<(), SetLoggerError> {
     set_logger(&LOGGER).map(|()| set_max_level(level))
 }
 
+/// /proc/sys/kernel/log_level，直接读写 log 库中的全局日志等级。
+/// 初始值是 init_logger 中设置的等级
+pub struct LogLevelSysctl;
+
+impl Tunable for LogLevelSysctl {
+    fn read(&self) -> String {
+        max_level().format()
+    }
+    fn write(&self, value: &str) -> Result<(), ErrorNo> {
+        set_max_level(LogLevel::parse(value).ok_or(ErrorNo::EINVAL)?);
+        Ok(())
+    }
+}
+
 struct SimpleLogger;
 impl Log for SimpleLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
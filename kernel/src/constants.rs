@@ -21,12 +21,10 @@ pub const CLOCK_FREQ: usize = if PLATFORM_SIFIVE { 100_0000 } else { 1250_0000 }
 pub const IS_SINGLE_CORE: bool = true;
 /// 是否在启动后暂停。如果为 true，则所有核都只启动，不进入用户程序
 pub const SPIN_LOOP_AFTER_BOOT: bool = false;
-/// 运行时有多少内核输出
+/// 启动时有多少内核输出。之后可以通过 /proc/sys/kernel/log_level 或 bootargs 中的 sysctl.kernel.log_level 修改
 pub const LOG_LEVEL: crate::console::LogLevel = crate::console::LogLevel::Error;
 //pub const LOG_LEVEL: crate::console::LogLevel = crate::console::LogLevel::Off; // 评测时使用这个等级
 
-/// 是否输出访存报错信息。这个信息会干扰到评测判定(换行问题)，但平时很有用
-pub const REPORT_PAGE_FAULT: bool = false;
 /// 页表中每页的大小
 pub const PAGE_SIZE: usize = 0x1000; // 4 KB
 /// 即 log2(PAGE_SIZE)
@@ -35,12 +33,16 @@ pub const PAGE_SIZE_BITS: usize = 0xc; // 4 KB = 2^12
 pub const KERNEL_STACK_SIZE: usize = 0x80_000; // 512 KB
 /// 内核堆的大小
 pub const KERNEL_HEAP_SIZE: usize = 0xc0_0000; // 12 MB
-/// 用户栈大小
+/// 用户栈的初始大小。栈在 page fault 时会向下增长，直到 RLIMIT_STACK
 pub const USER_STACK_SIZE: usize = 0x20_0000; // 2 MB // `lmbench_all lat_fs /var/tmp` 会默认访问到 0x3ffdfb08 
 /// 初始用户栈大小，用于存放 argc/argv/envs/auxv
 pub const USER_INIT_STACK_SIZE: usize = 0x4000; // 16 KB,
-/// 用户栈底位置。同时也是最开始的用户堆顶位置
+/// 用户栈初始的最低地址
 pub const USER_STACK_OFFSET: usize = 0x4000_0000 - USER_STACK_SIZE;
+/// 默认的 RLIMIT_STACK，即用户栈最多能增长到多大
+pub const USER_STACK_LIMIT_DEFAULT: usize = 0x80_0000; // 8 MB
+/// 用户栈向下增长时，和下面的其他内存段之间至少要留出的空隙
+pub const USER_STACK_GUARD_GAP: usize = 0x10_0000; // 1 MB
 /// 用户地址最大不能超过这个值
 pub const USER_VIRT_ADDR_LIMIT: usize = 0xFFFF_FFFF;
 /// 内核中虚拟地址相对于物理地址的偏移
@@ -56,40 +58,31 @@ pub const ORIGIN_USER_PROC_NAME: &str = "start";
 
 /// 最小的 tid(进程号) 是 0，最大的 pid 是 TID_LIMIT-1
 pub const TID_LIMIT: usize = 4096;
-/// 预设的文件描述符数量限制
-pub const FD_LIMIT_ORIGIN: usize = 256;
 /// 最大允许的文件描述符数量
 pub const FD_LIMIT_HARD: usize = 256;
-/// sys_pipe创建的管道的大小，单位为字节
-pub const PIPE_SIZE_LIMIT: usize = 0x40_000; // 64 KB
-/// socket 使用的 buffer 大小
-pub const SOCKET_BUFFER_SIZE_LIMIT: usize = 0x200000; // 2 MB
 
 /// 一段左闭右开的地址区间，.0 为左端点， .1 为右端点，
 pub struct AddrArea(pub usize, pub usize);
-/// 用于设备 MMIO 的内存段。这些地址会在页表中做恒等映射
-pub const MMIO_REGIONS: &[AddrArea] = &[AddrArea(0x10001000, 0x10002000)];
+/// 用于设备 MMIO 的内存段。这些地址会在页表中做恒等映射。
+/// - qemu virt 机器上共有 8 个 virtio 设备的位置，块设备在第一个，网卡在其他位置
+/// - PLIC 的优先级、使能寄存器，以及各个上下文的阈值和 claim 寄存器
+pub const MMIO_REGIONS: &[AddrArea] = &[
+    AddrArea(0x10001000, 0x10009000),
+    AddrArea(0x0c00_0000, 0x0c00_3000),
+    AddrArea(0x0c20_0000, 0x0c21_0000),
+];
 
 /// 是否是比赛评测。线上评测时要求OS像一个批处理系统一样工作，这可能导致内核不会直接去拿初始进程并运行
 pub const IS_TEST_ENV: bool = true;
-/// 测试环境下，文件系统镜像是否是由qemu引入
-pub const IS_PRELOADED_FS_IMG: bool = false;
-/// 文件系统镜像的大小。注意这个量和 fs-init 模块中 `/src/main.rs` 里生成镜像时的大小相同。
-/// 启动时会从 .data 段加载加载
-const LOCAL_FS_IMG_SIZE: usize = 0x100_0000; // 16MB
-/// 测试时的文件系统镜像大小。
-/// 注意因为这个文件太大，默认是已经被qemu加载好了，启动时不会加载
-const TEST_FS_IMG_SIZE: usize = 0x4000_0000; // 1GB
-/// 文件系统镜像大小。只有这个常量可以被其他文件使用，而上面两个不能
-pub const FS_IMG_SIZE: usize = if IS_PRELOADED_FS_IMG {
-    TEST_FS_IMG_SIZE
-} else {
-    LOCAL_FS_IMG_SIZE
-};
-/// 设备(sdcard)映射到内存的起始位置
-pub const DEVICE_START: usize = 0x9000_0000;
-/// 设备映射到内存的最后位置
-pub const DEVICE_END: usize = DEVICE_START + FS_IMG_SIZE;
+/// 是否在块设备上的根文件系统上叠加 tmpfs(overlayfs)。
+/// 打开时对根文件系统的修改都只在内存中，评测用的镜像不会被改动，每次启动时的文件系统都相同
+pub const ROOT_OVERLAY: bool = true;
+/// 块缓存最多缓存的块数。每块大小为 BLOCK_CACHE_UNIT 字节
+pub const BLOCK_CACHE_SIZE: usize = 0x800; // 8 MB
+/// 块缓存中每块的大小，是块设备上扇区大小的整数倍
+pub const BLOCK_CACHE_UNIT: usize = 0x1000; // 4 KB
+/// 块缓存未命中时，顺带预读之后的块数
+pub const BLOCK_READ_AHEAD: usize = 8;
 
 /// 文件系统的根目录，注意斜杠方向
 pub const ROOT_DIR: &str = "./";
@@ -97,11 +90,17 @@ pub const ROOT_DIR: &str = "./";
 pub const AT_FDCWD: i32 = -100;
 /// 无父进程
 pub const NO_PARENT: usize = usize::MAX;
-/// 临时文件的大小限制
-pub const TMP_SIZE_LIMIT: usize = 0x8_000; // 1 MB
 
 /// 如果 elf 的 phdr 指示 base 是 0(如 libc-test 的 libc.so)，则需要找一个非0的位置放置
 pub const ELF_BASE_RELOCATE: usize = 0x400_0000;
+/// 地址随机化时，重定位的 ELF 基址最多向后偏移多少页
+pub const ELF_BASE_RANDOM_PAGES: usize = 0x1000; // 16 MB
+/// 用户程序 mmap 时不指定地址的默认起始位置
+pub const USER_MMAP_BASE: usize = 0x1000_0000;
+/// 地址随机化时，mmap 起始位置最多向后偏移多少页
+pub const USER_MMAP_RANDOM_PAGES: usize = 0x4000; // 64 MB
+/// 地址随机化时，用户栈顶最多向下偏移多少页
+pub const USER_STACK_RANDOM_PAGES: usize = 0x10; // 64 KB
 
 /// signal 中用到的 bitset 长度。
 pub const SIGSET_SIZE_IN_BYTE: usize = 8;
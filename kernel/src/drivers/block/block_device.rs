@@ -1,8 +1,30 @@
+use crate::syscall::ErrorNo;
 use core::any::Any;
+
+/// 块设备上一个扇区的大小
+pub const BLOCK_SIZE: usize = 512;
+
 /// 读写块设备的规范
 pub trait BlockDevice: Send + Sync + Any {
+    /// 一次读多段连续的块。requests 中每项为 (起始块号, 缓冲区)，缓冲区的长度是 BLOCK_SIZE 的整数倍。
+    /// 全部读完才返回，其中任何一段出错都返回 EIO
+    fn read_blocks(&self, requests: &mut [(usize, &mut [u8])]) -> Result<(), ErrorNo>;
+    /// 一次写多段连续的块，参数和返回值同 read_blocks
+    fn write_blocks(&self, requests: &[(usize, &[u8])]) -> Result<(), ErrorNo>;
+    /// 设备上块的总数
+    fn num_blocks(&self) -> usize;
+    /// 设备在 PLIC 中的中断号。不使用中断的设备返回 None
+    fn irq(&self) -> Option<usize> {
+        None
+    }
+    /// 处理设备的中断
+    fn handle_irq(&self) {}
     ///读一个块到buf
-    fn read_block(&self, block_id: usize, buf: &mut [u8]);
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), ErrorNo> {
+        self.read_blocks(&mut [(block_id, buf)])
+    }
     ///写一个块
-    fn write_block(&self, block_id: usize, buf: &[u8]);
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), ErrorNo> {
+        self.write_blocks(&[(block_id, buf)])
+    }
 }
@@ -0,0 +1,277 @@
+//! 块设备的缓存
+//!
+//! 缓存以 BLOCK_CACHE_UNIT 为单位，每个缓存块对应设备上连续的若干扇区。
+//! - 读写都先经过缓存。写只修改缓存并标记为脏，在缓存块被换出或者 sync 时才写回设备
+//! - 缓存满时换出最久没有使用的块(LRU)
+//! - 读未命中时顺带预读之后的 BLOCK_READ_AHEAD 块，顺序读文件时可以少等几次设备
+//! - 设备读写出错时返回 EIO，出错的块保持原样：读不进缓存，脏块也不会被丢掉
+//! - 读写设备时不持有缓存的锁。正在读入的块记录在 busy 中，其他线程用到它们时等待；
+//!   换出的脏块在写回期间仍留在缓存里，可以继续读写
+//!
+//! 所有文件系统共用一个缓存(见 mod.rs 中的 BLOCK_CACHE)，按字节偏移读写，不需要关心扇区的边界
+
+use super::{BlockDevice, BLOCK_SIZE};
+use crate::constants::{BLOCK_CACHE_SIZE, BLOCK_CACHE_UNIT, BLOCK_READ_AHEAD};
+use crate::syscall::ErrorNo;
+use crate::task::{can_suspend_current_task, suspend_current_task};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
+use lock::{Mutex, MutexGuard};
+
+/// 每个缓存块包含的扇区数
+const SECTORS_PER_UNIT: usize = BLOCK_CACHE_UNIT / BLOCK_SIZE;
+
+/// 一个缓存块
+struct CachedBlock {
+    data: Vec<u8>,
+    /// 是否被修改过，换出时需要写回
+    dirty: bool,
+    /// 被修改的次数。写回期间块又被修改时，写回后不能标记为干净
+    version: usize,
+    /// 最近一次使用的时间戳，是 CacheInner.lru 中的键
+    stamp: usize,
+}
+
+struct CacheInner {
+    /// 缓存块编号到缓存块的映射
+    blocks: BTreeMap<usize, CachedBlock>,
+    /// 正在从设备读入的块，值是读入时取的时间戳，用来区分不同的读入。它们不在 blocks 中，用到它们的线程需要等待
+    busy: BTreeMap<usize, usize>,
+    /// 时间戳到缓存块编号的映射，最早的就是最久没有使用的块
+    lru: BTreeMap<usize, usize>,
+    /// 下一个时间戳
+    clock: usize,
+}
+
+/// 块设备的缓存
+pub struct BlockCache {
+    device: Arc<dyn BlockDevice>,
+    /// 设备的大小(字节)
+    size: usize,
+    /// 访问设备时不持有这个锁
+    inner: Mutex<CacheInner>,
+}
+
+impl BlockCache {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        let size = device.num_blocks() * BLOCK_SIZE;
+        Self {
+            device: device,
+            size: size,
+            inner: Mutex::new(CacheInner {
+                blocks: BTreeMap::new(),
+                busy: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+    /// 设备的大小(字节)
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    /// 从设备的 offset 处开始读到 buf，返回读到的字节数。只有读到设备末尾时才会少于 buf 的长度
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize, ErrorNo> {
+        let len = min(buf.len(), self.size.saturating_sub(offset));
+        let mut inner = self.inner.lock();
+        let mut pos = 0;
+        while pos < len {
+            let (id, start) = ((offset + pos) / BLOCK_CACHE_UNIT, (offset + pos) % BLOCK_CACHE_UNIT);
+            let count = min(BLOCK_CACHE_UNIT - start, len - pos);
+            if !inner.blocks.contains_key(&id) {
+                inner = self.load(inner, id, BLOCK_READ_AHEAD)?;
+                // 放锁期间块可能又被换出了，重新检查
+                continue;
+            }
+            let block = inner.touch(id);
+            buf[pos..pos + count].copy_from_slice(&block.data[start..start + count]);
+            pos += count;
+        }
+        Ok(len)
+    }
+    /// 从设备的 offset 处开始写入 buf，返回写入的字节数。只有写到设备末尾时才会少于 buf 的长度
+    pub fn write(&self, offset: usize, buf: &[u8]) -> Result<usize, ErrorNo> {
+        let len = min(buf.len(), self.size.saturating_sub(offset));
+        let mut inner = self.inner.lock();
+        let mut pos = 0;
+        while pos < len {
+            let (id, start) = ((offset + pos) / BLOCK_CACHE_UNIT, (offset + pos) % BLOCK_CACHE_UNIT);
+            let count = min(BLOCK_CACHE_UNIT - start, len - pos);
+            if !inner.blocks.contains_key(&id) {
+                if inner.busy.contains_key(&id) {
+                    inner = self.wait_busy(inner, id);
+                } else if count == BLOCK_CACHE_UNIT {
+                    // 整块都会被覆盖，不需要先读出来
+                    inner = self.insert(inner, id, vec![0u8; BLOCK_CACHE_UNIT])?;
+                } else {
+                    inner = self.load(inner, id, 0)?;
+                }
+                continue;
+            }
+            let block = inner.touch(id);
+            block.data[start..start + count].copy_from_slice(&buf[pos..pos + count]);
+            block.dirty = true;
+            block.version += 1;
+            pos += count;
+        }
+        Ok(len)
+    }
+    /// 把所有脏块写回设备。所有脏块一次交给设备，相邻的块可以合并成一个请求。
+    /// 写回时不持有锁，所以先复制一份脏块的内容
+    pub fn sync(&self) -> Result<(), ErrorNo> {
+        let inner = self.inner.lock();
+        let dirty: Vec<(usize, usize, Vec<u8>)> = inner
+            .blocks
+            .iter()
+            .filter(|(_, block)| block.dirty)
+            .map(|(&id, block)| (id, block.version, block.data[..self.unit_len(id)].to_vec()))
+            .collect();
+        drop(inner);
+        let requests: Vec<(usize, &[u8])> = dirty
+            .iter()
+            .map(|(id, _, data)| (id * SECTORS_PER_UNIT, &data[..]))
+            .collect();
+        self.device.write_blocks(&requests)?;
+        drop(requests);
+        let mut inner = self.inner.lock();
+        for (id, version, _) in dirty {
+            if let Some(block) = inner.blocks.get_mut(&id) {
+                if block.version == version {
+                    block.dirty = false;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// 第 id 块在设备范围内的长度。设备大小不是缓存块的整数倍时，最后一块只有前一部分在设备上
+    fn unit_len(&self, id: usize) -> usize {
+        min(BLOCK_CACHE_UNIT, self.size - id * BLOCK_CACHE_UNIT)
+    }
+    /// 等待其他线程读入第 id 块。等待时不持有锁，返回时重新拿锁。
+    /// 不能让出 CPU 时(见 can_suspend_current_task)，读入的线程可能在这个核上睡眠，等下去就会死锁，
+    /// 所以作废那次读入，由当前线程自己重新读
+    fn wait_busy<'a>(&'a self, inner: MutexGuard<'a, CacheInner>, id: usize) -> MutexGuard<'a, CacheInner> {
+        drop(inner);
+        if can_suspend_current_task() {
+            suspend_current_task();
+            self.inner.lock()
+        } else {
+            let mut inner = self.inner.lock();
+            inner.busy.remove(&id);
+            inner
+        }
+    }
+    /// 从设备读入第 id 块，以及之后最多 read_ahead 个不在缓存里的块。这些块一起交给设备读。
+    /// 读设备时不持有锁，这些块先登记在 busy 中。第 id 块正在被其他线程读入时只等待，不读设备
+    fn load<'a>(
+        &'a self,
+        mut inner: MutexGuard<'a, CacheInner>,
+        id: usize,
+        read_ahead: usize,
+    ) -> Result<MutexGuard<'a, CacheInner>, ErrorNo> {
+        if inner.busy.contains_key(&id) {
+            return Ok(self.wait_busy(inner, id));
+        }
+        let last = min(id + read_ahead, (self.size - 1) / BLOCK_CACHE_UNIT);
+        // 预读到已经缓存的块就停下，它后面的块大概率也已经读过了
+        let count = (id + 1..=last)
+            .position(|next| inner.blocks.contains_key(&next) || inner.busy.contains_key(&next))
+            .unwrap_or(last - id)
+            + 1;
+        let epoch = inner.next_stamp();
+        for i in 0..count {
+            inner.busy.insert(id + i, epoch);
+        }
+        drop(inner);
+        // 超出设备的部分保持为 0
+        let mut units: Vec<Vec<u8>> = (0..count).map(|_| vec![0u8; BLOCK_CACHE_UNIT]).collect();
+        let mut requests: Vec<(usize, &mut [u8])> = units
+            .iter_mut()
+            .enumerate()
+            .map(|(i, data)| ((id + i) * SECTORS_PER_UNIT, &mut data[..self.unit_len(id + i)]))
+            .collect();
+        let ret = self.device.read_blocks(&mut requests);
+        drop(requests);
+        let mut inner = self.inner.lock();
+        // 被其他线程作废的块不再属于这次读入
+        let valid: Vec<bool> = (0..count)
+            .map(|i| inner.busy.get(&(id + i)) == Some(&epoch))
+            .collect();
+        for (i, &valid) in valid.iter().enumerate() {
+            if valid {
+                inner.busy.remove(&(id + i));
+            }
+        }
+        ret?;
+        for ((i, data), valid) in units.into_iter().enumerate().zip(valid) {
+            // 读设备期间可能有其他线程整块写入了这个块，此时缓存里的才是新的
+            if valid && !inner.blocks.contains_key(&(id + i)) {
+                inner = self.insert(inner, id + i, data)?;
+            }
+        }
+        Ok(inner)
+    }
+    /// 把一个缓存块放进缓存。缓存满时先换出最久没有使用的块，写回换出的块失败时不放入。
+    /// 写回时不持有锁，换出的块在写回期间仍留在缓存里，写回后重新选择要换出的块
+    fn insert<'a>(
+        &'a self,
+        mut inner: MutexGuard<'a, CacheInner>,
+        id: usize,
+        data: Vec<u8>,
+    ) -> Result<MutexGuard<'a, CacheInner>, ErrorNo> {
+        while inner.blocks.len() >= BLOCK_CACHE_SIZE {
+            let (&stamp, &old_id) = inner.lru.iter().next().unwrap();
+            let old = &inner.blocks[&old_id];
+            if old.dirty {
+                // 写回时不持有锁，所以先复制一份
+                let (version, data) = (old.version, old.data[..self.unit_len(old_id)].to_vec());
+                drop(inner);
+                self.device.write_blocks(&[(old_id * SECTORS_PER_UNIT, &data[..])])?;
+                inner = self.inner.lock();
+                if let Some(block) = inner.blocks.get_mut(&old_id) {
+                    if block.version == version {
+                        block.dirty = false;
+                    }
+                }
+                continue;
+            }
+            inner.lru.remove(&stamp);
+            inner.blocks.remove(&old_id);
+        }
+        // 放锁期间其他线程可能已经放入了这个块，保留它
+        if inner.blocks.contains_key(&id) {
+            return Ok(inner);
+        }
+        let stamp = inner.next_stamp();
+        inner.lru.insert(stamp, id);
+        inner.blocks.insert(
+            id,
+            CachedBlock {
+                data: data,
+                dirty: false,
+                version: 0,
+                stamp: stamp,
+            },
+        );
+        Ok(inner)
+    }
+}
+
+impl CacheInner {
+    fn next_stamp(&mut self) -> usize {
+        self.clock += 1;
+        self.clock
+    }
+    /// 获取一个已经在缓存里的块，并更新它的使用时间
+    fn touch(&mut self, id: usize) -> &mut CachedBlock {
+        let stamp = self.next_stamp();
+        let block = self.blocks.get_mut(&id).unwrap();
+        self.lru.remove(&block.stamp);
+        self.lru.insert(stamp, id);
+        block.stamp = stamp;
+        block
+    }
+}
@@ -1,13 +1,101 @@
+//! 块设备驱动，以及块设备上的缓存
+//!
+//! 根文件系统在 virtio 块设备上，读写都经过共享的块缓存 BLOCK_CACHE(见 cache.rs)，
+//! 再由 stream.rs 和 wrapper.rs 包装成 fatfs 需要的读写接口。
+//! 设备的读写请求由中断通知完成(见 virtio_block.rs)，出错时一路返回 EIO。
+//!
+//! 设备上可以有 MBR 或 GPT 分区表(见 partition.rs)，根文件系统在第一个 FAT 或 ext2 分区上，
+//! 整个设备和每个分区在 /dev 下都有对应的块设备文件，可以单独挂载
+
 use super::BlockDeviceImpl;
-use alloc::sync::Arc;
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use fatfs::{DefaultTimeProvider, FileSystem, FsOptions, LossyOemCpConverter};
 
 mod block_device;
+mod cache;
+mod partition;
+mod stream;
 mod virtio_block;
-pub use block_device::BlockDevice;
+mod wrapper;
+pub use block_device::{BlockDevice, BLOCK_SIZE};
+pub use cache::BlockCache;
+pub use partition::{is_fat_boot_sector, read_partitions, Partition};
+pub use stream::BlockStream;
 pub use virtio_block::VirtIOBlock;
+pub use wrapper::IoWrapper;
+
+pub type IoType = IoWrapper<BlockStream>;
+pub type FatFileSystem = FileSystem<IoType, DefaultTimeProvider, LossyOemCpConverter>;
+
+/// 块设备在 /dev 下的名字。分区的名字是它后面加上分区编号，如 vda1
+const DEVICE_NAME: &str = "vda";
+/// ext2 超级块中的魔数
+const EXT2_MAGIC: u16 = 0xef53;
 
 lazy_static::lazy_static! {
     pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    /// 块设备上的缓存，所有文件系统共用
+    pub static ref BLOCK_CACHE: Arc<BlockCache> = Arc::new(BlockCache::new(BLOCK_DEVICE.clone()));
+    /// 块设备上的分区。没有分区表时为空
+    pub static ref BLOCK_PARTITIONS: Vec<Partition> = read_partitions(BLOCK_DEVICE.as_ref()).unwrap_or_else(|_| {
+        warn!("failed to read the partition table");
+        Vec::new()
+    });
+}
+
+/// /dev 下的块设备文件，每项为 (名字, 分区编号, 起始位置, 结束位置)，位置以字节计。
+/// 第一项是整个设备，分区编号为 0，之后是各个分区
+pub fn block_device_nodes() -> Vec<(String, usize, usize, usize)> {
+    let mut nodes = Vec::new();
+    nodes.push((String::from(DEVICE_NAME), 0, 0, BLOCK_CACHE.size()));
+    for partition in BLOCK_PARTITIONS.iter() {
+        nodes.push((
+            format!("{}{}", DEVICE_NAME, partition.number),
+            partition.number,
+            partition.start * BLOCK_SIZE,
+            (partition.start + partition.sectors) * BLOCK_SIZE,
+        ));
+    }
+    nodes
+}
+
+/// 设备上 start 处是否是一个 FAT 文件系统
+fn is_fat_at(start: usize) -> bool {
+    let mut sector = [0u8; BLOCK_SIZE];
+    BLOCK_CACHE.read(start, &mut sector).is_ok() && is_fat_boot_sector(&sector)
+}
+
+/// 设备上 start 处是否是一个 ext2 文件系统。超级块在 1024 字节处，魔数在超级块的第 56 字节
+pub fn is_ext2_at(start: usize) -> bool {
+    let mut magic = [0u8; 2];
+    BLOCK_CACHE.read(start + 1024 + 56, &mut magic).is_ok() && u16::from_le_bytes(magic) == EXT2_MAGIC
+}
+
+/// 根文件系统所在的区间：第一个格式化成 FAT 或者 ext2 的分区。没有分区表时是整个设备
+pub fn root_fs_range() -> (usize, usize) {
+    BLOCK_PARTITIONS
+        .iter()
+        .map(|partition| (partition.start * BLOCK_SIZE, (partition.start + partition.sectors) * BLOCK_SIZE))
+        .find(|&(start, _)| is_fat_at(start) || is_ext2_at(start))
+        .unwrap_or((0, BLOCK_CACHE.size()))
+}
+
+/// 在设备的 [start, end) 区间上打开 FAT 文件系统。区间上不是 FAT 时返回 None。
+///
+/// read_only 为 true 时不更新访问时间，对设备的写入也都会失败，用于 overlayfs 的下层
+pub fn new_fat_fs(start: usize, end: usize, read_only: bool) -> Option<FatFileSystem> {
+    if !is_fat_at(start) {
+        return None;
+    }
+    let stream = BlockStream::new(BLOCK_CACHE.clone(), start, end, read_only);
+    let options = FsOptions::new().update_accessed_date(!read_only);
+    FileSystem::new(IoWrapper::new(stream), options).ok()
+}
+
+/// 创建块设备上的根文件系统实例
+pub fn new_block_fs() -> FatFileSystem {
+    let (start, end) = root_fs_range();
+    new_fat_fs(start, end, false).expect("no FAT filesystem on the block device")
 }
 
 #[allow(unused)]
@@ -19,8 +107,8 @@ pub fn block_device_test() {
         for byte in write_buffer.iter_mut() {
             *byte = i as u8;
         }
-        block_device.write_block(i as usize, &write_buffer);
-        block_device.read_block(i as usize, &mut read_buffer);
+        block_device.write_block(i as usize, &write_buffer).unwrap();
+        block_device.read_block(i as usize, &mut read_buffer).unwrap();
         assert_eq!(write_buffer, read_buffer);
     }
     println!("block device test passed!");
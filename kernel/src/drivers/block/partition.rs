@@ -0,0 +1,171 @@
+//! 分区表
+//!
+//! 从块设备的开头读取 MBR 或者 GPT 分区表，得到每个分区的位置。分区的编号和 Linux 一致：
+//! - MBR 的四个主分区按表项位置编号为 1~4，空的表项也占一个编号；扩展分区中的逻辑分区从 5 开始依次编号
+//! - MBR 中只有一个类型为 0xEE 的保护分区时，说明实际是 GPT，分区按表项位置从 1 开始编号
+//!
+//! 没有分区表的设备(比如整个设备直接格式化成 FAT)返回空的分区列表
+
+use super::{BlockDevice, BLOCK_SIZE};
+use crate::syscall::ErrorNo;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// MBR 中分区表的位置，以及结尾的签名
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+/// MBR 中表示 GPT 保护分区和扩展分区的类型
+const MBR_TYPE_GPT: u8 = 0xee;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+/// 最多读取的逻辑分区数，防止扩展分区的链表成环
+const MAX_LOGICAL_PARTITIONS: usize = 64;
+/// GPT 头的签名
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// 最多读取的 GPT 表项数
+const MAX_GPT_ENTRIES: usize = 128;
+
+/// 一个分区
+#[derive(Clone, Copy, Debug)]
+pub struct Partition {
+    /// 分区编号，从 1 开始
+    pub number: usize,
+    /// 起始扇区
+    pub start: usize,
+    /// 扇区数
+    pub sectors: usize,
+}
+
+/// 读取设备上的分区表。没有分区表时返回空的列表
+pub fn read_partitions(device: &dyn BlockDevice) -> Result<Vec<Partition>, ErrorNo> {
+    let mbr = read_sector(device, 0)?;
+    if mbr[510..512] != MBR_SIGNATURE || is_fat_boot_sector(&mbr) {
+        return Ok(Vec::new());
+    }
+    let entries = mbr_entries(&mbr);
+    // 状态字节只能是 0 或者 0x80，否则这个扇区多半不是 MBR
+    if entries.iter().any(|entry| entry.0 & 0x7f != 0) {
+        return Ok(Vec::new());
+    }
+    if entries.iter().any(|entry| entry.1 == MBR_TYPE_GPT) {
+        return read_gpt(device);
+    }
+    let mut partitions = Vec::new();
+    let mut extended = None;
+    for (i, &(_, kind, start, sectors)) in entries.iter().enumerate() {
+        if kind == 0 || sectors == 0 {
+            continue;
+        }
+        if MBR_TYPES_EXTENDED.contains(&kind) {
+            extended = Some(start);
+        } else {
+            partitions.push(Partition {
+                number: i + 1,
+                start: start,
+                sectors: sectors,
+            });
+        }
+    }
+    if let Some(extended_start) = extended {
+        read_logical_partitions(device, extended_start, &mut partitions)?;
+    }
+    partitions.retain(|partition| partition.start + partition.sectors <= device.num_blocks());
+    Ok(partitions)
+}
+
+/// 判断一个扇区是否是 FAT 的引导扇区。它和 MBR 一样以 0x55AA 结尾，需要区分开
+pub fn is_fat_boot_sector(sector: &[u8]) -> bool {
+    // 开头是跳转指令，并且在 FAT12/16 或者 FAT32 的位置上写着文件系统类型
+    (sector[0] == 0xeb || sector[0] == 0xe9)
+        && sector[510..512] == MBR_SIGNATURE
+        && (&sector[54..57] == b"FAT" || &sector[82..85] == b"FAT")
+}
+
+/// 读一个扇区
+fn read_sector(device: &dyn BlockDevice, id: usize) -> Result<Vec<u8>, ErrorNo> {
+    let mut sector = vec![0u8; BLOCK_SIZE];
+    device.read_block(id, &mut sector)?;
+    Ok(sector)
+}
+
+/// 取出 MBR 或 EBR 中的四个表项，每项为 (状态, 类型, 起始扇区, 扇区数)
+fn mbr_entries(sector: &[u8]) -> [(u8, u8, usize, usize); 4] {
+    let mut entries = [(0, 0, 0, 0); 4];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let raw = &sector[MBR_TABLE_OFFSET + i * MBR_ENTRY_SIZE..MBR_TABLE_OFFSET + (i + 1) * MBR_ENTRY_SIZE];
+        *entry = (
+            raw[0],
+            raw[4],
+            u32::from_le_bytes(raw[8..12].try_into().unwrap()) as usize,
+            u32::from_le_bytes(raw[12..16].try_into().unwrap()) as usize,
+        );
+    }
+    entries
+}
+
+/// 读取扩展分区中的逻辑分区。
+/// 每个 EBR 的第一项是逻辑分区，起始扇区相对这个 EBR；第二项指向下一个 EBR，起始扇区相对扩展分区的开头
+fn read_logical_partitions(device: &dyn BlockDevice, extended_start: usize, partitions: &mut Vec<Partition>) -> Result<(), ErrorNo> {
+    let mut ebr_start = extended_start;
+    for number in 5..5 + MAX_LOGICAL_PARTITIONS {
+        if ebr_start >= device.num_blocks() {
+            break;
+        }
+        let ebr = read_sector(device, ebr_start)?;
+        if ebr[510..512] != MBR_SIGNATURE {
+            break;
+        }
+        let entries = mbr_entries(&ebr);
+        let (_, kind, start, sectors) = entries[0];
+        if kind != 0 && sectors != 0 {
+            partitions.push(Partition {
+                number: number,
+                start: ebr_start + start,
+                sectors: sectors,
+            });
+        }
+        let (_, next_kind, next_start, _) = entries[1];
+        if next_kind == 0 || next_start == 0 {
+            break;
+        }
+        ebr_start = extended_start + next_start;
+    }
+    Ok(())
+}
+
+/// 读取 GPT 分区表。GPT 头在第 1 个扇区
+fn read_gpt(device: &dyn BlockDevice) -> Result<Vec<Partition>, ErrorNo> {
+    let header = read_sector(device, 1)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return Ok(Vec::new());
+    }
+    let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap()) as usize;
+    let entry_count = u32::from_le_bytes(header[80..84].try_into().unwrap()) as usize;
+    let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as usize;
+    if entry_size < 128 || BLOCK_SIZE % entry_size != 0 {
+        return Ok(Vec::new());
+    }
+    let entries_per_sector = BLOCK_SIZE / entry_size;
+    let mut partitions = Vec::new();
+    let mut sector = Vec::new();
+    for i in 0..entry_count.min(MAX_GPT_ENTRIES) {
+        if i % entries_per_sector == 0 {
+            sector = read_sector(device, entries_lba + i / entries_per_sector)?;
+        }
+        let entry = &sector[i % entries_per_sector * entry_size..][..entry_size];
+        // 类型 GUID 全为 0 表示这一项没有使用
+        if entry[0..16].iter().all(|&byte| byte == 0) {
+            continue;
+        }
+        let first = u64::from_le_bytes(entry[32..40].try_into().unwrap()) as usize;
+        let last = u64::from_le_bytes(entry[40..48].try_into().unwrap()) as usize;
+        if first <= last && last < device.num_blocks() {
+            partitions.push(Partition {
+                number: i + 1,
+                start: first,
+                sectors: last - first + 1,
+            });
+        }
+    }
+    Ok(partitions)
+}
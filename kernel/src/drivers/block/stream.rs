@@ -0,0 +1,89 @@
+//! 把块缓存中的一段字节区间包装成可以读写和 seek 的流，供 fatfs 使用
+
+pub mod fsio {
+    pub use fscommon::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+}
+
+use super::BlockCache;
+use crate::syscall::ErrorNo;
+use alloc::sync::Arc;
+
+pub struct BlockStream {
+    cache: Arc<BlockCache>,
+    /// 区间在设备上的起始位置(字节)
+    start: usize,
+    /// 区间在设备上的结束位置(字节)
+    end: usize,
+    /// 当前位置，相对 start
+    pos: usize,
+    /// 是否只读。只读时写入都会失败，保证设备上的内容不被修改
+    read_only: bool,
+}
+
+impl BlockStream {
+    /// 把设备上 [start, end) 的区间包装成流
+    pub fn new(cache: Arc<BlockCache>, start: usize, end: usize, read_only: bool) -> Self {
+        Self {
+            cache: cache,
+            start: start,
+            end: end,
+            pos: 0,
+            read_only: read_only,
+        }
+    }
+    /// 当前位置之后还剩下的长度
+    fn remaining(&self) -> usize {
+        self.end - self.start - self.pos
+    }
+}
+
+/// 设备读写出错。fatfs 会把它包装成 fatfs::Error::Io 返回给文件层
+fn device_error(_: ErrorNo) -> fsio::Error {
+    fsio::Error::from(fsio::ErrorKind::Other)
+}
+
+impl fsio::Read for BlockStream {
+    fn read(&mut self, buf: &mut [u8]) -> fsio::Result<usize> {
+        let len = core::cmp::min(self.remaining(), buf.len());
+        let read_len = self.cache.read(self.start + self.pos, &mut buf[..len]).map_err(device_error)?;
+        self.pos += read_len;
+        Ok(read_len)
+    }
+}
+
+impl fsio::Write for BlockStream {
+    fn write(&mut self, buf: &[u8]) -> fsio::Result<usize> {
+        if self.read_only {
+            return Err(fsio::Error::from(fsio::ErrorKind::PermissionDenied));
+        }
+        let len = core::cmp::min(self.remaining(), buf.len());
+        let write_len = self.cache.write(self.start + self.pos, &buf[..len]).map_err(device_error)?;
+        self.pos += write_len;
+        Ok(write_len)
+    }
+    /// 把缓存中的脏块写回设备。只读的流没有写过数据，不需要写回
+    fn flush(&mut self) -> fsio::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.cache.sync().map_err(device_error)
+    }
+}
+
+impl fsio::Seek for BlockStream {
+    fn seek(&mut self, pos: fsio::SeekFrom) -> fsio::Result<u64> {
+        let len = (self.end - self.start) as i64;
+        let new_pos = match pos {
+            fsio::SeekFrom::Current(delta) => self.pos as i64 + delta,
+            fsio::SeekFrom::Start(delta) => delta as i64,
+            fsio::SeekFrom::End(delta) => len + delta,
+        };
+        // 对于一般的文件来说，seek 到末尾之后会自动扩展文件，但磁盘的大小不会增长，所以需要直接报错
+        if new_pos < 0 || new_pos > len {
+            Err(fsio::Error::from(fsio::ErrorKind::Uncategorized))
+        } else {
+            self.pos = new_pos as usize;
+            Ok(self.pos as u64)
+        }
+    }
+}
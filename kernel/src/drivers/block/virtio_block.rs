@@ -1,67 +1,257 @@
-use crate::drivers::block::BlockDevice;
-use crate::memory::{phys_to_virt, virt_to_phys, Frame, PhysAddr, VirtAddr};
+//! virtio 块设备的驱动
+//!
+//! 按 legacy 的 virtio-mmio 接口直接操作设备，只使用一个请求队列：
+//! - 每个请求在队列中占一串描述符，依次是请求头、一段或多段数据、设备写回的状态字节。
+//!   同一批读写中扇区相邻的部分合并成一个多段的请求，一次交给设备
+//! - 一批请求会尽量填满队列后再通知设备，队列满了就等设备完成一些请求再继续提交
+//! - 设备完成请求后通过 PLIC 发来中断，中断处理时回收完成的请求并记下结果，唤醒等在这个请求上的线程，由它取走结果
+//! - 设备报告出错的请求返回 EIO，由上层的块缓存和文件系统一路传给用户
+//!
+//! 等待设备时，线程登记在请求的等待队列上，由中断唤醒，见 virtio_mmio 中的 wait_for_device
+
+use super::{BlockDevice, BLOCK_SIZE};
+use crate::drivers::plic;
+use crate::drivers::virtio::{find_virtio_device, virtio_irq};
+use crate::drivers::virtio_mmio::{wait_for_device, MmioRegs, Ring, DESC_F_NEXT, DESC_F_WRITE};
+use crate::file::WaitQueue;
+use crate::memory::virt_to_phys;
+use crate::syscall::ErrorNo;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
 use lock::Mutex;
-use virtio_drivers::{VirtIOBlk, VirtIOHeader};
 
-#[allow(unused)]
-const VIRTIO0: usize = 0x10001000;
+/// virtio 中块设备的设备类型
+const VIRTIO_DEVICE_BLOCK: u32 = 2;
+
+/// 队列的长度，即描述符的个数
+const QUEUE_SIZE: usize = 32;
+/// 一个请求最多带的数据段数，另外两个描述符给请求头和状态字节
+const MAX_SEGMENTS: usize = 8;
+
+/// 请求的类型
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+/// 设备写回的状态，0 表示成功
+const STATUS_OK: u8 = 0;
+
+/// 请求头
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// 已经交给设备、还没完成的请求。请求头和状态字节放在堆上，在请求完成前地址不变
+struct InFlight {
+    token: usize,
+    descs: Vec<u16>,
+    _header: Box<RequestHeader>,
+    status: Box<u8>,
+    /// 等待这个请求完成的线程
+    waiters: Arc<WaitQueue>,
+}
+
+/// 一段要读写的数据：(起始扇区, 缓冲区的虚拟地址, 长度)
+type Segment = (usize, usize, usize);
 
-pub struct VirtIOBlock(Mutex<VirtIOBlk<'static>>);
+struct VirtQueue {
+    ring: Ring,
+    /// 空闲的描述符
+    free: Vec<u16>,
+    /// 已提交还没完成的请求，按第一个描述符的编号索引
+    in_flight: BTreeMap<u16, InFlight>,
+    /// 已完成的请求是否成功，按请求编号索引
+    finished: BTreeMap<usize, bool>,
+    /// 下一个请求的编号
+    next_token: usize,
+    /// 是否有提交了但还没通知设备的请求
+    need_notify: bool,
+}
 
-static QUEUE_FRAMES: Mutex<Option<Frame>> = Mutex::new(None);
+pub struct VirtIOBlock {
+    regs: MmioRegs,
+    /// 在 PLIC 中的中断号
+    irq: usize,
+    /// 设备上的扇区数
+    capacity: usize,
+    queue: Mutex<VirtQueue>,
+    /// 等待队列中腾出描述符的线程，有请求完成时唤醒
+    free_waiters: WaitQueue,
+}
 
 impl BlockDevice for VirtIOBlock {
-    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        self.0
-            .lock()
-            .read_block(block_id, buf)
-            .expect("Error when reading VirtIOBlk");
+    fn read_blocks(&self, requests: &mut [(usize, &mut [u8])]) -> Result<(), ErrorNo> {
+        let segments = requests
+            .iter_mut()
+            .map(|(block_id, buf)| (*block_id, buf.as_mut_ptr() as usize, buf.len()))
+            .collect();
+        self.transfer(false, segments)
+    }
+    fn write_blocks(&self, requests: &[(usize, &[u8])]) -> Result<(), ErrorNo> {
+        let segments = requests
+            .iter()
+            .map(|(block_id, buf)| (*block_id, buf.as_ptr() as usize, buf.len()))
+            .collect();
+        self.transfer(true, segments)
+    }
+    fn num_blocks(&self) -> usize {
+        self.capacity
     }
-    fn write_block(&self, block_id: usize, buf: &[u8]) {
-        self.0
-            .lock()
-            .write_block(block_id, buf)
-            .expect("Error when writing VirtIOBlk");
+    fn irq(&self) -> Option<usize> {
+        Some(self.irq)
+    }
+    /// 回应设备的中断，回收已完成的请求并唤醒等待它们的线程
+    fn handle_irq(&self) {
+        self.regs.ack_interrupt();
+        let done = self.queue.lock().collect_used();
+        self.wake(done);
     }
 }
 
 impl VirtIOBlock {
     #[allow(unused)]
     pub fn new() -> Self {
-        unsafe {
-            Self(Mutex::new(
-                VirtIOBlk::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
-            ))
+        let base = find_virtio_device(VIRTIO_DEVICE_BLOCK).expect("virtio-blk not found");
+        let regs = MmioRegs::new(base);
+        let ring = Ring::new(QUEUE_SIZE).unwrap();
+        // 不需要任何可选的特性
+        assert!(regs.init(0, &ring), "only legacy virtio-mmio is supported");
+        // 配置空间的开头是 64 位的扇区数，按 32 位访问，所以分两次读
+        let capacity = regs.config_u32(0) as usize | (regs.config_u32(4) as usize) << 32;
+        let block = Self {
+            regs: regs,
+            irq: virtio_irq(base),
+            capacity: capacity,
+            queue: Mutex::new(VirtQueue {
+                ring: ring,
+                free: (0..QUEUE_SIZE as u16).rev().collect(),
+                in_flight: BTreeMap::new(),
+                finished: BTreeMap::new(),
+                next_token: 0,
+                need_notify: false,
+            }),
+            free_waiters: WaitQueue::new(),
+        };
+        plic::enable(block.irq);
+        info!("virtio-blk found at {:#x}, irq {}, {} sectors", base, block.irq, block.capacity);
+        block
+    }
+    /// 提交一批读写并等待它们全部完成，有任何一个请求出错时返回 EIO
+    fn transfer(&self, write: bool, mut segments: Vec<Segment>) -> Result<(), ErrorNo> {
+        segments.sort_by_key(|segment| segment.0);
+        let mut tokens = Vec::new();
+        let mut start = 0;
+        while start < segments.len() {
+            // 把扇区相邻的段合并到一个请求里
+            let mut end = start + 1;
+            while end < segments.len()
+                && end - start < MAX_SEGMENTS
+                && segments[end].0 == segments[end - 1].0 + segments[end - 1].2 / BLOCK_SIZE
+            {
+                end += 1;
+            }
+            // 队列中没有足够的描述符时，先等设备完成一些请求
+            let request = &segments[start..end];
+            tokens.push(self.wait_until(&self.free_waiters, |queue| queue.submit(write, request)));
+            start = end;
+        }
+        let mut success = true;
+        for (token, waiters) in tokens {
+            success &= self.wait_until(&waiters, |queue| queue.finished.remove(&token));
+        }
+        if success {
+            Ok(())
+        } else {
+            Err(ErrorNo::EIO)
         }
     }
+    /// 反复尝试 f，直到它返回 Some。每次失败后通知设备处理已提交的请求，然后在 waiters 上睡眠，直到被中断唤醒
+    fn wait_until<T>(&self, waiters: &WaitQueue, mut f: impl FnMut(&mut VirtQueue) -> Option<T>) -> T {
+        wait_for_device(waiters, || {
+            let mut queue = self.queue.lock();
+            // 中断可能来不及处理(比如在中断到来前就检查了)，所以这里也回收一次
+            let done = queue.collect_used();
+            let ret = f(&mut queue);
+            if ret.is_none() && queue.need_notify {
+                queue.need_notify = false;
+                self.regs.notify();
+            }
+            // 中断处理时要拿队列的锁，所以睡眠前要先放掉
+            drop(queue);
+            self.wake(done);
+            ret
+        })
+    }
+    /// 唤醒等待 done 中的请求的线程，以及等待空闲描述符的线程
+    fn wake(&self, done: Vec<Arc<WaitQueue>>) {
+        if done.is_empty() {
+            return;
+        }
+        for waiters in done {
+            waiters.wake_all();
+        }
+        self.free_waiters.wake_all();
+    }
 }
 
-#[no_mangle]
-/// 这里用 new_contiguous 可以要求分配一段连续的内存。
-/// 因为这个函数是面向 virtio-drivers 的接口，而且仅在内核启动时初始化，
-/// 所以这里默认可以拿到需要的空间，不处理分配失败导致的异常
-pub extern "C" fn virtio_dma_alloc(pages: usize) -> PhysAddr {
-    let frame = Frame::new_contiguous(pages, 0).unwrap();
-    let paddr = frame.start_paddr();
-    *QUEUE_FRAMES.lock() = Some(frame);
-    paddr
-}
-
-#[no_mangle]
-/// Frame 在 Drop 时会释放页帧，所以这里不用做其他处理
-pub extern "C" fn virtio_dma_dealloc(_pa: PhysAddr, _pages: usize) -> i32 {
-    *QUEUE_FRAMES.lock() = None;
-    0
-}
-
-#[no_mangle]
-/// 默认只在内核态才访问块设备，所以直接用内核固定偏移的虚拟地址
-pub extern "C" fn virtio_phys_to_virt(paddr: PhysAddr) -> VirtAddr {
-    phys_to_virt(paddr)
-}
-
-#[no_mangle]
-/// 默认只在内核态才访问块设备，所以直接用内核固定偏移的虚拟地址
-pub extern "C" fn virtio_virt_to_phys(vaddr: VirtAddr) -> PhysAddr {
-    virt_to_phys(vaddr)
+impl VirtQueue {
+    /// 把一个请求放进可用环，返回请求的编号和等待它完成的队列。描述符不够时返回 None
+    fn submit(&mut self, write: bool, segments: &[Segment]) -> Option<(usize, Arc<WaitQueue>)> {
+        if self.free.len() < segments.len() + 2 {
+            return None;
+        }
+        let header = Box::new(RequestHeader {
+            kind: if write { REQUEST_OUT } else { REQUEST_IN },
+            reserved: 0,
+            sector: segments[0].0 as u64,
+        });
+        let status = Box::new(0xffu8);
+        let data_flags = if write { 0 } else { DESC_F_WRITE };
+        let mut buffers = Vec::with_capacity(segments.len() + 2);
+        buffers.push((&*header as *const RequestHeader as usize, size_of::<RequestHeader>(), 0));
+        buffers.extend(segments.iter().map(|&(_, addr, len)| (addr, len, data_flags)));
+        buffers.push((&*status as *const u8 as usize, 1, DESC_F_WRITE));
+        let descs: Vec<u16> = (0..buffers.len()).map(|_| self.free.pop().unwrap()).collect();
+        for (i, &(addr, len, flags)) in buffers.iter().enumerate() {
+            let next = descs.get(i + 1).copied();
+            let desc = self.ring.desc(descs[i]);
+            desc.addr = virt_to_phys(addr) as u64;
+            desc.len = len as u32;
+            desc.flags = flags | if next.is_some() { DESC_F_NEXT } else { 0 };
+            desc.next = next.unwrap_or(0);
+        }
+        self.ring.push_avail(descs[0]);
+        let token = self.next_token;
+        self.next_token += 1;
+        let waiters = Arc::new(WaitQueue::new());
+        self.in_flight.insert(
+            descs[0],
+            InFlight {
+                token: token,
+                descs: descs,
+                _header: header,
+                status: status,
+                waiters: waiters.clone(),
+            },
+        );
+        self.need_notify = true;
+        Some((token, waiters))
+    }
+    /// 回收设备已经完成的请求，记下它们的结果。返回等待这些请求的队列，由调用者放锁之后唤醒
+    fn collect_used(&mut self) -> Vec<Arc<WaitQueue>> {
+        let mut done = Vec::new();
+        while let Some((head, _)) = self.ring.pop_used() {
+            if let Some(request) = self.in_flight.remove(&head) {
+                let status = unsafe { (&*request.status as *const u8).read_volatile() };
+                self.finished.insert(request.token, status == STATUS_OK);
+                self.free.extend(request.descs);
+                done.push(request.waiters);
+            }
+        }
+        done
+    }
 }
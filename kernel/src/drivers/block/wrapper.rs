@@ -1,4 +1,4 @@
-use super::device::fsio;
+use super::stream::fsio;
 use fatfs::{IoBase, IoError, Read, Seek, SeekFrom, Write};
 
 /*
@@ -0,0 +1,218 @@
+//! 设备树(FDT)的读取
+//!
+//! 启动时 SBI 会把设备树的物理地址放在 a1 中传给内核。这里只按结构块的格式顺序扫描，
+//! 找出需要的属性，不建立完整的树
+
+use crate::memory::phys_to_virt;
+use alloc::{string::String, vec::Vec};
+use core::ops::Range;
+use lock::Mutex;
+
+/// 设备树开头的魔数
+const FDT_MAGIC: u32 = 0xd00dfeed;
+/// 结构块中的标记
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+/// 启动页表中恒等映射的范围。设备树必须在其中才能在切换到内核页表前读取
+const BOOT_MAPPED: Range<usize> = 0x8000_0000..0xc000_0000;
+
+/// 设备树中一个核的信息
+#[derive(Clone)]
+pub struct CpuNode {
+    /// hart id
+    pub hart: usize,
+    /// 支持的指令集，如 "rv64imafdc"
+    pub isa: String,
+    /// 页表格式，如 "sv39"
+    pub mmu: String,
+    /// 核的型号，如 "sifive,u74-mc"
+    pub uarch: String,
+}
+
+/// 启动时从设备树中读出的核
+static CPU_NODES: Mutex<Vec<CpuNode>> = Mutex::new(Vec::new());
+/// 启动时从设备树中读出的内核命令行
+static BOOTARGS: Mutex<String> = Mutex::new(String::new());
+
+/// 内存中的设备树
+pub struct Fdt {
+    data: &'static [u8],
+}
+
+impl Fdt {
+    /// 读取物理地址 paddr 处的设备树。地址不在启动页表的映射范围内，或者魔数不对时返回 None。
+    ///
+    /// **只能在切换到内核页表之前调用**，之后设备树所在的内存不一定有映射
+    pub fn from_paddr(paddr: usize) -> Option<Self> {
+        if !BOOT_MAPPED.contains(&paddr) || paddr + 8 > BOOT_MAPPED.end {
+            return None;
+        }
+        let header = unsafe { core::slice::from_raw_parts(phys_to_virt(paddr) as *const u8, 8) };
+        if be32(header, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let total_size = be32(header, 4)? as usize;
+        if paddr + total_size > BOOT_MAPPED.end {
+            return None;
+        }
+        Some(Self {
+            data: unsafe { core::slice::from_raw_parts(phys_to_virt(paddr) as *const u8, total_size) },
+        })
+    }
+    /// 查找路径为 node_path 的节点下名为 name 的属性，返回属性的值。
+    /// node_path 如 "/chosen"，根节点为 "/"
+    pub fn property(&self, node_path: &str, name: &str) -> Option<&'static [u8]> {
+        self.nodes(node_path)
+            .into_iter()
+            .flatten()
+            .find(|(prop_name, _)| *prop_name == name)
+            .map(|(_, value)| value)
+    }
+    /// 路径为 node_path 的所有节点，每个节点是 (属性名, 属性值) 的列表。
+    /// 节点名可以带 @地址，比较时只看前面的部分，所以 "/cpus/cpu" 会找到每一个核。设备树格式错误时返回已经找到的部分
+    pub fn nodes(&self, node_path: &str) -> Vec<Vec<(&'static str, &'static [u8])>> {
+        let mut nodes = Vec::new();
+        self.scan(node_path, &mut nodes);
+        nodes
+    }
+    /// 按顺序扫描结构块，把匹配 node_path 的节点放进 nodes
+    fn scan(&self, node_path: &str, nodes: &mut Vec<Vec<(&'static str, &'static [u8])>>) -> Option<()> {
+        let data = self.data;
+        let struct_off = be32(data, 8)? as usize;
+        let strings_off = be32(data, 12)? as usize;
+        let target: Vec<&str> = node_path.split('/').filter(|s| !s.is_empty()).collect();
+        // 当前节点的深度，以及从根开始有几层和 target 相同
+        let mut depth = 0;
+        let mut matched = 0;
+        let mut pos = struct_off;
+        loop {
+            let token = be32(data, pos)?;
+            pos += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name_len = data.get(pos..)?.iter().position(|&b| b == 0)?;
+                    let node_name = core::str::from_utf8(&data[pos..pos + name_len]).ok()?;
+                    // 节点名可以带 @地址，比较时只看前面的部分
+                    let node_name = node_name.split('@').next().unwrap_or("");
+                    // 根节点没有名字，深度为 0 的节点就是根
+                    if depth > 0 && matched == depth - 1 && target.get(depth - 1) == Some(&node_name) {
+                        matched = depth;
+                    }
+                    if depth == target.len() && matched == target.len() {
+                        nodes.push(Vec::new());
+                    }
+                    depth += 1;
+                    pos = align4(pos + name_len + 1);
+                }
+                FDT_END_NODE => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(());
+                    }
+                    matched = matched.min(depth - 1);
+                }
+                FDT_PROP => {
+                    let len = be32(data, pos)? as usize;
+                    let name_off = be32(data, pos + 4)? as usize;
+                    let value = data.get(pos + 8..pos + 8 + len)?;
+                    pos = align4(pos + 8 + len);
+                    // 属性属于深度为 depth - 1 的节点
+                    if depth >= 1 && matched == depth - 1 && matched == target.len() {
+                        let prop_name = data.get(strings_off + name_off..)?;
+                        let prop_len = prop_name.iter().position(|&b| b == 0)?;
+                        let prop_name = core::str::from_utf8(&prop_name[..prop_len]).ok()?;
+                        nodes.last_mut()?.push((prop_name, value));
+                    }
+                }
+                FDT_NOP => {}
+                FDT_END => return Some(()),
+                // 格式错误
+                _ => return None,
+            }
+        }
+    }
+    /// 设备树中所有可用的核
+    pub fn cpus(&self) -> Vec<CpuNode> {
+        self.nodes("/cpus/cpu")
+            .into_iter()
+            .filter_map(|props| {
+                let prop = |name: &str| props.iter().find(|(prop_name, _)| *prop_name == name).map(|(_, value)| *value);
+                if prop("status").map_or(false, |status| prop_str(status) == "disabled") {
+                    return None;
+                }
+                // compatible 中除了 "riscv" 之外的一项是核的型号，如 "sifive,u74-mc"
+                let uarch = prop("compatible")
+                    .and_then(|value| value.split(|&b| b == 0).map(prop_str).find(|s| !s.is_empty() && *s != "riscv"))
+                    .unwrap_or("");
+                let mmu = prop("mmu-type").map_or("", prop_str);
+                Some(CpuNode {
+                    hart: be_cells(prop("reg")?)?,
+                    isa: String::from(prop("riscv,isa").map_or("", prop_str)),
+                    mmu: String::from(mmu.strip_prefix("riscv,").unwrap_or(mmu)),
+                    uarch: String::from(uarch),
+                })
+            })
+            .collect()
+    }
+    /// 内核命令行，即 /chosen 节点的 bootargs 属性
+    pub fn bootargs(&self) -> Option<&'static str> {
+        Some(prop_str(self.property("/chosen", "bootargs")?))
+    }
+    /// 由 bootloader 或者 qemu 的 -initrd 加载的 initrd 在物理内存中的范围
+    pub fn initrd_range(&self) -> Option<Range<usize>> {
+        let start = be_cells(self.property("/chosen", "linux,initrd-start")?)?;
+        let end = be_cells(self.property("/chosen", "linux,initrd-end")?)?;
+        if start < end {
+            Some(start..end)
+        } else {
+            None
+        }
+    }
+}
+
+/// 读取设备树中启动之后还要用到的信息。
+///
+/// **必须在切换到内核页表之前调用**，原因同 Fdt::from_paddr
+pub fn save_fdt_info(dtb: usize) {
+    if let Some(fdt) = Fdt::from_paddr(dtb) {
+        *CPU_NODES.lock() = fdt.cpus();
+        *BOOTARGS.lock() = String::from(fdt.bootargs().unwrap_or(""));
+    }
+}
+
+/// 启动时保存的内核命令行，即 qemu 的 -append
+pub fn boot_args() -> String {
+    BOOTARGS.lock().clone()
+}
+
+/// 启动时保存的所有可用的核
+pub fn cpu_nodes() -> Vec<CpuNode> {
+    CPU_NODES.lock().clone()
+}
+
+/// 字符串属性的值，去掉结尾的 '\0'
+fn prop_str(value: &[u8]) -> &str {
+    let len = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+    core::str::from_utf8(&value[..len]).unwrap_or("")
+}
+
+/// 读取 data 中 pos 处大端序的 u32
+fn be32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().unwrap()))
+}
+
+/// 把 32 位或 64 位的大端序属性值读成整数
+fn be_cells(value: &[u8]) -> Option<usize> {
+    match value.len() {
+        4 => be32(value, 0).map(|v| v as usize),
+        8 => Some(u64::from_be_bytes(value.try_into().unwrap()) as usize),
+        _ => None,
+    }
+}
+
+fn align4(pos: usize) -> usize {
+    (pos + 3) & !3
+}
@@ -1,7 +1,26 @@
 mod block;
-mod memory;
-pub use block::BLOCK_DEVICE;
-pub use memory::new_memory_mapped_fs;
+mod fdt;
+mod net;
+mod plic;
+mod virtio;
+mod virtio_9p;
+mod virtio_mmio;
+pub use fdt::{boot_args, cpu_nodes, save_fdt_info, Fdt};
+pub use block::{block_device_nodes, is_ext2_at, new_block_fs, new_fat_fs, root_fs_range, BLOCK_CACHE, BLOCK_DEVICE};
+pub use net::{set_net_irq_handler, NetDevice, NET_DEVICE};
+pub use virtio_9p::{find_9p_device, VirtIO9p, P9_MAX_MESSAGE};
 
 pub type BlockDeviceImpl = block::VirtIOBlock;
-pub type MemoryMappedFsIoType = memory::IoType;
+pub type BlockFsIoType = block::IoType;
+
+/// 处理外部中断。从 PLIC 取出待处理的中断，交给对应的设备
+pub fn handle_external_interrupt() {
+    while let Some(irq) = plic::claim() {
+        if BLOCK_DEVICE.irq() == Some(irq) {
+            BLOCK_DEVICE.handle_irq();
+        } else if !net::handle_net_irq(irq) && !virtio_9p::handle_9p_irq(irq) {
+            warn!("unexpected external interrupt {}", irq);
+        }
+        plic::complete(irq);
+    }
+}
@@ -0,0 +1,39 @@
+//! 网卡驱动
+
+mod net_device;
+mod virtio_net;
+
+pub use net_device::NetDevice;
+use virtio_net::VirtIONetDevice;
+
+use alloc::sync::Arc;
+use lock::Mutex;
+
+lazy_static::lazy_static! {
+    /// 网卡。没有找到 virtio-net 设备时为 None，此时只能使用本地回环网络
+    pub static ref NET_DEVICE: Option<Arc<dyn NetDevice>> =
+        VirtIONetDevice::probe().map(|dev| Arc::new(dev) as Arc<dyn NetDevice>);
+}
+
+/// 网卡中断时调用的函数，由网卡上的协议栈注册。驱动不知道协议栈，所以通过它通知协议栈去轮询网卡
+static NET_IRQ_HANDLER: Mutex<Option<fn()>> = Mutex::new(None);
+
+/// 注册网卡中断时调用的函数
+pub fn set_net_irq_handler(handler: fn()) {
+    *NET_IRQ_HANDLER.lock() = Some(handler);
+}
+
+/// 处理网卡的中断。返回 irq 是否是网卡的中断
+pub fn handle_net_irq(irq: usize) -> bool {
+    match NET_DEVICE.as_ref() {
+        Some(dev) if dev.irq() == irq => {
+            dev.handle_irq();
+            let handler = *NET_IRQ_HANDLER.lock();
+            if let Some(handler) = handler {
+                handler();
+            }
+            true
+        }
+        _ => false,
+    }
+}
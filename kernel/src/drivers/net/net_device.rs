@@ -0,0 +1,18 @@
+use core::any::Any;
+/// 收发以太网帧的网卡的规范
+pub trait NetDevice: Send + Sync + Any {
+    /// 网卡的 MAC 地址
+    fn mac(&self) -> [u8; 6];
+    /// 是否可以发送一帧
+    fn can_send(&self) -> bool;
+    /// 是否收到了帧
+    fn can_recv(&self) -> bool;
+    /// 发送一帧，返回是否成功
+    fn send(&self, buf: &[u8]) -> bool;
+    /// 收一帧到 buf，返回帧的长度。没有收到帧时返回 None
+    fn recv(&self, buf: &mut [u8]) -> Option<usize>;
+    /// 网卡在 PLIC 中的中断号
+    fn irq(&self) -> usize;
+    /// 回应网卡的中断。收到的帧由协议栈在之后的轮询中取走
+    fn handle_irq(&self);
+}
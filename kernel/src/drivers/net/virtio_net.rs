@@ -0,0 +1,65 @@
+use crate::drivers::net::NetDevice;
+use crate::drivers::plic;
+use crate::drivers::virtio::{find_virtio_device, virtio_irq};
+use lock::Mutex;
+use virtio_drivers::{VirtIOHeader, VirtIONet};
+
+/// virtio 中网卡的设备类型
+const VIRTIO_DEVICE_NET: u32 = 1;
+
+pub struct VirtIONetDevice {
+    net: Mutex<VirtIONet<'static>>,
+    /// 在 PLIC 中的中断号
+    irq: usize,
+}
+
+impl NetDevice for VirtIONetDevice {
+    fn mac(&self) -> [u8; 6] {
+        self.net.lock().mac()
+    }
+    fn can_send(&self) -> bool {
+        self.net.lock().can_send()
+    }
+    fn can_recv(&self) -> bool {
+        self.net.lock().can_recv()
+    }
+    fn send(&self, buf: &[u8]) -> bool {
+        self.net.lock().send(buf).is_ok()
+    }
+    fn recv(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut net = self.net.lock();
+        if !net.can_recv() {
+            return None;
+        }
+        net.recv(buf).ok()
+    }
+    fn irq(&self) -> usize {
+        self.irq
+    }
+    fn handle_irq(&self) {
+        self.net.lock().ack_interrupt();
+    }
+}
+
+impl VirtIONetDevice {
+    /// 在 MMIO 区域中查找 virtio-net 设备并初始化。qemu 启动时没有加网卡则返回 None
+    pub fn probe() -> Option<Self> {
+        let addr = find_virtio_device(VIRTIO_DEVICE_NET)?;
+        let net = unsafe { VirtIONet::new(&mut *(addr as *mut VirtIOHeader)) };
+        match net {
+            Ok(net) => {
+                let irq = virtio_irq(addr);
+                info!("virtio-net found at {:#x}, irq {}, mac {:x?}", addr, irq, net.mac());
+                plic::enable(irq);
+                Some(Self {
+                    net: Mutex::new(net),
+                    irq: irq,
+                })
+            }
+            Err(e) => {
+                warn!("failed to init virtio-net at {:#x}: {:?}", addr, e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! 平台级中断控制器(PLIC)
+//!
+//! 外部设备的中断都经过 PLIC 转发给各个核。每个核的 S 态是 PLIC 中的一个上下文，
+//! 只有在这个上下文中使能、且优先级高于阈值的中断才会送到这个核上。
+//! 核收到中断后先 claim 拿到中断号，处理完之后再 complete，PLIC 才会继续发送这个中断
+
+use crate::arch::get_cpu_id;
+use crate::constants::{FIRST_CPU_ID, LAST_CPU_ID};
+
+/// qemu virt 机器上 PLIC 的 MMIO 地址
+const PLIC_BASE: usize = 0x0c00_0000;
+/// 使能寄存器的偏移，每个上下文占 0x80 字节，每一位对应一个中断号
+const PLIC_ENABLE: usize = 0x2000;
+/// 阈值寄存器的偏移，每个上下文占 0x1000 字节，其后紧跟 claim/complete 寄存器
+const PLIC_CONTEXT: usize = 0x20_0000;
+
+/// 第 cpu_id 个核的 S 态在 PLIC 中的上下文编号。qemu virt 上每个核依次有 M 态和 S 态两个上下文
+fn s_context(cpu_id: usize) -> usize {
+    cpu_id * 2 + 1
+}
+
+/// 当前核的 S 态在 PLIC 中的上下文编号
+fn current_context() -> usize {
+    s_context(get_cpu_id())
+}
+
+/// 在所有核上打开中断号为 irq 的中断。中断会交给先 claim 到它的核处理
+pub fn enable(irq: usize) {
+    unsafe {
+        // 优先级为 0 的中断永远不会触发
+        ((PLIC_BASE + irq * 4) as *mut u32).write_volatile(1);
+        for cpu_id in FIRST_CPU_ID..=LAST_CPU_ID {
+            let context = s_context(cpu_id);
+            let enable = (PLIC_BASE + PLIC_ENABLE + context * 0x80 + irq / 32 * 4) as *mut u32;
+            enable.write_volatile(enable.read_volatile() | 1 << (irq % 32));
+            // 阈值为 0，接收所有优先级不为 0 的中断
+            ((PLIC_BASE + PLIC_CONTEXT + context * 0x1000) as *mut u32).write_volatile(0);
+        }
+    }
+}
+
+/// 获取当前核上待处理的中断号，没有时返回 None
+pub fn claim() -> Option<usize> {
+    let claim = (PLIC_BASE + PLIC_CONTEXT + current_context() * 0x1000 + 4) as *const u32;
+    match unsafe { claim.read_volatile() } {
+        0 => None,
+        irq => Some(irq as usize),
+    }
+}
+
+/// 告诉 PLIC 中断 irq 已处理完
+pub fn complete(irq: usize) {
+    let complete = (PLIC_BASE + PLIC_CONTEXT + current_context() * 0x1000 + 4) as *mut u32;
+    unsafe { complete.write_volatile(irq as u32) };
+}
@@ -0,0 +1,69 @@
+//! virtio 设备的公共部分
+//!
+//! 包括 virtio-drivers 需要的 DMA 和地址转换接口，以及在 MMIO 区域中查找设备
+
+use crate::memory::{phys_to_virt, virt_to_phys, Frame, PhysAddr, VirtAddr};
+use alloc::{collections::BTreeMap, vec::Vec};
+use lock::Mutex;
+
+/// 第一个 virtio 设备的 MMIO 地址，块设备固定在这里
+const VIRTIO0: usize = 0x10001000;
+/// qemu virt 机器上 virtio 设备的数量，每个设备占一页
+const VIRTIO_SLOTS: usize = 8;
+/// virtio MMIO 寄存器开头的魔数，即 "virt"
+const VIRTIO_MAGIC: u32 = 0x74726976;
+
+/// 各个 virtio 设备的队列占用的页帧，按起始物理地址索引
+static QUEUE_FRAMES: Mutex<BTreeMap<PhysAddr, Frame>> = Mutex::new(BTreeMap::new());
+
+/// 查找第一个设备类型为 device_id 的 virtio 设备，返回它的 MMIO 地址
+pub fn find_virtio_device(device_id: u32) -> Option<usize> {
+    find_virtio_devices(device_id).into_iter().next()
+}
+
+/// 查找所有设备类型为 device_id 的 virtio 设备，返回它们的 MMIO 地址
+pub fn find_virtio_devices(device_id: u32) -> Vec<usize> {
+    (0..VIRTIO_SLOTS)
+        .map(|i| VIRTIO0 + i * 0x1000)
+        .filter(|&addr| unsafe {
+            // 寄存器依次为 MagicValue、Version、DeviceID
+            let regs = addr as *const u32;
+            regs.read_volatile() == VIRTIO_MAGIC && regs.add(2).read_volatile() == device_id
+        })
+        .collect()
+}
+
+/// MMIO 地址为 addr 的 virtio 设备在 PLIC 中的中断号。qemu virt 上第 i 个 virtio 设备的中断号是 i + 1
+pub fn virtio_irq(addr: usize) -> usize {
+    (addr - VIRTIO0) / 0x1000 + 1
+}
+
+#[no_mangle]
+/// 这里用 new_contiguous 可以要求分配一段连续的内存。
+/// 因为这个函数是面向 virtio-drivers 的接口，而且仅在内核启动时初始化，
+/// 所以这里默认可以拿到需要的空间，不处理分配失败导致的异常
+pub extern "C" fn virtio_dma_alloc(pages: usize) -> PhysAddr {
+    let frame = Frame::new_contiguous(pages, 0).unwrap();
+    let paddr = frame.start_paddr();
+    QUEUE_FRAMES.lock().insert(paddr, frame);
+    paddr
+}
+
+#[no_mangle]
+/// Frame 在 Drop 时会释放页帧，所以这里不用做其他处理
+pub extern "C" fn virtio_dma_dealloc(pa: PhysAddr, _pages: usize) -> i32 {
+    QUEUE_FRAMES.lock().remove(&pa);
+    0
+}
+
+#[no_mangle]
+/// 默认只在内核态才访问设备，所以直接用内核固定偏移的虚拟地址
+pub extern "C" fn virtio_phys_to_virt(paddr: PhysAddr) -> VirtAddr {
+    phys_to_virt(paddr)
+}
+
+#[no_mangle]
+/// 默认只在内核态才访问设备，所以直接用内核固定偏移的虚拟地址
+pub extern "C" fn virtio_virt_to_phys(vaddr: VirtAddr) -> PhysAddr {
+    virt_to_phys(vaddr)
+}
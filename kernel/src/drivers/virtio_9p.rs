@@ -0,0 +1,182 @@
+//! virtio-9p 设备的驱动
+//!
+//! 只负责把 9P 消息交给设备并取回回复，消息的格式由 file/p9 中的客户端处理。
+//! 和块设备一样按 legacy 的 virtio-mmio 接口直接操作设备：
+//! - 每个设备只有一个请求队列，每个请求占两个描述符，依次是请求消息和设备写回的回复
+//! - 请求和回复放在设备自己的缓冲区里，同一时间只有一个请求，所以不需要区分 9P 的 tag。
+//!   其他请求在等待队列上睡眠，直到前一个请求完成
+//! - 设备完成请求后通过 PLIC 发来中断，唤醒等待的线程。
+//!   醒来的线程中，先拿到锁的那个把回复复制出来交给发出请求的线程，之后设备就可以处理下一个请求了。
+//!   这样即使发出请求的线程还没被调度，等待设备的其他线程也不会一直等下去
+
+use super::plic;
+use super::virtio::{find_virtio_devices, virtio_irq};
+use super::virtio_mmio::{wait_for_device, Descriptor, MmioRegs, Ring, DESC_F_NEXT, DESC_F_WRITE};
+use crate::constants::PAGE_SIZE;
+use crate::file::WaitQueue;
+use crate::memory::{phys_to_virt, Frame};
+use crate::syscall::ErrorNo;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use lock::Mutex;
+
+/// virtio 中 9p 设备的设备类型
+const VIRTIO_DEVICE_9P: u32 = 9;
+
+/// 设备在配置空间中提供 mount tag 的特性。配置空间是 16 位的 tag 长度，之后是 tag
+const FEATURE_MOUNT_TAG: u32 = 1;
+
+/// 队列的长度。每次只有一个请求，用不到更多描述符
+const QUEUE_SIZE: usize = 2;
+
+/// 一条 9P 消息的最大长度，也是向服务端申请的 msize
+pub const P9_MAX_MESSAGE: usize = 0x10000;
+
+pub struct VirtIO9p {
+    regs: MmioRegs,
+    /// 在 PLIC 中的中断号
+    irq: usize,
+    /// 设备的 mount tag，挂载时用它选择设备
+    tag: String,
+    inner: Mutex<P9Queue>,
+    /// 等待设备空闲或者请求完成的线程，设备的中断和请求完成时唤醒
+    waiters: WaitQueue,
+}
+
+/// 设备的队列和收发缓冲区
+struct P9Queue {
+    ring: Ring,
+    /// 请求消息的缓冲区
+    request: Frame,
+    /// 回复消息的缓冲区
+    response: Frame,
+    /// 正在设备上处理的请求的编号
+    current: Option<usize>,
+    /// 已完成的请求的回复，按请求编号索引
+    finished: BTreeMap<usize, Vec<u8>>,
+    /// 下一个请求的编号
+    next_token: usize,
+}
+
+lazy_static::lazy_static! {
+    /// 所有的 virtio-9p 设备
+    pub static ref P9_DEVICES: Vec<VirtIO9p> = find_virtio_devices(VIRTIO_DEVICE_9P)
+        .into_iter()
+        .filter_map(VirtIO9p::new)
+        .collect();
+}
+
+/// 按 mount tag 查找 virtio-9p 设备
+pub fn find_9p_device(tag: &str) -> Option<&'static VirtIO9p> {
+    P9_DEVICES.iter().find(|dev| dev.tag == tag)
+}
+
+/// 如果 irq 是某个 virtio-9p 设备的中断，处理它并返回 true
+pub fn handle_9p_irq(irq: usize) -> bool {
+    match P9_DEVICES.iter().find(|dev| dev.irq == irq) {
+        Some(dev) => {
+            dev.regs.ack_interrupt();
+            dev.waiters.wake_all();
+            true
+        }
+        None => false,
+    }
+}
+
+impl VirtIO9p {
+    /// 初始化 base 处的设备。不是 legacy 接口或者分配不到缓冲区时返回 None
+    fn new(base: usize) -> Option<Self> {
+        let regs = MmioRegs::new(base);
+        let ring = Ring::new(QUEUE_SIZE)?;
+        // 只需要 mount tag 这一个特性
+        if !regs.init(FEATURE_MOUNT_TAG, &ring) {
+            warn!("virtio-9p at {:#x}: only legacy virtio-mmio is supported", base);
+            return None;
+        }
+        let request = Frame::new_contiguous(P9_MAX_MESSAGE / PAGE_SIZE, 0)?;
+        let response = Frame::new_contiguous(P9_MAX_MESSAGE / PAGE_SIZE, 0)?;
+        // 配置空间按字节读取 tag
+        let tag_len = regs.config_u8(0) as usize | (regs.config_u8(1) as usize) << 8;
+        let tag: Vec<u8> = (0..tag_len).map(|i| regs.config_u8(2 + i)).collect();
+        let tag: String = String::from_utf8_lossy(&tag).into();
+        let irq = virtio_irq(base);
+        plic::enable(irq);
+        info!("virtio-9p found at {:#x}, irq {}, tag {:?}", base, irq, tag);
+        Some(Self {
+            regs: regs,
+            irq: irq,
+            tag: tag,
+            inner: Mutex::new(P9Queue {
+                ring: ring,
+                request: request,
+                response: response,
+                current: None,
+                finished: BTreeMap::new(),
+                next_token: 0,
+            }),
+            waiters: WaitQueue::new(),
+        })
+    }
+    /// 发送一条 9P 消息，等待设备回复，返回回复的消息。
+    /// 消息超过 P9_MAX_MESSAGE 时返回 EINVAL
+    pub fn request(&self, request: &[u8]) -> Result<Vec<u8>, ErrorNo> {
+        if request.len() > P9_MAX_MESSAGE {
+            return Err(ErrorNo::EINVAL);
+        }
+        // 设备同一时间只处理一个请求，前一个请求完成之后才能提交
+        let token = wait_for_device(&self.waiters, || {
+            let mut inner = self.inner.lock();
+            inner.collect();
+            if inner.current.is_some() {
+                return None;
+            }
+            Some(inner.submit(request))
+        });
+        self.regs.notify();
+        Ok(wait_for_device(&self.waiters, || {
+            let mut inner = self.inner.lock();
+            inner.collect();
+            inner.finished.remove(&token)
+        }))
+    }
+}
+
+impl P9Queue {
+    /// 把 request 复制到请求缓冲区，放进可用环，返回请求的编号。
+    /// 队列中只有这一个请求，所以固定使用描述符 0 和 1
+    fn submit(&mut self, request: &[u8]) -> usize {
+        let request_paddr = self.request.start_paddr();
+        let response_paddr = self.response.start_paddr();
+        unsafe {
+            core::slice::from_raw_parts_mut(phys_to_virt(request_paddr) as *mut u8, request.len())
+                .copy_from_slice(request);
+        }
+        *self.ring.desc(0) = Descriptor {
+            addr: request_paddr as u64,
+            len: request.len() as u32,
+            flags: DESC_F_NEXT,
+            next: 1,
+        };
+        *self.ring.desc(1) = Descriptor {
+            addr: response_paddr as u64,
+            len: P9_MAX_MESSAGE as u32,
+            flags: DESC_F_WRITE,
+            next: 0,
+        };
+        self.ring.push_avail(0);
+        let token = self.next_token;
+        self.next_token += 1;
+        self.current = Some(token);
+        token
+    }
+    /// 如果设备已经完成了当前的请求，把回复复制出来，之后设备可以处理下一个请求
+    fn collect(&mut self) {
+        if let Some(token) = self.current {
+            if let Some((_, len)) = self.ring.pop_used() {
+                let len = len.min(P9_MAX_MESSAGE);
+                let reply = unsafe { core::slice::from_raw_parts(phys_to_virt(self.response.start_paddr()) as *const u8, len) };
+                self.finished.insert(token, Vec::from(reply));
+                self.current = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! legacy virtio-mmio 接口
+//!
+//! 块设备和 9p 设备不经过 virtio-drivers，而是按 legacy 的 virtio-mmio 接口直接操作设备，各自只使用队列 0。
+//! 这里是它们共用的部分：寄存器和设备的初始化、队列在内存中的布局，以及等待设备完成请求。
+//!
+//! 等待设备时，线程登记在等待队列上并让出 CPU，由设备的中断唤醒。
+//! 但如果线程还拿着锁(比如文件系统的锁)，切换到其他线程可能导致死锁，
+//! 此时不让出 CPU，而是用 wfi 睡眠，直到设备的中断把它叫醒
+
+use crate::file::WaitQueue;
+use crate::memory::{phys_to_virt, Frame};
+use crate::syscall::{set_waiter_for_thread, wake_thread, FutexWaiter};
+use crate::task::{can_suspend_current_task, get_current_task, suspend_current_task};
+use crate::trap::handle_pending_interrupts;
+use alloc::boxed::Box;
+use core::mem::size_of;
+use core::sync::atomic::{fence, Ordering};
+
+/// 寄存器的偏移
+const REG_VERSION: usize = 0x004;
+const REG_HOST_FEATURES: usize = 0x010;
+const REG_GUEST_FEATURES: usize = 0x020;
+const REG_GUEST_PAGE_SIZE: usize = 0x028;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_ALIGN: usize = 0x03c;
+const REG_QUEUE_PFN: usize = 0x040;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+/// 配置空间的偏移，其中的内容由设备类型决定
+const REG_CONFIG: usize = 0x100;
+
+/// 设备状态位
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+
+/// legacy 接口下队列的对齐要求，已用环从这个边界开始
+const QUEUE_ALIGN: usize = 0x1000;
+/// 队列占用的页数：描述符表和可用环在第一页，已用环在第二页
+const QUEUE_PAGES: usize = 2;
+
+/// 描述符的标志位
+pub const DESC_F_NEXT: u16 = 1;
+pub const DESC_F_WRITE: u16 = 2;
+
+/// 队列中的描述符
+#[repr(C)]
+pub struct Descriptor {
+    pub addr: u64,
+    pub len: u32,
+    pub flags: u16,
+    pub next: u16,
+}
+
+/// 设备的寄存器
+pub struct MmioRegs {
+    /// MMIO 寄存器的地址
+    base: usize,
+}
+
+impl MmioRegs {
+    pub fn new(base: usize) -> Self {
+        Self { base: base }
+    }
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+    /// 重置设备，按顺序设置状态位，只接受 features 中设备支持的特性，然后把 ring 设置为队列 0。
+    /// 设备不是 legacy 接口或者队列不够长时返回 false
+    pub fn init(&self, features: u32, ring: &Ring) -> bool {
+        if self.read(REG_VERSION) != 1 {
+            return false;
+        }
+        self.write(REG_STATUS, 0);
+        self.write(REG_STATUS, STATUS_ACKNOWLEDGE);
+        self.write(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        self.write(REG_GUEST_FEATURES, self.read(REG_HOST_FEATURES) & features);
+        self.write(REG_GUEST_PAGE_SIZE, QUEUE_ALIGN as u32);
+        self.write(REG_QUEUE_SEL, 0);
+        if (self.read(REG_QUEUE_NUM_MAX) as usize) < ring.size {
+            return false;
+        }
+        self.write(REG_QUEUE_NUM, ring.size as u32);
+        self.write(REG_QUEUE_ALIGN, QUEUE_ALIGN as u32);
+        self.write(REG_QUEUE_PFN, (ring.frame.start_paddr() / QUEUE_ALIGN) as u32);
+        self.write(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK);
+        true
+    }
+    /// 通知设备处理队列中新提交的请求
+    pub fn notify(&self) {
+        // 设备收到通知时，可用环的更新必须已经可见
+        fence(Ordering::SeqCst);
+        self.write(REG_QUEUE_NOTIFY, 0);
+    }
+    /// 回应设备的中断
+    pub fn ack_interrupt(&self) {
+        self.write(REG_INTERRUPT_ACK, self.read(REG_INTERRUPT_STATUS));
+    }
+    /// 按 32 位读取配置空间中 offset 处的值
+    pub fn config_u32(&self, offset: usize) -> u32 {
+        self.read(REG_CONFIG + offset)
+    }
+    /// 按字节读取配置空间中 offset 处的值
+    pub fn config_u8(&self, offset: usize) -> u8 {
+        unsafe { ((self.base + REG_CONFIG + offset) as *const u8).read_volatile() }
+    }
+}
+
+/// 设备的队列，即描述符表、可用环和已用环
+pub struct Ring {
+    /// 队列所在的页帧
+    frame: Frame,
+    /// 队列的长度，即描述符的个数
+    size: usize,
+    /// 下一个放进可用环的位置
+    avail_idx: u16,
+    /// 已用环中下一个要处理的位置
+    used_idx: u16,
+}
+
+impl Ring {
+    /// 分配长度为 size 的队列。分配不到连续的页帧时返回 None
+    pub fn new(size: usize) -> Option<Self> {
+        let mut frame = Frame::new_contiguous(QUEUE_PAGES, 0)?;
+        frame.zero();
+        Some(Self {
+            frame: frame,
+            size: size,
+            avail_idx: 0,
+            used_idx: 0,
+        })
+    }
+    /// 队列的虚拟地址
+    fn base(&self) -> usize {
+        phys_to_virt(self.frame.start_paddr())
+    }
+    pub fn desc(&mut self, id: u16) -> &mut Descriptor {
+        unsafe { &mut *(self.base() as *mut Descriptor).add(id as usize) }
+    }
+    /// 把以 head 开头的描述符链放进可用环。可用环紧跟在描述符表之后，依次是 flags、idx 和 ring
+    pub fn push_avail(&mut self, head: u16) {
+        let avail = (self.base() + size_of::<Descriptor>() * self.size) as *mut u16;
+        unsafe {
+            avail.add(2 + self.avail_idx as usize % self.size).write_volatile(head);
+            // 设备看到 idx 更新时，描述符和环中的内容必须已经写好
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            avail.add(1).write_volatile(self.avail_idx);
+        }
+    }
+    /// 取出已用环中的下一项，返回设备完成的描述符链的第一个描述符，以及设备写入的长度。
+    /// 设备没有完成新的请求时返回 None。
+    ///
+    /// 已用环从下一个对齐边界开始，依次是 flags、idx 和 ring，ring 中每项为 (id: u32, len: u32)
+    pub fn pop_used(&mut self) -> Option<(u16, usize)> {
+        let used = (self.base() + QUEUE_ALIGN) as *const u16;
+        fence(Ordering::SeqCst);
+        if self.used_idx == unsafe { used.add(1).read_volatile() } {
+            return None;
+        }
+        // 看到 idx 更新之后，设备写回的数据才一定可见
+        fence(Ordering::SeqCst);
+        let elem = unsafe { (used.add(2) as *const u32).add(self.used_idx as usize % self.size * 2) };
+        let (id, len) = unsafe { (elem.read_volatile() as u16, elem.add(1).read_volatile() as usize) };
+        self.used_idx = self.used_idx.wrapping_add(1);
+        Some((id, len))
+    }
+}
+
+/// 反复尝试 f，直到它返回 Some。每次失败后在 waiters 上睡眠，直到被设备的中断唤醒。
+///
+/// f 中需要放掉设备的锁再返回，因为中断处理时也要拿这把锁
+pub fn wait_for_device<T>(waiters: &WaitQueue, mut f: impl FnMut() -> Option<T>) -> T {
+    let tid = if can_suspend_current_task() {
+        get_current_task().map(|task| task.get_tid_num())
+    } else {
+        None
+    };
+    loop {
+        if let Some(tid) = tid {
+            // 先登记再检查，这样检查之后、睡眠之前完成的请求也能唤醒线程
+            set_waiter_for_thread(tid, Box::new(FutexWaiter::new(None)));
+            waiters.register(tid);
+        }
+        if let Some(ret) = f() {
+            if let Some(tid) = tid {
+                waiters.unregister(tid);
+                // 清除 WAITING_BOARD 上还没被唤醒的 waiter
+                wake_thread(tid);
+            }
+            return ret;
+        }
+        if tid.is_some() {
+            suspend_current_task();
+        } else {
+            // 关着全局中断时 wfi 也会被已使能的中断唤醒，所以检查之后、睡眠之前到来的中断不会被错过。
+            // 醒来后短暂打开全局中断，让中断在 trap 中被处理
+            unsafe { riscv::asm::wfi() };
+            handle_pending_interrupts();
+        }
+    }
+}
@@ -32,11 +32,15 @@ pub enum OSError {
     // 一般是因为找不到地址所对应的 VmArea，
     // 相当于表示用户程序传过来的地址不合法
     PageFaultHandler_Unhandled,
+    // 在vmm.rs
+    // 向下增长的用户栈超过了 RLIMIT_STACK，或者碰到了其他内存段
+    PageFaultHandler_StackOverflow,
 
     PmArea_OutOfRange,
     PmArea_InvalidRange,
     PmArea_ShrinkFailed,
     PmArea_SplitFailed,
+    PmArea_GrowFailed,
     PmAreaLazy_ReleaseNotAllocatedPage,
 
     // 没有空的*物理*页
@@ -3,10 +3,10 @@
 
 //#![deny(missing_docs)]
 
-use super::{get_link_count, File, FsFile, OpenFlags};
+use super::{get_link_count, FATFileSystem, File, FsFile, OpenFlags};
 use crate::{
-    constants::FS_IMG_SIZE,
-    file::{normal_file_mode, Kstat, StMode},
+    drivers::BLOCK_CACHE,
+    file::{normal_file_mode, user_path, Kstat, StMode},
     timer::TimeSpec,
 };
 use alloc::{string::String, sync::Arc, vec::Vec};
@@ -30,6 +30,8 @@ pub struct FatFile {
     pub inner: Mutex<FatFileInnner>,
     /// 内部实际文件
     pub file: Arc<Mutex<FsFile>>,
+    /// 所在的文件系统。file 借用了它，所以这个字段要放在 file 之后，保证 file 先被释放
+    _fs: Arc<FATFileSystem>,
 }
 
 /// 文件在os中运行时的可变信息
@@ -43,6 +45,8 @@ pub struct FatFileInnner {
     /// 打开时的选项。
     /// 主要用于判断 CLOEXEC，即 exec 时是否关闭。默认为 false。
     pub flags: OpenFlags,
+    /// 上次读写失败是否是因为设备出错
+    pub io_error: bool,
 }
 
 impl FatFile {
@@ -54,6 +58,7 @@ impl FatFile {
         name: String,
         fs_file: FsFile,
         flags: OpenFlags,
+        fs: Arc<FATFileSystem>,
     ) -> Self {
         Self {
             readable: readable,
@@ -66,7 +71,9 @@ impl FatFile {
                 mtime: TimeSpec::default(), // 因为 FAT 里的时间结构非常粗略，而且精度很低，
                 ctime: TimeSpec::default(), // 不好适应实际操作中用到的秒/纳秒量级
                 flags: flags,
+                io_error: false,
             }),
+            _fs: fs,
         }
     }
 }
@@ -89,9 +96,10 @@ impl File for FatFile {
                         pos += read_len;
                     }
                 }
-                Err(_) => {
+                Err(e) => {
                     if pos == 0 {
                         // 如果什么都没读到，则报错
+                        self.inner.lock().io_error = matches!(e, fatfs::Error::Io(_));
                         return None;
                     } else {
                         //否则说明还是读了一些的
@@ -119,8 +127,9 @@ impl File for FatFile {
                         pos += write_len;
                     }
                 }
-                Err(_) => {
+                Err(e) => {
                     if pos == 0 {
+                        self.inner.lock().io_error = matches!(e, fatfs::Error::Io(_));
                         return None;
                     } else {
                         return Some(pos);
@@ -141,9 +150,14 @@ impl File for FatFile {
         temp.resize(len, 0);
         let mut pos = 0;
         while pos < len {
-            let read_len = file.read(&mut temp[pos..]).unwrap();
-            //println!("read {} bytes", read_len);
-            pos += read_len;
+            match file.read(&mut temp[pos..]) {
+                Ok(read_len) if read_len > 0 => pos += read_len,
+                // 设备出错时只返回读到的部分，由调用者发现内容不完整
+                _ => {
+                    temp.truncate(pos);
+                    break;
+                }
+            }
         }
         /*
         // println!("{} {} {} {}", temp[0], temp[1], temp[2], temp[3]); // elf
@@ -187,6 +201,14 @@ impl File for FatFile {
         }
         true
     }
+    /// 文件的完整路径
+    fn get_path(&self) -> Option<String> {
+        Some(user_path(&(self.dir.clone() + &self.name)))
+    }
+    /// 取出并清除设备出错的标记
+    fn take_io_error(&self) -> bool {
+        core::mem::take(&mut self.inner.lock().io_error)
+    }
     /// 切换文件指针位置
     fn seek(&self, seekfrom: SeekFrom) -> Option<usize> {
         let mut file = self.file.lock();
@@ -196,7 +218,7 @@ impl File for FatFile {
                 match seekfrom {
                     SeekFrom::Start(origin) => {
                         let len = file.seek(SeekFrom::End(0)).unwrap();
-                        if len < origin && origin - len <= FS_IMG_SIZE as u64 {
+                        if len < origin && origin - len <= BLOCK_CACHE.size() as u64 {
                             let mut buf: Vec<u8> = Vec::new();
                             buf.resize(origin as usize - len as usize, 0);
                             file.write(buf.as_slice()).unwrap();
@@ -261,6 +283,27 @@ impl File for FatFile {
     fn get_status(&self) -> OpenFlags {
         self.inner.lock().flags
     }
+    /// 修改文件大小。变大时补 0，不改变文件指针的位置
+    fn truncate(&self, len: usize) -> bool {
+        if !self.writable {
+            return false;
+        }
+        let mut file = self.file.lock();
+        let pos = file.seek(SeekFrom::Current(0)).unwrap();
+        let size = file.seek(SeekFrom::End(0)).unwrap() as usize;
+        let result = if len > size {
+            let buf = vec![0u8; len - size];
+            file.write_all(buf.as_slice())
+        } else {
+            file.seek(SeekFrom::Start(len as u64)).and_then(|_| file.truncate())
+        };
+        file.seek(SeekFrom::Start(pos)).unwrap();
+        if let Err(e) = result {
+            self.inner.lock().io_error = matches!(e, fatfs::Error::Io(_));
+            return false;
+        }
+        true
+    }
     /// 清空文件
     fn clear(&self) {
         let mut file = self.file.lock();
@@ -0,0 +1,115 @@
+//! 只读的 FAT 文件系统，用作 overlayfs 的下层
+//!
+//! FAT 本身没有权限、所有者和符号链接，这里按 UnixFs 的接口提供目录和普通文件，所有修改都返回 EROFS。
+//! 文件系统需要用 new_fat_fs 以只读方式打开，这样读文件时也不会更新访问时间而写回镜像
+
+use super::{stat, FATFileSystem, FatFile, FdDir, OpenedDir};
+use crate::file::{File, FsStat, OpenFlags, StMode, UnixFs};
+use crate::syscall::ErrorNo;
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+/// 只读的 FAT 文件系统
+pub struct FatLower {
+    fs: Arc<FATFileSystem>,
+}
+
+impl FatLower {
+    pub fn new(fs: Arc<FATFileSystem>) -> Self {
+        Self { fs: fs }
+    }
+    /// 打开目录 dir
+    fn open_dir(&self, dir: &str) -> Option<OpenedDir> {
+        OpenedDir::open(&self.fs, dir)
+    }
+    /// dir 目录下的 name 是否存在，存在时返回它是否是目录
+    fn find(&self, dir: &str, name: &str) -> Option<bool> {
+        self.open_dir(dir)?
+            .iter()
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.file_name() == name)
+            .map(|entry| entry.is_dir())
+    }
+}
+
+impl UnixFs for FatLower {
+    /// 只能以只读方式打开已存在的文件和目录
+    fn open(self: Arc<Self>, dir: &str, name: &str, full_dir: &str, flags: OpenFlags) -> Option<Arc<dyn File>> {
+        if flags.writable() || flags.contains(OpenFlags::CREATE) {
+            return None;
+        }
+        let is_dir = if name.is_empty() {
+            self.open_dir(dir).map(|_| true)?
+        } else {
+            self.find(dir, name)?
+        };
+        if is_dir {
+            return Some(Arc::new(FdDir::new(String::from(full_dir) + name)));
+        }
+        if flags.contains(OpenFlags::DIR) || flags.contains(OpenFlags::DSYNC) {
+            return None;
+        }
+        let file = self.open_dir(dir)?.open_file(name).ok()?;
+        Some(Arc::new(FatFile::new(
+            true,
+            false,
+            String::from(full_dir),
+            String::from(name),
+            file,
+            flags,
+            self.fs.clone(),
+        )))
+    }
+    fn exists(&self, dir: &str, name: &str) -> Option<bool> {
+        if name.is_empty() {
+            self.open_dir(dir).map(|_| true)
+        } else {
+            self.find(dir, name)
+        }
+    }
+    fn mkdir(&self, _dir: &str, _name: &str) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EROFS)
+    }
+    fn remove(&self, _dir: &str, _name: &str) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EROFS)
+    }
+    fn rename(&self, _old_dir: &str, _old_name: &str, _new_dir: &str, _new_name: &str, _replace: bool) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EROFS)
+    }
+    fn link(&self, _old_dir: &str, _old_name: &str, _new_dir: &str, _new_name: &str) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EROFS)
+    }
+    fn symlink(&self, _target: &str, _dir: &str, _name: &str) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EROFS)
+    }
+    fn mknod(&self, _dir: &str, _name: &str, _mode: u32, _rdev: u64) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EROFS)
+    }
+    /// FAT 中没有符号链接
+    fn read_link(&self, _dir: &str, _name: &str) -> Result<String, ErrorNo> {
+        Err(ErrorNo::EINVAL)
+    }
+    fn chmod(&self, _dir: &str, _name: &str, _mode: u32) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EROFS)
+    }
+    fn chown(&self, _dir: &str, _name: &str, _uid: Option<u32>, _gid: Option<u32>, _follow: bool) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EROFS)
+    }
+    /// FAT 中只区分目录和普通文件
+    fn list(&self, dir: &str) -> Result<Vec<(String, StMode)>, ErrorNo> {
+        let dir = self.open_dir(dir).ok_or(ErrorNo::ENOENT)?;
+        Ok(dir
+            .iter()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let mode = if entry.is_dir() { StMode::S_IFDIR } else { StMode::S_IFREG };
+                (entry.file_name(), mode)
+            })
+            .collect())
+    }
+    fn fs_type(&self) -> &'static str {
+        "vfat"
+    }
+    fn stat_fs(&self, stat: *mut FsStat) {
+        stat::get_fs_stat(stat);
+    }
+}
@@ -4,9 +4,14 @@
 
 //#![deny(missing_docs)]
 
-use super::{check_dir_exists, check_file_exists, remove_file, split_path_and_file};
+use super::{
+    check_dir_exists, check_file_exists, link_in_same_unix_fs, remove_file, split_path_and_file, Ext2FileSystem,
+    FATFileSystem, P9FileSystem, TmpFs, UnixFs, PROC_FS,
+};
 use crate::constants::ROOT_DIR;
-use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use crate::drivers::{block_device_nodes, find_9p_device, new_fat_fs, root_fs_range, BLOCK_CACHE};
+use crate::syscall::ErrorNo;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
 use lock::Mutex;
 
 /// 用户看到的文件到实际文件的映射
@@ -97,6 +102,10 @@ pub fn try_add_link(old_path: String, old_file: &str, new_path: String, new_file
             .map(|(path, file)| (path, String::from(file)))
             .map(parse_file_name)
         {
+            // 两边都在同一个 ext2 或 9p 文件系统中时，直接在文件系统里创建硬链接
+            if let Some(linked) = link_in_same_unix_fs(&old_path, &old_file, &new_path, &new_file) {
+                return linked;
+            }
             if check_file_exists(old_path.as_str(), old_file.as_str())
                 && !check_file_exists(new_path.as_str(), new_file.as_str())
             {
@@ -180,21 +189,68 @@ impl FileDisc {
     }
 }
 
+/// 一个文件系统实例。打开的文件也持有所在文件系统的引用
+#[derive(Clone)]
+pub enum FsRef {
+    Fat(Arc<FATFileSystem>),
+    /// 带有 Unix 元数据的文件系统，即 ext2 或 9p
+    Unix(Arc<dyn UnixFs>),
+}
+
+impl FsRef {
+    /// 文件系统的类型
+    pub fn fs_type(&self) -> &'static str {
+        match self {
+            FsRef::Fat(_) => "vfat",
+            FsRef::Unix(fs) => fs.fs_type(),
+        }
+    }
+    /// 是否是同一个文件系统实例
+    pub fn same(&self, other: &FsRef) -> bool {
+        match (self, other) {
+            (FsRef::Fat(a), FsRef::Fat(b)) => Arc::ptr_eq(a, b),
+            (FsRef::Unix(a), FsRef::Unix(b)) => same_unix_fs(&**a, &**b),
+            _ => false,
+        }
+    }
+    /// 除了挂载点之外，是否还有打开的文件等在引用这个文件系统。
+    /// procfs 还被 PROC_FS 引用，但它的文件不引用文件系统，所以总是可以卸载
+    fn in_use(&self) -> bool {
+        match self {
+            FsRef::Fat(fs) => Arc::strong_count(fs) > 1,
+            FsRef::Unix(fs) => Arc::strong_count(fs) > 1 && !same_unix_fs(&**fs, &**PROC_FS),
+        }
+    }
+}
+
+/// 两个 UnixFs 是否是同一个实例。只比较数据指针，不比较虚表
+pub fn same_unix_fs(a: &dyn UnixFs, b: &dyn UnixFs) -> bool {
+    a as *const dyn UnixFs as *const u8 == b as *const dyn UnixFs as *const u8
+}
+
 /// 挂载的文件系统。
-/// 目前"挂载"的语义是，把一个文件当作文件系统读写
-/// TODO: 把 mod.rs 中文件系统的操作全部封装为 struct，然后挂载时用文件实例化它
+///
+/// 挂载 /dev 下的块设备文件(如 /dev/vda2)时，会在设备对应的区间上打开一个 FAT 或 ext2 文件系统，
+/// 挂载 9p 时会连接 mount tag 对应的 virtio-9p 设备，
+/// 之后挂载点下的路径都在这个文件系统中查找。挂载其他文件时仍然只记录挂载信息。
+/// 打开的文件持有所在的文件系统，所以它们都关闭之后才能卸载
 pub struct MountedFs {
-    //pub inner: Arc<Mutex<FATFileSystem>>,
+    /// 挂载的设备文件。9p 文件系统是 mount tag
     pub device: String,
     pub mnt_dir: String,
+    /// 挂载的文件系统。只记录挂载信息时为 None
+    pub fs: Option<FsRef>,
+    /// 文件系统在块设备上的起始位置。不在块设备上时为 None
+    pub start: Option<usize>,
 }
 
 impl MountedFs {
-    pub fn new(device: &str, mnt_dir: &str) -> Self {
+    pub fn new(device: &str, mnt_dir: &str, fs: Option<FsRef>, start: Option<usize>) -> Self {
         Self {
-            //inner: Arc::new_uninit(),
             device: String::from(device),
             mnt_dir: String::from(mnt_dir),
+            fs: fs,
+            start: start,
         }
     }
 }
@@ -203,32 +259,190 @@ impl MountedFs {
 /// 注意启动时的文件系统不在这个 vec 里，它在 mod.rs 里。
 static MOUNTED: Mutex<Vec<MountedFs>> = Mutex::new(Vec::new());
 
-/// 挂载一个fatfs类型的设备
-pub fn mount_fat_fs(device_path: String, device_file: &str, mount_path: String) -> bool {
+/// 挂载一个设备，fs_type 为 "vfat" 或 "ext2"
+pub fn mount_fs(device_path: String, device_file: &str, mount_path: String, fs_type: &str) -> Result<(), ErrorNo> {
     // 地址经过链接转换
-    if let Some((device_path, device_file)) = split_path_and_file(device_path.as_str(), device_file)
+    let (device_path, device_file) = split_path_and_file(device_path.as_str(), device_file)
         .map(|(path, file)| (path, String::from(file)))
         .map(parse_file_name)
-    {
-        let mount_path = split_path_and_file(mount_path.as_str(), "").unwrap().0;
-        // mount_path 不需要转换，因为目前目录没有链接。只需要检查其在挂在前是否存在
-        if check_dir_exists(mount_path.as_str())
-        // && check_file_exists(device_path.as_str(), device_file.as_str())
-        {
-            MOUNTED.lock().push(MountedFs::new(
-                (device_path + device_file.as_str()).as_str(),
-                mount_path.as_str(),
-            ));
-            return true;
+        .ok_or(ErrorNo::EINVAL)?;
+    let mount_path = check_mount_path(mount_path)?;
+    let device = device_path + device_file.as_str();
+    let mut mounted = MOUNTED.lock();
+    if mounted.iter().any(|mfs| mfs.mnt_dir == mount_path) {
+        return Err(ErrorNo::EBUSY);
+    }
+    let (fs, start) = match find_block_device(&device) {
+        Some((start, end)) => {
+            // 同一个分区上同时有两个文件系统实例会互相覆盖对方的修改。
+            // 根目录上挂载了 initramfs 时，块设备上的根文件系统不会被打开，所以可以挂载根分区。
+            // overlayfs 的下层是根分区，它的挂载信息中记录了根分区的位置
+            let root_in_use = start == root_fs_range().0 && !mounted.iter().any(|mfs| mfs.mnt_dir == ROOT_DIR);
+            if root_in_use || mounted.iter().any(|mfs| mfs.start == Some(start)) {
+                return Err(ErrorNo::EBUSY);
+            }
+            let fs = if fs_type == "ext2" {
+                FsRef::Unix(Arc::new(Ext2FileSystem::new(start)?))
+            } else {
+                FsRef::Fat(Arc::new(new_fat_fs(start, end, false).ok_or(ErrorNo::EINVAL)?))
+            };
+            (Some(fs), Some(start))
         }
+        // ext2 必须挂载在块设备上
+        None if fs_type == "ext2" => return Err(ErrorNo::ENOTBLK),
+        // 挂载的不是块设备。比如测例会挂载不存在的 /dev/vda2，这时和以前一样只记录挂载信息
+        None => (None, None),
+    };
+    mounted.push(MountedFs::new(device.as_str(), mount_path.as_str(), fs, start));
+    Ok(())
+}
+
+/// 挂载 mount tag 为 tag 的 virtio-9p 设备
+pub fn mount_9p_fs(tag: &str, mount_path: String) -> Result<(), ErrorNo> {
+    let mount_path = check_mount_path(mount_path)?;
+    let device = find_9p_device(tag).ok_or(ErrorNo::ENOENT)?;
+    let mut mounted = MOUNTED.lock();
+    if mounted.iter().any(|mfs| mfs.mnt_dir == mount_path) {
+        return Err(ErrorNo::EBUSY);
     }
-    false
+    // 连接时的 Tversion 会重置服务端的会话，所以同一个设备不能挂载两次
+    if mounted.iter().any(|mfs| mfs.fs.is_some() && mfs.start.is_none() && mfs.device == tag) {
+        return Err(ErrorNo::EBUSY);
+    }
+    let fs = Arc::new(P9FileSystem::new(device)?);
+    mounted.push(MountedFs::new(tag, mount_path.as_str(), Some(FsRef::Unix(fs)), None));
+    Ok(())
+}
+
+/// 所有挂载点的 (设备, 挂载目录, 文件系统类型)，按挂载的先后排列
+pub fn mounted_list() -> Vec<(String, String, &'static str)> {
+    MOUNTED
+        .lock()
+        .iter()
+        .map(|mfs| {
+            // 只记录挂载信息的一定是 vfat，因为 ext2 必须挂载在块设备上
+            let fs_type = mfs.fs.as_ref().map_or("vfat", FsRef::fs_type);
+            (mfs.device.clone(), mfs.mnt_dir.clone(), fs_type)
+        })
+        .collect()
+}
+
+/// 在 mount_path 上挂载 procfs。所有挂载点共用同一个 procfs
+pub fn mount_proc_fs(mount_path: String) -> Result<(), ErrorNo> {
+    let mount_path = check_mount_path(mount_path)?;
+    let mut mounted = MOUNTED.lock();
+    if mounted.iter().any(|mfs| mfs.mnt_dir == mount_path) {
+        return Err(ErrorNo::EBUSY);
+    }
+    mounted.push(MountedFs::new("proc", mount_path.as_str(), Some(FsRef::Unix(PROC_FS.clone())), None));
+    Ok(())
+}
+
+/// 在 mount_path 上挂载一个新的空 tmpfs。每次挂载都是独立的文件系统，卸载后其中的内容随之释放
+pub fn mount_tmp_fs(mount_path: String) -> Result<(), ErrorNo> {
+    let mount_path = check_mount_path(mount_path)?;
+    let mut mounted = MOUNTED.lock();
+    if mounted.iter().any(|mfs| mfs.mnt_dir == mount_path) {
+        return Err(ErrorNo::EBUSY);
+    }
+    mounted.push(MountedFs::new("tmpfs", mount_path.as_str(), Some(FsRef::Unix(Arc::new(TmpFs::new()))), None));
+    Ok(())
 }
 
-pub fn umount_fat_fs(mount_path: String) -> bool {
+/// 把 fs 挂载到根目录上，之后块设备上的根文件系统不会再被直接访问。
+/// 用于从 initramfs 启动，或者在根文件系统上叠加 overlayfs。
+/// start 是 fs 用到的块设备上的文件系统的起始位置，这个分区之后不能再被挂载
+pub fn mount_root_fs(device: &str, fs: Arc<dyn UnixFs>, start: Option<usize>) {
+    MOUNTED.lock().push(MountedFs::new(device, ROOT_DIR, Some(FsRef::Unix(fs)), start));
+}
+
+/// 把挂载在 from 的文件系统连同它下面的挂载点一起移动到 to，即 MS_MOVE。
+///
+/// 移动到根目录时(如 switch_root)，原来的根文件系统以及不在 from 下的挂载点都不再能访问，所以直接去掉它们。
+///
+/// 成功时返回规范化之后的 from 和 to
+pub fn move_mount(from: String, to: String) -> Result<(String, String), ErrorNo> {
+    let from = split_path_and_file(from.as_str(), "").ok_or(ErrorNo::EINVAL)?.0;
+    let to = check_mount_path(to)?;
+    // 去掉的挂载点在释放锁之后再释放，因为释放文件系统时可能要读写设备
+    let mut released: Vec<MountedFs> = Vec::new();
+    let mut mounted = MOUNTED.lock();
+    if !mounted.iter().any(|mfs| mfs.mnt_dir == from && mfs.fs.is_some()) {
+        return Err(ErrorNo::EINVAL);
+    }
+    if to == ROOT_DIR {
+        if from == ROOT_DIR {
+            return Ok((from, to));
+        }
+        released.extend(mounted.drain_filter(|mfs| !mfs.mnt_dir.starts_with(from.as_str())));
+    } else {
+        // 不能移动到自己下面
+        if to.starts_with(from.as_str()) {
+            return Err(ErrorNo::EINVAL);
+        }
+        if mounted.iter().any(|mfs| mfs.mnt_dir == to) {
+            return Err(ErrorNo::EBUSY);
+        }
+    }
+    for mfs in mounted.iter_mut() {
+        if mfs.mnt_dir.starts_with(from.as_str()) {
+            mfs.mnt_dir = to.clone() + &mfs.mnt_dir[from.len()..];
+        }
+    }
+    Ok((from, to))
+}
+
+/// 规范化挂载点的路径，并检查它是否存在
+fn check_mount_path(mount_path: String) -> Result<String, ErrorNo> {
+    let mount_path = split_path_and_file(mount_path.as_str(), "").unwrap().0;
+    // mount_path 不需要转换，因为目前目录没有链接。只需要检查其在挂在前是否存在
+    if !check_dir_exists(mount_path.as_str()) {
+        return Err(ErrorNo::ENOENT);
+    }
+    Ok(mount_path)
+}
+
+/// 如果 device 是 /dev 下的块设备文件，返回它在设备上的区间
+fn find_block_device(device: &str) -> Option<(usize, usize)> {
+    let name = device.strip_prefix("./dev/")?;
+    block_device_nodes()
+        .into_iter()
+        .find(|node| node.0 == name)
+        .map(|(_, _, start, end)| (start, end))
+}
+
+/// 找到 dir 所在的挂载的文件系统，返回文件系统和 dir 在其中的路径(以 "./" 开头)。
+/// 有多个挂载点时取最深的那个。dir 不在任何挂载的分区下时返回 None
+pub fn find_mounted_fs(dir: &str) -> Option<(FsRef, String)> {
+    MOUNTED
+        .lock()
+        .iter()
+        .filter(|mfs| mfs.fs.is_some() && dir.starts_with(mfs.mnt_dir.as_str()))
+        .max_by_key(|mfs| mfs.mnt_dir.len())
+        .map(|mfs| (mfs.fs.clone().unwrap(), String::from("./") + &dir[mfs.mnt_dir.len()..]))
+}
+
+/// 卸载文件系统。卸载分区时把缓存中的修改写回设备。9p 的修改已经直接发给了服务端，不需要写回。
+///
+/// 没有挂载时返回 EINVAL；还有打开的文件在使用这个文件系统时返回 EBUSY
+pub fn umount_fs(mount_path: String) -> Result<(), ErrorNo> {
     let mount_path = split_path_and_file(mount_path.as_str(), "").unwrap().0;
+    // 根目录上的文件系统只能被 move_mount 替换
+    if mount_path == ROOT_DIR {
+        return Err(ErrorNo::EINVAL);
+    }
     let mut mounted = MOUNTED.lock();
-    let size_before = mounted.len();
-    mounted.retain(|mfs| mfs.mnt_dir != mount_path);
-    mounted.len() < size_before
+    let pos = mounted.iter().position(|mfs| mfs.mnt_dir == mount_path).ok_or(ErrorNo::EINVAL)?;
+    if mounted[pos].fs.as_ref().map_or(false, FsRef::in_use) {
+        return Err(ErrorNo::EBUSY);
+    }
+    let removed = mounted.remove(pos);
+    drop(mounted);
+    let on_block = removed.start.is_some();
+    // 这是文件系统的最后一个引用。FAT 在释放时把 FSInfo 写到缓存里，ext2 在释放时写回缓存，9p 释放根目录的 fid
+    drop(removed);
+    if on_block && BLOCK_CACHE.sync().is_err() {
+        warn!("failed to write back {} when unmounting", mount_path);
+    }
+    Ok(())
 }
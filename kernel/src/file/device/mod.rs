@@ -1,10 +1,13 @@
 //! FAT文件系统设备的抽象
 //! 包括读写文件等的支持
+//!
+//! 根文件系统或者挂载的分区是 ext2，或者挂载的是 9p 文件系统时，各个操作转交给对应的 UnixFs 处理
 
 //#![deny(missing_docs)]
 
 mod fat_dir;
 mod fat_file;
+mod fat_lower;
 mod fd_dir;
 mod link;
 mod open_flags;
@@ -12,29 +15,44 @@ mod stat;
 mod test;
 
 use super::{
+    add_block_device_files,
+    add_system_proc_files,
     get_virt_file_if_possible,
     check_virt_dir_exists,
     get_virt_dir_if_possible,
     check_virt_file_exists,
     try_remove_virt_file,
     try_make_virt_dir,
-    File
+    Ext2FileSystem,
+    File,
+    FsStat,
+    FD_LIMIT_ORIGIN,
+    OverlayFs,
+    P9FileSystem,
+    StMode,
+    UnixFs,
+    PIPE_SIZE_LIMIT,
+    PROC_FS,
+    TmpFs,
+    TMP_SIZE_LIMIT,
 };
 use crate::{
-    constants::ROOT_DIR,
+    constants::{ROOT_DIR, ROOT_OVERLAY},
     syscall::ErrorNo,
-    drivers::{new_memory_mapped_fs, MemoryMappedFsIoType},
+    sysctl::register_sysctl,
+    drivers::{is_ext2_at, new_block_fs, new_fat_fs, root_fs_range, BlockFsIoType},
 };
-use alloc::{string::String, sync::Arc};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::ops::Deref;
+use fat_lower::FatLower;
 use fatfs::{DefaultTimeProvider, Error, FileSystem, LossyOemCpConverter};
-use link::parse_file_name;
+use link::{find_mounted_fs, mounted_list, parse_file_name, same_unix_fs, FsRef};
 
-type FsIO = MemoryMappedFsIoType;
+type FsIO = BlockFsIoType;
 type FsTP = DefaultTimeProvider;
 type FsOCC = LossyOemCpConverter;
 
 type FsDir = fatfs::Dir<'static, FsIO, FsTP, FsOCC>;
-type FsDirIter<'a> = fatfs::DirIter<'a, FsIO, FsTP, FsOCC>;
 type FsFile = fatfs::File<'static, FsIO, FsTP, FsOCC>;
 type FATFileSystem = FileSystem<FsIO, FsTP, FsOCC>;
 
@@ -45,14 +63,18 @@ pub use link::FileDisc;
 pub use link::{
     read_link,
     get_link_count,
-    mount_fat_fs,
+    mount_9p_fs,
+    mount_fs,
+    mount_proc_fs,
+    mount_root_fs,
+    mount_tmp_fs,
+    move_mount,
     try_add_link,
     try_add_rev_link,
     try_remove_link,
-    umount_fat_fs,
+    umount_fs,
 };
 pub use open_flags::OpenFlags;
-pub use stat::get_fs_stat as origin_fs_stat;
 pub use test::{
     //load_testcases,
     load_next_testcase,
@@ -61,45 +83,128 @@ pub use test::{
 };
 
 lazy_static::lazy_static! {
-    //static ref MEMORY_FS: Arc<Mutex<FileSystem<FsIO, FsTP, FsOCC>>> = Arc::new(Mutex::new(new_memory_mapped_fs()));
-    static ref MEMORY_FS: FATFileSystem = new_memory_mapped_fs();
+    /// 块设备上的根文件系统。根分区是 ext2 时不会用到它
+    static ref ROOT_FS: Arc<FATFileSystem> = Arc::new(new_block_fs());
+    /// 根分区是 ext2 时，块设备上的根文件系统
+    static ref ROOT_EXT2: Option<Arc<Ext2FileSystem>> = {
+        let start = root_fs_range().0;
+        if is_ext2_at(start) {
+            Some(Arc::new(Ext2FileSystem::new(start).expect("unsupported ext2 filesystem on the block device")))
+        } else {
+            None
+        }
+    };
+}
+
+/// 根文件系统
+fn root_fs() -> FsRef {
+    match ROOT_EXT2.as_ref() {
+        Some(fs) => FsRef::Unix(fs.clone()),
+        None => FsRef::Fat(ROOT_FS.clone()),
+    }
+}
+
+/// 打开的 FAT 目录。FsDir 借用了所在的文件系统，所以这里同时持有文件系统。
+/// dir 声明在 fs 之前，保证目录先被释放
+struct OpenedDir {
+    dir: FsDir,
+    fs: Arc<FATFileSystem>,
+}
+
+impl OpenedDir {
+    /// 打开 fs 中的目录 dir。dir 以 "./" 开头，是根目录时直接返回 root
+    fn open(fs: &Arc<FATFileSystem>, dir: &str) -> Option<Self> {
+        // 目录和它借用的 fs 放在一起，fs 比目录活得更久，所以可以把借用看作 'static
+        let root = unsafe { &*Arc::as_ptr(fs) }.root_dir();
+        let dir = if dir == ROOT_DIR {
+            root
+        } else {
+            // 根目录是 "./" ，所以所有目录也是以 "./" 开头的，这里输入 fatfs 时要过滤掉这两个字符
+            root.open_dir(&dir[2..]).ok()?
+        };
+        Some(Self {
+            dir: dir,
+            fs: fs.clone(),
+        })
+    }
+}
+
+impl Deref for OpenedDir {
+    type Target = FsDir;
+    fn deref(&self) -> &FsDir {
+        &self.dir
+    }
+}
+
+/// 在块设备上的根文件系统上叠加 overlayfs，之后对根文件系统的修改都只在内存中。
+/// 需要打开 ROOT_OVERLAY，且没有从 initramfs 启动
+pub fn mount_root_overlay() {
+    if !ROOT_OVERLAY || find_mounted_fs(ROOT_DIR).is_some() {
+        return;
+    }
+    let (start, end) = root_fs_range();
+    let lower: Arc<dyn UnixFs> = match root_fs() {
+        FsRef::Unix(fs) => fs,
+        // 下层另外以只读方式打开，读文件时不会更新访问时间，也就不会写回镜像
+        FsRef::Fat(_) => match new_fat_fs(start, end, true) {
+            Some(fs) => Arc::new(FatLower::new(Arc::new(fs))),
+            None => return,
+        },
+    };
+    mount_root_fs("overlay", Arc::new(OverlayFs::new(lower)), Some(start));
+}
+
+/// 所有挂载的文件系统，每项为 (设备, 挂载目录, 文件系统类型)。
+/// 根目录上没有挂载其他文件系统时，第一项是块设备上的根文件系统
+pub fn mount_info() -> Vec<(String, String, &'static str)> {
+    let mut list = mounted_list();
+    if !list.iter().any(|(_, dir, _)| dir == ROOT_DIR) {
+        list.insert(0, (String::from("/dev/root"), String::from(ROOT_DIR), root_fs().fs_type()));
+    }
+    list
 }
 
 /// 输出根目录下的所有文件
 ///
 /// 注意，这个函数的输出是 info，这表示不打开 info 时它什么都不会输出
 pub fn list_files_at_root() {
-    //let fs = MEMORY_FS.lock();
-    //let root = fs.root_dir();
-    let root = MEMORY_FS.root_dir();
-    for dir_entry in root.iter() {
-        let file = dir_entry.unwrap();
-        info!("file: {}", file.file_name());
+    for (name, mode) in get_dir_entries(ROOT_DIR).unwrap_or_default() {
+        info!("file: {}", name);
         // 如果是子目录，则再继续遍历
-        if file.is_dir() {
-            info!("dir: {}/", file.file_name());
-            for dir_entry in root.open_dir(file.file_name().as_str()).unwrap().iter() {
-                let file = dir_entry.unwrap();
+        if mode == StMode::S_IFDIR && !name.starts_with(".") {
+            info!("dir: {}/", name);
+            for (name, _) in get_dir_entries(&[ROOT_DIR, name.as_str(), "/"].concat()).unwrap_or_default() {
                 // "." 开头的是当前目录、父目录以及(未来可能的)隐藏文件
-                if !file.file_name().starts_with(".") {
-                    info!("\tfile: {}", file.file_name());
+                if !name.starts_with(".") {
+                    info!("\tfile: {}", name);
                 }
             }
         }
     }
 }
 
+/// 根文件系统的信息。从 initramfs 启动或者 switch_root 之后，是挂载在根目录上的文件系统
+pub fn origin_fs_stat(stat: *mut FsStat) {
+    match resolve_mounted_fs(ROOT_DIR).0 {
+        FsRef::Unix(fs) => fs.stat_fs(stat),
+        FsRef::Fat(_) => stat::get_fs_stat(stat),
+    }
+}
+
 /// 初始化硬盘内容。
-/// 由于它需要调用 MEMORY_FS，所以不能塞进其它初始化过程里
+/// 由于它需要调用 ROOT_FS，所以不能塞进其它初始化过程里
 pub fn fs_init() {
     mkdir(ROOT_DIR, "dev");
     mkdir(ROOT_DIR, "lib");
     mkdir(ROOT_DIR, "tmp");
+    // ext2 和 overlayfs 中的硬链接是真正的目录项，链接所在的目录必须存在
+    mkdir(ROOT_DIR, "bin");
     //mkdir(ROOT_DIR, "dev");
     try_add_link(("./bin/").into(), "sh".into(), "./bin/".into(), "busybox".into());
     try_add_link(("./bin/").into(), "ls".into(), "./bin/".into(), "busybox".into());
 
     mkdir("dev/", "shm");
+    add_block_device_files();
     let dso = &"tls_get_new-dtv_dso.so"; // 该库要去lib等目录找，所以需要链接. 仅用于libc-test
     let libc_so = &"ld-musl-riscv64-sf.so.1";
     let libc_so2 = &"ld-musl-riscv64.so.1"; // 另一种名字的 libc.so，非 libc-test 测例库用
@@ -110,12 +215,17 @@ pub fn fs_init() {
     try_add_link(ROOT_DIR.into(), "lmbench_all".into(), "./sbin/".into(), "lmbench_all".into()); // busybox会去这里找
     try_add_link(ROOT_DIR.into(), "busybox".into(), "./sbin/".into(), "busybox".into());
     try_add_link(ROOT_DIR.into(), "busybox".into(), "/sbin/".into(), "ls".into());
-    // 一些系统信息文件。todo: 更正确的方式应该是放到 vfs 里而不是直接塞 fat32 这边
-    mkdir(ROOT_DIR, "proc"); // 进程状态信息
-    let _meminfo = open_file("./proc/", "meminfo", OpenFlags::CREATE).unwrap(); // 内存占用信息
-    let _mounts = open_file("./proc/", "mounts", OpenFlags::CREATE).unwrap(); // 所有的文件系统信息
+    // 进程和系统的状态信息，由 procfs 在读取时生成
+    mkdir(ROOT_DIR, "proc");
+    if let Err(errno) = mount_proc_fs(String::from("./proc/")) {
+        warn!("failed to mount procfs on /proc: {:?}", errno);
+    }
+    add_system_proc_files();
+    // 文件系统相关的内核参数，在 /proc/sys/fs 下
+    register_sysctl("fs/fd_limit_origin", &FD_LIMIT_ORIGIN);
+    register_sysctl("fs/pipe_size_limit", &PIPE_SIZE_LIMIT);
+    register_sysctl("fs/tmp_size_limit", &TMP_SIZE_LIMIT);
     mkdir("dev/", "misc");
-    let _rtc = open_file("./dev/misc/", "rtc", OpenFlags::CREATE).unwrap(); // 硬件时钟信息
     if let Some(_lat_sig) = open_file(ROOT_DIR, "lat_sig", OpenFlags::CREATE) {}; // lat_sig prot 测例要求的文件。测例只管读这个文件，但又不创建
 
     // 动态程序支持
@@ -192,6 +302,11 @@ fn split_path_and_file<'a>(dir_name: &str, file_path: &'a str) -> Option<(String
     Some((dir, &file_path[pos..]))
 }
 
+/// 把 dir_name 下的 file_path 转换成以 '/' 开头的绝对路径，如 "/bin/busybox"。会处理 "./" 和 "../"
+pub fn absolute_path(dir_name: &str, file_path: &str) -> Option<String> {
+    split_path_and_file(dir_name, file_path).map(|(dir, file)| String::from(&dir[1..]) + file)
+}
+
 /// 分割文件所在路径，然后经过link转换。
 fn map_path_and_file(dir_name: &str, file_path: &str) -> Option<(String, String)> {
     if !dir_name.ends_with('/') {
@@ -220,20 +335,31 @@ fn get_file_dir<'a>(dir_name: &str, file_path: &'a str) -> String {
 }
 */
 
-/// 打开目录。如果是根目录，特判直接返回 root；否则打开代表目录的 FsDir
+/// 打开目录。如果是某个文件系统的根目录，特判直接返回 root；否则打开代表目录的 FsDir。
+/// 目录在挂载的分区下时，从那个分区的文件系统里打开
 ///
-/// 因为需要通过 move 传入 root，这个函数只在模块内使用。
 /// 如果其他库需要打开目录(作为文件)，需要用 open_file 然后在 flags 里加入 DIR 一项
-fn inner_open_dir(root: FsDir, dir_name: &str) -> Option<FsDir> {
-    if dir_name == ROOT_DIR {
-        Some(root)
-    } else {
-        // 根目录是 "./" ，所以所有目录也是以 "./" 开头的，这里输入 fatfs 时要过滤掉这两个字符
-        if let Ok(dir) = root.open_dir(&dir_name[2..]) {
-            Some(dir)
-        } else {
-            None
-        }
+fn inner_open_dir(dir_name: &str) -> Option<OpenedDir> {
+    match resolve_mounted_fs(dir_name) {
+        (FsRef::Fat(fs), dir_name) => OpenedDir::open(&fs, dir_name.as_str()),
+        // ext2 和 9p 的目录不通过 FsDir 访问
+        (FsRef::Unix(_), _) => None,
+    }
+}
+
+/// 找到目录所在的文件系统，返回文件系统和目录在其中的路径(仍以 "./" 开头)。
+/// 不在任何挂载的分区下时返回根文件系统和原路径。
+///
+/// 根目录上挂载了文件系统(如 initramfs)时，块设备上的根文件系统不会被打开
+fn resolve_mounted_fs(dir_name: &str) -> (FsRef, String) {
+    find_mounted_fs(dir_name).unwrap_or_else(|| (root_fs(), String::from(dir_name)))
+}
+
+/// 如果目录在 ext2 或 9p 文件系统中，返回文件系统和目录在其中的路径
+fn find_unix_fs(dir_name: &str) -> Option<(Arc<dyn UnixFs>, String)> {
+    match resolve_mounted_fs(dir_name) {
+        (FsRef::Unix(fs), dir_name) => Some((fs, dir_name)),
+        (FsRef::Fat(_), _) => None,
     }
 }
 
@@ -250,7 +376,6 @@ fn inner_open_dir(root: FsDir, dir_name: &str) -> Option<FsDir> {
 /// 如果包含 OpenFlags::DIR，则只有打开已存在的目录成功时返回 FdDir
 pub fn open_file(dir_name: &str, file_path: &str, flags: OpenFlags) -> Option<Arc<dyn File>> {
 
-    let root = MEMORY_FS.root_dir();
 
     let (real_dir, file_name) = map_path_and_file(dir_name, file_path)?;
 
@@ -283,7 +408,10 @@ pub fn open_file(dir_name: &str, file_path: &str, flags: OpenFlags) -> Option<Ar
     } else {
         file_name.as_str()
     };
-    if let Some(dir) = inner_open_dir(root, real_dir.as_str()) {
+    if let Some((fs, dir)) = find_unix_fs(real_dir.as_str()) {
+        return fs.open(dir.as_str(), file_name, real_dir.as_str(), flags);
+    }
+    if let Some(dir) = inner_open_dir(real_dir.as_str()) {
         if flags.contains(OpenFlags::DIR)
             || flags.contains(OpenFlags::DSYNC)
             || file_name.len() == 0
@@ -291,18 +419,12 @@ pub fn open_file(dir_name: &str, file_path: &str, flags: OpenFlags) -> Option<Ar
             // 要求打开目录
             // 用户传入 sys_open 的目录名如果是有斜线的，那么 file_path 就是空的了
             // 否则 file_path 是当前目录下的一个子目录的名字
-            let dir = if file_name.len() == 0 {
-                Ok(dir)
+            if file_name.len() == 0 || dir.open_dir(file_name).is_ok() {
+                // 不考虑是否有 CREATE 参数，只要找到目录就可以直接返回
+                Some(Arc::new(FdDir::new(String::from(real_dir) + file_name)))
             } else {
-                dir.open_dir(file_name)
-            };
-            match dir {
-                Ok(_dir) => {
-                    // 不考虑是否有 CREATE 参数，只要找到目录就可以直接返回
-                    Some(Arc::new(FdDir::new(String::from(real_dir) + file_name)))
-                }
                 // 如果找不到，也不考虑 CREATE。创建目录应该用 mkdir 而不是 open_file
-                _ => None,
+                None
             }
         } else {
             // 否则要求打开文件
@@ -323,6 +445,7 @@ pub fn open_file(dir_name: &str, file_path: &str, flags: OpenFlags) -> Option<Ar
                         String::from(file_name),
                         file,
                         flags,
+                        dir.fs.clone(),
                     );
                     if flags.contains(OpenFlags::CREATE) {
                         // 清空这个文件
@@ -342,6 +465,7 @@ pub fn open_file(dir_name: &str, file_path: &str, flags: OpenFlags) -> Option<Ar
                             String::from(file_name),
                             file,
                             flags,
+                            dir.fs.clone(),
                         )))
                     } else {
                         None
@@ -362,9 +486,6 @@ pub fn open_file(dir_name: &str, file_path: &str, flags: OpenFlags) -> Option<Ar
 /// 这里并不直接试图打开文件检查是否成功，而是检查目录下是否存在对应文件。
 /// 这是因为其他进程占用文件等情况也可能导致打开文件失败，所以打开失败不等于文件不存在
 pub fn check_file_exists(dir_name: &str, file_path: &str) -> bool {
-    //let fs = MEMORY_FS.lock();
-    //let root = fs.root_dir();
-    let root = MEMORY_FS.root_dir();
     map_path_and_file(dir_name, file_path)
         .map(|(real_dir, file_name)| {
             info!(
@@ -374,7 +495,10 @@ pub fn check_file_exists(dir_name: &str, file_path: &str) -> bool {
             if let Some(exist) = check_virt_file_exists(&real_dir, &file_name) {
                 return exist;
             }
-            inner_open_dir(root, real_dir.as_str())
+            if let Some((fs, dir)) = find_unix_fs(real_dir.as_str()) {
+                return fs.exists(dir.as_str(), file_name.as_str()) == Some(false);
+            }
+            inner_open_dir(real_dir.as_str())
                 .map(|dir| {
                     for entry in dir.iter() {
                         let file = entry.unwrap();
@@ -393,12 +517,17 @@ pub fn check_file_exists(dir_name: &str, file_path: &str) -> bool {
 ///
 /// **调用这个函数时默认文件存在，且 path/name 已经过 split_path_and_file 格式化**
 fn remove_file(path: &str, name: &str) {
-    let root = MEMORY_FS.root_dir();
     // 如果在 vfs 里能找到文件，就直接在里面删除
     if let Some(_) = try_remove_virt_file(&path.into(), &name.into()) {
         return;
     }
-    let dir = inner_open_dir(root, path).unwrap();
+    if let Some((fs, dir)) = find_unix_fs(path) {
+        if let Err(e) = fs.remove(dir.as_str(), name) {
+            warn!("failed to remove {}{}: {:?}", path, name, e);
+        }
+        return;
+    }
+    let dir = inner_open_dir(path).unwrap();
     dir.remove(name).unwrap();
     /*
     dir.remove(name).unwrap_or_else(|_| {
@@ -409,13 +538,15 @@ fn remove_file(path: &str, name: &str) {
 
 /// 创建目录，返回是否成功
 pub fn mkdir(dir_name: &str, file_path: &str) -> bool {
-    let root = MEMORY_FS.root_dir();
     map_path_and_file(dir_name, file_path)
         .map(|(real_dir, file_name)| {
             if let Some(vdir) = get_virt_dir_if_possible(&real_dir) {
                 return try_make_virt_dir(&vdir, &file_name);
             }
-            inner_open_dir(root, real_dir.as_str())
+            if let Some((fs, dir)) = find_unix_fs(real_dir.as_str()) {
+                return file_name.len() != 0 && fs.mkdir(dir.as_str(), file_name.as_str()).is_ok();
+            }
+            inner_open_dir(real_dir.as_str())
                 .map(|dir| {
                     // 说明现在打开的 dir 就是想要创建的目录，那么它已经存在了
                     if file_name.len() == 0 {
@@ -436,12 +567,21 @@ pub fn mkdir(dir_name: &str, file_path: &str) -> bool {
 }
 
 /// 移动文件，如果 new_dir == old_dir 则表现为重命名
-/// 只检查 FAT32，不考虑 vfs。不考虑符号链接，因为实现是在 fs 里实现的，而链接在内核里
+/// 只检查块设备上的文件系统，不考虑 vfs。FAT 上不考虑符号链接，因为实现是在 fs 里实现的，而链接在内核里
 /// 
 /// replace 的语义为，如果目标位置的文件已存在，是否替换它
 pub fn rename_or_move(old_dir: &str, old_file: &str, new_dir: &str, new_file: &str, replace: bool) -> Result<(), ErrorNo> {
-    if let Some(old_dir) = inner_open_dir(MEMORY_FS.root_dir(), old_dir) {
-        if let Some(new_dir) = inner_open_dir(MEMORY_FS.root_dir(), new_dir) {
+    let (old_fs, old_fs_dir) = resolve_mounted_fs(old_dir);
+    let (new_fs, new_fs_dir) = resolve_mounted_fs(new_dir);
+    // 不同分区上的文件系统之间不能直接移动
+    if !old_fs.same(&new_fs) {
+        return Err(ErrorNo::EXDEV);
+    }
+    if let FsRef::Unix(fs) = old_fs {
+        return fs.rename(old_fs_dir.as_str(), old_file, new_fs_dir.as_str(), new_file, replace);
+    }
+    if let Some(old_dir) = inner_open_dir(old_dir) {
+        if let Some(new_dir) = inner_open_dir(new_dir) {
             return match old_dir.rename(old_file, &new_dir, new_file) {
                 Ok(_) => Ok(()),
                 // 如果文件已存在，检查
@@ -471,13 +611,13 @@ pub fn rename_or_move(old_dir: &str, old_file: &str, new_dir: &str, new_file: &s
 #[allow(unused)]
 pub fn add_link_for_all_files_in_dir(origin_dir: String, link_dir: String, is_create: bool, recursive: bool) -> bool {
     // 打开原目录
-    if let Some(origin_fsdir) = inner_open_dir(MEMORY_FS.root_dir(), origin_dir.as_str()) {
+    if let Some(origin_fsdir) = inner_open_dir(origin_dir.as_str()) {
         // 打开需要链接到的目录
-        if inner_open_dir(MEMORY_FS.root_dir(), link_dir.as_str()).is_none() {
+        if inner_open_dir(link_dir.as_str()).is_none() {
             //如果不存在，则考察是否需要创建
             // mkdir 时，目录后不该有 '/'，所以要去掉最后一个字符
             if is_create && mkdir(ROOT_DIR, &link_dir.as_str()[..link_dir.len()-1]) {
-                inner_open_dir(MEMORY_FS.root_dir(), link_dir.as_str());
+                inner_open_dir(link_dir.as_str());
             } else {
                 return false;
             }
@@ -512,12 +652,14 @@ pub fn add_link_for_all_files_in_dir(origin_dir: String, link_dir: String, is_cr
     true
 }
 
+/// 检查路径处理 "./" 和 "../" 之后是否是根目录。dir_name 需要以 '/' 结尾
+pub fn is_root_dir(dir_name: &str) -> bool {
+    split_path_and_file(dir_name, "").map_or(false, |(dir, _)| dir == ROOT_DIR)
+}
+
 /// 检查目录是否存在
 /// 要求 dir_name 使用 os 中的格式，即以 "./" 开头
 pub fn check_dir_exists(dir_name: &str) -> bool {
-    //let fs = MEMORY_FS.lock();
-    //let root = fs.root_dir();
-    let root = MEMORY_FS.root_dir();
     let mut dir_name = String::from(dir_name);
     if !dir_name.ends_with('/') {
         dir_name.push('/');
@@ -527,8 +669,11 @@ pub fn check_dir_exists(dir_name: &str) -> bool {
     if check_virt_dir_exists(&dir_name) == Some(true) {
         return true;
     }
+    if let Some((fs, dir)) = find_unix_fs(dir_name.as_str()) {
+        return fs.exists(dir.as_str(), "") == Some(true);
+    }
     // 去掉字符串开头的 '.' 或者 "./"
-    inner_open_dir(root, dir_name.as_str()).is_some()
+    inner_open_dir(dir_name.as_str()).is_some()
 }
 
 /*
@@ -536,12 +681,9 @@ pub fn check_dir_exists(dir_name: &str) -> bool {
 ///
 /// 这里实际上没有检查硬链接
 pub fn get_kth_dir_entry_info_of_path(dir_name: &str, entry_id: usize) -> Option<(bool, String)> {
-    //let fs = MEMORY_FS.lock();
-    //let root = fs.root_dir();
-    let root = MEMORY_FS.root_dir();
     let dir_name = map_path_and_file(dir_name, "").unwrap().0;
     info!("get dir: dir = {}", dir_name);
-    inner_open_dir(root, dir_name.as_str())
+    inner_open_dir(dir_name.as_str())
         .map(|dir| {
             let mut now_id = 0;
             for entry in dir.iter() {
@@ -557,14 +699,91 @@ pub fn get_kth_dir_entry_info_of_path(dir_name: &str, entry_id: usize) -> Option
 }
 */
 
-/// 获取一个迭代器，每次返回一个目录中的 DirEntry。如果对应目录不存在，返回 None
+/// 获取目录中的所有目录项，每项为 (文件名, 文件类型)。如果对应目录不存在，返回 None
 ///
-/// 这里实际上没有检查硬链接
-pub fn get_dir_entry_iter<'a>(dir_name: &str) -> Option<FsDirIter<'a>> {
-    //let fs = MEMORY_FS.lock();
-    //let root = fs.root_dir();
-    let root = MEMORY_FS.root_dir();
+/// FAT 中只区分目录和普通文件。这里实际上没有检查硬链接
+pub fn get_dir_entries(dir_name: &str) -> Option<Vec<(String, StMode)>> {
     let dir_name = map_path_and_file(dir_name, "").unwrap().0;
     info!("get dir: dir = {}", dir_name);
-    inner_open_dir(root, dir_name.as_str()).map(|dir| dir.iter())
+    if let Some((fs, dir)) = find_unix_fs(dir_name.as_str()) {
+        return fs.list(dir.as_str()).ok();
+    }
+    inner_open_dir(dir_name.as_str()).map(|dir| {
+        dir.iter()
+            .map(|entry| {
+                let file = entry.unwrap();
+                let mode = if file.is_dir() { StMode::S_IFDIR } else { StMode::S_IFREG };
+                (file.file_name(), mode)
+            })
+            .collect()
+    })
+}
+
+/// 如果 old_path/old_file 和 new_path/new_file 在同一个 ext2 或 9p 文件系统中，在其中创建硬链接，返回是否成功。
+/// 两者都不在这类文件系统中时返回 None，由链接表处理；两边不在同一个文件系统中时不能链接，返回 Some(false)
+///
+/// **路径和文件需要已经过 split_path_and_file 格式化**
+fn link_in_same_unix_fs(old_path: &str, old_file: &str, new_path: &str, new_file: &str) -> Option<bool> {
+    match (find_unix_fs(old_path), find_unix_fs(new_path)) {
+        (Some((old_fs, old_dir)), Some((new_fs, new_dir))) => Some(
+            same_unix_fs(&*old_fs, &*new_fs) && old_fs.link(old_dir.as_str(), old_file, new_dir.as_str(), new_file).is_ok(),
+        ),
+        (None, None) => None,
+        _ => Some(false),
+    }
+}
+
+/// 读取符号链接 dir_name/file_path 的目标。只有 ext2 和 9p 中有真正的符号链接
+pub fn read_symlink(dir_name: &str, file_path: &str) -> Result<String, ErrorNo> {
+    let (real_dir, file_name) = map_path_and_file(dir_name, file_path).ok_or(ErrorNo::EINVAL)?;
+    match find_unix_fs(real_dir.as_str()) {
+        Some((fs, dir)) => fs.read_link(dir.as_str(), file_name.as_str()),
+        None => Err(ErrorNo::EINVAL),
+    }
+}
+
+/// 创建指向 target 的符号链接 dir_name/file_path。FAT 不支持符号链接
+pub fn make_symlink(target: &str, dir_name: &str, file_path: &str) -> Result<(), ErrorNo> {
+    let (real_dir, file_name) = map_path_and_file(dir_name, file_path).ok_or(ErrorNo::EINVAL)?;
+    match find_unix_fs(real_dir.as_str()) {
+        Some((fs, dir)) => fs.symlink(target, dir.as_str(), file_name.as_str()),
+        None => Err(ErrorNo::EPERM),
+    }
+}
+
+/// 创建文件节点 dir_name/file_path，mode 中包含文件类型，rdev 是设备号。
+/// FAT 上只能创建普通文件
+pub fn make_node(dir_name: &str, file_path: &str, mode: u32, rdev: u64) -> Result<(), ErrorNo> {
+    let (real_dir, file_name) = map_path_and_file(dir_name, file_path).ok_or(ErrorNo::EINVAL)?;
+    if let Some((fs, dir)) = find_unix_fs(real_dir.as_str()) {
+        return fs.mknod(dir.as_str(), file_name.as_str(), mode, rdev);
+    }
+    let file_type = mode & StMode::S_IFMT.bits();
+    if file_type != 0 && file_type != StMode::S_IFREG.bits() {
+        return Err(ErrorNo::EPERM);
+    }
+    if check_file_exists(real_dir.as_str(), file_name.as_str()) || check_dir_exists(&[real_dir.as_str(), file_name.as_str()].concat()) {
+        return Err(ErrorNo::EEXIST);
+    }
+    open_file(real_dir.as_str(), file_name.as_str(), OpenFlags::CREATE)
+        .map(|_| ())
+        .ok_or(ErrorNo::ENOENT)
+}
+
+/// 修改 dir_name/file_path 的权限位。FAT 没有权限，直接返回成功
+pub fn set_file_mode(dir_name: &str, file_path: &str, mode: u32) -> Result<(), ErrorNo> {
+    let (real_dir, file_name) = map_path_and_file(dir_name, file_path).ok_or(ErrorNo::EINVAL)?;
+    match find_unix_fs(real_dir.as_str()) {
+        Some((fs, dir)) => fs.chmod(dir.as_str(), file_name.as_str(), mode),
+        None => Ok(()),
+    }
+}
+
+/// 修改 dir_name/file_path 的所有者和组，为 None 的一项不修改。FAT 没有所有者，直接返回成功
+pub fn set_file_owner(dir_name: &str, file_path: &str, uid: Option<u32>, gid: Option<u32>, follow: bool) -> Result<(), ErrorNo> {
+    let (real_dir, file_name) = map_path_and_file(dir_name, file_path).ok_or(ErrorNo::EINVAL)?;
+    match find_unix_fs(real_dir.as_str()) {
+        Some((fs, dir)) => fs.chown(dir.as_str(), file_name.as_str(), uid, gid, follow),
+        None => Ok(()),
+    }
 }
@@ -21,6 +21,10 @@ bitflags! {
         const NOCTTY = 1 << 8;
         /// 同上，在不同的库中可能会用到这个或者上一个
         const EXCL = 1 << 9;
+        /// 打开时清空文件。和 EXCL 是同一位，musl 中 O_TRUNC 的值就是它，ext2 中按这个语义处理
+        const TRUNC = 1 << 9;
+        /// 每次写入前先把指针移到文件末尾
+        const APPEND = 1 << 10;
         /// 非阻塞读写?(虽然不知道为什么但 date.lua 也要)
         const NON_BLOCK = 1 << 11;
         /// 要求把 CR-LF 都换成 LF
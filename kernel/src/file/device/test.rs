@@ -2,7 +2,7 @@
 
 use crate::{
     constants::{NO_PARENT, ROOT_DIR},
-    task::TaskControlBlock,
+    task::{global_register_task, TaskControlBlock},
 };
 use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
 use lock::Mutex;
@@ -41,9 +41,11 @@ pub fn load_next_testcase() -> Option<Arc<TaskControlBlock>> {
             //let argv = argv.drain_filter(|s| s != "").collect();
             let argv = split_argv(user_command.as_bytes());
             TEST_STATUS.lock().load(&user_command.into());
-            Some(Arc::new(
+            let task = Arc::new(
                 TaskControlBlock::from_app_name(ROOT_DIR, NO_PARENT, argv).unwrap(),
-            ))
+            );
+            global_register_task(&task);
+            Some(task)
         },
     )
 }
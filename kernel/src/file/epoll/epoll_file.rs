@@ -1,9 +1,21 @@
 //! epoll 类型文件
+//!
+//! epoll 等待时登记到被监控文件的等待队列上，被唤醒后检查被监控的文件，
+//! 把产生了事件的文件放进就绪队列，epoll_wait 再从就绪队列的队首取出事件。
+//! 水平触发的文件被取出后如果仍然就绪，会在下次检查时重新排到队尾，这样每个文件都有机会被返回。
+//!
+//! - EPOLLET(边缘触发)：文件的等待队列在两次检查之间被唤醒过(如管道被写入了新数据)，或者出现了上次检查时没有的事件，
+//!   文件才会进入就绪队列。只比较两次检查时的状态是不够的：用户读空文件之后又来了新数据，两次检查时都是可读的
+//! - EPOLLONESHOT：文件的事件被返回一次后停用，直到 EPOLL_CTL_MOD 重新启用
+//! - EPOLLEXCLUSIVE：只在 EPOLL_CTL_ADD 时允许。等待时以独占方式登记到文件上，文件状态变化时只唤醒一个这样等待的线程
+//! - EPOLLRDHUP：文件的另一端已关闭时产生
 
 use lock::Mutex;
-use alloc::sync::Arc;
-use alloc::collections::{BTreeMap, BTreeSet};
-use super::{File, EpollEvent, EpollCtl};
+use alloc::sync::{Arc, Weak};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use super::{File, EpollEvent, EpollEventType, EpollCtl};
+use crate::file::{OpenFlags, PollEvents};
 use crate::syscall::ErrorNo;
 
 /// 用作 epoll 的文件
@@ -11,21 +23,105 @@ pub struct EpollFile {
     pub inner: Arc<Mutex<EpollFileInner>>,
 }
 
+/// 一个被监控的文件
+struct EpollItem {
+    /// 被监控的文件。这里不持有文件，文件的最后一个 fd 被关闭后这一项会被自动删除
+    file: Weak<dyn File>,
+    /// 用户注册的事件和数据
+    event: EpollEvent,
+    /// 上一次检查时文件上发生的事件，用于边缘触发
+    last_events: EpollEventType,
+    /// 上一次检查时文件的等待队列被唤醒的次数，用于边缘触发
+    last_wakes: Option<usize>,
+    /// 是否因为 EPOLLONESHOT 而停用
+    disabled: bool,
+}
+
+impl EpollItem {
+    /// 新建一项
+    fn new(file: &Arc<dyn File>, event: EpollEvent) -> Self {
+        Self {
+            file: Arc::downgrade(file),
+            event: event,
+            last_events: EpollEventType::empty(),
+            last_wakes: None,
+            disabled: false,
+        }
+    }
+    /// 获取文件上当前发生的、用户关心的事件。EPOLLERR 和 EPOLLHUP 总是会被报告
+    fn poll(&self, file: &Arc<dyn File>) -> EpollEventType {
+        let events = self.event.events;
+        let revents = file.poll(PollEvents::from_bits_truncate(events.bits() as u16));
+        let mut ret = EpollEventType::from_bits_truncate(revents.bits() as u32);
+        if events.contains(EpollEventType::EPOLLRDHUP) && file.is_hang_up() {
+            ret |= EpollEventType::EPOLLRDHUP;
+        }
+        ret & (events | EpollEventType::EPOLLERR | EpollEventType::EPOLLHUP)
+    }
+    /// 检查文件，返回这一项是否应该进入就绪队列
+    fn check(&mut self, file: &Arc<dyn File>) -> bool {
+        if self.disabled {
+            return false;
+        }
+        let revents = self.poll(file);
+        let wakes = file.wake_count();
+        let new_events = if !self.event.events.contains(EpollEventType::EPOLLET) {
+            revents
+        } else if wakes.is_some() && wakes != self.last_wakes {
+            // 文件状态变化过，当前的事件都算作新事件
+            revents
+        } else {
+            revents - self.last_events
+        };
+        self.last_events = revents;
+        self.last_wakes = wakes;
+        !new_events.is_empty()
+    }
+}
+
 /// epoll 内部可变部分
 pub struct EpollFileInner {
-    /// 监控的所有文件(fd)。key 不用 Arc<dyn File> 只是因为不好针对 map 做
-    pub interest_list: BTreeMap<i32, EpollEvent>,
-    /// 已经相应事件的文件(fd)
-    pub _ready_list: BTreeSet<i32>,
+    /// 监控的所有文件，按 fd 索引
+    interest_list: BTreeMap<i32, EpollItem>,
+    /// 已经产生事件、等待被 epoll_wait 取出的文件(fd)
+    ready_list: VecDeque<i32>,
+    /// 文件状态，目前只有 CLOEXEC
+    flags: OpenFlags,
+}
+
+impl EpollFileInner {
+    /// 检查所有被监控的文件，把新产生事件的文件加入就绪队列。已经被关闭的文件会被删除
+    fn scan(&mut self) {
+        let mut closed: Vec<i32> = Vec::new();
+        for (&fd, item) in self.interest_list.iter_mut() {
+            match item.file.upgrade() {
+                Some(file) => {
+                    if item.check(&file) && !self.ready_list.contains(&fd) {
+                        self.ready_list.push_back(fd);
+                    }
+                }
+                None => closed.push(fd),
+            }
+        }
+        for fd in closed {
+            self.remove(fd);
+        }
+    }
+    /// 删除 fd 对应的项
+    fn remove(&mut self, fd: i32) -> Option<EpollItem> {
+        self.ready_list.retain(|&ready_fd| ready_fd != fd);
+        self.interest_list.remove(&fd)
+    }
 }
 
 impl EpollFile {
     /// 新建一个 epoll 文件
-    pub fn new() -> Self {
+    pub fn new(flags: OpenFlags) -> Self {
         Self {
             inner : Arc::new(Mutex::new(EpollFileInner {
                 interest_list: BTreeMap::new(),
-                _ready_list: BTreeSet::new(),
+                ready_list: VecDeque::new(),
+                flags: flags,
             }))
         }
     }
@@ -36,34 +132,79 @@ impl EpollFile {
             inner: self.inner.clone()
         }
     }
-    /// 进行控制操作，如成功则返回 Ok(())，否则返回对应的错误编号
-    pub fn epoll_ctl(&self, op: EpollCtl, fd: i32, event: EpollEvent) -> Result<(), ErrorNo> {
+    /// 进行控制操作，如成功则返回 Ok(())，否则返回对应的错误编号。file 是 fd 对应的文件
+    pub fn epoll_ctl(&self, op: EpollCtl, fd: i32, file: &Arc<dyn File>, event: EpollEvent) -> Result<(), ErrorNo> {
         info!("epool ctl: {:?}, fd: {}, event: {:?}", op, fd, event);
-        let list = &mut self.inner.lock().interest_list;
+        let mut inner = self.inner.lock();
+        // fd 已被关闭又分配给了其他文件时，原来的项已经失效
+        let registered = inner
+            .interest_list
+            .get(&fd)
+            .map_or(false, |item| item.file.upgrade().map_or(false, |f| Arc::ptr_eq(&f, file)));
+        let exclusive = event.events.contains(EpollEventType::EPOLLEXCLUSIVE);
         match op {
             EpollCtl::ADD => {
-                if list.contains_key(&fd) {
+                if registered {
                     return Err(ErrorNo::EEXIST);
-                } else {
-                    list.insert(fd, event);
                 }
+                if exclusive && event.events.contains(EpollEventType::EPOLLONESHOT) {
+                    return Err(ErrorNo::EINVAL);
+                }
+                inner.remove(fd);
+                inner.interest_list.insert(fd, EpollItem::new(file, event));
             },
             EpollCtl::MOD => {
-                if list.contains_key(&fd) {
-                    // 根据 BTreeMap 的语义，这里的 insert 相当于把原来的值替换掉
-                    list.insert(fd, event);
-                } else {
+                if !registered {
                     return Err(ErrorNo::ENOENT);
                 }
+                let item = inner.interest_list.get_mut(&fd).unwrap();
+                // EPOLLEXCLUSIVE 的项不能修改，也不能通过修改加上 EPOLLEXCLUSIVE
+                if exclusive || item.event.events.contains(EpollEventType::EPOLLEXCLUSIVE) {
+                    return Err(ErrorNo::EINVAL);
+                }
+                // 修改后重新开始检查，已就绪的状态也会重新报告
+                *item = EpollItem::new(file, event);
+                inner.ready_list.retain(|&ready_fd| ready_fd != fd);
             },
             EpollCtl::DEL => {
-                if list.remove(&fd).is_none() {
+                if !registered {
                     return Err(ErrorNo::ENOENT);
                 }
+                inner.remove(fd);
             }
         }
         Ok(())
     }
+    /// 取出最多 max_events 个已发生的事件，没有事件时返回空的 Vec，不会阻塞
+    pub fn take_events(&self, max_events: usize) -> Vec<EpollEvent> {
+        let mut inner = self.inner.lock();
+        inner.scan();
+        let mut events: Vec<EpollEvent> = Vec::new();
+        while events.len() < max_events {
+            let fd = match inner.ready_list.pop_front() {
+                Some(fd) => fd,
+                None => break,
+            };
+            let item = inner.interest_list.get_mut(&fd).unwrap();
+            let file = match item.file.upgrade() {
+                Some(file) => file,
+                None => continue,
+            };
+            // 报告的是取出时文件的状态。水平触发的文件可能已经不再就绪了
+            let revents = item.poll(&file);
+            if revents.is_empty() {
+                continue;
+            }
+            if item.event.events.contains(EpollEventType::EPOLLONESHOT) {
+                item.disabled = true;
+            }
+            events.push(EpollEvent {
+                events: revents,
+                data: item.event.data,
+            });
+        }
+        events
+    }
 }
 
 impl File for EpollFile {
@@ -75,6 +216,50 @@ impl File for EpollFile {
     fn write(&self, _buf: &[u8]) -> Option<usize> {
         None
     }
+    /// 有已发生的事件时可读，这样 epoll 也可以被 poll 或者另一个 epoll 监控
+    fn ready_to_read(&self) -> bool {
+        let mut inner = self.inner.lock();
+        inner.scan();
+        !inner.ready_list.is_empty()
+    }
+    /// epoll 文件不可直接写
+    fn ready_to_write(&self) -> bool {
+        false
+    }
+    /// 登记到所有被监控的文件上，带 EPOLLEXCLUSIVE 的文件以独占方式登记。
+    /// 只要有一个文件不会主动唤醒线程，epoll 就只能反复检查
+    fn register_waiter(&self, tid: usize) -> bool {
+        let inner = self.inner.lock();
+        let mut all_registered = true;
+        for item in inner.interest_list.values() {
+            if let Some(file) = item.file.upgrade() {
+                all_registered &= if item.event.events.contains(EpollEventType::EPOLLEXCLUSIVE) {
+                    file.register_exclusive_waiter(tid)
+                } else {
+                    file.register_waiter(tid)
+                };
+            }
+        }
+        all_registered
+    }
+    /// 从所有被监控的文件上取消登记
+    fn unregister_waiter(&self, tid: usize) {
+        let inner = self.inner.lock();
+        for item in inner.interest_list.values() {
+            if let Some(file) = item.file.upgrade() {
+                file.unregister_waiter(tid);
+            }
+        }
+    }
+    /// 获取文件状态信息
+    fn get_status(&self) -> OpenFlags {
+        self.inner.lock().flags
+    }
+    /// 设置 CLOEXEC 位
+    fn set_close_on_exec(&self, is_set: bool) -> bool {
+        self.inner.lock().flags.set(OpenFlags::CLOEXEC, is_set);
+        true
+    }
     /// 如果这个文件对应的是一个 epoll，则获取 epoll 文件。否则，返回 None
     fn get_epoll_fd(&self) -> Option<EpollFile> {
         Some(self.clone())
@@ -0,0 +1,156 @@
+//! eventfd，内部是一个 64 位的计数器，用于线程/进程间的事件通知
+//!
+//! write 把写入的值加到计数器上，read 读出计数器的值并把它清零。
+//! 信号量模式(EFD_SEMAPHORE)下 read 每次只读出 1，并把计数器减 1。
+//! 计数器为 0 时 read 阻塞，计数器将要超过 u64::MAX - 1 时 write 阻塞。
+//! 阻塞的线程在 eventfd 的等待队列上睡眠，计数器变化时唤醒
+
+use super::{File, OpenFlags, WaitQueue};
+use crate::syscall::ErrorNo;
+use core::mem::size_of;
+use lock::Mutex;
+
+/// 信号量模式
+pub const EFD_SEMAPHORE: u32 = 1;
+
+/// eventfd 文件
+pub struct EventFd {
+    inner: Mutex<EventFdInner>,
+    /// 等待计数器变化的线程
+    waiters: WaitQueue,
+}
+
+struct EventFdInner {
+    /// 计数器的值
+    count: u64,
+    /// 是否是信号量模式
+    semaphore: bool,
+    /// 文件状态。EFD_NONBLOCK 和 EFD_CLOEXEC 与 O_NONBLOCK 和 O_CLOEXEC 的值相同
+    flags: OpenFlags,
+    /// 上次读写失败的原因，只记录参数不合法
+    error: Option<ErrorNo>,
+}
+
+impl EventFd {
+    /// 创建一个计数器初值为 initval 的 eventfd
+    pub fn new(initval: u32, flags: u32) -> Self {
+        Self {
+            inner: Mutex::new(EventFdInner {
+                count: initval as u64,
+                semaphore: flags & EFD_SEMAPHORE != 0,
+                flags: OpenFlags::RDWR
+                    | (OpenFlags::from_bits_truncate(flags) & (OpenFlags::NON_BLOCK | OpenFlags::CLOEXEC)),
+                error: None,
+            }),
+            waiters: WaitQueue::new(),
+        }
+    }
+    /// 参数不合法，读写失败
+    fn invalid_input(&self) -> Option<usize> {
+        self.inner.lock().error = Some(ErrorNo::EINVAL);
+        None
+    }
+}
+
+impl File for EventFd {
+    /// 读出计数器的值，buf 至少要有 8 字节。计数器为 0 时阻塞
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        if buf.len() < size_of::<u64>() {
+            return self.invalid_input();
+        }
+        loop {
+            let wakes = self.waiters.wake_count();
+            let mut inner = self.inner.lock();
+            if inner.count > 0 {
+                let value = if inner.semaphore { 1 } else { inner.count };
+                inner.count -= value;
+                buf[..size_of::<u64>()].copy_from_slice(&value.to_ne_bytes());
+                drop(inner);
+                self.waiters.wake_all();
+                return Some(size_of::<u64>());
+            }
+            if inner.flags.contains(OpenFlags::NON_BLOCK) {
+                return None;
+            }
+            drop(inner);
+            // 被信号打断
+            if self.waiters.wait(wakes, None).is_err() {
+                return None;
+            }
+        }
+    }
+    /// 把 buf 中的 8 字节整数加到计数器上。计数器会超过上限时阻塞
+    fn write(&self, buf: &[u8]) -> Option<usize> {
+        if buf.len() < size_of::<u64>() {
+            return self.invalid_input();
+        }
+        let value = u64::from_ne_bytes(buf[..size_of::<u64>()].try_into().unwrap());
+        if value == u64::MAX {
+            return self.invalid_input();
+        }
+        loop {
+            let wakes = self.waiters.wake_count();
+            let mut inner = self.inner.lock();
+            if u64::MAX - 1 - inner.count >= value {
+                inner.count += value;
+                drop(inner);
+                self.waiters.wake_all();
+                return Some(size_of::<u64>());
+            }
+            if inner.flags.contains(OpenFlags::NON_BLOCK) {
+                return None;
+            }
+            drop(inner);
+            // 被信号打断
+            if self.waiters.wait(wakes, None).is_err() {
+                return None;
+            }
+        }
+    }
+    /// 参数不合法时返回 EINVAL
+    fn take_rw_error(&self) -> Option<ErrorNo> {
+        self.inner.lock().error.take()
+    }
+    /// 计数器不为 0 时可读
+    fn ready_to_read(&self) -> bool {
+        self.inner.lock().count > 0
+    }
+    /// 计数器至少还能加 1 时可写
+    fn ready_to_write(&self) -> bool {
+        self.inner.lock().count < u64::MAX - 1
+    }
+    /// 计数器变化时会唤醒等待的线程
+    fn register_waiter(&self, tid: usize) -> bool {
+        self.waiters.register(tid);
+        true
+    }
+    /// 独占等待
+    fn register_exclusive_waiter(&self, tid: usize) -> bool {
+        self.waiters.register_exclusive(tid);
+        true
+    }
+    /// 计数器变化的次数
+    fn wake_count(&self) -> Option<usize> {
+        Some(self.waiters.wake_count())
+    }
+    /// 取消等待
+    fn unregister_waiter(&self, tid: usize) {
+        self.waiters.unregister(tid);
+    }
+    /// 获取文件状态信息
+    fn get_status(&self) -> OpenFlags {
+        self.inner.lock().flags
+    }
+    /// 设置文件状态信息，只能修改 NON_BLOCK 和 CLOEXEC
+    fn set_status(&self, flags: OpenFlags) -> bool {
+        let fl = &mut self.inner.lock().flags;
+        fl.set(OpenFlags::NON_BLOCK, flags.contains(OpenFlags::NON_BLOCK));
+        fl.set(OpenFlags::CLOEXEC, flags.contains(OpenFlags::CLOEXEC));
+        true
+    }
+    /// 设置 CLOEXEC 位
+    fn set_close_on_exec(&self, is_set: bool) -> bool {
+        self.inner.lock().flags.set(OpenFlags::CLOEXEC, is_set);
+        true
+    }
+}
@@ -0,0 +1,361 @@
+//! ext2 的目录和路径查找
+//!
+//! 目录的数据是一串变长的目录项，每项为 (inode 编号, 这一项的长度, 名字长度, 文件类型, 名字)。
+//! 一项的长度可以比它实际需要的长，多出的空间留给之后插入的目录项；inode 编号为 0 表示这一项是空的。
+//! 目录项不会跨块
+
+use super::fs::{get_u16, get_u32, put_u16, put_u32, Ext2Inner, ROOT_INO};
+use super::inode::{Inode, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IFSOCK};
+use crate::syscall::ErrorNo;
+use crate::timer::get_time_sec;
+use alloc::{string::String, vec, vec::Vec};
+
+/// 目录项头部的长度，后面紧跟名字
+const ENTRY_HEADER_SIZE: usize = 8;
+/// 名字的最大长度
+const NAME_MAX: usize = 255;
+/// 解析路径时最多跟随的符号链接数
+const MAX_SYMLINK_FOLLOWS: usize = 40;
+
+/// 一个目录项
+pub struct DirEntry {
+    pub ino: u32,
+    pub name: String,
+    /// 目录项中记录的文件类型，没有记录时为 0
+    file_type: u8,
+}
+
+impl DirEntry {
+    /// 文件类型，格式同 inode 中 mode 的类型部分。目录项中没有记录类型时从 inode 中读取
+    pub fn file_type(&self, fs: &Ext2Inner) -> Result<u16, ErrorNo> {
+        Ok(match self.file_type {
+            1 => S_IFREG,
+            2 => S_IFDIR,
+            3 => S_IFCHR,
+            4 => S_IFBLK,
+            5 => S_IFIFO,
+            6 => S_IFSOCK,
+            7 => S_IFLNK,
+            _ => fs.read_inode(self.ino)?.file_type(),
+        })
+    }
+}
+
+/// 目录项中记录的文件类型
+fn entry_file_type(mode: u16) -> u8 {
+    match mode & S_IFMT {
+        S_IFREG => 1,
+        S_IFDIR => 2,
+        S_IFCHR => 3,
+        S_IFBLK => 4,
+        S_IFIFO => 5,
+        S_IFSOCK => 6,
+        S_IFLNK => 7,
+        _ => 0,
+    }
+}
+
+/// 名字长度为 name_len 的目录项至少需要的长度，按 4 字节对齐
+fn entry_size(name_len: usize) -> usize {
+    (ENTRY_HEADER_SIZE + name_len + 3) & !3
+}
+
+/// 解析目录块中 offset 处的目录项头部，返回 (inode 编号, 这一项的长度, 名字长度, 文件类型)
+fn parse_entry(block: &[u8], offset: usize) -> Result<(u32, usize, usize, u8), ErrorNo> {
+    let rec_len = get_u16(block, offset + 4) as usize;
+    let name_len = block[offset + 6] as usize;
+    if rec_len < ENTRY_HEADER_SIZE || offset + rec_len > block.len() || ENTRY_HEADER_SIZE + name_len > rec_len {
+        warn!("ext2: corrupted directory entry at offset {}", offset);
+        return Err(ErrorNo::EIO);
+    }
+    Ok((get_u32(block, offset), rec_len, name_len, block[offset + 7]))
+}
+
+/// 在目录块的 offset 处写入一个目录项的头部和名字
+fn write_entry(block: &mut [u8], offset: usize, ino: u32, rec_len: usize, name: &str, file_type: u8) {
+    put_u32(block, offset, ino);
+    put_u16(block, offset + 4, rec_len as u16);
+    block[offset + 6] = name.len() as u8;
+    block[offset + 7] = file_type;
+    block[offset + ENTRY_HEADER_SIZE..offset + ENTRY_HEADER_SIZE + name.len()].copy_from_slice(name.as_bytes());
+}
+
+impl Ext2Inner {
+    /// 读取目录的第 index 个块，返回块号和内容。目录中不应该有空洞
+    fn read_dir_block(&mut self, dir: &mut Inode, index: usize) -> Result<(u32, Vec<u8>), ErrorNo> {
+        let block = self.map_block(dir, index, false)?;
+        if block == 0 {
+            return Err(ErrorNo::EIO);
+        }
+        let mut buf = vec![0u8; self.block_size];
+        self.read_block(block, 0, &mut buf)?;
+        Ok((block, buf))
+    }
+    /// 目录的块数
+    fn dir_blocks(&self, dir: &Inode) -> usize {
+        dir.size as usize / self.block_size
+    }
+    /// 列出目录中的所有目录项
+    pub fn dir_entries(&mut self, dir: &mut Inode) -> Result<Vec<DirEntry>, ErrorNo> {
+        let mut entries = Vec::new();
+        for index in 0..self.dir_blocks(dir) {
+            let (_, block) = self.read_dir_block(dir, index)?;
+            let mut offset = 0;
+            while offset < block.len() {
+                let (ino, rec_len, name_len, file_type) = parse_entry(&block, offset)?;
+                if ino != 0 {
+                    let name = &block[offset + ENTRY_HEADER_SIZE..offset + ENTRY_HEADER_SIZE + name_len];
+                    entries.push(DirEntry {
+                        ino: ino,
+                        name: String::from_utf8_lossy(name).into_owned(),
+                        file_type: if self.has_file_type { file_type } else { 0 },
+                    });
+                }
+                offset += rec_len;
+            }
+        }
+        Ok(entries)
+    }
+    /// 在目录中找名字为 name 的项，返回它的 inode 编号
+    fn find_entry(&mut self, dir: &mut Inode, name: &str) -> Result<Option<u32>, ErrorNo> {
+        Ok(self
+            .dir_entries(dir)?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.ino))
+    }
+    /// 在目录中加入一项 name，指向 inode。不检查是否重名
+    pub fn add_entry(&mut self, dir: &mut Inode, name: &str, inode: &Inode) -> Result<(), ErrorNo> {
+        if name.len() > NAME_MAX {
+            return Err(ErrorNo::ENAMETOOLONG);
+        }
+        let need = entry_size(name.len());
+        let file_type = if self.has_file_type { entry_file_type(inode.mode) } else { 0 };
+        for index in 0..self.dir_blocks(dir) {
+            let (block_id, mut block) = self.read_dir_block(dir, index)?;
+            let mut offset = 0;
+            while offset < block.len() {
+                let (ino, rec_len, name_len, _) = parse_entry(&block, offset)?;
+                // 空的项整个可用，否则只有名字之后多出的空间可用
+                let used = if ino == 0 { 0 } else { entry_size(name_len) };
+                if rec_len - used >= need {
+                    if used > 0 {
+                        put_u16(&mut block, offset + 4, used as u16);
+                    }
+                    write_entry(&mut block, offset + used, inode.ino, rec_len - used, name, file_type);
+                    self.write_block(block_id, 0, &block)?;
+                    return self.dir_modified(dir);
+                }
+                offset += rec_len;
+            }
+        }
+        // 已有的块都放不下，在目录末尾加一块
+        let mut block = vec![0u8; self.block_size];
+        write_entry(&mut block, 0, inode.ino, self.block_size, name, file_type);
+        let block_id = self.map_block(dir, self.dir_blocks(dir), true)?;
+        self.write_block(block_id, 0, &block)?;
+        dir.size += self.block_size as u64;
+        self.dir_modified(dir)
+    }
+    /// 从目录中删除名字为 name 的项，返回它指向的 inode 编号
+    fn remove_entry(&mut self, dir: &mut Inode, name: &str) -> Result<u32, ErrorNo> {
+        for index in 0..self.dir_blocks(dir) {
+            let (block_id, mut block) = self.read_dir_block(dir, index)?;
+            let mut offset = 0;
+            let mut prev = None;
+            while offset < block.len() {
+                let (ino, rec_len, name_len, _) = parse_entry(&block, offset)?;
+                if ino != 0 && &block[offset + ENTRY_HEADER_SIZE..offset + ENTRY_HEADER_SIZE + name_len] == name.as_bytes() {
+                    match prev {
+                        // 并入前一项
+                        Some(prev) => {
+                            let prev_len = get_u16(&block, prev + 4) as usize;
+                            put_u16(&mut block, prev + 4, (prev_len + rec_len) as u16);
+                        }
+                        // 块中的第一项没有前一项，只把它标记为空
+                        None => put_u32(&mut block, offset, 0),
+                    }
+                    self.write_block(block_id, 0, &block)?;
+                    self.dir_modified(dir)?;
+                    return Ok(ino);
+                }
+                prev = Some(offset);
+                offset += rec_len;
+            }
+        }
+        Err(ErrorNo::ENOENT)
+    }
+    /// 把目录中名字为 name 的项改为指向 ino。用于移动目录后修改 ".."
+    fn set_entry(&mut self, dir: &mut Inode, name: &str, ino: u32) -> Result<(), ErrorNo> {
+        for index in 0..self.dir_blocks(dir) {
+            let (block_id, mut block) = self.read_dir_block(dir, index)?;
+            let mut offset = 0;
+            while offset < block.len() {
+                let (old_ino, rec_len, name_len, _) = parse_entry(&block, offset)?;
+                if old_ino != 0 && &block[offset + ENTRY_HEADER_SIZE..offset + ENTRY_HEADER_SIZE + name_len] == name.as_bytes() {
+                    put_u32(&mut block, offset, ino);
+                    return self.write_block(block_id, 0, &block);
+                }
+                offset += rec_len;
+            }
+        }
+        Err(ErrorNo::ENOENT)
+    }
+    /// 从目录 start 开始查找 path，返回找到的 inode 编号。
+    /// 路径中间的符号链接总是跟随，最后一项是符号链接时只有 follow 为 true 才跟随。
+    /// 符号链接中的绝对路径从这个文件系统的根目录开始查找
+    pub fn lookup(&mut self, start: u32, path: &str, follow: bool) -> Result<u32, ErrorNo> {
+        // 还没有查找的部分，倒序存放，栈顶是下一项
+        let mut rest: Vec<String> = path.split('/').rev().map(String::from).collect();
+        let mut current = start;
+        let mut follows = 0;
+        while let Some(name) = rest.pop() {
+            if name.is_empty() || name == "." {
+                continue;
+            }
+            let mut dir = self.read_inode(current)?;
+            if !dir.is_dir() {
+                return Err(ErrorNo::ENOTDIR);
+            }
+            let ino = self.find_entry(&mut dir, &name)?.ok_or(ErrorNo::ENOENT)?;
+            let mut inode = self.read_inode(ino)?;
+            let is_last = rest.iter().all(|name| name.is_empty() || name == ".");
+            if inode.is_symlink() && (follow || !is_last) {
+                follows += 1;
+                if follows > MAX_SYMLINK_FOLLOWS {
+                    return Err(ErrorNo::ELOOP);
+                }
+                let target = self.read_symlink(&mut inode)?;
+                if target.starts_with('/') {
+                    current = ROOT_INO;
+                }
+                rest.extend(target.split('/').rev().map(String::from));
+                continue;
+            }
+            current = ino;
+        }
+        Ok(current)
+    }
+    /// 查找目录 path，path 以 "./" 开头，是文件系统中的路径
+    pub fn lookup_dir(&mut self, path: &str) -> Result<u32, ErrorNo> {
+        let ino = self.lookup(ROOT_INO, path, true)?;
+        if self.read_inode(ino)?.is_dir() {
+            Ok(ino)
+        } else {
+            Err(ErrorNo::ENOTDIR)
+        }
+    }
+    /// 在目录 parent 中新建 name，类型和权限为 mode，链接数为 links。name 已存在时返回 EEXIST
+    pub fn create(&mut self, parent: &mut Inode, name: &str, mode: u16, links: u16) -> Result<Inode, ErrorNo> {
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(ErrorNo::EEXIST);
+        }
+        if self.find_entry(parent, name)?.is_some() {
+            return Err(ErrorNo::EEXIST);
+        }
+        let mut inode = self.new_inode(parent.ino, mode, links)?;
+        if let Err(e) = self.add_entry(parent, name, &inode) {
+            // 目录项加不进去，把刚分配的 inode 还回去
+            inode.links = 1;
+            self.drop_link(&mut inode)?;
+            return Err(e);
+        }
+        Ok(inode)
+    }
+    /// 在目录 parent 中新建子目录 name
+    pub fn make_dir(&mut self, parent: &mut Inode, name: &str, mode: u16) -> Result<(), ErrorNo> {
+        // 新目录的链接来自父目录中的项和自己的 "."
+        let mut dir = self.create(parent, name, mode, 2)?;
+        let mut block = vec![0u8; self.block_size];
+        let dir_type = if self.has_file_type { entry_file_type(S_IFDIR) } else { 0 };
+        write_entry(&mut block, 0, dir.ino, entry_size(1), ".", dir_type);
+        write_entry(&mut block, entry_size(1), parent.ino, self.block_size - entry_size(1), "..", dir_type);
+        let block_id = self.map_block(&mut dir, 0, true)?;
+        self.write_block(block_id, 0, &block)?;
+        dir.size = self.block_size as u64;
+        self.write_inode(&dir)?;
+        // 子目录的 ".." 是父目录的一个链接
+        parent.links += 1;
+        self.write_inode(parent)
+    }
+    /// 目录中是否只有 "." 和 ".."
+    fn is_empty_dir(&mut self, dir: &mut Inode) -> Result<bool, ErrorNo> {
+        Ok(self
+            .dir_entries(dir)?
+            .iter()
+            .all(|entry| entry.name == "." || entry.name == ".."))
+    }
+    /// 删除目录 parent 中的 name。目录必须为空
+    pub fn unlink(&mut self, parent: &mut Inode, name: &str) -> Result<(), ErrorNo> {
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(ErrorNo::EINVAL);
+        }
+        let ino = self.find_entry(parent, name)?.ok_or(ErrorNo::ENOENT)?;
+        let mut inode = self.read_inode(ino)?;
+        if inode.is_dir() && !self.is_empty_dir(&mut inode)? {
+            return Err(ErrorNo::ENOTEMPTY);
+        }
+        self.remove_entry(parent, name)?;
+        if inode.is_dir() {
+            parent.links -= 1;
+            self.write_inode(parent)?;
+        }
+        self.drop_link(&mut inode)
+    }
+    /// dir 是否是 ancestor 自己或者它的子孙目录
+    fn is_descendant(&mut self, dir: u32, ancestor: u32) -> Result<bool, ErrorNo> {
+        let mut current = dir;
+        loop {
+            if current == ancestor {
+                return Ok(true);
+            }
+            if current == ROOT_INO {
+                return Ok(false);
+            }
+            let mut inode = self.read_inode(current)?;
+            current = self.find_entry(&mut inode, "..")?.ok_or(ErrorNo::EIO)?;
+        }
+    }
+    /// 把目录 old_parent 中的 old_name 移动到目录 new_parent 中，改名为 new_name
+    pub fn rename(&mut self, old_parent: u32, old_name: &str, new_parent: u32, new_name: &str, replace: bool) -> Result<(), ErrorNo> {
+        if [old_name, new_name].iter().any(|name| name.is_empty() || *name == "." || *name == "..") {
+            return Err(ErrorNo::EINVAL);
+        }
+        let mut old_dir = self.read_inode(old_parent)?;
+        let ino = self.find_entry(&mut old_dir, old_name)?.ok_or(ErrorNo::ENOENT)?;
+        let mut inode = self.read_inode(ino)?;
+        let mut new_dir = self.read_inode(new_parent)?;
+        if inode.is_dir() && self.is_descendant(new_parent, ino)? {
+            // 不能把目录移动到它自己里面
+            return Err(ErrorNo::EINVAL);
+        }
+        if let Some(existing) = self.find_entry(&mut new_dir, new_name)? {
+            if existing == ino {
+                return Ok(());
+            }
+            if !replace {
+                return Err(ErrorNo::EEXIST);
+            }
+            let existing = self.read_inode(existing)?;
+            match (inode.is_dir(), existing.is_dir()) {
+                (true, false) => return Err(ErrorNo::ENOTDIR),
+                (false, true) => return Err(ErrorNo::EISDIR),
+                _ => {}
+            }
+            self.unlink(&mut new_dir, new_name)?;
+        }
+        self.add_entry(&mut new_dir, new_name, &inode)?;
+        // 两个目录相同时，new_dir 中是刚修改过的内容
+        let mut old_dir = if old_parent == new_parent { new_dir.clone() } else { old_dir };
+        self.remove_entry(&mut old_dir, old_name)?;
+        if inode.is_dir() && old_parent != new_parent {
+            self.set_entry(&mut inode, "..", new_parent)?;
+            old_dir.links -= 1;
+            self.write_inode(&old_dir)?;
+            new_dir.links += 1;
+            self.write_inode(&new_dir)?;
+        }
+        inode.ctime = get_time_sec() as u32;
+        self.write_inode(&inode)
+    }
+}
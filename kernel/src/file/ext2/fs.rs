@@ -0,0 +1,666 @@
+//! ext2 文件系统的整体结构：超级块、块组描述符，以及块和 inode 的分配。
+//!
+//! 所有修改都在一把锁 `Ext2FileSystem::inner` 下进行，锁内再通过块缓存读写磁盘
+
+use super::dir::DirEntry;
+use super::file::Ext2File;
+use super::inode::{Inode, FAST_SYMLINK_MAX, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IFSOCK};
+use crate::drivers::BLOCK_CACHE;
+use crate::file::{File, FsStat, Kstat, OpenFlags, StMode, UnixFs};
+use crate::syscall::ErrorNo;
+use crate::timer::{get_time_sec, TimeSpec};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use lock::Mutex;
+
+/// 超级块在分区中的位置
+const SUPERBLOCK_OFFSET: usize = 1024;
+/// 超级块中的魔数
+pub const EXT2_MAGIC: u16 = 0xef53;
+/// 根目录的 inode 编号
+pub const ROOT_INO: u32 = 2;
+/// 块组描述符的大小
+const GROUP_DESC_SIZE: usize = 32;
+/// 支持的不兼容特性：目录项中记录文件类型
+const INCOMPAT_SUPPORTED: u32 = 0x2;
+/// 支持的只读兼容特性：稀疏的超级块备份、大于 2G 的文件
+const RO_COMPAT_SUPPORTED: u32 = 0x1 | 0x2;
+/// 新建的普通文件和目录的权限
+const DEFAULT_FILE_PERM: u16 = 0o644;
+const DEFAULT_DIR_PERM: u16 = 0o755;
+
+/// 从 buf 的 offset 处读写小端序整数
+pub fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+pub fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+pub fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+pub fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// 块组描述符
+pub struct GroupDesc {
+    /// 块位图所在的块
+    block_bitmap: u32,
+    /// inode 位图所在的块
+    inode_bitmap: u32,
+    /// inode 表的第一个块
+    pub inode_table: u32,
+    free_blocks: u16,
+    free_inodes: u16,
+    used_dirs: u16,
+}
+
+/// ext2 文件系统
+pub struct Ext2FileSystem {
+    inner: Mutex<Ext2Inner>,
+}
+
+/// 文件系统的可变部分
+pub struct Ext2Inner {
+    /// 分区在块设备上的起始位置(字节)
+    start: usize,
+    pub block_size: usize,
+    pub inode_size: usize,
+    pub inodes_count: u32,
+    pub inodes_per_group: u32,
+    blocks_count: u32,
+    blocks_per_group: u32,
+    /// 保留给 root 的块数
+    reserved_blocks: u32,
+    free_blocks: u32,
+    free_inodes: u32,
+    /// 第一个块组的起始块。块大小为 1K 时是 1，否则是 0
+    first_data_block: u32,
+    /// 第一个可以分配给文件的 inode，之前的是保留的
+    first_ino: u32,
+    /// 目录项中是否记录文件类型
+    pub has_file_type: bool,
+    pub groups: Vec<GroupDesc>,
+    /// 每个 inode 被打开的次数
+    open_count: BTreeMap<u32, usize>,
+    /// 已经没有链接、但还被打开的 inode，最后一次关闭时才释放
+    orphans: BTreeSet<u32>,
+}
+
+impl Ext2Inner {
+    /// 读写分区中 offset 处的数据
+    pub fn read_bytes(&self, offset: usize, buf: &mut [u8]) -> Result<(), ErrorNo> {
+        BLOCK_CACHE.read(self.start + offset, buf).map(|_| ())
+    }
+    pub fn write_bytes(&self, offset: usize, buf: &[u8]) -> Result<(), ErrorNo> {
+        BLOCK_CACHE.write(self.start + offset, buf).map(|_| ())
+    }
+    /// 读写第 block 块中 offset 处的数据
+    pub fn read_block(&self, block: u32, offset: usize, buf: &mut [u8]) -> Result<(), ErrorNo> {
+        self.read_bytes(block as usize * self.block_size + offset, buf)
+    }
+    pub fn write_block(&self, block: u32, offset: usize, buf: &[u8]) -> Result<(), ErrorNo> {
+        self.write_bytes(block as usize * self.block_size + offset, buf)
+    }
+    /// 把块看作 u32 数组(即间接块)，读写其中第 index 项
+    pub fn read_block_u32(&self, block: u32, index: usize) -> Result<u32, ErrorNo> {
+        let mut buf = [0u8; 4];
+        self.read_block(block, index * 4, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+    pub fn write_block_u32(&self, block: u32, index: usize, value: u32) -> Result<(), ErrorNo> {
+        self.write_block(block, index * 4, &value.to_le_bytes())
+    }
+    /// inode 所在的块组。分配块时优先放在这里
+    pub fn group_of_ino(&self, ino: u32) -> usize {
+        ((ino - 1) / self.inodes_per_group) as usize
+    }
+    /// 把空闲块数和空闲 inode 数写回超级块
+    fn write_super_counts(&self) -> Result<(), ErrorNo> {
+        let mut counts = [0u8; 8];
+        put_u32(&mut counts, 0, self.free_blocks);
+        put_u32(&mut counts, 4, self.free_inodes);
+        self.write_bytes(SUPERBLOCK_OFFSET + 12, &counts)
+    }
+    /// 把块组描述符写回磁盘
+    fn write_group(&self, group: usize) -> Result<(), ErrorNo> {
+        let desc = &self.groups[group];
+        let mut raw = [0u8; 18];
+        put_u32(&mut raw, 0, desc.block_bitmap);
+        put_u32(&mut raw, 4, desc.inode_bitmap);
+        put_u32(&mut raw, 8, desc.inode_table);
+        put_u16(&mut raw, 12, desc.free_blocks);
+        put_u16(&mut raw, 14, desc.free_inodes);
+        put_u16(&mut raw, 16, desc.used_dirs);
+        let table = (self.first_data_block as usize + 1) * self.block_size;
+        self.write_bytes(table + group * GROUP_DESC_SIZE, &raw)
+    }
+    /// 在位图的前 count 位中找到一个 0，把它置为 1 并返回它的位置
+    fn alloc_bit(&self, bitmap: u32, count: usize) -> Result<Option<usize>, ErrorNo> {
+        let mut bits = vec![0u8; self.block_size];
+        self.read_block(bitmap, 0, &mut bits)?;
+        for (i, byte) in bits.iter().enumerate().take((count + 7) / 8) {
+            if *byte == 0xff {
+                continue;
+            }
+            let bit = i * 8 + byte.trailing_ones() as usize;
+            if bit >= count {
+                break;
+            }
+            self.write_block(bitmap, i, &[byte | 1 << (bit % 8)])?;
+            return Ok(Some(bit));
+        }
+        Ok(None)
+    }
+    /// 把位图中的第 bit 位置为 0
+    fn free_bit(&self, bitmap: u32, bit: usize) -> Result<(), ErrorNo> {
+        let mut byte = [0u8; 1];
+        self.read_block(bitmap, bit / 8, &mut byte)?;
+        if byte[0] & 1 << (bit % 8) == 0 {
+            warn!("ext2: freeing a free bit {} in bitmap block {}", bit, bitmap);
+            return Err(ErrorNo::EIO);
+        }
+        self.write_block(bitmap, bit / 8, &[byte[0] & !(1 << (bit % 8))])
+    }
+    /// 分配一个块并清零，优先从块组 goal 中找。返回块号。
+    /// 超级块中 s_r_blocks_count 保留的块是给 root 用的，而内核中所有进程都以 root 运行，
+    /// 所以可以用完所有空闲块，之后才返回 ENOSPC。这和 statfs 中的 f_bfree 一致
+    pub fn alloc_block(&mut self, goal: usize) -> Result<u32, ErrorNo> {
+        if self.free_blocks == 0 {
+            return Err(ErrorNo::ENOSPC);
+        }
+        for i in 0..self.groups.len() {
+            let group = (goal + i) % self.groups.len();
+            if self.groups[group].free_blocks == 0 {
+                continue;
+            }
+            // 最后一个块组可能不满
+            let group_start = self.first_data_block + group as u32 * self.blocks_per_group;
+            let count = self.blocks_per_group.min(self.blocks_count - group_start) as usize;
+            if let Some(bit) = self.alloc_bit(self.groups[group].block_bitmap, count)? {
+                self.groups[group].free_blocks -= 1;
+                self.free_blocks -= 1;
+                self.write_group(group)?;
+                self.write_super_counts()?;
+                let block = group_start + bit as u32;
+                self.write_block(block, 0, &vec![0u8; self.block_size])?;
+                return Ok(block);
+            }
+        }
+        Err(ErrorNo::ENOSPC)
+    }
+    /// 释放一个块
+    pub fn free_block(&mut self, block: u32) -> Result<(), ErrorNo> {
+        if block < self.first_data_block || block >= self.blocks_count {
+            return Err(ErrorNo::EIO);
+        }
+        let group = ((block - self.first_data_block) / self.blocks_per_group) as usize;
+        let bit = ((block - self.first_data_block) % self.blocks_per_group) as usize;
+        self.free_bit(self.groups[group].block_bitmap, bit)?;
+        self.groups[group].free_blocks += 1;
+        self.free_blocks += 1;
+        self.write_group(group)?;
+        self.write_super_counts()
+    }
+    /// 分配一个 inode 并清空它在磁盘上的内容，优先从块组 goal 中找。返回 inode 编号
+    fn alloc_inode(&mut self, goal: usize, is_dir: bool) -> Result<u32, ErrorNo> {
+        if self.free_inodes == 0 {
+            return Err(ErrorNo::ENOSPC);
+        }
+        for i in 0..self.groups.len() {
+            let group = (goal + i) % self.groups.len();
+            if self.groups[group].free_inodes == 0 {
+                continue;
+            }
+            if let Some(bit) = self.alloc_bit(self.groups[group].inode_bitmap, self.inodes_per_group as usize)? {
+                let ino = group as u32 * self.inodes_per_group + bit as u32 + 1;
+                if ino < self.first_ino {
+                    // 保留的 inode 在 mkfs 时就已经标记为使用了，走到这里说明位图损坏
+                    return Err(ErrorNo::EIO);
+                }
+                self.groups[group].free_inodes -= 1;
+                if is_dir {
+                    self.groups[group].used_dirs += 1;
+                }
+                self.free_inodes -= 1;
+                self.write_group(group)?;
+                self.write_super_counts()?;
+                self.clear_inode(ino)?;
+                return Ok(ino);
+            }
+        }
+        Err(ErrorNo::ENOSPC)
+    }
+    /// 释放 inode 在位图中的位置
+    fn free_inode_bit(&mut self, ino: u32, is_dir: bool) -> Result<(), ErrorNo> {
+        let group = self.group_of_ino(ino);
+        let bit = ((ino - 1) % self.inodes_per_group) as usize;
+        self.free_bit(self.groups[group].inode_bitmap, bit)?;
+        self.groups[group].free_inodes += 1;
+        if is_dir {
+            self.groups[group].used_dirs -= 1;
+        }
+        self.free_inodes += 1;
+        self.write_group(group)?;
+        self.write_super_counts()
+    }
+    /// 新建一个类型和权限为 mode 的 inode，链接数为 links，写回磁盘后返回
+    pub fn new_inode(&mut self, parent: u32, mode: u16, links: u16) -> Result<Inode, ErrorNo> {
+        let ino = self.alloc_inode(self.group_of_ino(parent), mode & S_IFMT == S_IFDIR)?;
+        let mut inode = Inode::new(ino, mode);
+        inode.links = links;
+        self.write_inode(&inode)?;
+        Ok(inode)
+    }
+    /// inode 的链接数减一。没有链接时释放它，除非它还被打开着
+    pub fn drop_link(&mut self, inode: &mut Inode) -> Result<(), ErrorNo> {
+        // 目录的 "." 也算一个链接，删除目录时直接清零
+        inode.links = if inode.is_dir() { 0 } else { inode.links.saturating_sub(1) };
+        inode.ctime = get_time_sec() as u32;
+        if inode.links > 0 {
+            return self.write_inode(inode);
+        }
+        if self.open_count.contains_key(&inode.ino) {
+            self.orphans.insert(inode.ino);
+            return self.write_inode(inode);
+        }
+        self.free_inode(inode)
+    }
+    /// 释放 inode 及其所有数据块
+    fn free_inode(&mut self, inode: &mut Inode) -> Result<(), ErrorNo> {
+        self.free_data_blocks(inode, 0)?;
+        inode.size = 0;
+        inode.dtime = get_time_sec() as u32;
+        self.write_inode(inode)?;
+        self.free_inode_bit(inode.ino, inode.is_dir())
+    }
+}
+
+impl Ext2FileSystem {
+    /// 读取分区上 start 处的 ext2 文件系统。不是 ext2 或者有不支持的特性时返回 EINVAL
+    pub fn new(start: usize) -> Result<Self, ErrorNo> {
+        let mut sb = [0u8; 1024];
+        BLOCK_CACHE.read(start + SUPERBLOCK_OFFSET, &mut sb)?;
+        if get_u16(&sb, 56) != EXT2_MAGIC {
+            return Err(ErrorNo::EINVAL);
+        }
+        let rev_level = get_u32(&sb, 76);
+        let (first_ino, inode_size, incompat, ro_compat) = if rev_level == 0 {
+            (11, 128, 0, 0)
+        } else {
+            (get_u32(&sb, 84), get_u16(&sb, 88) as usize, get_u32(&sb, 96), get_u32(&sb, 100))
+        };
+        if incompat & !INCOMPAT_SUPPORTED != 0 || ro_compat & !RO_COMPAT_SUPPORTED != 0 {
+            warn!("ext2: unsupported features incompat={:#x} ro_compat={:#x}", incompat, ro_compat);
+            return Err(ErrorNo::EINVAL);
+        }
+        // 超级块可能是损坏的，先检查过再用于移位和减法，以免溢出
+        let log_block_size = get_u32(&sb, 24);
+        let blocks_count = get_u32(&sb, 4);
+        let first_data_block = get_u32(&sb, 20);
+        if log_block_size > 6 || first_data_block >= blocks_count {
+            warn!("ext2: bad superblock log_block_size={} first_data_block={} blocks_count={}", log_block_size, first_data_block, blocks_count);
+            return Err(ErrorNo::EINVAL);
+        }
+        let block_size = 1024 << log_block_size;
+        let blocks_per_group = get_u32(&sb, 32);
+        let inodes_per_group = get_u32(&sb, 40);
+        if blocks_per_group == 0 || inodes_per_group == 0 || inode_size < 128 || block_size >= 0x10000 {
+            return Err(ErrorNo::EINVAL);
+        }
+        let data_blocks = (blocks_count - first_data_block) as usize;
+        let group_count = (data_blocks + blocks_per_group as usize - 1) / blocks_per_group as usize;
+        let mut table = vec![0u8; group_count * GROUP_DESC_SIZE];
+        BLOCK_CACHE.read(start + (first_data_block as usize + 1) * block_size, &mut table)?;
+        let groups = table
+            .chunks(GROUP_DESC_SIZE)
+            .map(|raw| GroupDesc {
+                block_bitmap: get_u32(raw, 0),
+                inode_bitmap: get_u32(raw, 4),
+                inode_table: get_u32(raw, 8),
+                free_blocks: get_u16(raw, 12),
+                free_inodes: get_u16(raw, 14),
+                used_dirs: get_u16(raw, 16),
+            })
+            .collect();
+        Ok(Self {
+            inner: Mutex::new(Ext2Inner {
+                start: start,
+                block_size: block_size,
+                inode_size: inode_size,
+                inodes_count: get_u32(&sb, 0),
+                inodes_per_group: inodes_per_group,
+                blocks_count: blocks_count,
+                blocks_per_group: blocks_per_group,
+                reserved_blocks: get_u32(&sb, 8),
+                free_blocks: get_u32(&sb, 12),
+                free_inodes: get_u32(&sb, 16),
+                first_data_block: first_data_block,
+                first_ino: first_ino,
+                has_file_type: incompat & INCOMPAT_SUPPORTED != 0,
+                groups: groups,
+                open_count: BTreeMap::new(),
+                orphans: BTreeSet::new(),
+            }),
+        })
+    }
+}
+
+impl Drop for Ext2FileSystem {
+    /// 卸载后最后一个打开的文件也关闭时，把缓存中的修改写回设备
+    fn drop(&mut self) {
+        if BLOCK_CACHE.sync().is_err() {
+            warn!("ext2: failed to write back the filesystem when releasing it");
+        }
+    }
+}
+
+impl UnixFs for Ext2FileSystem {
+    /// 打开 dir 目录下的 name 文件，dir 是文件系统中的路径，full_dir 是它在整个目录树中的路径。
+    /// 规则和 FAT 中的 open_file 相同，name 为空时打开 dir 本身
+    fn open(self: Arc<Self>, dir: &str, name: &str, full_dir: &str, flags: OpenFlags) -> Option<Arc<dyn File>> {
+        let mut inner = self.inner.lock();
+        let parent = inner.lookup_dir(dir).ok()?;
+        let ino = match inner.lookup(parent, name, !flags.contains(OpenFlags::NOFOLLOW)) {
+            Ok(ino) => {
+                if flags.contains(OpenFlags::CREATE) && flags.contains(OpenFlags::EXCLUSIVE) {
+                    return None;
+                }
+                ino
+            }
+            Err(ErrorNo::ENOENT) if flags.contains(OpenFlags::CREATE) && !name.is_empty() => {
+                let mut parent = inner.read_inode(parent).ok()?;
+                inner.create(&mut parent, name, S_IFREG | DEFAULT_FILE_PERM, 1).ok()?.ino
+            }
+            Err(_) => return None,
+        };
+        let mut inode = inner.read_inode(ino).ok()?;
+        let (readable, writable) = flags.read_write();
+        let full_path = String::from(full_dir) + name;
+        if flags.contains(OpenFlags::DIR) || flags.contains(OpenFlags::DSYNC) || name.is_empty() {
+            if !inode.is_dir() {
+                return None;
+            }
+            drop(inner);
+            return Some(Arc::new(Ext2File::new(self, ino, full_path, true, false, false, flags)));
+        }
+        if inode.is_dir() {
+            return None;
+        }
+        if inode.is_reg() && writable && flags.contains(OpenFlags::TRUNC) {
+            inner.truncate(&mut inode, 0).ok()?;
+        }
+        drop(inner);
+        Some(Arc::new(Ext2File::new(self, ino, full_path, false, readable, writable, flags)))
+    }
+    /// dir 目录下的 name 是否存在，存在时返回它是否是目录。会跟随符号链接
+    fn exists(&self, dir: &str, name: &str) -> Option<bool> {
+        let mut inner = self.inner.lock();
+        let parent = inner.lookup_dir(dir).ok()?;
+        let ino = inner.lookup(parent, name, true).ok()?;
+        inner.read_inode(ino).ok().map(|inode| inode.is_dir())
+    }
+    /// 在 dir 目录下创建 name 目录
+    fn mkdir(&self, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let mut inner = self.inner.lock();
+        let parent = inner.lookup_dir(dir)?;
+        let mut parent = inner.read_inode(parent)?;
+        inner.make_dir(&mut parent, name, S_IFDIR | DEFAULT_DIR_PERM)
+    }
+    /// 删除 dir 目录下的 name。可以是文件，也可以是空目录
+    fn remove(&self, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let mut inner = self.inner.lock();
+        let parent = inner.lookup_dir(dir)?;
+        let mut parent = inner.read_inode(parent)?;
+        inner.unlink(&mut parent, name)
+    }
+    /// 把 old_dir 目录下的 old_name 移动到 new_dir 目录下，改名为 new_name。
+    /// replace 为 false 时，如果目标已存在则返回 EEXIST
+    fn rename(&self, old_dir: &str, old_name: &str, new_dir: &str, new_name: &str, replace: bool) -> Result<(), ErrorNo> {
+        let mut inner = self.inner.lock();
+        let old_parent = inner.lookup_dir(old_dir)?;
+        let new_parent = inner.lookup_dir(new_dir)?;
+        inner.rename(old_parent, old_name, new_parent, new_name, replace)
+    }
+    /// 创建硬链接，new_dir 目录下的 new_name 指向 old_dir 目录下的 old_name
+    fn link(&self, old_dir: &str, old_name: &str, new_dir: &str, new_name: &str) -> Result<(), ErrorNo> {
+        let mut inner = self.inner.lock();
+        let old_parent = inner.lookup_dir(old_dir)?;
+        let ino = inner.lookup(old_parent, old_name, false)?;
+        let mut inode = inner.read_inode(ino)?;
+        if inode.is_dir() {
+            return Err(ErrorNo::EPERM);
+        }
+        let new_parent = inner.lookup_dir(new_dir)?;
+        if inner.lookup(new_parent, new_name, false).is_ok() {
+            return Err(ErrorNo::EEXIST);
+        }
+        let mut new_parent = inner.read_inode(new_parent)?;
+        inner.add_entry(&mut new_parent, new_name, &inode)?;
+        inode.links += 1;
+        inode.ctime = get_time_sec() as u32;
+        inner.write_inode(&inode)
+    }
+    /// 在 dir 目录下创建指向 target 的符号链接 name
+    fn symlink(&self, target: &str, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let mut inner = self.inner.lock();
+        if target.is_empty() {
+            return Err(ErrorNo::ENOENT);
+        }
+        if target.len() >= inner.block_size {
+            return Err(ErrorNo::ENAMETOOLONG);
+        }
+        let parent = inner.lookup_dir(dir)?;
+        let mut parent = inner.read_inode(parent)?;
+        let mut inode = inner.create(&mut parent, name, S_IFLNK | 0o777, 1)?;
+        if target.len() < FAST_SYMLINK_MAX {
+            inode.set_fast_symlink(target.as_bytes());
+            inner.write_inode(&inode)
+        } else {
+            inner.write_data(&mut inode, 0, target.as_bytes()).map(|_| ())
+        }
+    }
+    /// 在 dir 目录下创建设备文件、管道或普通文件 name。mode 包含文件类型，rdev 是设备号
+    fn mknod(&self, dir: &str, name: &str, mode: u32, rdev: u64) -> Result<(), ErrorNo> {
+        let mode = mode as u16;
+        let mode = match mode & S_IFMT {
+            // 没有给出类型时是普通文件
+            0 => mode | S_IFREG,
+            S_IFREG | S_IFCHR | S_IFBLK | S_IFIFO | S_IFSOCK => mode,
+            _ => return Err(ErrorNo::EINVAL),
+        };
+        let mut inner = self.inner.lock();
+        let parent = inner.lookup_dir(dir)?;
+        let mut parent = inner.read_inode(parent)?;
+        let mut inode = inner.create(&mut parent, name, mode, 1)?;
+        if matches!(mode & S_IFMT, S_IFCHR | S_IFBLK) {
+            inode.set_rdev(rdev);
+            inner.write_inode(&inode)?;
+        }
+        Ok(())
+    }
+    /// 读取 dir 目录下的符号链接 name 的目标路径
+    fn read_link(&self, dir: &str, name: &str) -> Result<String, ErrorNo> {
+        let mut inner = self.inner.lock();
+        let parent = inner.lookup_dir(dir)?;
+        let ino = inner.lookup(parent, name, false)?;
+        let mut inode = inner.read_inode(ino)?;
+        if !inode.is_symlink() {
+            return Err(ErrorNo::EINVAL);
+        }
+        inner.read_symlink(&mut inode)
+    }
+    /// 修改 dir 目录下的 name 的权限位
+    fn chmod(&self, dir: &str, name: &str, mode: u32) -> Result<(), ErrorNo> {
+        let mut inner = self.inner.lock();
+        let parent = inner.lookup_dir(dir)?;
+        let ino = inner.lookup(parent, name, true)?;
+        let mut inode = inner.read_inode(ino)?;
+        inode.mode = inode.file_type() | (mode & 0o7777) as u16;
+        inode.ctime = get_time_sec() as u32;
+        inner.write_inode(&inode)
+    }
+    /// 修改 dir 目录下的 name 的所有者和组，为 None 的一项不修改
+    fn chown(&self, dir: &str, name: &str, uid: Option<u32>, gid: Option<u32>, follow: bool) -> Result<(), ErrorNo> {
+        let mut inner = self.inner.lock();
+        let parent = inner.lookup_dir(dir)?;
+        let ino = inner.lookup(parent, name, follow)?;
+        let mut inode = inner.read_inode(ino)?;
+        inode.uid = uid.unwrap_or(inode.uid);
+        inode.gid = gid.unwrap_or(inode.gid);
+        inode.ctime = get_time_sec() as u32;
+        inner.write_inode(&inode)
+    }
+    /// 列出 dir 目录下的所有目录项，每项为 (名字, 文件类型)。包括 "." 和 ".."
+    fn list(&self, dir: &str) -> Result<Vec<(String, StMode)>, ErrorNo> {
+        let mut inner = self.inner.lock();
+        let ino = inner.lookup_dir(dir)?;
+        let mut dir = inner.read_inode(ino)?;
+        let entries = inner.dir_entries(&mut dir)?;
+        entries
+            .into_iter()
+            .map(|entry: DirEntry| {
+                let file_type = match entry.file_type(&inner)? {
+                    S_IFDIR => StMode::S_IFDIR,
+                    S_IFLNK => StMode::S_IFLNK,
+                    S_IFCHR => StMode::S_IFCHR,
+                    S_IFBLK => StMode::S_IFBLK,
+                    S_IFIFO => StMode::S_IFIFO,
+                    S_IFSOCK => StMode::S_IFSOCK,
+                    _ => StMode::S_IFREG,
+                };
+                Ok((entry.name, file_type))
+            })
+            .collect()
+    }
+    fn fs_type(&self) -> &'static str {
+        "ext2"
+    }
+    /// 文件系统的信息
+    fn stat_fs(&self, stat: *mut FsStat) {
+        let inner = self.inner.lock();
+        unsafe {
+            (*stat).f_type = EXT2_MAGIC as i64;
+            (*stat).f_bsize = inner.block_size as i64;
+            (*stat).f_blocks = inner.blocks_count as u64;
+            (*stat).f_bfree = inner.free_blocks as u64;
+            (*stat).f_bavail = inner.free_blocks.saturating_sub(inner.reserved_blocks) as u64;
+            (*stat).f_files = inner.inodes_count as u64;
+            (*stat).f_ffree = inner.free_inodes as u64;
+            (*stat).f_fsid = [0, 0];
+            (*stat).f_namelen = 255;
+            (*stat).f_frsize = inner.block_size as isize;
+            (*stat).f_flags = 0;
+            (*stat).f_spare = [0, 0, 0, 0];
+        }
+    }
+}
+
+impl Ext2FileSystem {
+    /// 打开的文件登记 inode，防止它在关闭前被释放
+    pub fn inode_opened(&self, ino: u32) {
+        *self.inner.lock().open_count.entry(ino).or_insert(0) += 1;
+    }
+    /// 关闭文件。如果 inode 已经没有链接，并且这是最后一次关闭，就释放它
+    pub fn inode_closed(&self, ino: u32) {
+        let mut inner = self.inner.lock();
+        let count = inner.open_count.get_mut(&ino).unwrap();
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        inner.open_count.remove(&ino);
+        if inner.orphans.remove(&ino) {
+            let result = inner.read_inode(ino).and_then(|mut inode| inner.free_inode(&mut inode));
+            if result.is_err() {
+                warn!("ext2: failed to free inode {}", ino);
+            }
+        }
+    }
+    /// 从文件的 pos 处读数据
+    pub fn read_at(&self, ino: u32, pos: usize, buf: &mut [u8]) -> Result<usize, ErrorNo> {
+        let mut inner = self.inner.lock();
+        let mut inode = inner.read_inode(ino)?;
+        inner.read_data(&mut inode, pos, buf)
+    }
+    /// 向文件的 pos 处写数据。pos 为 None 时写到文件末尾。返回写入的长度和写完后的位置
+    pub fn write_at(&self, ino: u32, pos: Option<usize>, buf: &[u8]) -> Result<(usize, usize), ErrorNo> {
+        let mut inner = self.inner.lock();
+        let mut inode = inner.read_inode(ino)?;
+        let pos = pos.unwrap_or(inode.size as usize);
+        let len = inner.write_data(&mut inode, pos, buf)?;
+        Ok((len, pos + len))
+    }
+    /// 文件大小
+    pub fn size(&self, ino: u32) -> Result<usize, ErrorNo> {
+        let inner = self.inner.lock();
+        inner.read_inode(ino).map(|inode| inode.size as usize)
+    }
+    /// 修改文件大小
+    pub fn truncate(&self, ino: u32, size: usize) -> Result<(), ErrorNo> {
+        let mut inner = self.inner.lock();
+        let mut inode = inner.read_inode(ino)?;
+        if !inode.is_reg() {
+            return Err(ErrorNo::EINVAL);
+        }
+        inner.truncate(&mut inode, size)
+    }
+    /// 修改文件的访问时间和修改时间，格式同 TimeSpec::set_as_utime
+    pub fn set_time(&self, ino: u32, atime: &TimeSpec, mtime: &TimeSpec) -> Result<(), ErrorNo> {
+        let inner = self.inner.lock();
+        let mut inode = inner.read_inode(ino)?;
+        let mut new_atime = TimeSpec { tv_sec: inode.atime as usize, tv_nsec: 0 };
+        let mut new_mtime = TimeSpec { tv_sec: inode.mtime as usize, tv_nsec: 0 };
+        new_atime.set_as_utime(atime);
+        new_mtime.set_as_utime(mtime);
+        inode.atime = new_atime.tv_sec as u32;
+        inode.mtime = new_mtime.tv_sec as u32;
+        inode.ctime = get_time_sec() as u32;
+        inner.write_inode(&inode)
+    }
+    /// 把文件的属性写入 stat
+    pub fn stat(&self, ino: u32, stat: *mut Kstat) -> Result<(), ErrorNo> {
+        let inner = self.inner.lock();
+        let inode = inner.read_inode(ino)?;
+        let rdev = if matches!(inode.file_type(), S_IFCHR | S_IFBLK) { inode.rdev() } else { 0 };
+        unsafe {
+            (*stat).st_dev = 2;
+            (*stat).st_ino = ino as u64;
+            (*stat).st_mode = inode.mode as u32;
+            (*stat).st_nlink = inode.links as u32;
+            (*stat).st_uid = inode.uid;
+            (*stat).st_gid = inode.gid;
+            (*stat).st_rdev = rdev;
+            (*stat).st_size = inode.size;
+            (*stat).st_blksize = inner.block_size as u32;
+            (*stat).st_blocks = inode.blocks as u64;
+            (*stat).st_atime_sec = inode.atime as isize;
+            (*stat).st_atime_nsec = 0;
+            (*stat).st_mtime_sec = inode.mtime as isize;
+            (*stat).st_mtime_nsec = 0;
+            (*stat).st_ctime_sec = inode.ctime as isize;
+            (*stat).st_ctime_nsec = 0;
+        }
+        Ok(())
+    }
+    /// 读取文件全部内容
+    pub fn read_all(&self, ino: u32) -> Result<Vec<u8>, ErrorNo> {
+        let mut inner = self.inner.lock();
+        let mut inode = inner.read_inode(ino)?;
+        let mut buf = vec![0u8; inode.size as usize];
+        let len = inner.read_data(&mut inode, 0, &mut buf)?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+    /// 文件是否是普通文件。设备文件和管道等只能查看属性，不能读写
+    pub fn is_regular(&self, ino: u32) -> bool {
+        let inner = self.inner.lock();
+        inner.read_inode(ino).map_or(false, |inode| inode.is_reg())
+    }
+}
@@ -0,0 +1,430 @@
+//! ext2 的 inode，以及按 inode 读写文件数据
+//!
+//! 文件的数据块由 inode 中的 15 个指针找到：前 12 个直接指向数据块，
+//! 后 3 个分别指向一级、二级、三级间接块
+
+use super::fs::{get_u16, get_u32, put_u16, put_u32, Ext2Inner};
+use crate::syscall::ErrorNo;
+use crate::timer::get_time_sec;
+use alloc::{string::String, vec, vec::Vec};
+
+/// inode 中读写的部分的大小。更大的 inode 后面是扩展字段，这里不处理
+pub const INODE_RECORD_SIZE: usize = 128;
+/// 直接块指针的个数
+const DIRECT_BLOCKS: usize = 12;
+/// 数据直接存在 inode 块指针里的符号链接，目标路径的最大长度
+pub const FAST_SYMLINK_MAX: usize = 60;
+
+/// inode 中 mode 的文件类型部分
+pub const S_IFMT: u16 = 0xf000;
+pub const S_IFSOCK: u16 = 0xc000;
+pub const S_IFLNK: u16 = 0xa000;
+pub const S_IFREG: u16 = 0x8000;
+pub const S_IFBLK: u16 = 0x6000;
+pub const S_IFDIR: u16 = 0x4000;
+pub const S_IFCHR: u16 = 0x2000;
+pub const S_IFIFO: u16 = 0x1000;
+
+/// 目录使用了 hash 索引
+const EXT2_INDEX_FL: u32 = 0x1000;
+
+/// 内存中的 inode
+#[derive(Clone)]
+pub struct Inode {
+    /// inode 编号
+    pub ino: u32,
+    /// 文件类型和权限
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    /// 文件大小。目录的大小只有低 32 位
+    pub size: u64,
+    pub atime: u32,
+    pub ctime: u32,
+    pub mtime: u32,
+    /// 删除时间
+    pub dtime: u32,
+    /// 硬链接数
+    pub links: u16,
+    /// 占用的空间，以 512 字节为单位，包括间接块
+    pub blocks: u32,
+    pub flags: u32,
+    /// 块指针。符号链接和设备文件用它保存目标路径和设备号
+    pub block: [u32; 15],
+    /// 扩展属性所在的块
+    pub file_acl: u32,
+    /// 磁盘上的原始数据，保留这里没有解析的字段
+    raw: [u8; INODE_RECORD_SIZE],
+}
+
+impl Inode {
+    /// 新建一个类型和权限为 mode 的 inode，其余字段为空
+    pub fn new(ino: u32, mode: u16) -> Self {
+        let now = get_time_sec() as u32;
+        Self {
+            ino: ino,
+            mode: mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            atime: now,
+            ctime: now,
+            mtime: now,
+            dtime: 0,
+            links: 0,
+            blocks: 0,
+            flags: 0,
+            block: [0; 15],
+            file_acl: 0,
+            raw: [0; INODE_RECORD_SIZE],
+        }
+    }
+    /// 从磁盘上的数据解析 inode
+    fn parse(ino: u32, raw: [u8; INODE_RECORD_SIZE]) -> Self {
+        let mode = get_u16(&raw, 0);
+        let mut block = [0; 15];
+        for (i, ptr) in block.iter_mut().enumerate() {
+            *ptr = get_u32(&raw, 40 + i * 4);
+        }
+        // 普通文件的 i_size_high 是大小的高 32 位，目录的这个位置是 i_dir_acl
+        let size_high = if mode & S_IFMT == S_IFREG { get_u32(&raw, 108) as u64 } else { 0 };
+        Self {
+            ino: ino,
+            mode: mode,
+            uid: get_u16(&raw, 2) as u32 | (get_u16(&raw, 120) as u32) << 16,
+            gid: get_u16(&raw, 24) as u32 | (get_u16(&raw, 122) as u32) << 16,
+            size: get_u32(&raw, 4) as u64 | size_high << 32,
+            atime: get_u32(&raw, 8),
+            ctime: get_u32(&raw, 12),
+            mtime: get_u32(&raw, 16),
+            dtime: get_u32(&raw, 20),
+            links: get_u16(&raw, 26),
+            blocks: get_u32(&raw, 28),
+            flags: get_u32(&raw, 32),
+            block: block,
+            file_acl: get_u32(&raw, 104),
+            raw: raw,
+        }
+    }
+    /// 转换成磁盘上的格式
+    fn serialize(&self) -> [u8; INODE_RECORD_SIZE] {
+        let mut raw = self.raw;
+        put_u16(&mut raw, 0, self.mode);
+        put_u16(&mut raw, 2, self.uid as u16);
+        put_u16(&mut raw, 120, (self.uid >> 16) as u16);
+        put_u16(&mut raw, 24, self.gid as u16);
+        put_u16(&mut raw, 122, (self.gid >> 16) as u16);
+        put_u32(&mut raw, 4, self.size as u32);
+        if self.is_reg() {
+            put_u32(&mut raw, 108, (self.size >> 32) as u32);
+        }
+        put_u32(&mut raw, 8, self.atime);
+        put_u32(&mut raw, 12, self.ctime);
+        put_u32(&mut raw, 16, self.mtime);
+        put_u32(&mut raw, 20, self.dtime);
+        put_u16(&mut raw, 26, self.links);
+        put_u32(&mut raw, 28, self.blocks);
+        put_u32(&mut raw, 32, self.flags);
+        for (i, ptr) in self.block.iter().enumerate() {
+            put_u32(&mut raw, 40 + i * 4, *ptr);
+        }
+        put_u32(&mut raw, 104, self.file_acl);
+        raw
+    }
+    /// 文件类型
+    pub fn file_type(&self) -> u16 {
+        self.mode & S_IFMT
+    }
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == S_IFDIR
+    }
+    pub fn is_reg(&self) -> bool {
+        self.file_type() == S_IFREG
+    }
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == S_IFLNK
+    }
+    /// 块指针是否真的指向数据块。
+    /// 设备文件的块指针存的是设备号，短的符号链接直接把目标路径存在块指针里
+    pub fn has_data_blocks(&self, block_size: usize) -> bool {
+        match self.file_type() {
+            S_IFREG | S_IFDIR => true,
+            // 扩展属性块也算在 i_blocks 里，除去它之后还占用空间才是存在数据块里的符号链接
+            S_IFLNK => {
+                let acl_blocks = if self.file_acl != 0 { (block_size / 512) as u32 } else { 0 };
+                self.blocks > acl_blocks
+            }
+            _ => false,
+        }
+    }
+    /// 把短的符号链接的目标路径存到块指针里
+    pub fn set_fast_symlink(&mut self, target: &[u8]) {
+        let mut bytes = [0u8; FAST_SYMLINK_MAX];
+        bytes[..target.len()].copy_from_slice(target);
+        for (i, ptr) in self.block.iter_mut().enumerate() {
+            *ptr = get_u32(&bytes, i * 4);
+        }
+        self.size = target.len() as u64;
+    }
+    /// 读取存在块指针里的符号链接目标
+    fn fast_symlink(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FAST_SYMLINK_MAX);
+        for ptr in self.block.iter() {
+            bytes.extend_from_slice(&ptr.to_le_bytes());
+        }
+        bytes.truncate((self.size as usize).min(FAST_SYMLINK_MAX));
+        bytes
+    }
+    /// 设备文件的设备号，格式和 Kstat 中的 st_rdev 相同
+    pub fn rdev(&self) -> u64 {
+        // 主次设备号都小于 256 时用旧格式存在第一个块指针里，否则用新格式存在第二个里
+        let (major, minor) = if self.block[0] != 0 {
+            ((self.block[0] >> 8) & 0xff, self.block[0] & 0xff)
+        } else {
+            let dev = self.block[1];
+            ((dev & 0xfff00) >> 8, (dev & 0xff) | ((dev >> 12) & 0xfff00))
+        };
+        let (major, minor) = (major as u64, minor as u64);
+        ((major & 0xfffff000) << 32) | ((major & 0xfff) << 8) | ((minor & 0xffffff00) << 12) | (minor & 0xff)
+    }
+    /// 设置设备文件的设备号，rdev 的格式和 Kstat 中的 st_rdev 相同
+    pub fn set_rdev(&mut self, rdev: u64) {
+        let major = (((rdev >> 32) & 0xfffff000) | ((rdev >> 8) & 0xfff)) as u32;
+        let minor = (((rdev >> 12) & 0xffffff00) | (rdev & 0xff)) as u32;
+        if major < 256 && minor < 256 {
+            self.block[0] = major << 8 | minor;
+        } else {
+            self.block[1] = (minor & 0xff) | (major << 8) | ((minor & !0xff) << 12);
+        }
+    }
+    /// 修改了内容，更新修改时间
+    pub fn touch(&mut self) {
+        let now = get_time_sec() as u32;
+        self.mtime = now;
+        self.ctime = now;
+    }
+}
+
+impl Ext2Inner {
+    /// inode 在分区中的位置
+    fn inode_offset(&self, ino: u32) -> Result<usize, ErrorNo> {
+        if ino == 0 || ino > self.inodes_count {
+            return Err(ErrorNo::EIO);
+        }
+        let group = ((ino - 1) / self.inodes_per_group) as usize;
+        let index = ((ino - 1) % self.inodes_per_group) as usize;
+        Ok(self.groups[group].inode_table as usize * self.block_size + index * self.inode_size)
+    }
+    /// 读取 inode
+    pub fn read_inode(&self, ino: u32) -> Result<Inode, ErrorNo> {
+        let mut raw = [0u8; INODE_RECORD_SIZE];
+        self.read_bytes(self.inode_offset(ino)?, &mut raw)?;
+        Ok(Inode::parse(ino, raw))
+    }
+    /// 把 inode 写回磁盘
+    pub fn write_inode(&self, inode: &Inode) -> Result<(), ErrorNo> {
+        self.write_bytes(self.inode_offset(inode.ino)?, &inode.serialize())
+    }
+    /// 清空磁盘上的 inode，包括扩展字段。新分配 inode 时使用
+    pub fn clear_inode(&self, ino: u32) -> Result<(), ErrorNo> {
+        self.write_bytes(self.inode_offset(ino)?, &vec![0u8; self.inode_size])
+    }
+    /// 找到文件的第 index 个数据块，返回块号。
+    /// alloc 为 true 时会分配缺少的数据块和间接块，此时 inode 可能被修改，需要调用者写回；
+    /// 否则遇到没有分配的块(文件中的空洞)时返回 0
+    pub fn map_block(&mut self, inode: &mut Inode, index: usize, alloc: bool) -> Result<u32, ErrorNo> {
+        let per_block = self.block_size / 4;
+        // 找到 index 所在的那棵树：根在 inode.block 中的位置、树的深度，以及 index 在树中的序号
+        let (root, depth, mut index) = if index < DIRECT_BLOCKS {
+            (index, 0, 0)
+        } else {
+            let mut index = index - DIRECT_BLOCKS;
+            let mut span = per_block;
+            let mut depth = 1;
+            while index >= span {
+                index -= span;
+                span *= per_block;
+                depth += 1;
+                if depth > 3 {
+                    return Err(ErrorNo::EFBIG);
+                }
+            }
+            (DIRECT_BLOCKS + depth - 1, depth, index)
+        };
+        let goal = self.group_of_ino(inode.ino);
+        if inode.block[root] == 0 {
+            if !alloc {
+                return Ok(0);
+            }
+            inode.block[root] = self.alloc_block(goal)?;
+            inode.blocks += (self.block_size / 512) as u32;
+        }
+        let mut ptr = inode.block[root];
+        for level in (0..depth).rev() {
+            let span = per_block.pow(level as u32);
+            let slot = index / span;
+            index %= span;
+            let mut next = self.read_block_u32(ptr, slot)?;
+            if next == 0 {
+                if !alloc {
+                    return Ok(0);
+                }
+                next = self.alloc_block(goal)?;
+                inode.blocks += (self.block_size / 512) as u32;
+                self.write_block_u32(ptr, slot, next)?;
+            }
+            ptr = next;
+        }
+        Ok(ptr)
+    }
+    /// 从文件的 pos 处读数据到 buf，返回读到的长度。读到文件末尾时返回 0
+    pub fn read_data(&mut self, inode: &mut Inode, pos: usize, buf: &mut [u8]) -> Result<usize, ErrorNo> {
+        let size = inode.size as usize;
+        if pos >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - pos);
+        let mut done = 0;
+        while done < len {
+            let offset = (pos + done) % self.block_size;
+            let part = (self.block_size - offset).min(len - done);
+            let block = self.map_block(inode, (pos + done) / self.block_size, false)?;
+            if block == 0 {
+                // 文件中的空洞读出来是 0
+                buf[done..done + part].fill(0);
+            } else {
+                self.read_block(block, offset, &mut buf[done..done + part])?;
+            }
+            done += part;
+        }
+        Ok(len)
+    }
+    /// 把 buf 写到文件的 pos 处，返回写入的长度，并写回 inode。
+    /// 写到一半空间不足时返回已写入的长度
+    pub fn write_data(&mut self, inode: &mut Inode, pos: usize, buf: &[u8]) -> Result<usize, ErrorNo> {
+        let mut done = 0;
+        let mut result = Ok(());
+        while done < buf.len() {
+            let offset = (pos + done) % self.block_size;
+            let part = (self.block_size - offset).min(buf.len() - done);
+            match self.map_block(inode, (pos + done) / self.block_size, true) {
+                Ok(block) => self.write_block(block, offset, &buf[done..done + part])?,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+            done += part;
+        }
+        if done > 0 {
+            inode.size = inode.size.max((pos + done) as u64);
+            inode.touch();
+        }
+        self.write_inode(inode)?;
+        match result {
+            Err(e) if done == 0 => Err(e),
+            _ => Ok(done),
+        }
+    }
+    /// 把文件大小改为 size，释放多出来的数据块，并写回 inode
+    pub fn truncate(&mut self, inode: &mut Inode, size: usize) -> Result<(), ErrorNo> {
+        if size < inode.size as usize {
+            let keep = (size + self.block_size - 1) / self.block_size;
+            self.free_data_blocks(inode, keep)?;
+            // 最后一块中超出大小的部分清零，之后再变大时读到的是 0
+            if size % self.block_size != 0 {
+                let block = self.map_block(inode, size / self.block_size, false)?;
+                if block != 0 {
+                    let offset = size % self.block_size;
+                    self.write_block(block, offset, &vec![0u8; self.block_size - offset])?;
+                }
+            }
+        }
+        inode.size = size as u64;
+        inode.touch();
+        self.write_inode(inode)
+    }
+    /// 释放文件第 keep 个数据块之后的所有块，不写回 inode
+    pub fn free_data_blocks(&mut self, inode: &mut Inode, keep: usize) -> Result<(), ErrorNo> {
+        if !inode.has_data_blocks(self.block_size) {
+            return Ok(());
+        }
+        let per_block = self.block_size / 4;
+        let mut freed = 0;
+        for i in keep.min(DIRECT_BLOCKS)..DIRECT_BLOCKS {
+            if inode.block[i] != 0 {
+                self.free_block(inode.block[i])?;
+                inode.block[i] = 0;
+                freed += 1;
+            }
+        }
+        let mut base = DIRECT_BLOCKS;
+        let mut span = per_block;
+        for depth in 1..=3 {
+            let root = DIRECT_BLOCKS + depth - 1;
+            let keep_in_tree = keep.saturating_sub(base);
+            if inode.block[root] != 0 && keep_in_tree < span {
+                freed += self.free_tree(inode.block[root], depth, keep_in_tree)?;
+                if keep_in_tree == 0 {
+                    inode.block[root] = 0;
+                }
+            }
+            base += span;
+            span *= per_block;
+        }
+        inode.blocks -= freed * (self.block_size / 512) as u32;
+        Ok(())
+    }
+    /// 在以 block 为根、深度为 depth 的间接块树中，释放第 keep 个数据块之后的所有块。
+    /// keep 为 0 时连同 block 本身一起释放。返回释放的块数
+    fn free_tree(&mut self, block: u32, depth: usize, keep: usize) -> Result<u32, ErrorNo> {
+        let per_block = self.block_size / 4;
+        let child_span = per_block.pow(depth as u32 - 1);
+        let mut ptrs = vec![0u8; self.block_size];
+        self.read_block(block, 0, &mut ptrs)?;
+        let mut freed = 0;
+        let mut changed = false;
+        for slot in 0..per_block {
+            let child = get_u32(&ptrs, slot * 4);
+            let child_keep = keep.saturating_sub(slot * child_span);
+            if child == 0 || child_keep >= child_span {
+                continue;
+            }
+            if depth == 1 {
+                self.free_block(child)?;
+                freed += 1;
+            } else {
+                freed += self.free_tree(child, depth - 1, child_keep)?;
+            }
+            if child_keep == 0 {
+                put_u32(&mut ptrs, slot * 4, 0);
+                changed = true;
+            }
+        }
+        if keep == 0 {
+            self.free_block(block)?;
+            freed += 1;
+        } else if changed {
+            self.write_block(block, 0, &ptrs)?;
+        }
+        Ok(freed)
+    }
+    /// 读取符号链接的目标路径
+    pub fn read_symlink(&mut self, inode: &mut Inode) -> Result<String, ErrorNo> {
+        let target = if inode.has_data_blocks(self.block_size) {
+            let mut buf = vec![0u8; inode.size as usize];
+            let len = self.read_data(inode, 0, &mut buf)?;
+            buf.truncate(len);
+            buf
+        } else {
+            inode.fast_symlink()
+        };
+        String::from_utf8(target).map_err(|_| ErrorNo::EINVAL)
+    }
+    /// 修改了目录的内容。线性修改目录后 hash 索引不再正确，所以去掉索引标记
+    pub fn dir_modified(&self, dir: &mut Inode) -> Result<(), ErrorNo> {
+        dir.flags &= !EXT2_INDEX_FL;
+        dir.touch();
+        self.write_inode(dir)
+    }
+}
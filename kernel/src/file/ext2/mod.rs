@@ -0,0 +1,16 @@
+//! ext2 文件系统
+//!
+//! 直接在块缓存上读写 ext2 的磁盘结构，和 FAT 一样可以作为根文件系统，也可以挂载到某个目录上。
+//! 与 FAT 不同，ext2 本身有权限、所有者、硬链接、符号链接和设备文件，不需要内核里的链接表来模拟。
+//!
+//! 支持 mkfs.ext2 默认生成的格式(filetype / sparse_super / large_file 等特性)：
+//! - 目录按线性表读写，带 hash 索引的目录修改后会去掉索引标记，退化成线性目录
+//! - 不读写扩展属性，也不更新超级块在其他块组中的备份
+//! - 读文件时不更新访问时间
+
+mod dir;
+mod file;
+mod fs;
+mod inode;
+
+pub use fs::Ext2FileSystem;
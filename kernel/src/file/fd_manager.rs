@@ -6,13 +6,17 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
-use crate::constants::{FD_LIMIT_HARD, FD_LIMIT_ORIGIN};
+use crate::constants::FD_LIMIT_HARD;
 use crate::error::{OSError, OSResult};
 use crate::memory::FdAllocator;
+use crate::sysctl::Sysctl;
 
 use super::{File, OpenFlags};
 use super::stdio::{Stderr, Stdin, Stdout};
 
+/// 预设的文件描述符数量限制，即 RLIMIT_NOFILE 的初始值。可以通过 /proc/sys/fs/fd_limit_origin 修改，只影响之后创建的进程
+pub static FD_LIMIT_ORIGIN: Sysctl<usize> = Sysctl::new(256, 3, FD_LIMIT_HARD);
+
 /// 文件描述符管理，每个进程应该有一个
 /// 这个结构 Drop 时会自动释放文件的 Arc
 pub struct FdManager {
@@ -30,7 +34,7 @@ pub struct FdManager {
 impl FdManager {
     /// 新建 FdManager 并插入 Stdin / Stdout / Stderr
     pub fn new(umask: i32) -> Self {
-        let limit = FD_LIMIT_ORIGIN;
+        let limit = FD_LIMIT_ORIGIN.get();
         let mut fd_manager = Self {
             files: Vec::new(),
             fd_allocator: FdAllocator::new(limit),
@@ -124,6 +128,11 @@ impl FdManager {
         }
     }
 
+    /// 所有已打开的 fd，从小到大排列
+    pub fn get_all_fds(&self) -> Vec<usize> {
+        (0..self.files.len()).filter(|&fd| self.files[fd].is_some()).collect()
+    }
+
     /// 检查是否 vec 里所有 fd 都存在，如果存在则返回它们对应的文件，否则返回 None
     pub fn get_files_if_all_exists(&self, vec: &Vec<usize>) -> Option<Vec<Arc<dyn File>>> {
         let mut files: Vec<Arc<dyn File>> = Vec::with_capacity(vec.len());
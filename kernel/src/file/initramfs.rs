@@ -0,0 +1,232 @@
+//! initramfs
+//!
+//! 启动时把 newc 格式的 cpio 包解压到 tmpfs 中，作为根文件系统。
+//! cpio 包可以在编译时通过 INITRAMFS 环境变量嵌入内核，也可以由 bootloader 或 qemu 的 -initrd 加载，
+//! 此时从设备树的 /chosen 节点中找到它的位置。两者都有时先解压嵌入的包，再解压 -initrd 的包，
+//! 后者中的同名文件覆盖前者。都没有时不使用 initramfs，根文件系统仍在块设备上。
+//!
+//! 之后可以挂载块设备上的分区，用 busybox 的 switch_root 切换过去
+
+use super::{mount_root_fs, OpenFlags, StMode, TmpFs, UnixFs};
+use crate::constants::ROOT_DIR;
+use crate::drivers::Fdt;
+use crate::memory::{phys_to_virt, release_initrd};
+use crate::syscall::ErrorNo;
+use crate::timer::TimeSpec;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::arch::global_asm;
+
+// build.rs 根据 INITRAMFS 环境变量生成，其中用 .incbin 嵌入 cpio 包。没有设置时 cpio 包为空
+global_asm!(include_str!(concat!(env!("OUT_DIR"), "/initramfs.S")));
+
+extern "C" {
+    fn initramfs_start();
+    fn initramfs_end();
+}
+
+/// newc 格式的魔数，后者带有校验和
+const NEWC_MAGIC: &[u8] = b"070701";
+const NEWC_CRC_MAGIC: &[u8] = b"070702";
+/// 文件头的长度，包括魔数和 13 个 8 位十六进制数
+const HEADER_LEN: usize = 110;
+/// 最后一项的名字，表示包结束
+const TRAILER: &str = "TRAILER!!!";
+
+/// 文件头中的信息。没有用到的项(如 devmajor、check)不保存
+struct CpioHeader {
+    ino: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    mtime: u32,
+    filesize: usize,
+    rdevmajor: u32,
+    rdevminor: u32,
+    namesize: usize,
+}
+
+impl CpioHeader {
+    /// 解析 data 开头的文件头。格式不对时返回 None
+    fn parse(data: &[u8]) -> Option<Self> {
+        let header = data.get(..HEADER_LEN)?;
+        if &header[..6] != NEWC_MAGIC && &header[..6] != NEWC_CRC_MAGIC {
+            return None;
+        }
+        let field = |i: usize| -> Option<u32> {
+            let hex = core::str::from_utf8(&header[6 + i * 8..6 + (i + 1) * 8]).ok()?;
+            u32::from_str_radix(hex, 16).ok()
+        };
+        Some(Self {
+            ino: field(0)?,
+            mode: field(1)?,
+            uid: field(2)?,
+            gid: field(3)?,
+            nlink: field(4)?,
+            mtime: field(5)?,
+            filesize: field(6)? as usize,
+            rdevmajor: field(9)?,
+            rdevminor: field(10)?,
+            namesize: field(11)? as usize,
+        })
+    }
+}
+
+/// 解压 initramfs。如果解压出了文件，就把 tmpfs 挂载为根文件系统。
+///
+/// dtb 是启动时 SBI 传入的设备树的物理地址。
+/// **必须在切换到内核页表之前调用**，因为 -initrd 加载的位置在解压完之前不在内核页表的映射范围内
+pub fn load_initramfs(dtb: usize) {
+    let mut archives: Vec<(&'static str, &'static [u8])> = Vec::new();
+    let embedded = unsafe {
+        let start = initramfs_start as usize;
+        core::slice::from_raw_parts(start as *const u8, initramfs_end as usize - start)
+    };
+    if !embedded.is_empty() {
+        archives.push(("embedded", embedded));
+    }
+    if let Some(range) = Fdt::from_paddr(dtb).and_then(|fdt| fdt.initrd_range()) {
+        let initrd = unsafe { core::slice::from_raw_parts(phys_to_virt(range.start) as *const u8, range.len()) };
+        archives.push(("initrd", initrd));
+    }
+    if archives.is_empty() {
+        return;
+    }
+    let fs = Arc::new(TmpFs::new());
+    let mut count = 0;
+    for (name, data) in archives {
+        match unpack(&fs, data) {
+            Ok(n) => {
+                info!("initramfs: unpacked {} entries from {} archive", n, name);
+                count += n;
+            }
+            Err(n) => warn!("initramfs: malformed {} archive, stopped after {} entries", name, n),
+        }
+    }
+    // 解压时分配的页帧不会覆盖 initrd，解压完之后它所在的页就可以使用了
+    release_initrd();
+    if count > 0 {
+        mount_root_fs("rootfs", fs, None);
+    }
+}
+
+/// 把 cpio 包解压到 fs 中，返回解压出的项数。包的格式错误时返回 Err，其中是已经解压出的项数
+fn unpack(fs: &Arc<TmpFs>, data: &[u8]) -> Result<usize, usize> {
+    // 有多个硬链接的文件，ino 到第一次出现时的路径的映射
+    let mut hard_links: BTreeMap<u32, (String, String)> = BTreeMap::new();
+    // 目录的修改时间要在所有文件都创建完之后再设置，否则会被其中文件的创建覆盖
+    let mut dir_times: Vec<(String, u32)> = Vec::new();
+    let mut count = 0;
+    let mut pos = 0;
+    loop {
+        let header = data.get(pos..).and_then(CpioHeader::parse).ok_or(count)?;
+        let name_start = pos + HEADER_LEN;
+        let name = data
+            .get(name_start..name_start + header.namesize)
+            .and_then(|name| core::str::from_utf8(name.strip_suffix(&[0])?).ok())
+            .ok_or(count)?;
+        let data_start = align4(name_start + header.namesize);
+        let content = data.get(data_start..data_start + header.filesize).ok_or(count)?;
+        pos = align4(data_start + header.filesize);
+        if name == TRAILER {
+            break;
+        }
+        // 包中的路径可能是 "./bin/sh"、"/bin/sh" 或 "bin/sh"
+        let path = name.trim_start_matches("./").trim_start_matches('/').trim_end_matches('/');
+        if path.is_empty() || path == "." {
+            continue;
+        }
+        let (dir, file) = match path.rfind('/') {
+            Some(split) => ([ROOT_DIR, &path[..split], "/"].concat(), &path[split + 1..]),
+            None => (String::from(ROOT_DIR), path),
+        };
+        match unpack_entry(fs, &header, &dir, file, content, &mut hard_links) {
+            Ok(()) => count += 1,
+            Err(errno) => warn!("initramfs: failed to unpack {}: {:?}", name, errno),
+        }
+        if header.mode & StMode::S_IFMT.bits() == StMode::S_IFDIR.bits() {
+            dir_times.push((dir + file, header.mtime));
+        }
+    }
+    for (dir, mtime) in dir_times.into_iter().rev() {
+        let dir = dir + "/";
+        if let Some(dir) = fs.clone().open(&dir, "", &dir, OpenFlags::DIR) {
+            let time = TimeSpec {
+                tv_sec: mtime as usize,
+                tv_nsec: 0,
+            };
+            dir.set_time(&time, &time);
+        }
+    }
+    Ok(count)
+}
+
+/// 在 fs 中创建包中的一项
+fn unpack_entry(
+    fs: &Arc<TmpFs>,
+    header: &CpioHeader,
+    dir: &str,
+    name: &str,
+    content: &[u8],
+    hard_links: &mut BTreeMap<u32, (String, String)>,
+) -> Result<(), ErrorNo> {
+    let file_type = header.mode & StMode::S_IFMT.bits();
+    let perm = header.mode & !StMode::S_IFMT.bits();
+    // 后解压的包覆盖先解压的包。目录直接沿用，只修改属性
+    match fs.exists(dir, name) {
+        Some(true) if file_type == StMode::S_IFDIR.bits() => {}
+        Some(_) => fs.remove(dir, name)?,
+        // 符号链接指向不存在的文件时，exists 也返回 None
+        None => {
+            let _ = fs.remove(dir, name);
+        }
+    }
+    if file_type == StMode::S_IFLNK.bits() {
+        let target = core::str::from_utf8(content).map_err(|_| ErrorNo::EINVAL)?;
+        fs.symlink(target, dir, name)?;
+        return fs.chown(dir, name, Some(header.uid), Some(header.gid), false);
+    }
+    if file_type == StMode::S_IFDIR.bits() {
+        if fs.exists(dir, name).is_none() {
+            fs.mkdir(dir, name)?;
+        }
+    } else if file_type == StMode::S_IFREG.bits() && header.nlink > 1 && hard_links.contains_key(&header.ino) {
+        // 同一个文件的其他硬链接已经解压过了
+        let (old_dir, old_name) = &hard_links[&header.ino];
+        fs.link(old_dir, old_name, dir, name)?;
+    } else {
+        let rdev = make_dev(header.rdevmajor, header.rdevminor);
+        fs.mknod(dir, name, header.mode, rdev)?;
+        if file_type == StMode::S_IFREG.bits() && header.nlink > 1 {
+            hard_links.insert(header.ino, (String::from(dir), String::from(name)));
+        }
+    }
+    fs.chmod(dir, name, perm)?;
+    fs.chown(dir, name, Some(header.uid), Some(header.gid), false)?;
+    if file_type == StMode::S_IFDIR.bits() {
+        return Ok(());
+    }
+    // 硬链接中只有一项带有文件内容，写入任何一项都会写到同一个文件中
+    let flags = if content.is_empty() { OpenFlags::RDONLY } else { OpenFlags::RDWR };
+    if let Some(file) = fs.clone().open(dir, name, dir, flags) {
+        if !content.is_empty() && file.write(content) != Some(content.len()) {
+            return Err(ErrorNo::ENOSPC);
+        }
+        let time = TimeSpec {
+            tv_sec: header.mtime as usize,
+            tv_nsec: 0,
+        };
+        file.set_time(&time, &time);
+    }
+    Ok(())
+}
+
+/// 把主设备号和次设备号合成 st_rdev 中的设备号，编码方式和 musl 的 makedev 相同
+fn make_dev(major: u32, minor: u32) -> u64 {
+    let (major, minor) = (major as u64, minor as u64);
+    ((major & 0xfffff000) << 32) | ((major & 0xfff) << 8) | ((minor & 0xffffff00) << 12) | (minor & 0xff)
+}
+
+fn align4(pos: usize) -> usize {
+    (pos + 3) & !3
+}
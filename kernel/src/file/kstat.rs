@@ -8,6 +8,7 @@ use bitflags::*;
 
 /// 文件信息类
 #[repr(C)]
+#[derive(Default)]
 pub struct Kstat {
     /// 设备
     pub st_dev: u64,
@@ -54,6 +55,16 @@ bitflags! {
         const S_IFDIR = 1 << 14;
         /// 是字符设备
         const S_IFCHR = 1 << 13;
+        /// 是块设备
+        const S_IFBLK = (1 << 14) | (1 << 13);
+        /// 是符号链接
+        const S_IFLNK = (1 << 15) | (1 << 13);
+        /// 是管道(FIFO)
+        const S_IFIFO = 1 << 12;
+        /// 是 socket
+        const S_IFSOCK = (1 << 15) | (1 << 14);
+        /// 文件类型部分的掩码
+        const S_IFMT = (1 << 15) | (1 << 14) | (1 << 13) | (1 << 12);
         /// 是否设置 uid/gid/sticky
         //const S_ISUID = 1 << 14;
         //const S_ISGID = 1 << 13;
@@ -82,3 +93,24 @@ bitflags! {
 pub fn normal_file_mode(file_type: StMode) -> StMode {
     file_type | StMode::S_IWUSR | StMode::S_IWUSR | StMode::S_IWGRP | StMode::S_IRGRP
 }
+
+/// 填写不在文件系统中的文件(如 /proc 下的信息文件、设备文件)的属性。
+/// 这些文件的 inode、大小和时间都为 0，类型为 file_type，设备号为 rdev
+pub fn virt_file_stat(stat: *mut Kstat, file_type: StMode, rdev: u64) {
+    unsafe {
+        (*stat).st_dev = 0;
+        (*stat).st_ino = 0;
+        (*stat).st_nlink = 1;
+        (*stat).st_mode = normal_file_mode(file_type).bits();
+        (*stat).st_rdev = rdev;
+        (*stat).st_size = 0;
+        (*stat).st_uid = 0;
+        (*stat).st_gid = 0;
+        (*stat).st_atime_sec = 0;
+        (*stat).st_atime_nsec = 0;
+        (*stat).st_mtime_sec = 0;
+        (*stat).st_mtime_nsec = 0;
+        (*stat).st_ctime_sec = 0;
+        (*stat).st_ctime_nsec = 0;
+    }
+}
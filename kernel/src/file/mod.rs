@@ -3,17 +3,28 @@
 mod backend;
 mod device;
 mod epoll;
+mod eventfd;
+mod ext2;
 mod fd_manager;
 mod fs_stat;
+mod initramfs;
 mod kstat;
+mod overlay;
+mod p9;
 mod pipe;
 mod poll_events;
+mod procfs;
+mod signalfd;
 mod stdio;
+mod timerfd;
+mod tmpfs;
+mod unix_fs;
 mod vfs;
+mod wait_queue;
 pub mod socket;
 
-use crate::timer::TimeSpec;
-use alloc::vec::Vec;
+use crate::{syscall::ErrorNo, timer::TimeSpec};
+use alloc::{string::String, vec::Vec};
 use core::any::Any;
 
 pub use fatfs::SeekFrom;
@@ -46,6 +57,16 @@ pub trait File: Send + Sync + AsAny {
         let _ = self.seek(SeekFrom::Current(old_pos as i64)).unwrap(); // 不管有没有写入，都要返回原来的位置
         write_len
     }
+    /// 取出并清除"上次读写失败是因为设备出错"的标记。
+    /// read / write 返回 None 时，syscall 用它判断是否应该返回 EIO
+    fn take_io_error(&self) -> bool {
+        false
+    }
+    /// 取出并清除上次读写失败的具体原因，如参数不合法时的 EINVAL。
+    /// read / write 返回 None 时，syscall 优先返回这里记录的错误。没有记录时按 read_write_error 中的规则判断
+    fn take_rw_error(&self) -> Option<ErrorNo> {
+        None
+    }
     /// 已准备好读。对于 pipe 来说，这意味着读端的buffer内有值
     fn ready_to_read(&self) -> bool {
         true
@@ -80,9 +101,36 @@ pub trait File: Send + Sync + AsAny {
         }
         ret
     } 
+    /// 登记线程 tid，在文件状态可能变化时唤醒它。select / poll / epoll 等待文件时使用。
+    ///
+    /// 返回 false 表示文件不会主动唤醒线程，此时等待的线程只能反复检查文件状态
+    fn register_waiter(&self, _tid: usize) -> bool {
+        false
+    }
+    /// 同 register_waiter，但以独占方式登记：文件状态变化时，独占等待的线程只唤醒一个。用于 EPOLLEXCLUSIVE。
+    ///
+    /// 不区分独占的文件按普通方式登记
+    fn register_exclusive_waiter(&self, tid: usize) -> bool {
+        self.register_waiter(tid)
+    }
+    /// 取消 register_waiter / register_exclusive_waiter 的登记
+    fn unregister_waiter(&self, _tid: usize) {
+    }
+    /// 文件的等待队列被唤醒的总次数。epoll 的边缘触发用它判断两次检查之间文件状态是否变化过。
+    ///
+    /// 不会主动唤醒线程的文件返回 None，此时只能比较两次检查时的状态
+    fn wake_count(&self) -> Option<usize> {
+        None
+    }
     /// 清空文件
     fn clear(&self) {
     }
+    /// 把文件大小改为 len，返回是否修改成功。
+    ///
+    /// 普通文件系统中的文件和 /dev/shm 下的共享内存文件支持，管道、socket 等不支持
+    fn truncate(&self, _len: usize) -> bool {
+        false
+    }
     /// 切换当前指针，返回切换后指针到文件开头的距离
     /// 如果文件本身不支持 seek(如pipe，是FIFO"设备") 则返回 None
     fn seek(&self, _seekfrom: SeekFrom) -> Option<usize> {
@@ -94,6 +142,12 @@ pub trait File: Send + Sync + AsAny {
     fn get_dir(&self) -> Option<&str> {
         None
     }
+    /// 文件在整个目录树中的路径，如 "/bin/busybox"。/proc/<pid>/fd 下的链接指向这个路径。
+    /// - 目录和文件系统中的文件在打开时记录了路径
+    /// - pipe、socket 等没有路径的文件返回 None
+    fn get_path(&self) -> Option<String> {
+        self.get_dir().map(user_path)
+    }
     /// 读取全部数据。
     /// 不是所有类型都实现了 read_all，目前只有文件系统中的文件是可知明确"大小"的，所以可以读"all"。
     /// 对于其他类型来说，这个函数没有实现。
@@ -127,20 +181,6 @@ pub trait File: Send + Sync + AsAny {
     fn get_status(&self) -> OpenFlags {
         OpenFlags::empty()
     }
-    /// 发送消息，当且仅当这个文件是 socket 时可用
-    fn sendto(&self, _buf: &[u8], _flags: i32, _dest_addr: usize) -> Option<usize> {
-        None
-    }
-    /// 收取消息，当且仅当这个文件是 socket 时可用
-    fn recvfrom(
-        &self,
-        _buf: &mut [u8],
-        _flags: i32,
-        _src_addr: usize,
-        _src_len: &mut u32,
-    ) -> Option<usize> {
-        None
-    }
     /// 如果这个文件对应的是一个 epoll，则获取 epoll 文件。否则，返回 None
     fn get_epoll_fd(&self) -> Option<EpollFile> {
         None
@@ -154,23 +194,46 @@ impl<T: Any> AsAny for T {
     fn as_any(&self) -> &dyn Any { self }
 }
 
+/// 把内核中 "./dir/name" 形式的路径转换成用户看到的 "/dir/name"，去掉目录结尾的 '/'
+pub fn user_path(path: &str) -> String {
+    let path = path.trim_start_matches('.').trim_end_matches('/');
+    if path.is_empty() {
+        String::from("/")
+    } else {
+        String::from(path)
+    }
+}
+
 pub use device::{
+    absolute_path,
     check_dir_exists,
     check_file_exists,
     fs_init,
-    get_dir_entry_iter,
+    get_dir_entries,
+    is_root_dir,
     list_files_at_root,
     //load_testcases,
     load_next_testcase,
+    make_node,
+    make_symlink,
     mkdir,
-    mount_fat_fs,
+    mount_9p_fs,
+    mount_fs,
+    mount_proc_fs,
+    mount_root_fs,
+    mount_root_overlay,
+    mount_tmp_fs,
+    move_mount,
     open_file,
     origin_fs_stat,
     show_testcase_result,
     try_add_link,
     try_remove_link,
     read_link,
-    umount_fat_fs,
+    read_symlink,
+    set_file_mode,
+    set_file_owner,
+    umount_fs,
     rename_or_move,
     add_sys_info,
 };
@@ -178,15 +241,32 @@ pub use device::{
 pub use backend::{BackEndFile, SyncPolicy};
 pub use device::{FileDisc, OpenFlags};
 pub use epoll::{EpollFile, EpollEvent, EpollEventType, EpollCtl};
-pub use fd_manager::FdManager;
+pub use eventfd::{EventFd, EFD_SEMAPHORE};
+pub use ext2::Ext2FileSystem;
+pub use fd_manager::{FdManager, FD_LIMIT_ORIGIN};
 pub use fs_stat::FsStat;
-pub use kstat::normal_file_mode;
+pub use initramfs::load_initramfs;
+pub use kstat::{normal_file_mode, virt_file_stat};
 pub use kstat::{Kstat, StMode};
+pub use overlay::OverlayFs;
+pub use p9::P9FileSystem;
 pub use pipe::{Pipe, RingBuffer};
+use pipe::PIPE_SIZE_LIMIT;
 pub use poll_events::PollEvents;
-pub use socket::Socket;
+pub use procfs::{add_proc_bin_file, add_proc_file};
+use procfs::{add_system_proc_files, PROC_FS};
+pub use signalfd::SignalFd;
+pub use timerfd::{wake_expired_timers, ITimerSpec, TimerFd, CLOCK_BOOTTIME, CLOCK_MONOTONIC, CLOCK_REALTIME};
+pub use tmpfs::TmpFs;
+use tmpfs::TMP_SIZE_LIMIT;
+pub use unix_fs::UnixFs;
+pub use wait_queue::{wait_for, WaitQueue};
 pub use vfs::{
     BufferFile,
+    RtcFile,
+    ShmFile,
+    VirtFile,
+    add_block_device_files,
     get_virt_file_if_possible,
     get_virt_dir_if_possible,
     check_virt_dir_exists,
@@ -0,0 +1,498 @@
+//! overlayfs 文件系统
+//!
+//! 在只读的下层文件系统上叠加一个可写的 tmpfs 上层。查找时先看上层，上层没有时再看下层；
+//! 修改下层的文件前先把它复制到上层(copy-up)，之后只修改上层。
+//!
+//! - 删除下层中存在的文件时，在上层留一个设备号为 0 的字符设备作为 whiteout，遮住下层的同名文件
+//! - 上层的目录中有 `.wh..wh..opq` 时是不透明目录，下层的同名目录中的内容不再可见。
+//!   tmpfs 没有扩展属性，所以用这个文件代替 Linux 中的 trusted.overlay.opaque
+//! - 和 Linux 默认的行为一样，不支持重命名下层中存在的目录，返回 EXDEV，由用户程序复制后删除
+
+use super::{File, FsStat, Kstat, OpenFlags, StMode, TmpFs, UnixFs};
+use crate::constants::ROOT_DIR;
+use crate::syscall::ErrorNo;
+use crate::timer::TimeSpec;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+/// statfs 中 overlayfs 的类型
+const OVERLAYFS_MAGIC: i64 = 0x794c_7630;
+/// 标记不透明目录的文件名
+const OPAQUE_MARKER: &str = ".wh..wh..opq";
+/// 查找路径时最多跟随的符号链接数，超过时返回 ELOOP
+const MAX_SYMLINK_FOLLOWS: usize = 40;
+
+/// 文件所在的层
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Layer {
+    Upper,
+    Lower,
+}
+
+/// 查找路径的结果。dir 中不含符号链接，name 可能不存在
+struct Resolved {
+    /// 所在目录，以 "./" 开头，以 '/' 结尾
+    dir: String,
+    /// 文件名，为空时表示根目录
+    name: String,
+    /// 下层中 dir 目录的内容是否可见
+    lower_ok: bool,
+}
+
+impl Resolved {
+    /// 把 name 当作目录时，它的路径
+    fn as_dir(&self) -> String {
+        if self.name.is_empty() {
+            self.dir.clone()
+        } else {
+            self.dir.clone() + &self.name + "/"
+        }
+    }
+}
+
+/// 叠加在只读文件系统上的可写文件系统
+pub struct OverlayFs {
+    upper: Arc<TmpFs>,
+    /// 下层文件系统。打开的下层文件持有它，所以 overlayfs 释放后它可能还在使用
+    lower: Arc<dyn UnixFs>,
+}
+
+impl OverlayFs {
+    /// 在 lower 上叠加一个空的 tmpfs
+    pub fn new(lower: Arc<dyn UnixFs>) -> Self {
+        Self {
+            upper: Arc::new(TmpFs::new()),
+            lower: lower,
+        }
+    }
+    /// 上层中的目录项是否是 whiteout
+    fn is_whiteout(&self, dir: &str, name: &str) -> bool {
+        matches!(self.upper.node_type(dir, name), Some((mode, 0)) if mode == StMode::S_IFCHR)
+    }
+    /// 上层中的目录 dir 是否是不透明目录
+    fn is_opaque(&self, dir: &str) -> bool {
+        self.upper.node_type(dir, OPAQUE_MARKER).is_some()
+    }
+    /// 下层中 dir 目录下的 name 的文件类型，不跟随符号链接
+    fn lower_type(&self, dir: &str, name: &str) -> Option<StMode> {
+        if self.lower.read_link(dir, name).is_ok() {
+            return Some(StMode::S_IFLNK);
+        }
+        self.lower.exists(dir, name).map(|is_dir| if is_dir { StMode::S_IFDIR } else { StMode::S_IFREG })
+    }
+    /// 合并后 dir 目录下的 name 所在的层和文件类型，不跟随符号链接。lower_ok 表示下层中 dir 的内容是否可见
+    fn node(&self, dir: &str, name: &str, lower_ok: bool) -> Option<(Layer, StMode)> {
+        match self.upper.node_type(dir, name) {
+            Some((mode, 0)) if mode == StMode::S_IFCHR => None,
+            Some((mode, _)) => Some((Layer::Upper, mode)),
+            None if lower_ok => self.lower_type(dir, name).map(|mode| (Layer::Lower, mode)),
+            None => None,
+        }
+    }
+    /// 查找结果对应的文件
+    fn node_at(&self, r: &Resolved) -> Option<(Layer, StMode)> {
+        if r.name.is_empty() {
+            return Some((Layer::Upper, StMode::S_IFDIR));
+        }
+        self.node(&r.dir, &r.name, r.lower_ok)
+    }
+    /// 下层中目录 r 的内容是否可见
+    fn lower_dir_visible(&self, r: &Resolved) -> bool {
+        if r.name.is_empty() {
+            return !self.is_opaque(ROOT_DIR);
+        }
+        r.lower_ok && self.lower_type(&r.dir, &r.name) == Some(StMode::S_IFDIR) && !self.is_opaque(&r.as_dir())
+    }
+    /// 读取符号链接的目标
+    fn link_target(&self, dir: &str, name: &str, layer: Layer) -> Result<String, ErrorNo> {
+        match layer {
+            Layer::Upper => self.upper.read_link(dir, name),
+            Layer::Lower => self.lower.read_link(dir, name),
+        }
+    }
+    /// 在合并后的目录树中查找 path。路径中间的符号链接总是跟随，最后一项只在 follow 为 true 时跟随。
+    /// 最后一项可以不存在
+    fn resolve(&self, path: &str, follow: bool) -> Result<Resolved, ErrorNo> {
+        // 还没有处理的路径，倒序存放，末尾是下一个要处理的名字
+        let mut pending: Vec<String> = path.split('/').rev().map(String::from).collect();
+        // 从根目录开始经过的每一层目录，以及下层中它的内容是否可见
+        let mut walked: Vec<(String, bool)> = Vec::new();
+        let mut follows = 0;
+        while let Some(name) = pending.pop() {
+            match name.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    walked.pop();
+                    continue;
+                }
+                _ => {}
+            }
+            let dir = walked_path(&walked);
+            let lower_ok = walked.last().map_or(!self.is_opaque(ROOT_DIR), |(_, ok)| *ok);
+            let is_last = pending.iter().all(|name| name.is_empty() || name == ".");
+            match self.node(&dir, &name, lower_ok) {
+                Some((layer, mode)) if mode == StMode::S_IFLNK && (follow || !is_last) => {
+                    follows += 1;
+                    if follows > MAX_SYMLINK_FOLLOWS {
+                        return Err(ErrorNo::ELOOP);
+                    }
+                    let target = self.link_target(&dir, &name, layer)?;
+                    if target.starts_with('/') {
+                        walked.clear();
+                    }
+                    pending.extend(target.split('/').rev().map(String::from));
+                }
+                Some((layer, mode)) if mode == StMode::S_IFDIR => {
+                    let child = dir + &name + "/";
+                    let child_ok = lower_ok && (layer == Layer::Lower || !self.is_opaque(&child));
+                    walked.push((name, child_ok));
+                }
+                _ if is_last => {
+                    return Ok(Resolved {
+                        dir: dir,
+                        name: name,
+                        lower_ok: lower_ok,
+                    })
+                }
+                Some(_) => return Err(ErrorNo::ENOTDIR),
+                None => return Err(ErrorNo::ENOENT),
+            }
+        }
+        // 路径的最后一项是目录
+        let name = walked.pop().map(|(name, _)| name).unwrap_or_default();
+        Ok(Resolved {
+            dir: walked_path(&walked),
+            name: name,
+            lower_ok: walked.last().map_or(!self.is_opaque(ROOT_DIR), |(_, ok)| *ok),
+        })
+    }
+    /// 下层中文件的属性
+    fn lower_stat(&self, dir: &str, name: &str, is_dir: bool) -> Option<Kstat> {
+        let flags = if is_dir { OpenFlags::DIR } else { OpenFlags::RDONLY };
+        let file = self.lower.clone().open(dir, name, dir, flags | OpenFlags::NOFOLLOW)?;
+        let mut stat = Kstat::default();
+        if file.get_stat(&mut stat) {
+            Some(stat)
+        } else {
+            None
+        }
+    }
+    /// 把下层文件的权限、所有者和时间复制到上层
+    fn copy_attr(&self, dir: &str, name: &str, stat: &Kstat, is_dir: bool) -> Result<(), ErrorNo> {
+        self.upper.chmod(dir, name, stat.st_mode)?;
+        self.upper.chown(dir, name, Some(stat.st_uid), Some(stat.st_gid), false)?;
+        let flags = if is_dir { OpenFlags::DIR } else { OpenFlags::RDONLY };
+        if let Some(file) = self.upper.clone().open(dir, name, dir, flags) {
+            let atime = TimeSpec {
+                tv_sec: stat.st_atime_sec as usize,
+                tv_nsec: stat.st_atime_nsec as usize,
+            };
+            let mtime = TimeSpec {
+                tv_sec: stat.st_mtime_sec as usize,
+                tv_nsec: stat.st_mtime_nsec as usize,
+            };
+            file.set_time(&atime, &mtime);
+        }
+        Ok(())
+    }
+    /// 保证目录 dir 在上层中存在。dir 中不能有符号链接，每一级缺少的目录都从下层复制
+    fn copy_up_dir(&self, dir: &str) -> Result<(), ErrorNo> {
+        let mut parent = String::from(ROOT_DIR);
+        for name in dir[ROOT_DIR.len()..].split('/').filter(|name| !name.is_empty()) {
+            match self.upper.node_type(&parent, name) {
+                Some((mode, _)) if mode == StMode::S_IFDIR => {}
+                Some(_) => return Err(ErrorNo::ENOTDIR),
+                None => {
+                    self.upper.mkdir(&parent, name)?;
+                    if let Some(stat) = self.lower_stat(&parent, name, true) {
+                        self.copy_attr(&parent, name, &stat, true)?;
+                    }
+                }
+            }
+            parent = parent + name + "/";
+        }
+        Ok(())
+    }
+    /// 保证 r 在上层中存在。只在下层中时把它复制到上层
+    fn copy_up(&self, r: &Resolved) -> Result<(), ErrorNo> {
+        let mode = match self.node_at(r) {
+            Some((Layer::Upper, _)) => return Ok(()),
+            Some((Layer::Lower, mode)) => mode,
+            None => return Err(ErrorNo::ENOENT),
+        };
+        self.copy_up_dir(&r.dir)?;
+        let (dir, name) = (r.dir.as_str(), r.name.as_str());
+        if mode == StMode::S_IFLNK {
+            let target = self.lower.read_link(dir, name)?;
+            return self.upper.symlink(&target, dir, name);
+        }
+        let is_dir = mode == StMode::S_IFDIR;
+        let stat = self.lower_stat(dir, name, is_dir).ok_or(ErrorNo::ENOENT)?;
+        let file_type = stat.st_mode & StMode::S_IFMT.bits();
+        if is_dir {
+            self.upper.mkdir(dir, name)?;
+        } else if file_type == StMode::S_IFREG.bits() || file_type == 0 {
+            self.upper.mknod(dir, name, StMode::S_IFREG.bits() | (stat.st_mode & 0o7777), 0)?;
+            let src = self.lower.clone().open(dir, name, dir, OpenFlags::RDONLY).ok_or(ErrorNo::ENOENT)?;
+            let dst = self.upper.clone().open(dir, name, dir, OpenFlags::RDWR).ok_or(ErrorNo::ENOENT)?;
+            let data = unsafe { src.read_all() };
+            if dst.write(&data) != Some(data.len()) {
+                let _ = self.upper.remove(dir, name);
+                return Err(ErrorNo::ENOSPC);
+            }
+        } else {
+            self.upper.mknod(dir, name, stat.st_mode, stat.st_rdev)?;
+        }
+        self.copy_attr(dir, name, &stat, is_dir)
+    }
+    /// 准备在 r 处新建文件：它的目录要在上层中存在，上层中同名的 whiteout 要删除。
+    /// 返回是否删除了 whiteout，此时新建的目录需要标记为不透明
+    fn prepare_create(&self, r: &Resolved) -> Result<bool, ErrorNo> {
+        if r.name.is_empty() || self.node_at(r).is_some() {
+            return Err(ErrorNo::EEXIST);
+        }
+        self.copy_up_dir(&r.dir)?;
+        if self.is_whiteout(&r.dir, &r.name) {
+            self.upper.remove(&r.dir, &r.name)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+    /// 把上层中的目录 dir 标记为不透明
+    fn set_opaque(&self, dir: &str) -> Result<(), ErrorNo> {
+        self.upper.mknod(dir, OPAQUE_MARKER, StMode::S_IFREG.bits(), 0)
+    }
+    /// 删除 r 之后，如果下层中还有同名文件，在上层中放一个 whiteout 遮住它
+    fn cover_lower(&self, r: &Resolved) -> Result<(), ErrorNo> {
+        if r.lower_ok && self.lower_type(&r.dir, &r.name).is_some() {
+            self.copy_up_dir(&r.dir)?;
+            self.upper.mknod(&r.dir, &r.name, StMode::S_IFCHR.bits(), 0)?;
+        }
+        Ok(())
+    }
+    /// 删除上层中的目录 dir 里的 whiteout 和不透明标记。调用前需保证合并后的目录为空
+    fn clear_upper_dir(&self, dir: &str) -> Result<(), ErrorNo> {
+        for (name, _) in self.upper.list(dir)? {
+            if name != "." && name != ".." {
+                self.upper.remove(dir, &name)?;
+            }
+        }
+        Ok(())
+    }
+    /// 合并后的目录 r 中的目录项，不含 "." 和 ".."
+    fn merged_entries(&self, r: &Resolved) -> Result<BTreeMap<String, StMode>, ErrorNo> {
+        let dir = r.as_dir();
+        let mut entries = BTreeMap::new();
+        if self.lower_dir_visible(r) {
+            entries.extend(self.lower.list(&dir)?);
+        }
+        if let Ok(upper) = self.upper.list(&dir) {
+            for (name, mode) in upper {
+                if name == OPAQUE_MARKER {
+                    continue;
+                }
+                if self.is_whiteout(&dir, &name) {
+                    entries.remove(&name);
+                } else {
+                    entries.insert(name, mode);
+                }
+            }
+        }
+        entries.remove(".");
+        entries.remove("..");
+        Ok(entries)
+    }
+    /// 删除 r。它可以是文件，也可以是合并后为空的目录
+    fn remove_at(&self, r: &Resolved) -> Result<(), ErrorNo> {
+        let (layer, mode) = self.node_at(r).ok_or(ErrorNo::ENOENT)?;
+        if mode == StMode::S_IFDIR && !self.merged_entries(r)?.is_empty() {
+            return Err(ErrorNo::ENOTEMPTY);
+        }
+        if layer == Layer::Upper {
+            if mode == StMode::S_IFDIR {
+                self.clear_upper_dir(&r.as_dir())?;
+            }
+            self.upper.remove(&r.dir, &r.name)?;
+        }
+        self.cover_lower(r)
+    }
+}
+
+/// 经过的目录组成的路径
+fn walked_path(walked: &[(String, bool)]) -> String {
+    let mut path = String::from(ROOT_DIR);
+    for (name, _) in walked {
+        path += name;
+        path.push('/');
+    }
+    path
+}
+
+impl UnixFs for OverlayFs {
+    /// 打开文件。以可写方式打开下层中的文件时，先把它复制到上层
+    fn open(self: Arc<Self>, dir: &str, name: &str, full_dir: &str, flags: OpenFlags) -> Option<Arc<dyn File>> {
+        let r = self.resolve(&(String::from(dir) + name), !flags.contains(OpenFlags::NOFOLLOW)).ok()?;
+        let (layer, mode) = match self.node_at(&r) {
+            Some(node) => node,
+            None if flags.contains(OpenFlags::CREATE) => {
+                self.prepare_create(&r).ok()?;
+                return self.upper.clone().open(&r.dir, &r.name, full_dir, flags);
+            }
+            None => return None,
+        };
+        if flags.contains(OpenFlags::CREATE) && flags.contains(OpenFlags::EXCLUSIVE) {
+            return None;
+        }
+        if mode == StMode::S_IFDIR {
+            // 和其他文件系统一样，目录只能以 DIR 方式或者用路径本身打开
+            if !(flags.contains(OpenFlags::DIR) || flags.contains(OpenFlags::DSYNC) || name.is_empty()) {
+                return None;
+            }
+            let full_path = String::from(full_dir) + name;
+            return match layer {
+                Layer::Upper => self.upper.clone().open(&r.as_dir(), "", &full_path, flags),
+                Layer::Lower => self.lower.clone().open(&r.as_dir(), "", &full_path, flags),
+            };
+        }
+        if layer == Layer::Lower && flags.writable() {
+            self.copy_up(&r).ok()?;
+        }
+        match self.node_at(&r)?.0 {
+            Layer::Upper => self.upper.clone().open(&r.dir, &r.name, full_dir, flags),
+            Layer::Lower => self.lower.clone().open(&r.dir, &r.name, full_dir, flags),
+        }
+    }
+    fn exists(&self, dir: &str, name: &str) -> Option<bool> {
+        let r = self.resolve(&(String::from(dir) + name), true).ok()?;
+        self.node_at(&r).map(|(_, mode)| mode == StMode::S_IFDIR)
+    }
+    fn mkdir(&self, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let r = self.resolve(&(String::from(dir) + name), false)?;
+        let covered = self.prepare_create(&r)?;
+        self.upper.mkdir(&r.dir, &r.name)?;
+        // 下层中同名的目录已经被删除过，它的内容不能再出现在新目录里
+        if covered {
+            self.set_opaque(&r.as_dir())?;
+        }
+        Ok(())
+    }
+    fn remove(&self, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let r = self.resolve(&(String::from(dir) + name), false)?;
+        self.remove_at(&r)
+    }
+    fn rename(&self, old_dir: &str, old_name: &str, new_dir: &str, new_name: &str, replace: bool) -> Result<(), ErrorNo> {
+        let old = self.resolve(&(String::from(old_dir) + old_name), false)?;
+        let new = self.resolve(&(String::from(new_dir) + new_name), false)?;
+        let (layer, mode) = self.node_at(&old).ok_or(ErrorNo::ENOENT)?;
+        if new.name.is_empty() {
+            return Err(ErrorNo::EINVAL);
+        }
+        if old.dir == new.dir && old.name == new.name {
+            return Ok(());
+        }
+        let is_dir = mode == StMode::S_IFDIR;
+        if is_dir {
+            // 不能把目录移动到它自己里面
+            if new.dir.starts_with(old.as_dir().as_str()) {
+                return Err(ErrorNo::EINVAL);
+            }
+            // 下层中的目录不能只在上层中移动
+            if layer == Layer::Lower || self.lower_dir_visible(&old) {
+                return Err(ErrorNo::EXDEV);
+            }
+        }
+        let target = self.node_at(&new);
+        if let Some((_, target_mode)) = target {
+            if !replace {
+                return Err(ErrorNo::EEXIST);
+            }
+            match (is_dir, target_mode == StMode::S_IFDIR) {
+                (true, false) => return Err(ErrorNo::ENOTDIR),
+                (false, true) => return Err(ErrorNo::EISDIR),
+                _ => {}
+            }
+        }
+        self.copy_up(&old)?;
+        // 删除目标，目标在下层中时留下 whiteout，之后被移动过来的文件覆盖
+        let covered = match target {
+            Some(_) => {
+                self.remove_at(&new)?;
+                let covered = self.is_whiteout(&new.dir, &new.name);
+                if covered {
+                    self.upper.remove(&new.dir, &new.name)?;
+                }
+                covered
+            }
+            None => self.prepare_create(&new)?,
+        };
+        self.upper.rename(&old.dir, &old.name, &new.dir, &new.name, false)?;
+        if is_dir && covered {
+            self.set_opaque(&new.as_dir())?;
+        }
+        self.cover_lower(&old)
+    }
+    /// 创建硬链接。下层中的文件先复制到上层，之后两个名字都指向上层中的文件
+    fn link(&self, old_dir: &str, old_name: &str, new_dir: &str, new_name: &str) -> Result<(), ErrorNo> {
+        let old = self.resolve(&(String::from(old_dir) + old_name), false)?;
+        let new = self.resolve(&(String::from(new_dir) + new_name), false)?;
+        match self.node_at(&old) {
+            Some((_, mode)) if mode == StMode::S_IFDIR => return Err(ErrorNo::EPERM),
+            Some(_) => {}
+            None => return Err(ErrorNo::ENOENT),
+        }
+        if self.node_at(&new).is_some() {
+            return Err(ErrorNo::EEXIST);
+        }
+        self.copy_up(&old)?;
+        self.prepare_create(&new)?;
+        self.upper.link(&old.dir, &old.name, &new.dir, &new.name)
+    }
+    fn symlink(&self, target: &str, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let r = self.resolve(&(String::from(dir) + name), false)?;
+        self.prepare_create(&r)?;
+        self.upper.symlink(target, &r.dir, &r.name)
+    }
+    fn mknod(&self, dir: &str, name: &str, mode: u32, rdev: u64) -> Result<(), ErrorNo> {
+        let r = self.resolve(&(String::from(dir) + name), false)?;
+        self.prepare_create(&r)?;
+        self.upper.mknod(&r.dir, &r.name, mode, rdev)
+    }
+    fn read_link(&self, dir: &str, name: &str) -> Result<String, ErrorNo> {
+        let r = self.resolve(&(String::from(dir) + name), false)?;
+        match self.node_at(&r) {
+            Some((layer, mode)) if mode == StMode::S_IFLNK => self.link_target(&r.dir, &r.name, layer),
+            Some(_) => Err(ErrorNo::EINVAL),
+            None => Err(ErrorNo::ENOENT),
+        }
+    }
+    fn chmod(&self, dir: &str, name: &str, mode: u32) -> Result<(), ErrorNo> {
+        let r = self.resolve(&(String::from(dir) + name), true)?;
+        self.copy_up(&r)?;
+        self.upper.chmod(&r.dir, &r.name, mode)
+    }
+    fn chown(&self, dir: &str, name: &str, uid: Option<u32>, gid: Option<u32>, follow: bool) -> Result<(), ErrorNo> {
+        let r = self.resolve(&(String::from(dir) + name), follow)?;
+        self.copy_up(&r)?;
+        self.upper.chown(&r.dir, &r.name, uid, gid, false)
+    }
+    fn list(&self, dir: &str) -> Result<Vec<(String, StMode)>, ErrorNo> {
+        let r = self.resolve(dir, true)?;
+        match self.node_at(&r) {
+            Some((_, mode)) if mode == StMode::S_IFDIR => {}
+            Some(_) => return Err(ErrorNo::ENOTDIR),
+            None => return Err(ErrorNo::ENOENT),
+        }
+        let mut list = Vec::from([(String::from("."), StMode::S_IFDIR), (String::from(".."), StMode::S_IFDIR)]);
+        list.extend(self.merged_entries(&r)?);
+        Ok(list)
+    }
+    fn fs_type(&self) -> &'static str {
+        "overlay"
+    }
+    /// 文件系统的大小按下层计算
+    fn stat_fs(&self, stat: *mut FsStat) {
+        self.lower.stat_fs(stat);
+        unsafe {
+            (*stat).f_type = OVERLAYFS_MAGIC;
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! 9p 中打开的文件或目录
+
+use super::P9FileSystem;
+use crate::file::{user_path, File, Kstat, OpenFlags, SeekFrom};
+use crate::syscall::ErrorNo;
+use crate::timer::TimeSpec;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use lock::Mutex;
+
+/// 9p 中打开的文件。打开的是目录时只用于保存路径和获取属性，不能读写
+pub struct P9File {
+    /// 所在的文件系统。文件打开期间文件系统不会被卸载
+    fs: Arc<P9FileSystem>,
+    /// 文件在服务端的 fid，关闭时释放
+    fid: u32,
+    /// 在整个目录树中的路径。打开的是目录时以 '/' 结尾
+    path: String,
+    /// 打开的是否是目录
+    is_dir: bool,
+    /// 是否可读
+    readable: bool,
+    /// 是否可写
+    writable: bool,
+    /// 可变部分
+    inner: Mutex<P9FileInner>,
+}
+
+/// 文件在os中运行时的可变信息
+struct P9FileInner {
+    /// 文件指针
+    pos: usize,
+    /// 打开时的选项
+    flags: OpenFlags,
+    /// 上次读写失败是否是因为设备出错
+    io_error: bool,
+}
+
+impl P9File {
+    /// 用 fs 中的 fid 创建文件。fid 已经打开，或者不可读写(目录、设备文件等)
+    pub fn new(
+        fs: Arc<P9FileSystem>,
+        fid: u32,
+        mut path: String,
+        is_dir: bool,
+        readable: bool,
+        writable: bool,
+        flags: OpenFlags,
+    ) -> Self {
+        if is_dir && !path.ends_with('/') {
+            path.push('/');
+        }
+        Self {
+            fs: fs,
+            fid: fid,
+            path: path,
+            is_dir: is_dir,
+            readable: readable,
+            writable: writable,
+            inner: Mutex::new(P9FileInner {
+                pos: 0,
+                flags: flags,
+                io_error: false,
+            }),
+        }
+    }
+    /// 记录读写的结果。失败时如果是设备出错，设置 io_error
+    fn check<T>(&self, inner: &mut P9FileInner, result: Result<T, ErrorNo>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                inner.io_error = matches!(e, ErrorNo::EIO);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for P9File {
+    fn drop(&mut self) {
+        self.fs.clunk(self.fid);
+    }
+}
+
+impl File for P9File {
+    /// 读取文件
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        if !self.readable {
+            return None;
+        }
+        let mut inner = self.inner.lock();
+        let result = self.fs.read_at(self.fid, inner.pos, buf);
+        let len = self.check(&mut inner, result)?;
+        inner.pos += len;
+        Some(len)
+    }
+    /// 写入文件
+    fn write(&self, buf: &[u8]) -> Option<usize> {
+        if !self.writable {
+            return None;
+        }
+        let mut inner = self.inner.lock();
+        let pos = if inner.flags.contains(OpenFlags::APPEND) { None } else { Some(inner.pos) };
+        let result = self.fs.write_at(self.fid, pos, buf);
+        let (len, new_pos) = self.check(&mut inner, result)?;
+        inner.pos = new_pos;
+        Some(len)
+    }
+    /// 从某个位置读文件内容到 buf 中，不改变文件指针
+    fn read_from_offset(&self, pos: usize, buf: &mut [u8]) -> Option<usize> {
+        if !self.readable {
+            return None;
+        }
+        let result = self.fs.read_at(self.fid, pos, buf);
+        self.check(&mut self.inner.lock(), result)
+    }
+    /// 将 buf 写入文件中的某个位置，不改变文件指针
+    fn write_to_offset(&self, pos: usize, buf: &[u8]) -> Option<usize> {
+        if !self.writable {
+            return None;
+        }
+        let result = self.fs.write_at(self.fid, Some(pos), buf);
+        self.check(&mut self.inner.lock(), result).map(|(len, _)| len)
+    }
+    /// 取出并清除设备出错的标记
+    fn take_io_error(&self) -> bool {
+        core::mem::take(&mut self.inner.lock().io_error)
+    }
+    /// 切换文件指针位置。可以移到文件末尾之后，之后写入时中间是空洞
+    fn seek(&self, seekfrom: SeekFrom) -> Option<usize> {
+        let mut inner = self.inner.lock();
+        let new_pos = match seekfrom {
+            SeekFrom::Start(off) => off as i64,
+            SeekFrom::Current(off) => inner.pos as i64 + off,
+            SeekFrom::End(off) => self.fs.size(self.fid).ok()? as i64 + off,
+        };
+        if new_pos < 0 {
+            return None;
+        }
+        inner.pos = new_pos as usize;
+        Some(inner.pos)
+    }
+    /// 获取路径。只有目录有
+    fn get_dir(&self) -> Option<&str> {
+        if self.is_dir {
+            Some(self.path.as_str())
+        } else {
+            None
+        }
+    }
+    /// 打开时记录的路径
+    fn get_path(&self) -> Option<String> {
+        Some(user_path(&self.path))
+    }
+    /// 读取所有数据
+    unsafe fn read_all(&self) -> Vec<u8> {
+        // 设备出错时返回空的内容，由调用者发现内容不完整
+        self.fs.read_all(self.fid).unwrap_or_default()
+    }
+    /// 文件属性
+    fn get_stat(&self, stat: *mut Kstat) -> bool {
+        self.fs.stat(self.fid, stat).is_ok()
+    }
+    /// 修改文件大小
+    fn truncate(&self, len: usize) -> bool {
+        self.writable && self.fs.truncate(self.fid, len).is_ok()
+    }
+    /// 清空文件
+    fn clear(&self) {
+        if self.writable && self.fs.truncate(self.fid, 0).is_err() {
+            warn!("9p: failed to clear fid {}", self.fid);
+        }
+    }
+    /// 设置时间，返回是否设置成功。
+    fn set_time(&self, atime: &TimeSpec, mtime: &TimeSpec) -> bool {
+        self.fs.set_time(self.fid, atime, mtime).is_ok()
+    }
+    /// 设置文件状态信息，返回设置是否成功。
+    fn set_status(&self, flags: OpenFlags) -> bool {
+        self.inner.lock().flags = flags;
+        true
+    }
+    /// 设置状态信息的 CLOEXEC 位，返回设置是否成功。
+    fn set_close_on_exec(&self, is_set: bool) -> bool {
+        if is_set {
+            self.inner.lock().flags |= OpenFlags::CLOEXEC;
+        } else {
+            self.inner.lock().flags &= !OpenFlags::CLOEXEC;
+        }
+        true
+    }
+    /// 获取文件状态信息
+    fn get_status(&self) -> OpenFlags {
+        self.inner.lock().flags
+    }
+}
@@ -0,0 +1,619 @@
+//! 9P2000.L 客户端
+//!
+//! 每个操作都从根目录的 fid 出发，用 Twalk 走到目标，操作完成后 Tclunk 掉走出来的 fid。
+//! 打开的文件持有自己的 fid，直到关闭时才释放
+
+use super::file::P9File;
+use super::message::{
+    errno_to_error, Qid, Reply, Request, HEADER_SIZE, RLERROR, TATTACH, TCLUNK, TGETATTR, TLCREATE, TLINK, TLOPEN,
+    TMKDIR, TMKNOD, TREAD, TREADDIR, TREADLINK, TRENAMEAT, TSETATTR, TSTATFS, TSYMLINK, TUNLINKAT, TVERSION, TWALK,
+    TWRITE,
+};
+use crate::drivers::{VirtIO9p, P9_MAX_MESSAGE};
+use crate::file::{File, FsStat, Kstat, OpenFlags, StMode, UnixFs};
+use crate::syscall::ErrorNo;
+use crate::timer::TimeSpec;
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// 协议版本
+const VERSION: &str = "9P2000.L";
+/// 根目录的 fid
+const ROOT_FID: u32 = 0;
+/// 不使用认证时 Tattach 中的 afid
+const NOFID: u32 = !0;
+/// 一次 Twalk 最多走的层数
+const MAX_WALK_NAMES: usize = 16;
+/// Tread / Twrite / Treaddir 的消息头之外的开销
+const IO_HEADER_SIZE: usize = 24;
+/// 查找路径时最多跟随的符号链接数，超过时返回 ELOOP
+const MAX_SYMLINK_FOLLOWS: usize = 40;
+/// 新建文件和目录的默认权限
+const DEFAULT_FILE_PERM: u32 = 0o644;
+const DEFAULT_DIR_PERM: u32 = 0o755;
+
+/// Tlopen / Tlcreate 的打开选项，和 Linux 的 open 相同
+const L_O_WRONLY: u32 = 0o1;
+const L_O_RDWR: u32 = 0o2;
+const L_O_TRUNC: u32 = 0o1000;
+const L_O_DIRECTORY: u32 = 0o200000;
+/// Tunlinkat 中表示删除目录
+const AT_REMOVEDIR: u32 = 0x200;
+/// Tgetattr 中请求基本属性
+const GETATTR_BASIC: u64 = 0x7ff;
+/// Tsetattr 中表示要修改的属性
+const SETATTR_MODE: u32 = 0x1;
+const SETATTR_UID: u32 = 0x2;
+const SETATTR_GID: u32 = 0x4;
+const SETATTR_SIZE: u32 = 0x8;
+const SETATTR_ATIME: u32 = 0x10;
+const SETATTR_MTIME: u32 = 0x20;
+const SETATTR_CTIME: u32 = 0x40;
+const SETATTR_ATIME_SET: u32 = 0x80;
+const SETATTR_MTIME_SET: u32 = 0x100;
+
+/// Treaddir 返回的目录项类型，和 Linux 的 d_type 相同
+const DT_FIFO: u8 = 1;
+const DT_CHR: u8 = 2;
+const DT_DIR: u8 = 4;
+const DT_BLK: u8 = 6;
+const DT_LNK: u8 = 10;
+const DT_SOCK: u8 = 12;
+
+/// Tgetattr 得到的文件属性
+struct Attr {
+    qid: Qid,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u64,
+    rdev: u64,
+    size: u64,
+    blksize: u64,
+    blocks: u64,
+    atime: (u64, u64),
+    mtime: (u64, u64),
+    ctime: (u64, u64),
+}
+
+/// Tsetattr 要修改的属性，valid 中没有标记的项不修改
+#[derive(Default)]
+struct SetAttr {
+    valid: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    atime: (u64, u64),
+    mtime: (u64, u64),
+}
+
+/// Twalk 的结果
+enum Walked {
+    /// 走完了整个路径，得到新的 fid 和目标的 qid
+    Found(u32, Qid),
+    /// 在中途停下，附带已经走过的每一层的 qid
+    Stopped(Vec<Qid>),
+}
+
+/// 通过 virtio-9p 访问的文件系统
+pub struct P9FileSystem {
+    dev: &'static VirtIO9p,
+    /// 协商得到的最大消息长度
+    msize: usize,
+    /// 根目录的 qid
+    root: Qid,
+    /// 下一个可以分配的 fid
+    next_fid: AtomicU32,
+    /// 是否已经 Tattach 到服务端，即 ROOT_FID 是否有效
+    attached: bool,
+}
+
+/// 把 target 中的路径接到 base 后面，处理其中的 "." 和 ".."。target 以 '/' 开头时从根目录开始
+fn join_names(mut base: Vec<String>, target: &str) -> Vec<String> {
+    if target.starts_with('/') {
+        base.clear();
+    }
+    for name in target.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                base.pop();
+            }
+            _ => base.push(String::from(name)),
+        }
+    }
+    base
+}
+
+impl P9FileSystem {
+    /// 在设备上建立 9P 会话，把导出的目录作为根目录
+    pub fn new(dev: &'static VirtIO9p) -> Result<Self, ErrorNo> {
+        let mut fs = Self {
+            dev: dev,
+            msize: P9_MAX_MESSAGE,
+            root: Qid::default(),
+            next_fid: AtomicU32::new(ROOT_FID + 1),
+            attached: false,
+        };
+        let mut reply = fs.call(Request::new(TVERSION).u32(P9_MAX_MESSAGE as u32).str(VERSION))?;
+        fs.msize = (reply.u32()? as usize).min(P9_MAX_MESSAGE);
+        if reply.str()? != VERSION || fs.msize <= IO_HEADER_SIZE {
+            warn!("9p: the server does not support {}", VERSION);
+            return Err(ErrorNo::EINVAL);
+        }
+        let request = Request::new(TATTACH).u32(ROOT_FID).u32(NOFID).str("root").str("").u32(0);
+        fs.root = fs.call(request)?.qid()?;
+        fs.attached = true;
+        Ok(fs)
+    }
+    /// 发送请求并等待回复。服务端返回 Rlerror 时转换成对应的错误
+    fn call(&self, request: Request) -> Result<Reply, ErrorNo> {
+        let (kind, mut reply) = Reply::parse(self.dev.request(&request.finish())?)?;
+        if kind == RLERROR {
+            return Err(errno_to_error(reply.u32()?));
+        }
+        Ok(reply)
+    }
+    fn new_fid(&self) -> u32 {
+        self.next_fid.fetch_add(1, Ordering::Relaxed)
+    }
+    /// 释放 fid
+    pub fn clunk(&self, fid: u32) {
+        if self.call(Request::new(TCLUNK).u32(fid)).is_err() {
+            warn!("9p: failed to clunk fid {}", fid);
+        }
+    }
+    /// 对 fid 执行 f，之后释放 fid
+    fn with_fid<T>(&self, fid: u32, f: impl FnOnce(u32) -> Result<T, ErrorNo>) -> Result<T, ErrorNo> {
+        let result = f(fid);
+        self.clunk(fid);
+        result
+    }
+    /// 从根目录沿着 names 走下去。不跟随符号链接
+    fn walk(&self, names: &[String]) -> Result<Walked, ErrorNo> {
+        let fid = self.new_fid();
+        let mut qids = Vec::new();
+        let mut from = ROOT_FID;
+        // 路径为空时也要走一次，得到根目录的新 fid
+        let chunks: Vec<&[String]> = if names.is_empty() { vec![names] } else { names.chunks(MAX_WALK_NAMES).collect() };
+        for chunk in chunks {
+            let mut request = Request::new(TWALK).u32(from).u32(fid).u16(chunk.len() as u16);
+            for name in chunk {
+                request = request.str(name);
+            }
+            let walked = match self.call(request) {
+                Ok(mut reply) => {
+                    let count = reply.u16()? as usize;
+                    for _ in 0..count {
+                        qids.push(reply.qid()?);
+                    }
+                    count
+                }
+                // 第一层就找不到时，服务端返回错误而不是空的 Rwalk
+                Err(ErrorNo::ENOENT) | Err(ErrorNo::ENOTDIR) => 0,
+                Err(e) => {
+                    if from == fid {
+                        self.clunk(fid);
+                    }
+                    return Err(e);
+                }
+            };
+            if walked < chunk.len() {
+                // 没有走完时，只有之前已经走出来的 fid 需要释放
+                if from == fid {
+                    self.clunk(fid);
+                }
+                return Ok(Walked::Stopped(qids));
+            }
+            from = fid;
+        }
+        Ok(Walked::Found(fid, qids.last().copied().unwrap_or(self.root)))
+    }
+    /// 查找 path，返回新的 fid 和目标的 qid。路径中间的符号链接总是跟随，最后一项只在 follow 为 true 时跟随。
+    /// 符号链接中的绝对路径从这个文件系统的根目录开始查找
+    pub fn lookup(&self, path: &str, follow: bool) -> Result<(u32, Qid), ErrorNo> {
+        let mut names = join_names(Vec::new(), path);
+        for _ in 0..=MAX_SYMLINK_FOLLOWS {
+            match self.walk(&names)? {
+                Walked::Found(fid, qid) => {
+                    if !(follow && qid.is_symlink()) {
+                        return Ok((fid, qid));
+                    }
+                    let target = self.with_fid(fid, |fid| self.read_link_fid(fid))?;
+                    names.pop();
+                    names = join_names(names, &target);
+                }
+                Walked::Stopped(qids) => {
+                    let walked = qids.len();
+                    match qids.last() {
+                        // 中途遇到符号链接，把它替换成目标路径再继续
+                        Some(qid) if qid.is_symlink() => {
+                            let fid = match self.walk(&names[..walked])? {
+                                Walked::Found(fid, _) => fid,
+                                Walked::Stopped(_) => return Err(ErrorNo::ENOENT),
+                            };
+                            let target = self.with_fid(fid, |fid| self.read_link_fid(fid))?;
+                            let rest = names.split_off(walked);
+                            names.pop();
+                            names = join_names(names, &target);
+                            names.extend(rest);
+                        }
+                        Some(qid) if !qid.is_dir() => return Err(ErrorNo::ENOTDIR),
+                        _ => return Err(ErrorNo::ENOENT),
+                    }
+                }
+            }
+        }
+        Err(ErrorNo::ELOOP)
+    }
+    /// 查找目录 dir，返回新的 fid
+    fn lookup_dir(&self, dir: &str) -> Result<u32, ErrorNo> {
+        let (fid, qid) = self.lookup(dir, true)?;
+        if !qid.is_dir() {
+            self.clunk(fid);
+            return Err(ErrorNo::ENOTDIR);
+        }
+        Ok(fid)
+    }
+    fn read_link_fid(&self, fid: u32) -> Result<String, ErrorNo> {
+        self.call(Request::new(TREADLINK).u32(fid))?.str()
+    }
+    /// 打开 fid 对应的文件
+    fn lopen(&self, fid: u32, flags: u32) -> Result<(), ErrorNo> {
+        self.call(Request::new(TLOPEN).u32(fid).u32(flags)).map(|_| ())
+    }
+    /// 获取 fid 的属性
+    fn getattr(&self, fid: u32) -> Result<Attr, ErrorNo> {
+        let mut reply = self.call(Request::new(TGETATTR).u32(fid).u64(GETATTR_BASIC))?;
+        let _valid = reply.u64()?;
+        Ok(Attr {
+            qid: reply.qid()?,
+            mode: reply.u32()?,
+            uid: reply.u32()?,
+            gid: reply.u32()?,
+            nlink: reply.u64()?,
+            rdev: reply.u64()?,
+            size: reply.u64()?,
+            blksize: reply.u64()?,
+            blocks: reply.u64()?,
+            atime: (reply.u64()?, reply.u64()?),
+            mtime: (reply.u64()?, reply.u64()?),
+            ctime: (reply.u64()?, reply.u64()?),
+        })
+    }
+    fn setattr(&self, fid: u32, attr: SetAttr) -> Result<(), ErrorNo> {
+        let request = Request::new(TSETATTR)
+            .u32(fid)
+            .u32(attr.valid)
+            .u32(attr.mode)
+            .u32(attr.uid)
+            .u32(attr.gid)
+            .u64(attr.size)
+            .u64(attr.atime.0)
+            .u64(attr.atime.1)
+            .u64(attr.mtime.0)
+            .u64(attr.mtime.1);
+        self.call(request).map(|_| ())
+    }
+    /// 从打开的 fid 的 pos 处读数据
+    pub fn read_at(&self, fid: u32, pos: usize, buf: &mut [u8]) -> Result<usize, ErrorNo> {
+        let mut read_len = 0;
+        while read_len < buf.len() {
+            let count = (buf.len() - read_len).min(self.msize - IO_HEADER_SIZE);
+            let request = Request::new(TREAD).u32(fid).u64((pos + read_len) as u64).u32(count as u32);
+            let mut reply = self.call(request)?;
+            let data = reply.data()?;
+            let len = data.len().min(count);
+            buf[read_len..read_len + len].copy_from_slice(&data[..len]);
+            read_len += len;
+            if len < count {
+                break;
+            }
+        }
+        Ok(read_len)
+    }
+    /// 向打开的 fid 的 pos 处写数据。pos 为 None 时写到文件末尾。返回写入的长度和写完后的位置
+    pub fn write_at(&self, fid: u32, pos: Option<usize>, buf: &[u8]) -> Result<(usize, usize), ErrorNo> {
+        let pos = match pos {
+            Some(pos) => pos,
+            None => self.size(fid)?,
+        };
+        let mut written = 0;
+        while written < buf.len() {
+            let count = (buf.len() - written).min(self.msize - IO_HEADER_SIZE);
+            let request = Request::new(TWRITE)
+                .u32(fid)
+                .u64((pos + written) as u64)
+                .data(&buf[written..written + count]);
+            let len = self.call(request)?.u32()? as usize;
+            written += len;
+            if len < count {
+                break;
+            }
+        }
+        Ok((written, pos + written))
+    }
+    /// 文件大小
+    pub fn size(&self, fid: u32) -> Result<usize, ErrorNo> {
+        self.getattr(fid).map(|attr| attr.size as usize)
+    }
+    /// 修改 fid 的大小
+    pub fn truncate(&self, fid: u32, size: usize) -> Result<(), ErrorNo> {
+        let attr = SetAttr {
+            valid: SETATTR_SIZE,
+            size: size as u64,
+            ..Default::default()
+        };
+        self.setattr(fid, attr)
+    }
+    /// 修改 fid 的访问时间和修改时间，格式同 TimeSpec::set_as_utime
+    pub fn set_time(&self, fid: u32, atime: &TimeSpec, mtime: &TimeSpec) -> Result<(), ErrorNo> {
+        let old = self.getattr(fid)?;
+        let mut new_atime = TimeSpec { tv_sec: old.atime.0 as usize, tv_nsec: old.atime.1 as usize };
+        let mut new_mtime = TimeSpec { tv_sec: old.mtime.0 as usize, tv_nsec: old.mtime.1 as usize };
+        new_atime.set_as_utime(atime);
+        new_mtime.set_as_utime(mtime);
+        let attr = SetAttr {
+            valid: SETATTR_ATIME | SETATTR_ATIME_SET | SETATTR_MTIME | SETATTR_MTIME_SET | SETATTR_CTIME,
+            atime: (new_atime.tv_sec as u64, new_atime.tv_nsec as u64),
+            mtime: (new_mtime.tv_sec as u64, new_mtime.tv_nsec as u64),
+            ..Default::default()
+        };
+        self.setattr(fid, attr)
+    }
+    /// 把 fid 的属性写入 stat
+    pub fn stat(&self, fid: u32, stat: *mut Kstat) -> Result<(), ErrorNo> {
+        let attr = self.getattr(fid)?;
+        unsafe {
+            (*stat).st_dev = 3;
+            (*stat).st_ino = attr.qid.path;
+            (*stat).st_mode = attr.mode;
+            (*stat).st_nlink = attr.nlink as u32;
+            (*stat).st_uid = attr.uid;
+            (*stat).st_gid = attr.gid;
+            (*stat).st_rdev = attr.rdev;
+            (*stat).st_size = attr.size;
+            (*stat).st_blksize = attr.blksize as u32;
+            (*stat).st_blocks = attr.blocks;
+            (*stat).st_atime_sec = attr.atime.0 as isize;
+            (*stat).st_atime_nsec = attr.atime.1 as isize;
+            (*stat).st_mtime_sec = attr.mtime.0 as isize;
+            (*stat).st_mtime_nsec = attr.mtime.1 as isize;
+            (*stat).st_ctime_sec = attr.ctime.0 as isize;
+            (*stat).st_ctime_nsec = attr.ctime.1 as isize;
+        }
+        Ok(())
+    }
+    /// 读取打开的 fid 的全部内容
+    pub fn read_all(&self, fid: u32) -> Result<Vec<u8>, ErrorNo> {
+        let mut buf = vec![0u8; self.size(fid)?];
+        let len = self.read_at(fid, 0, &mut buf)?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+impl Drop for P9FileSystem {
+    /// 卸载后最后一个打开的文件也关闭时，释放根目录的 fid，结束和服务端的会话
+    fn drop(&mut self) {
+        if self.attached {
+            self.clunk(ROOT_FID);
+        }
+    }
+}
+
+impl UnixFs for P9FileSystem {
+    /// 打开 dir 目录下的 name 文件。只有普通文件会用 Tlopen 打开，其他类型只用来获取属性
+    fn open(self: Arc<Self>, dir: &str, name: &str, full_dir: &str, flags: OpenFlags) -> Option<Arc<dyn File>> {
+        let (readable, writable) = flags.read_write();
+        let mut open_flags = match (readable, writable) {
+            (true, true) => L_O_RDWR,
+            (false, true) => L_O_WRONLY,
+            _ => 0,
+        };
+        if writable && flags.contains(OpenFlags::TRUNC) {
+            open_flags |= L_O_TRUNC;
+        }
+        let path = String::from(dir) + name;
+        let full_path = String::from(full_dir) + name;
+        let (fid, qid) = match self.lookup(&path, !flags.contains(OpenFlags::NOFOLLOW)) {
+            Ok((fid, qid)) => {
+                if flags.contains(OpenFlags::CREATE) && flags.contains(OpenFlags::EXCLUSIVE) {
+                    self.clunk(fid);
+                    return None;
+                }
+                (fid, qid)
+            }
+            Err(ErrorNo::ENOENT) if flags.contains(OpenFlags::CREATE) && !name.is_empty() => {
+                // Tlcreate 之后，目录的 fid 就变成了打开的新文件
+                let fid = self.lookup_dir(dir).ok()?;
+                let request = Request::new(TLCREATE).u32(fid).str(name).u32(open_flags).u32(DEFAULT_FILE_PERM).u32(0);
+                if self.call(request).is_err() {
+                    self.clunk(fid);
+                    return None;
+                }
+                return Some(Arc::new(P9File::new(self, fid, full_path, false, readable, writable, flags)));
+            }
+            Err(_) => return None,
+        };
+        if flags.contains(OpenFlags::DIR) || flags.contains(OpenFlags::DSYNC) || name.is_empty() {
+            if !qid.is_dir() {
+                self.clunk(fid);
+                return None;
+            }
+            return Some(Arc::new(P9File::new(self, fid, full_path, true, false, false, flags)));
+        }
+        if qid.is_dir() {
+            self.clunk(fid);
+            return None;
+        }
+        let is_regular = self.getattr(fid).map_or(false, |attr| attr.mode & StMode::S_IFMT.bits() == StMode::S_IFREG.bits());
+        if !is_regular {
+            return Some(Arc::new(P9File::new(self, fid, full_path, false, false, false, flags)));
+        }
+        if self.lopen(fid, open_flags).is_err() {
+            self.clunk(fid);
+            return None;
+        }
+        Some(Arc::new(P9File::new(self, fid, full_path, false, readable, writable, flags)))
+    }
+    fn exists(&self, dir: &str, name: &str) -> Option<bool> {
+        let (fid, qid) = self.lookup(&(String::from(dir) + name), true).ok()?;
+        self.clunk(fid);
+        Some(qid.is_dir())
+    }
+    fn mkdir(&self, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let fid = self.lookup_dir(dir)?;
+        self.with_fid(fid, |fid| {
+            self.call(Request::new(TMKDIR).u32(fid).str(name).u32(DEFAULT_DIR_PERM).u32(0)).map(|_| ())
+        })
+    }
+    fn remove(&self, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let (fid, qid) = self.lookup(&(String::from(dir) + name), false)?;
+        self.clunk(fid);
+        let flags = if qid.is_dir() { AT_REMOVEDIR } else { 0 };
+        let fid = self.lookup_dir(dir)?;
+        self.with_fid(fid, |fid| self.call(Request::new(TUNLINKAT).u32(fid).str(name).u32(flags)).map(|_| ()))
+    }
+    fn rename(&self, old_dir: &str, old_name: &str, new_dir: &str, new_name: &str, replace: bool) -> Result<(), ErrorNo> {
+        if !replace {
+            if let Ok((fid, _)) = self.lookup(&(String::from(new_dir) + new_name), false) {
+                self.clunk(fid);
+                return Err(ErrorNo::EEXIST);
+            }
+        }
+        let old_fid = self.lookup_dir(old_dir)?;
+        self.with_fid(old_fid, |old_fid| {
+            let new_fid = self.lookup_dir(new_dir)?;
+            self.with_fid(new_fid, |new_fid| {
+                let request = Request::new(TRENAMEAT).u32(old_fid).str(old_name).u32(new_fid).str(new_name);
+                self.call(request).map(|_| ())
+            })
+        })
+    }
+    fn link(&self, old_dir: &str, old_name: &str, new_dir: &str, new_name: &str) -> Result<(), ErrorNo> {
+        let (fid, qid) = self.lookup(&(String::from(old_dir) + old_name), false)?;
+        self.with_fid(fid, |fid| {
+            if qid.is_dir() {
+                return Err(ErrorNo::EPERM);
+            }
+            let dir_fid = self.lookup_dir(new_dir)?;
+            self.with_fid(dir_fid, |dir_fid| {
+                self.call(Request::new(TLINK).u32(dir_fid).u32(fid).str(new_name)).map(|_| ())
+            })
+        })
+    }
+    fn symlink(&self, target: &str, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let fid = self.lookup_dir(dir)?;
+        self.with_fid(fid, |fid| {
+            self.call(Request::new(TSYMLINK).u32(fid).str(name).str(target).u32(0)).map(|_| ())
+        })
+    }
+    fn mknod(&self, dir: &str, name: &str, mode: u32, rdev: u64) -> Result<(), ErrorNo> {
+        // 没有给出类型时是普通文件
+        let mode = if mode & StMode::S_IFMT.bits() == 0 { mode | StMode::S_IFREG.bits() } else { mode };
+        // Linux 的设备号编码
+        let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+        let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+        let fid = self.lookup_dir(dir)?;
+        self.with_fid(fid, |fid| {
+            let request = Request::new(TMKNOD).u32(fid).str(name).u32(mode).u32(major as u32).u32(minor as u32).u32(0);
+            self.call(request).map(|_| ())
+        })
+    }
+    fn read_link(&self, dir: &str, name: &str) -> Result<String, ErrorNo> {
+        let (fid, qid) = self.lookup(&(String::from(dir) + name), false)?;
+        self.with_fid(fid, |fid| {
+            if !qid.is_symlink() {
+                return Err(ErrorNo::EINVAL);
+            }
+            self.read_link_fid(fid)
+        })
+    }
+    fn chmod(&self, dir: &str, name: &str, mode: u32) -> Result<(), ErrorNo> {
+        let (fid, _) = self.lookup(&(String::from(dir) + name), true)?;
+        let attr = SetAttr {
+            valid: SETATTR_MODE,
+            mode: mode & 0o7777,
+            ..Default::default()
+        };
+        self.with_fid(fid, |fid| self.setattr(fid, attr))
+    }
+    fn chown(&self, dir: &str, name: &str, uid: Option<u32>, gid: Option<u32>, follow: bool) -> Result<(), ErrorNo> {
+        let (fid, _) = self.lookup(&(String::from(dir) + name), follow)?;
+        let attr = SetAttr {
+            valid: uid.map_or(0, |_| SETATTR_UID) | gid.map_or(0, |_| SETATTR_GID),
+            uid: uid.unwrap_or(0),
+            gid: gid.unwrap_or(0),
+            ..Default::default()
+        };
+        self.with_fid(fid, |fid| self.setattr(fid, attr))
+    }
+    fn list(&self, dir: &str) -> Result<Vec<(String, StMode)>, ErrorNo> {
+        let fid = self.lookup_dir(dir)?;
+        self.with_fid(fid, |fid| {
+            self.lopen(fid, L_O_DIRECTORY)?;
+            let mut entries = Vec::new();
+            let mut offset = 0;
+            loop {
+                let request = Request::new(TREADDIR).u32(fid).u64(offset).u32((self.msize - IO_HEADER_SIZE) as u32);
+                let mut reply = self.call(request)?;
+                let count = reply.u32()? as usize;
+                if count == 0 {
+                    break;
+                }
+                // 目录项依次为 qid[13] offset[8] type[1] name[s]
+                let end = HEADER_SIZE + 4 + count;
+                while reply.position() < end {
+                    let _qid = reply.qid()?;
+                    offset = reply.u64()?;
+                    let file_type = match reply.u8()? {
+                        DT_DIR => StMode::S_IFDIR,
+                        DT_LNK => StMode::S_IFLNK,
+                        DT_CHR => StMode::S_IFCHR,
+                        DT_BLK => StMode::S_IFBLK,
+                        DT_FIFO => StMode::S_IFIFO,
+                        DT_SOCK => StMode::S_IFSOCK,
+                        _ => StMode::S_IFREG,
+                    };
+                    entries.push((reply.str()?, file_type));
+                }
+            }
+            Ok(entries)
+        })
+    }
+    fn fs_type(&self) -> &'static str {
+        "9p"
+    }
+    fn stat_fs(&self, stat: *mut FsStat) {
+        let result = self.call(Request::new(TSTATFS).u32(ROOT_FID)).and_then(|mut reply| {
+            let fs_type = reply.u32()?;
+            let bsize = reply.u32()?;
+            let numbers = [reply.u64()?, reply.u64()?, reply.u64()?, reply.u64()?, reply.u64()?];
+            let _fsid = reply.u64()?;
+            Ok((fs_type, bsize, numbers, reply.u32()?))
+        });
+        let (fs_type, bsize, [blocks, bfree, bavail, files, ffree], namelen) = match result {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("9p: statfs failed: {:?}", e);
+                (0, 0, [0; 5], 0)
+            }
+        };
+        unsafe {
+            (*stat).f_type = fs_type as i64;
+            (*stat).f_bsize = bsize as i64;
+            (*stat).f_blocks = blocks;
+            (*stat).f_bfree = bfree;
+            (*stat).f_bavail = bavail;
+            (*stat).f_files = files;
+            (*stat).f_ffree = ffree;
+            (*stat).f_fsid = [0, 0];
+            (*stat).f_namelen = namelen as isize;
+            (*stat).f_frsize = bsize as isize;
+            (*stat).f_flags = 0;
+            (*stat).f_spare = [0, 0, 0, 0];
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! 9P2000.L 消息的编码和解码
+//!
+//! 每条消息的开头是 size[4] type[1] tag[2]，之后是各类消息自己的字段，全部为小端序。
+//! 字符串是 len[2] 加上 UTF-8 内容，qid 是 type[1] version[4] path[8]
+
+use crate::syscall::ErrorNo;
+use alloc::{string::String, vec, vec::Vec};
+
+/// 消息类型。T 开头的是请求，对应的回复是它加一
+pub const RLERROR: u8 = 7;
+pub const TSTATFS: u8 = 8;
+pub const TLOPEN: u8 = 12;
+pub const TLCREATE: u8 = 14;
+pub const TSYMLINK: u8 = 16;
+pub const TMKNOD: u8 = 18;
+pub const TREADLINK: u8 = 22;
+pub const TGETATTR: u8 = 24;
+pub const TSETATTR: u8 = 26;
+pub const TREADDIR: u8 = 40;
+pub const TLINK: u8 = 70;
+pub const TMKDIR: u8 = 72;
+pub const TRENAMEAT: u8 = 74;
+pub const TUNLINKAT: u8 = 76;
+pub const TVERSION: u8 = 100;
+pub const TATTACH: u8 = 104;
+pub const TWALK: u8 = 110;
+pub const TREAD: u8 = 116;
+pub const TWRITE: u8 = 118;
+pub const TCLUNK: u8 = 120;
+
+/// Tversion 使用的 tag
+const NOTAG: u16 = !0;
+/// 消息头的长度
+pub const HEADER_SIZE: usize = 7;
+
+/// qid 中的类型位
+const QTDIR: u8 = 0x80;
+const QTSYMLINK: u8 = 0x02;
+
+/// 服务端中文件的唯一标识
+#[derive(Clone, Copy, Default)]
+pub struct Qid {
+    /// 文件类型
+    kind: u8,
+    /// 文件在服务端的编号，相当于 inode 编号
+    pub path: u64,
+}
+
+impl Qid {
+    pub fn is_dir(&self) -> bool {
+        self.kind & QTDIR != 0
+    }
+    pub fn is_symlink(&self) -> bool {
+        self.kind & QTSYMLINK != 0
+    }
+}
+
+/// 正在构造的请求
+pub struct Request {
+    buf: Vec<u8>,
+}
+
+impl Request {
+    /// 新的 kind 类型的请求。同一时间只有一个请求，所以除 Tversion 外 tag 都是 0
+    pub fn new(kind: u8) -> Self {
+        let tag = if kind == TVERSION { NOTAG } else { 0 };
+        let mut buf = vec![0u8; 4];
+        buf.push(kind);
+        buf.extend_from_slice(&tag.to_le_bytes());
+        Self { buf: buf }
+    }
+    pub fn u16(mut self, value: u16) -> Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+    pub fn u32(mut self, value: u32) -> Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+    pub fn u64(mut self, value: u64) -> Self {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+    pub fn str(self, value: &str) -> Self {
+        let mut req = self.u16(value.len() as u16);
+        req.buf.extend_from_slice(value.as_bytes());
+        req
+    }
+    /// 写入的数据，前面是 32 位的长度
+    pub fn data(self, data: &[u8]) -> Self {
+        let mut req = self.u32(data.len() as u32);
+        req.buf.extend_from_slice(data);
+        req
+    }
+    /// 填上消息长度，得到完整的消息
+    pub fn finish(mut self) -> Vec<u8> {
+        let size = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&size.to_le_bytes());
+        self.buf
+    }
+}
+
+/// 收到的回复，按顺序读取其中的字段。回复比预期短时返回 EIO
+pub struct Reply {
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Reply {
+    /// 检查消息头，返回消息类型和从消息体开始读取的 Reply
+    pub fn parse(buf: Vec<u8>) -> Result<(u8, Self), ErrorNo> {
+        if buf.len() < HEADER_SIZE {
+            return Err(ErrorNo::EIO);
+        }
+        let kind = buf[4];
+        Ok((kind, Self { buf: buf, pos: HEADER_SIZE }))
+    }
+    /// 已经读到消息中的哪个位置
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+    fn bytes(&mut self, len: usize) -> Result<&[u8], ErrorNo> {
+        if self.pos + len > self.buf.len() {
+            return Err(ErrorNo::EIO);
+        }
+        self.pos += len;
+        Ok(&self.buf[self.pos - len..self.pos])
+    }
+    pub fn u8(&mut self) -> Result<u8, ErrorNo> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub fn u16(&mut self) -> Result<u16, ErrorNo> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+    pub fn u32(&mut self) -> Result<u32, ErrorNo> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    pub fn u64(&mut self) -> Result<u64, ErrorNo> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+    pub fn str(&mut self) -> Result<String, ErrorNo> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into())
+    }
+    pub fn qid(&mut self) -> Result<Qid, ErrorNo> {
+        let kind = self.u8()?;
+        let _version = self.u32()?;
+        Ok(Qid {
+            kind: kind,
+            path: self.u64()?,
+        })
+    }
+    /// 读到的数据，前面是 32 位的长度
+    pub fn data(&mut self) -> Result<&[u8], ErrorNo> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+}
+
+/// 把 Rlerror 中的 Linux 错误码转换成 ErrorNo。不认识的错误码都当作 EIO
+pub fn errno_to_error(errno: u32) -> ErrorNo {
+    match errno {
+        1 => ErrorNo::EPERM,
+        2 => ErrorNo::ENOENT,
+        9 => ErrorNo::EBADF,
+        13 => ErrorNo::EACCES,
+        16 => ErrorNo::EBUSY,
+        17 => ErrorNo::EEXIST,
+        18 => ErrorNo::EXDEV,
+        20 => ErrorNo::ENOTDIR,
+        21 => ErrorNo::EISDIR,
+        22 => ErrorNo::EINVAL,
+        28 => ErrorNo::ENOSPC,
+        36 => ErrorNo::ENAMETOOLONG,
+        39 => ErrorNo::ENOTEMPTY,
+        40 => ErrorNo::ELOOP,
+        _ => ErrorNo::EIO,
+    }
+}
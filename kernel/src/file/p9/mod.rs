@@ -0,0 +1,13 @@
+//! 9P2000.L 文件系统
+//!
+//! 通过 virtio-9p 访问宿主机用 QEMU 的 `-virtfs` / `-fsdev local` 共享出来的目录，
+//! 用 `mount -t 9p <mount_tag> <dir>` 挂载。文件的权限、所有者、链接等元数据都由服务端保存，
+//! 内核不缓存文件内容，每次读写都直接发给服务端。
+//!
+//! 同一时间只有一个请求在设备上，不使用 flush，也不支持 xattr 和文件锁
+
+mod file;
+mod fs;
+mod message;
+
+pub use fs::P9FileSystem;
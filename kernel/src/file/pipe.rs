@@ -4,11 +4,14 @@
 //! Pipe 的读写可能会触发进程切换。
 //! 目前的实现中，Pipe会请求并获取页帧，不占用内核堆/栈
 
-use super::{File, BufferFile, OpenFlags};
-use crate::{constants::PIPE_SIZE_LIMIT, task::suspend_current_task};
+use super::{File, BufferFile, OpenFlags, WaitQueue};
+use crate::{constants::PAGE_SIZE, sysctl::Sysctl, task::suspend_current_task};
 use alloc::sync::Arc;
 use lock::Mutex;
 
+/// 新建的管道的大小，单位为字节。可以通过 /proc/sys/fs/pipe_size_limit 修改，只影响之后创建的管道
+pub static PIPE_SIZE_LIMIT: Sysctl<usize> = Sysctl::new(0x40_000, PAGE_SIZE, 0x400_0000); // 默认 256 KB，最大 64 MB
+
 /// 管道内部的 buffer，是个循环队列
 pub struct RingBuffer {
     data: BufferFile,
@@ -91,20 +94,38 @@ pub struct Pipe {
     /// 管道内保存的数据
     /// 只有所有持有管道的 Arc 被 Drop 时，才会释放其中的 PipeBuffer 的空间
     data: Arc<Mutex<RingBuffer>>,
+    /// 等待管道状态变化的线程，两端共用。
+    /// 注意它必须声明在 data 之后，见 PipeWaiters
+    waiters: PipeWaiters,
+}
+
+/// 管道一端持有的等待队列。
+///
+/// 一端关闭时，另一端会进入 HUP 状态，需要唤醒等待的线程。
+/// 结构体的字段按声明顺序释放，所以这里被释放时 data 上的引用计数已经减少，被唤醒的线程可以看到 HUP 状态
+struct PipeWaiters(Arc<WaitQueue>);
+
+impl Drop for PipeWaiters {
+    fn drop(&mut self) {
+        self.0.wake_all();
+    }
 }
 
 impl Pipe {
     /// 新建一个管道，返回两端
     pub fn new_pipe() -> (Self, Self) {
-        let buf = Arc::new(Mutex::new(RingBuffer::new(PIPE_SIZE_LIMIT)));
+        let buf = Arc::new(Mutex::new(RingBuffer::new(PIPE_SIZE_LIMIT.get())));
+        let waiters = Arc::new(WaitQueue::new());
         (
             Self {
                 is_read: true,
                 data: buf.clone(),
+                waiters: PipeWaiters(waiters.clone()),
             },
             Self {
                 is_read: false,
                 data: buf,
+                waiters: PipeWaiters(waiters),
             },
         )
     }
@@ -136,6 +157,10 @@ impl File for Pipe {
             }
             //if buf.len() != read_len { println!("tid {} read {} final got {}", tid, buf.len(), read_len); }
             //else { println!("tid {} read {} final got {}", tid, buf.len(), read_len); }
+            if read_len > 0 {
+                // 读出数据后写端可能可以继续写了
+                self.waiters.0.wake_all();
+            }
             Some(read_len)
         } else {
             None
@@ -179,6 +204,9 @@ impl File for Pipe {
             }
             //if buf.len() != write_len { println!("tid {} write {} final got {}", tid, buf.len(), write_len); }
             //else { println!("tid {} write {} final got {}", tid, buf.len(), write_len); }
+            if write_len > 0 {
+                self.waiters.0.wake_all();
+            }
             Some(write_len)
         }
     }
@@ -198,4 +226,23 @@ impl File for Pipe {
             Arc::strong_count(&self.data) < 2
         }
     }
+    /// 管道的状态变化时会唤醒等待的线程
+    fn register_waiter(&self, tid: usize) -> bool {
+        self.waiters.0.register(tid);
+        true
+    }
+    /// 独占等待
+    fn register_exclusive_waiter(&self, tid: usize) -> bool {
+        self.waiters.0.register_exclusive(tid);
+        true
+    }
+    /// 两端共用一个等待队列
+    fn wake_count(&self) -> Option<usize> {
+        Some(self.waiters.0.wake_count())
+    }
+    /// 取消等待
+    fn unregister_waiter(&self, tid: usize) {
+        self.waiters.0.unregister(tid);
+    }
 }
+
@@ -0,0 +1,408 @@
+//! procfs 文件系统，挂载在 /proc
+//!
+//! 其中没有保存任何文件，所有内容都在读取时从内核的状态生成：
+//! - 每个进程有一个以 pid 命名的目录，其中有 cmdline、stat、status、maps 等信息文件，
+//!   fd 目录下是指向打开的文件的符号链接，task 目录下是每个线程的目录
+//! - self 和 thread-self 是指向当前进程和当前线程的符号链接
+//! - meminfo、stat、loadavg 等系统信息文件在 system 中注册。其他模块也可以通过 add_proc_file 注册，如 /proc/sysvipc/shm
+//! - sys 目录下是 sysctl 中注册的内核参数，可以读写
+//!
+//! 打开的文件是打开时内容的快照。procfs 中不能创建、删除或重命名文件
+
+mod proc_file;
+mod process;
+mod sys;
+mod system;
+
+use super::{device::FdDir, open_file, user_path, File, FsStat, OpenFlags, StMode, UnixFs};
+use crate::constants::ROOT_DIR;
+use crate::memory::PAGE_SIZE;
+use crate::syscall::ErrorNo;
+use crate::sysctl::{read_sysctl, sysctl_paths};
+use crate::task::{get_all_pids, get_current_task, get_task_from_pid, TaskControlBlock};
+use alloc::{collections::BTreeMap, format, string::String, sync::Arc, vec::Vec};
+use lock::Mutex;
+use proc_file::{snapshot, ProcFile};
+use process::{fd_target, generate, threads_of, TaskFile, TASK_FILES};
+use sys::{list_sysctl, SysctlFile};
+pub use system::add_system_proc_files;
+
+/// statfs 中 procfs 的 f_type，和 Linux 相同
+const PROC_SUPER_MAGIC: i64 = 0x9fa0;
+
+lazy_static::lazy_static! {
+    /// 挂载在 /proc 的 procfs
+    pub static ref PROC_FS: Arc<ProcFs> = Arc::new(ProcFs::new());
+}
+
+/// procfs 文件系统
+pub struct ProcFs {
+    /// 注册的系统信息文件。键是相对 /proc 的目录(根目录为 "")，值是目录下的文件。
+    /// 每个目录的上级目录也在其中
+    entries: Mutex<BTreeMap<String, BTreeMap<String, Arc<ProcFile>>>>,
+}
+
+/// procfs 中的一项
+enum Node {
+    /// 注册的文件所在的目录，值为相对 /proc 的路径
+    Dir(String),
+    /// 注册的文件
+    File(Arc<ProcFile>),
+    /// /proc/<pid>
+    Process(Arc<TaskControlBlock>),
+    /// /proc/<pid>/task/<tid>
+    Thread(Arc<TaskControlBlock>),
+    /// /proc/<pid>/task
+    Threads(Arc<TaskControlBlock>),
+    /// /proc/<pid>/fd
+    Fds(Arc<TaskControlBlock>),
+    /// 进程或线程目录下的信息文件
+    TaskFile(Arc<TaskControlBlock>, TaskFile),
+    /// /proc/sys 下的目录，值为相对 /proc/sys 的路径
+    SysctlDir(String),
+    /// /proc/sys 下的参数，值为参数的路径
+    Sysctl(&'static str),
+    /// 符号链接
+    Link(Link),
+}
+
+/// procfs 中的符号链接
+enum Link {
+    /// /proc/self
+    SelfProcess,
+    /// /proc/thread-self
+    SelfThread,
+    /// /proc/<pid>/cwd
+    Cwd(Arc<TaskControlBlock>),
+    /// /proc/<pid>/exe
+    Exe(Arc<TaskControlBlock>),
+    /// /proc/<pid>/fd/<fd>
+    Fd(Arc<dyn File>),
+}
+
+impl Link {
+    /// 链接指向的路径
+    fn target(&self) -> Option<String> {
+        let task = get_current_task();
+        Some(match self {
+            Link::SelfProcess => format!("{}", task?.pid),
+            Link::SelfThread => {
+                let task = task?;
+                format!("{}/task/{}", task.pid, task.get_tid_num())
+            }
+            // 工作目录形如 "./dir/"，转换成 "/dir"
+            Link::Cwd(task) => user_path(&task.inner.lock().dir),
+            Link::Exe(task) => task.exe.lock().clone(),
+            Link::Fd(file) => fd_target(file),
+        })
+    }
+    /// 跟随指向 procfs 内部的链接。指向 procfs 外部的链接返回 None
+    fn follow(&self) -> Option<Node> {
+        let task = get_current_task()?;
+        match self {
+            Link::SelfProcess => Some(Node::Process(get_task_from_pid(task.pid)?)),
+            Link::SelfThread => Some(Node::Thread(task)),
+            _ => None,
+        }
+    }
+}
+
+impl ProcFs {
+    pub fn new() -> Self {
+        let mut entries = BTreeMap::new();
+        entries.insert(String::new(), BTreeMap::new());
+        Self {
+            entries: Mutex::new(entries),
+        }
+    }
+    /// 在 dir 目录下注册文件 name，dir 是相对 /proc 的路径，不存在时自动创建
+    fn add_file(&self, dir: &str, name: &str, file: ProcFile) {
+        let dir = dir.trim_matches('/');
+        let mut entries = self.entries.lock();
+        let mut path = String::new();
+        for component in dir.split('/').filter(|c| !c.is_empty()) {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path += component;
+            entries.entry(path.clone()).or_insert_with(BTreeMap::new);
+        }
+        entries
+            .entry(String::from(dir))
+            .or_insert_with(BTreeMap::new)
+            .insert(String::from(name), Arc::new(file));
+    }
+    /// 根目录下的 name
+    fn lookup_root(&self, name: &str) -> Option<Node> {
+        match name {
+            "self" => return Some(Node::Link(Link::SelfProcess)),
+            "thread-self" => return Some(Node::Link(Link::SelfThread)),
+            "sys" => return Some(Node::SysctlDir(String::new())),
+            _ => {}
+        }
+        if !name.is_empty() && name.bytes().all(|c| c.is_ascii_digit()) {
+            return get_task_from_pid(name.parse().ok()?).map(Node::Process);
+        }
+        self.lookup_registered("", name)
+    }
+    /// 注册的目录 dir 下的 name
+    fn lookup_registered(&self, dir: &str, name: &str) -> Option<Node> {
+        let entries = self.entries.lock();
+        let path = if dir.is_empty() {
+            String::from(name)
+        } else {
+            format!("{}/{}", dir, name)
+        };
+        if entries.contains_key(&path) {
+            return Some(Node::Dir(path));
+        }
+        entries.get(dir)?.get(name).map(|file| Node::File(file.clone()))
+    }
+    /// /proc/sys 下 dir 目录中的 name
+    fn lookup_sysctl(&self, dir: &str, name: &str) -> Option<Node> {
+        let path = if dir.is_empty() {
+            String::from(name)
+        } else {
+            format!("{}/{}", dir, name)
+        };
+        let prefix = path.clone() + "/";
+        let paths = sysctl_paths();
+        if let Some(tunable) = paths.iter().find(|p| **p == path) {
+            Some(Node::Sysctl(*tunable))
+        } else if paths.iter().any(|p| p.starts_with(prefix.as_str())) {
+            Some(Node::SysctlDir(path))
+        } else {
+            None
+        }
+    }
+    /// 目录 node 下的 name
+    fn lookup_child(&self, node: Node, name: &str) -> Option<Node> {
+        match node {
+            Node::Dir(dir) if dir.is_empty() => self.lookup_root(name),
+            Node::Dir(dir) => self.lookup_registered(&dir, name),
+            Node::SysctlDir(dir) => self.lookup_sysctl(&dir, name),
+            Node::Process(task) if name == "task" => Some(Node::Threads(task)),
+            Node::Process(task) | Node::Thread(task) => match name {
+                "fd" => Some(Node::Fds(task)),
+                "cwd" => Some(Node::Link(Link::Cwd(task))),
+                "exe" => Some(Node::Link(Link::Exe(task))),
+                _ => TaskFile::from_name(name).map(|file| Node::TaskFile(task, file)),
+            },
+            Node::Threads(process) => {
+                let tid: usize = name.parse().ok()?;
+                threads_of(&process)
+                    .into_iter()
+                    .find(|thread| thread.get_tid_num() == tid)
+                    .map(Node::Thread)
+            }
+            Node::Fds(task) => {
+                let file = task.fd_manager.lock().get_file(name.parse().ok()?).ok()?;
+                Some(Node::Link(Link::Fd(file)))
+            }
+            _ => None,
+        }
+    }
+    /// 找到 dir 目录下的 name，name 为空时找 dir 本身。路径中间的链接会被跟随，但 name 本身是链接时不跟随
+    fn lookup(&self, dir: &str, name: &str) -> Option<Node> {
+        let mut node = Node::Dir(String::new());
+        for component in dir.split('/').chain([name]) {
+            if component.is_empty() || component == "." {
+                continue;
+            }
+            if let Node::Link(link) = node {
+                node = link.follow()?;
+            }
+            node = self.lookup_child(node, component)?;
+        }
+        Some(node)
+    }
+    /// 找到 dir 目录下的 name，如果是指向 procfs 内部的链接则跟随它
+    fn lookup_follow(&self, dir: &str, name: &str) -> Option<Node> {
+        match self.lookup(dir, name)? {
+            Node::Link(link) => link.follow().or(Some(Node::Link(link))),
+            node => Some(node),
+        }
+    }
+}
+
+impl UnixFs for ProcFs {
+    /// 目录打开为 FdDir。信息文件打开时生成内容的快照，只有注册时带 writer 的文件可以写
+    fn open(self: Arc<Self>, dir: &str, name: &str, full_dir: &str, flags: OpenFlags) -> Option<Arc<dyn File>> {
+        let want_dir = flags.contains(OpenFlags::DIR) || flags.contains(OpenFlags::DSYNC);
+        match self.lookup_follow(dir, name)? {
+            // 指向 procfs 外部的链接，直接打开目标
+            Node::Link(Link::Cwd(task)) => {
+                let cwd = task.inner.lock().dir.clone();
+                open_file(cwd.as_str(), "", OpenFlags::DIR)
+            }
+            Node::Link(Link::Exe(task)) => {
+                let exe = task.exe.lock().clone();
+                open_file(ROOT_DIR, exe.trim_start_matches('/'), flags)
+            }
+            Node::Link(Link::Fd(file)) => {
+                let dir = file.get_dir().map(String::from);
+                match (dir, file.get_path()) {
+                    // 目录和 cwd 一样重新打开
+                    (Some(dir), _) => open_file(dir.as_str(), "", flags | OpenFlags::DIR),
+                    // 和 Linux 一样重新打开链接指向的文件，按这次的 flags 得到新的文件指针和读写权限
+                    (None, Some(path)) => open_file(ROOT_DIR, path.trim_start_matches('/'), flags),
+                    // pipe、socket 等没有路径，直接使用原来的文件
+                    (None, None) => Some(file),
+                }
+            }
+            Node::Link(_) => None,
+            Node::File(_) | Node::TaskFile(..) | Node::Sysctl(_) if want_dir => None,
+            Node::File(file) => {
+                if file.open_for_write(flags) {
+                    Some(file)
+                } else if flags.writable() {
+                    None
+                } else {
+                    Some(file.open())
+                }
+            }
+            Node::TaskFile(task, file) => {
+                if flags.writable() {
+                    None
+                } else {
+                    Some(snapshot(&generate(&task, file)))
+                }
+            }
+            Node::Sysctl(path) => {
+                if flags.writable() {
+                    Some(Arc::new(SysctlFile::new(path)))
+                } else {
+                    Some(snapshot((read_sysctl(path)? + "\n").as_bytes()))
+                }
+            }
+            _ => Some(Arc::new(FdDir::new(String::from(full_dir) + name))),
+        }
+    }
+    fn exists(&self, dir: &str, name: &str) -> Option<bool> {
+        Some(match self.lookup_follow(dir, name)? {
+            Node::File(_) | Node::TaskFile(..) | Node::Sysctl(_) => false,
+            Node::Link(Link::Cwd(_)) => true,
+            Node::Link(Link::Exe(_)) => false,
+            Node::Link(Link::Fd(file)) => file.get_dir().is_some(),
+            Node::Link(_) => return None,
+            _ => true,
+        })
+    }
+    fn mkdir(&self, _dir: &str, _name: &str) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EPERM)
+    }
+    fn remove(&self, _dir: &str, _name: &str) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EPERM)
+    }
+    fn rename(&self, _old_dir: &str, _old_name: &str, _new_dir: &str, _new_name: &str, _replace: bool) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EPERM)
+    }
+    fn link(&self, _old_dir: &str, _old_name: &str, _new_dir: &str, _new_name: &str) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EPERM)
+    }
+    fn symlink(&self, _target: &str, _dir: &str, _name: &str) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EPERM)
+    }
+    fn mknod(&self, _dir: &str, _name: &str, _mode: u32, _rdev: u64) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EPERM)
+    }
+    fn read_link(&self, dir: &str, name: &str) -> Result<String, ErrorNo> {
+        match self.lookup(dir, name).ok_or(ErrorNo::ENOENT)? {
+            Node::Link(link) => link.target().ok_or(ErrorNo::ENOENT),
+            _ => Err(ErrorNo::EINVAL),
+        }
+    }
+    fn chmod(&self, _dir: &str, _name: &str, _mode: u32) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EPERM)
+    }
+    fn chown(&self, _dir: &str, _name: &str, _uid: Option<u32>, _gid: Option<u32>, _follow: bool) -> Result<(), ErrorNo> {
+        Err(ErrorNo::EPERM)
+    }
+    fn list(&self, dir: &str) -> Result<Vec<(String, StMode)>, ErrorNo> {
+        let mut list = Vec::from([
+            (String::from("."), StMode::S_IFDIR),
+            (String::from(".."), StMode::S_IFDIR),
+        ]);
+        let task_dir = |list: &mut Vec<(String, StMode)>| {
+            list.extend(TASK_FILES.iter().map(|(name, _)| (String::from(*name), StMode::S_IFREG)));
+            list.push((String::from("cwd"), StMode::S_IFLNK));
+            list.push((String::from("exe"), StMode::S_IFLNK));
+            list.push((String::from("fd"), StMode::S_IFDIR));
+        };
+        match self.lookup_follow(dir, "").ok_or(ErrorNo::ENOENT)? {
+            Node::Dir(path) => {
+                if path.is_empty() {
+                    list.push((String::from("self"), StMode::S_IFLNK));
+                    list.push((String::from("thread-self"), StMode::S_IFLNK));
+                    list.push((String::from("sys"), StMode::S_IFDIR));
+                    list.extend(get_all_pids().into_iter().map(|pid| (format!("{}", pid), StMode::S_IFDIR)));
+                }
+                let entries = self.entries.lock();
+                // 直接下级的目录
+                let prefix = if path.is_empty() { String::new() } else { path.clone() + "/" };
+                list.extend(
+                    entries
+                        .keys()
+                        .filter_map(|key| key.strip_prefix(prefix.as_str()))
+                        .filter(|sub| !sub.is_empty() && !sub.contains('/'))
+                        .map(|sub| (String::from(sub), StMode::S_IFDIR)),
+                );
+                if let Some(files) = entries.get(&path) {
+                    list.extend(files.keys().map(|name| (name.clone(), StMode::S_IFREG)));
+                }
+            }
+            Node::SysctlDir(path) => list.extend(list_sysctl(&path).into_iter().map(|(name, is_dir)| {
+                (name, if is_dir { StMode::S_IFDIR } else { StMode::S_IFREG })
+            })),
+            Node::Process(_) => {
+                task_dir(&mut list);
+                list.push((String::from("task"), StMode::S_IFDIR));
+            }
+            Node::Thread(_) => task_dir(&mut list),
+            Node::Threads(process) => list.extend(
+                threads_of(&process)
+                    .into_iter()
+                    .map(|thread| (format!("{}", thread.get_tid_num()), StMode::S_IFDIR)),
+            ),
+            Node::Fds(task) => list.extend(
+                task.fd_manager
+                    .lock()
+                    .get_all_fds()
+                    .into_iter()
+                    .map(|fd| (format!("{}", fd), StMode::S_IFLNK)),
+            ),
+            _ => return Err(ErrorNo::ENOTDIR),
+        }
+        Ok(list)
+    }
+    fn fs_type(&self) -> &'static str {
+        "proc"
+    }
+    fn stat_fs(&self, stat: *mut FsStat) {
+        unsafe {
+            (*stat).f_type = PROC_SUPER_MAGIC;
+            (*stat).f_bsize = PAGE_SIZE as i64;
+            (*stat).f_blocks = 0;
+            (*stat).f_bfree = 0;
+            (*stat).f_bavail = 0;
+            (*stat).f_files = 0;
+            (*stat).f_ffree = 0;
+            (*stat).f_fsid = [0, 0];
+            (*stat).f_namelen = 255;
+            (*stat).f_frsize = PAGE_SIZE as isize;
+            (*stat).f_flags = 0;
+            (*stat).f_spare = [0; 4];
+        }
+    }
+}
+
+/// 在 /proc 的 dir 目录下注册只读的文本文件 name，每次打开时调用 generator 生成内容。
+/// dir 是相对 /proc 的路径，为空时放在 /proc 下
+pub fn add_proc_file(dir: &str, name: &str, generator: fn() -> String) {
+    PROC_FS.add_file(dir, name, ProcFile::new(generator));
+}
+
+/// 在 /proc 的 dir 目录下注册二进制文件 name。writer 不为 None 时文件可写，写入的内容交给它处理
+pub fn add_proc_bin_file(dir: &str, name: &str, generator: fn() -> Vec<u8>, writer: Option<fn(&[u8]) -> bool>) {
+    PROC_FS.add_file(dir, name, ProcFile::new_binary(generator, writer));
+}
@@ -0,0 +1,79 @@
+//! 内容动态生成的只读文件，用于 /proc 下的各种信息文件
+//!
+//! procfs 中注册的 ProcFile 本身不可读写，每次打开时会调用 generator 生成当前的内容，
+//! 放进一个新的 VirtFile 里返回。这样每个打开的文件都有自己的指针，读到的是打开时的快照。
+//!
+//! 有 writer 的文件以写方式打开时直接返回 ProcFile 本身，写入的内容交给 writer 处理，如 /proc/net/pcap 的开关
+
+use crate::file::{virt_file_stat, File, Kstat, OpenFlags, SeekFrom, StMode, VirtFile};
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+/// 生成文件内容的函数
+enum Generator {
+    /// 文本文件，大部分信息文件都是这种
+    Text(fn() -> String),
+    /// 二进制文件
+    Binary(fn() -> Vec<u8>),
+}
+
+/// 内容动态生成的文件
+pub struct ProcFile {
+    generator: Generator,
+    /// 处理写入的内容，返回是否接受。为 None 时文件只读
+    writer: Option<fn(&[u8]) -> bool>,
+}
+
+impl ProcFile {
+    /// 创建只读的文本文件，generator 用于在打开时生成文件内容
+    pub fn new(generator: fn() -> String) -> Self {
+        Self {
+            generator: Generator::Text(generator),
+            writer: None,
+        }
+    }
+    /// 创建二进制文件。writer 不为 None 时文件可写
+    pub fn new_binary(generator: fn() -> Vec<u8>, writer: Option<fn(&[u8]) -> bool>) -> Self {
+        Self {
+            generator: Generator::Binary(generator),
+            writer: writer,
+        }
+    }
+    /// 是否可以以 flags 的方式直接打开这个文件写入
+    pub fn open_for_write(&self, flags: OpenFlags) -> bool {
+        self.writer.is_some() && flags.intersects(OpenFlags::WRONLY | OpenFlags::RDWR)
+    }
+    /// 生成一份当前内容的快照
+    pub fn open(&self) -> Arc<dyn File> {
+        match self.generator {
+            Generator::Text(generator) => snapshot(generator().as_bytes()),
+            Generator::Binary(generator) => snapshot(&generator()),
+        }
+    }
+}
+
+/// 把 content 放进一个新的只读文件中，文件指针在开头
+pub fn snapshot(content: &[u8]) -> Arc<dyn File> {
+    let file = VirtFile::new(OpenFlags::RDONLY);
+    file.write(content);
+    file.seek(SeekFrom::Start(0));
+    Arc::new(file)
+}
+
+impl File for ProcFile {
+    /// 需要通过 open 获取快照后再读
+    fn read(&self, _buf: &mut [u8]) -> Option<usize> {
+        None
+    }
+    /// 交给 writer 处理，没有 writer 时不可写
+    fn write(&self, buf: &[u8]) -> Option<usize> {
+        match self.writer {
+            Some(writer) if writer(buf) => Some(buf.len()),
+            _ => None,
+        }
+    }
+    /// 文件属性。和 Linux 一样，大小显示为 0
+    fn get_stat(&self, stat: *mut Kstat) -> bool {
+        virt_file_stat(stat, StMode::S_IFREG, 0);
+        true
+    }
+}
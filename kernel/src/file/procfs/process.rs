@@ -0,0 +1,240 @@
+//! /proc/<pid> 下的信息文件
+//!
+//! 内容来自 TaskControlBlock、地址空间中的 VmArea 以及 FdManager，格式尽量和 Linux 相同，
+//! 这样 busybox 的 ps、top、pidof 等工具可以直接解析。内核里没有的信息(如缺页次数、调度优先级)填 0 或者默认值
+
+use super::super::{
+    socket::{TcpSocket, UdpSocket, UnixSocket},
+    stdio::{Stderr, Stdin, Stdout},
+    EpollFile, EventFd, File, Kstat, Pipe, SignalFd, TimerFd,
+};
+use crate::loaders::CLOCK_TICKS_PER_SEC;
+use crate::memory::PTEFlags;
+use crate::task::{get_task_from_pid, TaskControlBlock, TaskStatus};
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+
+/// 进程和线程目录下的信息文件
+#[derive(Clone, Copy)]
+pub enum TaskFile {
+    Cmdline,
+    Comm,
+    Environ,
+    Maps,
+    Stat,
+    Status,
+}
+
+/// 信息文件的名字，按字母序排列
+pub const TASK_FILES: [(&str, TaskFile); 6] = [
+    ("cmdline", TaskFile::Cmdline),
+    ("comm", TaskFile::Comm),
+    ("environ", TaskFile::Environ),
+    ("maps", TaskFile::Maps),
+    ("stat", TaskFile::Stat),
+    ("status", TaskFile::Status),
+];
+
+/// comm 的最大长度，和 Linux 的 TASK_COMM_LEN - 1 相同
+const COMM_LEN: usize = 15;
+
+impl TaskFile {
+    pub fn from_name(name: &str) -> Option<Self> {
+        TASK_FILES.iter().find(|(file_name, _)| *file_name == name).map(|(_, file)| *file)
+    }
+}
+
+/// 生成 task 的信息文件的内容
+pub fn generate(task: &Arc<TaskControlBlock>, file: TaskFile) -> Vec<u8> {
+    match file {
+        // 参数和环境变量直接从用户栈上读，和 Linux 一样能看到用户程序对它们的修改
+        TaskFile::Cmdline => task.vm.lock().read_args(),
+        TaskFile::Environ => task.vm.lock().read_envs(),
+        TaskFile::Comm => (comm(task) + "\n").into_bytes(),
+        TaskFile::Maps => maps(task).into_bytes(),
+        TaskFile::Stat => stat(task).into_bytes(),
+        TaskFile::Status => status(task).into_bytes(),
+    }
+}
+
+/// 进程中的所有线程，第一项是进程的主线程。
+///
+/// 线程是创建它的线程的子任务，所以从主线程开始，沿着 children 找 pid 相同的任务。已经退出的线程不算在内
+pub fn threads_of(process: &Arc<TaskControlBlock>) -> Vec<Arc<TaskControlBlock>> {
+    let mut threads = Vec::from([process.clone()]);
+    let mut i = 0;
+    while i < threads.len() {
+        let children = threads[i].inner.lock().children.clone();
+        threads.extend(children.into_iter().filter(|child| {
+            child.pid == process.pid && !matches!(child.get_status(), TaskStatus::Zombie | TaskStatus::Exited)
+        }));
+        i += 1;
+    }
+    threads
+}
+
+/// 进程中的线程数。task 可以是进程中的任意一个线程
+fn thread_count(task: &Arc<TaskControlBlock>) -> usize {
+    get_task_from_pid(task.pid).map_or(1, |process| threads_of(&process).len())
+}
+
+/// 程序名，即可执行文件的文件名，最长 COMM_LEN 字节
+fn comm(task: &Arc<TaskControlBlock>) -> String {
+    let exe = task.exe.lock();
+    let name = exe.rsplit('/').next().unwrap_or("");
+    let mut len = name.len().min(COMM_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    String::from(&name[..len])
+}
+
+/// 任务状态对应的字母和描述。
+///
+/// 就绪但没有在运行的任务大多是在等待事件时让出了 CPU，所以显示为 S
+fn state(task: &Arc<TaskControlBlock>) -> (char, &'static str) {
+    match task.get_status() {
+        TaskStatus::Running => ('R', "running"),
+        TaskStatus::UnInit | TaskStatus::Ready => ('S', "sleeping"),
+        TaskStatus::Dying | TaskStatus::Zombie => ('Z', "zombie"),
+        TaskStatus::Exited => ('X', "dead"),
+    }
+}
+
+/// 微秒转换成 times() 使用的时钟数
+fn us_to_clock_ticks(us: usize) -> usize {
+    us / (1_000_000 / CLOCK_TICKS_PER_SEC)
+}
+
+/// /proc/<pid>/stat，一行中用空格分隔的 52 项。各项的含义见 proc(5)，内核中没有的项填 0
+fn stat(task: &Arc<TaskControlBlock>) -> String {
+    let (utime, stime, start) = {
+        let time = task.time.lock();
+        let (utime, stime) = time.output_raw();
+        (utime, stime, time.start_time_us())
+    };
+    let (state, _) = state(task);
+    let vsize = task.vm.lock().user_size();
+    // 退出时发送给父进程的信号，即 SIGCHLD
+    let exit_signal = if task.send_sigchld_when_exit { 17 } else { 0 };
+    let exit_code = task.inner.lock().exit_code;
+    format!(
+        "{} ({}) {} {} {} {} 0 -1 0 0 0 0 0 {} {} 0 0 20 0 {} 0 {} {} 0 {} \
+        0 0 0 0 0 0 0 0 0 0 0 0 {} 0 0 0 0 0 0 0 0 0 0 0 0 0 {}\n",
+        // pid、comm、state、ppid、pgrp、session，之后是 tty_nr、tpgid、flags 和 4 项缺页次数
+        task.get_tid_num(),
+        comm(task),
+        state,
+        task.get_ppid(),
+        task.pid,
+        task.pid,
+        // utime、stime，之后是 cutime、cstime、priority、nice
+        us_to_clock_ticks(utime),
+        us_to_clock_ticks(stime),
+        // num_threads，之后是 itrealvalue
+        thread_count(task),
+        // starttime、vsize，之后是 rss
+        us_to_clock_ticks(start),
+        vsize,
+        // rsslim，之后是 startcode 到 cnswap 的 12 项
+        u64::MAX,
+        // exit_signal，之后是 processor 到 env_end 的 13 项
+        exit_signal,
+        exit_code,
+    )
+}
+
+/// /proc/<pid>/status，每行是 "名字:\t值"
+fn status(task: &Arc<TaskControlBlock>) -> String {
+    let (state, state_desc) = state(task);
+    let (umask, fd_limit) = {
+        let fd_manager = task.fd_manager.lock();
+        (fd_manager.get_umask(), fd_manager.get_limit())
+    };
+    let (vm_size, vm_data, vm_stack) = {
+        let vm = task.vm.lock();
+        let stack: usize = vm
+            .user_areas()
+            .into_iter()
+            .filter(|(_, _, _, name, _)| *name == "user_stack")
+            .map(|(start, end, ..)| end - start)
+            .sum();
+        (vm.user_size(), vm.data_size(), stack)
+    };
+    format!(
+        "Name:\t{}\nUmask:\t{:04o}\nState:\t{} ({})\nTgid:\t{}\nNgid:\t0\nPid:\t{}\nPPid:\t{}\nTracerPid:\t0\n\
+        Uid:\t0\t0\t0\t0\nGid:\t0\t0\t0\t0\nFDSize:\t{}\n\
+        VmSize:\t{:8} kB\nVmData:\t{:8} kB\nVmStk:\t{:8} kB\nThreads:\t{}\n",
+        comm(task),
+        umask,
+        state,
+        state_desc,
+        task.pid,
+        task.get_tid_num(),
+        task.get_ppid(),
+        fd_limit,
+        vm_size / 1024,
+        vm_data / 1024,
+        vm_stack / 1024,
+        thread_count(task),
+    )
+}
+
+/// /proc/<pid>/maps，每个用户地址段一行。地址段的名字转换成 Linux 中对应的路径或者 [heap]、[stack]
+fn maps(task: &Arc<TaskControlBlock>) -> String {
+    let exe = task.exe.lock().clone();
+    let mut maps = String::new();
+    for (start, end, flags, name, shared) in task.vm.lock().user_areas() {
+        let path = match name {
+            "elf_segment" => exe.as_str(),
+            "user_heap" => "[heap]",
+            "user_stack" => "[stack]",
+            _ => "",
+        };
+        let line = format!(
+            "{:08x}-{:08x} {}{}{}{} 00000000 00:00 0",
+            start,
+            end,
+            if flags.contains(PTEFlags::READ) { 'r' } else { '-' },
+            if flags.contains(PTEFlags::WRITE) { 'w' } else { '-' },
+            if flags.contains(PTEFlags::EXECUTE) { 'x' } else { '-' },
+            if shared { 's' } else { 'p' },
+        );
+        if path.is_empty() {
+            maps += &format!("{}\n", line);
+        } else {
+            // Linux 会把路径对齐到同一列
+            maps += &format!("{:<72} {}\n", line, path);
+        }
+    }
+    maps
+}
+
+/// /proc/<pid>/fd 下的符号链接指向的路径。
+///
+/// 文件系统中的文件和目录记录了打开时的路径。其他文件没有路径，和 Linux 一样按类型显示，如 "pipe:[ino]"
+pub fn fd_target(file: &Arc<dyn File>) -> String {
+    if let Some(path) = file.get_path() {
+        return path;
+    }
+    let any = file.as_ref().as_any();
+    if any.is::<Stdin>() || any.is::<Stdout>() || any.is::<Stderr>() {
+        return String::from("/dev/console");
+    }
+    let mut stat = Kstat::default();
+    let ino = if file.get_stat(&mut stat) { stat.st_ino } else { 0 };
+    if any.is::<Pipe>() {
+        format!("pipe:[{}]", ino)
+    } else if any.is::<TcpSocket>() || any.is::<UdpSocket>() || any.is::<UnixSocket>() {
+        format!("socket:[{}]", ino)
+    } else if any.is::<EventFd>() {
+        String::from("anon_inode:[eventfd]")
+    } else if any.is::<EpollFile>() {
+        String::from("anon_inode:[eventpoll]")
+    } else if any.is::<TimerFd>() {
+        String::from("anon_inode:[timerfd]")
+    } else if any.is::<SignalFd>() {
+        String::from("anon_inode:[signalfd]")
+    } else {
+        String::from("anon_inode:[file]")
+    }
+}
@@ -0,0 +1,69 @@
+//! /proc/sys 下的内核参数
+//!
+//! 目录结构由 sysctl 中注册的参数的路径决定。以只读方式打开时得到当前值的快照，
+//! 以写方式打开时得到 SysctlFile，读到的是读取时的值，写入的内容交给 sysctl 解析
+
+use super::super::{virt_file_stat, File, Kstat, StMode};
+use crate::sysctl::{read_sysctl, sysctl_paths, write_sysctl};
+use alloc::{string::String, vec::Vec};
+use lock::Mutex;
+
+/// 以写方式打开的参数文件
+pub struct SysctlFile {
+    /// 参数的路径，如 "fs/pipe_size_limit"
+    path: &'static str,
+    /// 读取的位置
+    pos: Mutex<usize>,
+}
+
+impl SysctlFile {
+    pub fn new(path: &'static str) -> Self {
+        Self {
+            path: path,
+            pos: Mutex::new(0),
+        }
+    }
+}
+
+/// /proc/sys 下 dir 目录中的参数和子目录，第二项表示是否为目录。dir 是相对 /proc/sys 的路径
+pub fn list_sysctl(dir: &str) -> Vec<(String, bool)> {
+    let prefix = if dir.is_empty() { String::new() } else { String::from(dir) + "/" };
+    let mut list: Vec<(String, bool)> = Vec::new();
+    for path in sysctl_paths() {
+        if let Some(sub) = path.strip_prefix(prefix.as_str()) {
+            let entry = match sub.split_once('/') {
+                Some((sub_dir, _)) => (String::from(sub_dir), true),
+                None => (String::from(sub), false),
+            };
+            // 路径按字母序排列，同一个子目录下的参数是相邻的
+            if list.last() != Some(&entry) {
+                list.push(entry);
+            }
+        }
+    }
+    list
+}
+
+impl File for SysctlFile {
+    /// 读取参数的当前值，格式和只读打开时一样，如 "4096\n"
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let value = read_sysctl(self.path)? + "\n";
+        let mut pos = self.pos.lock();
+        let len = value.len().saturating_sub(*pos).min(buf.len());
+        if len > 0 {
+            buf[..len].copy_from_slice(&value.as_bytes()[*pos..*pos + len]);
+        }
+        *pos += len;
+        Some(len)
+    }
+    /// 一次写入整个值，如 echo 写入的 "4096\n"。值不合法时写入失败
+    fn write(&self, buf: &[u8]) -> Option<usize> {
+        let value = core::str::from_utf8(buf).ok()?.trim();
+        write_sysctl(self.path, value).ok()?;
+        Some(buf.len())
+    }
+    fn get_stat(&self, stat: *mut Kstat) -> bool {
+        virt_file_stat(stat, StMode::S_IFREG, 0);
+        true
+    }
+}
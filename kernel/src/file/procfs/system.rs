@@ -0,0 +1,158 @@
+//! /proc 下描述整个系统的文件
+//!
+//! 内容在每次打开时从页帧分配器、内核堆、调度统计和设备树中生成，格式和 Linux 相同，
+//! 这样 busybox 的 free、uptime、top、mount 等工具可以直接解析。内核中没有的项(如 swap、缓存)填 0
+
+use super::super::{device::mount_info, user_path};
+use super::add_proc_file;
+use crate::drivers::cpu_nodes;
+use crate::loaders::CLOCK_TICKS_PER_SEC;
+use crate::memory::mem_stat;
+use crate::syscall::{UTS_RELEASE, UTS_SYSNAME, UTS_VERSION};
+use crate::task::{get_all_pids, get_process_count, hart_stats, load_avg, nr_running, FIXED_1, FSHIFT};
+use crate::timer::get_time_us;
+use alloc::{format, string::String};
+
+/// 注册 /proc 下的系统信息文件，在 fs_init 中调用
+pub fn add_system_proc_files() {
+    add_proc_file("", "cpuinfo", cpuinfo);
+    add_proc_file("", "filesystems", filesystems);
+    add_proc_file("", "loadavg", loadavg);
+    add_proc_file("", "meminfo", meminfo);
+    add_proc_file("", "mounts", mounts);
+    add_proc_file("", "stat", stat);
+    add_proc_file("", "uptime", uptime);
+    add_proc_file("", "version", version);
+}
+
+/// 微秒转换成 times() 使用的时钟数
+fn us_to_clock_ticks(us: usize) -> usize {
+    us / (1_000_000 / CLOCK_TICKS_PER_SEC)
+}
+
+/// 把 FSHIFT 位小数的定点数格式化为两位小数
+fn fixed_to_string(x: usize) -> String {
+    format!("{}.{:02}", x >> FSHIFT, ((x & (FIXED_1 - 1)) * 100) >> FSHIFT)
+}
+
+/// /proc/meminfo，单位是 kB。内核堆的分配算作不可回收的 Slab
+fn meminfo() -> String {
+    let stat = mem_stat();
+    format!(
+        "MemTotal:       {:8} kB\nMemFree:        {:8} kB\nMemAvailable:   {:8} kB\n\
+        Buffers:        {:8} kB\nCached:         {:8} kB\nSwapCached:     {:8} kB\n\
+        SwapTotal:      {:8} kB\nSwapFree:       {:8} kB\nShmem:          {:8} kB\n\
+        Slab:           {:8} kB\nSReclaimable:   {:8} kB\nSUnreclaim:     {:8} kB\n",
+        stat.total / 1024,
+        stat.free / 1024,
+        stat.free / 1024,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        stat.heap_used / 1024,
+        0,
+        stat.heap_used / 1024,
+    )
+}
+
+/// /proc/stat。每个核的时间单位是 USER_HZ(即 CLOCK_TICKS_PER_SEC)，第一行是所有核的和。
+/// 核没有运行任务的时间都算作 idle
+fn stat() -> String {
+    let uptime = get_time_us();
+    let harts = hart_stats();
+    let line = |name: String, user: usize, system: usize, idle: usize| {
+        format!(
+            "{} {} 0 {} {} 0 0 0 0 0 0\n",
+            name,
+            us_to_clock_ticks(user),
+            us_to_clock_ticks(system),
+            us_to_clock_ticks(idle)
+        )
+    };
+    let (mut user, mut system, mut idle, mut switches) = (0, 0, 0, 0);
+    let mut per_hart = String::new();
+    for (cpu_id, hart) in harts.iter().enumerate() {
+        let hart_idle = uptime.saturating_sub(hart.user_us + hart.system_us);
+        per_hart += &line(format!("cpu{}", cpu_id), hart.user_us, hart.system_us, hart_idle);
+        user += hart.user_us;
+        system += hart.system_us;
+        idle += hart_idle;
+        switches += hart.switches;
+    }
+    format!(
+        "{}{}intr 0\nctxt {}\nbtime 0\nprocesses {}\nprocs_running {}\nprocs_blocked 0\n",
+        line(String::from("cpu "), user, system, idle),
+        per_hart,
+        switches,
+        get_process_count(),
+        nr_running(),
+    )
+}
+
+/// /proc/loadavg，依次是 1、5、15 分钟的平均负载，正在运行的任务数/总进程数，和最大的 pid
+fn loadavg() -> String {
+    let load = load_avg();
+    format!(
+        "{} {} {} {}/{} {}\n",
+        fixed_to_string(load[0]),
+        fixed_to_string(load[1]),
+        fixed_to_string(load[2]),
+        nr_running(),
+        get_process_count(),
+        get_all_pids().last().copied().unwrap_or(0),
+    )
+}
+
+/// /proc/uptime，启动以来的秒数和所有核空闲的秒数之和
+fn uptime() -> String {
+    let uptime = get_time_us();
+    let idle: usize = hart_stats()
+        .iter()
+        .map(|hart| uptime.saturating_sub(hart.user_us + hart.system_us))
+        .sum();
+    format!(
+        "{}.{:02} {}.{:02}\n",
+        uptime / 1_000_000,
+        uptime % 1_000_000 / 10_000,
+        idle / 1_000_000,
+        idle % 1_000_000 / 10_000
+    )
+}
+
+/// /proc/cpuinfo，每个核一段，信息来自设备树的 /cpus 节点
+fn cpuinfo() -> String {
+    let mut info = String::new();
+    for (processor, cpu) in cpu_nodes().iter().enumerate() {
+        info += &format!(
+            "processor\t: {}\nhart\t\t: {}\nisa\t\t: {}\nmmu\t\t: {}\nuarch\t\t: {}\n\n",
+            processor, cpu.hart, cpu.isa, cpu.mmu, cpu.uarch
+        );
+    }
+    info
+}
+
+/// /proc/version，和 uname 返回的信息一致
+fn version() -> String {
+    format!("{} version {} #{}\n", UTS_SYSNAME, UTS_RELEASE, UTS_VERSION)
+}
+
+/// /proc/filesystems，内核支持的文件系统类型。不需要块设备的类型前面标 nodev。
+///
+/// overlay 只用于叠加在根文件系统上，不能再用 mount 挂载
+fn filesystems() -> String {
+    String::from("\text2\n\tvfat\nnodev\t9p\nnodev\tproc\nnodev\ttmpfs\nnodev\toverlay\n")
+}
+
+/// /proc/mounts，每行是 "设备 挂载点 类型 选项 0 0"。内核中的 "./dir/" 形式的路径转换成 "/dir"
+fn mounts() -> String {
+    let mut mounts = String::new();
+    for (device, dir, fs_type) in mount_info() {
+        let dir = user_path(&dir);
+        let device = device.strip_prefix('.').filter(|path| path.starts_with('/')).unwrap_or(&device);
+        mounts += &format!("{} {} {} rw 0 0\n", device, dir, fs_type);
+    }
+    mounts
+}
@@ -0,0 +1,171 @@
+//! signalfd，通过文件描述符接收信号
+//!
+//! 读取时从当前线程已收到的信号中取出在 mask 中的信号，每个信号生成一个 signalfd_siginfo 结构。
+//! 被取出的信号不会再按 SigAction 处理，所以用户通常会先用 sigprocmask 屏蔽这些信号
+
+use super::{File, OpenFlags};
+use crate::{
+    signal::{Bitset, SignalNo},
+    task::{get_current_task, suspend_current_task_interruptible},
+};
+use core::mem::size_of;
+use lock::Mutex;
+
+/// read 读出的信号信息，即 struct signalfd_siginfo
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SignalFdSigInfo {
+    pub ssi_signo: u32,
+    pub ssi_errno: i32,
+    pub ssi_code: i32,
+    pub ssi_pid: u32,
+    pub ssi_uid: u32,
+    pub ssi_fd: i32,
+    pub ssi_tid: u32,
+    pub ssi_band: u32,
+    pub ssi_overrun: u32,
+    pub ssi_trapno: u32,
+    pub ssi_status: i32,
+    pub ssi_int: i32,
+    pub ssi_ptr: u64,
+    pub ssi_utime: u64,
+    pub ssi_stime: u64,
+    pub ssi_addr: u64,
+    /// 补齐到 128 字节
+    _pad: [u8; 48],
+}
+
+impl SignalFdSigInfo {
+    /// 目前没有记录信号的来源，所以只填写信号编号。ssi_code 为 0 即 SI_USER
+    fn new(signum: usize) -> Self {
+        Self {
+            ssi_signo: signum as u32,
+            ssi_errno: 0,
+            ssi_code: 0,
+            ssi_pid: 0,
+            ssi_uid: 0,
+            ssi_fd: 0,
+            ssi_tid: 0,
+            ssi_band: 0,
+            ssi_overrun: 0,
+            ssi_trapno: 0,
+            ssi_status: 0,
+            ssi_int: 0,
+            ssi_ptr: 0,
+            ssi_utime: 0,
+            ssi_stime: 0,
+            ssi_addr: 0,
+            _pad: [0; 48],
+        }
+    }
+}
+
+/// signalfd 文件
+pub struct SignalFd {
+    inner: Mutex<SignalFdInner>,
+}
+
+struct SignalFdInner {
+    /// 要接收的信号集合
+    mask: Bitset,
+    /// 文件状态。SFD_NONBLOCK 和 SFD_CLOEXEC 与 O_NONBLOCK 和 O_CLOEXEC 的值相同
+    flags: OpenFlags,
+}
+
+impl SignalFd {
+    /// 创建一个接收 mask 中信号的 signalfd
+    pub fn new(mask: usize, flags: u32) -> Self {
+        Self {
+            inner: Mutex::new(SignalFdInner {
+                mask: Self::valid_mask(mask),
+                flags: OpenFlags::RDONLY
+                    | (OpenFlags::from_bits_truncate(flags) & (OpenFlags::NON_BLOCK | OpenFlags::CLOEXEC)),
+            }),
+        }
+    }
+    /// 修改要接收的信号集合
+    pub fn set_mask(&self, mask: usize) {
+        self.inner.lock().mask = Self::valid_mask(mask);
+    }
+    /// SIGKILL 和 SIGSTOP 不能通过 signalfd 接收，需要从 mask 中去掉
+    fn valid_mask(mask: usize) -> Bitset {
+        let mut mask = Bitset::new(mask);
+        mask.remove_bit(SignalNo::SIGKILL as usize - 1);
+        mask.remove_bit(SignalNo::SIGSTOP as usize - 1);
+        mask
+    }
+}
+
+impl File for SignalFd {
+    /// 读出尽可能多的信号，每个信号占一个 SignalFdSigInfo。没有信号时阻塞
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let max_count = buf.len() / size_of::<SignalFdSigInfo>();
+        if max_count == 0 {
+            return None;
+        }
+        let task = get_current_task().unwrap();
+        loop {
+            let inner = self.inner.lock();
+            let mut receivers = task.signal_receivers.lock();
+            let mut count = 0;
+            while count < max_count {
+                match receivers.take_signal_in(inner.mask) {
+                    Some(signum) => {
+                        let info = SignalFdSigInfo::new(signum);
+                        let start = count * size_of::<SignalFdSigInfo>();
+                        // 用户的 buf 不一定按结构对齐
+                        unsafe {
+                            (buf[start..].as_mut_ptr() as *mut SignalFdSigInfo).write_unaligned(info);
+                        }
+                        count += 1;
+                    }
+                    None => break,
+                }
+            }
+            if count > 0 {
+                return Some(count * size_of::<SignalFdSigInfo>());
+            }
+            if inner.flags.contains(OpenFlags::NON_BLOCK) {
+                return None;
+            }
+            drop(receivers);
+            drop(inner);
+            if !suspend_current_task_interruptible() {
+                return None;
+            }
+        }
+    }
+    /// 不可写
+    fn write(&self, _buf: &[u8]) -> Option<usize> {
+        None
+    }
+    /// 当前线程收到了 mask 中的信号时可读
+    fn ready_to_read(&self) -> bool {
+        let mask = self.inner.lock().mask;
+        get_current_task().unwrap().signal_receivers.lock().has_signal_in(mask)
+    }
+    /// 不可写
+    fn ready_to_write(&self) -> bool {
+        false
+    }
+    /// 不需要等待队列：send_signal 会唤醒收到信号的线程，而 signalfd 读的正是当前线程的信号
+    fn register_waiter(&self, _tid: usize) -> bool {
+        true
+    }
+    /// 获取文件状态信息
+    fn get_status(&self) -> OpenFlags {
+        self.inner.lock().flags
+    }
+    /// 设置文件状态信息，只能修改 NON_BLOCK 和 CLOEXEC
+    fn set_status(&self, flags: OpenFlags) -> bool {
+        let fl = &mut self.inner.lock().flags;
+        fl.set(OpenFlags::NON_BLOCK, flags.contains(OpenFlags::NON_BLOCK));
+        fl.set(OpenFlags::CLOEXEC, flags.contains(OpenFlags::CLOEXEC));
+        true
+    }
+    /// 设置 CLOEXEC 位
+    fn set_close_on_exec(&self, is_set: bool) -> bool {
+        self.inner.lock().flags.set(OpenFlags::CLOEXEC, is_set);
+        true
+    }
+}
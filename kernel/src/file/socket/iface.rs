@@ -0,0 +1,409 @@
+//! 网卡上的 TCP/IP 协议栈
+//!
+//! 协议栈由 smoltcp 实现，包括 ARP、IPv4、ICMP(回应 ping)、UDP、TCP 和 DHCP 客户端。
+//! 网卡只有一个，即 eth0。启动后默认用 DHCP 获取地址，用 ioctl 手动设置地址后就不再使用 DHCP。
+//!
+//! 协议栈在 socket 的操作中通过 with_iface 轮询，网卡收到帧时也会在中断中轮询一次。
+//! TCP 重传、延迟 ACK、保活和 DHCP 重试等定时的工作由调度循环通过 poll_if_due 在到期时轮询。
+//! 轮询后如果有 socket 的状态可能变化了，就唤醒 NET_WAITERS 中所有等待网卡上的 socket 的线程，
+//! 由它们各自检查自己的 socket
+
+use super::pcap::capture_frame;
+use super::{InetIp, SocketOptions};
+use crate::drivers::{set_net_irq_handler, NetDevice, NET_DEVICE};
+use crate::file::WaitQueue;
+use crate::syscall::ErrorNo;
+use crate::timer::get_time_us;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use lock::Mutex;
+use smoltcp::iface::{Interface, InterfaceBuilder, NeighborCache, Routes, SocketHandle};
+use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
+use smoltcp::socket::{
+    Dhcpv4Event, Dhcpv4Socket, TcpSocket as SmolTcpSocket, TcpSocketBuffer, TcpState, UdpPacketMetadata,
+    UdpSocket as SmolUdpSocket, UdpSocketBuffer,
+};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address, Ipv4Cidr};
+
+/// 以太网帧的最大长度
+const MAX_FRAME_SIZE: usize = 1514;
+/// 网卡上每个 TCP socket 的收发缓冲区大小的上限。SO_RCVBUF 和 SO_SNDBUF 更小时使用它们
+const TCP_BUFFER_SIZE: usize = 0x10000;
+/// 网卡上每个 UDP socket 的收发缓冲区大小，以及最多缓存的数据报个数
+const UDP_BUFFER_SIZE: usize = 0x10000;
+const UDP_PACKET_COUNT: usize = 64;
+/// TCP 连接在这么长时间内没有回应就断开，包括 connect 时等待握手
+const TCP_TIMEOUT_SECS: u64 = 60;
+/// 设置了 SO_KEEPALIVE 的连接空闲时发送保活报文的间隔，即 Linux 的 tcp_keepalive_intvl
+const TCP_KEEPALIVE_SECS: u64 = 75;
+
+/// 把 NetDevice 包装成 smoltcp 使用的设备
+pub struct NetDeviceWrapper(Arc<dyn NetDevice>);
+
+pub struct NetRxToken(Vec<u8>);
+pub struct NetTxToken(Arc<dyn NetDevice>);
+
+impl<'a> Device<'a> for NetDeviceWrapper {
+    type RxToken = NetRxToken;
+    type TxToken = NetTxToken;
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let mut buf = vec![0u8; MAX_FRAME_SIZE];
+        let len = self.0.recv(&mut buf)?;
+        buf.truncate(len);
+        capture_frame(&buf);
+        Some((NetRxToken(buf), NetTxToken(self.0.clone())))
+    }
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        if self.0.can_send() {
+            Some(NetTxToken(self.0.clone()))
+        } else {
+            None
+        }
+    }
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ethernet;
+        caps.max_transmission_unit = MAX_FRAME_SIZE;
+        caps.max_burst_size = Some(1);
+        caps
+    }
+}
+
+impl RxToken for NetRxToken {
+    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        f(&mut self.0)
+    }
+}
+
+impl TxToken for NetTxToken {
+    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        let mut buf = vec![0u8; len];
+        let ret = f(&mut buf)?;
+        capture_frame(&buf);
+        if self.0.send(&buf) {
+            Ok(ret)
+        } else {
+            Err(smoltcp::Error::Exhausted)
+        }
+    }
+}
+
+/// 网卡的配置
+pub struct IfConfig {
+    /// MAC 地址
+    pub mac: [u8; 6],
+    /// ip 地址，主机字节序
+    pub addr: u32,
+    /// 子网掩码的长度
+    pub prefix_len: u8,
+    /// 网卡是否启用
+    pub up: bool,
+}
+
+/// 网卡和上面的协议栈
+pub struct NetIface {
+    iface: Interface<'static, NetDeviceWrapper>,
+    /// DHCP 客户端。手动设置地址后为 None
+    dhcp: Option<SocketHandle>,
+    /// 已经关闭，等待挥手完成后释放的 TCP socket
+    closing: Vec<SocketHandle>,
+    /// 网卡是否启用。停用时不收发数据
+    up: bool,
+}
+
+lazy_static::lazy_static! {
+    /// 网卡上的协议栈。没有网卡时为 None
+    static ref NET_IFACE: Option<Mutex<NetIface>> = NET_DEVICE.clone().map(|dev| Mutex::new(NetIface::new(dev)));
+    /// 等待网卡上的 socket 的线程
+    pub static ref NET_WAITERS: WaitQueue = WaitQueue::new();
+}
+
+/// 初始化协议栈，并在网卡中断时轮询它。初始化时先轮询一次，让 DHCP 在启动时就开始
+pub fn init() {
+    if NET_IFACE.is_some() {
+        set_net_irq_handler(poll_on_irq);
+        poll_on_irq();
+    }
+}
+
+/// 轮询协议栈之后执行 f，然后再轮询一次以发出 f 产生的数据。没有网卡时返回 None
+pub fn with_iface<R>(f: impl FnOnce(&mut NetIface) -> R) -> Option<R> {
+    NET_IFACE.as_ref().map(|iface| {
+        let mut iface = iface.lock();
+        let mut changed = iface.poll();
+        let ret = f(&mut iface);
+        changed |= iface.poll();
+        // 唤醒时要拿 WAITING_BOARD 的锁，所以先放掉协议栈的锁
+        drop(iface);
+        if changed {
+            NET_WAITERS.wake_all();
+        }
+        ret
+    })
+}
+
+/// 网卡中断时轮询协议栈。协议栈正在被使用时直接返回，使用者放锁前还会再轮询一次
+fn poll_on_irq() {
+    if let Some(mut iface) = NET_IFACE.as_ref().and_then(|iface| iface.try_lock()) {
+        let changed = iface.poll();
+        drop(iface);
+        if changed {
+            NET_WAITERS.wake_all();
+        }
+    }
+}
+
+/// 协议栈中有定时的工作到期时轮询它。在调度循环中调用，
+/// 这样即使所有使用网卡的线程都在睡眠，重传、超时和 DHCP 也能按时进行
+pub fn poll_if_due() {
+    if let Some(mut iface) = NET_IFACE.as_ref().and_then(|iface| iface.try_lock()) {
+        if !iface.is_poll_due() {
+            return;
+        }
+        let changed = iface.poll();
+        drop(iface);
+        if changed {
+            NET_WAITERS.wake_all();
+        }
+    }
+}
+
+/// 是否是网卡的地址
+pub fn is_iface_addr(ip: u32) -> bool {
+    with_iface(|iface| iface.addr().0 == ip && ip != 0).unwrap_or(false)
+}
+
+/// 是否是本机的地址，即回环地址或网卡的地址
+pub fn is_local_addr(ip: InetIp) -> bool {
+    ip.is_loopback() || ip.v4().map_or(false, is_iface_addr)
+}
+
+/// smoltcp 的地址转换为主机字节序的 ip 和端口
+pub fn endpoint_to_addr(endpoint: IpEndpoint) -> (u32, u16) {
+    match endpoint.addr {
+        IpAddress::Ipv4(ip) => (u32::from_be_bytes(ip.0), endpoint.port),
+        _ => (0, endpoint.port),
+    }
+}
+
+/// 主机字节序的 ip 和端口转换为 smoltcp 的地址
+pub fn addr_to_endpoint((ip, port): (u32, u16)) -> IpEndpoint {
+    IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::from_bytes(&ip.to_be_bytes())), port)
+}
+
+impl NetIface {
+    fn new(dev: Arc<dyn NetDevice>) -> Self {
+        let mac = dev.mac();
+        let mut iface = InterfaceBuilder::new(NetDeviceWrapper(dev), vec![])
+            .hardware_addr(EthernetAddress(mac).into())
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs(vec![IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)])
+            .routes(Routes::new(BTreeMap::new()))
+            .finalize();
+        let dhcp = iface.add_socket(Dhcpv4Socket::new());
+        Self {
+            iface: iface,
+            dhcp: Some(dhcp),
+            closing: Vec::new(),
+            up: true,
+        }
+    }
+    /// 收发数据，处理 DHCP 的结果，释放已经关闭的 TCP socket。返回是否有 socket 的状态可能变化了
+    fn poll(&mut self) -> bool {
+        if !self.up {
+            return false;
+        }
+        let now = Instant::from_micros(get_time_us() as i64);
+        let changed = match self.iface.poll(now) {
+            Ok(changed) => changed,
+            Err(e) => {
+                debug!("net iface poll error: {:?}", e);
+                false
+            }
+        };
+        if let Some(dhcp) = self.dhcp {
+            match self.iface.get_socket::<Dhcpv4Socket>(dhcp).poll() {
+                Some(Dhcpv4Event::Configured(config)) => {
+                    info!("DHCP got address {}", config.address);
+                    self.set_cidr(config.address);
+                    self.set_gateway(config.router);
+                }
+                Some(Dhcpv4Event::Deconfigured) => {
+                    self.set_cidr(Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0));
+                    self.set_gateway(None);
+                }
+                None => {}
+            }
+        }
+        let iface = &mut self.iface;
+        self.closing.retain(|&handle| {
+            if iface.get_socket::<SmolTcpSocket>(handle).state() == TcpState::Closed {
+                iface.remove_socket(handle);
+                false
+            } else {
+                true
+            }
+        });
+        changed
+    }
+    /// 是否到了需要轮询的时间
+    fn is_poll_due(&mut self) -> bool {
+        let now = Instant::from_micros(get_time_us() as i64);
+        self.up && self.iface.poll_delay(now).map_or(false, |delay| delay.total_micros() == 0)
+    }
+    /// 获取网卡的 ip 地址和子网掩码的长度
+    fn addr(&self) -> (u32, u8) {
+        self.iface
+            .ip_addrs()
+            .iter()
+            .find_map(|cidr| match cidr {
+                IpCidr::Ipv4(cidr) => Some((u32::from_be_bytes(cidr.address().0), cidr.prefix_len())),
+                _ => None,
+            })
+            .unwrap_or((0, 0))
+    }
+    fn set_cidr(&mut self, cidr: Ipv4Cidr) {
+        self.iface.update_ip_addrs(|addrs| {
+            if let Some(addr) = addrs.iter_mut().next() {
+                *addr = IpCidr::Ipv4(cidr);
+            }
+        });
+    }
+    fn set_gateway(&mut self, gateway: Option<Ipv4Address>) {
+        match gateway {
+            Some(gateway) => {
+                if self.iface.routes_mut().add_default_ipv4_route(gateway).is_err() {
+                    warn!("failed to set default gateway {}", gateway);
+                }
+            }
+            None => {
+                self.iface.routes_mut().remove_default_ipv4_route();
+            }
+        }
+    }
+    /// 停止 DHCP，改为手动设置地址
+    fn stop_dhcp(&mut self) {
+        if let Some(dhcp) = self.dhcp.take() {
+            self.iface.remove_socket(dhcp);
+        }
+    }
+    /// 获取网卡的配置
+    pub fn config(&self) -> IfConfig {
+        let (addr, prefix_len) = self.addr();
+        IfConfig {
+            mac: self.iface.hardware_addr().as_bytes().try_into().unwrap_or([0; 6]),
+            addr: addr,
+            prefix_len: prefix_len,
+            up: self.up,
+        }
+    }
+    /// 手动设置 ip 地址，保留原来的子网掩码
+    pub fn set_addr(&mut self, addr: u32) {
+        self.stop_dhcp();
+        let prefix_len = match self.addr() {
+            (_, 0) => 24,
+            (_, len) => len,
+        };
+        self.set_cidr(Ipv4Cidr::new(Ipv4Address::from_bytes(&addr.to_be_bytes()), prefix_len));
+    }
+    /// 手动设置子网掩码的长度，不能超过 32
+    pub fn set_prefix_len(&mut self, prefix_len: u8) {
+        self.stop_dhcp();
+        let addr = self.addr().0;
+        self.set_cidr(Ipv4Cidr::new(Ipv4Address::from_bytes(&addr.to_be_bytes()), prefix_len));
+    }
+    /// 启用或停用网卡
+    pub fn set_up(&mut self, up: bool) {
+        self.up = up;
+    }
+    /// 手动设置默认网关，gateway 为 None 时删除
+    pub fn set_default_gateway(&mut self, gateway: Option<u32>) {
+        self.stop_dhcp();
+        self.set_gateway(gateway.map(|ip| Ipv4Address::from_bytes(&ip.to_be_bytes())));
+    }
+    /// 按 socket 的选项新建一个 TCP socket
+    fn add_tcp_socket(&mut self, options: &SocketOptions) -> SocketHandle {
+        let socket = SmolTcpSocket::new(
+            TcpSocketBuffer::new(vec![0; options.rcvbuf.min(TCP_BUFFER_SIZE)]),
+            TcpSocketBuffer::new(vec![0; options.sndbuf.min(TCP_BUFFER_SIZE)]),
+        );
+        let handle = self.iface.add_socket(socket);
+        self.set_tcp_options(handle, options);
+        handle
+    }
+    /// 设置 TCP socket 的保活和 Nagle 算法。buffer 的大小只在新建时确定
+    pub fn set_tcp_options(&mut self, handle: SocketHandle, options: &SocketOptions) {
+        let socket = self.tcp(handle);
+        socket.set_keep_alive(options.keepalive.then(|| Duration::from_secs(TCP_KEEPALIVE_SECS)));
+        socket.set_nagle_enabled(!options.nodelay);
+    }
+    /// 新建一个在 port 上监听的 TCP socket，建立的连接使用 options 中的选项
+    pub fn listen_tcp(&mut self, port: u16, options: &SocketOptions) -> Result<SocketHandle, ErrorNo> {
+        let handle = self.add_tcp_socket(options);
+        if self.tcp(handle).listen(port).is_err() {
+            self.iface.remove_socket(handle);
+            return Err(ErrorNo::EADDRINUSE);
+        }
+        Ok(handle)
+    }
+    /// 新建一个 TCP socket，从 local_port 连接到 remote
+    pub fn connect_tcp(
+        &mut self,
+        remote: (u32, u16),
+        local_port: u16,
+        options: &SocketOptions,
+    ) -> Result<SocketHandle, ErrorNo> {
+        let handle = self.add_tcp_socket(options);
+        let (socket, cx) = self.iface.get_socket_and_context::<SmolTcpSocket>(handle);
+        socket.set_timeout(Some(Duration::from_secs(TCP_TIMEOUT_SECS)));
+        if socket.connect(cx, addr_to_endpoint(remote), local_port).is_err() {
+            self.iface.remove_socket(handle);
+            return Err(ErrorNo::ENETUNREACH);
+        }
+        Ok(handle)
+    }
+    /// 获取 TCP socket
+    pub fn tcp(&mut self, handle: SocketHandle) -> &mut SmolTcpSocket<'static> {
+        self.iface.get_socket::<SmolTcpSocket>(handle)
+    }
+    /// 关闭 TCP socket。abort 为 true 或者还有没读的数据时重置连接，否则发送 FIN，等挥手完成后再释放
+    pub fn release_tcp(&mut self, handle: SocketHandle, abort: bool) {
+        let socket = self.tcp(handle);
+        if abort || socket.can_recv() || socket.is_listening() {
+            socket.abort();
+        } else {
+            socket.close();
+        }
+        self.closing.push(handle);
+    }
+    /// 新建一个绑定在 port 上的 UDP socket
+    pub fn bind_udp(&mut self, port: u16) -> Result<SocketHandle, ErrorNo> {
+        let socket = SmolUdpSocket::new(
+            UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; UDP_PACKET_COUNT], vec![0; UDP_BUFFER_SIZE]),
+            UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; UDP_PACKET_COUNT], vec![0; UDP_BUFFER_SIZE]),
+        );
+        let handle = self.iface.add_socket(socket);
+        if self.udp(handle).bind(port).is_err() {
+            self.iface.remove_socket(handle);
+            return Err(ErrorNo::EADDRINUSE);
+        }
+        Ok(handle)
+    }
+    /// 获取 UDP socket
+    pub fn udp(&mut self, handle: SocketHandle) -> &mut SmolUdpSocket<'static> {
+        self.iface.get_socket::<SmolUdpSocket>(handle)
+    }
+    /// 释放 UDP socket
+    pub fn release_udp(&mut self, handle: SocketHandle) {
+        self.iface.remove_socket(handle);
+    }
+}
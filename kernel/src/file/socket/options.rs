@@ -0,0 +1,92 @@
+//! socket 选项。三种 socket 都用 SocketOptions 保存 setsockopt 设置的通用选项，
+//! 各自在收发和关闭时按这些选项处理
+
+use crate::file::{wait_for, File};
+use crate::syscall::ErrorNo;
+use crate::sysctl::Sysctl;
+use crate::timer::get_time_us;
+
+/// SO_RCVBUF 和 SO_SNDBUF 的下限
+const SOCKET_BUFFER_SIZE_MIN: usize = 0x800;
+/// socket 默认的 buffer 大小，也是 SO_RCVBUF 和 SO_SNDBUF 的上限。可以通过 /proc/sys/net/socket_buffer_size_limit 修改
+pub static SOCKET_BUFFER_SIZE_LIMIT: Sysctl<usize> = Sysctl::new(0x200000, SOCKET_BUFFER_SIZE_MIN, 0x1000_0000); // 默认 2 MB，最大 256 MB
+
+/// socket 的通用选项
+#[derive(Clone, Copy)]
+pub struct SocketOptions {
+    /// 接收 buffer 的大小，即 SO_RCVBUF
+    pub rcvbuf: usize,
+    /// 发送 buffer 的大小，即 SO_SNDBUF
+    pub sndbuf: usize,
+    /// 接收时最多阻塞的时间(us)，即 SO_RCVTIMEO。为 0 时一直等待
+    pub rcvtimeo: usize,
+    /// 发送时最多阻塞的时间(us)，即 SO_SNDTIMEO。为 0 时一直等待
+    pub sndtimeo: usize,
+    /// 是否定期发送保活报文，即 SO_KEEPALIVE
+    pub keepalive: bool,
+    /// 关闭时等待数据发完的秒数，即 SO_LINGER。为 Some(0) 时关闭会直接重置连接，
+    /// 不为 0 时 close 最多阻塞这么长时间，等待发送的数据被对方收下
+    pub linger: Option<u32>,
+    /// 是否关闭 Nagle 算法，即 TCP_NODELAY
+    pub nodelay: bool,
+}
+
+impl SocketOptions {
+    pub fn new() -> Self {
+        let buffer_size = SOCKET_BUFFER_SIZE_LIMIT.get();
+        Self {
+            rcvbuf: buffer_size,
+            sndbuf: buffer_size,
+            rcvtimeo: 0,
+            sndtimeo: 0,
+            keepalive: false,
+            linger: None,
+            nodelay: false,
+        }
+    }
+    /// 用户设置 buffer 大小时实际使用的值。和 Linux 一样取用户给出的两倍，再限制在允许的范围内
+    pub fn buffer_size(size: usize) -> usize {
+        size.saturating_mul(2).clamp(SOCKET_BUFFER_SIZE_MIN, SOCKET_BUFFER_SIZE_LIMIT.get())
+    }
+    /// 从现在开始接收时，阻塞的截止时刻
+    pub fn recv_deadline(&self) -> Option<usize> {
+        deadline(self.rcvtimeo)
+    }
+    /// 从现在开始发送时，阻塞的截止时刻
+    pub fn send_deadline(&self) -> Option<usize> {
+        deadline(self.sndtimeo)
+    }
+    /// 关闭时是否直接重置连接
+    pub fn abort_on_close(&self) -> bool {
+        self.linger == Some(0)
+    }
+    /// 从现在开始关闭时，等待数据发完的截止时刻。没有设置 SO_LINGER 或者时间为 0 时返回 None，此时不等待
+    pub fn linger_deadline(&self) -> Option<usize> {
+        match self.linger {
+            Some(secs) if secs > 0 => deadline(secs as usize * 1_000_000),
+            _ => None,
+        }
+    }
+}
+
+/// 由超时时间得到截止时刻(us)
+fn deadline(timeout: usize) -> Option<usize> {
+    if timeout == 0 {
+        None
+    } else {
+        Some(get_time_us().saturating_add(timeout))
+    }
+}
+
+/// 阻塞的 socket 操作在 socket 的等待队列上睡眠一轮，直到 socket 的状态可能变化、超过截止时刻或者收到信号。
+/// wakes 是检查等待条件之前 socket 的 wake_count。已经超过截止时刻时返回 EAGAIN，被信号打断时返回 EINTR
+pub fn wait_until(socket: &dyn File, wakes: Option<usize>, deadline: Option<usize>) -> Result<(), ErrorNo> {
+    wait_for(
+        deadline,
+        |tid| {
+            socket.register_waiter(tid);
+            socket.wake_count() != wakes
+        },
+        |tid| socket.unregister_waiter(tid),
+    )
+}
@@ -0,0 +1,238 @@
+//! 抓包。开启后把经过 socket 层的报文按 pcap 格式记下来，从 /proc/net/pcap 读出后可以直接用 Wireshark 打开
+//!
+//! 回环上的通信没有真正的报文，记录时按两端的地址和端口合成 IPv4/IPv6 和 TCP/UDP 的头部，
+//! TCP 的序号就是这个方向上已发送的字节数。网卡上的帧在收发时记录，去掉以太网头部，ARP 等不是 IP 的帧不记录。
+//! 所以抓到的都是 IP 报文，链路层类型为 LINKTYPE_RAW。AF_UNIX 的 socket 不经过 IP，不记录
+//!
+//! 向 /proc/net/pcap 写入 1 开始抓包，同时清掉之前抓到的报文；写入 0 停止抓包，已抓到的报文仍然可以读。
+//! 报文存在一个环形缓冲区里，总长度超过 PCAP_BUFFER_SIZE 时丢掉最早的报文
+
+use super::InetIp;
+use crate::file::add_proc_bin_file;
+use crate::timer::get_time_us;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use bitflags::*;
+use core::cmp::min;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lock::Mutex;
+
+/// 保存抓到的报文的缓冲区大小的上限，包括每个报文的 pcap 记录头
+const PCAP_BUFFER_SIZE: usize = 0x10_0000;
+/// pcap 文件头中的 magic number，按本机字节序写入
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+/// 每个报文最多记录的长度
+const PCAP_SNAPLEN: u32 = 0xffff;
+/// 链路层类型，表示报文直接从 IP 头部开始
+const LINKTYPE_RAW: u32 = 101;
+/// 以太网头部的长度，以及其中表示 IPv4 和 IPv6 的类型
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+/// 合成的 IP 头部中的 TTL
+const IP_TTL: u8 = 64;
+/// 合成的 TCP 报文最多带的数据。一次发送更多数据时拆成多个报文，保证 IP 报文的长度不超过 16 位
+const MAX_TCP_SEGMENT: usize = 0xffff - 60 - 20;
+
+bitflags! {
+    /// TCP 头部中的标志位
+    pub struct TcpFlags: u8 {
+        const FIN = 1 << 0;
+        const SYN = 1 << 1;
+        const RST = 1 << 2;
+        const PSH = 1 << 3;
+        const ACK = 1 << 4;
+    }
+}
+
+/// 是否正在抓包
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    /// 抓到的报文，每一项是带 pcap 记录头的一个报文
+    static ref PACKETS: Mutex<PcapBuffer> = Mutex::new(PcapBuffer {
+        records: VecDeque::new(),
+        size: 0,
+    });
+}
+
+struct PcapBuffer {
+    records: VecDeque<Vec<u8>>,
+    /// records 的总长度
+    size: usize,
+}
+
+/// 注册 /proc/net/pcap
+pub fn init() {
+    add_proc_bin_file("net", "pcap", read_capture, Some(write_control));
+}
+
+/// 是否正在抓包。记录报文前先检查它，避免在不抓包时合成报文
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 生成 /proc/net/pcap 的内容，即 pcap 文件头加上所有抓到的报文
+fn read_capture() -> Vec<u8> {
+    let mut file = Vec::new();
+    file.extend_from_slice(&PCAP_MAGIC.to_ne_bytes());
+    file.extend_from_slice(&2u16.to_ne_bytes()); // 版本 2.4
+    file.extend_from_slice(&4u16.to_ne_bytes());
+    file.extend_from_slice(&0i32.to_ne_bytes()); // 时区
+    file.extend_from_slice(&0u32.to_ne_bytes()); // 时间戳精度
+    file.extend_from_slice(&PCAP_SNAPLEN.to_ne_bytes());
+    file.extend_from_slice(&LINKTYPE_RAW.to_ne_bytes());
+    for record in PACKETS.lock().records.iter() {
+        file.extend_from_slice(record);
+    }
+    file
+}
+
+/// 写入 /proc/net/pcap。1 表示开始抓包，0 表示停止，其他内容不接受
+fn write_control(buf: &[u8]) -> bool {
+    match core::str::from_utf8(buf).map(str::trim) {
+        Ok("1") => {
+            let mut packets = PACKETS.lock();
+            packets.records.clear();
+            packets.size = 0;
+            ENABLED.store(true, Ordering::Relaxed);
+            true
+        }
+        Ok("0") => {
+            ENABLED.store(false, Ordering::Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// 记录一个从 IP 头部开始的报文
+fn record(packet: &[u8]) {
+    let time = get_time_us();
+    let len = min(packet.len(), PCAP_SNAPLEN as usize);
+    let mut record = Vec::with_capacity(16 + len);
+    record.extend_from_slice(&((time / 1_000_000) as u32).to_ne_bytes());
+    record.extend_from_slice(&((time % 1_000_000) as u32).to_ne_bytes());
+    record.extend_from_slice(&(len as u32).to_ne_bytes());
+    record.extend_from_slice(&(packet.len() as u32).to_ne_bytes());
+    record.extend_from_slice(&packet[..len]);
+    let mut packets = PACKETS.lock();
+    packets.size += record.len();
+    packets.records.push_back(record);
+    while packets.size > PCAP_BUFFER_SIZE {
+        let dropped = packets.records.pop_front().unwrap();
+        packets.size -= dropped.len();
+    }
+}
+
+/// 记录网卡收发的一个以太网帧
+pub fn capture_frame(frame: &[u8]) {
+    if !is_enabled() || frame.len() < ETHERNET_HEADER_LEN {
+        return;
+    }
+    match u16::from_be_bytes([frame[12], frame[13]]) {
+        ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => record(&frame[ETHERNET_HEADER_LEN..]),
+        _ => {}
+    }
+}
+
+/// 记录回环上从 src 发往 dst 的一个 UDP 数据报
+pub fn capture_udp(src: (InetIp, u16), dst: (InetIp, u16), data: &[u8]) {
+    if !is_enabled() {
+        return;
+    }
+    let mut datagram = Vec::with_capacity(8 + data.len());
+    datagram.extend_from_slice(&src.1.to_be_bytes());
+    datagram.extend_from_slice(&dst.1.to_be_bytes());
+    datagram.extend_from_slice(&((8 + data.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]); // 校验和，在 ip_packet 里填
+    datagram.extend_from_slice(data);
+    record(&ip_packet(src.0, dst.0, IPPROTO_UDP, datagram, 6));
+}
+
+/// 记录回环上从 src 发往 dst 的 TCP 报文。seq 为第一个字节的序号，数据太长时拆成多个报文
+pub fn capture_tcp(src: (InetIp, u16), dst: (InetIp, u16), seq: u32, ack: u32, flags: TcpFlags, data: &[u8]) {
+    if !is_enabled() {
+        return;
+    }
+    let mut seq = seq;
+    let mut chunks = data.chunks(MAX_TCP_SEGMENT);
+    // 不带数据的报文也要记录一次
+    let first: &[u8] = chunks.next().unwrap_or(&[]);
+    for chunk in core::iter::once(first).chain(chunks) {
+        let mut segment = Vec::with_capacity(20 + chunk.len());
+        segment.extend_from_slice(&src.1.to_be_bytes());
+        segment.extend_from_slice(&dst.1.to_be_bytes());
+        segment.extend_from_slice(&seq.to_be_bytes());
+        segment.extend_from_slice(&ack.to_be_bytes());
+        segment.push(5 << 4); // 头部长度为 5 个 32 位字
+        segment.push(flags.bits());
+        segment.extend_from_slice(&0xffffu16.to_be_bytes()); // 窗口
+        segment.extend_from_slice(&[0, 0, 0, 0]); // 校验和与紧急指针
+        segment.extend_from_slice(chunk);
+        record(&ip_packet(src.0, dst.0, IPPROTO_TCP, segment, 16));
+        seq = seq.wrapping_add(chunk.len() as u32);
+    }
+}
+
+/// 在传输层的报文前加上 IP 头部，并填上传输层的校验和，checksum_offset 是校验和在报文中的位置。
+/// 两端都是 IPv4 地址时生成 IPv4 报文，否则生成 IPv6 报文
+fn ip_packet(src: InetIp, dst: InetIp, protocol: u8, mut payload: Vec<u8>, checksum_offset: usize) -> Vec<u8> {
+    let len = payload.len();
+    let mut packet = Vec::with_capacity(40 + len);
+    let pseudo_header = match (src, dst) {
+        (InetIp::V4(src), InetIp::V4(dst)) => {
+            let mut pseudo_header = Vec::with_capacity(12);
+            pseudo_header.extend_from_slice(&src.to_be_bytes());
+            pseudo_header.extend_from_slice(&dst.to_be_bytes());
+            pseudo_header.extend_from_slice(&[0, protocol]);
+            pseudo_header.extend_from_slice(&(len as u16).to_be_bytes());
+            packet.extend_from_slice(&[0x45, 0]); // 版本 4，头部长度 5 个 32 位字
+            packet.extend_from_slice(&((20 + len) as u16).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0]); // 不分片
+            packet.extend_from_slice(&[IP_TTL, protocol, 0, 0]);
+            packet.extend_from_slice(&src.to_be_bytes());
+            packet.extend_from_slice(&dst.to_be_bytes());
+            let header_checksum = checksum(&[&packet]);
+            packet[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+            pseudo_header
+        }
+        _ => {
+            let (src, dst) = (src.to_v6().to_be_bytes(), dst.to_v6().to_be_bytes());
+            let mut pseudo_header = Vec::with_capacity(40);
+            pseudo_header.extend_from_slice(&src);
+            pseudo_header.extend_from_slice(&dst);
+            pseudo_header.extend_from_slice(&(len as u32).to_be_bytes());
+            pseudo_header.extend_from_slice(&[0, 0, 0, protocol]);
+            packet.extend_from_slice(&[0x60, 0, 0, 0]); // 版本 6
+            packet.extend_from_slice(&(len as u16).to_be_bytes());
+            packet.extend_from_slice(&[protocol, IP_TTL]);
+            packet.extend_from_slice(&src);
+            packet.extend_from_slice(&dst);
+            pseudo_header
+        }
+    };
+    let mut payload_checksum = checksum(&[&pseudo_header, &payload]);
+    if payload_checksum == 0 && protocol == IPPROTO_UDP {
+        // UDP 的校验和为 0 表示没有校验和
+        payload_checksum = 0xffff;
+    }
+    payload[checksum_offset..checksum_offset + 2].copy_from_slice(&payload_checksum.to_be_bytes());
+    packet.extend_from_slice(&payload);
+    packet
+}
+
+/// 计算 IP 校验和，即按 16 位求和取反。除最后一段外，每段的长度都必须是偶数
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for part in parts {
+        for word in part.chunks(2) {
+            let word = if word.len() == 2 { [word[0], word[1]] } else { [word[0], 0] };
+            sum += u16::from_be_bytes(word) as u32;
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+    }
+    !(sum as u16)
+}
@@ -1,9 +1,16 @@
-//! 地址解析。目前只有 ip 地址的解析
+//! 地址解析。包括 ip 地址(IPv4 和 IPv6)和 unix socket 的地址
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::mem::size_of;
 
 #[derive(Debug, Clone, Copy)]
 pub enum AddrType {
     /// ip 地址和端口
     Ip(u32, u16),
+    /// IPv6 地址和端口，包括 v4 映射的地址
+    Ipv6(u128, u16),
     /// 未知
     Unknown,
 }
@@ -15,8 +22,117 @@ pub struct IpAddr {
     pub addr: u32,
 }
 
-//const FAMILY_UNIX: u16 = 1;
+/// 即 sockaddr_in6
+#[repr(C)]
+pub struct Ipv6Addr {
+    pub family: u16,
+    pub port: u16,
+    pub flowinfo: u32,
+    pub addr: [u8; 16],
+    pub scope_id: u32,
+}
+
+const FAMILY_UNIX: u16 = 1;
 const FAMILY_INTERNET: u16 = 2;
+const FAMILY_INTERNET6: u16 = 10;
+/// sockaddr_in 的长度
+const SOCKADDR_IN_LEN: usize = 16;
+/// sockaddr_un 中 sun_path 的长度
+const UNIX_PATH_MAX: usize = 108;
+/// v4 映射的 IPv6 地址 ::ffff:a.b.c.d 中，IPv4 地址前面的部分
+const V4_MAPPED_PREFIX: u128 = 0xffff_0000_0000;
+
+/// socket 使用的 ip 地址，主机字节序。
+///
+/// AF_INET6 的 socket 可以同时和 IPv4 通信，此时对方的地址在用户看来是 v4 映射的地址 ::ffff:a.b.c.d，
+/// 但在内核中统一存成 V4，这样同一个 IPv4 地址只有一种表示
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InetIp {
+    V4(u32),
+    V6(u128),
+}
+
+impl InetIp {
+    /// 由 IPv6 地址得到 InetIp，v4 映射的地址转为 V4
+    pub fn from_v6(ip: u128) -> Self {
+        if ip >> 32 == V4_MAPPED_PREFIX >> 32 {
+            Self::V4(ip as u32)
+        } else {
+            Self::V6(ip)
+        }
+    }
+    /// 转为 IPv6 地址，V4 转为 v4 映射的地址
+    pub fn to_v6(self) -> u128 {
+        match self {
+            Self::V4(ip) => V4_MAPPED_PREFIX | ip as u128,
+            Self::V6(ip) => ip,
+        }
+    }
+    /// 如果是 IPv4 地址则返回它
+    pub fn v4(self) -> Option<u32> {
+        match self {
+            Self::V4(ip) => Some(ip),
+            Self::V6(_) => None,
+        }
+    }
+    /// 未指定的地址，即 0.0.0.0 或 ::
+    pub fn unspecified(v6: bool) -> Self {
+        if v6 {
+            Self::V6(0)
+        } else {
+            Self::V4(0)
+        }
+    }
+    /// 本地回环地址，即 127.0.0.1 或 ::1
+    pub fn loopback(v6: bool) -> Self {
+        if v6 {
+            Self::V6(1)
+        } else {
+            Self::V4(LOCAL_LOOPBACK_ADDR)
+        }
+    }
+    pub fn is_v6(self) -> bool {
+        matches!(self, Self::V6(_))
+    }
+    pub fn is_unspecified(self) -> bool {
+        self == Self::V4(0) || self == Self::V6(0)
+    }
+    /// 是否是本机的地址，即未指定的地址、127.0.0.0/8 或者 ::1
+    pub fn is_loopback(self) -> bool {
+        match self {
+            Self::V4(ip) => ip == 0 || (ip >> 24) == 127,
+            Self::V6(ip) => ip <= 1,
+        }
+    }
+    /// 发往未指定地址的数据实际上发给了本地回环地址
+    pub fn or_loopback(self) -> Self {
+        if self.is_unspecified() {
+            Self::loopback(self.is_v6())
+        } else {
+            self
+        }
+    }
+    /// 绑定在这个地址上的 socket 是否接收发往 dest 的连接或数据报。
+    /// 绑定在 :: 上的 socket 同时接收 IPv4 的数据，除非设置了 IPV6_V6ONLY
+    pub fn accepts(self, dest: InetIp, v6only: bool) -> bool {
+        match (self, dest) {
+            (Self::V4(0), Self::V4(_)) | (Self::V6(0), Self::V6(_)) => true,
+            (Self::V6(0), Self::V4(_)) => !v6only,
+            _ => self == dest,
+        }
+    }
+}
+
+/// unix socket 的地址
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UnixAddr {
+    /// 没有绑定地址
+    Unnamed,
+    /// 文件系统中的路径。这里存的是内核中的绝对路径，即以 "./" 开头
+    Path(String),
+    /// 抽象命名空间中的名字，不含开头的 '\0'
+    Abstract(Vec<u8>),
+}
 
 pub fn addr_resolution(family_user_addr: *const u16) -> AddrType {
     let family = unsafe { *family_user_addr };
@@ -25,22 +141,120 @@ pub fn addr_resolution(family_user_addr: *const u16) -> AddrType {
             let ip_addr = unsafe { &*(family_user_addr as *const IpAddr) };
             AddrType::Ip(u32::from_be(ip_addr.addr), u16::from_be(ip_addr.port))
         }
+        FAMILY_INTERNET6 => {
+            let ip_addr = unsafe { &*(family_user_addr as *const Ipv6Addr) };
+            AddrType::Ipv6(u128::from_be_bytes(ip_addr.addr), u16::from_be(ip_addr.port))
+        }
         _ => AddrType::Unknown,
     }
 }
 
-pub fn get_ephemeral_port() -> u16 {
-    // TODO selects non-conflict high port
-    static mut EPHEMERAL_PORT: u16 = 0;
-    unsafe {
-        if EPHEMERAL_PORT == 0 {
-            EPHEMERAL_PORT = (49152 + 0 % (65536 - 49152)) as u16;
+/// 解析用户给出的 sockaddr_in，len 为整个地址结构的长度。返回主机字节序的 ip 和端口，
+/// 地址不合法时返回 None
+pub fn ip_addr_resolution(user_addr: *const u8, len: usize) -> Option<(u32, u16)> {
+    if len < size_of::<IpAddr>() {
+        return None;
+    }
+    match addr_resolution(user_addr as *const u16) {
+        AddrType::Ip(ip, port) => Some((ip, port)),
+        _ => None,
+    }
+}
+
+/// 解析用户给出的 socket 地址，len 为整个地址结构的长度。v6 表示是否是 AF_INET6 的 socket，
+/// 此时地址必须是 sockaddr_in6，否则必须是 sockaddr_in。地址不合法时返回 None
+pub fn inet_addr_resolution(user_addr: *const u8, len: usize, v6: bool) -> Option<(InetIp, u16)> {
+    if v6 {
+        if len < size_of::<Ipv6Addr>() {
+            return None;
+        }
+        match addr_resolution(user_addr as *const u16) {
+            AddrType::Ipv6(ip, port) => Some((InetIp::from_v6(ip), port)),
+            _ => None,
         }
-        if EPHEMERAL_PORT == 65535 {
-            EPHEMERAL_PORT = 49152;
+    } else {
+        ip_addr_resolution(user_addr, len).map(|(ip, port)| (InetIp::V4(ip), port))
+    }
+}
+
+/// 把 ip 和端口写成 sockaddr_in 放到 user_addr，buf_len 是用户 buffer 的长度，放不下的部分会被截断。
+/// 返回地址结构的实际长度
+pub fn ip_addr_to_user(ip: u32, port: u16, user_addr: *mut u8, buf_len: usize) -> usize {
+    // sockaddr_in 在 IpAddr 后面还有 8 字节的填充
+    let mut bytes = [0u8; SOCKADDR_IN_LEN];
+    bytes[0..2].copy_from_slice(&FAMILY_INTERNET.to_ne_bytes());
+    bytes[2..4].copy_from_slice(&port.to_be_bytes());
+    bytes[4..8].copy_from_slice(&ip.to_be_bytes());
+    let copy_len = min(bytes.len(), buf_len);
+    unsafe { core::slice::from_raw_parts_mut(user_addr, copy_len) }.copy_from_slice(&bytes[..copy_len]);
+    bytes.len()
+}
+
+/// 把地址写到 user_addr。v6 表示是否是 AF_INET6 的 socket，此时写成 sockaddr_in6，否则写成 sockaddr_in。
+/// buf_len 是用户 buffer 的长度，放不下的部分会被截断。返回地址结构的实际长度
+pub fn inet_addr_to_user((ip, port): (InetIp, u16), v6: bool, user_addr: *mut u8, buf_len: usize) -> usize {
+    if !v6 {
+        // AF_INET 的 socket 不会碰到 IPv6 的地址
+        return ip_addr_to_user(ip.v4().unwrap_or(0), port, user_addr, buf_len);
+    }
+    let mut bytes = [0u8; size_of::<Ipv6Addr>()];
+    bytes[0..2].copy_from_slice(&FAMILY_INTERNET6.to_ne_bytes());
+    bytes[2..4].copy_from_slice(&port.to_be_bytes());
+    bytes[8..24].copy_from_slice(&ip.to_v6().to_be_bytes());
+    let copy_len = min(bytes.len(), buf_len);
+    unsafe { core::slice::from_raw_parts_mut(user_addr, copy_len) }.copy_from_slice(&bytes[..copy_len]);
+    bytes.len()
+}
+
+/// 本地的网络地址，即 127.0.0.1
+pub const LOCAL_LOOPBACK_ADDR: u32 = 0x7f000001;
+/// 自动分配的端口的范围
+pub const EPHEMERAL_PORT_START: u16 = 49152;
+pub const EPHEMERAL_PORT_END: u16 = 65535;
+
+/// 解析用户给出的 sockaddr_un，len 为整个地址结构的长度，相对路径基于 cwd 解析。
+/// 地址不合法时返回 None
+pub fn unix_addr_resolution(user_addr: *const u8, len: usize, cwd: &str) -> Option<UnixAddr> {
+    if len < 2 || len > 2 + UNIX_PATH_MAX || unsafe { *(user_addr as *const u16) } != FAMILY_UNIX {
+        return None;
+    }
+    let path = unsafe { core::slice::from_raw_parts(user_addr.add(2), len - 2) };
+    if path.is_empty() {
+        Some(UnixAddr::Unnamed)
+    } else if path[0] == 0 {
+        Some(UnixAddr::Abstract(path[1..].to_vec()))
+    } else {
+        // 路径不一定以 '\0' 结尾
+        let end = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+        let path = core::str::from_utf8(&path[..end]).ok()?;
+        Some(UnixAddr::Path(if path.starts_with('/') {
+            String::from(".") + path
+        } else if cwd.ends_with('/') {
+            String::from(cwd) + path
         } else {
-            EPHEMERAL_PORT = EPHEMERAL_PORT + 1;
+            String::from(cwd) + "/" + path
+        }))
+    }
+}
+
+/// 把地址写成 sockaddr_un 放到 user_addr，buf_len 是用户 buffer 的长度，放不下的部分会被截断。
+/// 返回地址结构的实际长度
+pub fn unix_addr_to_user(addr: &UnixAddr, user_addr: *mut u8, buf_len: usize) -> usize {
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend_from_slice(&FAMILY_UNIX.to_ne_bytes());
+    match addr {
+        UnixAddr::Unnamed => {}
+        UnixAddr::Path(path) => {
+            // 去掉开头的 '.'
+            bytes.extend_from_slice(path[1..].as_bytes());
+            bytes.push(0);
+        }
+        UnixAddr::Abstract(name) => {
+            bytes.push(0);
+            bytes.extend_from_slice(name);
         }
-        EPHEMERAL_PORT
     }
+    let copy_len = min(bytes.len(), buf_len);
+    unsafe { core::slice::from_raw_parts_mut(user_addr, copy_len) }.copy_from_slice(&bytes[..copy_len]);
+    bytes.len()
 }
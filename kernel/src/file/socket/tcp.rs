@@ -0,0 +1,935 @@
+//! TCP
+//!
+//! 本地回环上的连接不模拟报文。一个连接就是一对 TcpBuffer，每个方向一个，连接的两端各自持有这两个 buffer。
+//! - 绑定的端口记录在全局表 TCP_PORTS 里。connect 时直接新建一个服务端的 socket，放进对方的监听队列等待 accept
+//! - shutdown 写或者关闭相当于发送 FIN：对方读完已有的数据后读到文件尾，再写会得到 EPIPE
+//! - 关闭时还有没读完的数据，或者监听的 socket 关闭时还有没 accept 的连接，相当于发送 RST：
+//!   对方的读写都会得到 ECONNRESET
+//! - 抓包时按上面的过程合成 SYN、数据、FIN 和 RST 报文(见 pcap.rs)
+//!
+//! 其他地址上的连接交给网卡上的协议栈(见 iface.rs)，socket 只保存协议栈中 socket 的句柄。
+//! 监听的 socket 如果绑定在 0.0.0.0 或网卡的地址上，也会在协议栈里监听同一个端口
+//!
+//! AF_INET6 的 socket 只能在回环 ::1 上使用 IPv6，通过 v4 映射的地址和 IPv4 通信。
+//! 绑定在 :: 上的 socket 同时接受 IPv4 的连接，除非设置了 IPV6_V6ONLY。
+//! 端口不区分 IPv4 和 IPv6，同一个端口只能被一个 socket 绑定
+
+use super::iface::{endpoint_to_addr, is_local_addr, with_iface, NET_WAITERS};
+use super::options::wait_until;
+use super::pcap::{capture_tcp, TcpFlags};
+use super::{InetIp, SocketOptions, EPHEMERAL_PORT_END, EPHEMERAL_PORT_START};
+use crate::file::{File, OpenFlags, WaitQueue};
+use crate::syscall::ErrorNo;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use core::cmp::min;
+use core::sync::atomic::{AtomicU32, Ordering};
+use lock::Mutex;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::TcpState;
+
+/// 监听队列长度的上限，即 SOMAXCONN
+const MAX_BACKLOG: usize = 4096;
+
+/// 已绑定的端口到对应 socket 监听队列的映射。socket 关闭后，其中的 Weak 就会失效
+static TCP_PORTS: Mutex<BTreeMap<u16, Weak<AcceptQueue>>> = Mutex::new(BTreeMap::new());
+
+/// 连接中一个方向的数据
+struct TcpBuffer {
+    inner: Mutex<BufferInner>,
+    /// 等待这个 buffer 状态变化的线程，包括等待读的线程和等待腾出空间的发送者
+    waiters: WaitQueue,
+    /// 发送方下一个字节的序号，只用于抓包。SYN 和 FIN 各占一个序号
+    seq: AtomicU32,
+}
+
+struct BufferInner {
+    /// 发送了但还没读出的数据
+    data: VecDeque<u8>,
+    /// data 的长度上限，即接收方的 SO_RCVBUF
+    capacity: usize,
+    /// 发送方不再写入(FIN)，读完已有数据后读到文件尾
+    fin: bool,
+    /// 接收方已关闭，不再读出数据
+    closed: bool,
+    /// 连接已被重置(RST)
+    reset: bool,
+}
+
+impl TcpBuffer {
+    fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(BufferInner {
+                data: VecDeque::new(),
+                capacity: capacity,
+                fin: false,
+                closed: false,
+                reset: false,
+            }),
+            waiters: WaitQueue::new(),
+            seq: AtomicU32::new(0),
+        })
+    }
+}
+
+/// 一个已建立的连接，从某一端看的两个方向
+#[derive(Clone)]
+struct TcpConnection {
+    /// 对方发给自己的数据
+    recv: Arc<TcpBuffer>,
+    /// 自己发给对方的数据
+    send: Arc<TcpBuffer>,
+    /// 自己和对方的地址，只用于抓包
+    addrs: ((InetIp, u16), (InetIp, u16)),
+}
+
+impl TcpConnection {
+    /// 新建一个连接，返回两端。rcvbuf1 和 rcvbuf2 是两端的 SO_RCVBUF，addr1 和 addr2 是两端的地址。
+    /// 抓包时记录三次握手
+    fn new_pair(rcvbuf1: usize, rcvbuf2: usize, addr1: (InetIp, u16), addr2: (InetIp, u16)) -> (Self, Self) {
+        let buf1 = TcpBuffer::new(rcvbuf1);
+        let buf2 = TcpBuffer::new(rcvbuf2);
+        let conn1 = Self {
+            recv: buf1.clone(),
+            send: buf2.clone(),
+            addrs: (addr1, addr2),
+        };
+        let conn2 = Self {
+            recv: buf2,
+            send: buf1,
+            addrs: (addr2, addr1),
+        };
+        conn1.capture(TcpFlags::SYN, &[]);
+        conn2.capture(TcpFlags::SYN | TcpFlags::ACK, &[]);
+        conn1.capture(TcpFlags::ACK, &[]);
+        (conn1, conn2)
+    }
+    /// 自己向对方发出了一个报文，推进序号并在抓包时记录它。调用时需要持有 send 的锁，保证序号和数据的顺序一致
+    fn capture(&self, flags: TcpFlags, data: &[u8]) {
+        let len = data.len() + flags.intersects(TcpFlags::SYN | TcpFlags::FIN) as usize;
+        let seq = self.send.seq.fetch_add(len as u32, Ordering::Relaxed);
+        let ack = if flags.contains(TcpFlags::ACK) { self.recv.seq.load(Ordering::Relaxed) } else { 0 };
+        capture_tcp(self.addrs.0, self.addrs.1, seq, ack, flags, data);
+    }
+    /// 不再发送数据，对方读完已有的数据后读到文件尾
+    fn send_fin(&self) {
+        let mut buf_inner = self.send.inner.lock();
+        if !buf_inner.fin && !buf_inner.reset {
+            buf_inner.fin = true;
+            self.capture(TcpFlags::FIN | TcpFlags::ACK, &[]);
+        }
+        drop(buf_inner);
+        self.send.waiters.wake_all();
+    }
+    /// 重置连接，两端之后的读写都会得到 ECONNRESET
+    fn reset(&self) {
+        let buf_inner = self.send.inner.lock();
+        if !buf_inner.reset {
+            self.capture(TcpFlags::RST | TcpFlags::ACK, &[]);
+        }
+        drop(buf_inner);
+        for buf in [&self.recv, &self.send] {
+            let mut buf_inner = buf.inner.lock();
+            buf_inner.reset = true;
+            buf_inner.data.clear();
+            drop(buf_inner);
+            buf.waiters.wake_all();
+        }
+    }
+}
+
+/// socket 的监听队列。绑定端口后登记在 TCP_PORTS 里
+struct AcceptQueue {
+    /// socket 是否是 AF_INET6 的
+    v6: bool,
+    inner: Mutex<AcceptQueueInner>,
+    /// 等待 accept 的线程，以及等待监听队列腾出位置的 connect
+    waiters: WaitQueue,
+}
+
+struct AcceptQueueInner {
+    /// 是否正在监听
+    listening: bool,
+    /// 监听队列的长度上限
+    max_backlog: usize,
+    /// 已建立但还没有 accept 的连接
+    backlog: VecDeque<Arc<TcpSocket>>,
+    /// 在网卡上监听的协议栈 socket
+    remote_listener: Option<SocketHandle>,
+    /// 监听的 ip，connect 时用来检查是否接受这个连接
+    local_ip: InetIp,
+    /// 是否设置了 IPV6_V6ONLY，即不接受 IPv4 的连接
+    v6only: bool,
+    /// socket 的选项。放在这里是为了让 accept 得到的 socket 继承监听 socket 的选项
+    options: SocketOptions,
+}
+
+/// TCP socket
+pub struct TcpSocket {
+    /// 自己的监听队列
+    accept_queue: Arc<AcceptQueue>,
+    inner: Mutex<TcpSocketInner>,
+}
+
+struct TcpSocketInner {
+    /// 文件状态，包括 NON_BLOCK 和 CLOEXEC
+    flags: OpenFlags,
+    /// 绑定的 ip 和端口
+    local_addr: Option<(InetIp, u16)>,
+    /// 对方的 ip 和端口
+    peer_addr: (InetIp, u16),
+    /// 建立的连接
+    conn: Option<TcpConnection>,
+    /// 网卡上的连接在协议栈中的 socket
+    remote: Option<SocketHandle>,
+    /// 是否 shutdown 了读
+    read_shutdown: bool,
+    /// 是否 shutdown 了写。只用于网卡上的连接，回环上的连接记录在 TcpBuffer 里
+    write_shutdown: bool,
+    /// 网卡上的连接是否完成过握手，用来区分连接被拒绝和被重置
+    established: bool,
+    /// 连接的错误是否已经通过 SO_ERROR 取走
+    error_taken: bool,
+}
+
+impl TcpSocket {
+    /// 新建一个未绑定、未连接的 socket。v6 表示是否是 AF_INET6 的 socket
+    pub fn new(flags: OpenFlags, v6: bool) -> Self {
+        Self {
+            accept_queue: Arc::new(AcceptQueue {
+                v6: v6,
+                inner: Mutex::new(AcceptQueueInner {
+                    listening: false,
+                    max_backlog: 0,
+                    backlog: VecDeque::new(),
+                    remote_listener: None,
+                    local_ip: InetIp::unspecified(v6),
+                    v6only: false,
+                    options: SocketOptions::new(),
+                }),
+                waiters: WaitQueue::new(),
+            }),
+            inner: Mutex::new(TcpSocketInner {
+                flags: flags,
+                local_addr: None,
+                peer_addr: (InetIp::unspecified(v6), 0),
+                conn: None,
+                remote: None,
+                read_shutdown: false,
+                write_shutdown: false,
+                established: false,
+                error_taken: false,
+            }),
+        }
+    }
+    /// 是否非阻塞。dont_wait 为 MSG_DONTWAIT
+    fn is_non_block(&self, dont_wait: bool) -> bool {
+        dont_wait || self.inner.lock().flags.contains(OpenFlags::NON_BLOCK)
+    }
+    /// 是否正在监听
+    fn is_listening(&self) -> bool {
+        self.accept_queue.inner.lock().listening
+    }
+    /// 是否是 AF_INET6 的 socket
+    pub fn is_v6(&self) -> bool {
+        self.accept_queue.v6
+    }
+    /// 是否只使用 IPv6，即设置了 IPV6_V6ONLY
+    fn is_v6only(&self) -> bool {
+        self.accept_queue.inner.lock().v6only
+    }
+    /// 设置 IPV6_V6ONLY，需要在绑定前设置
+    pub fn set_v6only(&self, v6only: bool) -> Result<(), ErrorNo> {
+        if self.inner.lock().local_addr.is_some() {
+            return Err(ErrorNo::EINVAL);
+        }
+        self.accept_queue.inner.lock().v6only = v6only;
+        Ok(())
+    }
+    /// 获取 socket 的选项
+    pub fn options(&self) -> SocketOptions {
+        self.accept_queue.inner.lock().options
+    }
+    /// 设置 socket 的选项。已建立的回环连接会立即按新的 SO_RCVBUF 限制对方发送的数据，
+    /// 网卡上的连接只更新保活和 Nagle 算法
+    pub fn set_options(&self, options: SocketOptions) {
+        self.accept_queue.inner.lock().options = options;
+        let (conn, remote) = {
+            let inner = self.inner.lock();
+            (inner.conn.clone(), inner.remote)
+        };
+        if let Some(conn) = conn {
+            conn.recv.inner.lock().capacity = options.rcvbuf;
+            // 可能腾出了空间
+            conn.recv.waiters.wake_all();
+        }
+        if let Some(handle) = remote {
+            with_iface(|iface| iface.set_tcp_options(handle, &options));
+        }
+    }
+    /// 取出连接上的错误，即 SO_ERROR。每个错误只报告一次
+    pub fn take_error(&self) -> Option<ErrorNo> {
+        let mut inner = self.inner.lock();
+        let error = if let Some(handle) = inner.remote {
+            match with_iface(|iface| iface.tcp(handle).state()).unwrap_or(TcpState::Closed) {
+                state if is_connecting(state) => None,
+                // 自己没有关闭写，连接却关闭了
+                TcpState::Closed if !inner.write_shutdown => {
+                    Some(if inner.established { ErrorNo::ECONNRESET } else { ErrorNo::ECONNREFUSED })
+                }
+                _ => {
+                    inner.established = true;
+                    None
+                }
+            }
+        } else {
+            inner.conn.as_ref().filter(|conn| conn.recv.inner.lock().reset).map(|_| ErrorNo::ECONNRESET)
+        };
+        if error.is_some() && !inner.error_taken {
+            inner.error_taken = true;
+            error
+        } else {
+            None
+        }
+    }
+    /// 绑定地址。端口为 0 时自动分配一个
+    pub fn bind(&self, ip: InetIp, port: u16) -> Result<(), ErrorNo> {
+        if self.is_v6only() && !ip.is_v6() {
+            return Err(ErrorNo::EINVAL);
+        }
+        if !is_local_addr(ip) {
+            return Err(ErrorNo::EADDRNOTAVAIL);
+        }
+        let mut inner = self.inner.lock();
+        if inner.local_addr.is_some() {
+            return Err(ErrorNo::EINVAL);
+        }
+        let port = self.register_port(port)?;
+        inner.local_addr = Some((ip, port));
+        Ok(())
+    }
+    /// 在 TCP_PORTS 中登记端口，返回实际绑定的端口
+    fn register_port(&self, port: u16) -> Result<u16, ErrorNo> {
+        let mut ports = TCP_PORTS.lock();
+        let is_free = |port: &u16| ports.get(port).map_or(true, |queue| queue.strong_count() == 0);
+        let port = if port == 0 {
+            (EPHEMERAL_PORT_START..=EPHEMERAL_PORT_END)
+                .find(is_free)
+                .ok_or(ErrorNo::EADDRINUSE)?
+        } else if is_free(&port) {
+            port
+        } else {
+            return Err(ErrorNo::EADDRINUSE);
+        };
+        ports.insert(port, Arc::downgrade(&self.accept_queue));
+        Ok(port)
+    }
+    /// 开始监听连接。没有绑定时自动分配端口
+    pub fn listen(&self, backlog: usize) -> Result<(), ErrorNo> {
+        let mut inner = self.inner.lock();
+        if inner.conn.is_some() || inner.remote.is_some() {
+            return Err(ErrorNo::EINVAL);
+        }
+        if inner.local_addr.is_none() {
+            inner.local_addr = Some((InetIp::unspecified(self.is_v6()), self.register_port(0)?));
+        }
+        let (ip, port) = inner.local_addr.unwrap();
+        let mut queue_inner = self.accept_queue.inner.lock();
+        // 绑定在 0.0.0.0、网卡的地址或者接受 IPv4 的 :: 上时，同时在网卡上监听
+        let on_iface = match ip {
+            InetIp::V4(addr) => addr == 0 || !ip.is_loopback(),
+            InetIp::V6(addr) => addr == 0 && !queue_inner.v6only,
+        };
+        if queue_inner.remote_listener.is_none() && on_iface {
+            let options = queue_inner.options;
+            queue_inner.remote_listener = with_iface(|iface| iface.listen_tcp(port, &options)).transpose()?;
+        }
+        queue_inner.local_ip = ip;
+        queue_inner.listening = true;
+        queue_inner.max_backlog = backlog.clamp(1, MAX_BACKLOG);
+        Ok(())
+    }
+    /// 网卡上监听的 socket 建立了连接时，把连接放进监听队列，并在协议栈里新建一个 socket 继续监听
+    fn check_remote_listener(&self) {
+        let port = self.local_addr().1;
+        let mut queue_inner = self.accept_queue.inner.lock();
+        let handle = match queue_inner.remote_listener {
+            Some(handle) if queue_inner.listening && queue_inner.backlog.len() < queue_inner.max_backlog => handle,
+            _ => return,
+        };
+        let options = queue_inner.options;
+        let established = with_iface(|iface| {
+            let socket = iface.tcp(handle);
+            match socket.state() {
+                TcpState::Listen | TcpState::SynReceived => None,
+                _ => {
+                    let addrs = (endpoint_to_addr(socket.local_endpoint()), endpoint_to_addr(socket.remote_endpoint()));
+                    Some((addrs, iface.listen_tcp(port, &options).ok()))
+                }
+            }
+        })
+        .flatten();
+        if let Some((((local_ip, local_port), (peer_ip, peer_port)), listener)) = established {
+            queue_inner.remote_listener = listener;
+            let server = TcpSocket::new(OpenFlags::RDWR, self.is_v6());
+            server.accept_queue.inner.lock().options = options;
+            {
+                let mut server_inner = server.inner.lock();
+                server_inner.local_addr = Some((InetIp::V4(local_ip), local_port));
+                server_inner.peer_addr = (InetIp::V4(peer_ip), peer_port);
+                server_inner.remote = Some(handle);
+                server_inner.established = true;
+            }
+            queue_inner.backlog.push_back(Arc::new(server));
+        }
+    }
+    /// 连接到 ip 的 port 端口。没有绑定时自动分配端口
+    pub fn connect(&self, ip: InetIp, port: u16) -> Result<(), ErrorNo> {
+        {
+            let inner = self.inner.lock();
+            if inner.conn.is_some() || inner.remote.is_some() {
+                return Err(ErrorNo::EISCONN);
+            }
+        }
+        if self.is_listening() {
+            return Err(ErrorNo::EINVAL);
+        }
+        if self.is_v6only() && !ip.is_v6() {
+            return Err(ErrorNo::ENETUNREACH);
+        }
+        // 连接到网卡自己的地址也走回环。网卡只支持 IPv4
+        if !is_local_addr(ip) {
+            return match ip {
+                InetIp::V4(ip) => self.connect_remote(ip, port),
+                InetIp::V6(_) => Err(ErrorNo::ENETUNREACH),
+            };
+        }
+        let target = match TCP_PORTS.lock().get(&port).and_then(|queue| queue.upgrade()) {
+            Some(queue) => queue,
+            None => return Err(ErrorNo::ECONNREFUSED),
+        };
+        let local_addr = {
+            let mut inner = self.inner.lock();
+            if inner.local_addr.is_none() {
+                inner.local_addr = Some((InetIp::unspecified(self.is_v6()), self.register_port(0)?));
+            }
+            inner.local_addr.unwrap()
+        };
+        let dest = ip.or_loopback();
+        // 回环上的连接由本机发出，所以对方看到的地址总是 127.0.0.1 或 ::1
+        let local_addr = (InetIp::loopback(dest.is_v6()), local_addr.1);
+        let peer_addr = (dest, port);
+        let options = self.options();
+        let deadline = options.send_deadline();
+        loop {
+            let wakes = target.waiters.wake_count();
+            let mut target_inner = target.inner.lock();
+            if !target_inner.listening || !target_inner.local_ip.accepts(dest, target_inner.v6only) {
+                return Err(ErrorNo::ECONNREFUSED);
+            }
+            if target_inner.backlog.len() < target_inner.max_backlog {
+                // 为这个连接新建一个服务端的 socket，等 accept 时取出。它继承监听 socket 的选项
+                let (client_conn, server_conn) =
+                    TcpConnection::new_pair(options.rcvbuf, target_inner.options.rcvbuf, local_addr, peer_addr);
+                let server = TcpSocket::new(OpenFlags::RDWR, target.v6);
+                server.accept_queue.inner.lock().options = target_inner.options;
+                {
+                    let mut server_inner = server.inner.lock();
+                    server_inner.local_addr = Some(peer_addr);
+                    server_inner.peer_addr = local_addr;
+                    server_inner.conn = Some(server_conn);
+                }
+                target_inner.backlog.push_back(Arc::new(server));
+                drop(target_inner);
+                let mut inner = self.inner.lock();
+                inner.peer_addr = peer_addr;
+                inner.conn = Some(client_conn);
+                drop(inner);
+                target.waiters.wake_all();
+                return Ok(());
+            }
+            drop(target_inner);
+            if self.is_non_block(false) {
+                return Err(ErrorNo::EAGAIN);
+            }
+            target.waiters.wait(wakes, deadline)?;
+        }
+    }
+    /// 通过网卡连接到 ip 的 port 端口。非阻塞或者超过 SO_SNDTIMEO 时不等待握手完成，返回 EINPROGRESS
+    fn connect_remote(&self, ip: u32, port: u16) -> Result<(), ErrorNo> {
+        let local_port = {
+            let mut inner = self.inner.lock();
+            if inner.local_addr.is_none() {
+                inner.local_addr = Some((InetIp::unspecified(self.is_v6()), self.register_port(0)?));
+            }
+            inner.local_addr.unwrap().1
+        };
+        let options = self.options();
+        let (handle, local_ip) = with_iface(|iface| {
+            iface
+                .connect_tcp((ip, port), local_port, &options)
+                .map(|handle| (handle, iface.config().addr))
+        })
+        .unwrap_or(Err(ErrorNo::ENETUNREACH))?;
+        {
+            let mut inner = self.inner.lock();
+            inner.local_addr = Some((InetIp::V4(local_ip), local_port));
+            inner.peer_addr = (InetIp::V4(ip), port);
+            inner.remote = Some(handle);
+        }
+        let deadline = options.send_deadline();
+        loop {
+            let wakes = self.wake_count();
+            match with_iface(|iface| iface.tcp(handle).state()).unwrap() {
+                TcpState::SynSent | TcpState::SynReceived => {}
+                TcpState::Closed => {
+                    self.inner.lock().remote = None;
+                    with_iface(|iface| iface.release_tcp(handle, false));
+                    return Err(ErrorNo::ECONNREFUSED);
+                }
+                _ => {
+                    self.inner.lock().established = true;
+                    return Ok(());
+                }
+            }
+            if self.is_non_block(false) {
+                return Err(ErrorNo::EINPROGRESS);
+            }
+            // 超时后握手仍在后台进行
+            match wait_until(self, wakes, deadline) {
+                Err(ErrorNo::EAGAIN) => return Err(ErrorNo::EINPROGRESS),
+                ret => ret?,
+            }
+        }
+    }
+    /// 取出一个已建立的连接。socket 必须正在监听
+    pub fn accept(&self) -> Result<Arc<TcpSocket>, ErrorNo> {
+        let deadline = self.options().recv_deadline();
+        loop {
+            let wakes = self.wake_count();
+            self.check_remote_listener();
+            let mut queue_inner = self.accept_queue.inner.lock();
+            if !queue_inner.listening {
+                return Err(ErrorNo::EINVAL);
+            }
+            if let Some(server) = queue_inner.backlog.pop_front() {
+                drop(queue_inner);
+                // 监听队列腾出了位置
+                self.accept_queue.waiters.wake_all();
+                return Ok(server);
+            }
+            drop(queue_inner);
+            if self.is_non_block(false) {
+                return Err(ErrorNo::EAGAIN);
+            }
+            wait_until(self, wakes, deadline)?;
+        }
+    }
+    /// 获取连接。没有连接时返回 ENOTCONN
+    fn get_conn(&self) -> Result<TcpConnection, ErrorNo> {
+        self.inner.lock().conn.clone().ok_or(ErrorNo::ENOTCONN)
+    }
+    /// 设置了不为 0 的 SO_LINGER 时，等待发送的数据被对方收下，最多等待 SO_LINGER 秒。
+    /// 由最后一次 close 调用，超时或者被信号打断时不再等待，之后照常在 drop 时关闭连接
+    pub fn linger(&self) {
+        let deadline = match self.options().linger_deadline() {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        loop {
+            let wakes = self.wake_count();
+            let remote = self.inner.lock().remote;
+            let pending = if let Some(handle) = remote {
+                // smoltcp 的发送队列中的数据在收到 ACK 之后才会移除
+                with_iface(|iface| {
+                    let socket = iface.tcp(handle);
+                    socket.state() != TcpState::Closed && socket.send_queue() > 0
+                })
+                .unwrap()
+            } else if let Ok(conn) = self.get_conn() {
+                // 回环上的数据被对方读出才算收下
+                let buf_inner = conn.send.inner.lock();
+                !buf_inner.data.is_empty() && !buf_inner.reset && !buf_inner.closed
+            } else {
+                false
+            };
+            if !pending || wait_until(self, wakes, Some(deadline)).is_err() {
+                return;
+            }
+        }
+    }
+    /// 是否是网卡上的连接或者监听
+    fn on_iface(&self) -> bool {
+        self.inner.lock().remote.is_some() || self.accept_queue.inner.lock().remote_listener.is_some()
+    }
+    /// 发送数据，返回发送的字节数。对方的 buffer 满时只发送一部分
+    pub fn send(&self, data: &[u8], dont_wait: bool) -> Result<usize, ErrorNo> {
+        // 先取出句柄再释放锁，send_remote / recv_remote 里还要拿锁
+        let remote = self.inner.lock().remote;
+        if let Some(handle) = remote {
+            return self.send_remote(handle, data, dont_wait);
+        }
+        let conn = self.get_conn()?;
+        let non_block = self.is_non_block(dont_wait);
+        let options = self.options();
+        let deadline = options.send_deadline();
+        let mut sent = 0;
+        loop {
+            let wakes = self.wake_count();
+            let mut buf_inner = conn.send.inner.lock();
+            if buf_inner.reset {
+                return Err(ErrorNo::ECONNRESET);
+            }
+            if buf_inner.fin || buf_inner.closed {
+                return Err(ErrorNo::EPIPE);
+            }
+            // 回环上没有单独的发送 buffer，对方接收 buffer 中的数据同时受自己的 SO_SNDBUF 限制
+            let space = min(buf_inner.capacity, options.sndbuf).saturating_sub(buf_inner.data.len());
+            let len = min(space, data.len() - sent);
+            buf_inner.data.extend(&data[sent..sent + len]);
+            if len > 0 {
+                conn.capture(TcpFlags::PSH | TcpFlags::ACK, &data[sent..sent + len]);
+            }
+            sent += len;
+            drop(buf_inner);
+            if len > 0 {
+                conn.send.waiters.wake_all();
+            }
+            if sent == data.len() {
+                return Ok(sent);
+            }
+            if non_block {
+                return if sent > 0 { Ok(sent) } else { Err(ErrorNo::EAGAIN) };
+            }
+            if let Err(err) = wait_until(self, wakes, deadline) {
+                return if sent > 0 { Ok(sent) } else { Err(err) };
+            }
+        }
+    }
+    /// 接收数据，返回读到的字节数，0 表示读到了文件尾。peek 为 true 时不取走数据
+    pub fn recv(&self, buf: &mut [u8], peek: bool, dont_wait: bool) -> Result<usize, ErrorNo> {
+        let remote = self.inner.lock().remote;
+        if let Some(handle) = remote {
+            return self.recv_remote(handle, buf, peek, dont_wait);
+        }
+        let conn = self.get_conn()?;
+        let non_block = self.is_non_block(dont_wait);
+        let deadline = self.options().recv_deadline();
+        loop {
+            let wakes = self.wake_count();
+            if self.inner.lock().read_shutdown {
+                return Ok(0);
+            }
+            let mut buf_inner = conn.recv.inner.lock();
+            if buf_inner.reset {
+                return Err(ErrorNo::ECONNRESET);
+            }
+            if !buf_inner.data.is_empty() || buf.is_empty() {
+                let len = min(buf_inner.data.len(), buf.len());
+                for (dst, src) in buf.iter_mut().zip(buf_inner.data.iter()) {
+                    *dst = *src;
+                }
+                if !peek {
+                    buf_inner.data.drain(..len);
+                    drop(buf_inner);
+                    // 腾出了空间
+                    conn.recv.waiters.wake_all();
+                }
+                return Ok(len);
+            }
+            if buf_inner.fin {
+                return Ok(0);
+            }
+            drop(buf_inner);
+            if non_block {
+                return Err(ErrorNo::EAGAIN);
+            }
+            wait_until(self, wakes, deadline)?;
+        }
+    }
+    /// 通过网卡上的连接发送数据。握手还没完成时等待
+    fn send_remote(&self, handle: SocketHandle, data: &[u8], dont_wait: bool) -> Result<usize, ErrorNo> {
+        let non_block = self.is_non_block(dont_wait);
+        let write_shutdown = self.inner.lock().write_shutdown;
+        let deadline = self.options().send_deadline();
+        loop {
+            let wakes = self.wake_count();
+            let ret = with_iface(|iface| {
+                let socket = iface.tcp(handle);
+                match socket.state() {
+                    state if is_connecting(state) => None,
+                    // 自己没有关闭写，连接却关闭了，说明被对方重置
+                    TcpState::Closed if !write_shutdown => Some(Err(ErrorNo::ECONNRESET)),
+                    _ if !socket.may_send() => Some(Err(ErrorNo::EPIPE)),
+                    _ if socket.can_send() => Some(socket.send_slice(data).map_err(|_| ErrorNo::EPIPE)),
+                    _ => None,
+                }
+            })
+            .unwrap();
+            if let Some(ret) = ret {
+                return ret;
+            }
+            if non_block {
+                return Err(ErrorNo::EAGAIN);
+            }
+            wait_until(self, wakes, deadline)?;
+        }
+    }
+    /// 通过网卡上的连接接收数据。握手还没完成时等待
+    fn recv_remote(&self, handle: SocketHandle, buf: &mut [u8], peek: bool, dont_wait: bool) -> Result<usize, ErrorNo> {
+        let non_block = self.is_non_block(dont_wait);
+        let deadline = self.options().recv_deadline();
+        loop {
+            let wakes = self.wake_count();
+            let (read_shutdown, write_shutdown) = {
+                let inner = self.inner.lock();
+                (inner.read_shutdown, inner.write_shutdown)
+            };
+            if read_shutdown {
+                return Ok(0);
+            }
+            let ret = with_iface(|iface| {
+                let socket = iface.tcp(handle);
+                if socket.can_recv() {
+                    let ret = if peek { socket.peek_slice(buf) } else { socket.recv_slice(buf) };
+                    return Some(ret.map_err(|_| ErrorNo::ECONNRESET));
+                }
+                match socket.state() {
+                    state if is_connecting(state) => None,
+                    TcpState::Closed if !write_shutdown => Some(Err(ErrorNo::ECONNRESET)),
+                    // 对方发送了 FIN，读到文件尾
+                    _ if !socket.may_recv() => Some(Ok(0)),
+                    _ => None,
+                }
+            })
+            .unwrap();
+            if let Some(ret) = ret {
+                return ret;
+            }
+            if non_block {
+                return Err(ErrorNo::EAGAIN);
+            }
+            wait_until(self, wakes, deadline)?;
+        }
+    }
+    /// 关闭连接的读(how = 0)、写(how = 1)或者读写(how = 2)。关闭写会向对方发送 FIN
+    pub fn shutdown(&self, how: usize) -> Result<(), ErrorNo> {
+        if how > 2 {
+            return Err(ErrorNo::EINVAL);
+        }
+        let mut inner = self.inner.lock();
+        if let Some(handle) = inner.remote {
+            if how != 1 {
+                inner.read_shutdown = true;
+            }
+            if how != 0 {
+                inner.write_shutdown = true;
+                with_iface(|iface| iface.tcp(handle).close());
+            }
+            return Ok(());
+        }
+        let conn = inner.conn.clone().ok_or(ErrorNo::ENOTCONN)?;
+        if how != 1 {
+            inner.read_shutdown = true;
+        }
+        drop(inner);
+        if how != 0 {
+            conn.send_fin();
+        }
+        conn.recv.waiters.wake_all();
+        Ok(())
+    }
+    /// 获取绑定的地址，没有绑定时为 0.0.0.0:0 或 [::]:0
+    pub fn local_addr(&self) -> (InetIp, u16) {
+        self.inner.lock().local_addr.unwrap_or((InetIp::unspecified(self.is_v6()), 0))
+    }
+    /// 获取对方的地址
+    pub fn peer_addr(&self) -> Result<(InetIp, u16), ErrorNo> {
+        let inner = self.inner.lock();
+        if inner.conn.is_none() && inner.remote.is_none() {
+            return Err(ErrorNo::ENOTCONN);
+        }
+        Ok(inner.peer_addr)
+    }
+}
+
+/// 网卡上的连接是否还在握手
+fn is_connecting(state: TcpState) -> bool {
+    matches!(state, TcpState::SynSent | TcpState::SynReceived)
+}
+
+impl File for TcpSocket {
+    /// 相当于不带 flags 的 recv
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        self.recv(buf, false, false).ok()
+    }
+    /// 相当于不带 flags 的 send
+    fn write(&self, buf: &[u8]) -> Option<usize> {
+        self.send(buf, false).ok()
+    }
+    /// 有数据、有等待 accept 的连接，或者读会立即返回时可读
+    fn ready_to_read(&self) -> bool {
+        let inner = self.inner.lock();
+        if let Some(handle) = inner.remote {
+            let read_shutdown = inner.read_shutdown;
+            drop(inner);
+            return read_shutdown
+                || with_iface(|iface| {
+                    let socket = iface.tcp(handle);
+                    socket.can_recv() || (!socket.may_recv() && !is_connecting(socket.state()))
+                })
+                .unwrap_or(true);
+        }
+        match inner.conn.clone() {
+            Some(conn) => {
+                let read_shutdown = inner.read_shutdown;
+                drop(inner);
+                let buf_inner = conn.recv.inner.lock();
+                read_shutdown || !buf_inner.data.is_empty() || buf_inner.fin || buf_inner.reset
+            }
+            None => {
+                drop(inner);
+                self.check_remote_listener();
+                !self.accept_queue.inner.lock().backlog.is_empty()
+            }
+        }
+    }
+    /// 对方的 buffer 还有空间时可写。连接已关闭时也算可写，因为写会立即返回错误
+    fn ready_to_write(&self) -> bool {
+        if let Some(handle) = self.inner.lock().remote {
+            return with_iface(|iface| {
+                let socket = iface.tcp(handle);
+                socket.can_send() || (!socket.may_send() && !is_connecting(socket.state()))
+            })
+            .unwrap_or(true);
+        }
+        match self.get_conn() {
+            Ok(conn) => {
+                let sndbuf = self.options().sndbuf;
+                let buf_inner = conn.send.inner.lock();
+                buf_inner.data.len() < min(buf_inner.capacity, sndbuf)
+                    || buf_inner.fin
+                    || buf_inner.closed
+                    || buf_inner.reset
+            }
+            Err(_) => false,
+        }
+    }
+    /// 对方不再发送数据时为 HUP
+    fn is_hang_up(&self) -> bool {
+        if let Some(handle) = self.inner.lock().remote {
+            return with_iface(|iface| {
+                let socket = iface.tcp(handle);
+                !socket.may_recv() && !is_connecting(socket.state())
+            })
+            .unwrap_or(true);
+        }
+        match self.get_conn() {
+            Ok(conn) => {
+                let buf_inner = conn.recv.inner.lock();
+                buf_inner.fin || buf_inner.reset
+            }
+            Err(_) => false,
+        }
+    }
+    /// 监听队列和连接的两个方向状态变化时都会唤醒等待的线程。
+    /// 网卡上的连接和监听则登记在 NET_WAITERS 上，协议栈轮询后唤醒
+    fn register_waiter(&self, tid: usize) -> bool {
+        self.accept_queue.waiters.register(tid);
+        if let Ok(conn) = self.get_conn() {
+            conn.recv.waiters.register(tid);
+            conn.send.waiters.register(tid);
+        }
+        if self.on_iface() {
+            NET_WAITERS.register(tid);
+        }
+        true
+    }
+    /// 同 register_waiter，但以独占方式登记。NET_WAITERS 是所有网卡上的 socket 共用的，所以仍按普通方式登记
+    fn register_exclusive_waiter(&self, tid: usize) -> bool {
+        self.accept_queue.waiters.register_exclusive(tid);
+        if let Ok(conn) = self.get_conn() {
+            conn.recv.waiters.register_exclusive(tid);
+            conn.send.waiters.register_exclusive(tid);
+        }
+        if self.on_iface() {
+            NET_WAITERS.register(tid);
+        }
+        true
+    }
+    /// 监听队列和连接的两个方向被唤醒的次数之和。网卡上的 socket 加上 NET_WAITERS 被唤醒的次数
+    fn wake_count(&self) -> Option<usize> {
+        let mut count = self.accept_queue.waiters.wake_count();
+        if let Ok(conn) = self.get_conn() {
+            count = count
+                .wrapping_add(conn.recv.waiters.wake_count())
+                .wrapping_add(conn.send.waiters.wake_count());
+        }
+        if self.on_iface() {
+            count = count.wrapping_add(NET_WAITERS.wake_count());
+        }
+        Some(count)
+    }
+    /// 取消等待
+    fn unregister_waiter(&self, tid: usize) {
+        self.accept_queue.waiters.unregister(tid);
+        if let Ok(conn) = self.get_conn() {
+            conn.recv.waiters.unregister(tid);
+            conn.send.waiters.unregister(tid);
+        }
+        NET_WAITERS.unregister(tid);
+    }
+    /// 获取文件状态信息
+    fn get_status(&self) -> OpenFlags {
+        self.inner.lock().flags
+    }
+    /// 设置文件状态信息，只能修改 NON_BLOCK 和 CLOEXEC
+    fn set_status(&self, flags: OpenFlags) -> bool {
+        let fl = &mut self.inner.lock().flags;
+        fl.set(OpenFlags::NON_BLOCK, flags.contains(OpenFlags::NON_BLOCK));
+        fl.set(OpenFlags::CLOEXEC, flags.contains(OpenFlags::CLOEXEC));
+        true
+    }
+    /// 设置 CLOEXEC 位
+    fn set_close_on_exec(&self, is_set: bool) -> bool {
+        self.inner.lock().flags.set(OpenFlags::CLOEXEC, is_set);
+        true
+    }
+}
+
+impl Drop for TcpSocket {
+    /// 关闭连接。设置了 SO_LINGER 且时间为 0，或者有没读完的数据时重置连接，否则向对方发送 FIN。
+    /// 没有 accept 的连接都会被重置。SO_LINGER 不为 0 时的等待在 close 中进行，见 linger
+    fn drop(&mut self) {
+        let abort = self.options().abort_on_close();
+        let remote = self.inner.lock().remote.take();
+        let remote_listener = self.accept_queue.inner.lock().remote_listener.take();
+        for handle in remote.into_iter().chain(remote_listener) {
+            with_iface(|iface| iface.release_tcp(handle, abort));
+        }
+        let conn = self.inner.lock().conn.take();
+        if let Some(conn) = conn {
+            let mut recv_inner = conn.recv.inner.lock();
+            recv_inner.closed = true;
+            let has_unread = !recv_inner.data.is_empty();
+            drop(recv_inner);
+            if abort || has_unread {
+                conn.reset();
+            } else {
+                conn.send_fin();
+                conn.recv.waiters.wake_all();
+            }
+        }
+        let mut queue_inner = self.accept_queue.inner.lock();
+        queue_inner.listening = false;
+        // 取出来在锁外释放，因为释放这些 socket 时也会进入这个函数
+        let backlog = core::mem::take(&mut queue_inner.backlog);
+        drop(queue_inner);
+        for server in backlog {
+            if let Some(conn) = server.inner.lock().conn.take() {
+                conn.reset();
+            }
+        }
+        self.accept_queue.waiters.wake_all();
+    }
+}
@@ -0,0 +1,499 @@
+//! UDP
+//!
+//! 每个 socket 有一个自己的接收队列 UdpEndpoint，发送数据报就是按目的端口找到绑定在这个端口上的接收队列，
+//! 把数据报复制进去。数据报保留边界，接收队列满时直接丢弃新的数据报，发送方不会阻塞。
+//!
+//! 绑定的端口记录在全局表 UDP_PORTS 里。设置了 SO_REUSEADDR 或 SO_REUSEPORT 的 socket 可以绑定到同一个端口，
+//! 此时发往广播地址的数据报会复制给其中的每一个，其他数据报只交给最后绑定的那个
+//!
+//! 绑定在 0.0.0.0 或网卡地址上的 socket 还会在网卡的协议栈(见 iface.rs)里绑定同一个端口，
+//! 发往其他地址的数据报从网卡发出，接收时两边的数据报都会读到
+//!
+//! AF_INET6 的 socket 和 TCP 一样，只能在回环 ::1 上使用 IPv6，通过 v4 映射的地址和 IPv4 通信
+
+use super::iface::{addr_to_endpoint, endpoint_to_addr, is_local_addr, with_iface, NET_WAITERS};
+use super::options::wait_until;
+use super::pcap::capture_udp;
+use super::{InetIp, SocketOptions, EPHEMERAL_PORT_END, EPHEMERAL_PORT_START};
+use crate::file::{File, OpenFlags, WaitQueue};
+use crate::syscall::ErrorNo;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::cmp::min;
+use lock::Mutex;
+use smoltcp::iface::SocketHandle;
+
+/// 一个 UDP 数据报最多能带的数据
+const MAX_DATAGRAM_SIZE: usize = 65507;
+/// 广播地址 255.255.255.255
+const BROADCAST_ADDR: InetIp = InetIp::V4(0xffffffff);
+
+/// 已绑定的端口到绑定在这个端口上的接收队列的映射，按绑定的先后排列。socket 关闭后，其中的 Weak 就会失效
+static UDP_PORTS: Mutex<BTreeMap<u16, Vec<Weak<UdpEndpoint>>>> = Mutex::new(BTreeMap::new());
+
+/// 接收队列中的一个数据报
+struct Datagram {
+    data: Vec<u8>,
+    /// 发送者的 ip 和端口
+    from: (InetIp, u16),
+}
+
+/// recv 的结果
+pub struct UdpRecv {
+    /// 读到的字节数
+    pub len: usize,
+    /// 数据报的实际长度，大于 len 说明数据报被截断了
+    pub msg_len: usize,
+    /// 发送者的 ip 和端口
+    pub from: (InetIp, u16),
+}
+
+/// socket 的接收队列
+struct UdpEndpoint {
+    inner: Mutex<EndpointInner>,
+    /// 等待数据报的线程
+    waiters: WaitQueue,
+}
+
+struct EndpointInner {
+    /// 收到但还没读出的数据报
+    datagrams: VecDeque<Datagram>,
+    /// datagrams 中数据的总长度
+    len: usize,
+    /// 接收队列中数据总长度的上限，即 SO_RCVBUF，超过时丢弃新的数据报
+    max_len: usize,
+    /// 绑定的 ip 和端口
+    local_addr: Option<(InetIp, u16)>,
+    /// connect 设置的默认目的地。设置后只接收来自这个地址的数据报
+    peer_addr: Option<(InetIp, u16)>,
+    /// 是否设置了 SO_REUSEADDR 或 SO_REUSEPORT
+    reuse: bool,
+    /// 是否设置了 IPV6_V6ONLY，即不收发 IPv4 的数据报
+    v6only: bool,
+    /// 已连接时发出的数据报没有接收者，下一次收发会得到 ECONNREFUSED
+    refused: bool,
+    /// 是否 shutdown 了读
+    recv_shutdown: bool,
+}
+
+impl UdpEndpoint {
+    /// 是否接收 from 发往 dest 的数据报
+    fn accepts(&self, dest: InetIp, from: (InetIp, u16)) -> bool {
+        let inner = self.inner.lock();
+        let ip_match = match inner.local_addr {
+            // 广播的数据报绑定在任意 IPv4 地址上的 socket 都能收到
+            Some((ip, _)) => ip.accepts(dest, inner.v6only) || (dest == BROADCAST_ADDR && !ip.is_v6()),
+            None => false,
+        };
+        ip_match && inner.peer_addr.map_or(true, |peer| peer == from)
+    }
+    /// 放入一个数据报。接收队列满时丢弃，返回是否放入
+    fn push(&self, data: &[u8], from: (InetIp, u16)) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.recv_shutdown || inner.len + data.len() > inner.max_len {
+            return false;
+        }
+        inner.len += data.len();
+        inner.datagrams.push_back(Datagram {
+            data: data.to_vec(),
+            from: from,
+        });
+        drop(inner);
+        self.waiters.wake_all();
+        true
+    }
+}
+
+/// UDP socket
+pub struct UdpSocket {
+    /// 是否是 AF_INET6 的 socket
+    v6: bool,
+    /// 自己的接收队列
+    endpoint: Arc<UdpEndpoint>,
+    inner: Mutex<UdpSocketInner>,
+}
+
+struct UdpSocketInner {
+    /// 文件状态，包括 NON_BLOCK 和 CLOEXEC
+    flags: OpenFlags,
+    /// 是否允许发送到广播地址，即 SO_BROADCAST
+    broadcast: bool,
+    /// 在网卡的协议栈中绑定的 socket
+    remote: Option<SocketHandle>,
+    /// socket 的选项
+    options: SocketOptions,
+}
+
+impl UdpSocket {
+    /// 新建一个未绑定的 socket。v6 表示是否是 AF_INET6 的 socket
+    pub fn new(flags: OpenFlags, v6: bool) -> Self {
+        let options = SocketOptions::new();
+        Self {
+            v6: v6,
+            endpoint: Arc::new(UdpEndpoint {
+                inner: Mutex::new(EndpointInner {
+                    datagrams: VecDeque::new(),
+                    len: 0,
+                    max_len: options.rcvbuf,
+                    local_addr: None,
+                    peer_addr: None,
+                    reuse: false,
+                    v6only: false,
+                    refused: false,
+                    recv_shutdown: false,
+                }),
+                waiters: WaitQueue::new(),
+            }),
+            inner: Mutex::new(UdpSocketInner {
+                flags: flags,
+                broadcast: false,
+                remote: None,
+                options: options,
+            }),
+        }
+    }
+    /// 是否非阻塞。dont_wait 为 MSG_DONTWAIT
+    fn is_non_block(&self, dont_wait: bool) -> bool {
+        dont_wait || self.inner.lock().flags.contains(OpenFlags::NON_BLOCK)
+    }
+    /// 设置 SO_REUSEADDR 或 SO_REUSEPORT，需要在绑定前设置
+    pub fn set_reuse(&self, reuse: bool) {
+        self.endpoint.inner.lock().reuse = reuse;
+    }
+    /// 设置 SO_BROADCAST
+    pub fn set_broadcast(&self, broadcast: bool) {
+        self.inner.lock().broadcast = broadcast;
+    }
+    /// 是否是 AF_INET6 的 socket
+    pub fn is_v6(&self) -> bool {
+        self.v6
+    }
+    /// 获取 socket 的选项
+    pub fn options(&self) -> SocketOptions {
+        self.inner.lock().options
+    }
+    /// 设置 socket 的选项。SO_RCVBUF 立即限制接收队列，已经收到的数据报不受影响
+    pub fn set_options(&self, options: SocketOptions) {
+        self.inner.lock().options = options;
+        self.endpoint.inner.lock().max_len = options.rcvbuf;
+    }
+    /// 设置 IPV6_V6ONLY，需要在绑定前设置
+    pub fn set_v6only(&self, v6only: bool) -> Result<(), ErrorNo> {
+        let mut inner = self.endpoint.inner.lock();
+        if inner.local_addr.is_some() {
+            return Err(ErrorNo::EINVAL);
+        }
+        inner.v6only = v6only;
+        Ok(())
+    }
+    /// 绑定地址。端口为 0 时自动分配一个
+    pub fn bind(&self, ip: InetIp, port: u16) -> Result<(), ErrorNo> {
+        let (bound, reuse, v6only) = {
+            let inner = self.endpoint.inner.lock();
+            (inner.local_addr.is_some(), inner.reuse, inner.v6only)
+        };
+        if v6only && !ip.is_v6() {
+            return Err(ErrorNo::EINVAL);
+        }
+        if !is_local_addr(ip) {
+            return Err(ErrorNo::EADDRNOTAVAIL);
+        }
+        if bound {
+            return Err(ErrorNo::EINVAL);
+        }
+        let mut ports = UDP_PORTS.lock();
+        // 端口上已有的 socket 和新的 socket 都允许重用时才能共用端口
+        let can_bind = |port: &u16| {
+            ports.get(port).map_or(true, |endpoints| {
+                endpoints
+                    .iter()
+                    .filter_map(|ep| ep.upgrade())
+                    .all(|ep| reuse && ep.inner.lock().reuse)
+            })
+        };
+        let port = if port == 0 {
+            (EPHEMERAL_PORT_START..=EPHEMERAL_PORT_END)
+                .find(|port| ports.get(port).map_or(true, |endpoints| endpoints.iter().all(|ep| ep.strong_count() == 0)))
+                .ok_or(ErrorNo::EADDRINUSE)?
+        } else if can_bind(&port) {
+            port
+        } else {
+            return Err(ErrorNo::EADDRINUSE);
+        };
+        let endpoints = ports.entry(port).or_insert_with(Vec::new);
+        endpoints.retain(|ep| ep.strong_count() > 0);
+        endpoints.push(Arc::downgrade(&self.endpoint));
+        drop(ports);
+        self.endpoint.inner.lock().local_addr = Some((ip, port));
+        // 绑定在 0.0.0.0、网卡的地址或者接受 IPv4 的 :: 上时，同时在网卡上绑定
+        let on_iface = match ip {
+            InetIp::V4(addr) => addr == 0 || !ip.is_loopback(),
+            InetIp::V6(addr) => addr == 0 && !v6only,
+        };
+        if on_iface {
+            self.inner.lock().remote = with_iface(|iface| iface.bind_udp(port)).transpose()?;
+        }
+        Ok(())
+    }
+    /// 设置默认的目的地，之后只接收来自这个地址的数据报。peer 为 None 时取消
+    pub fn connect(&self, peer: Option<(InetIp, u16)>) -> Result<(), ErrorNo> {
+        if let Some((ip, _)) = peer {
+            if self.endpoint.inner.lock().v6only && !ip.is_v6() {
+                return Err(ErrorNo::ENETUNREACH);
+            }
+            if self.endpoint.inner.lock().local_addr.is_none() {
+                self.bind(InetIp::unspecified(self.v6), 0)?;
+            }
+            // 只有 IPv4 的地址可以从网卡发出
+            if !is_local_addr(ip) && (ip.is_v6() || self.inner.lock().remote.is_none()) {
+                return Err(ErrorNo::ENETUNREACH);
+            }
+        }
+        let mut inner = self.endpoint.inner.lock();
+        inner.peer_addr = peer.map(|(ip, port)| (ip.or_loopback(), port));
+        inner.refused = false;
+        Ok(())
+    }
+    /// 检查并清除已连接时记录的错误，也用于 SO_ERROR
+    pub fn take_error(&self) -> Result<(), ErrorNo> {
+        let mut inner = self.endpoint.inner.lock();
+        if inner.refused {
+            inner.refused = false;
+            return Err(ErrorNo::ECONNREFUSED);
+        }
+        Ok(())
+    }
+    /// 发送一个数据报，dest 为 None 时发送给 connect 设置的地址。没有绑定时自动分配端口
+    pub fn send(&self, data: &[u8], dest: Option<(InetIp, u16)>) -> Result<usize, ErrorNo> {
+        self.take_error()?;
+        // 数据报必须一次发出，不能超过 SO_SNDBUF
+        if data.len() > min(MAX_DATAGRAM_SIZE, self.options().sndbuf) {
+            return Err(ErrorNo::EMSGSIZE);
+        }
+        let connected = self.endpoint.inner.lock().peer_addr;
+        let (ip, port) = dest.or(connected).ok_or(ErrorNo::EDESTADDRREQ)?;
+        if ip == BROADCAST_ADDR && !self.inner.lock().broadcast {
+            return Err(ErrorNo::EACCES);
+        }
+        if self.endpoint.inner.lock().v6only && !ip.is_v6() {
+            return Err(ErrorNo::ENETUNREACH);
+        }
+        if self.endpoint.inner.lock().local_addr.is_none() {
+            self.bind(InetIp::unspecified(self.v6), 0)?;
+        }
+        if ip == BROADCAST_ADDR || !is_local_addr(ip) {
+            let remote = self.inner.lock().remote;
+            match (remote, ip) {
+                (Some(handle), InetIp::V4(addr)) => {
+                    with_iface(|iface| iface.udp(handle).send_slice(data, addr_to_endpoint((addr, port))))
+                        .unwrap_or(Err(smoltcp::Error::Unaddressable))
+                        .map_err(|e| match e {
+                            // 网卡的发送缓冲区满了
+                            smoltcp::Error::Exhausted => ErrorNo::ENOBUFS,
+                            smoltcp::Error::Truncated => ErrorNo::EMSGSIZE,
+                            _ => ErrorNo::ENETUNREACH,
+                        })?;
+                }
+                _ if ip != BROADCAST_ADDR => return Err(ErrorNo::ENETUNREACH),
+                _ => {}
+            }
+            if ip != BROADCAST_ADDR {
+                return Ok(data.len());
+            }
+        }
+        // 回环上的数据报由本机发出，所以对方看到的地址总是 127.0.0.1 或 ::1
+        let dest_ip = ip.or_loopback();
+        let from = (InetIp::loopback(dest_ip.is_v6()), self.endpoint.inner.lock().local_addr.unwrap().1);
+        capture_udp(from, (dest_ip, port), data);
+        let targets: Vec<Arc<UdpEndpoint>> = match UDP_PORTS.lock().get(&port) {
+            Some(endpoints) => endpoints.iter().filter_map(|ep| ep.upgrade()).collect(),
+            None => Vec::new(),
+        };
+        let targets = targets.into_iter().filter(|ep| ep.accepts(dest_ip, from));
+        let delivered = if ip == BROADCAST_ADDR {
+            targets.fold(false, |delivered, ep| ep.push(data, from) || delivered)
+        } else {
+            // 交给最后绑定的 socket
+            targets.last().map_or(false, |ep| ep.push(data, from))
+        };
+        if !delivered && connected.is_some() && dest.is_none() {
+            // 相当于收到了 ICMP 端口不可达
+            self.endpoint.inner.lock().refused = true;
+        }
+        Ok(data.len())
+    }
+    /// 接收一个数据报，放不下的部分被丢弃。peek 为 true 时不取走数据报
+    pub fn recv(&self, buf: &mut [u8], peek: bool, dont_wait: bool) -> Result<UdpRecv, ErrorNo> {
+        let non_block = self.is_non_block(dont_wait);
+        let deadline = self.options().recv_deadline();
+        loop {
+            let wakes = self.wake_count();
+            self.take_error()?;
+            let mut inner = self.endpoint.inner.lock();
+            if let Some(datagram) = inner.datagrams.front() {
+                let len = min(datagram.data.len(), buf.len());
+                buf[..len].copy_from_slice(&datagram.data[..len]);
+                let ret = UdpRecv {
+                    len: len,
+                    msg_len: datagram.data.len(),
+                    from: datagram.from,
+                };
+                if !peek {
+                    inner.len -= ret.msg_len;
+                    inner.datagrams.pop_front();
+                }
+                return Ok(ret);
+            }
+            if inner.recv_shutdown {
+                return Ok(UdpRecv {
+                    len: 0,
+                    msg_len: 0,
+                    from: (InetIp::unspecified(self.v6), 0),
+                });
+            }
+            let peer = inner.peer_addr;
+            drop(inner);
+            if let Some(ret) = self.recv_remote(buf, peek, peer) {
+                return Ok(ret);
+            }
+            if non_block {
+                return Err(ErrorNo::EAGAIN);
+            }
+            wait_until(self, wakes, deadline)?;
+        }
+    }
+    /// 从网卡上接收一个数据报。已连接时丢弃不是来自 peer 的数据报
+    fn recv_remote(&self, buf: &mut [u8], peek: bool, peer: Option<(InetIp, u16)>) -> Option<UdpRecv> {
+        let handle = self.inner.lock().remote?;
+        with_iface(|iface| {
+            let socket = iface.udp(handle);
+            loop {
+                let (data, from) = socket.peek().ok()?;
+                let (ip, port) = endpoint_to_addr(*from);
+                let from = (InetIp::V4(ip), port);
+                if peer.map_or(true, |peer| peer == from) {
+                    let len = min(data.len(), buf.len());
+                    buf[..len].copy_from_slice(&data[..len]);
+                    let ret = UdpRecv {
+                        len: len,
+                        msg_len: data.len(),
+                        from: from,
+                    };
+                    if !peek {
+                        let _ = socket.recv();
+                    }
+                    return Some(ret);
+                }
+                let _ = socket.recv();
+            }
+        })
+        .flatten()
+    }
+    /// 关闭读(how = 0 或 2)。UDP 没有连接，关闭写没有效果
+    pub fn shutdown(&self, how: usize) -> Result<(), ErrorNo> {
+        if how > 2 {
+            return Err(ErrorNo::EINVAL);
+        }
+        let mut inner = self.endpoint.inner.lock();
+        if inner.peer_addr.is_none() {
+            return Err(ErrorNo::ENOTCONN);
+        }
+        if how != 1 {
+            inner.recv_shutdown = true;
+        }
+        drop(inner);
+        self.endpoint.waiters.wake_all();
+        Ok(())
+    }
+    /// 获取绑定的地址，没有绑定时为 0.0.0.0:0 或 [::]:0
+    pub fn local_addr(&self) -> (InetIp, u16) {
+        self.endpoint.inner.lock().local_addr.unwrap_or((InetIp::unspecified(self.v6), 0))
+    }
+    /// 获取 connect 设置的地址
+    pub fn peer_addr(&self) -> Result<(InetIp, u16), ErrorNo> {
+        self.endpoint.inner.lock().peer_addr.ok_or(ErrorNo::ENOTCONN)
+    }
+}
+
+impl File for UdpSocket {
+    /// 相当于不带 flags 的 recv
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        self.recv(buf, false, false).ok().map(|ret| ret.len)
+    }
+    /// 相当于不带目的地址的 send
+    fn write(&self, buf: &[u8]) -> Option<usize> {
+        self.send(buf, None).ok()
+    }
+    /// 有数据报或者有错误要报告时可读
+    fn ready_to_read(&self) -> bool {
+        let inner = self.endpoint.inner.lock();
+        if !inner.datagrams.is_empty() || inner.refused || inner.recv_shutdown {
+            return true;
+        }
+        drop(inner);
+        match self.inner.lock().remote {
+            Some(handle) => with_iface(|iface| iface.udp(handle).can_recv()).unwrap_or(false),
+            None => false,
+        }
+    }
+    /// 发送不会阻塞，所以总是可写
+    fn ready_to_write(&self) -> bool {
+        true
+    }
+    /// 回环上收到数据报时会唤醒等待的线程。网卡上的 socket 另外登记在 NET_WAITERS 上，协议栈轮询后唤醒
+    fn register_waiter(&self, tid: usize) -> bool {
+        self.endpoint.waiters.register(tid);
+        if self.inner.lock().remote.is_some() {
+            NET_WAITERS.register(tid);
+        }
+        true
+    }
+    /// 独占等待。NET_WAITERS 是所有网卡上的 socket 共用的，所以仍按普通方式登记
+    fn register_exclusive_waiter(&self, tid: usize) -> bool {
+        self.endpoint.waiters.register_exclusive(tid);
+        if self.inner.lock().remote.is_some() {
+            NET_WAITERS.register(tid);
+        }
+        true
+    }
+    /// 收到数据报的次数。网卡上的 socket 加上 NET_WAITERS 被唤醒的次数
+    fn wake_count(&self) -> Option<usize> {
+        let count = self.endpoint.waiters.wake_count();
+        if self.inner.lock().remote.is_some() {
+            Some(count.wrapping_add(NET_WAITERS.wake_count()))
+        } else {
+            Some(count)
+        }
+    }
+    /// 取消等待
+    fn unregister_waiter(&self, tid: usize) {
+        self.endpoint.waiters.unregister(tid);
+        NET_WAITERS.unregister(tid);
+    }
+    /// 获取文件状态信息
+    fn get_status(&self) -> OpenFlags {
+        self.inner.lock().flags
+    }
+    /// 设置文件状态信息，只能修改 NON_BLOCK 和 CLOEXEC
+    fn set_status(&self, flags: OpenFlags) -> bool {
+        let fl = &mut self.inner.lock().flags;
+        fl.set(OpenFlags::NON_BLOCK, flags.contains(OpenFlags::NON_BLOCK));
+        fl.set(OpenFlags::CLOEXEC, flags.contains(OpenFlags::CLOEXEC));
+        true
+    }
+    /// 设置 CLOEXEC 位
+    fn set_close_on_exec(&self, is_set: bool) -> bool {
+        self.inner.lock().flags.set(OpenFlags::CLOEXEC, is_set);
+        true
+    }
+}
+
+impl Drop for UdpSocket {
+    /// 释放网卡上绑定的 socket
+    fn drop(&mut self) {
+        if let Some(handle) = self.inner.lock().remote.take() {
+            with_iface(|iface| iface.release_udp(handle));
+        }
+    }
+}
@@ -0,0 +1,665 @@
+//! unix 域 socket (AF_UNIX)
+//!
+//! 每个 socket 有一个自己的接收端 UnixEndpoint，发送消息就是把消息放进对方的接收端里。
+//! - SOCK_STREAM：面向连接的字节流。读取时会合并多条消息，但不会跨过带有辅助数据的消息
+//! - SOCK_SEQPACKET：面向连接，但保留消息边界
+//! - SOCK_DGRAM：无连接，发送时按地址找到对方的接收端
+//!
+//! 绑定的地址记录在全局表 UNIX_ADDR_MAP 里，可以是文件系统中的路径，也可以是抽象命名空间中的名字。
+//! 绑定到路径时会在文件系统中创建一个同名的空文件。和 Linux 一样，socket 关闭后这个文件不会被删除，
+//! 需要用户 unlink 之后才能再次绑定这个路径
+
+use super::options::wait_until;
+use super::{SocketOptions, SocketType, UnixAddr};
+use crate::file::{check_file_exists, open_file, File, OpenFlags, WaitQueue};
+use crate::syscall::ErrorNo;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::cmp::min;
+use lock::Mutex;
+
+/// 监听队列长度的上限，即 SOMAXCONN
+const MAX_BACKLOG: usize = 4096;
+
+/// 已绑定的地址到对应 socket 接收端的映射。socket 关闭后，其中的 Weak 就会失效
+static UNIX_ADDR_MAP: Mutex<BTreeMap<UnixAddr, Weak<UnixEndpoint>>> = Mutex::new(BTreeMap::new());
+
+/// 进程的身份，即 struct ucred，通过 SCM_CREDENTIALS 传递
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct UCred {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// recv 的结果
+pub struct UnixRecv {
+    /// 读到的字节数
+    pub len: usize,
+    /// 消息的实际长度。对 SOCK_DGRAM 和 SOCK_SEQPACKET，大于 len 说明消息被截断了
+    pub msg_len: usize,
+    /// 随消息传来的文件(SCM_RIGHTS)
+    pub rights: Vec<Arc<dyn File>>,
+    /// 随消息传来的发送者身份(SCM_CREDENTIALS)
+    pub cred: Option<UCred>,
+    /// 发送者的地址
+    pub from: UnixAddr,
+}
+
+/// 接收端中的一条消息
+struct UnixMessage {
+    data: Vec<u8>,
+    /// data 中已经被流式 socket 读走的长度
+    offset: usize,
+    /// 随消息传递的文件
+    rights: Vec<Arc<dyn File>>,
+    /// 发送者的身份
+    cred: Option<UCred>,
+    /// 发送者绑定的地址
+    from: UnixAddr,
+}
+
+impl UnixMessage {
+    /// 是否带有辅助数据
+    fn has_ancillary(&self) -> bool {
+        !self.rights.is_empty() || self.cred.is_some()
+    }
+}
+
+/// 接收端内部可变部分
+struct EndpointInner {
+    /// 收到但还没读出的消息
+    messages: VecDeque<UnixMessage>,
+    /// messages 中还没读出的数据总长度
+    len: usize,
+    /// len 的上限，即所属 socket 的 SO_RCVBUF
+    max_len: usize,
+    /// 等待 accept 的连接，只有监听中的 socket 使用
+    backlog: VecDeque<Arc<UnixSocket>>,
+    /// 是否正在监听
+    listening: bool,
+    /// 监听队列的长度上限
+    max_backlog: usize,
+    /// 这一端不再接收数据(已关闭或者 shutdown 了读)，对方再发送会得到 EPIPE
+    recv_shutdown: bool,
+    /// 这一端不再发送数据(已关闭或者 shutdown 了写)，对方读完已有数据后会读到文件尾
+    send_shutdown: bool,
+}
+
+/// socket 的接收端。连接到这个 socket 或者给它发送过数据报的 socket 会持有它
+pub struct UnixEndpoint {
+    /// 所属 socket 的类型，只有相同类型的 socket 才能互相连接
+    stype: SocketType,
+    inner: Mutex<EndpointInner>,
+    /// 等待这个接收端状态变化的线程，包括等待读的线程和等待对方腾出空间的发送者
+    waiters: WaitQueue,
+}
+
+impl UnixEndpoint {
+    fn new(stype: SocketType) -> Arc<Self> {
+        Arc::new(Self {
+            stype: stype,
+            inner: Mutex::new(EndpointInner {
+                messages: VecDeque::new(),
+                len: 0,
+                max_len: SocketOptions::new().rcvbuf,
+                backlog: VecDeque::new(),
+                listening: false,
+                max_backlog: 0,
+                recv_shutdown: false,
+                send_shutdown: false,
+            }),
+            waiters: WaitQueue::new(),
+        })
+    }
+    /// 这一端是否已不再发送数据
+    fn is_send_shutdown(&self) -> bool {
+        self.inner.lock().send_shutdown
+    }
+}
+
+/// unix 域 socket
+pub struct UnixSocket {
+    /// 连接类型，只会是 SOCK_STREAM、SOCK_DGRAM 或者 SOCK_SEQPACKET
+    stype: SocketType,
+    /// 自己的接收端
+    endpoint: Arc<UnixEndpoint>,
+    inner: Mutex<UnixSocketInner>,
+}
+
+struct UnixSocketInner {
+    /// 文件状态，包括 NON_BLOCK 和 CLOEXEC
+    flags: OpenFlags,
+    /// 绑定的地址
+    local_addr: UnixAddr,
+    /// 对方的接收端。面向连接的 socket 连接后才有，数据报 socket connect 后作为默认的目的地
+    peer: Option<Arc<UnixEndpoint>>,
+    /// 对方的地址
+    peer_addr: UnixAddr,
+    /// socket 的选项
+    options: SocketOptions,
+}
+
+impl UnixSocket {
+    /// 新建一个未绑定、未连接的 socket
+    pub fn new(stype: SocketType, flags: OpenFlags) -> Self {
+        Self {
+            stype: stype,
+            endpoint: UnixEndpoint::new(stype),
+            inner: Mutex::new(UnixSocketInner {
+                flags: flags,
+                local_addr: UnixAddr::Unnamed,
+                peer: None,
+                peer_addr: UnixAddr::Unnamed,
+                options: SocketOptions::new(),
+            }),
+        }
+    }
+    /// 新建一对互相连接的 socket，用于 socketpair
+    pub fn new_pair(stype: SocketType, flags: OpenFlags) -> (Self, Self) {
+        let sock1 = Self::new(stype, flags);
+        let sock2 = Self::new(stype, flags);
+        sock1.inner.lock().peer = Some(sock2.endpoint.clone());
+        sock2.inner.lock().peer = Some(sock1.endpoint.clone());
+        (sock1, sock2)
+    }
+    /// 是否面向连接
+    fn is_connection_oriented(&self) -> bool {
+        self.stype != SocketType::SOCK_DGRAM
+    }
+    /// 是否非阻塞。dont_wait 为 MSG_DONTWAIT
+    fn is_non_block(&self, dont_wait: bool) -> bool {
+        dont_wait || self.inner.lock().flags.contains(OpenFlags::NON_BLOCK)
+    }
+    /// socket 的类型
+    pub fn socket_type(&self) -> SocketType {
+        self.stype
+    }
+    /// 获取 socket 的选项
+    pub fn options(&self) -> SocketOptions {
+        self.inner.lock().options
+    }
+    /// 设置 socket 的选项。SO_RCVBUF 立即限制对方之后放进接收端的数据
+    pub fn set_options(&self, options: SocketOptions) {
+        self.inner.lock().options = options;
+        self.endpoint.inner.lock().max_len = options.rcvbuf;
+        // 可能腾出了空间
+        self.endpoint.waiters.wake_all();
+    }
+    /// 绑定地址。地址为空时自动在抽象命名空间中分配一个名字
+    pub fn bind(&self, addr: UnixAddr) -> Result<(), ErrorNo> {
+        let mut inner = self.inner.lock();
+        if inner.local_addr != UnixAddr::Unnamed {
+            return Err(ErrorNo::EINVAL);
+        }
+        let mut addr_map = UNIX_ADDR_MAP.lock();
+        let addr = match addr {
+            UnixAddr::Unnamed => (0..0x100000)
+                .map(|id| UnixAddr::Abstract(format!("{:05x}", id).into_bytes()))
+                .find(|addr| addr_map.get(addr).map_or(true, |ep| ep.strong_count() == 0))
+                .ok_or(ErrorNo::EADDRINUSE)?,
+            UnixAddr::Path(path) => {
+                // 路径已经存在时，不管是不是 socket 都不能绑定
+                if check_file_exists("./", &path[2..]) {
+                    return Err(ErrorNo::EADDRINUSE);
+                }
+                if open_file("./", &path[2..], OpenFlags::CREATE).is_none() {
+                    return Err(ErrorNo::ENOENT);
+                }
+                UnixAddr::Path(path)
+            }
+            UnixAddr::Abstract(name) => {
+                let addr = UnixAddr::Abstract(name);
+                if addr_map.get(&addr).map_or(false, |ep| ep.strong_count() > 0) {
+                    return Err(ErrorNo::EADDRINUSE);
+                }
+                addr
+            }
+        };
+        addr_map.insert(addr.clone(), Arc::downgrade(&self.endpoint));
+        inner.local_addr = addr;
+        Ok(())
+    }
+    /// 开始监听连接
+    pub fn listen(&self, backlog: usize) -> Result<(), ErrorNo> {
+        if !self.is_connection_oriented() {
+            return Err(ErrorNo::EOPNOTSUPP);
+        }
+        let inner = self.inner.lock();
+        if inner.local_addr == UnixAddr::Unnamed || inner.peer.is_some() {
+            return Err(ErrorNo::EINVAL);
+        }
+        let mut ep_inner = self.endpoint.inner.lock();
+        ep_inner.listening = true;
+        ep_inner.max_backlog = backlog.clamp(1, MAX_BACKLOG);
+        Ok(())
+    }
+    /// 连接到 addr。对数据报 socket 来说只是设置默认的目的地
+    pub fn connect(&self, addr: &UnixAddr) -> Result<(), ErrorNo> {
+        let target = lookup(addr)?;
+        if target.stype != self.stype {
+            return Err(ErrorNo::EPROTOTYPE);
+        }
+        if !self.is_connection_oriented() {
+            let mut inner = self.inner.lock();
+            inner.peer = Some(target);
+            inner.peer_addr = addr.clone();
+            return Ok(());
+        }
+        if self.inner.lock().peer.is_some() {
+            return Err(ErrorNo::EISCONN);
+        }
+        if self.endpoint.inner.lock().listening {
+            return Err(ErrorNo::EINVAL);
+        }
+        let local_addr = self.inner.lock().local_addr.clone();
+        let deadline = self.options().send_deadline();
+        loop {
+            let wakes = target.waiters.wake_count();
+            let mut target_inner = target.inner.lock();
+            if !target_inner.listening {
+                return Err(ErrorNo::ECONNREFUSED);
+            }
+            if target_inner.backlog.len() < target_inner.max_backlog {
+                // 为这个连接新建一个服务端的 socket，等 accept 时取出
+                let server = UnixSocket::new(self.stype, OpenFlags::RDWR);
+                {
+                    let mut server_inner = server.inner.lock();
+                    server_inner.local_addr = addr.clone();
+                    server_inner.peer = Some(self.endpoint.clone());
+                    server_inner.peer_addr = local_addr;
+                }
+                let server_endpoint = server.endpoint.clone();
+                target_inner.backlog.push_back(Arc::new(server));
+                drop(target_inner);
+                let mut inner = self.inner.lock();
+                inner.peer = Some(server_endpoint);
+                inner.peer_addr = addr.clone();
+                drop(inner);
+                target.waiters.wake_all();
+                return Ok(());
+            }
+            drop(target_inner);
+            if self.is_non_block(false) {
+                return Err(ErrorNo::EAGAIN);
+            }
+            target.waiters.wait(wakes, deadline)?;
+        }
+    }
+    /// 取出一个等待中的连接。socket 必须正在监听
+    pub fn accept(&self) -> Result<Arc<UnixSocket>, ErrorNo> {
+        let deadline = self.options().recv_deadline();
+        loop {
+            let wakes = self.wake_count();
+            let mut ep_inner = self.endpoint.inner.lock();
+            if !ep_inner.listening {
+                return Err(ErrorNo::EINVAL);
+            }
+            if let Some(server) = ep_inner.backlog.pop_front() {
+                drop(ep_inner);
+                // 监听队列腾出了位置
+                self.endpoint.waiters.wake_all();
+                return Ok(server);
+            }
+            drop(ep_inner);
+            if self.is_non_block(false) {
+                return Err(ErrorNo::EAGAIN);
+            }
+            wait_until(self, wakes, deadline)?;
+        }
+    }
+    /// 发送消息，返回发送的字节数。rights 和 cred 是随消息发送的辅助数据。
+    ///
+    /// dest 只对数据报 socket 有效，为 None 时发送给 connect 设置的地址
+    pub fn send(
+        &self,
+        data: &[u8],
+        rights: Vec<Arc<dyn File>>,
+        cred: Option<UCred>,
+        dest: Option<&UnixAddr>,
+        dont_wait: bool,
+    ) -> Result<usize, ErrorNo> {
+        let inner = self.inner.lock();
+        let from = inner.local_addr.clone();
+        let target = match dest {
+            Some(addr) if !self.is_connection_oriented() => lookup(addr)?,
+            _ => inner.peer.clone().ok_or(ErrorNo::ENOTCONN)?,
+        };
+        drop(inner);
+        if target.stype != self.stype {
+            return Err(ErrorNo::EPROTOTYPE);
+        }
+        if self.endpoint.is_send_shutdown() {
+            return Err(ErrorNo::EPIPE);
+        }
+        let non_block = self.is_non_block(dont_wait);
+        let options = self.options();
+        let deadline = options.send_deadline();
+        // 流式 socket 可以只发送一部分。其他类型的消息必须一次放进对方的接收端
+        let is_stream = self.stype == SocketType::SOCK_STREAM;
+        if !is_stream && data.len() > options.sndbuf {
+            return Err(ErrorNo::EMSGSIZE);
+        }
+        if is_stream && data.is_empty() {
+            return Ok(0);
+        }
+        let mut rights = Some(rights);
+        let mut sent = 0;
+        loop {
+            let wakes = target.waiters.wake_count();
+            let mut target_inner = target.inner.lock();
+            if target_inner.recv_shutdown {
+                return if self.is_connection_oriented() {
+                    Err(ErrorNo::EPIPE)
+                } else {
+                    Err(ErrorNo::ECONNREFUSED)
+                };
+            }
+            // 对方接收端中的数据同时受对方的 SO_RCVBUF 和自己的 SO_SNDBUF 限制
+            let limit = min(target_inner.max_len, options.sndbuf);
+            if !is_stream && data.len() > limit {
+                return Err(ErrorNo::EMSGSIZE);
+            }
+            let space = limit.saturating_sub(target_inner.len);
+            let len = if is_stream { min(space, data.len() - sent) } else { data.len() };
+            if (is_stream && len > 0) || (!is_stream && len <= space) {
+                target_inner.messages.push_back(UnixMessage {
+                    data: data[sent..sent + len].to_vec(),
+                    offset: 0,
+                    // 辅助数据只随第一段发送
+                    rights: rights.take().unwrap_or_default(),
+                    cred: if sent == 0 { cred } else { None },
+                    from: from.clone(),
+                });
+                target_inner.len += len;
+                sent += len;
+                drop(target_inner);
+                target.waiters.wake_all();
+                if sent == data.len() {
+                    return Ok(sent);
+                }
+                continue;
+            }
+            drop(target_inner);
+            if non_block {
+                return if sent > 0 { Ok(sent) } else { Err(ErrorNo::EAGAIN) };
+            }
+            if let Err(err) = target.waiters.wait(wakes, deadline) {
+                return if sent > 0 { Ok(sent) } else { Err(err) };
+            }
+        }
+    }
+    /// 接收消息。peek 为 true 时不取走消息
+    pub fn recv(&self, buf: &mut [u8], peek: bool, dont_wait: bool) -> Result<UnixRecv, ErrorNo> {
+        let non_block = self.is_non_block(dont_wait);
+        let deadline = self.options().recv_deadline();
+        loop {
+            let wakes = self.wake_count();
+            let peer = self.inner.lock().peer.clone();
+            if self.is_connection_oriented() && peer.is_none() {
+                return Err(if self.endpoint.inner.lock().listening {
+                    ErrorNo::EINVAL
+                } else {
+                    ErrorNo::ENOTCONN
+                });
+            }
+            // 不能同时拿着两个接收端的锁，所以先检查对方是否已关闭。数据报 socket 没有连接，不会读到文件尾
+            let peer_closed = self.is_connection_oriented() && peer.map_or(false, |peer| peer.is_send_shutdown());
+            let mut ep_inner = self.endpoint.inner.lock();
+            if !ep_inner.messages.is_empty() {
+                let ret = if self.stype == SocketType::SOCK_STREAM {
+                    Self::recv_stream(&mut ep_inner, buf, peek)
+                } else {
+                    Self::recv_packet(&mut ep_inner, buf, peek)
+                };
+                drop(ep_inner);
+                if !peek {
+                    // 接收端腾出了空间
+                    self.endpoint.waiters.wake_all();
+                }
+                return Ok(ret);
+            }
+            if peer_closed || ep_inner.recv_shutdown {
+                // 读到文件尾
+                return Ok(UnixRecv {
+                    len: 0,
+                    msg_len: 0,
+                    rights: Vec::new(),
+                    cred: None,
+                    from: UnixAddr::Unnamed,
+                });
+            }
+            drop(ep_inner);
+            if non_block {
+                return Err(ErrorNo::EAGAIN);
+            }
+            wait_until(self, wakes, deadline)?;
+        }
+    }
+    /// 从流中读出尽可能多的数据，但不跨过带有辅助数据的消息
+    fn recv_stream(ep_inner: &mut EndpointInner, buf: &mut [u8], peek: bool) -> UnixRecv {
+        let mut ret = UnixRecv {
+            len: 0,
+            msg_len: 0,
+            rights: Vec::new(),
+            cred: None,
+            from: UnixAddr::Unnamed,
+        };
+        for (i, msg) in ep_inner.messages.iter_mut().enumerate() {
+            if ret.len == buf.len() || (i > 0 && msg.has_ancillary()) {
+                break;
+            }
+            if i == 0 {
+                ret.rights = if peek { msg.rights.clone() } else { core::mem::take(&mut msg.rights) };
+                ret.cred = msg.cred;
+                ret.from = msg.from.clone();
+                if !peek {
+                    msg.cred = None;
+                }
+            }
+            let len = min(msg.data.len() - msg.offset, buf.len() - ret.len);
+            buf[ret.len..ret.len + len].copy_from_slice(&msg.data[msg.offset..msg.offset + len]);
+            ret.len += len;
+            if !peek {
+                msg.offset += len;
+            }
+        }
+        ret.msg_len = ret.len;
+        if !peek {
+            ep_inner.len -= ret.len;
+            while ep_inner.messages.front().map_or(false, |msg| msg.offset == msg.data.len()) {
+                ep_inner.messages.pop_front();
+            }
+        }
+        ret
+    }
+    /// 读出一条完整的消息，放不下的部分被丢弃
+    fn recv_packet(ep_inner: &mut EndpointInner, buf: &mut [u8], peek: bool) -> UnixRecv {
+        let msg = if peek {
+            let msg = ep_inner.messages.front().unwrap();
+            UnixMessage {
+                data: msg.data.clone(),
+                offset: 0,
+                rights: msg.rights.clone(),
+                cred: msg.cred,
+                from: msg.from.clone(),
+            }
+        } else {
+            let msg = ep_inner.messages.pop_front().unwrap();
+            ep_inner.len -= msg.data.len();
+            msg
+        };
+        let len = min(msg.data.len(), buf.len());
+        buf[..len].copy_from_slice(&msg.data[..len]);
+        UnixRecv {
+            len: len,
+            msg_len: msg.data.len(),
+            rights: msg.rights,
+            cred: msg.cred,
+            from: msg.from,
+        }
+    }
+    /// 关闭连接的读(how = 0)、写(how = 1)或者读写(how = 2)
+    pub fn shutdown(&self, how: usize) -> Result<(), ErrorNo> {
+        let peer = self.inner.lock().peer.clone();
+        if self.is_connection_oriented() && peer.is_none() {
+            return Err(ErrorNo::ENOTCONN);
+        }
+        let mut ep_inner = self.endpoint.inner.lock();
+        match how {
+            0 => ep_inner.recv_shutdown = true,
+            1 => ep_inner.send_shutdown = true,
+            2 => {
+                ep_inner.recv_shutdown = true;
+                ep_inner.send_shutdown = true;
+            }
+            _ => return Err(ErrorNo::EINVAL),
+        }
+        drop(ep_inner);
+        self.endpoint.waiters.wake_all();
+        if let Some(peer) = peer {
+            peer.waiters.wake_all();
+        }
+        Ok(())
+    }
+    /// 获取绑定的地址
+    pub fn local_addr(&self) -> UnixAddr {
+        self.inner.lock().local_addr.clone()
+    }
+    /// 获取对方的地址
+    pub fn peer_addr(&self) -> Result<UnixAddr, ErrorNo> {
+        let inner = self.inner.lock();
+        if inner.peer.is_none() {
+            return Err(ErrorNo::ENOTCONN);
+        }
+        Ok(inner.peer_addr.clone())
+    }
+}
+
+/// 找到地址对应的接收端
+fn lookup(addr: &UnixAddr) -> Result<Arc<UnixEndpoint>, ErrorNo> {
+    match UNIX_ADDR_MAP.lock().get(addr).map(|ep| ep.upgrade()) {
+        Some(Some(ep)) => Ok(ep),
+        // 绑定过但 socket 已经关闭
+        Some(None) => Err(ErrorNo::ECONNREFUSED),
+        None => match addr {
+            UnixAddr::Path(path) if check_file_exists("./", &path[2..]) => Err(ErrorNo::ECONNREFUSED),
+            UnixAddr::Path(_) => Err(ErrorNo::ENOENT),
+            _ => Err(ErrorNo::ECONNREFUSED),
+        },
+    }
+}
+
+impl File for UnixSocket {
+    /// 相当于不带辅助数据的 recv
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        self.recv(buf, false, false).ok().map(|ret| ret.len)
+    }
+    /// 相当于不带辅助数据的 send
+    fn write(&self, buf: &[u8]) -> Option<usize> {
+        self.send(buf, Vec::new(), None, None, false).ok()
+    }
+    /// 有消息、有等待 accept 的连接，或者对方已关闭时可读
+    fn ready_to_read(&self) -> bool {
+        if self.is_hang_up() {
+            return true;
+        }
+        let ep_inner = self.endpoint.inner.lock();
+        !ep_inner.messages.is_empty() || !ep_inner.backlog.is_empty() || ep_inner.recv_shutdown
+    }
+    /// 对方的接收端还有空间时可写。对方已关闭时也算可写，因为写会立即返回错误
+    fn ready_to_write(&self) -> bool {
+        let (peer, sndbuf) = {
+            let inner = self.inner.lock();
+            (inner.peer.clone(), inner.options.sndbuf)
+        };
+        match peer {
+            Some(peer) => {
+                let peer_inner = peer.inner.lock();
+                peer_inner.recv_shutdown || peer_inner.len < min(peer_inner.max_len, sndbuf)
+            }
+            None => !self.is_connection_oriented(),
+        }
+    }
+    /// 对方不再发送数据时为 HUP
+    fn is_hang_up(&self) -> bool {
+        let peer = self.inner.lock().peer.clone();
+        self.is_connection_oriented() && peer.map_or(false, |peer| peer.is_send_shutdown())
+    }
+    /// 自己和对方的接收端状态变化时都会唤醒等待的线程
+    fn register_waiter(&self, tid: usize) -> bool {
+        self.endpoint.waiters.register(tid);
+        let peer = self.inner.lock().peer.clone();
+        if let Some(peer) = peer {
+            peer.waiters.register(tid);
+        }
+        true
+    }
+    /// 同 register_waiter，但以独占方式登记
+    fn register_exclusive_waiter(&self, tid: usize) -> bool {
+        self.endpoint.waiters.register_exclusive(tid);
+        let peer = self.inner.lock().peer.clone();
+        if let Some(peer) = peer {
+            peer.waiters.register_exclusive(tid);
+        }
+        true
+    }
+    /// 自己和对方的接收端被唤醒的次数之和
+    fn wake_count(&self) -> Option<usize> {
+        let mut count = self.endpoint.waiters.wake_count();
+        let peer = self.inner.lock().peer.clone();
+        if let Some(peer) = peer {
+            count = count.wrapping_add(peer.waiters.wake_count());
+        }
+        Some(count)
+    }
+    /// 取消等待
+    fn unregister_waiter(&self, tid: usize) {
+        self.endpoint.waiters.unregister(tid);
+        let peer = self.inner.lock().peer.clone();
+        if let Some(peer) = peer {
+            peer.waiters.unregister(tid);
+        }
+    }
+    /// 获取文件状态信息
+    fn get_status(&self) -> OpenFlags {
+        self.inner.lock().flags
+    }
+    /// 设置文件状态信息，只能修改 NON_BLOCK 和 CLOEXEC
+    fn set_status(&self, flags: OpenFlags) -> bool {
+        let fl = &mut self.inner.lock().flags;
+        fl.set(OpenFlags::NON_BLOCK, flags.contains(OpenFlags::NON_BLOCK));
+        fl.set(OpenFlags::CLOEXEC, flags.contains(OpenFlags::CLOEXEC));
+        true
+    }
+    /// 设置 CLOEXEC 位
+    fn set_close_on_exec(&self, is_set: bool) -> bool {
+        self.inner.lock().flags.set(OpenFlags::CLOEXEC, is_set);
+        true
+    }
+}
+
+impl Drop for UnixSocket {
+    /// 关闭接收端并唤醒等待的线程。未读的消息中传递的文件和未 accept 的连接也在这里释放
+    fn drop(&mut self) {
+        let mut ep_inner = self.endpoint.inner.lock();
+        ep_inner.recv_shutdown = true;
+        ep_inner.send_shutdown = true;
+        ep_inner.listening = false;
+        ep_inner.len = 0;
+        // 取出来在锁外释放，因为释放未 accept 的 socket 时也会进入这个函数
+        let messages = core::mem::take(&mut ep_inner.messages);
+        let backlog = core::mem::take(&mut ep_inner.backlog);
+        drop(ep_inner);
+        drop(messages);
+        drop(backlog);
+        self.endpoint.waiters.wake_all();
+        let peer = self.inner.lock().peer.take();
+        if let Some(peer) = peer {
+            peer.waiters.wake_all();
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! timerfd，通过文件描述符通知定时器到期
+//!
+//! 到期次数在读取或查询时根据当前时间计算。已启动的定时器另外登记在 ARMED_TIMERS 中，
+//! 调度循环通过 wake_expired_timers 检查它们，到期时唤醒 select / poll / epoll 等待的线程。
+//! read 返回上次读取以来定时器到期的次数并清零，还没有到期时在定时器的等待队列上睡眠，到期时被唤醒。
+//!
+//! 定时器内部都以开机时间计时。CLOCK_REALTIME 的定时器以 TFD_TIMER_ABSTIME 设置时，给出的是系统时间，
+//! 要先减去 REALTIME_OFFSET_US 换算成开机时间；CLOCK_MONOTONIC 和 CLOCK_BOOTTIME 给出的本来就是开机时间
+
+use super::{File, OpenFlags, WaitQueue};
+use crate::{
+    syscall::ErrorNo,
+    timer::{get_time_us, TimeSpec, REALTIME_OFFSET_US},
+};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::mem::size_of;
+use lock::Mutex;
+
+/// 系统时间
+pub const CLOCK_REALTIME: usize = 0;
+/// 开机后经过的时间
+pub const CLOCK_MONOTONIC: usize = 1;
+/// 开机后经过的时间，包括挂起的时间。这里不会挂起，所以和 CLOCK_MONOTONIC 相同
+pub const CLOCK_BOOTTIME: usize = 7;
+/// settime 时给出的是绝对时间而不是相对当前的时间
+pub const TFD_TIMER_ABSTIME: u32 = 1;
+
+/// timerfd_settime / timerfd_gettime 使用的结构，即 struct itimerspec
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct ITimerSpec {
+    /// 定时器的周期。为 0 时只触发一次
+    pub it_interval: TimeSpec,
+    /// 到下一次到期的时间。为 0 时表示定时器未启动
+    pub it_value: TimeSpec,
+}
+
+/// timerfd 文件
+pub struct TimerFd {
+    /// 定时器使用的时钟
+    clockid: usize,
+    inner: Arc<Mutex<TimerFdInner>>,
+    /// 等待定时器到期的线程
+    waiters: Arc<WaitQueue>,
+}
+
+/// 已启动的定时器和它们的等待队列。定时器停止或者文件被关闭后，在下一次检查时删除
+static ARMED_TIMERS: Mutex<Vec<(Weak<Mutex<TimerFdInner>>, Weak<WaitQueue>)>> = Mutex::new(Vec::new());
+
+/// 唤醒等待已到期的定时器的线程，在调度循环中调用。其他核正在检查时直接返回
+pub fn wake_expired_timers() {
+    let mut armed = match ARMED_TIMERS.try_lock() {
+        Some(armed) => armed,
+        None => return,
+    };
+    let now = get_time_us();
+    let mut expired: Vec<Arc<WaitQueue>> = Vec::new();
+    armed.retain(|(inner, waiters)| match (inner.upgrade(), waiters.upgrade()) {
+        (Some(inner), Some(waiters)) => {
+            let mut inner = inner.lock();
+            if inner.next_expire_us != 0 && now >= inner.next_expire_us {
+                inner.update();
+                expired.push(waiters);
+            }
+            // 一次性的定时器到期后就停止了
+            inner.next_expire_us != 0
+        }
+        _ => false,
+    });
+    drop(armed);
+    for waiters in expired {
+        waiters.wake_all();
+    }
+}
+
+struct TimerFdInner {
+    /// 下一次到期的时间，单位为微秒。为 0 时表示定时器未启动
+    next_expire_us: usize,
+    /// 定时器的周期，单位为微秒。为 0 时只触发一次
+    interval_us: usize,
+    /// 已经到期但还没有被读取的次数
+    expirations: u64,
+    /// 文件状态。TFD_NONBLOCK 和 TFD_CLOEXEC 与 O_NONBLOCK 和 O_CLOEXEC 的值相同
+    flags: OpenFlags,
+    /// 上次读写失败的原因，只记录参数不合法
+    error: Option<ErrorNo>,
+}
+
+impl TimerFdInner {
+    /// 根据当前时间更新到期次数和下一次到期的时间
+    fn update(&mut self) {
+        let now = get_time_us();
+        if self.next_expire_us == 0 || now < self.next_expire_us {
+            return;
+        }
+        if self.interval_us == 0 {
+            self.expirations += 1;
+            self.next_expire_us = 0;
+        } else {
+            let count = (now - self.next_expire_us) / self.interval_us + 1;
+            self.expirations += count as u64;
+            self.next_expire_us += count * self.interval_us;
+        }
+    }
+    /// 获取定时器当前的设置，it_value 是距离下一次到期的时间
+    fn get_time(&mut self) -> ITimerSpec {
+        self.update();
+        let remained_us = if self.next_expire_us == 0 {
+            0
+        } else {
+            self.next_expire_us.saturating_sub(get_time_us())
+        };
+        ITimerSpec {
+            it_interval: TimeSpec::from_us(self.interval_us),
+            it_value: TimeSpec::from_us(remained_us),
+        }
+    }
+}
+
+impl TimerFd {
+    /// 创建一个使用时钟 clockid 的未启动的定时器
+    pub fn new(clockid: usize, flags: u32) -> Self {
+        Self {
+            clockid: clockid,
+            inner: Arc::new(Mutex::new(TimerFdInner {
+                next_expire_us: 0,
+                interval_us: 0,
+                expirations: 0,
+                flags: OpenFlags::RDONLY
+                    | (OpenFlags::from_bits_truncate(flags) & (OpenFlags::NON_BLOCK | OpenFlags::CLOEXEC)),
+                error: None,
+            })),
+            waiters: Arc::new(WaitQueue::new()),
+        }
+    }
+    /// 设置定时器，返回原来的设置。new_value.it_value 为 0 时停止定时器
+    pub fn set_time(&self, new_value: &ITimerSpec, flags: u32) -> ITimerSpec {
+        let mut inner = self.inner.lock();
+        let old_value = inner.get_time();
+        let value_us = new_value.it_value.to_us();
+        inner.next_expire_us = if value_us == 0 {
+            0
+        } else if flags & TFD_TIMER_ABSTIME != 0 {
+            let expire_us = if self.clockid == CLOCK_REALTIME {
+                value_us.saturating_sub(REALTIME_OFFSET_US)
+            } else {
+                value_us
+            };
+            // 已经过去的时间会在下一次 update 时立即到期。这里至少取 1，以免和未启动混淆
+            expire_us.max(1)
+        } else {
+            get_time_us() + value_us
+        };
+        inner.interval_us = new_value.it_interval.to_us();
+        inner.expirations = 0;
+        let armed = inner.next_expire_us != 0;
+        // ARMED_TIMERS 的检查中会拿定时器的锁，所以这里要先放掉
+        drop(inner);
+        if armed {
+            let mut timers = ARMED_TIMERS.lock();
+            let weak = Arc::downgrade(&self.inner);
+            if !timers.iter().any(|(inner, _)| inner.ptr_eq(&weak)) {
+                timers.push((weak, Arc::downgrade(&self.waiters)));
+            }
+        }
+        old_value
+    }
+    /// 获取定时器当前的设置
+    pub fn get_time(&self) -> ITimerSpec {
+        self.inner.lock().get_time()
+    }
+    /// 参数不合法，读写失败
+    fn invalid_input(&self) -> Option<usize> {
+        self.inner.lock().error = Some(ErrorNo::EINVAL);
+        None
+    }
+}
+
+impl File for TimerFd {
+    /// 读出到期次数，buf 至少要有 8 字节。还没有到期时阻塞
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        if buf.len() < size_of::<u64>() {
+            return self.invalid_input();
+        }
+        loop {
+            let wakes = self.waiters.wake_count();
+            let mut inner = self.inner.lock();
+            inner.update();
+            if inner.expirations > 0 {
+                buf[..size_of::<u64>()].copy_from_slice(&inner.expirations.to_ne_bytes());
+                inner.expirations = 0;
+                return Some(size_of::<u64>());
+            }
+            if inner.flags.contains(OpenFlags::NON_BLOCK) {
+                return None;
+            }
+            drop(inner);
+            // 被信号打断
+            if self.waiters.wait(wakes, None).is_err() {
+                return None;
+            }
+        }
+    }
+    /// 不可写，返回 EINVAL
+    fn write(&self, _buf: &[u8]) -> Option<usize> {
+        self.invalid_input()
+    }
+    /// 参数不合法时返回 EINVAL
+    fn take_rw_error(&self) -> Option<ErrorNo> {
+        self.inner.lock().error.take()
+    }
+    /// 定时器到期过时可读
+    fn ready_to_read(&self) -> bool {
+        let mut inner = self.inner.lock();
+        inner.update();
+        inner.expirations > 0
+    }
+    /// 不可写
+    fn ready_to_write(&self) -> bool {
+        false
+    }
+    /// 定时器到期时会唤醒等待的线程
+    fn register_waiter(&self, tid: usize) -> bool {
+        self.waiters.register(tid);
+        true
+    }
+    /// 独占等待
+    fn register_exclusive_waiter(&self, tid: usize) -> bool {
+        self.waiters.register_exclusive(tid);
+        true
+    }
+    /// 取消等待
+    fn unregister_waiter(&self, tid: usize) {
+        self.waiters.unregister(tid);
+    }
+    /// 定时器到期的次数
+    fn wake_count(&self) -> Option<usize> {
+        Some(self.waiters.wake_count())
+    }
+    /// 获取文件状态信息
+    fn get_status(&self) -> OpenFlags {
+        self.inner.lock().flags
+    }
+    /// 设置文件状态信息，只能修改 NON_BLOCK 和 CLOEXEC
+    fn set_status(&self, flags: OpenFlags) -> bool {
+        let fl = &mut self.inner.lock().flags;
+        fl.set(OpenFlags::NON_BLOCK, flags.contains(OpenFlags::NON_BLOCK));
+        fl.set(OpenFlags::CLOEXEC, flags.contains(OpenFlags::CLOEXEC));
+        true
+    }
+    /// 设置 CLOEXEC 位
+    fn set_close_on_exec(&self, is_set: bool) -> bool {
+        self.inner.lock().flags.set(OpenFlags::CLOEXEC, is_set);
+        true
+    }
+}
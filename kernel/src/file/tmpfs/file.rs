@@ -0,0 +1,168 @@
+//! tmpfs 中打开的文件或目录
+
+use super::inode::Inode;
+use crate::file::{user_path, File, Kstat, OpenFlags, SeekFrom};
+use crate::timer::TimeSpec;
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use lock::Mutex;
+
+/// tmpfs 中打开的文件。打开的是目录时只用于保存路径和获取属性，不能读写
+pub struct TmpFile {
+    inode: Arc<Inode>,
+    /// 在整个目录树中的路径。打开的是目录时以 '/' 结尾
+    path: String,
+    /// 打开的是否是目录
+    is_dir: bool,
+    /// 是否可读
+    readable: bool,
+    /// 是否可写
+    writable: bool,
+    /// 可变部分
+    inner: Mutex<TmpFileInner>,
+}
+
+/// 文件在os中运行时的可变信息
+struct TmpFileInner {
+    /// 文件指针
+    pos: usize,
+    /// 打开时的选项
+    flags: OpenFlags,
+}
+
+impl TmpFile {
+    /// 打开路径为 path 的 inode。设备文件和管道等不是普通文件的 inode 不能读写
+    pub fn new(inode: Arc<Inode>, mut path: String, is_dir: bool, readable: bool, writable: bool, flags: OpenFlags) -> Self {
+        if is_dir && !path.ends_with('/') {
+            path.push('/');
+        }
+        Self {
+            inode: inode,
+            path: path,
+            is_dir: is_dir,
+            readable: readable,
+            writable: writable,
+            inner: Mutex::new(TmpFileInner {
+                pos: 0,
+                flags: flags,
+            }),
+        }
+    }
+}
+
+impl File for TmpFile {
+    /// 读取文件
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        if !self.readable {
+            return None;
+        }
+        let mut inner = self.inner.lock();
+        let len = self.inode.read_at(inner.pos, buf).ok()?;
+        inner.pos += len;
+        Some(len)
+    }
+    /// 写入文件
+    fn write(&self, buf: &[u8]) -> Option<usize> {
+        if !self.writable {
+            return None;
+        }
+        let mut inner = self.inner.lock();
+        if inner.flags.contains(OpenFlags::APPEND) {
+            inner.pos = self.inode.size();
+        }
+        let len = self.inode.write_at(inner.pos, buf).ok()?;
+        inner.pos += len;
+        Some(len)
+    }
+    /// 从某个位置读文件内容到 buf 中，不改变文件指针
+    fn read_from_offset(&self, pos: usize, buf: &mut [u8]) -> Option<usize> {
+        if !self.readable {
+            return None;
+        }
+        self.inode.read_at(pos, buf).ok()
+    }
+    /// 将 buf 写入文件中的某个位置，不改变文件指针
+    fn write_to_offset(&self, pos: usize, buf: &[u8]) -> Option<usize> {
+        if !self.writable {
+            return None;
+        }
+        self.inode.write_at(pos, buf).ok()
+    }
+    /// 切换文件指针位置。可以移到文件末尾之后，之后写入时中间填 0
+    fn seek(&self, seekfrom: SeekFrom) -> Option<usize> {
+        let mut inner = self.inner.lock();
+        let new_pos = match seekfrom {
+            SeekFrom::Start(off) => off as i64,
+            SeekFrom::Current(off) => inner.pos as i64 + off,
+            SeekFrom::End(off) => self.inode.size() as i64 + off,
+        };
+        if new_pos < 0 {
+            return None;
+        }
+        inner.pos = new_pos as usize;
+        Some(inner.pos)
+    }
+    /// 获取路径。只有目录有
+    fn get_dir(&self) -> Option<&str> {
+        if self.is_dir {
+            Some(self.path.as_str())
+        } else {
+            None
+        }
+    }
+    /// 打开时记录的路径
+    fn get_path(&self) -> Option<String> {
+        Some(user_path(&self.path))
+    }
+    /// 读取所有数据
+    unsafe fn read_all(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; self.inode.size()];
+        let len = self.inode.read_at(0, &mut buf).unwrap_or(0);
+        buf.truncate(len);
+        buf
+    }
+    /// 文件属性
+    fn get_stat(&self, stat: *mut Kstat) -> bool {
+        self.inode.stat(stat);
+        true
+    }
+    /// 修改文件大小
+    fn truncate(&self, len: usize) -> bool {
+        self.writable && self.inode.truncate(len).is_ok()
+    }
+    /// 清空文件
+    fn clear(&self) {
+        if self.writable {
+            // 只有普通文件可写，清空不会失败
+            let _ = self.inode.truncate(0);
+        }
+    }
+    /// 设置时间，返回是否设置成功。
+    fn set_time(&self, atime: &TimeSpec, mtime: &TimeSpec) -> bool {
+        let mut inode = self.inode.lock();
+        let (mut new_atime, mut new_mtime) = (inode.atime, inode.mtime);
+        new_atime.set_as_utime(atime);
+        new_mtime.set_as_utime(mtime);
+        inode.atime = new_atime;
+        inode.mtime = new_mtime;
+        inode.ctime = TimeSpec::now();
+        true
+    }
+    /// 设置文件状态信息，返回设置是否成功。
+    fn set_status(&self, flags: OpenFlags) -> bool {
+        self.inner.lock().flags = flags;
+        true
+    }
+    /// 设置状态信息的 CLOEXEC 位，返回设置是否成功。
+    fn set_close_on_exec(&self, is_set: bool) -> bool {
+        if is_set {
+            self.inner.lock().flags |= OpenFlags::CLOEXEC;
+        } else {
+            self.inner.lock().flags &= !OpenFlags::CLOEXEC;
+        }
+        true
+    }
+    /// 获取文件状态信息
+    fn get_status(&self) -> OpenFlags {
+        self.inner.lock().flags
+    }
+}
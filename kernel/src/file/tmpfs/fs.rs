@@ -0,0 +1,319 @@
+//! tmpfs 文件系统
+//!
+//! 所有内容都在内存中，从 initramfs 启动时作为根文件系统
+
+use super::file::TmpFile;
+use super::inode::{Content, Inode};
+use crate::constants::PAGE_SIZE;
+use crate::file::{File, FsStat, OpenFlags, StMode, UnixFs};
+use crate::syscall::ErrorNo;
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// statfs 中 tmpfs 的类型
+const TMPFS_MAGIC: i64 = 0x0102_1994;
+/// 查找路径时最多跟随的符号链接数，超过时返回 ELOOP
+const MAX_SYMLINK_FOLLOWS: usize = 40;
+/// 文件名的最大长度
+const NAME_MAX: usize = 255;
+/// 新建文件和目录的默认权限
+const DEFAULT_FILE_PERM: u32 = 0o644;
+const DEFAULT_DIR_PERM: u32 = 0o755;
+
+/// 内存中的文件系统
+pub struct TmpFs {
+    root: Arc<Inode>,
+    /// 下一个可以分配的 inode 编号
+    next_ino: AtomicU64,
+}
+
+impl TmpFs {
+    /// 新建只有根目录的文件系统
+    pub fn new() -> Self {
+        let root = Inode::new(1, StMode::S_IFDIR.bits() | DEFAULT_DIR_PERM, Content::Dir(BTreeMap::new()));
+        Self {
+            root: Arc::new(root),
+            next_ino: AtomicU64::new(2),
+        }
+    }
+    /// 查找 path。路径中间的符号链接总是跟随，最后一项只在 follow 为 true 时跟随。
+    /// 符号链接中的绝对路径从这个文件系统的根目录开始查找
+    fn lookup(&self, path: &str, follow: bool) -> Result<Arc<Inode>, ErrorNo> {
+        // 还没有处理的路径，倒序存放，末尾是下一个要处理的名字
+        let mut pending: Vec<String> = path.split('/').rev().map(String::from).collect();
+        // 从根目录到当前位置经过的每一层
+        let mut walked = Vec::from([self.root.clone()]);
+        let mut follows = 0;
+        while let Some(name) = pending.pop() {
+            match name.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    if walked.len() > 1 {
+                        walked.pop();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            let child = walked.last().unwrap().child(&name)?;
+            let is_last = pending.iter().all(|name| name.is_empty() || name == ".");
+            match child.symlink_target() {
+                Some(target) if follow || !is_last => {
+                    follows += 1;
+                    if follows > MAX_SYMLINK_FOLLOWS {
+                        return Err(ErrorNo::ELOOP);
+                    }
+                    if target.starts_with('/') {
+                        walked.truncate(1);
+                    }
+                    pending.extend(target.split('/').rev().map(String::from));
+                }
+                _ => walked.push(child),
+            }
+        }
+        Ok(walked.pop().unwrap())
+    }
+    /// dir 目录下的 name 的文件类型和设备号。不跟随最后一项的符号链接，name 为空时是 dir 本身
+    pub fn node_type(&self, dir: &str, name: &str) -> Option<(StMode, u64)> {
+        let inode = self.lookup(&(String::from(dir) + name), false).ok()?;
+        let rdev = inode.lock().rdev;
+        Some((inode.file_type(), rdev))
+    }
+    /// 查找目录 dir
+    fn lookup_dir(&self, dir: &str) -> Result<Arc<Inode>, ErrorNo> {
+        let inode = self.lookup(dir, true)?;
+        if inode.is_dir() {
+            Ok(inode)
+        } else {
+            Err(ErrorNo::ENOTDIR)
+        }
+    }
+    /// 在 dir 目录下加入名为 name 的新 inode，返回它
+    fn create(&self, dir: &str, name: &str, mode: u32, content: Content) -> Result<Arc<Inode>, ErrorNo> {
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(ErrorNo::EEXIST);
+        }
+        if name.len() > NAME_MAX {
+            return Err(ErrorNo::ENAMETOOLONG);
+        }
+        let parent = self.lookup_dir(dir)?;
+        let ino = self.next_ino.fetch_add(1, Ordering::Relaxed);
+        let inode = Arc::new(Inode::new(ino, mode, content));
+        insert_entry(&parent, name, inode.clone())?;
+        Ok(inode)
+    }
+}
+
+/// 在目录中加入目录项。已存在时返回 EEXIST
+fn insert_entry(dir: &Inode, name: &str, inode: Arc<Inode>) -> Result<(), ErrorNo> {
+    match &mut dir.lock().content {
+        Content::Dir(entries) => {
+            if entries.contains_key(name) {
+                return Err(ErrorNo::EEXIST);
+            }
+            entries.insert(String::from(name), inode);
+            Ok(())
+        }
+        _ => Err(ErrorNo::ENOTDIR),
+    }
+}
+
+/// 从目录中取出目录项
+fn remove_entry(dir: &Inode, name: &str) -> Option<Arc<Inode>> {
+    match &mut dir.lock().content {
+        Content::Dir(entries) => entries.remove(name),
+        _ => None,
+    }
+}
+
+/// 是否是空目录
+fn is_empty_dir(inode: &Inode) -> bool {
+    matches!(&inode.lock().content, Content::Dir(entries) if entries.is_empty())
+}
+
+impl UnixFs for TmpFs {
+    /// 打开 dir 目录下的 name 文件。只有普通文件可以读写
+    fn open(self: Arc<Self>, dir: &str, name: &str, full_dir: &str, flags: OpenFlags) -> Option<Arc<dyn File>> {
+        let (readable, writable) = flags.read_write();
+        let path = String::from(dir) + name;
+        let full_path = String::from(full_dir) + name;
+        let inode = match self.lookup(&path, !flags.contains(OpenFlags::NOFOLLOW)) {
+            Ok(inode) => {
+                if flags.contains(OpenFlags::CREATE) && flags.contains(OpenFlags::EXCLUSIVE) {
+                    return None;
+                }
+                inode
+            }
+            Err(ErrorNo::ENOENT) if flags.contains(OpenFlags::CREATE) && !name.is_empty() => {
+                let mode = StMode::S_IFREG.bits() | DEFAULT_FILE_PERM;
+                let inode = self.create(dir, name, mode, Content::File(Vec::new(), 0)).ok()?;
+                return Some(Arc::new(TmpFile::new(inode, full_path, false, readable, writable, flags)));
+            }
+            Err(_) => return None,
+        };
+        if flags.contains(OpenFlags::DIR) || flags.contains(OpenFlags::DSYNC) || name.is_empty() {
+            if !inode.is_dir() {
+                return None;
+            }
+            return Some(Arc::new(TmpFile::new(inode, full_path, true, false, false, flags)));
+        }
+        if inode.is_dir() {
+            return None;
+        }
+        if !inode.is_regular() {
+            return Some(Arc::new(TmpFile::new(inode, full_path, false, false, false, flags)));
+        }
+        if writable && flags.contains(OpenFlags::TRUNC) && inode.truncate(0).is_err() {
+            return None;
+        }
+        Some(Arc::new(TmpFile::new(inode, full_path, false, readable, writable, flags)))
+    }
+    fn exists(&self, dir: &str, name: &str) -> Option<bool> {
+        self.lookup(&(String::from(dir) + name), true).ok().map(|inode| inode.is_dir())
+    }
+    fn mkdir(&self, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let mode = StMode::S_IFDIR.bits() | DEFAULT_DIR_PERM;
+        self.create(dir, name, mode, Content::Dir(BTreeMap::new())).map(|_| ())
+    }
+    fn remove(&self, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let parent = self.lookup_dir(dir)?;
+        let inode = parent.child(name)?;
+        if inode.is_dir() && !is_empty_dir(&inode) {
+            return Err(ErrorNo::ENOTEMPTY);
+        }
+        remove_entry(&parent, name);
+        // 打开的文件仍然持有 inode，全部关闭后才释放内容
+        let mut inner = inode.lock();
+        inner.nlink = inner.nlink.saturating_sub(1);
+        Ok(())
+    }
+    fn rename(&self, old_dir: &str, old_name: &str, new_dir: &str, new_name: &str, replace: bool) -> Result<(), ErrorNo> {
+        let old_parent = self.lookup_dir(old_dir)?;
+        let new_parent = self.lookup_dir(new_dir)?;
+        let inode = old_parent.child(old_name)?;
+        if new_name.is_empty() || new_name == "." || new_name == ".." {
+            return Err(ErrorNo::EINVAL);
+        }
+        if inode.is_dir() {
+            // 不能把目录移动到它自己里面
+            let moved = String::from(old_dir) + old_name + "/";
+            if new_dir.starts_with(moved.as_str()) {
+                return Err(ErrorNo::EINVAL);
+            }
+        }
+        if let Ok(target) = new_parent.child(new_name) {
+            if Arc::ptr_eq(&target, &inode) {
+                return Ok(());
+            }
+            if !replace {
+                return Err(ErrorNo::EEXIST);
+            }
+            match (inode.is_dir(), target.is_dir()) {
+                (true, false) => return Err(ErrorNo::ENOTDIR),
+                (false, true) => return Err(ErrorNo::EISDIR),
+                (true, true) if !is_empty_dir(&target) => return Err(ErrorNo::ENOTEMPTY),
+                _ => {}
+            }
+            remove_entry(&new_parent, new_name);
+            let mut inner = target.lock();
+            inner.nlink = inner.nlink.saturating_sub(1);
+        }
+        remove_entry(&old_parent, old_name);
+        insert_entry(&new_parent, new_name, inode)
+    }
+    fn link(&self, old_dir: &str, old_name: &str, new_dir: &str, new_name: &str) -> Result<(), ErrorNo> {
+        let inode = self.lookup(&(String::from(old_dir) + old_name), false)?;
+        if inode.is_dir() {
+            return Err(ErrorNo::EPERM);
+        }
+        let new_parent = self.lookup_dir(new_dir)?;
+        insert_entry(&new_parent, new_name, inode.clone())?;
+        inode.lock().nlink += 1;
+        Ok(())
+    }
+    fn symlink(&self, target: &str, dir: &str, name: &str) -> Result<(), ErrorNo> {
+        let mode = StMode::S_IFLNK.bits() | 0o777;
+        self.create(dir, name, mode, Content::Symlink(String::from(target))).map(|_| ())
+    }
+    fn mknod(&self, dir: &str, name: &str, mode: u32, rdev: u64) -> Result<(), ErrorNo> {
+        let (mode, content) = match mode & StMode::S_IFMT.bits() {
+            // 没有给出类型时是普通文件
+            0 => (mode | StMode::S_IFREG.bits(), Content::File(Vec::new(), 0)),
+            file_type if file_type == StMode::S_IFREG.bits() => (mode, Content::File(Vec::new(), 0)),
+            file_type if file_type == StMode::S_IFDIR.bits() => return Err(ErrorNo::EINVAL),
+            _ => (mode, Content::Node),
+        };
+        let inode = self.create(dir, name, mode, content)?;
+        inode.lock().rdev = rdev;
+        Ok(())
+    }
+    fn read_link(&self, dir: &str, name: &str) -> Result<String, ErrorNo> {
+        let inode = self.lookup(&(String::from(dir) + name), false)?;
+        inode.symlink_target().ok_or(ErrorNo::EINVAL)
+    }
+    fn chmod(&self, dir: &str, name: &str, mode: u32) -> Result<(), ErrorNo> {
+        let inode = self.lookup(&(String::from(dir) + name), true)?;
+        let mut inner = inode.lock();
+        inner.mode = (inner.mode & StMode::S_IFMT.bits()) | (mode & 0o7777);
+        Ok(())
+    }
+    fn chown(&self, dir: &str, name: &str, uid: Option<u32>, gid: Option<u32>, follow: bool) -> Result<(), ErrorNo> {
+        let inode = self.lookup(&(String::from(dir) + name), follow)?;
+        let mut inner = inode.lock();
+        if let Some(uid) = uid {
+            inner.uid = uid;
+        }
+        if let Some(gid) = gid {
+            inner.gid = gid;
+        }
+        Ok(())
+    }
+    fn list(&self, dir: &str) -> Result<Vec<(String, StMode)>, ErrorNo> {
+        let inode = self.lookup_dir(dir)?;
+        let children: Vec<(String, Arc<Inode>)> = match &inode.lock().content {
+            Content::Dir(entries) => entries.iter().map(|(name, child)| (name.clone(), child.clone())).collect(),
+            _ => return Err(ErrorNo::ENOTDIR),
+        };
+        let mut list = Vec::from([(String::from("."), StMode::S_IFDIR), (String::from(".."), StMode::S_IFDIR)]);
+        list.extend(children.into_iter().map(|(name, child)| (name, child.file_type())));
+        Ok(list)
+    }
+    fn fs_type(&self) -> &'static str {
+        "tmpfs"
+    }
+    fn stat_fs(&self, stat: *mut FsStat) {
+        // 不限制 tmpfs 的大小，所以只统计已经使用的部分。硬链接指向的 inode 只算一次
+        let mut counted = BTreeSet::new();
+        let mut pages = 0;
+        let mut stack = Vec::from([self.root.clone()]);
+        while let Some(inode) = stack.pop() {
+            if !counted.insert(inode.ino) {
+                continue;
+            }
+            match &inode.lock().content {
+                Content::Dir(entries) => stack.extend(entries.values().cloned()),
+                Content::File(frames, _) => pages += frames.len(),
+                _ => {}
+            }
+        }
+        unsafe {
+            (*stat).f_type = TMPFS_MAGIC;
+            (*stat).f_bsize = PAGE_SIZE as i64;
+            (*stat).f_blocks = pages as u64;
+            (*stat).f_bfree = 0;
+            (*stat).f_bavail = 0;
+            (*stat).f_files = counted.len() as u64;
+            (*stat).f_ffree = 0;
+            (*stat).f_fsid = [0, 0];
+            (*stat).f_namelen = NAME_MAX as isize;
+            (*stat).f_frsize = PAGE_SIZE as isize;
+            (*stat).f_flags = 0;
+            (*stat).f_spare = [0, 0, 0, 0];
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! tmpfs 中的 inode
+//!
+//! 普通文件的内容保存在页帧里，目录保存名字到子 inode 的映射。
+//! 硬链接就是同一个 inode 出现在多个目录中，nlink 记录它被多少个目录项引用
+
+use crate::constants::PAGE_SIZE;
+use crate::file::{Kstat, StMode};
+use crate::memory::Frame;
+use crate::syscall::ErrorNo;
+use crate::sysctl::Sysctl;
+use crate::timer::TimeSpec;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use lock::{Mutex, MutexGuard};
+
+/// tmpfs 中单个文件的大小上限，为 0 时不限制。可以通过 /proc/sys/fs/tmp_size_limit 修改
+pub static TMP_SIZE_LIMIT: Sysctl<usize> = Sysctl::new(0, 0, usize::MAX);
+
+/// inode 中保存的内容
+pub enum Content {
+    /// 目录，保存目录项
+    Dir(BTreeMap<String, Arc<Inode>>),
+    /// 普通文件，保存数据所在的页帧和文件长度
+    File(Vec<Frame>, usize),
+    /// 符号链接，保存目标路径
+    Symlink(String),
+    /// 设备文件、管道和 socket，没有内容
+    Node,
+}
+
+/// tmpfs 中的文件、目录或其他节点
+pub struct Inode {
+    /// inode 编号
+    pub ino: u64,
+    inner: Mutex<InodeInner>,
+}
+
+/// inode 的可变部分
+pub struct InodeInner {
+    /// 文件类型和权限
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// 指向它的目录项数
+    pub nlink: u32,
+    /// 设备号，只对设备文件有意义
+    pub rdev: u64,
+    pub atime: TimeSpec,
+    pub mtime: TimeSpec,
+    pub ctime: TimeSpec,
+    pub content: Content,
+}
+
+impl Inode {
+    /// 新建 inode。mode 中包含文件类型，由它决定 inode 中保存的内容
+    pub fn new(ino: u64, mode: u32, content: Content) -> Self {
+        let now = TimeSpec::now();
+        Self {
+            ino: ino,
+            inner: Mutex::new(InodeInner {
+                mode: mode,
+                uid: 0,
+                gid: 0,
+                nlink: 1,
+                rdev: 0,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                content: content,
+            }),
+        }
+    }
+    /// 获取可变部分
+    pub fn lock(&self) -> MutexGuard<InodeInner> {
+        self.inner.lock()
+    }
+    pub fn is_dir(&self) -> bool {
+        matches!(self.lock().content, Content::Dir(_))
+    }
+    pub fn is_regular(&self) -> bool {
+        matches!(self.lock().content, Content::File(_, _))
+    }
+    /// 符号链接的目标。不是符号链接时返回 None
+    pub fn symlink_target(&self) -> Option<String> {
+        match &self.lock().content {
+            Content::Symlink(target) => Some(target.clone()),
+            _ => None,
+        }
+    }
+    /// 目录中名为 name 的目录项。self 不是目录时返回 ENOTDIR
+    pub fn child(&self, name: &str) -> Result<Arc<Inode>, ErrorNo> {
+        match &self.lock().content {
+            Content::Dir(entries) => entries.get(name).cloned().ok_or(ErrorNo::ENOENT),
+            _ => Err(ErrorNo::ENOTDIR),
+        }
+    }
+    /// 文件长度。目录和其他节点为 0，符号链接为目标路径的长度
+    pub fn size(&self) -> usize {
+        match &self.lock().content {
+            Content::File(_, size) => *size,
+            Content::Symlink(target) => target.len(),
+            _ => 0,
+        }
+    }
+    /// 从 pos 处读文件
+    pub fn read_at(&self, pos: usize, buf: &mut [u8]) -> Result<usize, ErrorNo> {
+        let mut inner = self.lock();
+        let (frames, size) = match &inner.content {
+            Content::File(frames, size) => (frames, *size),
+            Content::Dir(_) => return Err(ErrorNo::EISDIR),
+            _ => return Err(ErrorNo::EINVAL),
+        };
+        let len = buf.len().min(size.saturating_sub(pos));
+        let mut done = 0;
+        while done < len {
+            let offset = (pos + done) % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - offset).min(len - done);
+            let frame = &frames[(pos + done) / PAGE_SIZE];
+            buf[done..done + chunk].copy_from_slice(&frame.as_slice()[offset..offset + chunk]);
+            done += chunk;
+        }
+        inner.atime = TimeSpec::now();
+        Ok(len)
+    }
+    /// 向 pos 处写文件。写到文件末尾之后时，中间的部分填 0
+    pub fn write_at(&self, pos: usize, buf: &[u8]) -> Result<usize, ErrorNo> {
+        let mut inner = self.lock();
+        let (frames, size) = match &mut inner.content {
+            Content::File(frames, size) => (frames, size),
+            Content::Dir(_) => return Err(ErrorNo::EISDIR),
+            _ => return Err(ErrorNo::EINVAL),
+        };
+        resize_frames(frames, pos + buf.len())?;
+        let mut done = 0;
+        while done < buf.len() {
+            let offset = (pos + done) % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - offset).min(buf.len() - done);
+            let frame = &mut frames[(pos + done) / PAGE_SIZE];
+            frame.as_slice_mut()[offset..offset + chunk].copy_from_slice(&buf[done..done + chunk]);
+            done += chunk;
+        }
+        *size = (*size).max(pos + buf.len());
+        let now = TimeSpec::now();
+        inner.mtime = now;
+        inner.ctime = now;
+        Ok(buf.len())
+    }
+    /// 修改文件长度。变长时新增的部分为 0
+    pub fn truncate(&self, len: usize) -> Result<(), ErrorNo> {
+        let mut inner = self.lock();
+        let (frames, size) = match &mut inner.content {
+            Content::File(frames, size) => (frames, size),
+            Content::Dir(_) => return Err(ErrorNo::EISDIR),
+            _ => return Err(ErrorNo::EINVAL),
+        };
+        if len < *size {
+            frames.truncate((len + PAGE_SIZE - 1) / PAGE_SIZE);
+            // 最后一页中文件末尾之后的部分清零，之后变长时才能读出 0
+            if len % PAGE_SIZE != 0 {
+                frames[len / PAGE_SIZE].as_slice_mut()[len % PAGE_SIZE..].fill(0);
+            }
+        } else {
+            resize_frames(frames, len)?;
+        }
+        *size = len;
+        let now = TimeSpec::now();
+        inner.mtime = now;
+        inner.ctime = now;
+        Ok(())
+    }
+    /// 把属性写入 stat
+    pub fn stat(&self, stat: *mut Kstat) {
+        let size = self.size();
+        let inner = self.lock();
+        // 目录的链接数是它自己的 "." 、父目录中的目录项，以及每个子目录中的 ".."
+        let nlink = match &inner.content {
+            Content::Dir(entries) => 2 + entries.values().filter(|child| child.is_dir()).count() as u32,
+            _ => inner.nlink,
+        };
+        let blocks = match &inner.content {
+            Content::File(frames, _) => frames.len() * PAGE_SIZE / 512,
+            _ => 0,
+        };
+        unsafe {
+            (*stat).st_dev = 4;
+            (*stat).st_ino = self.ino;
+            (*stat).st_mode = inner.mode;
+            (*stat).st_nlink = nlink;
+            (*stat).st_uid = inner.uid;
+            (*stat).st_gid = inner.gid;
+            (*stat).st_rdev = inner.rdev;
+            (*stat).st_size = size as u64;
+            (*stat).st_blksize = PAGE_SIZE as u32;
+            (*stat).st_blocks = blocks as u64;
+            (*stat).st_atime_sec = inner.atime.tv_sec as isize;
+            (*stat).st_atime_nsec = inner.atime.tv_nsec as isize;
+            (*stat).st_mtime_sec = inner.mtime.tv_sec as isize;
+            (*stat).st_mtime_nsec = inner.mtime.tv_nsec as isize;
+            (*stat).st_ctime_sec = inner.ctime.tv_sec as isize;
+            (*stat).st_ctime_nsec = inner.ctime.tv_nsec as isize;
+        }
+    }
+    /// 文件类型
+    pub fn file_type(&self) -> StMode {
+        StMode::from_bits_truncate(self.lock().mode & StMode::S_IFMT.bits())
+    }
+}
+
+/// 增加页帧，使它们能放下 len 字节。新的页帧都清零。
+/// 超过 TMP_SIZE_LIMIT 时返回 EFBIG，已经超过上限的文件仍可以在已有的页帧中写入。内存不足时返回 ENOSPC
+fn resize_frames(frames: &mut Vec<Frame>, len: usize) -> Result<(), ErrorNo> {
+    let limit = TMP_SIZE_LIMIT.get();
+    if limit != 0 && len > limit && len > frames.len() * PAGE_SIZE {
+        return Err(ErrorNo::EFBIG);
+    }
+    while frames.len() * PAGE_SIZE < len {
+        let mut frame = Frame::new().ok_or(ErrorNo::ENOSPC)?;
+        frame.zero();
+        frames.push(frame);
+    }
+    Ok(())
+}
@@ -0,0 +1,11 @@
+//! tmpfs 文件系统
+//!
+//! 文件内容保存在物理页帧里，目录树、权限、所有者、链接等元数据都在 inode 中。
+//! 从 initramfs 启动时，cpio 包解压到 tmpfs 中作为根文件系统
+
+mod file;
+mod fs;
+mod inode;
+
+pub use fs::TmpFs;
+pub use inode::TMP_SIZE_LIMIT;
@@ -0,0 +1,45 @@
+//! 带有 Unix 元数据的文件系统
+//!
+//! ext2 和 9p 上的文件本身有权限、所有者、硬链接、符号链接和设备文件，
+//! 不需要内核里的链接表来模拟。device 模块在路径落在这类文件系统中时，把操作转交给它们
+
+use super::{File, FsStat, OpenFlags, StMode};
+use crate::syscall::ErrorNo;
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+/// 带有 Unix 元数据的文件系统。
+///
+/// 参数中的 dir 都是文件系统中的路径，以 "./" 开头，以 '/' 结尾
+pub trait UnixFs: Send + Sync {
+    /// 打开 dir 目录下的 name 文件，full_dir 是 dir 在整个目录树中的路径。
+    /// 规则和 FAT 中的 open_file 相同，name 为空时打开 dir 本身。
+    /// 打开的文件需要持有 self，卸载时据此判断文件系统是否还在使用
+    fn open(self: Arc<Self>, dir: &str, name: &str, full_dir: &str, flags: OpenFlags) -> Option<Arc<dyn File>>;
+    /// dir 目录下的 name 是否存在，存在时返回它是否是目录。会跟随符号链接
+    fn exists(&self, dir: &str, name: &str) -> Option<bool>;
+    /// 在 dir 目录下创建 name 目录
+    fn mkdir(&self, dir: &str, name: &str) -> Result<(), ErrorNo>;
+    /// 删除 dir 目录下的 name。可以是文件，也可以是空目录
+    fn remove(&self, dir: &str, name: &str) -> Result<(), ErrorNo>;
+    /// 把 old_dir 目录下的 old_name 移动到 new_dir 目录下，改名为 new_name。
+    /// replace 为 false 时，如果目标已存在则返回 EEXIST
+    fn rename(&self, old_dir: &str, old_name: &str, new_dir: &str, new_name: &str, replace: bool) -> Result<(), ErrorNo>;
+    /// 创建硬链接，new_dir 目录下的 new_name 指向 old_dir 目录下的 old_name
+    fn link(&self, old_dir: &str, old_name: &str, new_dir: &str, new_name: &str) -> Result<(), ErrorNo>;
+    /// 在 dir 目录下创建指向 target 的符号链接 name
+    fn symlink(&self, target: &str, dir: &str, name: &str) -> Result<(), ErrorNo>;
+    /// 在 dir 目录下创建设备文件、管道或普通文件 name。mode 包含文件类型，rdev 是设备号
+    fn mknod(&self, dir: &str, name: &str, mode: u32, rdev: u64) -> Result<(), ErrorNo>;
+    /// 读取 dir 目录下的符号链接 name 的目标路径
+    fn read_link(&self, dir: &str, name: &str) -> Result<String, ErrorNo>;
+    /// 修改 dir 目录下的 name 的权限位
+    fn chmod(&self, dir: &str, name: &str, mode: u32) -> Result<(), ErrorNo>;
+    /// 修改 dir 目录下的 name 的所有者和组，为 None 的一项不修改
+    fn chown(&self, dir: &str, name: &str, uid: Option<u32>, gid: Option<u32>, follow: bool) -> Result<(), ErrorNo>;
+    /// 列出 dir 目录下的所有目录项，每项为 (名字, 文件类型)
+    fn list(&self, dir: &str) -> Result<Vec<(String, StMode)>, ErrorNo>;
+    /// 文件系统的类型，即 mount 时的 fs_type，如 "ext2"
+    fn fs_type(&self) -> &'static str;
+    /// 文件系统的信息
+    fn stat_fs(&self, stat: *mut FsStat);
+}
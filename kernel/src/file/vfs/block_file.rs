@@ -0,0 +1,118 @@
+//! 块设备文件，如 /dev/vda 和 /dev/vda1
+//!
+//! 目录中保存的 BlockFile 只记录设备上的一段区间，每次打开时生成一个有自己文件指针的新 BlockFile。
+//! 读写都经过块缓存，和挂载在同一区间上的文件系统看到的内容一致
+
+use crate::drivers::BLOCK_CACHE;
+use crate::file::{normal_file_mode, File, Kstat, SeekFrom, StMode};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lock::Mutex;
+
+/// 块设备文件
+pub struct BlockFile {
+    /// 区间在设备上的起始位置(字节)
+    start: usize,
+    /// 区间在设备上的结束位置(字节)
+    end: usize,
+    /// 设备号，整个设备的次设备号为 0，分区的次设备号为分区编号
+    rdev: u64,
+    /// 当前位置，相对 start
+    pos: Mutex<usize>,
+    /// 上次读写失败是否是因为设备出错
+    io_error: AtomicBool,
+}
+
+impl BlockFile {
+    pub fn new(start: usize, end: usize, rdev: u64) -> Self {
+        Self {
+            start: start,
+            end: end,
+            rdev: rdev,
+            pos: Mutex::new(0),
+            io_error: AtomicBool::new(false),
+        }
+    }
+    /// 以这个文件的区间打开一个新文件
+    pub fn open(&self) -> Arc<dyn File> {
+        Arc::new(Self::new(self.start, self.end, self.rdev))
+    }
+    /// 区间的长度
+    fn size(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+impl File for BlockFile {
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut pos = self.pos.lock();
+        let read_len = self.read_from_offset(*pos, buf)?;
+        *pos += read_len;
+        Some(read_len)
+    }
+    fn write(&self, buf: &[u8]) -> Option<usize> {
+        let mut pos = self.pos.lock();
+        let write_len = self.write_to_offset(*pos, buf)?;
+        *pos += write_len;
+        Some(write_len)
+    }
+    /// 从某个位置读文件内容到 buf 中，不改变文件指针。读到区间末尾时返回 Some(0)
+    fn read_from_offset(&self, pos: usize, buf: &mut [u8]) -> Option<usize> {
+        let len = buf.len().min(self.size().saturating_sub(pos));
+        match BLOCK_CACHE.read(self.start + pos, &mut buf[..len]) {
+            Ok(read_len) => Some(read_len),
+            Err(_) => {
+                self.io_error.store(true, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+    /// 将 buf 写入文件中的某个位置，不改变文件指针。设备的大小是固定的，超出区间的部分写不进去
+    fn write_to_offset(&self, pos: usize, buf: &[u8]) -> Option<usize> {
+        let len = buf.len().min(self.size().saturating_sub(pos));
+        match BLOCK_CACHE.write(self.start + pos, &buf[..len]) {
+            Ok(write_len) => Some(write_len),
+            Err(_) => {
+                self.io_error.store(true, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+    fn take_io_error(&self) -> bool {
+        self.io_error.swap(false, Ordering::Relaxed)
+    }
+    /// 可以 seek 到区间内的任意位置
+    fn seek(&self, seekfrom: SeekFrom) -> Option<usize> {
+        let mut pos = self.pos.lock();
+        let new_pos = match seekfrom {
+            SeekFrom::Start(off) => off as i64,
+            SeekFrom::Current(off) => *pos as i64 + off,
+            SeekFrom::End(off) => self.size() as i64 + off,
+        };
+        if new_pos < 0 || new_pos as usize > self.size() {
+            return None;
+        }
+        *pos = new_pos as usize;
+        Some(*pos)
+    }
+    /// 文件属性。块设备的大小就是区间的长度
+    fn get_stat(&self, stat: *mut Kstat) -> bool {
+        unsafe {
+            (*stat).st_dev = 0;
+            (*stat).st_ino = 0;
+            (*stat).st_nlink = 1;
+            (*stat).st_mode = normal_file_mode(StMode::S_IFBLK).bits();
+            (*stat).st_rdev = self.rdev;
+            (*stat).st_size = self.size() as u64;
+            (*stat).st_uid = 0;
+            (*stat).st_gid = 0;
+            (*stat).st_atime_sec = 0;
+            (*stat).st_atime_nsec = 0;
+            (*stat).st_mtime_sec = 0;
+            (*stat).st_mtime_nsec = 0;
+            (*stat).st_ctime_sec = 0;
+            (*stat).st_ctime_nsec = 0;
+        }
+        true
+    }
+}
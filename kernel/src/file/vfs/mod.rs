@@ -1,7 +1,10 @@
 //! 虚拟文件系统管理
 //! 用于对一些特殊目录和文件的访问，如 /dev/zero 或 /tmp
 
+mod block_file;
 mod null;
+mod rtc;
+mod shm_file;
 mod temp;
 mod virt_dir;
 mod virt_file;
@@ -13,10 +16,15 @@ use lock::Mutex;
 // 但 std::collections::HashMap 不是那么容易在 no_std 下找到，需要引入依赖库
 // 所以方便起见就不用 HashMap 了
 use super::{File, Kstat, OpenFlags};
+use crate::drivers::block_device_nodes;
 use alloc::collections::BTreeMap;
+use block_file::BlockFile;
 use null::NullFile;
+pub use rtc::RtcFile;
+pub use shm_file::ShmFile;
 use virt_dir::VirtDir;
-use virt_file::{VirtFile, VirtFileInner};
+pub use virt_file::VirtFile;
+use virt_file::VirtFileInner;
 pub type BufferFile = VirtFileInner;
 use zero::ZeroFile;
 
@@ -30,6 +38,14 @@ lazy_static::lazy_static! {
             dev.create_file(&String::from("zero"), Arc::new(ZeroFile));
             dev
         }));
+        // /dev/shm 中新建的文件都是共享内存文件
+        let shm_dir = Arc::new(VirtDir::new_shared_mem(String::from("dev/shm")));
+        dirs.get("dev").unwrap().create_file(&String::from("shm"), shm_dir.clone());
+        dirs.insert(String::from("dev/shm"), shm_dir);
+        let misc_dir = Arc::new(VirtDir::new(String::from("dev/misc")));
+        misc_dir.create_file(&String::from("rtc"), Arc::new(RtcFile));
+        dirs.get("dev").unwrap().create_file(&String::from("misc"), misc_dir.clone());
+        dirs.insert(String::from("dev/misc"), misc_dir);
         /*
         dirs.insert(String::from("tmp"), Arc::new({
             VirtDir::new(String::from("tmp"))
@@ -42,6 +58,18 @@ lazy_static::lazy_static! {
     });
 }
 
+/// 块设备的主设备号，和 Linux 上 virtio-blk 通常分到的一致
+const BLOCK_DEVICE_MAJOR: u64 = 254;
+
+/// 在 /dev 下加入块设备和它的每个分区对应的文件，如 /dev/vda 和 /dev/vda1
+pub fn add_block_device_files() {
+    let dev = VFS_DIRS.lock().get("dev").unwrap().clone();
+    for (name, number, start, end) in block_device_nodes() {
+        let rdev = BLOCK_DEVICE_MAJOR << 8 | number as u64;
+        dev.create_file(&name, Arc::new(BlockFile::new(start, end, rdev)));
+    }
+}
+
 /// 查询这个目录是否是 vfs 里的目录，如果是则从 vfs 中取对应文件
 pub fn get_virt_file_if_possible(dir: &String, file: &String, flags: OpenFlags) -> Option<Arc<dyn File>> {
     match VFS_DIRS
@@ -0,0 +1,28 @@
+//! 硬件时钟，用于 dev/misc/rtc
+//!
+//! 没有真正的 RTC 设备，时间和 CLOCK_REALTIME 一样从启动时的 1970-01-01 算起。
+//! 只支持 busybox 的 hwclock 用到的 RTC_RD_TIME，见 sys_ioctl
+
+use super::{File, Kstat};
+use crate::file::{virt_file_stat, StMode};
+
+/// misc 设备的主设备号和 rtc 的次设备号，和 Linux 相同
+const RTC_RDEV: u64 = (10 << 8) | 135;
+
+pub struct RtcFile;
+
+impl File for RtcFile {
+    /// 读到的是时钟中断的信息，这里没有时钟中断
+    fn read(&self, _buf: &mut [u8]) -> Option<usize> {
+        None
+    }
+    /// 不能直接写，设置时间需要用 ioctl
+    fn write(&self, _buf: &[u8]) -> Option<usize> {
+        None
+    }
+    /// 文件属性
+    fn get_stat(&self, stat: *mut Kstat) -> bool {
+        virt_file_stat(stat, StMode::S_IFCHR, RTC_RDEV);
+        true
+    }
+}
@@ -0,0 +1,198 @@
+//! /dev/shm 下的共享内存文件，shm_open 打开的就是这种文件
+//!
+//! 文件内容保存在一组 SharedFrames 中。以 MAP_SHARED 方式 mmap 这个文件时，
+//! 直接把这组页帧以 PmAreaShared 的形式映射进地址空间，所以不同进程的修改互相可见，也不需要 msync。
+//!
+//! 目录中保存一个 ShmFile，每次打开时生成一个共享同一份内容、但有自己的文件指针的新 ShmFile
+
+use crate::constants::PAGE_SIZE;
+use crate::file::{normal_file_mode, File, Kstat, SeekFrom, StMode};
+use crate::memory::{addr_to_page_id, new_shared_frames, page_count, page_offset, Frame, SharedFrames};
+use alloc::sync::Arc;
+use lock::Mutex;
+
+/// 共享内存文件
+pub struct ShmFile {
+    data: Arc<Mutex<ShmData>>,
+    /// 当前文件指针位置
+    pos: Mutex<usize>,
+}
+
+/// 文件内容，被同一个文件的所有打开实例共享
+struct ShmData {
+    /// 保存文件内容的页帧。页数可能超过文件大小，多出的部分是 mmap 时映射到文件末尾之后的页
+    frames: SharedFrames,
+    /// 文件大小
+    size: usize,
+}
+
+impl ShmData {
+    /// 保证页帧数组至少有 pages 页
+    fn reserve(&self, pages: usize) {
+        let mut frames = self.frames.lock();
+        if frames.len() < pages {
+            frames.resize_with(pages, || None);
+        }
+    }
+    /// 从 pos 处读文件内容到 buf 中，返回读到的字节数。没有分配的页读出来是 0
+    fn read_at(&self, pos: usize, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.size.saturating_sub(pos));
+        let frames = self.frames.lock();
+        let mut done = 0;
+        while done < len {
+            let off = page_offset(pos + done);
+            let n = (PAGE_SIZE - off).min(len - done);
+            match &frames[addr_to_page_id(pos + done)] {
+                Some(frame) => buf[done..done + n].copy_from_slice(&frame.as_slice()[off..off + n]),
+                None => buf[done..done + n].fill(0),
+            }
+            done += n;
+        }
+        len
+    }
+    /// 把 buf 写入文件的 pos 处，返回写入的字节数。必要时扩大文件
+    fn write_at(&mut self, pos: usize, buf: &[u8]) -> usize {
+        self.reserve(page_count(pos + buf.len()));
+        let mut frames = self.frames.lock();
+        let mut done = 0;
+        while done < buf.len() {
+            let off = page_offset(pos + done);
+            let n = (PAGE_SIZE - off).min(buf.len() - done);
+            let slot = &mut frames[addr_to_page_id(pos + done)];
+            if slot.is_none() {
+                match Frame::new() {
+                    Some(mut frame) => {
+                        frame.zero();
+                        *slot = Some(frame);
+                    }
+                    // 内存不够时只写入已经写完的部分
+                    None => break,
+                }
+            }
+            slot.as_mut().unwrap().as_slice_mut()[off..off + n].copy_from_slice(&buf[done..done + n]);
+            done += n;
+        }
+        drop(frames);
+        self.size = self.size.max(pos + done);
+        done
+    }
+    /// 修改文件大小。缩小时被截掉的内容清零，这样之后再扩大时读到的是 0
+    fn truncate(&mut self, len: usize) {
+        if len < self.size {
+            let mut frames = self.frames.lock();
+            let keep = page_count(len);
+            if page_offset(len) != 0 {
+                if let Some(frame) = &mut frames[keep - 1] {
+                    frame.as_slice_mut()[page_offset(len)..].fill(0);
+                }
+            }
+            if Arc::strong_count(&self.frames) == 1 {
+                // 没有被映射，多出的页可以直接释放
+                frames.truncate(keep);
+            } else {
+                // 这些页可能还映射在某些地址空间里，只能清零
+                for frame in frames[keep..].iter_mut().flatten() {
+                    frame.zero();
+                }
+            }
+        }
+        self.reserve(page_count(len));
+        self.size = len;
+    }
+}
+
+impl ShmFile {
+    /// 创建一个空文件
+    pub fn new() -> Self {
+        Self {
+            data: Arc::new(Mutex::new(ShmData {
+                frames: new_shared_frames(0),
+                size: 0,
+            })),
+            pos: Mutex::new(0),
+        }
+    }
+    /// 打开文件，返回一个共享文件内容、指针从头开始的新实例
+    pub fn open(&self) -> Arc<dyn File> {
+        Arc::new(Self {
+            data: self.data.clone(),
+            pos: Mutex::new(0),
+        })
+    }
+    /// 获取 mmap 文件中 [offset, offset + len) 时需要映射的页帧数组。
+    /// 如果这段区间超过了文件末尾，会先扩充页帧数组，但不改变文件大小
+    pub fn frames_for_mmap(&self, offset: usize, len: usize) -> SharedFrames {
+        let data = self.data.lock();
+        data.reserve(page_count(offset + len));
+        data.frames.clone()
+    }
+}
+
+impl File for ShmFile {
+    /// 读取文件
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut pos = self.pos.lock();
+        let read_len = self.data.lock().read_at(*pos, buf);
+        *pos += read_len;
+        Some(read_len)
+    }
+    /// 写入文件
+    fn write(&self, buf: &[u8]) -> Option<usize> {
+        let mut pos = self.pos.lock();
+        let write_len = self.data.lock().write_at(*pos, buf);
+        *pos += write_len;
+        Some(write_len)
+    }
+    /// 从某个位置读文件内容到 buf 中，返回读到的字节数，但不改变指针位置
+    fn read_from_offset(&self, pos: usize, buf: &mut [u8]) -> Option<usize> {
+        Some(self.data.lock().read_at(pos, buf))
+    }
+    /// 将 buf 写入文件中的某个位置，返回写入的字节数，但不改变指针位置
+    fn write_to_offset(&self, pos: usize, buf: &[u8]) -> Option<usize> {
+        Some(self.data.lock().write_at(pos, buf))
+    }
+    /// 切换文件指针位置
+    fn seek(&self, seekfrom: SeekFrom) -> Option<usize> {
+        let mut pos = self.pos.lock();
+        let new_pos = match seekfrom {
+            SeekFrom::Start(off) => off as i64,
+            SeekFrom::Current(off) => *pos as i64 + off,
+            SeekFrom::End(off) => self.data.lock().size as i64 + off,
+        };
+        // 不能移动到文件前
+        if new_pos < 0 {
+            return None;
+        }
+        *pos = new_pos as usize;
+        Some(*pos)
+    }
+    /// 清空文件
+    fn clear(&self) {
+        self.data.lock().truncate(0);
+    }
+    /// 修改文件大小
+    fn truncate(&self, len: usize) -> bool {
+        self.data.lock().truncate(len);
+        true
+    }
+    /// 文件属性
+    fn get_stat(&self, stat: *mut Kstat) -> bool {
+        let size = self.data.lock().size;
+        unsafe {
+            (*stat).st_dev = 0;
+            (*stat).st_ino = 0;
+            (*stat).st_nlink = 1;
+            (*stat).st_mode = normal_file_mode(StMode::S_IFREG).bits();
+            (*stat).st_size = size as u64;
+            (*stat).st_uid = 0;
+            (*stat).st_gid = 0;
+            (*stat).st_atime_sec = 0;
+            (*stat).st_atime_nsec = 0;
+            (*stat).st_mtime_sec = 0;
+            (*stat).st_mtime_nsec = 0;
+            (*stat).st_ctime_sec = 0;
+            (*stat).st_ctime_nsec = 0;
+        }
+        true
+    }
+}
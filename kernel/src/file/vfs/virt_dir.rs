@@ -4,7 +4,7 @@
 use crate::file::{normal_file_mode, File, OpenFlags, Kstat, StMode};
 use alloc::{string::String, sync::Arc, vec::Vec};
 use lock::Mutex;
-use super::VirtFile;
+use super::{BlockFile, ShmFile, VirtFile};
 
 /// 目录项
 pub struct DirEntry {
@@ -26,6 +26,8 @@ impl DirEntry {
 pub struct VirtDir {
     entry: Mutex<Vec<DirEntry>>,
     name: String,
+    /// 目录中新建的文件是否是共享内存文件，如 /dev/shm。子目录也继承这个属性
+    shared_mem: bool,
 }
 
 impl VirtDir {
@@ -34,6 +36,15 @@ impl VirtDir {
         Self {
             entry: Mutex::new(Vec::new()),
             name: name,
+            shared_mem: false,
+        }
+    }
+    /// 创建目录，其中新建的文件都是共享内存文件
+    pub fn new_shared_mem(name: String) -> Self {
+        Self {
+            entry: Mutex::new(Vec::new()),
+            name: name,
+            shared_mem: true,
         }
     }
     /// 获取目录名字
@@ -67,9 +78,19 @@ impl VirtDir {
             let mut self_entry = self.entry.lock();
             match self_entry.iter().find(|&e| e.name == *file_name).map(|e| e.file.clone()) {
                 Some(f) => {
-                    if flags.contains(OpenFlags::EXCL) {
+                    if let Some(shm_file) = (*f).as_any().downcast_ref::<ShmFile>() {
+                        // 共享内存文件在 O_CREAT 时不清空，否则 shm_open 同一个对象的进程会互相清掉内容
+                        if flags.contains(OpenFlags::EXCLUSIVE) {
+                            None
+                        } else {
+                            Some(shm_file.open())
+                        }
+                    } else if flags.contains(OpenFlags::EXCL) {
                         //要求必须要创建文件
                         None
+                    } else if let Some(block_file) = (*f).as_any().downcast_ref::<BlockFile>() {
+                        // 块设备文件每次打开都有自己的文件指针。O_CREAT 时也不能清空设备
+                        Some(block_file.open())
                     } else {
                         if flags.contains(OpenFlags::CREATE) {
                             // 清空这个文件
@@ -81,6 +102,12 @@ impl VirtDir {
                 None => {
                     // 找不到且要求创建，则默认创建 VirtFile
                     if flags.contains(OpenFlags::CREATE) {
+                        if self.shared_mem {
+                            let file = ShmFile::new();
+                            let ret = file.open();
+                            self_entry.push(DirEntry::new(file_name.clone(), Arc::new(file)));
+                            return Some(ret);
+                        }
                         let file:Arc<dyn File> = Arc::new(VirtFile::new(flags));
                         let ret = file.clone();
                         self_entry.push(DirEntry::new(file_name.clone(), file));
@@ -100,7 +127,11 @@ impl VirtDir {
         if self.check_file_exists(dir_name) {
             None
         } else {
-            let dir:Arc<VirtDir> = Arc::new(VirtDir::new(self.name.clone() + "/" + dir_name.as_str()));
+            let dir:Arc<VirtDir> = Arc::new(VirtDir {
+                entry: Mutex::new(Vec::new()),
+                name: self.name.clone() + "/" + dir_name.as_str(),
+                shared_mem: self.shared_mem,
+            });
             let ret = dir.clone();
             self.entry.lock().push(DirEntry::new(dir_name.clone(), dir));
             Some(ret)
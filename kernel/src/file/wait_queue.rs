@@ -0,0 +1,117 @@
+//! 文件的等待队列
+//!
+//! select / poll / epoll 等待文件时，把线程的 tid 登记在文件的等待队列里，然后通过 WAITING_BOARD 睡眠。
+//! 文件状态变化(如 pipe 被写入数据)时唤醒队列中的线程，由它们自己重新检查文件是否就绪。
+//!
+//! 以 EPOLLEXCLUSIVE 登记的线程是"独占"的：每次唤醒时普通线程全部唤醒，独占的线程只唤醒一个。
+//! 队列还记录被唤醒的次数，epoll 的边缘触发用它判断文件状态是否变化过。
+//!
+//! 阻塞的读写等操作也通过 wait_for 在队列上睡眠。它们先记下队列被唤醒的次数再检查条件，
+//! 登记之后如果次数变了，说明检查之后状态可能已经变化，就不再睡眠，这样不会错过唤醒
+
+use crate::syscall::{set_waiter_for_thread, wake_thread, ErrorNo, FutexWaiter};
+use crate::task::{get_current_task, suspend_current_task};
+use crate::timer::get_time_us;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeSet, VecDeque};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lock::Mutex;
+
+/// 等待某个文件状态变化的线程
+pub struct WaitQueue {
+    tids: Mutex<BTreeSet<usize>>,
+    /// 独占等待的线程，按登记的顺序唤醒
+    exclusive_tids: Mutex<VecDeque<usize>>,
+    /// 队列被唤醒的次数
+    wakes: AtomicUsize,
+}
+
+impl WaitQueue {
+    /// 新建一个空的等待队列
+    pub fn new() -> Self {
+        Self {
+            tids: Mutex::new(BTreeSet::new()),
+            exclusive_tids: Mutex::new(VecDeque::new()),
+            wakes: AtomicUsize::new(0),
+        }
+    }
+    /// 登记线程 tid。重复登记只算一次
+    pub fn register(&self, tid: usize) {
+        self.tids.lock().insert(tid);
+    }
+    /// 以独占方式登记线程 tid。重复登记只算一次
+    pub fn register_exclusive(&self, tid: usize) {
+        let mut exclusive_tids = self.exclusive_tids.lock();
+        if !exclusive_tids.contains(&tid) {
+            exclusive_tids.push_back(tid);
+        }
+    }
+    /// 取消线程 tid 的登记
+    pub fn unregister(&self, tid: usize) {
+        self.tids.lock().remove(&tid);
+        self.exclusive_tids.lock().retain(|&t| t != tid);
+    }
+    /// 唤醒并清空队列中的所有普通线程，以及一个独占的线程
+    pub fn wake_all(&self) {
+        self.wakes.fetch_add(1, Ordering::Release);
+        // 先取出队列再唤醒，避免拿着队列的锁去拿 WAITING_BOARD 的锁
+        let tids = core::mem::take(&mut *self.tids.lock());
+        for tid in tids {
+            wake_thread(tid);
+        }
+        // 跳过已经不在等待的线程，否则这次唤醒就丢了
+        loop {
+            let tid = match self.exclusive_tids.lock().pop_front() {
+                Some(tid) => tid,
+                None => break,
+            };
+            if wake_thread(tid) {
+                break;
+            }
+        }
+    }
+    /// 队列被唤醒的次数
+    pub fn wake_count(&self) -> usize {
+        self.wakes.load(Ordering::Acquire)
+    }
+    /// 当前线程登记在队列上睡眠一轮，见 wait_for。wakes 是检查等待条件之前的 wake_count
+    pub fn wait(&self, wakes: usize, deadline: Option<usize>) -> Result<(), ErrorNo> {
+        wait_for(
+            deadline,
+            |tid| {
+                self.register(tid);
+                self.wake_count() != wakes
+            },
+            |tid| self.unregister(tid),
+        )
+    }
+}
+
+/// 当前线程睡眠一轮，直到被唤醒、超过截止时刻 deadline(us) 或者收到信号。
+/// 已经超过截止时刻时返回 EAGAIN，被信号打断时返回 EINTR，否则返回 Ok，由调用者重新检查等待的条件。
+///
+/// register 把线程登记到等待队列上，返回登记之后是否发现队列已经被唤醒过，此时不睡眠。醒来后调用 unregister 取消登记
+pub fn wait_for(
+    deadline: Option<usize>,
+    register: impl FnOnce(usize) -> bool,
+    unregister: impl FnOnce(usize),
+) -> Result<(), ErrorNo> {
+    if deadline.map_or(false, |time| get_time_us() >= time) {
+        return Err(ErrorNo::EAGAIN);
+    }
+    let task = get_current_task().unwrap();
+    let tid = task.get_tid_num();
+    // 先设置 waiter 再登记，这样登记之后的唤醒和信号都能叫醒线程。超时由 waiter 自己判断
+    set_waiter_for_thread(tid, Box::new(FutexWaiter::new(deadline)));
+    if !register(tid) && !task.signal_receivers.lock().has_pending_signal() {
+        suspend_current_task();
+    }
+    unregister(tid);
+    // 清除 WAITING_BOARD 上还没被唤醒的 waiter，否则线程之后会被它阻塞
+    wake_thread(tid);
+    if task.signal_receivers.lock().has_pending_signal() {
+        Err(ErrorNo::EINTR)
+    } else {
+        Ok(())
+    }
+}
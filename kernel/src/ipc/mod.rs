@@ -0,0 +1,165 @@
+//! System V IPC，包括共享内存(shm)、信号量集(sem)和消息队列(msg)
+//!
+//! 三种对象各有一个独立的命名空间，用户通过 key 获取对象的 id，之后的操作都通过 id 进行。
+//! key 为 IPC_PRIVATE 时总是创建新对象。
+//!
+//! 这个模块只负责对象本身，用户地址的检查和读写在 syscall 模块中完成。
+//! 所有对象都可以在 /proc/sysvipc 下查看
+
+mod msg;
+mod sem;
+mod shm;
+
+pub use msg::{msg_ctl, msg_get, msg_receive, msg_send, MsqidDs, MSGMAX};
+pub use sem::{
+    exit_sem, sem_count, sem_ctl, sem_ctl_value, sem_get, sem_get_all, sem_op, sem_set_all, SemBuf,
+    SemidDs, GETALL, SEMOPM, SETALL,
+};
+pub use shm::{shm_attach, shm_ctl, shm_detach, shm_fork, shm_get, ShmidDs, SHM_NAME};
+
+use crate::{file::add_proc_file, syscall::ErrorNo};
+use alloc::{collections::BTreeMap, sync::Arc};
+
+/// 总是创建新对象的 key
+pub const IPC_PRIVATE: i32 = 0;
+/// 对象不存在时创建它
+pub const IPC_CREAT: i32 = 0o1000;
+/// 和 IPC_CREAT 一起使用，要求对象必须是新创建的
+pub const IPC_EXCL: i32 = 0o2000;
+/// 操作无法立即完成时不阻塞，而是返回错误
+pub const IPC_NOWAIT: i32 = 0o4000;
+
+// 各种 ctl 共用的命令
+/// 删除对象
+pub const IPC_RMID: i32 = 0;
+/// 设置对象的权限等信息
+pub const IPC_SET: i32 = 1;
+/// 获取对象的信息
+pub const IPC_STAT: i32 = 2;
+
+/// 用户态传入的 cmd 可能带有这个标记，表示使用 64 位的结构。本来就是 64 位的平台上可以忽略它
+const IPC_64: i32 = 0x100;
+
+/// 去掉 cmd 中的 IPC_64 标记
+pub fn ipc_cmd(cmd: i32) -> i32 {
+    cmd & !IPC_64
+}
+
+/// IPC 对象的权限信息，即 struct ipc64_perm
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IpcPerm {
+    pub key: i32,
+    pub uid: u32,
+    pub gid: u32,
+    pub cuid: u32,
+    pub cgid: u32,
+    pub mode: u32,
+    pub seq: u16,
+    _pad: u16,
+    _unused1: u64,
+    _unused2: u64,
+}
+
+impl IpcPerm {
+    /// 新建对象时的权限。目前只有 root 用户，所以 uid 和 gid 都是 0
+    pub fn new(key: i32, flags: i32) -> Self {
+        Self {
+            key,
+            uid: 0,
+            gid: 0,
+            cuid: 0,
+            cgid: 0,
+            mode: (flags & 0o777) as u32,
+            seq: 0,
+            _pad: 0,
+            _unused1: 0,
+            _unused2: 0,
+        }
+    }
+    /// IPC_SET 时只能修改所有者和权限位
+    pub fn set(&mut self, new_perm: &IpcPerm) {
+        self.uid = new_perm.uid;
+        self.gid = new_perm.gid;
+        self.mode = (self.mode & !0o777) | (new_perm.mode & 0o777);
+    }
+}
+
+/// 一种 IPC 对象的命名空间，保存 key 到 id 和 id 到对象的映射
+pub struct IpcNamespace<T> {
+    /// key 到 id 的映射。IPC_PRIVATE 创建的对象和已被删除的对象不在这里
+    keys: BTreeMap<i32, usize>,
+    /// id 到对象的映射
+    objects: BTreeMap<usize, Arc<T>>,
+    /// 下一个分配的 id。id 不会复用，这样用户拿着已删除对象的 id 不会误操作到新对象
+    next_id: usize,
+}
+
+impl<T> IpcNamespace<T> {
+    /// 新建一个空的命名空间
+    pub const fn new() -> Self {
+        Self {
+            keys: BTreeMap::new(),
+            objects: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// 按 key 获取对象的 id，返回 id 以及对象是否是新创建的。
+    ///
+    /// 对象不存在且 flags 中有 IPC_CREAT 时，调用 create 创建对象
+    pub fn get_or_create(
+        &mut self,
+        key: i32,
+        flags: i32,
+        create: impl FnOnce() -> Result<T, ErrorNo>,
+    ) -> Result<(usize, bool), ErrorNo> {
+        if key != IPC_PRIVATE {
+            if let Some(&id) = self.keys.get(&key) {
+                if flags & IPC_CREAT != 0 && flags & IPC_EXCL != 0 {
+                    return Err(ErrorNo::EEXIST);
+                }
+                return Ok((id, false));
+            }
+            if flags & IPC_CREAT == 0 {
+                return Err(ErrorNo::ENOENT);
+            }
+        }
+        let object = create()?;
+        let id = self.next_id;
+        self.next_id += 1;
+        if key != IPC_PRIVATE {
+            self.keys.insert(key, id);
+        }
+        self.objects.insert(id, Arc::new(object));
+        Ok((id, true))
+    }
+
+    /// 获取 id 对应的对象
+    pub fn get(&self, id: usize) -> Option<Arc<T>> {
+        self.objects.get(&id).cloned()
+    }
+
+    /// 让对象不能再通过 key 找到，但对象本身仍然存在
+    pub fn unlink_key(&mut self, id: usize) {
+        self.keys.retain(|_, &mut v| v != id);
+    }
+
+    /// 删除 id 对应的对象
+    pub fn remove(&mut self, id: usize) -> Option<Arc<T>> {
+        self.unlink_key(id);
+        self.objects.remove(&id)
+    }
+
+    /// 遍历所有对象
+    pub fn iter(&self) -> impl Iterator<Item = (&usize, &Arc<T>)> {
+        self.objects.iter()
+    }
+}
+
+/// 在 /proc/sysvipc 下注册所有 IPC 对象的信息文件
+pub fn init() {
+    add_proc_file("sysvipc", "shm", shm::proc_info);
+    add_proc_file("sysvipc", "sem", sem::proc_info);
+    add_proc_file("sysvipc", "msg", msg::proc_info);
+}
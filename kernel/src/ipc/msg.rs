@@ -0,0 +1,251 @@
+//! 消息队列
+//!
+//! 每条消息带有一个正整数的类型。msgrcv 可以按类型选择消息：
+//! - msgtyp == 0 时取队列中的第一条
+//! - msgtyp > 0 时取第一条类型等于 msgtyp 的消息(有 MSG_EXCEPT 时是第一条不等于的)
+//! - msgtyp < 0 时取类型不超过 |msgtyp| 的消息中类型最小的第一条
+//!
+//! 队列满时 msgsnd 阻塞，找不到消息时 msgrcv 阻塞，除非指定了 IPC_NOWAIT。
+//! 阻塞的线程在队列的等待队列上睡眠，消息被发送或取走、队列大小被修改或者队列被删除时唤醒
+
+use super::{ipc_cmd, IpcNamespace, IpcPerm, IPC_NOWAIT, IPC_RMID, IPC_SET, IPC_STAT};
+use crate::{file::WaitQueue, syscall::ErrorNo, timer::get_time_sec};
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use lock::Mutex;
+
+/// 单条消息的最大长度
+pub const MSGMAX: usize = 8192;
+/// 队列默认的最大总字节数
+const MSGMNB: usize = 16384;
+/// 消息比用户的 buffer 长时截断而不是报错
+const MSG_NOERROR: i32 = 0o10000;
+/// msgtyp > 0 时取第一条类型不等于 msgtyp 的消息
+const MSG_EXCEPT: i32 = 0o20000;
+
+/// msgctl 使用的结构，即 struct msqid64_ds
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MsqidDs {
+    pub msg_perm: IpcPerm,
+    pub msg_stime: isize,
+    pub msg_rtime: isize,
+    pub msg_ctime: isize,
+    pub msg_cbytes: usize,
+    pub msg_qnum: usize,
+    pub msg_qbytes: usize,
+    pub msg_lspid: i32,
+    pub msg_lrpid: i32,
+    _unused4: usize,
+    _unused5: usize,
+}
+
+/// 一条消息
+struct Message {
+    mtype: isize,
+    data: Vec<u8>,
+}
+
+/// 一个消息队列
+struct MsgQueue {
+    inner: Mutex<MsgQueueInner>,
+    /// 等待发送或接收的线程
+    waiters: WaitQueue,
+}
+
+struct MsgQueueInner {
+    perm: IpcPerm,
+    messages: VecDeque<Message>,
+    /// 队列中所有消息的总字节数
+    cbytes: usize,
+    /// 队列允许的最大总字节数
+    qbytes: usize,
+    /// 最后一次 msgsnd 的时间
+    stime: usize,
+    /// 最后一次 msgrcv 的时间
+    rtime: usize,
+    /// 创建或最后一次 IPC_SET 的时间
+    ctime: usize,
+    /// 最后一次 msgsnd 的 pid
+    lspid: usize,
+    /// 最后一次 msgrcv 的 pid
+    lrpid: usize,
+    /// 是否已被删除。删除时正在等待的线程会返回 EIDRM
+    removed: bool,
+}
+
+impl MsgQueueInner {
+    /// 按 msgtyp 和 flags 寻找要接收的消息，返回它在队列中的下标
+    fn find_message(&self, msgtyp: isize, flags: i32) -> Option<usize> {
+        if msgtyp == 0 {
+            (!self.messages.is_empty()).then_some(0)
+        } else if msgtyp > 0 {
+            let except = flags & MSG_EXCEPT != 0;
+            self.messages.iter().position(|msg| (msg.mtype == msgtyp) != except)
+        } else {
+            // 类型相同时取最早的，所以只在严格更小时替换
+            let mut found: Option<usize> = None;
+            for (i, msg) in self.messages.iter().enumerate() {
+                if msg.mtype <= -msgtyp && found.map_or(true, |j| msg.mtype < self.messages[j].mtype) {
+                    found = Some(i);
+                }
+            }
+            found
+        }
+    }
+}
+
+/// 所有消息队列
+static MSG_QUEUES: Mutex<IpcNamespace<MsgQueue>> = Mutex::new(IpcNamespace::new());
+
+/// 获取 key 对应的消息队列，必要时创建新队列。返回队列的 id
+pub fn msg_get(key: i32, flags: i32) -> Result<usize, ErrorNo> {
+    let (id, _) = MSG_QUEUES.lock().get_or_create(key, flags, || {
+        Ok(MsgQueue {
+            inner: Mutex::new(MsgQueueInner {
+                perm: IpcPerm::new(key, flags),
+                messages: VecDeque::new(),
+                cbytes: 0,
+                qbytes: MSGMNB,
+                stime: 0,
+                rtime: 0,
+                ctime: get_time_sec(),
+                lspid: 0,
+                lrpid: 0,
+                removed: false,
+            }),
+            waiters: WaitQueue::new(),
+        })
+    })?;
+    Ok(id)
+}
+
+/// 向 id 对应的队列发送一条类型为 mtype 的消息，队列满时阻塞
+pub fn msg_send(id: usize, mtype: isize, data: Vec<u8>, flags: i32, pid: usize) -> Result<usize, ErrorNo> {
+    if mtype < 1 || data.len() > MSGMAX {
+        return Err(ErrorNo::EINVAL);
+    }
+    let queue = MSG_QUEUES.lock().get(id).ok_or(ErrorNo::EINVAL)?;
+    loop {
+        let wakes = queue.waiters.wake_count();
+        let mut inner = queue.inner.lock();
+        if inner.removed {
+            return Err(ErrorNo::EIDRM);
+        }
+        if inner.cbytes + data.len() <= inner.qbytes {
+            inner.cbytes += data.len();
+            inner.messages.push_back(Message { mtype, data });
+            inner.stime = get_time_sec();
+            inner.lspid = pid;
+            drop(inner);
+            queue.waiters.wake_all();
+            return Ok(0);
+        }
+        if flags & IPC_NOWAIT != 0 {
+            return Err(ErrorNo::EAGAIN);
+        }
+        drop(inner);
+        queue.waiters.wait(wakes, None)?;
+    }
+}
+
+/// 从 id 对应的队列中按 msgtyp 接收一条消息，返回消息的类型和内容。找不到消息时阻塞。
+///
+/// 消息长度超过 max_len 时，如果有 MSG_NOERROR 则截断，否则返回 E2BIG 且消息留在队列中
+pub fn msg_receive(id: usize, msgtyp: isize, max_len: usize, flags: i32, pid: usize) -> Result<(isize, Vec<u8>), ErrorNo> {
+    let queue = MSG_QUEUES.lock().get(id).ok_or(ErrorNo::EINVAL)?;
+    loop {
+        let wakes = queue.waiters.wake_count();
+        let mut inner = queue.inner.lock();
+        if inner.removed {
+            return Err(ErrorNo::EIDRM);
+        }
+        if let Some(idx) = inner.find_message(msgtyp, flags) {
+            if inner.messages[idx].data.len() > max_len && flags & MSG_NOERROR == 0 {
+                return Err(ErrorNo::E2BIG);
+            }
+            let mut msg = inner.messages.remove(idx).unwrap();
+            inner.cbytes -= msg.data.len();
+            inner.rtime = get_time_sec();
+            inner.lrpid = pid;
+            drop(inner);
+            // 队列腾出了空间
+            queue.waiters.wake_all();
+            msg.data.truncate(max_len);
+            return Ok((msg.mtype, msg.data));
+        }
+        if flags & IPC_NOWAIT != 0 {
+            return Err(ErrorNo::ENOMSG);
+        }
+        drop(inner);
+        queue.waiters.wait(wakes, None)?;
+    }
+}
+
+/// 对 id 对应的队列执行 msgctl 操作。IPC_STAT 时结果写入 ds，IPC_SET 时从 ds 中读取新的权限和队列大小
+pub fn msg_ctl(id: usize, cmd: i32, ds: &mut MsqidDs) -> Result<usize, ErrorNo> {
+    let mut queues = MSG_QUEUES.lock();
+    let queue = queues.get(id).ok_or(ErrorNo::EINVAL)?;
+    let mut inner = queue.inner.lock();
+    match ipc_cmd(cmd) {
+        IPC_STAT => {
+            *ds = MsqidDs {
+                msg_perm: inner.perm,
+                msg_stime: inner.stime as isize,
+                msg_rtime: inner.rtime as isize,
+                msg_ctime: inner.ctime as isize,
+                msg_cbytes: inner.cbytes,
+                msg_qnum: inner.messages.len(),
+                msg_qbytes: inner.qbytes,
+                msg_lspid: inner.lspid as i32,
+                msg_lrpid: inner.lrpid as i32,
+                _unused4: 0,
+                _unused5: 0,
+            };
+        }
+        IPC_SET => {
+            inner.perm.set(&ds.msg_perm);
+            inner.qbytes = ds.msg_qbytes;
+            inner.ctime = get_time_sec();
+            drop(inner);
+            // 队列变大时等待发送的线程可能可以继续
+            queue.waiters.wake_all();
+        }
+        IPC_RMID => {
+            // 唤醒正在等待的线程，它们会发现队列已删除
+            inner.removed = true;
+            drop(inner);
+            queue.waiters.wake_all();
+            queues.remove(id);
+        }
+        _ => return Err(ErrorNo::EINVAL),
+    }
+    Ok(0)
+}
+
+/// 生成 /proc/sysvipc/msg 的内容
+pub fn proc_info() -> String {
+    let mut info = String::from(
+        "       key      msqid perms      cbytes       qnum lspid lrpid   uid   gid  cuid  cgid      stime      rtime      ctime\n",
+    );
+    for (id, queue) in MSG_QUEUES.lock().iter() {
+        let inner = queue.inner.lock();
+        info += &format!(
+            "{:>10} {:>10}  {:>4o}  {:>10} {:>10} {:>5} {:>5} {:>5} {:>5} {:>5} {:>5} {:>10} {:>10} {:>10}\n",
+            inner.perm.key,
+            id,
+            inner.perm.mode,
+            inner.cbytes,
+            inner.messages.len(),
+            inner.lspid,
+            inner.lrpid,
+            inner.perm.uid,
+            inner.perm.gid,
+            inner.perm.cuid,
+            inner.perm.cgid,
+            inner.stime,
+            inner.rtime,
+            inner.ctime,
+        );
+    }
+    info
+}
@@ -0,0 +1,370 @@
+//! 信号量集
+//!
+//! semop 的一组操作要么全部完成，要么全部不做。无法完成时(除非指定 IPC_NOWAIT)会在集合的等待队列上睡眠，
+//! 集合中的信号量被修改或者集合被删除时唤醒，然后重新尝试整组操作。
+//!
+//! 带 SEM_UNDO 的操作会记录在进程的调整表中，进程退出时按表把信号量的值改回去。
+//! 调整表按 pid 保存，所以同一进程的所有线程共享一张表
+
+use super::{ipc_cmd, IpcNamespace, IpcPerm, IPC_NOWAIT, IPC_RMID, IPC_SET, IPC_STAT};
+use crate::{
+    file::WaitQueue,
+    syscall::ErrorNo,
+    timer::{get_time_sec, get_time_us},
+};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use lock::Mutex;
+
+/// 进程退出时需要撤销这个操作
+const SEM_UNDO: i16 = 0x1000;
+/// 信号量的最大值
+const SEMVMX: i32 = 32767;
+/// 一个信号量集中最多的信号量数
+const SEMMSL: usize = 32000;
+/// 一次 semop 最多的操作数
+pub const SEMOPM: usize = 500;
+
+// semctl 中针对单个信号量或整个集合的值的命令
+/// 获取最后一次操作这个信号量的 pid
+const GETPID: i32 = 11;
+/// 获取信号量的值
+const GETVAL: i32 = 12;
+/// 获取集合中所有信号量的值
+pub const GETALL: i32 = 13;
+/// 获取等待信号量增加的线程数
+const GETNCNT: i32 = 14;
+/// 获取等待信号量变为 0 的线程数
+const GETZCNT: i32 = 15;
+/// 设置信号量的值
+const SETVAL: i32 = 16;
+/// 设置集合中所有信号量的值
+pub const SETALL: i32 = 17;
+
+/// semop 的一个操作，即 struct sembuf
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SemBuf {
+    /// 信号量在集合中的下标
+    pub sem_num: u16,
+    /// 正数表示增加，负数表示等待并减少，0 表示等待值变为 0
+    pub sem_op: i16,
+    /// IPC_NOWAIT 和 SEM_UNDO
+    pub sem_flg: i16,
+}
+
+/// semctl 使用的结构，即 struct semid64_ds
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SemidDs {
+    pub sem_perm: IpcPerm,
+    pub sem_otime: isize,
+    pub sem_ctime: isize,
+    pub sem_nsems: usize,
+    _unused3: usize,
+    _unused4: usize,
+}
+
+/// 一个信号量
+#[derive(Clone, Copy)]
+struct Semaphore {
+    val: i32,
+    /// 最后一次操作它的 pid
+    pid: usize,
+    /// 等待它增加的线程数
+    ncnt: usize,
+    /// 等待它变为 0 的线程数
+    zcnt: usize,
+}
+
+/// 一个信号量集
+struct SemSet {
+    inner: Mutex<SemSetInner>,
+    /// 等待信号量变化的线程
+    waiters: WaitQueue,
+}
+
+struct SemSetInner {
+    perm: IpcPerm,
+    sems: Vec<Semaphore>,
+    /// 最后一次 semop 的时间
+    otime: usize,
+    /// 创建或最后一次修改的时间
+    ctime: usize,
+    /// 是否已被删除。删除时正在等待的线程会返回 EIDRM
+    removed: bool,
+}
+
+/// 尝试执行一组操作的结果
+enum SemOpResult {
+    /// 全部完成
+    Done,
+    /// 第 i 个操作需要等待
+    Blocked(usize),
+    /// 操作不合法
+    Failed(ErrorNo),
+}
+
+impl SemSetInner {
+    /// 尝试原子地执行一组操作。只有全部可以完成时才会修改信号量
+    fn try_apply(&mut self, ops: &[SemBuf], pid: usize) -> SemOpResult {
+        let mut vals: Vec<i32> = self.sems.iter().map(|sem| sem.val).collect();
+        for (i, op) in ops.iter().enumerate() {
+            let val = &mut vals[op.sem_num as usize];
+            let new_val = *val + op.sem_op as i32;
+            if (op.sem_op == 0 && *val != 0) || new_val < 0 {
+                return SemOpResult::Blocked(i);
+            }
+            if new_val > SEMVMX {
+                return SemOpResult::Failed(ErrorNo::ERANGE);
+            }
+            *val = new_val;
+        }
+        for op in ops {
+            let sem = &mut self.sems[op.sem_num as usize];
+            sem.val = vals[op.sem_num as usize];
+            sem.pid = pid;
+        }
+        SemOpResult::Done
+    }
+}
+
+/// 所有信号量集
+static SEM_SETS: Mutex<IpcNamespace<SemSet>> = Mutex::new(IpcNamespace::new());
+
+/// 每个进程的 SEM_UNDO 调整表，pid -> ((semid, sem_num) -> 退出时要加上的值)
+static SEM_UNDO_LISTS: Mutex<BTreeMap<usize, BTreeMap<(usize, usize), i32>>> =
+    Mutex::new(BTreeMap::new());
+
+/// 清除所有进程中某个信号量(sem_num 为 None 时是整个集合)的调整值。设置信号量的值或删除集合时需要。
+///
+/// 修改调整表时需要拿着集合的锁，否则其他线程可能在信号量的值和调整表之间插入操作，
+/// 使调整表和信号量的值不一致。所以锁的顺序总是先集合再调整表
+fn clear_undo(semid: usize, sem_num: Option<usize>) {
+    for list in SEM_UNDO_LISTS.lock().values_mut() {
+        list.retain(|&(id, num), _| id != semid || sem_num.map_or(false, |n| n != num));
+    }
+}
+
+/// 获取 key 对应的信号量集，必要时创建有 nsems 个信号量的新集合。返回集合的 id
+pub fn sem_get(key: i32, nsems: usize, flags: i32) -> Result<usize, ErrorNo> {
+    let mut sets = SEM_SETS.lock();
+    let (id, created) = sets.get_or_create(key, flags, || {
+        if nsems == 0 || nsems > SEMMSL {
+            return Err(ErrorNo::EINVAL);
+        }
+        Ok(SemSet {
+            inner: Mutex::new(SemSetInner {
+                perm: IpcPerm::new(key, flags),
+                sems: vec![Semaphore { val: 0, pid: 0, ncnt: 0, zcnt: 0 }; nsems],
+                otime: 0,
+                ctime: get_time_sec(),
+                removed: false,
+            }),
+            waiters: WaitQueue::new(),
+        })
+    })?;
+    // 已有的集合不能比要求的小
+    if !created && nsems > sets.get(id).unwrap().inner.lock().sems.len() {
+        return Err(ErrorNo::EINVAL);
+    }
+    Ok(id)
+}
+
+/// 对 id 对应的信号量集原子地执行一组操作，必要时阻塞。
+///
+/// deadline 是阻塞的截止时间(us)，超过时返回 EAGAIN。为 None 时一直等待
+pub fn sem_op(id: usize, ops: &[SemBuf], pid: usize, deadline: Option<usize>) -> Result<usize, ErrorNo> {
+    let set = SEM_SETS.lock().get(id).ok_or(ErrorNo::EINVAL)?;
+    loop {
+        let wakes = set.waiters.wake_count();
+        let mut inner = set.inner.lock();
+        if inner.removed {
+            return Err(ErrorNo::EIDRM);
+        }
+        if ops.iter().any(|op| op.sem_num as usize >= inner.sems.len()) {
+            return Err(ErrorNo::EFBIG);
+        }
+        let blocked = match inner.try_apply(ops, pid) {
+            SemOpResult::Done => {
+                inner.otime = get_time_sec();
+                let mut undo_lists = SEM_UNDO_LISTS.lock();
+                for op in ops.iter().filter(|op| op.sem_flg & SEM_UNDO != 0) {
+                    let list = undo_lists.entry(pid).or_insert_with(BTreeMap::new);
+                    let adj = list.entry((id, op.sem_num as usize)).or_insert(0);
+                    *adj -= op.sem_op as i32;
+                    if *adj == 0 {
+                        list.remove(&(id, op.sem_num as usize));
+                    }
+                }
+                drop(undo_lists);
+                drop(inner);
+                set.waiters.wake_all();
+                return Ok(0);
+            }
+            SemOpResult::Failed(err) => return Err(err),
+            SemOpResult::Blocked(i) => ops[i],
+        };
+        if blocked.sem_flg as i32 & IPC_NOWAIT != 0 || deadline.map_or(false, |time| get_time_us() >= time) {
+            return Err(ErrorNo::EAGAIN);
+        }
+        // 登记等待的线程数，然后等待其他线程修改信号量
+        let num = blocked.sem_num as usize;
+        let wait_for_zero = blocked.sem_op == 0;
+        if wait_for_zero {
+            inner.sems[num].zcnt += 1;
+        } else {
+            inner.sems[num].ncnt += 1;
+        }
+        drop(inner);
+        let res = set.waiters.wait(wakes, deadline);
+        let mut inner = set.inner.lock();
+        if wait_for_zero {
+            inner.sems[num].zcnt -= 1;
+        } else {
+            inner.sems[num].ncnt -= 1;
+        }
+        drop(inner);
+        res?;
+    }
+}
+
+/// 对 id 对应的信号量集执行 IPC_STAT / IPC_SET / IPC_RMID 操作
+pub fn sem_ctl(id: usize, cmd: i32, ds: &mut SemidDs) -> Result<usize, ErrorNo> {
+    let mut sets = SEM_SETS.lock();
+    let set = sets.get(id).ok_or(ErrorNo::EINVAL)?;
+    let mut inner = set.inner.lock();
+    match ipc_cmd(cmd) {
+        IPC_STAT => {
+            *ds = SemidDs {
+                sem_perm: inner.perm,
+                sem_otime: inner.otime as isize,
+                sem_ctime: inner.ctime as isize,
+                sem_nsems: inner.sems.len(),
+                _unused3: 0,
+                _unused4: 0,
+            };
+        }
+        IPC_SET => {
+            inner.perm.set(&ds.sem_perm);
+            inner.ctime = get_time_sec();
+        }
+        IPC_RMID => {
+            // 唤醒正在等待的线程，它们会发现集合已删除
+            inner.removed = true;
+            clear_undo(id, None);
+            drop(inner);
+            set.waiters.wake_all();
+            sets.remove(id);
+        }
+        _ => return Err(ErrorNo::EINVAL),
+    }
+    Ok(0)
+}
+
+/// 对 id 对应的集合中的第 sem_num 个信号量执行 GETVAL / GETPID / GETNCNT / GETZCNT / SETVAL 操作
+pub fn sem_ctl_value(id: usize, sem_num: usize, cmd: i32, val: i32, pid: usize) -> Result<usize, ErrorNo> {
+    let set = SEM_SETS.lock().get(id).ok_or(ErrorNo::EINVAL)?;
+    let mut inner = set.inner.lock();
+    if sem_num >= inner.sems.len() {
+        return Err(ErrorNo::EINVAL);
+    }
+    let sem = &mut inner.sems[sem_num];
+    match ipc_cmd(cmd) {
+        GETVAL => Ok(sem.val as usize),
+        GETPID => Ok(sem.pid),
+        GETNCNT => Ok(sem.ncnt),
+        GETZCNT => Ok(sem.zcnt),
+        SETVAL => {
+            if val < 0 || val > SEMVMX {
+                return Err(ErrorNo::ERANGE);
+            }
+            sem.val = val;
+            sem.pid = pid;
+            inner.ctime = get_time_sec();
+            clear_undo(id, Some(sem_num));
+            drop(inner);
+            set.waiters.wake_all();
+            Ok(0)
+        }
+        _ => Err(ErrorNo::EINVAL),
+    }
+}
+
+/// 获取 id 对应的集合中所有信号量的值，即 GETALL
+pub fn sem_get_all(id: usize) -> Result<Vec<u16>, ErrorNo> {
+    let set = SEM_SETS.lock().get(id).ok_or(ErrorNo::EINVAL)?;
+    let vals = set.inner.lock().sems.iter().map(|sem| sem.val as u16).collect();
+    Ok(vals)
+}
+
+/// 设置 id 对应的集合中所有信号量的值，即 SETALL。vals 的长度必须和集合大小相同
+pub fn sem_set_all(id: usize, vals: &[u16], pid: usize) -> Result<usize, ErrorNo> {
+    let set = SEM_SETS.lock().get(id).ok_or(ErrorNo::EINVAL)?;
+    let mut inner = set.inner.lock();
+    if vals.len() != inner.sems.len() {
+        return Err(ErrorNo::EINVAL);
+    }
+    if vals.iter().any(|&val| val as i32 > SEMVMX) {
+        return Err(ErrorNo::ERANGE);
+    }
+    for (sem, &val) in inner.sems.iter_mut().zip(vals) {
+        sem.val = val as i32;
+        sem.pid = pid;
+    }
+    inner.ctime = get_time_sec();
+    clear_undo(id, None);
+    drop(inner);
+    set.waiters.wake_all();
+    Ok(0)
+}
+
+/// 获取 id 对应的集合中信号量的个数
+pub fn sem_count(id: usize) -> Result<usize, ErrorNo> {
+    let set = SEM_SETS.lock().get(id).ok_or(ErrorNo::EINVAL)?;
+    let count = set.inner.lock().sems.len();
+    Ok(count)
+}
+
+/// 进程退出时调用，按 SEM_UNDO 调整表撤销进程对信号量的修改
+pub fn exit_sem(pid: usize) {
+    let list = match SEM_UNDO_LISTS.lock().remove(&pid) {
+        Some(list) => list,
+        None => return,
+    };
+    let sets = SEM_SETS.lock();
+    for ((id, num), adj) in list {
+        if let Some(set) = sets.get(id) {
+            let mut inner = set.inner.lock();
+            if let Some(sem) = inner.sems.get_mut(num) {
+                sem.val = (sem.val + adj).clamp(0, SEMVMX);
+                sem.pid = pid;
+            }
+            drop(inner);
+            set.waiters.wake_all();
+        }
+    }
+}
+
+/// 生成 /proc/sysvipc/sem 的内容
+pub fn proc_info() -> String {
+    let mut info = String::from(
+        "       key      semid perms      nsems   uid   gid  cuid  cgid      otime      ctime\n",
+    );
+    for (id, set) in SEM_SETS.lock().iter() {
+        let inner = set.inner.lock();
+        info += &format!(
+            "{:>10} {:>10}  {:>4o} {:>10} {:>5} {:>5} {:>5} {:>5} {:>10} {:>10}\n",
+            inner.perm.key,
+            id,
+            inner.perm.mode,
+            inner.sems.len(),
+            inner.perm.uid,
+            inner.perm.gid,
+            inner.perm.cuid,
+            inner.perm.cgid,
+            inner.otime,
+            inner.ctime,
+        );
+    }
+    info
+}
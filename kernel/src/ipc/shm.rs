@@ -0,0 +1,229 @@
+//! 共享内存段
+//!
+//! 每个段的页帧保存在一组 SharedFrames 中，shmat 时以 PmAreaShared 的形式映射进地址空间。
+//! 段被映射的次数(shm_nattch)单独计数：shmat 时加一，shmdt 时减一，
+//! fork 时子进程复制的每个映射各加一，exit 和 exec 清空地址空间时各减一(见 MemorySet)。
+//! mprotect 把一个映射分成几段时仍然只算一次。
+//!
+//! 被 IPC_RMID 标记删除的段如果仍有映射，会在最后一次 shmdt 或者之后的任意 shm 操作时真正删除
+
+use super::{ipc_cmd, IpcNamespace, IpcPerm, IPC_RMID, IPC_SET, IPC_STAT};
+use crate::{
+    constants::PAGE_SIZE,
+    memory::{new_shared_frames, page_count, SharedFrames},
+    syscall::ErrorNo,
+    timer::get_time_sec,
+};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use lock::Mutex;
+
+/// shmat 映射的地址段的名字，shmdt 时用它确认地址段是共享内存段
+pub const SHM_NAME: &str = "sysv_shm";
+
+/// 单个共享内存段的大小上限
+const SHM_SIZE_LIMIT: usize = 0x4000_0000; // 1 GB
+
+// shmctl 中 Linux 特有的命令
+/// 锁定段的页帧不被换出。目前没有换出机制，所以什么也不用做
+const SHM_LOCK: i32 = 11;
+/// 取消锁定
+const SHM_UNLOCK: i32 = 12;
+
+/// shmctl 使用的结构，即 struct shmid64_ds
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ShmidDs {
+    pub shm_perm: IpcPerm,
+    pub shm_segsz: usize,
+    pub shm_atime: isize,
+    pub shm_dtime: isize,
+    pub shm_ctime: isize,
+    pub shm_cpid: i32,
+    pub shm_lpid: i32,
+    pub shm_nattch: usize,
+    _unused4: usize,
+    _unused5: usize,
+}
+
+/// 一个共享内存段
+struct ShmSegment {
+    /// 段的页帧
+    frames: SharedFrames,
+    /// 用户要求的大小(不一定按页对齐)
+    size: usize,
+    inner: Mutex<ShmSegmentInner>,
+}
+
+struct ShmSegmentInner {
+    perm: IpcPerm,
+    /// 最后一次 shmat 的时间
+    atime: usize,
+    /// 最后一次 shmdt 的时间
+    dtime: usize,
+    /// 创建或最后一次 IPC_SET 的时间
+    ctime: usize,
+    /// 创建者的 pid
+    cpid: usize,
+    /// 最后一次 shmat / shmdt 的 pid
+    lpid: usize,
+    /// 段当前被映射的次数
+    nattch: usize,
+    /// 是否已被 IPC_RMID 标记删除
+    removed: bool,
+}
+
+/// 所有共享内存段
+static SHM_SEGMENTS: Mutex<IpcNamespace<ShmSegment>> = Mutex::new(IpcNamespace::new());
+
+/// 真正删除那些已标记删除且不再被映射的段
+fn remove_unused_segments(segments: &mut IpcNamespace<ShmSegment>) {
+    let unused: Vec<usize> = segments
+        .iter()
+        .filter(|(_, seg)| {
+            let inner = seg.inner.lock();
+            inner.removed && inner.nattch == 0
+        })
+        .map(|(&id, _)| id)
+        .collect();
+    for id in unused {
+        segments.remove(id);
+    }
+}
+
+/// 获取 key 对应的共享内存段，必要时创建大小为 size 的新段。返回段的 id
+pub fn shm_get(key: i32, size: usize, flags: i32, pid: usize) -> Result<usize, ErrorNo> {
+    let mut segments = SHM_SEGMENTS.lock();
+    remove_unused_segments(&mut segments);
+    let (id, created) = segments.get_or_create(key, flags, || {
+        if size == 0 || size > SHM_SIZE_LIMIT {
+            return Err(ErrorNo::EINVAL);
+        }
+        Ok(ShmSegment {
+            frames: new_shared_frames(page_count(size)),
+            size,
+            inner: Mutex::new(ShmSegmentInner {
+                perm: IpcPerm::new(key, flags),
+                atime: 0,
+                dtime: 0,
+                ctime: get_time_sec(),
+                cpid: pid,
+                lpid: 0,
+                nattch: 0,
+                removed: false,
+            }),
+        })
+    })?;
+    // 已有的段不能比要求的小
+    if !created && size > segments.get(id).unwrap().size {
+        return Err(ErrorNo::EINVAL);
+    }
+    Ok(id)
+}
+
+/// 准备映射 id 对应的段，返回段的页帧和页数，段的映射次数加一。
+/// 调用者负责把它们映射到地址空间中，映射失败时需要调用 shm_detach
+pub fn shm_attach(id: usize, pid: usize) -> Result<(SharedFrames, usize), ErrorNo> {
+    let segment = SHM_SEGMENTS.lock().get(id).ok_or(ErrorNo::EINVAL)?;
+    let mut inner = segment.inner.lock();
+    inner.atime = get_time_sec();
+    inner.lpid = pid;
+    inner.nattch += 1;
+    Ok((segment.frames.clone(), page_count(segment.size)))
+}
+
+/// 在地址空间中删除了 frames 对应的映射之后调用，段的映射次数减一。
+/// pid 为 None 时表示地址空间被整个清空(exit 或 exec)，不更新 shm_lpid
+pub fn shm_detach(frames: SharedFrames, pid: Option<usize>) {
+    let mut segments = SHM_SEGMENTS.lock();
+    if let Some((_, segment)) = segments.iter().find(|(_, seg)| Arc::ptr_eq(&seg.frames, &frames)) {
+        let mut inner = segment.inner.lock();
+        inner.dtime = get_time_sec();
+        inner.nattch = inner.nattch.saturating_sub(1);
+        if let Some(pid) = pid {
+            inner.lpid = pid;
+        }
+    }
+    remove_unused_segments(&mut segments);
+}
+
+/// fork 时子进程复制了 frames 对应的映射，段的映射次数加一
+pub fn shm_fork(frames: &SharedFrames) {
+    let segments = SHM_SEGMENTS.lock();
+    if let Some((_, segment)) = segments.iter().find(|(_, seg)| Arc::ptr_eq(&seg.frames, frames)) {
+        segment.inner.lock().nattch += 1;
+    }
+}
+
+/// 对 id 对应的段执行 shmctl 操作。IPC_STAT 时结果写入 ds，IPC_SET 时从 ds 中读取新的权限
+pub fn shm_ctl(id: usize, cmd: i32, ds: &mut ShmidDs) -> Result<usize, ErrorNo> {
+    let mut segments = SHM_SEGMENTS.lock();
+    let segment = segments.get(id).ok_or(ErrorNo::EINVAL)?;
+    match ipc_cmd(cmd) {
+        IPC_STAT => {
+            let inner = segment.inner.lock();
+            *ds = ShmidDs {
+                shm_perm: inner.perm,
+                shm_segsz: segment.size,
+                shm_atime: inner.atime as isize,
+                shm_dtime: inner.dtime as isize,
+                shm_ctime: inner.ctime as isize,
+                shm_cpid: inner.cpid as i32,
+                shm_lpid: inner.lpid as i32,
+                shm_nattch: inner.nattch,
+                _unused4: 0,
+                _unused5: 0,
+            };
+            // 已标记删除的段，key 显示为 IPC_PRIVATE
+            if inner.removed {
+                ds.shm_perm.key = 0;
+            }
+        }
+        IPC_SET => {
+            let mut inner = segment.inner.lock();
+            inner.perm.set(&ds.shm_perm);
+            inner.ctime = get_time_sec();
+        }
+        IPC_RMID => {
+            segment.inner.lock().removed = true;
+            // 标记删除后就不能再通过 key 找到这个段了
+            segments.unlink_key(id);
+            drop(segment);
+            remove_unused_segments(&mut segments);
+        }
+        SHM_LOCK | SHM_UNLOCK => {}
+        _ => return Err(ErrorNo::EINVAL),
+    }
+    Ok(0)
+}
+
+/// 生成 /proc/sysvipc/shm 的内容
+pub fn proc_info() -> String {
+    let mut info = String::from(
+        "       key      shmid perms                  size  cpid  lpid nattch   uid   gid  cuid  cgid      atime      dtime      ctime                   rss                  swap\n",
+    );
+    for (id, segment) in SHM_SEGMENTS.lock().iter() {
+        let inner = segment.inner.lock();
+        let key = if inner.removed { 0 } else { inner.perm.key };
+        let rss = segment.frames.lock().iter().filter(|f| f.is_some()).count() * PAGE_SIZE;
+        info += &format!(
+            "{:>10} {:>10}  {:>4o} {:>21} {:>5} {:>5}  {:>5} {:>5} {:>5} {:>5} {:>5} {:>10} {:>10} {:>10} {:>21} {:>21}\n",
+            key,
+            id,
+            inner.perm.mode,
+            segment.size,
+            inner.cpid,
+            inner.lpid,
+            inner.nattch,
+            inner.perm.uid,
+            inner.perm.gid,
+            inner.perm.cuid,
+            inner.perm.cgid,
+            inner.atime,
+            inner.dtime,
+            inner.ctime,
+            rss,
+            0,
+        );
+    }
+    info
+}
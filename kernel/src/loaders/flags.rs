@@ -5,9 +5,34 @@ pub const AT_PHDR: u8 = 3;
 pub const AT_PHENT: u8 = 4;
 pub const AT_PHNUM: u8 = 5;
 pub const AT_PAGESZ: u8 = 6;
-//pub const AT_BASE: u8 = 7;
-//pub const AT_ENTRY: u8 = 9;
+pub const AT_BASE: u8 = 7;
+pub const AT_FLAGS: u8 = 8;
+pub const AT_ENTRY: u8 = 9;
+pub const AT_UID: u8 = 11;
+pub const AT_EUID: u8 = 12;
+pub const AT_GID: u8 = 13;
+pub const AT_EGID: u8 = 14;
+pub const AT_HWCAP: u8 = 16;
+pub const AT_CLKTCK: u8 = 17;
+pub const AT_SECURE: u8 = 23;
 pub const AT_RANDOM: u8 = 25;
+pub const AT_EXECFN: u8 = 31;
+pub const AT_MINSIGSTKSZ: u8 = 51;
+
+/// AT_HWCAP 中的 ISA 扩展位，每个字母扩展占 (字母 - 'a') 位。这里对应 rv64imafdc
+pub const HWCAP_ISA: usize = (1 << (b'i' - b'a'))
+    | (1 << (b'm' - b'a'))
+    | (1 << (b'a' - b'a'))
+    | (1 << (b'f' - b'a'))
+    | (1 << (b'd' - b'a'))
+    | (1 << (b'c' - b'a'));
+/// AT_CLKTCK 的值，即 times() 使用的每秒时钟数
+pub const CLOCK_TICKS_PER_SEC: usize = 100;
+/// 信号处理栈至少需要的大小
+pub const MINSIGSTKSZ: usize = 2048;
+
+/// PT_GNU_STACK 段的类型，它的权限表示用户栈是否可执行
+pub const PT_GNU_STACK: u32 = 0x6474_e551;
 
 pub const REL_GOT: u32 = 6;
 pub const REL_PLT: u32 = 7;
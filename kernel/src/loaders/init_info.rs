@@ -6,10 +6,12 @@
 use alloc::collections::btree_map::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
+use core::ops::Range;
 use core::ptr::null;
 
 use super::flags::*;
 use super::InitStack;
+use crate::random::fill_random;
 
 /// 初始化信息
 #[derive(Debug)]
@@ -25,32 +27,44 @@ pub struct InitInfo {
 impl InitInfo {
     /// 将初始信息序列化到栈上
     /// 由栈底(高地址)向栈顶(低地址)依次推入
-    pub fn serialize(&self, stack_top: usize) -> InitStack {
+    ///
+    /// 同时返回参数和环境变量字符串在栈上的区间
+    pub fn serialize(&self, stack_top: usize) -> (InitStack, Range<usize>, Range<usize>) {
         let mut writer = InitStack::new(stack_top);
-        // 程序名
-        writer.push_str(&self.args[0]);
-        // "随机"串。想要真正做到随机需要硬件，但目前实现暂不影响程序运行
-        let random_str = &[3703830112808742751usize, 7081108068768079778usize];
-        writer.push_slice(random_str.as_slice());
+        // 程序名，AT_EXECFN 指向它
+        let execfn_pos = writer.push_str(&self.args[0]);
+        // AT_RANDOM 指向的 16 字节随机串，libc 用它生成 stack canary 等
+        let mut random_bytes = [0u8; 16];
+        fill_random(&mut random_bytes);
+        writer.push_slice(random_bytes.as_slice());
         let random_pos = writer.sp;
-        // 环境变量
-        let envs: Vec<_> = self
+        // 环境变量。倒序推入，使它们在内存中按顺序相连，/proc/<pid>/environ 可以直接读出整段
+        let env_end = writer.sp;
+        let mut envs: Vec<_> = self
             .envs
             .iter()
+            .rev()
             .map(|item| writer.push_str(item.as_str()))
             .collect();
-        // 执行参数
-        let argv: Vec<_> = self
+        envs.reverse();
+        let env_range = writer.sp..env_end;
+        // 执行参数，同样倒序推入
+        let arg_end = writer.sp;
+        let mut argv: Vec<_> = self
             .args
             .iter()
+            .rev()
             .map(|item| writer.push_str(item.as_str()))
             .collect();
+        argv.reverse();
+        let arg_range = writer.sp..arg_end;
         // 辅助参数
         writer.push_slice(&[null::<u8>(), null::<u8>()]);
         for (&type_, &value) in self.auxv.iter() {
             //info!("auxv {} {:x}", type_ ,value);
             match type_ {
                 AT_RANDOM => writer.push_slice(&[type_ as usize, random_pos]),
+                AT_EXECFN => writer.push_slice(&[type_ as usize, execfn_pos]),
                 _ => writer.push_slice(&[type_ as usize, value]),
             };
         }
@@ -62,6 +76,6 @@ impl InitInfo {
         writer.push_slice(argv.as_slice());
         // 参数个数
         writer.push_slice(&[argv.len()]);
-        writer
+        (writer, arg_range, env_range)
     }
 }
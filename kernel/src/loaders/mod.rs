@@ -1,5 +1,6 @@
 mod flags;
 use flags::*;
+pub use flags::CLOCK_TICKS_PER_SEC;
 mod init_info;
 use init_info::InitInfo;
 mod init_stack;
@@ -21,9 +22,13 @@ use crate::constants::{
     //LIBC_SO_FILE,
     //LIBC_SO_DIR,
     ELF_BASE_RELOCATE,
+    ELF_BASE_RANDOM_PAGES,
     PAGE_SIZE,
     ROOT_DIR,
+    USER_MMAP_BASE,
+    USER_MMAP_RANDOM_PAGES,
     USER_STACK_OFFSET,
+    USER_STACK_RANDOM_PAGES,
     USER_STACK_SIZE,
 };
 use crate::error::{OSError, OSResult};
@@ -31,6 +36,7 @@ use crate::file::{open_file, OpenFlags};
 use crate::memory::addr::{page_count, page_offset, VirtAddr};
 use crate::memory::{MemorySet, PTEFlags};
 use crate::memory::{PmArea, PmAreaLazy, VmArea};
+use crate::random::random_page_offset;
 use crate::utils::raw_ptr_to_ref_str;
 
 pub struct ElfLoader<'a> {
@@ -114,18 +120,35 @@ impl<'a> ElfLoader<'a> {
             info!("phdr = {:x}", phdr);
             // 如果是 0，如 libc.so，则需要放到一个非零的合法地址。此处规定从某个特定位置开始往后找。
             // 这样设置是因为，动态库运行时可能会mmap实际的用户程序且指定 MAP_FIXED，
-            // 而用户程序的地址一般较低。为了让它们直接尽可能不冲突，所以会放到稍高的地址
+            // 而用户程序的地址一般较低。为了让它们直接尽可能不冲突，所以会放到稍高的地址。
+            // 在此基础上再加一段随机偏移，即 PIE 程序和解释器的地址随机化
             if phdr != 0 {
                 phdr
             } else {
-                dyn_base = ELF_BASE_RELOCATE;
-                ELF_BASE_RELOCATE
+                dyn_base = ELF_BASE_RELOCATE + random_page_offset(ELF_BASE_RANDOM_PAGES);
+                dyn_base
             }
         } else {
             //return Err(OSError::Loader_PhdrNotFound);
             // 自行构造的测例(rcore/初赛)可能会出现这种情况，而且也没有 phdr 段，此时认为 base addr = 0
             0
         };
+        // 用户栈默认不可执行，除非 PT_GNU_STACK 段要求可执行
+        let mut stack_flags = PTEFlags::READ | PTEFlags::WRITE | PTEFlags::USER;
+        for ph in self.elf.program_iter() {
+            match ph.get_type() {
+                Ok(Type::OsSpecific(PT_GNU_STACK)) => {
+                    if ph.flags().is_execute() {
+                        stack_flags |= PTEFlags::EXECUTE;
+                    }
+                }
+                // TLS 的初始数据由 libc 从 phdr 中找到并复制，内核只需保证它确实在某个被加载的段里
+                Ok(Type::Tls) => self.check_tls_segment(ph.virtual_addr(), ph.file_size(), ph.align())?,
+                _ => {}
+            }
+        }
+        // 所有 ELF 段的最高地址，用户堆从这里之后开始
+        let mut elf_end = 0;
         for ph in self.elf.program_iter() {
             if ph.get_type() != Ok(Type::Load) {
                 continue;
@@ -152,7 +175,9 @@ impl<'a> ElfLoader<'a> {
             )?;
             //info!("{:#?}", seg);
             vm.push(seg)?;
+            elf_end = elf_end.max((ph.virtual_addr() + ph.mem_size()) as VirtAddr + dyn_base);
         }
+        vm.set_heap_start(elf_end);
         // 如果需要重定位，即这是动态执行程序
         if let Some(rela_header) = self.elf.find_section_by_name(".rela.dyn") {
             let data = match rela_header.get_data(&self.elf).unwrap() {
@@ -245,8 +270,12 @@ impl<'a> ElfLoader<'a> {
             }
         }
         let user_entry = self.elf.header.pt2.entry_point() as usize;
+        // 随机化 mmap 的起始位置
+        vm.set_mmap_base(USER_MMAP_BASE + random_page_offset(USER_MMAP_RANDOM_PAGES));
         let stack_bottom = USER_STACK_OFFSET;
-        let mut stack_top = stack_bottom + USER_STACK_SIZE;
+        let stack_end = stack_bottom + USER_STACK_SIZE;
+        // 随机化栈顶位置
+        let mut stack_top = stack_end - random_page_offset(USER_STACK_RANDOM_PAGES);
         let mut stack_pma = PmAreaLazy::new(page_count(USER_STACK_SIZE), None)?;
 
         let info = InitInfo {
@@ -280,31 +309,63 @@ impl<'a> ElfLoader<'a> {
                 );
                 map.insert(AT_PHENT, self.elf.header.pt2.ph_entry_size() as usize);
                 map.insert(AT_PHNUM, self.elf.header.pt2.ph_count() as usize);
-                // AT_RANDOM 比较特殊，要求指向栈上的 16Byte 的随机子串。因此这里的 0 只是占位，在之后序列化时会特殊处理
-                map.insert(AT_RANDOM, 0);
                 map.insert(AT_PAGESZ, PAGE_SIZE);
+                // 解释器是作为用户程序本身直接加载的，所以这里没有额外的解释器基址
+                map.insert(AT_BASE, 0);
+                map.insert(AT_FLAGS, 0);
+                map.insert(AT_ENTRY, user_entry + dyn_base);
+                map.insert(AT_UID, 0);
+                map.insert(AT_EUID, 0);
+                map.insert(AT_GID, 0);
+                map.insert(AT_EGID, 0);
+                map.insert(AT_HWCAP, HWCAP_ISA);
+                map.insert(AT_CLKTCK, CLOCK_TICKS_PER_SEC);
+                map.insert(AT_SECURE, 0);
+                // AT_RANDOM 和 AT_EXECFN 比较特殊，要求指向栈上的数据。因此这里的 0 只是占位，在之后序列化时会特殊处理
+                map.insert(AT_RANDOM, 0);
+                map.insert(AT_EXECFN, 0);
+                map.insert(AT_MINSIGSTKSZ, MINSIGSTKSZ);
                 map
             },
         };
         
         info!("info {:#?}", info);
-        let init_stack = info.serialize(stack_top);
+        let (init_stack, arg_range, env_range) = info.serialize(stack_top);
+        vm.set_arg_env_range(arg_range, env_range);
         debug!("init user proc: stack len {}", init_stack.len());
-        stack_pma.write(USER_STACK_SIZE - init_stack.len(), &init_stack)?;
         stack_top -= init_stack.len();
+        stack_pma.write(stack_top - stack_bottom, &init_stack)?;
         
         // push user stack to `vm`
         let stack_vma = VmArea::new(
             stack_bottom,
-            stack_top,
-            PTEFlags::READ | PTEFlags::WRITE | PTEFlags::USER,
+            stack_end,
+            stack_flags,
             Arc::new(Mutex::new(stack_pma)),
             "user_stack",
-        )?;
+        )?
+        .with_grows_down();
         vm.push(stack_vma)?;
         // println!("{:#x?}", vm);
         Ok((user_entry + dyn_base, stack_top))
     }
+
+    /// 检查 PT_TLS 段是否合法：对齐必须是 2 的幂，且初始数据必须落在某个 PT_LOAD 段的文件数据中
+    fn check_tls_segment(&self, vaddr: u64, file_size: u64, align: u64) -> OSResult {
+        if align > 1 && !align.is_power_of_two() {
+            return Err(OSError::Loader_InvalidSegment);
+        }
+        let in_load_segment = self.elf.program_iter().any(|ph| {
+            ph.get_type() == Ok(Type::Load)
+                && ph.virtual_addr() <= vaddr
+                && vaddr + file_size <= ph.virtual_addr() + ph.file_size()
+        });
+        if in_load_segment {
+            Ok(())
+        } else {
+            Err(OSError::Loader_InvalidSegment)
+        }
+    }
 }
 
 impl From<Flags> for PTEFlags {
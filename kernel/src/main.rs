@@ -23,11 +23,14 @@ pub mod constants;
 pub mod drivers;
 pub mod error;
 pub mod file;
+pub mod ipc;
 pub mod lang;
 pub mod loaders;
 pub mod memory;
+pub mod random;
 pub mod signal;
 pub mod syscall;
+pub mod sysctl;
 pub mod task;
 pub mod testcases;
 pub mod timer;
@@ -51,7 +54,6 @@ mod fsio {
     pub use fscommon::{Read, Seek, Write};
 }
 
-core::arch::global_asm!(include_str!("fs.S"));
 
 // use core::sync::atomic::AtomicUsize;
 // static BOOTED_CPU_NUM: AtomicUsize = AtomicUsize::new(0);
@@ -59,20 +61,29 @@ use log::*;
 
 #[no_mangle]
 /// 主核启动OS
-pub extern "C" fn start_kernel(_arg0: usize, _arg1: usize) -> ! {
+pub extern "C" fn start_kernel(_arg0: usize, dtb: usize) -> ! {
     arch::clear_bss(); // 清空 bss 段
     console::init_logger(crate::constants::LOG_LEVEL).unwrap();
-    memory::allocator_init(); // 初始化堆分配器和页帧分配器
+    memory::allocator_init(dtb); // 初始化堆分配器和页帧分配器，其中不包括 initrd 所在的页
+    file::load_initramfs(dtb); // 如有 initramfs，解压到 tmpfs 中作为根文件系统。需要在切换页表前读取设备树和 initrd
+    drivers::save_fdt_info(dtb); // 保存之后还要用到的设备树信息，如 /proc/cpuinfo 中的核
+    sysctl::preset_sysctls(&drivers::boot_args()); // 读取 bootargs 中的 sysctl.* 参数，在参数注册时生效
+    sysctl::register_sysctl("kernel/log_level", &console::LogLevelSysctl); // 从这里开始日志等级使用 bootargs 中的值
+    sysctl::register_sysctl("vm/report_page_fault", &memory::REPORT_PAGE_FAULT);
     memory::enable_kernel_page_table(); // 构造并切换到内核态页表与 MemorySet
     trap::init(); // 设置异常/中断的入口，即 stvec
+    trap::enable_external_interrupt(); // 开启外部中断，块设备通过中断通知请求完成
     arch::allow_sum_access(); // 内核可以读写 USER 页表项中的数据
 
     //trap::enable_timer_interrupt(); // 开启时钟中断
     //timer::set_next_trigger(); // 设置时钟中断频率
 
     // file::list_apps_names_at_root_dir(); // 展示所有用户程序的名字
+    file::mount_root_overlay(); // 在根文件系统上叠加 tmpfs，使评测镜像不被修改
     file::list_files_at_root(); // 展示所有用户程序的名字
     file::fs_init(); // 初始化一些不是实际文件本身但是 OS 约定需要的"文件"
+    ipc::init(); // 在 /proc/sysvipc 下注册 IPC 对象的信息文件
+    file::socket::init(); // 在 /proc/net 下注册抓包文件
     let cpu_id = arch::get_cpu_id();
     info!("CPU [{cpu_id}] bootstrap");
     for other_cpu in constants::FIRST_CPU_ID..=constants::LAST_CPU_ID {
@@ -99,6 +110,7 @@ pub extern "C" fn start_kernel(_arg0: usize, _arg1: usize) -> ! {
 pub extern "C" fn start_kernel_secondary(_arg0: usize, _arg1: usize) -> ! {
     memory::enable_kernel_page_table(); // 构造并切换到内核态页表与 MemorySet
     trap::init(); // 设置异常/中断的入口，即 stvec
+    trap::enable_external_interrupt(); // 开启外部中断，设备的中断可能发给任何一个核
     arch::allow_sum_access(); // 修改 sstatus 的 SUM 位，使内核可以读写USER页表项中的数据
 
     //trap::enable_timer_interrupt(); // 开启时钟中断
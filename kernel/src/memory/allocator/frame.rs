@@ -9,6 +9,8 @@ type FrameAllocatorImpl = bitmap_allocator::BitAlloc16M;
 use bitmap_allocator::BitAlloc;
 
 use core::mem::ManuallyDrop;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use lock::Mutex;
 
@@ -17,6 +19,10 @@ use super::{PhysAddr, PAGE_SIZE, PHYS_MEMORY_OFFSET};
 
 /// 分配器全局只有一个，用互斥锁保护
 static FRAME_ALLOCATOR: Mutex<FrameAllocatorImpl> = Mutex::new(FrameAllocatorImpl::DEFAULT);
+/// 分配器管理的页帧总数
+static TOTAL_FRAMES: AtomicUsize = AtomicUsize::new(0);
+/// 已分配的页帧数
+static USED_FRAMES: AtomicUsize = AtomicUsize::new(0);
 
 /// 物理地址转页帧编号
 fn phys_addr_to_frame_idx(addr: PhysAddr) -> usize {
@@ -34,6 +40,9 @@ fn frame_idx_to_phys_addr(idx: usize) -> PhysAddr {
 unsafe fn alloc_frame() -> Option<PhysAddr> {
     let ret = FRAME_ALLOCATOR.lock().alloc().map(frame_idx_to_phys_addr);
     //println!("Allocate frame: {:x?}", ret);
+    if ret.is_some() {
+        USED_FRAMES.fetch_add(1, Ordering::Relaxed);
+    }
     ret
 }
 
@@ -46,6 +55,9 @@ unsafe fn alloc_frame_contiguous(frame_count: usize, align_log2: usize) -> Optio
         .lock()
         .alloc_contiguous(frame_count, align_log2)
         .map(frame_idx_to_phys_addr);
+    if ret.is_some() {
+        USED_FRAMES.fetch_add(frame_count, Ordering::Relaxed);
+    }
     /*
     println!(
         "Allocate {} frames with alignment {}: {:x?}",
@@ -64,7 +76,8 @@ unsafe fn dealloc_frame(target: PhysAddr) {
     //println!("Deallocate frame: {:x}", target);
     FRAME_ALLOCATOR
         .lock()
-        .dealloc(phys_addr_to_frame_idx(target))
+        .dealloc(phys_addr_to_frame_idx(target));
+    USED_FRAMES.fetch_sub(1, Ordering::Relaxed);
 }
 
 /// 回收一段连续的页帧
@@ -77,23 +90,35 @@ unsafe fn dealloc_frame_contiguous(target: PhysAddr, frame_count: usize) {
     for i in start_idx..start_idx + frame_count {
         ba.dealloc(i)
     }
+    USED_FRAMES.fetch_sub(frame_count, Ordering::Relaxed);
 }
 
 /// 初始化页帧分配器。
 ///
 /// 必须在启动时只由一个核调用，通常是启动核
 pub fn init() {
-    let mut ba = FRAME_ALLOCATOR.lock();
     let regions = super::get_phys_memory_regions();
     for region in regions {
-        let frame_start = phys_addr_to_frame_idx(region.start);
-        let frame_end = phys_addr_to_frame_idx(region.end - 1) + 1;
-        assert!(frame_start < frame_end, "illegal range for frame allocator");
-        ba.insert(frame_start..frame_end);
+        add_region(region);
     }
     //println!("frame allocator init end.");
 }
 
+/// 把物理地址区间 region 中的页帧交给分配器管理
+pub fn add_region(region: Range<PhysAddr>) {
+    let frame_start = phys_addr_to_frame_idx(region.start);
+    let frame_end = phys_addr_to_frame_idx(region.end - 1) + 1;
+    assert!(frame_start < frame_end, "illegal range for frame allocator");
+    FRAME_ALLOCATOR.lock().insert(frame_start..frame_end);
+    TOTAL_FRAMES.fetch_add(frame_end - frame_start, Ordering::Relaxed);
+}
+
+/// 页帧的总数和空闲的页帧数
+pub fn frame_stat() -> (usize, usize) {
+    let total = TOTAL_FRAMES.load(Ordering::Relaxed);
+    (total, total.saturating_sub(USED_FRAMES.load(Ordering::Relaxed)))
+}
+
 /// 页帧定义，自动用 new 和 Drop 包装了页帧的分配和回收过程
 #[derive(Debug)]
 pub struct Frame {
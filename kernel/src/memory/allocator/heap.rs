@@ -22,3 +22,8 @@ pub fn init() {
             .init(HEAP.as_ptr() as usize, HEAP_BLOCK * MACHINE_ALIGN);
     };
 }
+
+/// 堆中已分配的字节数，包括伙伴系统对齐到 2 的幂时多占的空间
+pub fn heap_used() -> usize {
+    HEAP_ALLOCATOR.lock().stats_alloc_actual()
+}
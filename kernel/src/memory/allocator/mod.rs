@@ -10,19 +10,46 @@ mod frame;
 mod heap;
 mod tid;
 
-use super::{get_phys_memory_regions, phys_to_virt};
+use super::{get_phys_memory_regions, phys_to_virt, reserve_initrd};
+use crate::drivers::Fdt;
 use super::{PhysAddr, PAGE_SIZE, PHYS_MEMORY_OFFSET};
 
 pub use fd::FdAllocator;
-pub use frame::Frame;
+pub use frame::{add_region, Frame};
 pub use tid::Tid;
 
-/// 初始化堆分配器、页帧分配器和 TID 分配器。需由其中一个核调用且仅调用一次
-pub fn allocator_init() {
+/// 物理内存和内核堆的使用情况，单位为字节。/proc/meminfo 和 sys_sysinfo 都从这里读取
+pub struct MemStat {
+    /// 页帧分配器管理的物理内存
+    pub total: usize,
+    /// 其中空闲的部分
+    pub free: usize,
+    /// 内核堆中已分配的部分
+    pub heap_used: usize,
+}
+
+/// 获取当前的内存使用情况
+pub fn mem_stat() -> MemStat {
+    let (total_frames, free_frames) = frame::frame_stat();
+    MemStat {
+        total: total_frames * PAGE_SIZE,
+        free: free_frames * PAGE_SIZE,
+        heap_used: heap::heap_used(),
+    }
+}
+
+/// 初始化堆分配器、页帧分配器和 TID 分配器。需由其中一个核调用且仅调用一次。
+///
+/// dtb 是启动时 SBI 传入的设备树的物理地址。其中记录的 initrd 所在的页要等解压完之后才能分配出去
+pub fn allocator_init(dtb: usize) {
     // println 中调用的 STDOUT 有 Mutex 锁，需要在堆上分配
     // 所以在 heap::init() 前请不要输出任何语句
     heap::init();
     info!("heap allocator inited.");
+    // 读设备树需要用到堆，所以放在堆分配器之后、页帧分配器之前
+    if let Some(range) = Fdt::from_paddr(dtb).and_then(|fdt| fdt.initrd_range()) {
+        reserve_initrd(range);
+    }
     frame::init();
     info!("frame allocator inited.");
     tid::init();
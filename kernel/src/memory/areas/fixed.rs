@@ -7,7 +7,7 @@ use core::slice;
 
 use lock::Mutex;
 
-use super::{PmArea, VmArea};
+use super::{PmArea, SharedFrames, VmArea};
 use crate::error::{OSError, OSResult};
 use crate::memory::{
     addr::{align_down, align_up},
@@ -104,6 +104,19 @@ impl PmArea for PmAreaFixed {
             Err(OSError::PmArea_SplitFailed)
         }
     }
+
+    fn grow_left(&mut self, _len: usize) -> OSResult {
+        // 直接映射的物理地址段不能扩展
+        Err(OSError::PmArea_GrowFailed)
+    }
+
+    fn grow_right(&mut self, _len: usize) -> OSResult {
+        Err(OSError::PmArea_GrowFailed)
+    }
+
+    fn shared_frames(&self) -> Option<SharedFrames> {
+        None
+    }
 }
 
 impl PmAreaFixed {
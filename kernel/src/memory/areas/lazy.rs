@@ -7,7 +7,7 @@ use core::fmt::{Debug, Formatter, Result};
 
 use lock::Mutex;
 
-use super::{PmArea, VmArea};
+use super::{PmArea, SharedFrames, VmArea};
 use crate::error::{OSError, OSResult};
 use crate::file::{File, BackEndFile};
 use crate::memory::{
@@ -128,6 +128,36 @@ impl PmArea for PmAreaLazy {
             Err(OSError::PmArea_SplitFailed)
         }
     }
+
+    fn grow_left(&mut self, len: usize) -> OSResult {
+        // 有后端文件时，向左扩展会让文件偏移变成负数
+        if self.backend.is_some() || len % PAGE_SIZE != 0 {
+            return Err(OSError::PmArea_GrowFailed);
+        }
+        let new_pages = len / PAGE_SIZE;
+        if self.frames.len() + new_pages > addr::page_count(USER_VIRT_ADDR_LIMIT) {
+            return Err(OSError::PmArea_GrowFailed);
+        }
+        self.frames.splice(0..0, (0..new_pages).map(|_| None));
+        Ok(())
+    }
+
+    fn grow_right(&mut self, len: usize) -> OSResult {
+        // 有后端文件时，扩展的部分不在文件映射的范围内
+        if self.backend.is_some() || len % PAGE_SIZE != 0 {
+            return Err(OSError::PmArea_GrowFailed);
+        }
+        let new_pages = len / PAGE_SIZE;
+        if self.frames.len() + new_pages > addr::page_count(USER_VIRT_ADDR_LIMIT) {
+            return Err(OSError::PmArea_GrowFailed);
+        }
+        self.frames.resize_with(self.frames.len() + new_pages, || None);
+        Ok(())
+    }
+
+    fn shared_frames(&self) -> Option<SharedFrames> {
+        None
+    }
 }
 
 impl PmAreaLazy {
@@ -5,6 +5,7 @@
 mod set;
 mod fixed;
 mod lazy;
+mod shared;
 
 use super::{
     addr::{align_down, align_up, PhysAddr, VirtAddr},
@@ -21,6 +22,7 @@ use lock::Mutex;
 pub use set::{DiffSet, CutSet};
 pub use fixed::PmAreaFixed;
 pub use lazy::PmAreaLazy;
+pub use shared::{new_shared_frames, PmAreaShared, SharedFrames};
 
 /// 一段访问权限相同的物理地址。注意物理地址本身不一定连续，只是拥有对应长度的空间
 ///
@@ -49,6 +51,13 @@ pub trait PmArea: core::fmt::Debug + Send + Sync {
     /// 分成三段区间(输入参数都是相对于地址段开头的偏移)
     /// 自己保留[start, left_end), 删除 [left_end, right_start)，返回 [right_start, end)
     fn split(&mut self, left_end: usize, right_start: usize) -> OSResult<Arc<Mutex<dyn PmArea>>>;
+    /// 向左侧(低地址)扩展 len 长度，新扩展的部分暂不分配页帧。一般是向下增长的栈需要
+    fn grow_left(&mut self, len: usize) -> OSResult;
+    /// 向右侧(高地址)扩展 len 长度，新扩展的部分暂不分配页帧。一般是用户堆需要
+    fn grow_right(&mut self, len: usize) -> OSResult;
+    /// 如果是在多个地址空间之间共享的地址段，返回共享的页帧数组。
+    /// 共享的地址段在 fork 时不复制数据，而是映射同一组页帧
+    fn shared_frames(&self) -> Option<SharedFrames>;
 }
 
 /// 一段访问权限相同的虚拟地址
@@ -62,7 +71,9 @@ pub struct VmArea {
     pub(super) flags: PTEFlags,
     /// 对应的物理地址段
     pub(super) pma: Arc<Mutex<dyn PmArea>>,
-    name: &'static str,
+    pub(super) name: &'static str,
+    /// 是否会在 page fault 时向下增长，即 MAP_GROWSDOWN
+    pub(super) grows_down: bool,
 }
 
 impl VmArea {
@@ -97,9 +108,50 @@ impl VmArea {
             flags,
             pma,
             name,
+            grows_down: false,
         })
     }
 
+    /// 把地址段标记为向下增长的，一般用于用户栈
+    pub fn with_grows_down(mut self) -> Self {
+        self.grows_down = true;
+        self
+    }
+
+    /// 向低地址扩展地址段，使其从 new_start 开始。新扩展的部分在页表中只做空映射，等到 page fault 时再分配
+    pub fn grow_down(&mut self, new_start: VirtAddr, pt: &mut PageTable) -> OSResult {
+        let new_start = align_down(new_start);
+        if new_start >= self.start {
+            return Ok(());
+        }
+        self.pma.lock().grow_left(self.start - new_start)?;
+        for vaddr in (new_start..self.start).step_by(PAGE_SIZE) {
+            pt.map(vaddr, 0, PTEFlags::empty()).map_err(|e| {
+                error!("failed to create mapping when growing down: {:#x?}, {:?}", vaddr, e);
+                e
+            })?;
+        }
+        self.start = new_start;
+        Ok(())
+    }
+
+    /// 向高地址扩展地址段，使其到 new_end 结束。新扩展的部分和 grow_down 一样只做空映射
+    pub fn grow_up(&mut self, new_end: VirtAddr, pt: &mut PageTable) -> OSResult {
+        let new_end = align_up(new_end);
+        if new_end <= self.end {
+            return Ok(());
+        }
+        self.pma.lock().grow_right(new_end - self.end)?;
+        for vaddr in (self.end..new_end).step_by(PAGE_SIZE) {
+            pt.map(vaddr, 0, PTEFlags::empty()).map_err(|e| {
+                error!("failed to create mapping when growing up: {:#x?}, {:?}", vaddr, e);
+                e
+            })?;
+        }
+        self.end = new_end;
+        Ok(())
+    }
+
     /// 当前地址段是否包含这个地址
     pub fn contains(&self, vaddr: VirtAddr) -> bool {
         self.start <= vaddr && vaddr < self.end
@@ -268,9 +320,13 @@ impl VmArea {
         let mut pma = self.pma.lock();
         for vaddr in (self.start..self.end).step_by(PAGE_SIZE) {
             if pma.get_frame((vaddr - self.start) / PAGE_SIZE, false)?.is_some() {
-                // 因为 pma 中拿到了页帧，所以这里一定是会成功的，可以 unwrap
-                // 不成功说明 OS 有问题
-                pt.set_flags(vaddr, self.flags).unwrap();
+                match pt.set_flags(vaddr, self.flags) {
+                    // 共享的页帧可能是其他地址空间分配的，在当前页表中还没有映射，等 page fault 时再映射即可
+                    Err(OSError::PageTable_PageNotMapped) if pma.shared_frames().is_some() => {}
+                    // 否则因为 pma 中拿到了页帧，所以这里一定是会成功的，可以 unwrap
+                    // 不成功说明 OS 有问题
+                    res => res.unwrap(),
+                }
             }
         }
         Ok(())
@@ -313,6 +369,10 @@ impl VmArea {
                 if res.is_err() {
                     return res;
                 }
+                // 共享的页帧可能是其他地址空间分配的，在当前页表中还没有映射
+                if pma.shared_frames().is_some() && !pt.get_entry(vaddr).map_or(false, |entry| unsafe { (*entry).is_valid() }) {
+                    continue;
+                }
                 pt.unmap(vaddr).map_err(|e| {
                     error!("failed to unmap VA: {:#x?}, {:?}", vaddr, e);
                     e
@@ -343,6 +403,7 @@ impl VmArea {
             flags: self.flags,
             pma: self.pma.lock().clone_as_fork()?,
             name: self.name,
+            grows_down: self.grows_down,
         })
     }
 
@@ -356,6 +417,10 @@ impl VmArea {
     /// 不是只有写这段内存才需要考虑 Copy on write，所以真正实现可能比想象的要复杂。
     pub fn copy_to_new_area_with_data(&self) -> OSResult<VmArea> {
         let new_area = self.copy_to_new_area_empty()?;
+        // 共享的地址段在 copy_to_new_area_empty 中已经指向了同一组页帧，不需要复制数据
+        if self.pma.lock().shared_frames().is_some() {
+            return Ok(new_area);
+        }
         let mut new_pma = new_area.pma.lock();
         let mut old_pma = self.pma.lock();
         for vaddr in (self.start..self.end).step_by(PAGE_SIZE) {
@@ -0,0 +1,192 @@
+//! 可以被多个地址空间共享的物理地址段
+
+//#![deny(missing_docs)]
+
+use alloc::{sync::Arc, vec::Vec};
+use core::fmt::{Debug, Formatter, Result};
+
+use lock::Mutex;
+
+use super::PmArea;
+use crate::error::{OSError, OSResult};
+use crate::memory::{
+    addr::{addr_to_page_id, align_down},
+    Frame, PhysAddr, PAGE_SIZE,
+};
+
+/// 共享的页帧数组。所有映射了这段内存的 PmAreaShared 都持有它的一个 Arc，
+/// 所以 Arc 的引用计数也可以用来统计有多少个地址段映射了它
+pub type SharedFrames = Arc<Mutex<Vec<Option<Frame>>>>;
+
+/// 共享的物理地址段，对应共享页帧数组中 [start_page, start_page + page_count) 的一段。
+///
+/// 页帧同样是 lazy 分配的，但分配后属于共享页帧数组而不是某个地址段，
+/// 所以 unmap 时只删除页表中的映射，页帧在最后一个持有者释放数组时才回收
+pub struct PmAreaShared {
+    frames: SharedFrames,
+    /// 在共享页帧数组中的起始页号
+    start_page: usize,
+    /// 页数
+    page_count: usize,
+}
+
+impl PmArea for PmAreaShared {
+    fn size(&self) -> usize {
+        self.page_count * PAGE_SIZE
+    }
+
+    fn clone_as_fork(&self) -> OSResult<Arc<Mutex<dyn PmArea>>> {
+        // fork 出的地址段和原来的指向同一组页帧
+        Ok(Arc::new(Mutex::new(Self {
+            frames: self.frames.clone(),
+            start_page: self.start_page,
+            page_count: self.page_count,
+        })))
+    }
+
+    fn get_frame(&mut self, idx: usize, need_alloc: bool) -> OSResult<Option<PhysAddr>> {
+        let mut frames = self.frames.lock();
+        let slot = &mut frames[self.start_page + idx];
+        if need_alloc && slot.is_none() {
+            let mut frame = Frame::new().ok_or(OSError::Memory_RunOutOfMemory)?;
+            frame.zero();
+            *slot = Some(frame);
+        }
+        Ok(slot.as_ref().map(|f| f.start_paddr()))
+    }
+
+    fn sync_frame_with_file(&mut self, _idx: usize) -> OSResult {
+        Ok(())
+    }
+
+    fn release_frame(&mut self, idx: usize) -> OSResult {
+        // 页帧属于所有共享者，这里不释放，只告诉调用者这一页是否可能被映射过
+        if self.frames.lock()[self.start_page + idx].is_some() {
+            Ok(())
+        } else {
+            Err(OSError::PmAreaLazy_ReleaseNotAllocatedPage)
+        }
+    }
+
+    fn read(&mut self, offset: usize, dst: &mut [u8]) -> OSResult<usize> {
+        self.for_each_frame(offset, dst.len(), |processed: usize, frame: &mut [u8]| {
+            dst[processed..processed + frame.len()].copy_from_slice(frame);
+        })
+    }
+
+    fn write(&mut self, offset: usize, src: &[u8]) -> OSResult<usize> {
+        self.for_each_frame(offset, src.len(), |processed: usize, frame: &mut [u8]| {
+            frame.copy_from_slice(&src[processed..processed + frame.len()]);
+        })
+    }
+
+    fn shrink_left(&mut self, new_start: usize) -> OSResult {
+        if new_start < self.size() {
+            let pages = addr_to_page_id(new_start);
+            self.start_page += pages;
+            self.page_count -= pages;
+            Ok(())
+        } else {
+            Err(OSError::PmArea_ShrinkFailed)
+        }
+    }
+
+    fn shrink_right(&mut self, new_end: usize) -> OSResult {
+        if new_end < self.size() {
+            self.page_count = addr_to_page_id(new_end);
+            Ok(())
+        } else {
+            Err(OSError::PmArea_ShrinkFailed)
+        }
+    }
+
+    fn split(&mut self, left_end: usize, right_start: usize) -> OSResult<Arc<Mutex<dyn PmArea>>> {
+        if left_end <= right_start && right_start < self.size() {
+            let right_page = addr_to_page_id(right_start);
+            let right = Self {
+                frames: self.frames.clone(),
+                start_page: self.start_page + right_page,
+                page_count: self.page_count - right_page,
+            };
+            self.page_count = addr_to_page_id(left_end);
+            Ok(Arc::new(Mutex::new(right)))
+        } else {
+            Err(OSError::PmArea_SplitFailed)
+        }
+    }
+
+    fn grow_left(&mut self, _len: usize) -> OSResult {
+        Err(OSError::PmArea_GrowFailed)
+    }
+
+    fn grow_right(&mut self, _len: usize) -> OSResult {
+        Err(OSError::PmArea_GrowFailed)
+    }
+
+    fn shared_frames(&self) -> Option<SharedFrames> {
+        Some(self.frames.clone())
+    }
+}
+
+impl PmAreaShared {
+    /// 用共享页帧数组中从 start_page 开始的 page_count 页生成 pma
+    pub fn new(frames: SharedFrames, start_page: usize, page_count: usize) -> OSResult<Self> {
+        if page_count == 0 || start_page + page_count > frames.lock().len() {
+            error!("invalid range in PmAreaShared: start {:x}, count {:x}", start_page, page_count);
+            return Err(OSError::PmArea_InvalidRange);
+        }
+        Ok(Self {
+            frames,
+            start_page,
+            page_count,
+        })
+    }
+
+    /// 对整体区间读写
+    fn for_each_frame(
+        &mut self,
+        offset: usize,
+        len: usize,
+        mut op: impl FnMut(usize, &mut [u8]),
+    ) -> OSResult<usize> {
+        if offset >= self.size() || offset + len > self.size() {
+            return Err(OSError::PmArea_OutOfRange);
+        }
+        let mut frames = self.frames.lock();
+        let mut start = offset;
+        let mut len = len;
+        let mut processed = 0;
+        while len > 0 {
+            let start_align = align_down(start);
+            let pgoff = start - start_align;
+            let n = (PAGE_SIZE - pgoff).min(len);
+
+            let slot = &mut frames[self.start_page + start_align / PAGE_SIZE];
+            if slot.is_none() {
+                let mut frame = Frame::new().ok_or(OSError::Memory_RunOutOfMemory)?;
+                frame.zero();
+                *slot = Some(frame);
+            }
+            let frame = slot.as_mut().unwrap();
+            op(processed, &mut frame.as_slice_mut()[pgoff..pgoff + n]);
+            start += n;
+            processed += n;
+            len -= n;
+        }
+        Ok(processed)
+    }
+}
+
+impl Debug for PmAreaShared {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        f.debug_struct("PmAreaShared")
+            .field("start_page", &self.start_page)
+            .field("size", &self.size())
+            .finish()
+    }
+}
+
+/// 新建一组共享页帧，所有页都暂不分配
+pub fn new_shared_frames(page_count: usize) -> SharedFrames {
+    Arc::new(Mutex::new((0..page_count).map(|_| None).collect()))
+}
@@ -7,18 +7,16 @@ mod page_table;
 mod user;
 mod vmm;
 
-use crate::{
-    constants::{
-        DEVICE_END, DEVICE_START, PAGE_SIZE, PHYS_MEMORY_END, PHYS_MEMORY_OFFSET, PHYS_VIRT_OFFSET,
-        USER_VIRT_ADDR_LIMIT,
-    },
-    error::OSResult,
+use crate::constants::{
+    PAGE_SIZE, PHYS_MEMORY_END, PHYS_MEMORY_OFFSET, PHYS_VIRT_OFFSET, USER_VIRT_ADDR_LIMIT,
 };
 use alloc::vec::Vec;
 use core::ops::Range;
+use lock::Mutex;
 
 pub use addr::*;
-pub use allocator::{allocator_init, FdAllocator, Frame, Tid};
+pub use allocator::{allocator_init, mem_stat, FdAllocator, Frame, Tid};
+use allocator::add_region;
 pub use page_table::{PTEFlags, PageTable, PageTableEntry};
 
 /*
@@ -29,16 +27,21 @@ pub use page_table_impl_rv64_sv39::{
 };
 */
 
-pub use areas::{DiffSet, CutSet, PmArea, PmAreaFixed, PmAreaLazy, VmArea};
+pub use areas::{
+    new_shared_frames, DiffSet, CutSet, PmArea, PmAreaFixed, PmAreaLazy, PmAreaShared, SharedFrames, VmArea,
+};
 
 pub use vmm::{
-    enable_kernel_page_table, handle_kernel_page_fault, new_memory_set_for_task, MemorySet,
+    enable_kernel_page_table, handle_kernel_page_fault, new_memory_set_for_task, MemorySet, REPORT_PAGE_FAULT,
 };
 
 pub use user::{UserPtr, UserPtrUnchecked};
 
-/// 获取从kernel_end的下一页起，至物理内存最后一页的物理页号
-pub fn get_phys_memory_regions() -> Vec<Range<usize>> {
+/// 启动时 -initrd 加载到的物理内存，解压完之前其中的页不能分配出去。没有 initrd 时为空区间
+static INITRD_RESERVED: Mutex<Range<usize>> = Mutex::new(0..0);
+
+/// 从kernel_end的下一页起至物理内存最后一页的物理地址区间，包括还没有解压的 initrd
+fn all_phys_memory_regions() -> Vec<Range<usize>> {
     extern "C" {
         fn kernel_end();
     }
@@ -47,13 +50,39 @@ pub fn get_phys_memory_regions() -> Vec<Range<usize>> {
     vec![start..end, 0xa000_0000..0xbe00_0000]
 }
 
-#[allow(dead_code)]
-pub fn create_mapping(ms: &mut MemorySet) -> OSResult {
-    ms.push(VmArea::from_fixed_pma(
-        DEVICE_START,
-        DEVICE_END,
-        PHYS_VIRT_OFFSET,
-        PTEFlags::READ | PTEFlags::WRITE,
-        "ramdisk",
-    )?)
+/// 获取可以分配的物理地址区间，即去掉 initrd 之后的 all_phys_memory_regions
+pub fn get_phys_memory_regions() -> Vec<Range<usize>> {
+    let reserved = INITRD_RESERVED.lock().clone();
+    let mut regions = Vec::new();
+    for region in all_phys_memory_regions() {
+        if reserved.start >= region.end || reserved.end <= region.start {
+            regions.push(region);
+            continue;
+        }
+        if region.start < reserved.start {
+            regions.push(region.start..reserved.start);
+        }
+        if reserved.end < region.end {
+            regions.push(reserved.end..region.end);
+        }
+    }
+    regions
+}
+
+/// 记录 initrd 所在的物理内存。需要在初始化页帧分配器之前调用
+fn reserve_initrd(range: Range<usize>) {
+    *INITRD_RESERVED.lock() = align_down(range.start)..align_up(range.end);
+}
+
+/// 解压完 initrd 之后，把它所在的页交给页帧分配器。
+/// 需要在构造内核页表之前调用，这样这些页也会被映射
+pub fn release_initrd() {
+    let reserved = core::mem::replace(&mut *INITRD_RESERVED.lock(), 0..0);
+    for region in all_phys_memory_regions() {
+        let start = region.start.max(reserved.start);
+        let end = region.end.min(reserved.end);
+        if start < end {
+            add_region(start..end);
+        }
+    }
 }
@@ -3,16 +3,20 @@
 use super::{
     addr_to_page_id, align_down, align_up, cross_page, get_phys_memory_regions, page_count,
     page_id_to_addr, virt_to_phys,
-    DiffSet, CutSet, PTEFlags, PageTable, PmAreaLazy, VirtAddr, VmArea,
+    DiffSet, CutSet, PTEFlags, PageTable, PmArea, PmAreaLazy, PmAreaShared, SharedFrames, VirtAddr,
+    VmArea,
 };
 use crate::{
     arch,
     file::BackEndFile,
     constants::{
-        CPU_ID_LIMIT, DEVICE_END, DEVICE_START, IS_PRELOADED_FS_IMG, IS_TEST_ENV, MMIO_REGIONS,
-        PAGE_SIZE, USER_VIRT_ADDR_LIMIT, REPORT_PAGE_FAULT,
+        CPU_ID_LIMIT, MMIO_REGIONS,
+        PAGE_SIZE, USER_MMAP_BASE, USER_STACK_GUARD_GAP, USER_STACK_LIMIT_DEFAULT,
+        USER_VIRT_ADDR_LIMIT,
     },
     error::{OSError, OSResult},
+    ipc::{shm_detach, shm_fork, SHM_NAME},
+    sysctl::Sysctl,
 };
 use alloc::{
     collections::BTreeMap,
@@ -22,9 +26,14 @@ use alloc::{
 use core::{
     fmt::{Debug, Formatter, Result},
     mem::size_of,
+    ops::Range,
 };
 use lock::Mutex;
 
+/// 是否输出访存报错信息。这个信息会干扰到评测判定(换行问题)，但平时很有用。
+/// 可以通过 /proc/sys/vm/report_page_fault 打开
+pub static REPORT_PAGE_FAULT: Sysctl<bool> = Sysctl::new_bool(false);
+
 /// 内存段和相关的页表
 pub struct MemorySet {
     /// 标记内存段的位置
@@ -33,6 +42,16 @@ pub struct MemorySet {
     pub pt: PageTable,
     /// 是否是用户态的
     is_user: bool,
+    /// mmap 不指定地址时，从这里开始寻找空闲区间。加载用户程序时会被随机化
+    mmap_base: VirtAddr,
+    /// 用户堆的起始位置，在用户程序的 ELF 段之后
+    heap_start: VirtAddr,
+    /// 向下增长的栈最多能有多大，即 RLIMIT_STACK
+    stack_limit: usize,
+    /// 用户栈上的参数字符串所在的区间，用于 /proc/<pid>/cmdline
+    arg_range: Range<VirtAddr>,
+    /// 用户栈上的环境变量字符串所在的区间，用于 /proc/<pid>/environ
+    env_range: Range<VirtAddr>,
 }
 
 impl MemorySet {
@@ -42,6 +61,11 @@ impl MemorySet {
             areas: BTreeMap::new(),
             pt: PageTable::new().unwrap(),
             is_user: false,
+            mmap_base: PAGE_SIZE,
+            heap_start: 0,
+            stack_limit: USER_STACK_LIMIT_DEFAULT,
+            arg_range: 0..0,
+            env_range: 0..0,
         }
     }
 
@@ -51,6 +75,11 @@ impl MemorySet {
             areas: BTreeMap::new(),
             pt: PageTable::new().unwrap(),
             is_user: true,
+            mmap_base: USER_MMAP_BASE,
+            heap_start: 0,
+            stack_limit: USER_STACK_LIMIT_DEFAULT,
+            arg_range: 0..0,
+            env_range: 0..0,
         }
         /*
         let mut pt = PageTable::new().unwrap();
@@ -62,13 +91,175 @@ impl MemorySet {
         */
     }
 
+    /// 设置 mmap 不指定地址时的起始位置
+    pub fn set_mmap_base(&mut self, base: VirtAddr) {
+        self.mmap_base = align_up(base);
+    }
+
+    /// 设置用户堆的起始位置
+    pub fn set_heap_start(&mut self, start: VirtAddr) {
+        self.heap_start = align_up(start);
+    }
+
+    /// 获取用户堆的起始位置
+    pub fn get_heap_start(&self) -> VirtAddr {
+        self.heap_start
+    }
+
+    /// 把用户堆的堆顶从 old_top 移动到 new_top，新增的部分 lazy 分配，缩减的部分直接 unmap。
+    ///
+    /// 堆顶下面紧挨着权限未被修改的堆时，直接扩展那个地址段，避免每次 brk 都产生一个新的小地址段
+    pub fn resize_heap(&mut self, old_top: VirtAddr, new_top: VirtAddr) -> OSResult {
+        let old_end = align_up(old_top);
+        let new_end = align_up(new_top);
+        if new_end > old_end {
+            if !self.test_free_area(old_end, new_end) || self.in_stack_reserve(old_end, new_end) {
+                return Err(OSError::MemorySet_InvalidRange);
+            }
+            let flags = PTEFlags::READ | PTEFlags::WRITE | PTEFlags::USER;
+            let last_heap = match self.areas.range(..old_end).last() {
+                Some((&start, area)) if area.end == old_end && area.name == "user_heap" && area.flags == flags => {
+                    Some(start)
+                }
+                _ => None,
+            };
+            if let Some(start) = last_heap {
+                return self.areas.get_mut(&start).unwrap().grow_up(new_end, &mut self.pt);
+            }
+            let pma = PmAreaLazy::new(page_count(new_end - old_end), None)?;
+            self.push(VmArea::new(old_end, new_end, flags, Arc::new(Mutex::new(pma)), "user_heap")?)?;
+        } else if new_end < old_end {
+            self.modify_overlap_areas(new_end, old_end)?;
+        }
+        Ok(())
+    }
+
+    /// 用户地址空间的总大小，用于检查 RLIMIT_AS
+    pub fn user_size(&self) -> usize {
+        self.areas
+            .values()
+            .filter(|area| area.is_user())
+            .map(|area| area.end - area.start)
+            .sum()
+    }
+
+    /// 数据段的总大小，即除了栈以外所有可写的用户地址段，用于检查 RLIMIT_DATA
+    pub fn data_size(&self) -> usize {
+        self.areas
+            .values()
+            .filter(|area| area.is_user() && !area.grows_down && area.flags.contains(PTEFlags::WRITE))
+            .map(|area| area.end - area.start)
+            .sum()
+    }
+
+    /// 设置向下增长的栈的大小上限
+    pub fn set_stack_limit(&mut self, limit: usize) {
+        self.stack_limit = limit;
+    }
+
+    /// 获取向下增长的栈的大小上限
+    pub fn get_stack_limit(&self) -> usize {
+        self.stack_limit
+    }
+
+    /// 记录加载用户程序时，参数和环境变量字符串在用户栈上的区间
+    pub fn set_arg_env_range(&mut self, args: Range<VirtAddr>, envs: Range<VirtAddr>) {
+        self.arg_range = args;
+        self.env_range = envs;
+    }
+
+    /// 读出用户栈上的参数字符串，每项以 '\0' 结尾。用户程序可能改写或者 unmap 这段内存，读不到时返回空
+    pub fn read_args(&self) -> Vec<u8> {
+        self.read_user_range(self.arg_range.clone())
+    }
+
+    /// 读出用户栈上的环境变量字符串，格式和 read_args 相同
+    pub fn read_envs(&self) -> Vec<u8> {
+        self.read_user_range(self.env_range.clone())
+    }
+
+    fn read_user_range(&self, range: Range<VirtAddr>) -> Vec<u8> {
+        let mut buf = vec![0u8; range.len()];
+        match self.read(range.start, range.len(), &mut buf, PTEFlags::READ) {
+            Ok(()) => buf,
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// 所有用户地址段的起止地址、权限、名字和是否是共享映射，按地址排序。用于 /proc/<pid>/maps
+    pub fn user_areas(&self) -> Vec<(VirtAddr, VirtAddr, PTEFlags, &'static str, bool)> {
+        self.areas
+            .values()
+            .filter(|area| area.is_user())
+            .map(|area| {
+                let shared = area.pma.lock().shared_frames().is_some();
+                (area.start, area.end, area.flags, area.name, shared)
+            })
+            .collect()
+    }
+
+    /// 地址空间中每个 SysV 共享内存映射的页帧。一个映射被 mprotect 分成的几段只算一次
+    fn shm_attachments(&self) -> Vec<SharedFrames> {
+        let mut attachments: Vec<SharedFrames> = Vec::new();
+        let mut last_end = 0;
+        for area in self.areas.values().filter(|area| area.name == SHM_NAME) {
+            if let Some(frames) = area.pma.lock().shared_frames() {
+                let continued = area.start == last_end
+                    && attachments.last().map_or(false, |last| Arc::ptr_eq(last, &frames));
+                if !continued {
+                    attachments.push(frames);
+                }
+                last_end = area.end;
+            }
+        }
+        attachments
+    }
+
+    /// 检查 [start, end) 是否和向下增长的栈预留的空间(包括栈下方的空隙)相交
+    ///
+    /// 如果栈的上限被设得很大(如 RLIM_INFINITY)，最多只预留默认大小，超出的部分由 try_grow_stack 检查
+    fn in_stack_reserve(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        let reserve_size = self.stack_limit.min(USER_STACK_LIMIT_DEFAULT);
+        self.areas.values().filter(|area| area.grows_down).any(|area| {
+            let reserve_start = area
+                .end
+                .saturating_sub(reserve_size)
+                .min(area.start)
+                .saturating_sub(USER_STACK_GUARD_GAP);
+            start < area.start && end > reserve_start
+        })
+    }
+
+    /// 尝试让 vaddr 上方的向下增长的栈扩展到包含 vaddr。
+    ///
+    /// 栈的总大小不能超过 stack_limit，且扩展后和下方的内存段之间至少要有 USER_STACK_GUARD_GAP 的空隙
+    fn try_grow_stack(&mut self, vaddr: VirtAddr) -> OSResult {
+        let area_start = match self.areas.range(vaddr..).next() {
+            Some((&start, area)) if area.grows_down => start,
+            _ => return Err(OSError::PageFaultHandler_Unhandled),
+        };
+        let new_start = align_down(vaddr);
+        if self.areas[&area_start].end - new_start > self.stack_limit {
+            return Err(OSError::PageFaultHandler_StackOverflow);
+        }
+        if let Some((_, below)) = self.areas.range(..area_start).last() {
+            if below.end + USER_STACK_GUARD_GAP > new_start {
+                return Err(OSError::PageFaultHandler_StackOverflow);
+            }
+        }
+        let mut area = self.areas.remove(&area_start).unwrap();
+        let res = area.grow_down(new_start, &mut self.pt);
+        self.areas.insert(area.start, area);
+        res
+    }
+
     /// 寻找一个起始地址不小于 addr_hint，长为 len 的内存段。找不到时报错
     pub fn find_free_area(&self, hint: VirtAddr, len: usize) -> OSResult<VirtAddr> {
-        // 最好不要有一段内存区间从 0 开始
-        let test_addr = if hint == 0 { PAGE_SIZE } else { align_up(hint) };
+        // 没有 hint 时从 mmap_base 开始找，它保证了不会有一段内存区间从 0 开始
+        let test_addr = if hint == 0 { self.mmap_base } else { align_up(hint) };
         let addr = core::iter::once(test_addr)
             .chain(self.areas.iter().map(|(_, area)| area.end))
-            .find(|&addr| self.test_free_area(addr, addr + len))
+            .find(|&addr| self.test_free_area(addr, addr + len) && !self.in_stack_reserve(addr, addr + len))
             .unwrap();
         if addr + len >= USER_VIRT_ADDR_LIMIT {
             Err(OSError::MemorySet_UserMmapIntersectWithKernel)
@@ -211,6 +402,56 @@ impl MemorySet {
         Ok(start)
     }
 
+    /// 映射一段共享的物理地址段，成功时返回映射的起始地址。
+    ///
+    /// 如果指定参数 anywhere，则把 start 作为 hint 寻找空闲区间; 否则必须映射在 start 处，且不能和已有的内存段相交
+    pub fn push_shared(
+        &mut self,
+        start: VirtAddr,
+        flags: PTEFlags,
+        pma: PmAreaShared,
+        name: &'static str,
+        anywhere: bool,
+    ) -> OSResult<VirtAddr> {
+        let len = pma.size();
+        let start = if anywhere {
+            self.find_free_area(start, len)?
+        } else {
+            if start + len >= USER_VIRT_ADDR_LIMIT {
+                return Err(OSError::MemorySet_UserMmapIntersectWithKernel);
+            }
+            start
+        };
+        self.push(VmArea::new(start, start + len, flags, Arc::new(Mutex::new(pma)), name)?)?;
+        self.flush_tlb();
+        Ok(start)
+    }
+
+    /// 删除从 start 开始的一段名为 name 的共享内存段，返回它映射的共享页帧数组。
+    /// 这段内存可能已经被 mprotect 分成了几段，它们会被一起删除。
+    ///
+    /// 如果 start 处不是这样一段内存的开头，则返回 error
+    pub fn remove_shared(&mut self, start: VirtAddr, name: &'static str) -> OSResult<SharedFrames> {
+        let frames = self
+            .areas
+            .get(&start)
+            .filter(|area| area.name == name)
+            .and_then(|area| area.pma.lock().shared_frames())
+            .ok_or(OSError::MemorySet_UnmapAreaNotFound)?;
+        let mut end = start;
+        while let Some(area) = self.areas.get(&end) {
+            let same_frames = area.name == name
+                && area.pma.lock().shared_frames().map_or(false, |f| Arc::ptr_eq(&f, &frames));
+            if !same_frames {
+                break;
+            }
+            end = area.end;
+        }
+        self.modify_overlap_areas(start, end)?;
+        self.flush_tlb();
+        Ok(frames)
+    }
+
     /// 插入一段内存段，并将其映射到页表里
     pub fn push(&mut self, vma: VmArea) -> OSResult {
         if !self.test_free_area(vma.start, vma.end) {
@@ -229,7 +470,18 @@ impl MemorySet {
                 return area.handle_page_fault(vaddr - area.start, access_flags, &mut self.pt);
             }
         }
-        if REPORT_PAGE_FAULT {
+        // 可能是栈需要向下增长
+        match self.try_grow_stack(vaddr) {
+            Ok(()) => return self.handle_page_fault(vaddr, access_flags),
+            Err(OSError::PageFaultHandler_Unhandled) => {}
+            Err(e) => {
+                if REPORT_PAGE_FAULT.get() {
+                    warn!("user stack overflow @ {:#x?}: {:?}", vaddr, e);
+                }
+                return Err(e);
+            }
+        }
+        if REPORT_PAGE_FAULT.get() {
             warn!(
                 "unhandled page fault @ {:#x?} with access {:?}",
                 vaddr, access_flags
@@ -245,7 +497,9 @@ impl MemorySet {
                 return area.manually_alloc_page(vaddr - area.start, &mut self.pt);
             }
         }
-        Err(OSError::PageFaultHandler_Unhandled)
+        // 可能是栈需要向下增长
+        self.try_grow_stack(vaddr)?;
+        self.manually_alloc_page(vaddr)
     }
 
     /// 检查一个放在某个地址上的结构是否分配空间，如果未分配则强制分配它
@@ -280,6 +534,9 @@ impl MemorySet {
             println!("cannot clear kernel memory set");
             return;
         }
+        for frames in self.shm_attachments() {
+            shm_detach(frames, None);
+        }
         for area in self.areas.values() {
             if area.is_user() {
                 area.unmap_area(&mut self.pt).unwrap();
@@ -294,6 +551,9 @@ impl MemorySet {
             error!("cannot clear kernel memory set");
             return;
         }
+        for frames in self.shm_attachments() {
+            shm_detach(frames, None);
+        }
         let mut user_area_start: Vec<usize> = Vec::new();
         for (start, area) in self.areas.iter() {
             if area.is_user() {
@@ -385,11 +645,19 @@ impl MemorySet {
     /// 2. 对用户的地址段，所有虚拟地址和其中的数据相同，但对应的物理地址与 self 中的不同
     pub fn copy_as_fork(&self) -> OSResult<MemorySet> {
         let mut ms = new_memory_set_for_task()?;
+        ms.mmap_base = self.mmap_base;
+        ms.heap_start = self.heap_start;
+        ms.stack_limit = self.stack_limit;
+        ms.arg_range = self.arg_range.clone();
+        ms.env_range = self.env_range.clone();
         for area in self.areas.values() {
             if area.is_user() {
                 ms.push(area.copy_to_new_area_with_data()?)?;
             }
         }
+        for frames in ms.shm_attachments() {
+            shm_fork(&frames);
+        }
         Ok(ms)
     }
 }
@@ -491,92 +759,24 @@ fn init_kernel_memory_set(ms: &mut MemorySet) -> OSResult {
         )?)?;
     }
 
-    if !IS_TEST_ENV {
-        // 插入设备的 MMIO 映射
-        for region in MMIO_REGIONS {
-            // 这里选择恒等映射是为了兼容设备
-            ms.push(VmArea::from_identical_pma(
-                region.0,
-                region.1,
-                PTEFlags::READ | PTEFlags::WRITE,
-                "MMIO",
-            )?)?;
-        }
-    }
-    // 测试环境，需要加载文件系统镜像到内存中
-    if IS_TEST_ENV {
-        if !IS_PRELOADED_FS_IMG {
-            extern "C" {
-                fn img_start();
-                fn img_end();
-            }
-            info!(
-                "img start {:x}, img_end {:x}",
-                img_start as usize, img_end as usize
-            );
-            let pstart = virt_to_phys(img_start as usize);
-            let pend = virt_to_phys(img_end as usize);
-            let offset = DEVICE_START - pstart;
-            // 文件系统的内存映射
-            ms.push(VmArea::from_fixed_pma(
-                pstart,
-                pend,
-                PHYS_VIRT_OFFSET + offset,
-                PTEFlags::READ | PTEFlags::WRITE,
-                "fs_in_memory",
-            )?)?;
-        } else {
-            ms.push(VmArea::from_fixed_pma(
-                DEVICE_START,
-                DEVICE_END,
-                PHYS_VIRT_OFFSET,
-                PTEFlags::READ | PTEFlags::WRITE,
-                "fs_in_memory",
-            )?)?;
-        }
+    // 插入设备的 MMIO 映射
+    for region in MMIO_REGIONS {
+        // 这里选择恒等映射是为了兼容设备
+        ms.push(VmArea::from_identical_pma(
+            region.0,
+            region.1,
+            PTEFlags::READ | PTEFlags::WRITE,
+            "MMIO",
+        )?)?;
     }
-    //create_mapping(ms)?;
     Ok(())
 }
 
-/*
-/// 加载 data 段中的文件系统。必须在测试环境且非预载文件系统的情况下调用。
-/// 即 IS_TEST_ENV && !IS_PRELOADED_FS_IMG
-///
-/// 仅用于fs出问题无法加载时进行测试
-fn load_fs_force() {
-    extern "C" {
-        fn img_start();
-        fn img_end();
-    }
-    println!(
-        "img start {:x}, img_end {:x}",
-        img_start as usize, img_end as usize
-    );
-    let data_len = img_end as usize - img_start as usize;
-    // println!("get data");
-    let src = unsafe { core::slice::from_raw_parts(img_start as *const u8, data_len) };
-    let dst = unsafe { core::slice::from_raw_parts_mut(DEVICE_START as *mut u8, data_len) };
-    dst.copy_from_slice(src);
-    unsafe {
-        println!("data[0]={}", (DEVICE_START as *mut u8).read_volatile());
-    }
-}
-*/
-
 lazy_static::lazy_static! {
     #[repr(align(64))]
     pub static ref KERNEL_MEMORY_SET: Mutex<MemorySet> = {
         let mut ms = MemorySet::new_kernel();
         init_kernel_memory_set(&mut ms).unwrap();
-        // 如果是测试环境的文件系统镜像，默认qemu已挂载好了不需要
-        // 否则，镜像在 data 段里，需要手动把它加载到 device 对应位置
-        // 目前的实现不考虑把修改后的文件系统写回的情况
-        /*
-        if IS_TEST_ENV && !IS_PRELOADED_FS_IMG {
-            load_fs_force();
-        }
-        */
         info!("kernel memory set init end:\n{:#x?}", ms);
         Mutex::new(ms)
     };
@@ -0,0 +1,50 @@
+//! 内核中使用的伪随机数生成器
+//!
+//! 目前没有硬件随机源，所以用 xorshift64* 生成随机数，种子来自 mtime 计时器。
+//! 每次取数时还会混入当前时间，让不同进程拿到的序列不至于完全可预测。
+//! 它只用于地址随机化和 AT_RANDOM 这类"足够随机即可"的场景，不能用于密码学。
+
+use lock::Mutex;
+
+use crate::constants::PAGE_SIZE;
+use crate::timer::get_time;
+
+/// 随机数生成器的状态。0 表示还未初始化
+static RNG_STATE: Mutex<u64> = Mutex::new(0);
+
+/// 获取一个 64 位随机数
+pub fn random_u64() -> u64 {
+    let mut state = RNG_STATE.lock();
+    if *state == 0 {
+        // 种子不能为 0，否则 xorshift 会一直输出 0
+        *state = (get_time() as u64 ^ 0x9E37_79B9_7F4A_7C15) | 1;
+    }
+    let mut x = *state ^ (get_time() as u64).rotate_left(32);
+    if x == 0 {
+        x = 0x9E37_79B9_7F4A_7C15;
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    *state = x;
+    x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+/// 用随机数填满 buf
+pub fn fill_random(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let bytes = random_u64().to_ne_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
+
+/// 获取一个 [0, page_limit) 页范围内的随机偏移，返回值按页对齐。
+///
+/// 用于给 ELF 加载基址、mmap 基址和栈顶加随机偏移
+pub fn random_page_offset(page_limit: usize) -> usize {
+    if page_limit == 0 {
+        0
+    } else {
+        (random_u64() as usize % page_limit) * PAGE_SIZE
+    }
+}
@@ -20,6 +20,7 @@ mod shadow_bitset;
 pub use shadow_bitset::ShadowBitset;
 mod tid2signals;
 use crate::constants::SIGSET_SIZE_IN_BIT;
+use crate::syscall::wake_thread;
 pub use tid2signals::{get_signals_from_tid, global_logoff_signals, global_register_signals};
 
 /// 处理信号的结构，每个线程有一个，根据 clone 的参数有可能是共享的
@@ -81,6 +82,9 @@ pub struct SignalReceivers {
     pub mask: Bitset,
     /// 当前已受到的信号
     pub sig_received: Bitset,
+    /// pselect / ppoll / epoll_pwait 等待时临时替换了掩码，这里保存原来的掩码。
+    /// 它在返回用户态处理信号时才被恢复，这样等待被打断时，打断它的信号仍按临时掩码取出
+    saved_mask: Option<Bitset>,
 }
 
 impl SignalReceivers {
@@ -89,12 +93,14 @@ impl SignalReceivers {
         Self {
             mask: Bitset::new(0),
             sig_received: Bitset::new(0),
+            saved_mask: None,
         }
     }
     /// 清空模块。
     pub fn clear(&mut self) {
         self.mask = Bitset::new(0);
         self.sig_received = Bitset::new(0);
+        self.saved_mask = None;
     }
     /// 处理一个信号。如果有收到的信号，则返回信号编号。否则返回 None
     pub fn get_one_signal(&mut self) -> Option<usize> {
@@ -104,6 +110,39 @@ impl SignalReceivers {
         })
     }
 
+    /// 是否有收到且未被屏蔽的信号。阻塞的系统调用需要据此判断是否被信号打断
+    pub fn has_pending_signal(&self) -> bool {
+        self.sig_received.find_first_one(self.mask).is_some()
+    }
+
+    /// 取出一个在 set 中的已收到信号，不管它是否被屏蔽。如果有，则返回信号编号。用于 signalfd
+    pub fn take_signal_in(&mut self, set: Bitset) -> Option<usize> {
+        self.sig_received.find_first_one(Bitset::new(!set.0)).map(|pos| {
+            self.sig_received.remove_bit(pos);
+            pos + 1
+        })
+    }
+
+    /// 是否收到了在 set 中的信号，不管它是否被屏蔽
+    pub fn has_signal_in(&self, set: Bitset) -> bool {
+        self.sig_received.find_first_one(Bitset::new(!set.0)).is_some()
+    }
+
+    /// 临时把掩码替换为 mask，原来的掩码会在下一次 restore_saved_mask 时恢复。
+    /// 替换和保存在同一次加锁中完成，所以不会有信号在两者之间漏过
+    pub fn set_temporary_mask(&mut self, mask: Bitset) {
+        let old_mask = core::mem::replace(&mut self.mask, mask);
+        // 如果已经有保存的掩码，那么它才是最初的掩码
+        self.saved_mask.get_or_insert(old_mask);
+    }
+
+    /// 恢复 set_temporary_mask 之前的掩码
+    pub fn restore_saved_mask(&mut self) {
+        if let Some(mask) = self.saved_mask.take() {
+            self.mask = mask;
+        }
+    }
+
     /// 尝试添加一个 bit 作为信号。发送的信号如果在 mask 中，则仍然会发送，只是可能不触发
     /// 因为 signum 的范围是 \[1,64\]，所以内部要 -1
     ///
@@ -119,5 +158,8 @@ pub fn send_signal(tid: usize, signum: usize) {
     if let Some(signals) = get_signals_from_tid(tid as usize) {
         // 获取目标线程(可以是自己)的 signals 数组
         signals.lock().try_add_bit(signum);
+        // 如果目标线程正在睡眠等待，则唤醒它，由它自己判断是否被打断。
+        // 即使信号被屏蔽也要唤醒，因为线程可能在通过 signalfd 等待这个信号
+        wake_thread(tid);
     }
 }
@@ -125,6 +125,11 @@ bitflags! {
     }
 }
 
+/// uname 中的系统名称、发行编号和版本，/proc/version 中也用到
+pub const UTS_SYSNAME: &str = "MaturinOS";
+pub const UTS_RELEASE: &str = "233";
+pub const UTS_VERSION: &str = "1.0";
+
 /// sys_uname 中指定的结构体类型
 #[repr(C)]
 pub struct UtsName {
@@ -146,10 +151,10 @@ impl UtsName {
     /// 默认 uname。这个结构的内容跟 os 没什么关系，所以就想写啥写啥了
     pub fn default() -> Self {
         Self {
-            sysname: Self::from_str("MaturinOS"),
+            sysname: Self::from_str(UTS_SYSNAME),
             nodename: Self::from_str("MaturinOS - machine[0]"),
-            release: Self::from_str("233"),
-            version: Self::from_str("1.0"),
+            release: Self::from_str(UTS_RELEASE),
+            version: Self::from_str(UTS_VERSION),
             machine: Self::from_str("RISC-V 64 on SIFIVE FU740"),
             domainname: Self::from_str("https://github.com/scPointer/maturin"),
         }
@@ -162,6 +167,63 @@ impl UtsName {
     }
 }
 
+/// ioctl 中读取硬件时钟的请求
+pub const RTC_RD_TIME: usize = 0x80247009;
+
+/// RTC_RD_TIME 读到的时间，和 C 中 struct tm 的前 9 项相同
+#[repr(C)]
+pub struct RtcTime {
+    pub tm_sec: i32,
+    pub tm_min: i32,
+    pub tm_hour: i32,
+    /// 一个月中的第几天，从 1 开始
+    pub tm_mday: i32,
+    /// 月份，从 0 开始
+    pub tm_mon: i32,
+    /// 从 1900 年开始的年数
+    pub tm_year: i32,
+    /// 星期几，周日为 0
+    pub tm_wday: i32,
+    /// 一年中的第几天，从 0 开始
+    pub tm_yday: i32,
+    pub tm_isdst: i32,
+}
+
+impl RtcTime {
+    /// 1970-01-01 00:00:00 之后 secs 秒的时间(UTC)
+    pub fn from_epoch_secs(secs: usize) -> Self {
+        let days = (secs / 86400) as i64;
+        let rem = (secs % 86400) as i32;
+        // 从天数推算年月日，算法见 Howard Hinnant 的 days_from_civil 的逆过程。
+        // 计算时把 3 月 1 日作为一年的开始，这样闰年多出的一天在年末
+        let z = days + 719468;
+        let era = z / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let mday = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 2 } else { mp - 10 };
+        let year = yoe + era * 400 + if month < 2 { 1 } else { 0 };
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        // 每个月之前的天数，不算闰年的 2 月 29 日
+        const DAYS_BEFORE_MONTH: [i64; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+        let yday = DAYS_BEFORE_MONTH[month as usize] + mday - 1 + if leap && month >= 2 { 1 } else { 0 };
+        Self {
+            tm_sec: rem % 60,
+            tm_min: rem / 60 % 60,
+            tm_hour: rem / 3600,
+            tm_mday: mday as i32,
+            tm_mon: month as i32,
+            tm_year: (year - 1900) as i32,
+            // 1970-01-01 是周四
+            tm_wday: ((days + 4) % 7) as i32,
+            tm_yday: yday as i32,
+            tm_isdst: 0,
+        }
+    }
+}
+
 /// sys_getdents64 中指定的结构体类型
 #[repr(C)]
 pub struct Dirent64 {
@@ -218,6 +280,190 @@ pub struct IoVec {
     pub len: usize,
 }
 
+/// sys_sendmsg / sys_recvmsg 中描述消息的结构，即 struct msghdr
+#[repr(C)]
+pub struct MsgHdr {
+    /// 消息的地址
+    pub name: usize,
+    /// 地址的长度
+    pub namelen: u32,
+    /// 数据所在的 IoVec 数组
+    pub iov: *mut IoVec,
+    /// IoVec 数组的长度
+    pub iovlen: usize,
+    /// 辅助数据，是一串 CMsgHdr
+    pub control: usize,
+    /// 辅助数据的总长度
+    pub controllen: usize,
+    /// recvmsg 时返回的消息状态
+    pub flags: u32,
+}
+
+/// sys_sendmmsg / sys_recvmmsg 中的一条消息，即 struct mmsghdr
+#[repr(C)]
+pub struct MMsgHdr {
+    pub hdr: MsgHdr,
+    /// 这条消息发送或接收的字节数
+    pub len: u32,
+}
+
+/// 一段辅助数据的头部，即 struct cmsghdr。数据紧跟在头部之后
+#[repr(C)]
+pub struct CMsgHdr {
+    /// 包括头部在内的长度
+    pub len: usize,
+    pub level: i32,
+    pub ctype: i32,
+}
+
+impl CMsgHdr {
+    /// 辅助数据按 usize 对齐
+    pub fn align(len: usize) -> usize {
+        (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+    }
+}
+
+/// 辅助数据和 socket 选项的 level，表示这是 socket 层的数据或选项
+pub const SOL_SOCKET: i32 = 1;
+/// 辅助数据的类型，传递文件描述符
+pub const SCM_RIGHTS: i32 = 1;
+/// 辅助数据的类型，传递进程身份
+pub const SCM_CREDENTIALS: i32 = 2;
+/// socket 选项，允许重用地址
+pub const SO_REUSEADDR: i32 = 2;
+/// socket 选项，获取 socket 的类型
+pub const SO_TYPE: i32 = 3;
+/// socket 选项，获取并清除 socket 上待处理的错误
+pub const SO_ERROR: i32 = 4;
+/// socket 选项，允许发送广播
+pub const SO_BROADCAST: i32 = 6;
+/// socket 选项，发送和接收 buffer 的大小
+pub const SO_SNDBUF: i32 = 7;
+pub const SO_RCVBUF: i32 = 8;
+/// socket 选项，定期发送保活报文
+pub const SO_KEEPALIVE: i32 = 9;
+/// socket 选项，关闭时的行为，参数是 Linger
+pub const SO_LINGER: i32 = 13;
+/// socket 选项，允许多个 socket 绑定同一个端口
+pub const SO_REUSEPORT: i32 = 15;
+/// socket 选项，接收和发送的超时时间，参数是 TimeVal
+pub const SO_RCVTIMEO: i32 = 20;
+pub const SO_SNDTIMEO: i32 = 21;
+/// socket 选项，获取 socket 的地址族
+pub const SO_DOMAIN: i32 = 39;
+/// socket 选项的 level，表示这是 TCP 层的选项
+pub const IPPROTO_TCP: i32 = 6;
+/// socket 选项，关闭 Nagle 算法
+pub const TCP_NODELAY: i32 = 1;
+/// socket 选项的 level，表示这是 IPv6 层的选项
+pub const IPPROTO_IPV6: i32 = 41;
+/// socket 选项，AF_INET6 的 socket 只使用 IPv6 通信
+pub const IPV6_V6ONLY: i32 = 26;
+
+/// SO_LINGER 选项的参数，即 struct linger
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Linger {
+    /// 是否启用
+    pub onoff: i32,
+    /// 关闭时等待的秒数
+    pub linger: i32,
+}
+
+bitflags! {
+    /// send / recv 系列 syscall 的选项，也用于 MsgHdr 中返回的消息状态
+    pub struct MsgFlags: u32 {
+        /// 读消息但不取走
+        const PEEK = 0x2;
+        /// 辅助数据被截断了
+        const CTRUNC = 0x8;
+        /// 消息被截断了。作为 recv 的参数时，要求返回消息的实际长度
+        const TRUNC = 0x20;
+        /// 这一次调用不阻塞
+        const DONTWAIT = 0x40;
+        /// 流式 socket 等到 buffer 填满再返回
+        const WAITALL = 0x100;
+        /// 对方已关闭时不发送 SIGPIPE
+        const NOSIGNAL = 0x4000;
+        /// recvmmsg 收到第一条消息后不再阻塞
+        const WAITFORONE = 0x10000;
+        /// 通过 SCM_RIGHTS 收到的 fd 设置 CLOEXEC
+        const CMSG_CLOEXEC = 0x40000000;
+    }
+}
+
+/// socket 上的 ioctl 请求，用于查询和配置网卡
+/// 获取所有网卡的地址
+pub const SIOCGIFCONF: usize = 0x8912;
+/// 获取网卡的标志
+pub const SIOCGIFFLAGS: usize = 0x8913;
+/// 设置网卡的标志
+pub const SIOCSIFFLAGS: usize = 0x8914;
+/// 获取网卡的 ip 地址
+pub const SIOCGIFADDR: usize = 0x8915;
+/// 设置网卡的 ip 地址
+pub const SIOCSIFADDR: usize = 0x8916;
+/// 获取网卡的子网掩码
+pub const SIOCGIFNETMASK: usize = 0x891b;
+/// 设置网卡的子网掩码
+pub const SIOCSIFNETMASK: usize = 0x891c;
+/// 获取网卡的 MTU
+pub const SIOCGIFMTU: usize = 0x8921;
+/// 获取网卡的 MAC 地址
+pub const SIOCGIFHWADDR: usize = 0x8927;
+/// 获取网卡的编号
+pub const SIOCGIFINDEX: usize = 0x8933;
+/// 添加路由
+pub const SIOCADDRT: usize = 0x890b;
+/// 删除路由
+pub const SIOCDELRT: usize = 0x890c;
+
+bitflags! {
+    /// 网卡的标志，即 IFF_*
+    pub struct IfFlags: u16 {
+        /// 已启用
+        const UP = 0x1;
+        /// 支持广播
+        const BROADCAST = 0x2;
+        /// 是本地回环
+        const LOOPBACK = 0x8;
+        /// 正在运行
+        const RUNNING = 0x40;
+        /// 支持多播
+        const MULTICAST = 0x1000;
+    }
+}
+
+/// 网卡相关 ioctl 的参数，即 struct ifreq
+#[repr(C)]
+pub struct IfReq {
+    /// 网卡的名字，以 \0 结尾
+    pub name: [u8; 16],
+    /// 具体内容由请求决定，可能是地址、标志或者编号
+    pub data: [u8; 24],
+}
+
+/// SIOCGIFCONF 的参数，即 struct ifconf
+#[repr(C)]
+pub struct IfConf {
+    /// buf 的长度，返回时为写入的长度
+    pub len: i32,
+    /// 放 IfReq 数组的用户地址。为 0 时只返回需要的长度
+    pub buf: usize,
+}
+
+/// 路由相关 ioctl 的参数，即 struct rtentry。这里只用到开头的几个地址
+#[repr(C)]
+pub struct RtEntry {
+    pub pad: usize,
+    /// 目的网络
+    pub dst: [u8; 16],
+    /// 网关
+    pub gateway: [u8; 16],
+    /// 目的网络的掩码
+    pub genmask: [u8; 16],
+}
+
 /// 错误编号
 #[repr(C)]
 #[derive(Debug)]
@@ -228,18 +474,32 @@ pub enum ErrorNo {
     ENOENT = -2,
     /// 找不到对应进程
     ESRCH = -3,
+    /// 阻塞的系统调用被信号打断
+    EINTR = -4,
+    /// 设备读写出错
+    EIO = -5,
+    /// 参数过长。例如 msgrcv 时消息长度超过了用户的 buffer
+    E2BIG = -7,
     /// 错误的文件描述符
     EBADF = -9,
     /// 资源暂时不可用。也可因为 futex_wait 时对应用户地址处的值与给定值不符
     EAGAIN = -11,
     /// 内存耗尽，或者没有对应的内存映射
     ENOMEM = -12,
+    /// 没有权限
+    EACCES = -13,
     /// 无效地址
     EFAULT = -14,
+    /// 不是块设备
+    ENOTBLK = -15,
     /// 设备或者资源被占用
     EBUSY = -16,
     /// 文件已存在
     EEXIST = -17,
+    /// 不能跨文件系统操作，如在两个分区之间 rename
+    EXDEV = -18,
+    /// 没有这个设备
+    ENODEV = -19,
     /// 不是一个目录(但要求需要是一个目录)
     ENOTDIR = -20,
     /// 是一个目录(但要求不能是)
@@ -248,13 +508,61 @@ pub enum ErrorNo {
     EINVAL = -22,
     /// fd（文件描述符）已满
     EMFILE = -24,
+    /// 文件过大
+    EFBIG = -27,
+    /// 设备上没有空间
+    ENOSPC = -28,
     /// 对文件进行了无效的 seek
     ESPIPE = -29,
+    /// 只读文件系统
+    EROFS = -30,
+    /// 管道或者 socket 的另一端已关闭
+    EPIPE = -32,
     /// 超过范围。例如用户提供的buffer不够长
     ERANGE = -34,
+    /// 文件名过长
+    ENAMETOOLONG = -36,
+    /// 目录不为空
+    ENOTEMPTY = -39,
+    /// 路径中的符号链接太多，可能成环
+    ELOOP = -40,
+    /// 找不到要求类型的消息
+    ENOMSG = -42,
+    /// IPC 对象已被删除
+    EIDRM = -43,
+    /// fd 不是一个 socket
+    ENOTSOCK = -88,
+    /// 没有指定目的地址
+    EDESTADDRREQ = -89,
+    /// 消息太长，无法一次发送
+    EMSGSIZE = -90,
+    /// socket 类型与协议或者对方不匹配
+    EPROTOTYPE = -91,
+    /// 不支持的 socket 选项
+    ENOPROTOOPT = -92,
+    /// 不支持的 socket 类型
+    ESOCKTNOSUPPORT = -94,
+    /// socket 不支持这个操作
+    EOPNOTSUPP = -95,
     EPFNOSUPPORT = -96,
     EAFNOSUPPORT = -97,
+    /// 地址已被占用
+    EADDRINUSE = -98,
+    /// 地址不是本机的地址
+    EADDRNOTAVAIL = -99,
+    /// 网络不可达
+    ENETUNREACH = -101,
+    /// 连接被对方重置
+    ECONNRESET = -104,
+    /// 发送缓冲区已满
+    ENOBUFS = -105,
+    /// socket 已经连接
+    EISCONN = -106,
+    /// socket 还没有连接
+    ENOTCONN = -107,
     ECONNREFUSED = -111,
+    /// 非阻塞的 connect 还没有完成
+    EINPROGRESS = -115,
 }
 
 // sys_lseek 时对应的条件
@@ -280,23 +588,6 @@ pub fn resolve_clone_flags_and_signal(flag: usize) -> (CloneFlags, SignalNo) {
     )
 }
 
-/// sys_prlimit64 使用的数组
-#[repr(C)]
-pub struct RLimit {
-    /// 软上限
-    pub rlim_cur: u64,
-    /// 硬上限
-    pub rlim_max: u64,
-}
-
-// sys_prlimit64 使用的选项
-/// 用户栈大小
-pub const RLIMIT_STACK: i32 = 3;
-/// 可以打开的 fd 数
-pub const RLIMIT_NOFILE: i32 = 7;
-/// 用户地址空间的最大大小
-pub const RLIMIT_AS: i32 = 9;
-
 numeric_enum_macro::numeric_enum! {
     #[repr(usize)]
     #[allow(non_camel_case_types)]
@@ -369,6 +660,26 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// sys_mount 用到的选项
+    pub struct MountFlags: u32 {
+        /// 只读挂载。目前不检查
+        const RDONLY = 1 << 0;
+        /// 忽略 suid 位。目前不检查
+        const NOSUID = 1 << 1;
+        /// 不允许访问其中的设备文件。目前不检查
+        const NODEV = 1 << 2;
+        /// 不允许执行其中的程序。目前不检查
+        const NOEXEC = 1 << 3;
+        /// 修改已有挂载的选项
+        const REMOUNT = 1 << 5;
+        /// 把一个目录挂载到另一个位置
+        const BIND = 1 << 12;
+        /// 把已有的挂载点移动到另一个位置。busybox 的 switch_root 用它把新的根文件系统移到根目录
+        const MOVE = 1 << 13;
+    }
+}
+
 bitflags! {
     /// sys_renameat2 用到的选项
     pub struct MSyncFlags: u32 {
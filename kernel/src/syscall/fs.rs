@@ -6,18 +6,22 @@
 //#![deny(missing_docs)]
 
 use super::{
-    Dirent64, Dirent64Type, ErrorNo, Fcntl64Cmd, IoVec, SysResult, UtimensatFlags, RenameFlags, 
-    SEEK_CUR, SEEK_END, SEEK_SET,
+    Dirent64, Dirent64Type, ErrorNo, Fcntl64Cmd, IoVec, MountFlags, SysResult, UtimensatFlags, RenameFlags, 
+    RtcTime, RTC_RD_TIME, SEEK_CUR, SEEK_END, SEEK_SET, socket_ioctl,
 };
 use crate::{
     constants::{AT_FDCWD, SENDFILE_BUFFER_SIZE},
+    drivers::BLOCK_CACHE,
     file::{
-        check_dir_exists, check_file_exists, get_dir_entry_iter, mkdir, mount_fat_fs, open_file,
-        origin_fs_stat, try_add_link, try_remove_link, read_link, umount_fat_fs, rename_or_move,
+        check_dir_exists, check_file_exists, get_dir_entries, is_root_dir, make_node, make_symlink, mkdir, mount_9p_fs, mount_fs,
+        mount_proc_fs, mount_tmp_fs, move_mount, open_file, origin_fs_stat, try_add_link, try_remove_link, read_link, read_symlink, set_file_mode,
+        set_file_owner, umount_fs, rename_or_move,
     },
-    file::{FsStat, Kstat, OpenFlags, Pipe, SeekFrom},
-    task::{get_current_task, TaskControlBlock},
-    timer::TimeSpec,
+    file::{EventFd, File, EFD_SEMAPHORE, FsStat, Kstat, OpenFlags, Pipe, RtcFile, SeekFrom, StMode},
+    file::socket::TcpSocket,
+    signal::{send_signal, SignalNo},
+    task::{get_current_task, TaskControlBlock, RLIMIT_FSIZE, RLIM_INFINITY},
+    timer::{get_time_sec, TimeSpec},
     utils::raw_ptr_to_ref_str,
 };
 use alloc::{string::String, sync::Arc};
@@ -69,7 +73,9 @@ pub fn sys_read(fd: usize, buf: *mut u8, len: usize) -> SysResult {
 
     let slice = unsafe { core::slice::from_raw_parts_mut(buf, len) };
     // 尝试了一下用 .map 串来写，但实际效果好像不如直接 if... 好看
-    if let Ok(file) = task.fd_manager.lock().get_file(fd) {
+    // 读文件可能阻塞，不能一直拿着 fd_manager 的锁，否则同一进程的其他线程无法操作 fd
+    let file = task.fd_manager.lock().get_file(fd);
+    if let Ok(file) = file {
         //let pos = file.seek(SeekFrom::Current(0)).unwrap();
         //info!("read from pos {pos}");
         // 读文件可能触发进程切换
@@ -79,10 +85,65 @@ pub fn sys_read(fd: usize, buf: *mut u8, len: usize) -> SysResult {
             //println!("[kernel] read syscall size {} wanted {}", read_len, len);
             return Ok(read_len);
         }
+        return Err(read_write_error(&file));
     }
     Err(ErrorNo::EINVAL)
 }
 
+/// 文件的 read / write 返回 None 时，判断 syscall 应该返回的错误。
+/// 文件记录了具体原因时返回它，设备出错时返回 EIO，非阻塞的文件暂时无法读写时返回 EAGAIN，
+/// 阻塞的读写被信号打断时返回 EINTR，其他情况说明文件不可读写
+fn read_write_error(file: &Arc<dyn File>) -> ErrorNo {
+    if let Some(err) = file.take_rw_error() {
+        err
+    } else if file.take_io_error() {
+        ErrorNo::EIO
+    } else if file.get_status().contains(OpenFlags::NON_BLOCK) {
+        ErrorNo::EAGAIN
+    } else if get_current_task().unwrap().signal_receivers.lock().has_pending_signal() {
+        ErrorNo::EINTR
+    } else {
+        ErrorNo::EINVAL
+    }
+}
+
+/// 检查 RLIMIT_FSIZE，返回从 pos 开始向 file 写 len 字节时，不超过上限的部分的长度。
+/// pos 为 None 时表示写到文件当前的位置，以 O_APPEND 打开的文件则总是写到末尾。
+/// 只有能 seek 的文件才有"写到多大"的概念，其他文件不受限制。
+///
+/// 所有会让文件变大的 syscall 都要经过这里。如果 pos 已经达到上限，则发送 SIGXFSZ 并返回 EFBIG
+fn check_fsize_limit(
+    task: &Arc<TaskControlBlock>,
+    file: &Arc<dyn File>,
+    pos: Option<usize>,
+    len: usize,
+) -> Result<usize, ErrorNo> {
+    let fsize_limit = task.rlimits.lock().get_cur(RLIMIT_FSIZE);
+    if fsize_limit == RLIM_INFINITY || len == 0 {
+        return Ok(len);
+    }
+    let pos = match pos {
+        Some(pos) => Some(pos),
+        None if file.get_status().contains(OpenFlags::APPEND) => file_size(file),
+        None => file.seek(SeekFrom::Current(0)),
+    };
+    match pos {
+        Some(pos) if pos as u64 >= fsize_limit => {
+            send_signal(task.get_tid_num(), SignalNo::SIGXFSZ as usize);
+            Err(ErrorNo::EFBIG)
+        }
+        // 写入一部分，直到恰好达到上限
+        Some(pos) => Ok(len.min((fsize_limit - pos as u64) as usize)),
+        None => Ok(len),
+    }
+}
+
+/// 获取文件的大小
+fn file_size(file: &Arc<dyn File>) -> Option<usize> {
+    let mut stat = Kstat::default();
+    file.get_stat(&mut stat).then(|| stat.st_size as usize)
+}
+
 /// 写一个字串到 fd 代表的文件。这个串放在 buf 中，长为 len
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> SysResult {
     info!("sys_write fd {fd}");
@@ -103,14 +164,18 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> SysResult {
         return Err(ErrorNo::EFAULT); // 地址不合法
     }
     let slice = unsafe { core::slice::from_raw_parts(buf, len) };
-    
-    if let Ok(file) = task.fd_manager.lock().get_file(fd) {
+
+    // 同 sys_read，写文件可能阻塞，不能一直拿着 fd_manager 的锁
+    let file = task.fd_manager.lock().get_file(fd);
+    if let Ok(file) = file {
         // 写文件也可能触发进程切换
         //drop(tcb_inner);
         drop(task_vm); //及时去锁，可能其他程序要用
-        if let Some(write_len) = file.write(slice) {
+        let len = check_fsize_limit(&task, &file, None, len)?;
+        if let Some(write_len) = file.write(&slice[..len]) {
             return Ok(write_len);
         }
+        return Err(read_write_error(&file));
     }
     Err(ErrorNo::EINVAL)
 }
@@ -205,21 +270,22 @@ pub fn sys_readlinkat(dir_fd: i32, path: *const u8, buf: *mut u8, len: usize) ->
     || task_vm.manually_alloc_user_str(buf, len).is_err(){
         return Err(ErrorNo::EFAULT); // 检查传入的地址是否合法
     }
+    // /proc 下的链接(如 /proc/self/cwd)需要读取当前进程的信息，所以不能继续持有锁
+    drop(task_vm);
 
     if let Some((path, file)) = resolve_path_from_fd(&task, dir_fd, path) {
-        if file == "proc/self/exe" {
-            let name = "/lmbench_all"; // 这里仅针对 lmbench 做了特判
-            let write_len = len.min(name.len());
-            let slice = unsafe { core::slice::from_raw_parts_mut(buf, write_len) };
-            slice.copy_from_slice(&name.as_bytes()[..write_len]);
-            return Ok(write_len);
-        }
         if let Some(linked_file) = read_link(path.as_str(), file) {
             //info!("readlinkat -> linked to {linked_file}");
             let slice = unsafe { core::slice::from_raw_parts_mut(buf, linked_file.len()) };
             slice.copy_from_slice(linked_file.as_bytes());
             return Ok(linked_file.len());
         }
+        // 不在链接表里时，看是不是文件系统(ext2 或 9p)中的符号链接
+        let target = read_symlink(path.as_str(), file)?;
+        let write_len = len.min(target.len());
+        let slice = unsafe { core::slice::from_raw_parts_mut(buf, write_len) };
+        slice.copy_from_slice(&target.as_bytes()[..write_len]);
+        return Ok(write_len);
     }
     Err(ErrorNo::EINVAL)
 }
@@ -255,6 +321,30 @@ pub fn sys_fstat(fd: usize, kstat: *mut Kstat) -> SysResult {
     }
     Err(ErrorNo::EINVAL)
 }
+/// 把块缓存中所有修改过的数据写回磁盘
+pub fn sys_sync() -> SysResult {
+    BLOCK_CACHE.sync().map(|_| 0)
+}
+/// 把文件修改过的数据写回磁盘。所有文件共用一个块缓存，所以和 sync 一样写回整个缓存
+pub fn sys_fsync(fd: usize) -> SysResult {
+    let task = get_current_task().unwrap();
+    task.fd_manager.lock().get_file(fd).map_err(|_| ErrorNo::EBADF)?;
+    BLOCK_CACHE.sync().map(|_| 0)
+}
+/// 修改文件大小
+pub fn sys_ftruncate(fd: usize, len: usize) -> SysResult {
+    let task = get_current_task().unwrap();
+    let file = task.fd_manager.lock().get_file(fd).map_err(|_| ErrorNo::EBADF)?;
+    // 把文件变大时，新的最后一个字节也要在 RLIMIT_FSIZE 以内。缩小文件不受限制
+    if file_size(&file).map_or(false, |size| len > size) {
+        check_fsize_limit(&task, &file, Some(len - 1), 1)?;
+    }
+    if file.truncate(len) {
+        Ok(0)
+    } else {
+        Err(ErrorNo::EINVAL)
+    }
+}
 /// 获取文件状态信息，但是给出的是目录 fd 和相对路径。
 pub fn sys_fstatat(dir_fd: i32, path: *const u8, kstat: *mut Kstat) -> SysResult {
     let task = get_current_task().unwrap();
@@ -359,6 +449,61 @@ pub fn sys_linkat(
     Err(ErrorNo::EINVAL)
 }
 
+/// 创建指向 target 的符号链接。只有 ext2 和 9p 支持符号链接
+pub fn sys_symlinkat(target: *const u8, new_dir_fd: i32, link_path: *const u8) -> SysResult {
+    let task = get_current_task().unwrap();
+    let mut task_vm = task.vm.lock();
+    if task_vm.manually_alloc_page(target as usize).is_err()
+        || task_vm.manually_alloc_page(link_path as usize).is_err()
+    {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    let target = unsafe { raw_ptr_to_ref_str(target) };
+    let (path, file) = resolve_path_from_fd(&task, new_dir_fd, link_path).ok_or(ErrorNo::EBADF)?;
+    info!("symlinkat: {}{} -> {}", path, file, target);
+    make_symlink(target, path.as_str(), file).map(|_| 0)
+}
+
+/// 创建文件节点，mode 中包含文件类型和权限，dev 是设备文件的设备号
+pub fn sys_mknodat(dir_fd: i32, path: *const u8, mode: u32, dev: u64) -> SysResult {
+    let task = get_current_task().unwrap();
+    if task.vm.lock().manually_alloc_page(path as usize).is_err() {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    let (path, file) = resolve_path_from_fd(&task, dir_fd, path).ok_or(ErrorNo::EBADF)?;
+    info!("mknodat: {}{} mode {:o} dev {:x}", path, file, mode, dev);
+    make_node(path.as_str(), file, mode, dev).map(|_| 0)
+}
+
+/// 修改文件权限。FAT 上的文件没有权限，总是成功
+pub fn sys_fchmodat(dir_fd: i32, path: *const u8, mode: u32, _flags: u32) -> SysResult {
+    let task = get_current_task().unwrap();
+    if task.vm.lock().manually_alloc_page(path as usize).is_err() {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    let (path, file) = resolve_path_from_fd(&task, dir_fd, path).ok_or(ErrorNo::EBADF)?;
+    if !check_file_exists(path.as_str(), file) && !check_dir_exists((path.clone() + file).as_str()) {
+        return Err(ErrorNo::ENOENT);
+    }
+    set_file_mode(path.as_str(), file, mode).map(|_| 0)
+}
+
+/// 修改文件的所有者和组。uid 或 gid 为 -1 时不修改对应的一项。FAT 上的文件没有所有者，总是成功
+pub fn sys_fchownat(dir_fd: i32, path: *const u8, uid: u32, gid: u32, flags: u32) -> SysResult {
+    let task = get_current_task().unwrap();
+    if task.vm.lock().manually_alloc_page(path as usize).is_err() {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    let (path, file) = resolve_path_from_fd(&task, dir_fd, path).ok_or(ErrorNo::EBADF)?;
+    let follow = !UtimensatFlags::from_bits_truncate(flags).contains(UtimensatFlags::SYMLINK_NOFOLLOW);
+    let uid = if uid == u32::MAX { None } else { Some(uid) };
+    let gid = if gid == u32::MAX { None } else { Some(gid) };
+    if !check_file_exists(path.as_str(), file) && !check_dir_exists((path.clone() + file).as_str()) {
+        return Err(ErrorNo::ENOENT);
+    }
+    set_file_owner(path.as_str(), file, uid, gid, follow).map(|_| 0)
+}
+
 /// 删除硬链接，并在链接数为0时实际删除文件。成功时返回0，失败时返回-1
 pub fn sys_unlinkat(dir_fd: i32, path: *const u8, _flags: u32) -> SysResult {
     let task = get_current_task().unwrap();
@@ -372,20 +517,48 @@ pub fn sys_unlinkat(dir_fd: i32, path: *const u8, _flags: u32) -> SysResult {
 
 /// 挂载文件系统。成功时返回0，失败时返回-1。
 ///
+/// 带有 MS_MOVE 时把挂载在 device 的文件系统移动到 mount_path，此时忽略 fs_type。
+///
 /// 目前只是语义上实现，还没有真实板子上测试过
 pub fn sys_mount(
     device: *const u8,
     mount_path: *const u8,
     fs_type: *const u8,
-    _flags: u32,
+    flags: u32,
     _data: *const u8,
 ) -> SysResult {
+    if MountFlags::from_bits_truncate(flags).contains(MountFlags::MOVE) {
+        return move_mount_point(device, mount_path);
+    }
     let fs_type = unsafe { raw_ptr_to_ref_str(fs_type) };
-    if fs_type != "vfat" {
+    if fs_type != "vfat" && fs_type != "ext2" && fs_type != "9p" && fs_type != "proc" && fs_type != "tmpfs" {
         // 不支持挂载其他类型
         return Err(ErrorNo::EINVAL);
     }
     let task = get_current_task().unwrap();
+    if fs_type == "9p" {
+        // 9p 的"设备"是 virtio-9p 设备的 mount tag，不是路径
+        let tag = unsafe { raw_ptr_to_ref_str(device) };
+        if let Some((mut mount_path, mount_file)) = resolve_path_from_fd(&task, AT_FDCWD, mount_path) {
+            mount_path += mount_file;
+            if !mount_path.ends_with('/') {
+                mount_path.push('/');
+            }
+            return mount_9p_fs(tag, mount_path).map(|_| 0);
+        }
+        return Err(ErrorNo::EINVAL);
+    }
+    if fs_type == "proc" || fs_type == "tmpfs" {
+        // procfs 和 tmpfs 没有设备，device 参数被忽略
+        if let Some((mut mount_path, mount_file)) = resolve_path_from_fd(&task, AT_FDCWD, mount_path) {
+            mount_path += mount_file;
+            if !mount_path.ends_with('/') {
+                mount_path.push('/');
+            }
+            return if fs_type == "proc" { mount_proc_fs(mount_path) } else { mount_tmp_fs(mount_path) }.map(|_| 0);
+        }
+        return Err(ErrorNo::EINVAL);
+    }
     // 这里把 fd 写成"当前目录"，但其实如果内部发现路径是 '/' 开头，会用绝对路径替代。
     // 这也是其他类似调用 open/close/mkdir/linkat 等的逻辑
     if let Some((device_path, device_file)) = resolve_path_from_fd(&task, AT_FDCWD, device) {
@@ -397,29 +570,49 @@ pub fn sys_mount(
                 // 挂载到的是一个目录，但用户输入目录时不一定加了 '/'
                 mount_path.push('/');
             }
-            if mount_fat_fs(device_path, device_file, mount_path) {
-                return Ok(0);
-            }
+            return mount_fs(device_path, device_file, mount_path, fs_type).map(|_| 0);
         }
     }
     Err(ErrorNo::EINVAL)
 }
 
-/// 卸载文件系统。成功时返回0，失败时(目录不存在/未挂载等)返回-1。
+/// 移动挂载点，即带有 MS_MOVE 的 sys_mount。
+///
+/// 当前进程的工作目录在移动的挂载点下时，跟着一起移动。busybox 的 switch_root 会先 chdir 到新的根，
+/// 再把它移动到根目录，之后 chroot(".")
+fn move_mount_point(from: *const u8, to: *const u8) -> SysResult {
+    let task = get_current_task().unwrap();
+    let (mut from_path, from_file) = resolve_path_from_fd(&task, AT_FDCWD, from).ok_or(ErrorNo::EINVAL)?;
+    let (mut to_path, to_file) = resolve_path_from_fd(&task, AT_FDCWD, to).ok_or(ErrorNo::EINVAL)?;
+    from_path += from_file;
+    to_path += to_file;
+    for path in [&mut from_path, &mut to_path] {
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+    }
+    let (from_path, to_path) = move_mount(from_path, to_path)?;
+    let mut tcb_inner = task.inner.lock();
+    if !tcb_inner.dir.ends_with('/') {
+        tcb_inner.dir.push('/');
+    }
+    if let Some(rest) = tcb_inner.dir.strip_prefix(from_path.as_str()) {
+        tcb_inner.dir = to_path + rest;
+    }
+    Ok(0)
+}
+
+/// 卸载文件系统。成功时返回0，目录不存在或未挂载时返回 EINVAL，还有打开的文件在使用它时返回 EBUSY。
 ///
 /// 目前只是语义上实现，还没有真实板子上测试过
 pub fn sys_umount(mount_path: *const u8, _flags: u32) -> SysResult {
     let task = get_current_task().unwrap();
-    if let Some((mut mount_path, mount_file)) = resolve_path_from_fd(&task, AT_FDCWD, mount_path) {
-        mount_path += mount_file;
-        if !mount_path.ends_with('/') {
-            mount_path.push('/');
-        }
-        if umount_fat_fs(mount_path) {
-            return Ok(0);
-        }
+    let (mut mount_path, mount_file) = resolve_path_from_fd(&task, AT_FDCWD, mount_path).ok_or(ErrorNo::EINVAL)?;
+    mount_path += mount_file;
+    if !mount_path.ends_with('/') {
+        mount_path.push('/');
     }
-    Err(ErrorNo::EINVAL)
+    umount_fs(mount_path).map(|_| 0)
 }
 
 /// 创建目录，成功时返回 0，失败时返回 -1
@@ -472,18 +665,39 @@ pub fn sys_chdir(path: *const u8) -> SysResult {
     }
 }
 
+/// 修改根目录。
+///
+/// 目前所有进程共用同一个根目录，所以只支持把根目录"改"成它自己。
+/// busybox 的 switch_root 在把新的根文件系统移动到根目录之后调用 chroot(".")，这时当前目录就是根目录
+pub fn sys_chroot(path: *const u8) -> SysResult {
+    let task = get_current_task().unwrap();
+    let (mut path, file) = resolve_path_from_fd(&task, AT_FDCWD, path).ok_or(ErrorNo::EINVAL)?;
+    path += file;
+    if !path.ends_with('/') {
+        path.push('/');
+    }
+    if !check_dir_exists(path.as_str()) {
+        return Err(ErrorNo::ENOENT);
+    }
+    if is_root_dir(path.as_str()) {
+        Ok(0)
+    } else {
+        Err(ErrorNo::EPERM)
+    }
+}
+
 /// 打开文件，返回对应的 fd。如打开失败，则返回 -1
 pub fn sys_open(dir_fd: i32, path: *const u8, flags: u32, user_mode: i32) -> SysResult {
     let task = get_current_task().unwrap();
-    let mut task_fd_manager = task.fd_manager.lock();
-    let mut task_vm = task.vm.lock();
     // 如果 fd 已满，则不再添加
-    if task_fd_manager.is_full() {
+    if task.fd_manager.lock().is_full() {
         return Err(ErrorNo::EMFILE);
     }
-    if task_vm.manually_alloc_page(path as usize).is_err() {
+    if task.vm.lock().manually_alloc_page(path as usize).is_err() {
         return Err(ErrorNo::EINVAL);
     }
+    // 打开文件时不能持有 fd_manager 和 vm 的锁：
+    // 从 dir_fd 获取目录，以及打开 /proc 下的文件时，都需要读取当前进程自己的 fd 和地址空间
     let tmp_path = unsafe { raw_ptr_to_ref_str(path) };
     info!(
         "openat: dir_fd={:?}, path={:?}, flags={:#x?}, mode={:#o}",
@@ -506,13 +720,13 @@ pub fn sys_open(dir_fd: i32, path: *const u8, flags: u32, user_mode: i32) -> Sys
 
         info!(
             "try open parent_dir={} file_path={} flag={:x} mode = 0o{:o}",
-            parent_dir, file_path, flags, user_mode & !task_fd_manager.get_umask()
+            parent_dir, file_path, flags, user_mode & !task.fd_manager.lock().get_umask()
         );
         if let Some(open_flags) = OpenFlags::from_bits(flags) {
             info!("[{:#?}]", open_flags);
             //println!("opened");
             if let Some(node) = open_file(parent_dir.as_str(), file_path.as_str(), open_flags) {
-                if let Ok(fd) = task_fd_manager.push(node) {
+                if let Ok(fd) = task.fd_manager.lock().push(node) {
                     //info!("return fd {}", fd);
                     //add_sys_info(parent_dir.clone() + file_path.as_str());
                     return Ok(fd);
@@ -532,10 +746,17 @@ pub fn sys_open(dir_fd: i32, path: *const u8, flags: u32, user_mode: i32) -> Sys
 pub fn sys_close(fd: usize) -> SysResult {
     let task = get_current_task().unwrap();
     let mut task_fd_manager = task.fd_manager.lock();
-    if let Ok(_file) = task_fd_manager.remove_file(fd) {
+    if let Ok(file) = task_fd_manager.remove_file(fd) {
         // 其实可以对 file 做最后处理。
         // 但此处不知道 file 的具体类型，所以还是推荐实现 Trait File 的类型自己写 Drop 时处理
         info!("close fd {fd}");
+        drop(task_fd_manager);
+        // 关闭 TCP socket 的最后一个 fd 时按 SO_LINGER 等待数据发完。进程退出时的关闭不等待，和 Linux 相同
+        if Arc::strong_count(&file) == 1 {
+            if let Some(sock) = file.as_any().downcast_ref::<TcpSocket>() {
+                sock.linger();
+            }
+        }
         Ok(0)
     } else {
         Err(ErrorNo::EINVAL)
@@ -566,6 +787,17 @@ pub fn sys_pipe(pipe: *mut u32) -> SysResult {
     Err(ErrorNo::EINVAL)
 }
 
+/// 创建一个计数器初值为 initval 的 eventfd，返回它的 fd
+pub fn sys_eventfd2(initval: u32, flags: u32) -> SysResult {
+    // 只允许 EFD_SEMAPHORE、EFD_NONBLOCK 和 EFD_CLOEXEC
+    if flags & !(EFD_SEMAPHORE | OpenFlags::NON_BLOCK.bits() | OpenFlags::CLOEXEC.bits()) != 0 {
+        return Err(ErrorNo::EINVAL);
+    }
+    let task = get_current_task().unwrap();
+    let mut fd_manager = task.fd_manager.lock();
+    fd_manager.push(Arc::new(EventFd::new(initval, flags))).map_err(|_| ErrorNo::EMFILE)
+}
+
 /// 复制一个 fd 中的文件到一个新 fd 中，成功时返回新的文件描述符，失败则返回 -1
 pub fn sys_dup(fd: usize) -> SysResult {
     let task = get_current_task().unwrap();
@@ -605,15 +837,17 @@ pub fn sys_getdents64(fd: usize, buf: *mut u8, len: usize) -> SysResult {
         {
             return Err(ErrorNo::EFAULT); // 检查传入的地址是否合法
         }
-        if let Some(dir_iter) = get_dir_entry_iter(dir.as_str()) {
+        if let Some(entries) = get_dir_entries(dir.as_str()) {
             let mut offset = 0; // buf 共有 len 长，当前将 buf.add(offset) 视为一个结构 Dirent64
-            for entry in dir_iter {
-                let file = entry.unwrap();
-                let file_name = file.file_name();
-                let file_type = if file.is_dir() {
-                    Dirent64Type::DIR
-                } else {
-                    Dirent64Type::REG
+            for (file_name, mode) in entries {
+                let file_type = match mode {
+                    StMode::S_IFDIR => Dirent64Type::DIR,
+                    StMode::S_IFLNK => Dirent64Type::LNK,
+                    StMode::S_IFCHR => Dirent64Type::CHR,
+                    StMode::S_IFBLK => Dirent64Type::BLK,
+                    StMode::S_IFIFO => Dirent64Type::FIFO,
+                    StMode::S_IFSOCK => Dirent64Type::SOCK,
+                    _ => Dirent64Type::REG,
                 };
                 // 当前的这一项如果要放到用户给的 buf 里，会有多大
                 let entry_size = Dirent64::d_name_offset() + file_name.len() + 1;
@@ -820,7 +1054,8 @@ pub fn sys_sendfile64(out_fd: usize, in_fd: usize, offset: *mut usize, count: us
             // 读取最多 count 字符
             // 这里目前直接限制了最大读取长度，没有分次读取
             // todo: 使用 buffer 分次读，避免一次读取太多到内存里
-            let mut buf = vec![0u8; count.min(SENDFILE_BUFFER_SIZE)];
+            let count = check_fsize_limit(&task, &out_file, None, count.min(SENDFILE_BUFFER_SIZE))?;
+            let mut buf = vec![0u8; count];
 
             if let Some(read_len) = in_file.read(&mut buf) {
                 if let Some(write_len) = out_file.write(&buf[..read_len]) {
@@ -872,13 +1107,26 @@ pub fn sys_ioctl(fd: usize, request: usize, argp: *mut usize) -> SysResult {
         "ioctl fd = {} request = {:x} argp {:x}",
         fd, request, argp as usize
     );
-    info!("ioctl unimplemented now, error checks only");
+    // socket 上的 ioctl 用于配置网卡
+    if let Some(ret) = socket_ioctl(fd, request, argp as usize) {
+        return ret;
+    }
     let task = get_current_task().unwrap();
     let mut task_vm = task.vm.lock();
     let fd_manager = task.fd_manager.lock();
-    if fd_manager.get_file(fd).is_err() {
-        return Err(ErrorNo::EBADF);
+    let file = fd_manager.get_file(fd).map_err(|_| ErrorNo::EBADF)?;
+    // 读取硬件时钟。内核没有真正的 RTC，返回的是启动以来的时间
+    if request == RTC_RD_TIME && file.as_ref().as_any().is::<RtcFile>() {
+        let time = argp as *mut RtcTime;
+        if task_vm.manually_alloc_type(time).is_err() {
+            return Err(ErrorNo::EFAULT); // 地址不合法
+        }
+        unsafe {
+            *time = RtcTime::from_epoch_secs(get_time_sec());
+        }
+        return Ok(0);
     }
+    info!("ioctl unimplemented now, error checks only");
     if task_vm.manually_alloc_page(argp as usize).is_err() {
         return Err(ErrorNo::EFAULT); // 地址不合法
     }
@@ -0,0 +1,214 @@
+//! System V IPC 相关的系统调用，包括共享内存、信号量集和消息队列
+//!
+//! IPC 对象本身在 crate::ipc 中实现，这里只负责检查和读写用户地址
+
+use super::{ErrorNo, SysResult};
+use crate::{
+    constants::PAGE_SIZE,
+    ipc::{
+        ipc_cmd, msg_ctl, msg_get, msg_receive, msg_send, sem_count, sem_ctl, sem_ctl_value,
+        sem_get, sem_get_all, sem_op, sem_set_all, shm_attach, shm_ctl, shm_detach, shm_get,
+        MsqidDs, SemBuf, SemidDs, ShmidDs, GETALL, IPC_RMID, IPC_SET, IPC_STAT, MSGMAX, SEMOPM,
+        SETALL, SHM_NAME,
+    },
+    memory::{align_down, PTEFlags, PmAreaShared},
+    task::get_current_task,
+    timer::{get_time_us, TimeSpec, TimeVal},
+};
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+// shmat 的选项
+/// 只读映射
+const SHM_RDONLY: i32 = 0o10000;
+/// 把 shmaddr 向下对齐到页
+const SHM_RND: i32 = 0o20000;
+/// 允许替换 shmaddr 处已有的映射
+const SHM_REMAP: i32 = 0o40000;
+/// 可执行映射
+const SHM_EXEC: i32 = 0o100000;
+
+/// 执行 IPC_STAT / IPC_SET / IPC_RMID 这类需要读写用户结构的 ctl 操作。
+/// IPC_SET 时先从 buf 读入结构，IPC_STAT 时再把结果写回 buf
+fn ctl_with_user_buf<T: Copy>(
+    buf: *mut T,
+    cmd: i32,
+    ctl: impl FnOnce(&mut T) -> SysResult,
+) -> SysResult {
+    let cmd = ipc_cmd(cmd);
+    let need_buf = cmd == IPC_STAT || cmd == IPC_SET;
+    if need_buf && get_current_task().unwrap().vm.lock().manually_alloc_type(buf).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    // IPC_RMID 不需要 buf，这时传给 ctl 的结构不会被用到
+    let mut ds: T = if need_buf { unsafe { *buf } } else { unsafe { core::mem::zeroed() } };
+    let ret = ctl(&mut ds)?;
+    if cmd == IPC_STAT {
+        unsafe { *buf = ds };
+    }
+    Ok(ret)
+}
+
+/// 获取 key 对应的共享内存段，必要时创建大小为 size 的新段
+pub fn sys_shmget(key: i32, size: usize, shmflg: i32) -> SysResult {
+    shm_get(key, size, shmflg, get_current_task().unwrap().get_pid_num())
+}
+
+/// 把共享内存段映射到 shmaddr 处。shmaddr 为 0 时由内核选择地址。返回映射的地址
+pub fn sys_shmat(shmid: usize, shmaddr: usize, shmflg: i32) -> SysResult {
+    let task = get_current_task().unwrap();
+    let addr = if shmflg & SHM_RND != 0 { align_down(shmaddr) } else { shmaddr };
+    if addr % PAGE_SIZE != 0 {
+        return Err(ErrorNo::EINVAL);
+    }
+    let (frames, pages) = shm_attach(shmid, task.get_pid_num())?;
+    let mut flags = PTEFlags::USER | PTEFlags::READ;
+    if shmflg & SHM_RDONLY == 0 {
+        flags |= PTEFlags::WRITE;
+    }
+    if shmflg & SHM_EXEC != 0 {
+        flags |= PTEFlags::EXECUTE;
+    }
+    let result = (|| {
+        if !task.check_vm_growth(pages * PAGE_SIZE, false) {
+            return Err(ErrorNo::ENOMEM);
+        }
+        let pma = PmAreaShared::new(frames.clone(), 0, pages).map_err(|_| ErrorNo::EINVAL)?;
+        let mut vm = task.vm.lock();
+        if addr != 0 && shmflg & SHM_REMAP != 0 {
+            vm.modify_overlap_areas(addr, addr + pages * PAGE_SIZE)
+                .map_err(|_| ErrorNo::EINVAL)?;
+        }
+        vm.push_shared(addr, flags, pma, SHM_NAME, addr == 0)
+            .map_err(|_| ErrorNo::EINVAL)
+    })();
+    // 没有映射成功，撤销 shm_attach 中增加的映射次数
+    if result.is_err() {
+        shm_detach(frames, Some(task.get_pid_num()));
+    }
+    result
+}
+
+/// 删除 shmaddr 处的共享内存段映射
+pub fn sys_shmdt(shmaddr: usize) -> SysResult {
+    let task = get_current_task().unwrap();
+    let frames = task
+        .vm
+        .lock()
+        .remove_shared(shmaddr, SHM_NAME)
+        .map_err(|_| ErrorNo::EINVAL)?;
+    shm_detach(frames, Some(task.get_pid_num()));
+    Ok(0)
+}
+
+/// 共享内存段的控制操作
+pub fn sys_shmctl(shmid: usize, cmd: i32, buf: *mut ShmidDs) -> SysResult {
+    ctl_with_user_buf(buf, cmd, |ds| shm_ctl(shmid, cmd, ds))
+}
+
+/// 获取 key 对应的信号量集，必要时创建有 nsems 个信号量的新集合
+pub fn sys_semget(key: i32, nsems: i32, semflg: i32) -> SysResult {
+    if nsems < 0 {
+        return Err(ErrorNo::EINVAL);
+    }
+    sem_get(key, nsems as usize, semflg)
+}
+
+/// 对信号量集原子地执行 nsops 个操作，必要时阻塞
+pub fn sys_semop(semid: usize, sops: *const SemBuf, nsops: usize) -> SysResult {
+    sys_semtimedop(semid, sops, nsops, core::ptr::null())
+}
+
+/// 同 sys_semop，但阻塞时最多等待 timeout 的时间，超时返回 EAGAIN。timeout 为空指针时一直等待
+pub fn sys_semtimedop(semid: usize, sops: *const SemBuf, nsops: usize, timeout: *const TimeSpec) -> SysResult {
+    if nsops == 0 {
+        return Err(ErrorNo::EINVAL);
+    }
+    if nsops > SEMOPM {
+        return Err(ErrorNo::E2BIG);
+    }
+    let task = get_current_task().unwrap();
+    let mut vm = task.vm.lock();
+    let start = sops as usize;
+    if vm.manually_alloc_range(start, start + nsops * size_of::<SemBuf>() - 1).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let ops: Vec<SemBuf> = unsafe { core::slice::from_raw_parts(sops, nsops) }.to_vec();
+    let deadline = if timeout.is_null() {
+        None
+    } else {
+        if vm.manually_alloc_type(timeout).is_err() {
+            return Err(ErrorNo::EFAULT);
+        }
+        let time_us: usize = TimeVal::from(unsafe { *timeout }).into();
+        Some(get_time_us() + time_us)
+    };
+    drop(vm);
+    sem_op(semid, &ops, task.get_pid_num(), deadline)
+}
+
+/// 信号量集的控制操作。arg 是用户传入的 union semun，根据 cmd 不同可能是值或者指针
+pub fn sys_semctl(semid: usize, semnum: usize, cmd: i32, arg: usize) -> SysResult {
+    let task = get_current_task().unwrap();
+    match ipc_cmd(cmd) {
+        IPC_STAT | IPC_SET | IPC_RMID => {
+            ctl_with_user_buf(arg as *mut SemidDs, cmd, |ds| sem_ctl(semid, cmd, ds))
+        }
+        GETALL => {
+            let vals = sem_get_all(semid)?;
+            if task.vm.lock().manually_alloc_range(arg, arg + vals.len() * size_of::<u16>() - 1).is_err() {
+                return Err(ErrorNo::EFAULT);
+            }
+            unsafe { core::slice::from_raw_parts_mut(arg as *mut u16, vals.len()) }.copy_from_slice(&vals);
+            Ok(0)
+        }
+        SETALL => {
+            let count = sem_count(semid)?;
+            if task.vm.lock().manually_alloc_range(arg, arg + count * size_of::<u16>() - 1).is_err() {
+                return Err(ErrorNo::EFAULT);
+            }
+            let vals: Vec<u16> = unsafe { core::slice::from_raw_parts(arg as *const u16, count) }.to_vec();
+            sem_set_all(semid, &vals, task.get_pid_num())
+        }
+        _ => sem_ctl_value(semid, semnum, cmd, arg as i32, task.get_pid_num()),
+    }
+}
+
+/// 获取 key 对应的消息队列，必要时创建新队列
+pub fn sys_msgget(key: i32, msgflg: i32) -> SysResult {
+    msg_get(key, msgflg)
+}
+
+/// 发送一条消息。msgp 指向 struct msgbuf，即一个 long 类型的消息类型，之后是 msgsz 字节的消息内容
+pub fn sys_msgsnd(msqid: usize, msgp: usize, msgsz: usize, msgflg: i32) -> SysResult {
+    if msgsz > MSGMAX {
+        return Err(ErrorNo::EINVAL);
+    }
+    let task = get_current_task().unwrap();
+    if task.vm.lock().manually_alloc_range(msgp, msgp + size_of::<isize>() + msgsz - 1).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let mtype = unsafe { *(msgp as *const isize) };
+    let data = unsafe { core::slice::from_raw_parts((msgp + size_of::<isize>()) as *const u8, msgsz) }.to_vec();
+    msg_send(msqid, mtype, data, msgflg, task.get_pid_num())
+}
+
+/// 按 msgtyp 接收一条消息，写入 msgp 指向的 struct msgbuf 中。返回消息内容的长度
+pub fn sys_msgrcv(msqid: usize, msgp: usize, msgsz: usize, msgtyp: isize, msgflg: i32) -> SysResult {
+    let task = get_current_task().unwrap();
+    // 先检查用户的 buffer，否则消息出队后可能无法写入
+    if task.vm.lock().manually_alloc_range(msgp, msgp + size_of::<isize>() + msgsz - 1).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let (mtype, data) = msg_receive(msqid, msgtyp, msgsz, msgflg, task.get_pid_num())?;
+    unsafe {
+        *(msgp as *mut isize) = mtype;
+        core::slice::from_raw_parts_mut((msgp + size_of::<isize>()) as *mut u8, data.len()).copy_from_slice(&data);
+    }
+    Ok(data.len())
+}
+
+/// 消息队列的控制操作
+pub fn sys_msgctl(msqid: usize, cmd: i32, buf: *mut MsqidDs) -> SysResult {
+    ctl_with_user_buf(buf, cmd, |ds| msg_ctl(msqid, cmd, ds))
+}
@@ -38,13 +38,10 @@ impl LoopCounter {
 // 则终止这个进程
 pub fn check_dead_loop(syscall_id: usize) {
     // 决定是否结束进程
-    let kill_proc = if let Ok(name) = SyscallNo::try_from(syscall_id) {
-        if name == SyscallNo::EPOLL_WAIT {
-            DEAD_LOOP_CNT.lock().count()
-        } else {
-            //DEAD_LOOP_CNT.lock().clear();
-            false
-        }
+    // 只统计未实现的 syscall。epoll_wait 等会阻塞的 syscall 不会空转，不需要统计
+    let kill_proc = if SyscallNo::try_from(syscall_id).is_ok() {
+        //DEAD_LOOP_CNT.lock().clear();
+        false
     } else {
         DEAD_LOOP_CNT.lock().count()
     };
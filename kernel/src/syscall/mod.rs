@@ -14,6 +14,7 @@
 mod flags;
 mod fs;
 mod futex;
+mod ipc;
 mod loops;
 mod process;
 mod select;
@@ -21,11 +22,12 @@ mod socket;
 mod syscall_no;
 mod times;
 
-pub use flags::{PollFd, ErrorNo};
+pub use flags::{PollFd, ErrorNo, UTS_RELEASE, UTS_SYSNAME, UTS_VERSION};
 use flags::*;
 use fs::*;
 use futex::*;
-pub use futex::{check_thread_blocked, wake_thread, set_waiter_for_thread};
+use ipc::*;
+pub use futex::{check_thread_blocked, wake_thread, set_waiter_for_thread, FutexWaiter};
 use loops::*;
 pub use loops::clear_loop_checker;
 use process::*;
@@ -34,9 +36,10 @@ use socket::*;
 use syscall_no::SyscallNo;
 use times::*;
 
-use crate::file::{FsStat, Kstat, EpollEvent};
+use crate::file::{FsStat, ITimerSpec, Kstat, EpollEvent};
+use crate::ipc::{MsqidDs, SemBuf, ShmidDs};
 use crate::signal::SigAction;
-use crate::task::ITimerVal;
+use crate::task::{ITimerVal, RLimit};
 use crate::timer::{TimeSpec, TimeVal};
 
 type SysResult = Result<usize, ErrorNo>;
@@ -57,13 +60,23 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
 
     let result = match syscall_id {
         SyscallNo::GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
-        SyscallNo::EPOLL_CREATE => sys_epoll_create(args[0]),
+        SyscallNo::EVENTFD2 => sys_eventfd2(args[0] as u32, args[1] as u32),
+        SyscallNo::EPOLL_CREATE1 => sys_epoll_create1(args[0] as u32),
         SyscallNo::EPOLL_CTL => sys_epoll_ctl(args[0] as i32, args[1] as i32, args[2] as i32, args[3] as *const EpollEvent),
-        SyscallNo::EPOLL_WAIT => sys_epoll_wait(args[0] as i32, args[1] as *mut EpollEvent, args[2] as i32, args[3] as i32),
+        SyscallNo::EPOLL_PWAIT => sys_epoll_pwait(
+            args[0] as i32,
+            args[1] as *mut EpollEvent,
+            args[2] as i32,
+            args[3] as i32,
+            args[4] as *const usize,
+            args[5],
+        ),
         SyscallNo::DUP => sys_dup(args[0]),
         SyscallNo::DUP3 => sys_dup3(args[0], args[1]),
         SyscallNo::FCNTL64 => sys_fcntl64(args[0], args[1], args[2]),
+        SyscallNo::MKNODAT => sys_mknodat(args[0] as i32, args[1] as *const u8, args[2] as u32, args[3] as u64),
         SyscallNo::UNLINKAT => sys_unlinkat(args[0] as i32, args[1] as *const u8, args[2] as u32),
+        SyscallNo::SYMLINKAT => sys_symlinkat(args[0] as *const u8, args[1] as i32, args[2] as *const u8),
         SyscallNo::LINKAT => sys_linkat(
             args[0] as i32,
             args[1] as *const u8,
@@ -83,6 +96,15 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SyscallNo::ACCESS => sys_access(args[0] as i32, args[1] as *const u8, args[2]),
         SyscallNo::MKDIR => sys_mkdir(args[0] as i32, args[1] as *const u8, args[2] as u32),
         SyscallNo::CHDIR => sys_chdir(args[0] as *const u8),
+        SyscallNo::CHROOT => sys_chroot(args[0] as *const u8),
+        SyscallNo::CHMOD => sys_fchmodat(args[0] as i32, args[1] as *const u8, args[2] as u32, args[3] as u32),
+        SyscallNo::FCHOWNAT => sys_fchownat(
+            args[0] as i32,
+            args[1] as *const u8,
+            args[2] as u32,
+            args[3] as u32,
+            args[4] as u32,
+        ),
         SyscallNo::OPEN => sys_open(
             args[0] as i32,
             args[1] as *const u8,
@@ -112,7 +134,9 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
             args[1],
             args[2] as *const TimeSpec,
             args[3] as *const usize,
+            args[4],
         ),
+        SyscallNo::SIGNALFD4 => sys_signalfd4(args[0] as i32, args[1] as *const usize, args[2], args[3] as u32),
         SyscallNo::READLINKAT => sys_readlinkat(
             args[0] as i32,
             args[1] as *const u8,
@@ -121,6 +145,17 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         ),
         SyscallNo::FSTATAT => sys_fstatat(args[0] as i32, args[1] as *const u8, args[2] as *mut Kstat),
         SyscallNo::FSTAT => sys_fstat(args[0], args[1] as *mut Kstat),
+        SyscallNo::FTRUNCATE => sys_ftruncate(args[0], args[1]),
+        SyscallNo::SYNC => sys_sync(),
+        SyscallNo::FSYNC | SyscallNo::FDATASYNC => sys_fsync(args[0]),
+        SyscallNo::TIMERFD_CREATE => sys_timerfd_create(args[0], args[1] as u32),
+        SyscallNo::TIMERFD_SETTIME => sys_timerfd_settime(
+            args[0],
+            args[1] as u32,
+            args[2] as *const ITimerSpec,
+            args[3] as *mut ITimerSpec,
+        ),
+        SyscallNo::TIMERFD_GETTIME => sys_timerfd_gettime(args[0], args[1] as *mut ITimerSpec),
         SyscallNo::UTIMENSAT => sys_utimensat(
             args[0] as i32,
             args[1] as *const u8,
@@ -163,6 +198,8 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SyscallNo::SIGRETURN => sys_sigreturn(),
         SyscallNo::TIMES => sys_times(args[0] as *mut TMS),
         SyscallNo::UNAME => sys_uname(args[0] as *mut UtsName),
+        SyscallNo::GETRLIMIT => sys_getrlimit(args[0] as i32, args[1] as *mut RLimit),
+        SyscallNo::SETRLIMIT => sys_setrlimit(args[0] as i32, args[1] as *const RLimit),
         SyscallNo::GETRUSAGE => sys_getrusage(args[0] as i32, args[1] as *mut TimeVal),
         SyscallNo::UMASK => sys_umask(args[0] as i32),
         SyscallNo::GET_TIME_OF_DAY => sys_get_time_of_day(args[0] as *mut TimeVal),
@@ -174,6 +211,18 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SyscallNo::GETEGID => sys_getegid(),
         SyscallNo::GETTID => sys_gettid(),
         SyscallNo::SYSINFO => sys_sysinfo(args[0] as *mut SysInfo),
+        SyscallNo::MSGGET => sys_msgget(args[0] as i32, args[1] as i32),
+        SyscallNo::MSGCTL => sys_msgctl(args[0], args[1] as i32, args[2] as *mut MsqidDs),
+        SyscallNo::MSGRCV => sys_msgrcv(args[0], args[1], args[2], args[3] as isize, args[4] as i32),
+        SyscallNo::MSGSND => sys_msgsnd(args[0], args[1], args[2], args[3] as i32),
+        SyscallNo::SEMGET => sys_semget(args[0] as i32, args[1] as i32, args[2] as i32),
+        SyscallNo::SEMCTL => sys_semctl(args[0], args[1], args[2] as i32, args[3]),
+        SyscallNo::SEMTIMEDOP => sys_semtimedop(args[0], args[1] as *const SemBuf, args[2], args[3] as *const TimeSpec),
+        SyscallNo::SEMOP => sys_semop(args[0], args[1] as *const SemBuf, args[2]),
+        SyscallNo::SHMGET => sys_shmget(args[0] as i32, args[1], args[2] as i32),
+        SyscallNo::SHMCTL => sys_shmctl(args[0], args[1] as i32, args[2] as *mut ShmidDs),
+        SyscallNo::SHMAT => sys_shmat(args[0], args[1], args[2] as i32),
+        SyscallNo::SHMDT => sys_shmdt(args[0]),
         SyscallNo::SOCKET => sys_socket(args[0], args[1], args[2]),
         SyscallNo::SENDTO => sys_sendto(
             args[0],
@@ -194,8 +243,18 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SyscallNo::BIND => sys_bind(args[0], args[1] as *const u8, args[2]),
         SyscallNo::LISTEN => sys_listen(args[0], args[1]),
         SyscallNo::CONNECT => sys_connect(args[0], args[1] as *const u8, args[2]),
-        SyscallNo::ACCEPT => sys_accept4(args[0], args[1] as *mut u8, args[2] as *mut u32, args[3] as i32),
+        SyscallNo::ACCEPT => sys_accept4(args[0], args[1] as *mut u8, args[2] as *mut u32, 0),
         SyscallNo::ACCEPT4 => sys_accept4(args[0], args[1] as *mut u8, args[2] as *mut u32, args[3] as i32),
+        SyscallNo::SOCKETPAIR => sys_socketpair(args[0], args[1], args[2], args[3] as *mut i32),
+        SyscallNo::GETSOCKNAME => sys_getsockname(args[0], args[1] as *mut u8, args[2] as *mut u32),
+        SyscallNo::GETPEERNAME => sys_getpeername(args[0], args[1] as *mut u8, args[2] as *mut u32),
+        SyscallNo::SHUTDOWN => sys_shutdown(args[0], args[1]),
+        SyscallNo::SETSOCKOPT => sys_setsockopt(args[0], args[1] as i32, args[2] as i32, args[3] as *const u8, args[4]),
+        SyscallNo::GETSOCKOPT => sys_getsockopt(args[0], args[1] as i32, args[2] as i32, args[3] as *mut u8, args[4] as *mut u32),
+        SyscallNo::SENDMSG => sys_sendmsg(args[0], args[1] as *const MsgHdr, args[2] as u32),
+        SyscallNo::RECVMSG => sys_recvmsg(args[0], args[1] as *mut MsgHdr, args[2] as u32),
+        SyscallNo::SENDMMSG => sys_sendmmsg(args[0], args[1] as *mut MMsgHdr, args[2], args[3] as u32),
+        SyscallNo::RECVMMSG => sys_recvmmsg(args[0], args[1] as *mut MMsgHdr, args[2], args[3] as u32, args[4] as *const TimeSpec),
         SyscallNo::BRK => sys_brk(args[0]),
         SyscallNo::MUNMAP => sys_munmap(args[0], args[1]),
         SyscallNo::CLONE => sys_clone(args[0], args[1], args[2], args[3], args[4]),
@@ -240,7 +299,6 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         //SyscallNo::MPROTECT => 0,
         SyscallNo::SIGTIMEDWAIT => Ok(0),
         SyscallNo::MEMBARRIER => Ok(0),
-        SyscallNo::FSYNC => Ok(0),
         _ => {
             //_ => panic!("Unsupported syscall id = {:#?}()", syscall_id, syscall_id as usize);
             warn!(
@@ -1,20 +1,22 @@
 //! 与进程相关的系统调用
 
 use super::{
-    resolve_clone_flags_and_signal, ErrorNo, MMAPFlags, RLimit, SysResult, UtsName, WaitFlags,
-    MMAPPROT, MSyncFlags, RLIMIT_AS, RLIMIT_NOFILE, RLIMIT_STACK, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK,
+    resolve_clone_flags_and_signal, ErrorNo, MMAPFlags, SysResult, UtsName, WaitFlags,
+    MMAPPROT, MSyncFlags, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK,
 };
 use crate::{
-    constants::{SIGSET_SIZE_IN_BYTE, USER_STACK_SIZE, USER_VIRT_ADDR_LIMIT, USE_MSYNC},
-    file::{SeekFrom, BackEndFile},
+    constants::{SIGSET_SIZE_IN_BYTE, USER_STACK_SIZE, USE_MSYNC},
+    file::{SeekFrom, BackEndFile, OpenFlags, ShmFile, SignalFd},
     signal::{send_signal, Bitset, SigAction, SignalNo},
-    memory::{page_offset, align_up, align_down},
+    memory::{page_offset, page_count, align_up, align_down, new_shared_frames, PmAreaShared},
     task::{
-        exec_new_task, exit_current_task, get_current_task, push_task_to_scheduler, signal_return,
-        suspend_current_task,
+        exec_new_task, exit_current_task, get_current_task, get_process_count, get_task_from_pid,
+        push_task_to_scheduler, signal_return, suspend_current_task, CloneFlags, RLimit,
+        TaskControlBlock, RLIMIT_NOFILE, RLIMIT_NPROC, RLIMIT_STACK,
     },
     utils::{raw_ptr_to_string, str_ptr_array_to_vec_string},
 };
+use alloc::sync::Arc;
 use core::mem::size_of;
 
 /// 进程退出，并提供 exit_code 供 wait 等 syscall 拿取
@@ -56,11 +58,17 @@ pub fn sys_gettid() -> SysResult {
 /// - 如输入 brk 为 0 ，则返回堆顶地址
 /// - 否则，尝试修改堆顶为 brk，成功时返回0，失败时返回-1。
 pub fn sys_brk(brk: usize) -> SysResult {
+    let task = get_current_task().unwrap();
+    let heap_top = task.get_user_heap_top();
     if brk == 0 {
-        Ok(get_current_task().unwrap().get_user_heap_top())
+        Ok(heap_top)
     } else {
         //info!("user try to move brk at {:x}", brk);
-        Ok(get_current_task().unwrap().set_user_heap_top(brk)) // 如果设置不合法，会保持不变并返回原来的堆顶
+        // 扩展堆时需要检查 RLIMIT_AS 和 RLIMIT_DATA
+        if brk > heap_top && !task.check_vm_growth(align_up(brk) - align_up(heap_top), true) {
+            return Ok(heap_top);
+        }
+        Ok(task.set_user_heap_top(brk)) // 如果设置不合法，会保持不变并返回原来的堆顶
     }
     //Err(ErrorNo::ENOMEM)
 }
@@ -84,6 +92,12 @@ pub fn sys_clone(
         Some(user_stack)
     };
     let old_task = get_current_task().unwrap();
+    // 创建新进程时检查 RLIMIT_NPROC
+    if !clone_flags.contains(CloneFlags::CLONE_THREAD)
+        && get_process_count() as u64 >= old_task.rlimits.lock().get_cur(RLIMIT_NPROC)
+    {
+        return Err(ErrorNo::EAGAIN);
+    }
     // 生成新任务。注意 from_clone 方法内部已经把对用户的返回值设成了0
     // 第二个参数指定了子任务退出时是否发送 SIGCHLD
     let new_task = old_task.from_clone(
@@ -278,6 +292,11 @@ pub fn sys_mmap(
     // 是否可以放在任意位置
     let anywhere = start == 0 || !flags.contains(MMAPFlags::MAP_FIXED);
     let task = get_current_task().unwrap();
+    // 检查 RLIMIT_AS，可写的私有映射还要计入 RLIMIT_DATA
+    let is_data = prot.contains(MMAPPROT::PROT_WRITE) && !flags.contains(MMAPFlags::MAP_SHARED);
+    if !task.check_vm_growth(len, is_data) {
+        return Err(ErrorNo::ENOMEM);
+    }
     let tcb_inner = task.inner.lock();
 
     //不实际映射到文件
@@ -285,11 +304,31 @@ pub fn sys_mmap(
         drop(tcb_inner);
         // 根据linux规范需要 fd 设为 -1 且 offset 设为 0
         if fd == -1 && offset == 0 {
-            if let Some(start) = task.mmap(start, start + len, prot.into(), None, anywhere) {
+            let start = if flags.contains(MMAPFlags::MAP_SHARED) {
+                // 共享的匿名映射使用单独的一组共享页帧，fork 出的子进程仍然映射同一组页帧
+                let pages = page_count(len);
+                PmAreaShared::new(new_shared_frames(pages), 0, pages)
+                    .ok()
+                    .and_then(|pma| task.mmap_shared(start, start + len, prot.into(), pma, anywhere))
+            } else {
+                task.mmap(start, start + len, prot.into(), None, anywhere)
+            };
+            if let Some(start) = start {
                 return Ok(start);
             }
         }
     } else if let Ok(file) = task.fd_manager.lock().get_file(fd as usize) {
+        // /dev/shm 中的文件以 MAP_SHARED 映射时，直接映射文件的页帧
+        if flags.contains(MMAPFlags::MAP_SHARED) && page_offset(offset) == 0 {
+            if let Some(shm_file) = (*file).as_any().downcast_ref::<ShmFile>() {
+                drop(tcb_inner);
+                let frames = shm_file.frames_for_mmap(offset, len);
+                return PmAreaShared::new(frames, page_count(offset), page_count(len))
+                    .ok()
+                    .and_then(|pma| task.mmap_shared(start, start + len, prot.into(), pma, anywhere))
+                    .ok_or(ErrorNo::EINVAL);
+            }
+        }
         //确认可以seek才获取文件，否则后续 lazy alloc 时不好处理
         if let Some(_off) = file.seek(SeekFrom::Start(offset as u64)) {
             // file 在从 fd 中拿的时候已经是 clone 了，所以这里可以直接传给 backend
@@ -479,6 +518,34 @@ pub fn sys_sigprocmask(
     Ok(0)
 }
 
+/// 创建一个接收 mask 中信号的 signalfd，返回它的 fd。
+///
+/// 如果 fd 不为 -1，则它必须是一个 signalfd，这时只修改它接收的信号集合并返回 fd
+pub fn sys_signalfd4(fd: i32, mask: *const usize, sizemask: usize, flags: u32) -> SysResult {
+    if sizemask != SIGSET_SIZE_IN_BYTE
+        || flags & !(OpenFlags::NON_BLOCK.bits() | OpenFlags::CLOEXEC.bits()) != 0
+    {
+        return Err(ErrorNo::EINVAL);
+    }
+    let task = get_current_task().unwrap();
+    if task.vm.lock().manually_alloc_type(mask).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let mask = unsafe { *mask };
+    let mut fd_manager = task.fd_manager.lock();
+    if fd == -1 {
+        return fd_manager.push(Arc::new(SignalFd::new(mask, flags))).map_err(|_| ErrorNo::EMFILE);
+    }
+    let file = fd_manager.get_file(fd as usize).map_err(|_| ErrorNo::EBADF)?;
+    match (*file).as_any().downcast_ref::<SignalFd>() {
+        Some(signal_fd) => {
+            signal_fd.set_mask(mask);
+            Ok(fd as usize)
+        }
+        None => Err(ErrorNo::EINVAL),
+    }
+}
+
 /// 改变当前进程的信号处理函数。
 ///
 /// 如果 action 为 0，则不设置；如果 old_action 为 0，则不存入。
@@ -556,9 +623,11 @@ pub fn sys_set_tid_address(addr: usize) -> SysResult {
     sys_gettid()
 }
 
-/// 修改一些资源的限制
+/// 获取或修改进程的资源限制
 ///
-/// pid 设为0时，表示应用于自己
+/// - pid 设为0时，表示应用于自己，否则应用于 pid 对应的进程
+/// - old_limit 非空时，写入修改前的限制
+/// - new_limit 非空时，按软/硬上限的规则设置新的限制。目前所有进程都是 root，所以总是有权限提高硬上限
 pub fn sys_prlimt64(
     pid: usize,
     resource: i32,
@@ -566,48 +635,57 @@ pub fn sys_prlimt64(
     old_limit: *mut RLimit,
 ) -> SysResult {
     info!("pid {} resource {}", pid, resource);
-    if pid == 0 {
-        let task = get_current_task().unwrap();
-        let mut fd_manger = task.fd_manager.lock();
-
-        match resource {
-            RLIMIT_STACK => {
-                if old_limit as usize != 0 {
-                    unsafe {
-                        *old_limit = RLimit {
-                            rlim_cur: USER_STACK_SIZE as u64,
-                            rlim_max: USER_STACK_SIZE as u64,
-                        };
-                    }
-                }
-            }
-            RLIMIT_NOFILE => {
-                if old_limit as usize != 0 {
-                    let limit = fd_manger.get_limit();
-                    unsafe {
-                        *old_limit = RLimit {
-                            rlim_cur: limit as u64,
-                            rlim_max: limit as u64,
-                        };
-                    }
-                }
-                if new_limit as usize != 0 {
-                    let new_limit = unsafe { (*new_limit).rlim_cur };
-                    fd_manger.modify_limit(new_limit as usize);
-                }
-            }
-            RLIMIT_AS => {
-                if old_limit as usize != 0 {
-                    unsafe {
-                        *old_limit = RLimit {
-                            rlim_cur: USER_VIRT_ADDR_LIMIT as u64,
-                            rlim_max: USER_VIRT_ADDR_LIMIT as u64,
-                        };
-                    }
-                }
-            }
-            _ => {}
+    let current = get_current_task().unwrap();
+    {
+        let mut vm = current.vm.lock();
+        if new_limit as usize != 0 && vm.manually_alloc_type(new_limit).is_err() {
+            return Err(ErrorNo::EFAULT);
+        }
+        if old_limit as usize != 0 && vm.manually_alloc_type(old_limit).is_err() {
+            return Err(ErrorNo::EFAULT);
+        }
+    }
+    let task = if pid == 0 || pid == current.get_pid_num() {
+        current
+    } else {
+        get_task_from_pid(pid).ok_or(ErrorNo::ESRCH)?
+    };
+    let mut rlimits = task.rlimits.lock();
+    let limit_before = rlimits.get(resource).ok_or(ErrorNo::EINVAL)?;
+    // new_limit 和 old_limit 可能是同一个地址，所以要先读后写
+    if new_limit as usize != 0 {
+        let limit = unsafe { *new_limit };
+        rlimits.set(resource, limit, sys_geteuid()? == 0)?;
+        drop(rlimits);
+        apply_new_rlimit(&task, resource, limit.rlim_cur);
+    }
+    if old_limit as usize != 0 {
+        unsafe {
+            *old_limit = limit_before;
         }
     }
     Ok(0)
 }
+
+/// 获取当前进程的资源限制
+pub fn sys_getrlimit(resource: i32, limit: *mut RLimit) -> SysResult {
+    sys_prlimt64(0, resource, core::ptr::null(), limit)
+}
+
+/// 修改当前进程的资源限制
+pub fn sys_setrlimit(resource: i32, limit: *const RLimit) -> SysResult {
+    sys_prlimt64(0, resource, limit, core::ptr::null_mut())
+}
+
+/// 资源限制被修改后，同步到需要它的模块中
+fn apply_new_rlimit(task: &Arc<TaskControlBlock>, resource: i32, rlim_cur: u64) {
+    match resource {
+        // 栈至少要能放下初始的大小
+        RLIMIT_STACK => task
+            .vm
+            .lock()
+            .set_stack_limit((rlim_cur as usize).max(USER_STACK_SIZE)),
+        RLIMIT_NOFILE => task.fd_manager.lock().modify_limit(rlim_cur as usize),
+        _ => {}
+    }
+}
@@ -1,18 +1,20 @@
 //! 处理 pselect 相关的结构
 
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::mem::size_of;
 use lock::MutexGuard;
 
-use crate::constants::FD_LIMIT_HARD;
-use crate::file::{FdManager, File, PollEvents, EpollFile, EpollEvent, EpollEventType, EpollCtl};
+use crate::constants::{FD_LIMIT_HARD, SIGSET_SIZE_IN_BYTE};
+use crate::file::{FdManager, File, OpenFlags, PollEvents, EpollFile, EpollEvent, EpollEventType, EpollCtl};
 use crate::memory::MemorySet;
-use crate::signal::ShadowBitset;
+use crate::signal::{Bitset, ShadowBitset};
 use crate::task::{get_current_task, suspend_current_task};
-use crate::timer::{get_time, get_time_ms, TimeSpec};
+use crate::timer::{get_time_us, TimeSpec};
 
-use super::{ErrorNo, SysResult, PollFd};
+use super::futex::FutexWaiter;
+use super::{set_waiter_for_thread, wake_thread, ErrorNo, SysResult, PollFd};
 
 /// 获取 fd 指向文件的集合，
 /// 每个文件存在 arc 里，每个 fd 值存在一个 usize 里，然后在用户地址原地清空建立一个 ShadowBitset。
@@ -52,14 +54,89 @@ fn init_fd_sets(
     }
 }
 
+/// 反复调用 check，直到它返回 Some、超时或者被信号打断。expire_us 是超时的时刻(微秒)，为 usize::MAX 时不会超时。
+///
+/// 每次 check 失败后，线程登记到 files 的等待队列上并睡眠，直到某个文件的状态变化、超时或者收到信号时才被唤醒。
+/// 如果有文件不会主动唤醒线程，那么只能让出 CPU，下次被调度时再检查。
+///
+/// 返回 Ok(Some(..)) 表示 check 成功，Ok(None) 表示超时，Err(EINTR) 表示被信号打断
+fn wait_for_files<T>(
+    files: &[Arc<dyn File>],
+    expire_us: usize,
+    mut check: impl FnMut() -> Option<T>,
+) -> Result<Option<T>, ErrorNo> {
+    let task = get_current_task().unwrap();
+    let tid = task.get_tid_num();
+    let result = loop {
+        // 先登记再检查，这样检查之后、睡眠之前发生的变化也能唤醒线程
+        set_waiter_for_thread(tid, Box::new(FutexWaiter::new(Some(expire_us))));
+        let mut all_registered = true;
+        for file in files {
+            all_registered &= file.register_waiter(tid);
+        }
+        if let Some(ret) = check() {
+            break Ok(Some(ret));
+        }
+        if task.signal_receivers.lock().has_pending_signal() {
+            break Err(ErrorNo::EINTR);
+        }
+        if get_time_us() >= expire_us {
+            break Ok(None);
+        }
+        if !all_registered {
+            // 有文件不会唤醒线程，只能下次被调度时再检查
+            wake_thread(tid);
+        }
+        suspend_current_task();
+    };
+    for file in files {
+        file.unregister_waiter(tid);
+    }
+    // 清除 WAITING_BOARD 上还没被唤醒的 waiter，否则线程之后会被它阻塞
+    wake_thread(tid);
+    result
+}
 
+/// 读取用户给出的信号掩码，临时替换当前线程的掩码。sigmask 为空时不替换。
+///
+/// 原来的掩码在返回用户态处理信号时恢复，见 SignalReceivers::set_temporary_mask
+fn set_temporary_sigmask(sigmask: *const usize, sigsetsize: usize) -> Result<(), ErrorNo> {
+    if sigmask.is_null() {
+        return Ok(());
+    }
+    if sigsetsize != SIGSET_SIZE_IN_BYTE {
+        return Err(ErrorNo::EINVAL);
+    }
+    let task = get_current_task().unwrap();
+    if task.vm.lock().manually_alloc_type(sigmask).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let mask = Bitset::new(unsafe { *sigmask });
+    task.signal_receivers.lock().set_temporary_mask(mask);
+    Ok(())
+}
+
+/// 计算超时的时刻(微秒)。timeout 为空时永不超时
+fn get_expire_us(timeout: *const TimeSpec, task_vm: &mut MutexGuard<MemorySet>) -> Result<usize, ErrorNo> {
+    if timeout.is_null() {
+        Ok(usize::MAX)
+    } else if task_vm.manually_alloc_type(timeout).is_err() {
+        Err(ErrorNo::EFAULT) // 无效地址
+    } else {
+        Ok(get_time_us().saturating_add(unsafe { (*timeout).to_us() }))
+    }
+}
+
+/// 等待 fd 集合中的文件可读、可写或者出现异常，返回满足条件的 fd 个数。
+///
+/// sigmask 指向 { const sigset_t *ss; size_t ss_len; }，等待期间把信号掩码换成 ss
 pub fn sys_pselect6(
     nfds: usize,
     readfds: *mut usize,
     writefds: *mut usize,
     exceptfds: *mut usize,
     timeout: *const TimeSpec, // pselect 不会更新 timeout 的值，而 select 会
-    _sigmask: *const usize,
+    sigmask: *const usize,
 ) -> SysResult {
     let task = get_current_task().unwrap();
     let mut task_vm = task.vm.lock();
@@ -72,29 +149,31 @@ pub fn sys_pselect6(
     let (efile, efd, eset) = init_fd_sets(exceptfds, nfds, &mut task_vm, &fd_manager)?;
     // 过期时间
     // 注意 pselect 不会修改用户空间中的 timeout，所以需要内核自己记录
-    // 这里用**时钟周期数**来记录，足够精确的同时 usize 也能存下。实际用微秒或者纳秒应该也没问题。
-    let expire_time = if timeout as usize != 0 {
-        if task_vm.manually_alloc_type(timeout).is_err() {
-            return Err(ErrorNo::EFAULT); // 无效地址
-        }
-        get_time() + unsafe { (*timeout).get_ticks() }
+    let expire_us = get_expire_us(timeout, &mut task_vm)?;
+    let (ss, ss_len) = if sigmask.is_null() {
+        (core::ptr::null(), 0)
+    } else if task_vm.manually_alloc_type(sigmask as *const [usize; 2]).is_err() {
+        return Err(ErrorNo::EFAULT);
     } else {
-        usize::MAX // 没有过期时间
+        let arg = unsafe { *(sigmask as *const [usize; 2]) };
+        (arg[0] as *const usize, arg[1])
     };
-    // 这里暂时不考虑 sigmask 的问题
 
     info!(
         "pselect {nfds} {:#?} {:#?} {:#?} {}(now {})",
         rfd,
         wfd,
         efd,
-        expire_time,
-        get_time()
+        expire_us,
+        get_time_us()
     );
 
     drop(task_vm); // select 的时间可能很长，之后不用 vm 了就及时释放
     drop(fd_manager); // fd_manager 同理
-    loop {
+    set_temporary_sigmask(ss, ss_len)?;
+    // 所有被监视的文件，等待时要登记到它们上面
+    let files: Vec<Arc<dyn File>> = rfile.iter().chain(wfile.iter()).chain(efile.iter()).cloned().collect();
+    let ret = wait_for_files(&files, expire_us, || {
         // 已设置的 fd
         let mut set: usize = 0;
         if rset.is_valid() {
@@ -130,30 +209,25 @@ pub fn sys_pselect6(
                 }
             }
         }
-        if set > 0 {
-            // 如果找到满足条件的 fd，则返回找到的 fd 数量
-            return Ok(set);
-        }
-        // 否则暂时 block 住
-        suspend_current_task();
-        if get_time() > expire_time {
-            // 检查超时
-            return Ok(0);
-        }
-    }
+        // 如果找到满足条件的 fd，则返回找到的 fd 数量
+        if set > 0 { Some(set) } else { None }
+    })?;
+    // 超时返回 0
+    Ok(ret.unwrap_or(0))
 }
 
+/// 等待 ufds 中的文件发生指定的事件，返回发生了事件的 fd 个数。等待期间把信号掩码换成 sigmask
 pub fn sys_ppoll(
     ufds: *mut PollFd,
     nfds: usize,
     timeout: *const TimeSpec, // ppoll 不会更新 timeout 的值，而 poll 会
-    _sigmask: *const usize
+    sigmask: *const usize,
+    sigsetsize: usize,
 ) -> SysResult {
-    //if nfds > 0 { return Ok(1); }
     let task = get_current_task().unwrap();
     let mut task_vm = task.vm.lock();
     debug!("ppoll ufds at {:x} nfds {} timeout at {:x}", ufds as usize, nfds, timeout as usize);
-    if task_vm.manually_alloc_user_str(ufds as *const u8, nfds * size_of::<PollFd>()).is_err() {
+    if nfds > 0 && task_vm.manually_alloc_user_str(ufds as *const u8, nfds * size_of::<PollFd>()).is_err() {
         return Err(ErrorNo::EFAULT); // 无效地址
     }
     let mut fds: Vec<PollFd> = Vec::new();
@@ -161,155 +235,140 @@ pub fn sys_ppoll(
         unsafe { fds.push(*ufds.add(i)); }
     }
     // 过期时间
-    // 这里用**时钟周期数**来记录，足够精确的同时 usize 也能存下。实际用微秒或者纳秒应该也没问题。
-    let expire_time = if timeout as usize != 0 {
-        if task_vm.manually_alloc_type(timeout).is_err() {
-            return Err(ErrorNo::EFAULT); // 无效地址
-        }
-        get_time() + unsafe { (*timeout).get_ticks() }
-    } else {
-        usize::MAX // 没有过期时间
-    };
-    drop(task_vm); // select 的时间可能很长，之后不用 vm 了就及时释放
-    loop {
-        let fd_manager = task.fd_manager.lock();
+    let expire_us = get_expire_us(timeout, &mut task_vm)?;
+    drop(task_vm); // poll 的时间可能很长，之后不用 vm 了就及时释放
+    // fd 为负数时忽略这一项，fd 不存在时返回 INVAL
+    let fd_manager = task.fd_manager.lock();
+    let poll_files: Vec<Option<Arc<dyn File>>> = fds
+        .iter()
+        .map(|req_fd| if req_fd.fd < 0 { None } else { fd_manager.get_file(req_fd.fd as usize).ok() })
+        .collect();
+    drop(fd_manager);
+    set_temporary_sigmask(sigmask, sigsetsize)?;
+    let files: Vec<Arc<dyn File>> = poll_files.iter().flatten().cloned().collect();
+    let ret = wait_for_files(&files, expire_us, || {
         // 已触发的 fd
         let mut set: usize = 0;
-        for req_fd in &mut fds {
-            if let Ok(file) = fd_manager.get_file(req_fd.fd as usize) {
-                req_fd.revents = file.poll(req_fd.events);
-                if !req_fd.revents.is_empty() {
-                    set += 1;
-                }
-            } else {
-                req_fd.revents = PollEvents::ERR;
+        for (req_fd, file) in fds.iter_mut().zip(poll_files.iter()) {
+            req_fd.revents = match file {
+                Some(file) => file.poll(req_fd.events),
+                None if req_fd.fd < 0 => PollEvents::empty(),
+                None => PollEvents::INVAL,
+            };
+            if !req_fd.revents.is_empty() {
                 set += 1;
             }
         }
-        if set > 0 {
-            // 如果找到满足条件的 fd，则返回找到的 fd 数量
-            for i in 0..fds.len() {
-                unsafe { *ufds.add(i) = fds[i]; }
-            }
-            return Ok(set);
-        }
-        // 否则暂时 block 住
-        if get_time() > expire_time {
-            // 检查超时
-            for i in 0..fds.len() {
-                unsafe { *ufds.add(i) = fds[i]; }
-            }
-            return Ok(0);
-        }
-        drop(fd_manager); // fd_manager 同理
-        suspend_current_task();
+        // 如果找到满足条件的 fd，则返回找到的 fd 数量
+        if set > 0 { Some(set) } else { None }
+    });
+    // 超时或者被打断时，也要写回每一项的 revents
+    for i in 0..fds.len() {
+        unsafe { *ufds.add(i) = fds[i]; }
     }
+    Ok(ret?.unwrap_or(0))
 }
 
-/// 创建一个 epoll 文件
-pub fn sys_epoll_create(_flags: usize) -> SysResult {
+/// 创建一个 epoll 文件。flags 只能是 0 或者 EPOLL_CLOEXEC
+pub fn sys_epoll_create1(flags: u32) -> SysResult {
     info!("epoll create");
+    // EPOLL_CLOEXEC 和 O_CLOEXEC 的值相同
+    if flags & !OpenFlags::CLOEXEC.bits() != 0 {
+        return Err(ErrorNo::EINVAL);
+    }
     let task = get_current_task().unwrap();
     let mut fd_manager = task.fd_manager.lock();
-    let epoll_file = EpollFile::new();
+    let epoll_file = EpollFile::new(OpenFlags::from_bits_truncate(flags));
     fd_manager.push(Arc::new(epoll_file)).map_err(|_| ErrorNo::EMFILE)
 }
 
+/// 在 epoll 中添加、修改或删除对 fd 的监控
 pub fn sys_epoll_ctl(epfd: i32, op: i32, fd: i32, event: *const EpollEvent) -> SysResult {
     info!("epoll ctl: epfd {epfd} op {op} fd {fd}");
     let task = get_current_task().unwrap();
     let mut task_vm = task.vm.lock();
     let fd_manager = task.fd_manager.lock();
-    let event = if task_vm.manually_alloc_type(event).is_err() {
+    let operator = EpollCtl::try_from(op).map_err(|_| ErrorNo::EINVAL)?; // 操作符不合法
+    // EPOLL_CTL_DEL 时 event 可以为空
+    let event = if operator == EpollCtl::DEL {
+        EpollEvent { events: EpollEventType::empty(), data: 0 }
+    } else if task_vm.manually_alloc_type(event).is_err() {
         return Err(ErrorNo::EFAULT); // 地址不合法
     } else {
         unsafe { *event }
     };
-    let operator = EpollCtl::try_from(op).map_err(|_| ErrorNo::EINVAL)?; // 操作符不合法
-    if let Some(epoll_file) = fd_manager
+    let epoll_file = fd_manager
         .get_file(epfd as usize)
-        .map(|file| file.get_epoll_fd())
-        .map_err(|_| ErrorNo::EBADF)? {
-        if fd_manager.get_file(fd as usize).is_err() {
-            return Err(ErrorNo::EBADF); // 错误的文件描述符
-        }
-        return epoll_file.epoll_ctl(operator, fd, event).map(|_| 0);
+        .map_err(|_| ErrorNo::EBADF)?
+        .get_epoll_fd()
+        .ok_or(ErrorNo::EINVAL)?; // epfd 不是 epoll
+    let file = fd_manager.get_file(fd as usize).map_err(|_| ErrorNo::EBADF)?;
+    // 不能监控自己
+    if fd == epfd {
+        return Err(ErrorNo::EINVAL);
     }
-    Err(ErrorNo::EBADF) // 错误的文件描述符
+    drop(fd_manager);
+    drop(task_vm);
+    epoll_file.epoll_ctl(operator, fd, &file, event).map(|_| 0)
 }
 
+/// 等待 epoll 中的事件，最多返回 maxevents 个，返回事件的个数。
+///
+/// timeout 为等待的毫秒数，为 -1 时一直等待，为 0 时立即返回
 pub fn sys_epoll_wait(epfd: i32, event: *mut EpollEvent, maxevents: i32, timeout: i32) -> SysResult {
     info!("epoll wait: epfd {epfd} event {event:?} maxevents {maxevents} timeout {timeout}");
+    if maxevents <= 0 {
+        return Err(ErrorNo::EINVAL);
+    }
     let task = get_current_task().unwrap();
-    let mut task_vm = task.vm.lock();
-    if task_vm.manually_alloc_type(event).is_err() {
+    let start = event as usize;
+    if task
+        .vm
+        .lock()
+        .manually_alloc_range(start, start + maxevents as usize * size_of::<EpollEvent>() - 1)
+        .is_err()
+    {
         return Err(ErrorNo::EFAULT); // 地址不合法
-    };
-    let fd_manager = task.fd_manager.lock();
-    let epoll_file = fd_manager
-        .get_file(epfd as usize)
-        .map(|file| file.get_epoll_fd())
-        .map_err(|_| ErrorNo::EBADF)?.unwrap();
-
-    //类似poll
-    let interest = &epoll_file.inner.lock().interest_list;
-    let mut epolls: Vec<EpollEvent> = Vec::new();
-    for (fd, evt) in interest {
-        let mut nevt = *evt;
-        if *fd as u64 != nevt.data {
-            warn!("fd: {} is not in Event: {:?}", fd, evt);
-            nevt.data = *fd as u64;
-        }
-        epolls.push(nevt);
     }
-    let expire_time = if timeout >= 0 {
-        get_time_ms() + timeout as usize
+    let epoll_file = task
+        .fd_manager
+        .lock()
+        .get_file(epfd as usize)
+        .map_err(|_| ErrorNo::EBADF)?;
+    let epoll = epoll_file.get_epoll_fd().ok_or(ErrorNo::EINVAL)?; // epfd 不是 epoll
+    let expire_us = if timeout >= 0 {
+        get_time_us() + timeout as usize * 1000
     } else {
         usize::MAX // 没有过期时间
     };
-    drop(fd_manager);
-    drop(task_vm); // select 的时间可能很长，之后不用 vm 了就及时释放
-
-    loop {
-        let fd_manager = task.fd_manager.lock();
-        // 已触发的 fd
-        let mut set: usize = 0;
-        for req_fd in &epolls {
-            if let Ok(file) = fd_manager.get_file(req_fd.data as usize) {
-                let revents = file.poll(PollEvents::from_bits_truncate(req_fd.events.bits() as u16));
-                if !revents.is_empty() {
-                    info!("Epoll found fd {} revent {:?}", req_fd.data, revents);
-                    // 回写epollevent, 
-                    unsafe {
-                        *event.add(set) = *req_fd;
-                        (*event.add(set)).events = EpollEventType::from_bits_truncate(revents.bits() as u32);
-                    }
-                    set += 1;
-                }
-            } else {
-                warn!("epoll can not get fd: {}", req_fd.data);
-                //let revents = PollEvents::ERR;
-                unsafe {
-                    *event.add(set) = *req_fd;
-                    (*event.add(set)).events = EpollEventType::EPOLLERR;
-                }
-                set += 1;
-            }
-        }
-        if set > 0 {
-            unsafe {
-                info!("Epoll ret: {:?}", *event);
-            }
+    // 直到有事件、超时或者收到信号
+    let events = wait_for_files(&[epoll_file], expire_us, || {
+        let events = epoll.take_events(maxevents as usize);
+        if events.is_empty() { None } else { Some(events) }
+    })?;
+    match events {
+        Some(events) => {
+            info!("Epoll ret: {:?}", events);
+            unsafe { core::slice::from_raw_parts_mut(event, events.len()) }.copy_from_slice(&events);
             // 正常返回响应了事件的fd个数
-            return Ok(set);
+            Ok(events.len())
         }
-        // 否则暂时 block 住
-        if get_time_ms() > expire_time {
-            // 超时返回0
-            return Ok(0);
-        }
-        drop(fd_manager); // fd_manager 同理
-        suspend_current_task();
+        // 超时返回0
+        None => Ok(0),
     }
 }
 
+/// 同 sys_epoll_wait，但等待期间把信号掩码替换为 sigmask。sigmask 为空时不替换。
+///
+/// 原来的掩码在返回用户态处理信号时才恢复，所以打断等待的信号仍然会被处理
+pub fn sys_epoll_pwait(
+    epfd: i32,
+    event: *mut EpollEvent,
+    maxevents: i32,
+    timeout: i32,
+    sigmask: *const usize,
+    sigsetsize: usize,
+) -> SysResult {
+    set_temporary_sigmask(sigmask, sigsetsize)?;
+    sys_epoll_wait(epfd, event, maxevents, timeout)
+}
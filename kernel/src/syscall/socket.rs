@@ -1,12 +1,191 @@
 //! 关于 socket 的 syscall
 
-use super::{ErrorNo, SysResult};
-use crate::task::suspend_current_task;
+use super::{
+    CMsgHdr, ErrorNo, IfConf, IfFlags, IfReq, IoVec, Linger, MMsgHdr, MsgFlags, MsgHdr, RtEntry, SysResult,
+    SCM_CREDENTIALS, SCM_RIGHTS, SIOCADDRT, SIOCDELRT, SIOCGIFADDR, SIOCGIFCONF, SIOCGIFFLAGS, SIOCGIFHWADDR,
+    SIOCGIFINDEX, SIOCGIFMTU, SIOCGIFNETMASK, SIOCSIFADDR, SIOCSIFFLAGS, SIOCSIFNETMASK, SOL_SOCKET, SO_BROADCAST,
+    SO_DOMAIN, SO_ERROR, SO_KEEPALIVE, SO_LINGER, SO_RCVBUF, SO_RCVTIMEO, SO_REUSEADDR, SO_REUSEPORT, SO_SNDBUF,
+    SO_SNDTIMEO, SO_TYPE, IPPROTO_IPV6, IPPROTO_TCP, IPV6_V6ONLY, TCP_NODELAY,
+};
+use crate::task::TaskControlBlock;
 use crate::file::socket::*;
-use crate::{file::{Socket, OpenFlags}, task::get_current_task};
+use crate::memory::MemorySet;
+use crate::timer::{get_time_us, TimeSpec, TimeVal};
+use crate::{file::{File, OpenFlags}, task::get_current_task};
 use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
 use core::mem::size_of;
 
+/// connect 时表示取消连接的地址族
+const AF_UNSPEC: u16 = 0;
+/// sendmmsg / recvmmsg 一次最多处理的消息数，即 UIO_MAXIOV
+const MAX_MMSG_COUNT: usize = 1024;
+/// 本地回环和网卡的 MTU
+const LOOPBACK_MTU: i32 = 65536;
+const ETHER_MTU: i32 = 1500;
+/// SIOCGIFHWADDR 返回的硬件地址类型，即 ARPHRD_ETHER 和 ARPHRD_LOOPBACK
+const ARPHRD_ETHER: u16 = 1;
+const ARPHRD_LOOPBACK: u16 = 772;
+
+/// 如果文件是 unix socket，则返回它
+fn as_unix_socket(file: &Arc<dyn File>) -> Option<&UnixSocket> {
+    file.as_any().downcast_ref::<UnixSocket>()
+}
+
+/// 如果文件是 UDP socket，则返回它
+fn as_udp_socket(file: &Arc<dyn File>) -> Option<&UdpSocket> {
+    file.as_any().downcast_ref::<UdpSocket>()
+}
+
+/// 如果文件是 TCP socket，则返回它
+fn as_tcp_socket(file: &Arc<dyn File>) -> Option<&TcpSocket> {
+    file.as_any().downcast_ref::<TcpSocket>()
+}
+
+/// 获取 socket 的选项。file 必须是 socket
+fn socket_options(file: &Arc<dyn File>) -> SocketOptions {
+    if let Some(sock) = as_unix_socket(file) {
+        sock.options()
+    } else if let Some(sock) = as_tcp_socket(file) {
+        sock.options()
+    } else {
+        as_udp_socket(file).unwrap().options()
+    }
+}
+
+/// 设置 socket 的选项。file 必须是 socket
+fn set_socket_options(file: &Arc<dyn File>, options: SocketOptions) {
+    if let Some(sock) = as_unix_socket(file) {
+        sock.set_options(options);
+    } else if let Some(sock) = as_tcp_socket(file) {
+        sock.set_options(options);
+    } else {
+        as_udp_socket(file).unwrap().set_options(options);
+    }
+}
+
+/// 从 TCP socket 接收数据。带 MSG_WAITALL 时一直读到 buf 填满，除非读到文件尾、出错或者被信号打断，
+/// 此时返回已经读到的部分
+fn tcp_recv(sock: &TcpSocket, buf: &mut [u8], flags: MsgFlags) -> Result<usize, ErrorNo> {
+    let (peek, dont_wait) = (flags.contains(MsgFlags::PEEK), flags.contains(MsgFlags::DONTWAIT));
+    let mut len = sock.recv(buf, peek, dont_wait)?;
+    if flags.contains(MsgFlags::WAITALL) && !peek && !dont_wait {
+        while len > 0 && len < buf.len() {
+            match sock.recv(&mut buf[len..], false, false) {
+                Ok(read_len) if read_len > 0 => len += read_len,
+                _ => break,
+            }
+        }
+    }
+    Ok(len)
+}
+
+/// 从 unix socket 接收消息。流式 socket 带 MSG_WAITALL 时和 tcp_recv 一样等到 buf 填满，
+/// 中途收到的辅助数据合并到结果中
+fn unix_recv(sock: &UnixSocket, buf: &mut [u8], flags: MsgFlags) -> Result<UnixRecv, ErrorNo> {
+    let (peek, dont_wait) = (flags.contains(MsgFlags::PEEK), flags.contains(MsgFlags::DONTWAIT));
+    let mut ret = sock.recv(buf, peek, dont_wait)?;
+    if flags.contains(MsgFlags::WAITALL) && !peek && !dont_wait && sock.socket_type() == SocketType::SOCK_STREAM {
+        while ret.len > 0 && ret.len < buf.len() {
+            match sock.recv(&mut buf[ret.len..], false, false) {
+                Ok(more) if more.len > 0 => {
+                    ret.len += more.len;
+                    ret.msg_len += more.msg_len;
+                    ret.rights.extend(more.rights);
+                    ret.cred = ret.cred.or(more.cred);
+                }
+                _ => break,
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// 从 s_type 中取出 SOCK_NONBLOCK 和 SOCK_CLOEXEC，它们与 O_NONBLOCK 和 O_CLOEXEC 的值相同
+fn socket_flags(s_type: usize) -> OpenFlags {
+    OpenFlags::RDWR | (OpenFlags::from_bits_truncate(s_type as u32) & (OpenFlags::NON_BLOCK | OpenFlags::CLOEXEC))
+}
+
+/// 检查 unix socket 的类型
+fn unix_socket_type(s_type: usize) -> Result<SocketType, ErrorNo> {
+    let socket_type = SocketType::try_from(s_type & (SOCKET_TYPE_MASK as usize)).map_err(|_| ErrorNo::EINVAL)?;
+    match socket_type {
+        SocketType::SOCK_STREAM | SocketType::SOCK_DGRAM | SocketType::SOCK_SEQPACKET => Ok(socket_type),
+        _ => Err(ErrorNo::ESOCKTNOSUPPORT),
+    }
+}
+
+/// 读取用户给出的 unix socket 地址，相对路径基于当前目录
+fn read_unix_addr(task: &Arc<TaskControlBlock>, addr: *const u8, addr_len: usize) -> Result<UnixAddr, ErrorNo> {
+    if addr_len == 0 {
+        return Err(ErrorNo::EINVAL);
+    }
+    if task.vm.lock().manually_alloc_user_str(addr, addr_len).is_err() {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    let cwd = task.inner.lock().dir.clone();
+    unix_addr_resolution(addr, addr_len, &cwd).ok_or(ErrorNo::EINVAL)
+}
+
+/// 读取用户给出的 ip 地址，返回主机字节序的 ip 和端口。v6 表示是否是 AF_INET6 的 socket
+fn read_ip_addr(task: &Arc<TaskControlBlock>, addr: *const u8, addr_len: usize, v6: bool) -> Result<(InetIp, u16), ErrorNo> {
+    if addr_len < size_of::<u16>() {
+        return Err(ErrorNo::EINVAL);
+    }
+    if task.vm.lock().manually_alloc_user_str(addr, addr_len).is_err() {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    let family = if v6 { Domain::AF_INET6 } else { Domain::AF_INET };
+    if unsafe { *(addr as *const u16) } as usize != family as usize {
+        return Err(ErrorNo::EAFNOSUPPORT);
+    }
+    // 地址族正确但长度不够
+    inet_addr_resolution(addr, addr_len, v6).ok_or(ErrorNo::EINVAL)
+}
+
+/// 把 socket 地址写到用户的 addr，具体的格式由 write 决定，它的参数是 buffer 和 buffer 的长度，返回地址的实际长度。
+/// addr_len 处原本是 buffer 的长度，返回时改为地址的实际长度。addr 为空时不写
+fn write_sock_addr(
+    task: &Arc<TaskControlBlock>,
+    addr: *mut u8,
+    addr_len: *mut u32,
+    write: impl FnOnce(*mut u8, usize) -> usize,
+) -> Result<(), ErrorNo> {
+    if addr.is_null() || addr_len.is_null() {
+        return Ok(());
+    }
+    let mut task_vm = task.vm.lock();
+    if task_vm.manually_alloc_type(addr_len).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let buf_len = unsafe { *addr_len } as usize;
+    if buf_len > 0 && task_vm.manually_alloc_user_str(addr, buf_len).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    unsafe {
+        *addr_len = write(addr, buf_len) as u32;
+    }
+    Ok(())
+}
+
+/// 把 unix socket 地址写到用户的 addr，见 write_sock_addr
+fn write_unix_addr(task: &Arc<TaskControlBlock>, unix_addr: &UnixAddr, addr: *mut u8, addr_len: *mut u32) -> Result<(), ErrorNo> {
+    write_sock_addr(task, addr, addr_len, |buf, len| unix_addr_to_user(unix_addr, buf, len))
+}
+
+/// 把 ip 地址写到用户的 addr，v6 表示是否是 AF_INET6 的 socket，见 write_sock_addr
+fn write_ip_addr(
+    task: &Arc<TaskControlBlock>,
+    ip_addr: (InetIp, u16),
+    v6: bool,
+    addr: *mut u8,
+    addr_len: *mut u32,
+) -> Result<(), ErrorNo> {
+    write_sock_addr(task, addr, addr_len, |buf, len| inet_addr_to_user(ip_addr, v6, buf, len))
+}
+
 /// 创建一个 socket
 pub fn sys_socket(domain: usize, s_type: usize, protocol: usize) -> SysResult {
     let domain = match Domain::try_from(domain) {
@@ -27,80 +206,94 @@ pub fn sys_socket(domain: usize, s_type: usize, protocol: usize) -> SysResult {
         "SOCKET domain: {:?}, s_type: {:?}, protocol: {:x}",
         domain, socket_type, protocol
     );
+    let flags = socket_flags(s_type);
+    let socket: Arc<dyn File> = match (domain, socket_type) {
+        (Domain::AF_UNIX, _) => Arc::new(UnixSocket::new(unix_socket_type(s_type)?, flags)),
+        (Domain::AF_INET, SocketType::SOCK_STREAM) => Arc::new(TcpSocket::new(flags, false)),
+        (Domain::AF_INET, SocketType::SOCK_DGRAM) => Arc::new(UdpSocket::new(flags, false)),
+        (Domain::AF_INET6, SocketType::SOCK_STREAM) => Arc::new(TcpSocket::new(flags, true)),
+        (Domain::AF_INET6, SocketType::SOCK_DGRAM) => Arc::new(UdpSocket::new(flags, true)),
+        _ => return Err(ErrorNo::ESOCKTNOSUPPORT),
+    };
     let task = get_current_task().unwrap();
     let mut fd_manager = task.fd_manager.lock();
-    if let Ok(fd) = fd_manager.push(Arc::new(Socket::new(domain, socket_type, protocol))) {
-        Ok(fd)
+    fd_manager.push(socket).map_err(|_| ErrorNo::EMFILE)
+}
+
+/// 获取 fd 对应的 socket 文件。fd 不是 socket 时返回 ENOTSOCK
+fn get_socket(task: &Arc<TaskControlBlock>, fd: usize) -> Result<Arc<dyn File>, ErrorNo> {
+    let file = task.fd_manager.lock().get_file(fd).map_err(|_| ErrorNo::EBADF)?;
+    if as_unix_socket(&file).is_some() || as_tcp_socket(&file).is_some() || as_udp_socket(&file).is_some() {
+        Ok(file)
     } else {
-        Err(ErrorNo::EMFILE)
+        Err(ErrorNo::ENOTSOCK)
     }
 }
 
-/// 发送消息，目的地在 dest_addr 的信息中
+/// 发送消息，目的地在 dest_addr 的信息中。dest_addr 为空时发送给 connect 设置的地址
 pub fn sys_sendto(
     fd: usize,
     buf: *const u8,
     len: usize,
     flags: i32,
     dest_addr: *const u8,
-    _addr_len: usize,
+    addr_len: usize,
 ) -> SysResult {
     let task = get_current_task().unwrap();
-    let mut task_vm = task.vm.lock();
-    let fd_manager = task.fd_manager.lock();
-    if task_vm.manually_alloc_user_str(buf, len).is_err() {
+    let file = get_socket(&task, fd)?;
+    if len > 0 && task.vm.lock().manually_alloc_user_str(buf, len).is_err() {
         return Err(ErrorNo::EFAULT); // 地址不合法
     }
     let slice = unsafe { core::slice::from_raw_parts(buf, len) };
-    if let Ok(file) = fd_manager.get_file(fd) {
-        // 这里不考虑进程切换.                             dest_addr可能为0
-        if let Some(write_len) = file.sendto(slice, flags, dest_addr as usize) {
-            return Ok(write_len);
+    let dont_wait = MsgFlags::from_bits_truncate(flags as u32).contains(MsgFlags::DONTWAIT);
+    if let Some(sock) = as_unix_socket(&file) {
+        let dest = if dest_addr.is_null() { None } else { Some(read_unix_addr(&task, dest_addr, addr_len)?) };
+        sock.send(slice, Vec::new(), None, dest.as_ref(), dont_wait)
+    } else if let Some(sock) = as_udp_socket(&file) {
+        let dest = if dest_addr.is_null() {
+            None
         } else {
-            return Err(ErrorNo::EINVAL);
-        }
+            Some(read_ip_addr(&task, dest_addr, addr_len, sock.is_v6())?)
+        };
+        sock.send(slice, dest)
     } else {
-        return Err(ErrorNo::EBADF);
+        // 已连接的 socket 忽略目的地址
+        as_tcp_socket(&file).unwrap().send(slice, dont_wait)
     }
 }
 
-/// 收取消息，消息地址需要解析 dest_addr 获得
+/// 收取消息，发送者的地址写到 src_addr
 ///
 /// 消息的地址信息的长度(注意不是消息长度)将被存放在 src_len_pos 中
 pub fn sys_recvfrom(
     fd: usize,
     buf: *mut u8,
     len: usize,
-    _flags: i32,
-    _src_addr: *mut u8,
-    _src_len_pos: *mut u32,
+    flags: i32,
+    src_addr: *mut u8,
+    src_len_pos: *mut u32,
 ) -> SysResult {
     let task = get_current_task().unwrap();
-    let mut task_vm = task.vm.lock();
-    if task_vm.manually_alloc_user_str(buf, len).is_err() {
+    let file = get_socket(&task, fd)?;
+    if len > 0 && task.vm.lock().manually_alloc_user_str(buf, len).is_err() {
         return Err(ErrorNo::EFAULT); // 地址不合法
     }
-    drop(task_vm);
     let slice = unsafe { core::slice::from_raw_parts_mut(buf, len) };
-    loop {
-        let fd_manager = task.fd_manager.lock();
-        if let Ok(file) = fd_manager.get_file(fd) {
-            /* if let Some(read_len) = file.recvfrom(slice, flags, src_addr, 
-               unsafe { src_len_pos.as_mut().unwrap() }) */
-            if let Some(read_len) = file.recvfrom(slice, 0, 0, &mut 0) {
-                return Ok(read_len);
-            }
-            let fl = file.get_status();
-            if fl.contains(OpenFlags::NON_BLOCK) {
-                info!("sys_recvfrom flags: {:?}", fl);
-                return Err(ErrorNo::EAGAIN);
-            }
-        } else {
-            return Err(ErrorNo::EBADF);
-        }
-
-        drop(fd_manager);
-        suspend_current_task(); // yield
+    let flags = MsgFlags::from_bits_truncate(flags as u32);
+    // 带 MSG_TRUNC 时返回消息的实际长度
+    if let Some(sock) = as_unix_socket(&file) {
+        let ret = unix_recv(sock, slice, flags)?;
+        write_unix_addr(&task, &ret.from, src_addr, src_len_pos)?;
+        Ok(if flags.contains(MsgFlags::TRUNC) { ret.msg_len } else { ret.len })
+    } else if let Some(sock) = as_udp_socket(&file) {
+        let ret = sock.recv(slice, flags.contains(MsgFlags::PEEK), flags.contains(MsgFlags::DONTWAIT))?;
+        write_ip_addr(&task, ret.from, sock.is_v6(), src_addr, src_len_pos)?;
+        Ok(if flags.contains(MsgFlags::TRUNC) { ret.msg_len } else { ret.len })
+    } else {
+        let sock = as_tcp_socket(&file).unwrap();
+        let read_len = tcp_recv(sock, slice, flags)?;
+        write_ip_addr(&task, sock.peer_addr()?, sock.is_v6(), src_addr, src_len_pos)?;
+        Ok(read_len)
     }
 }
 
@@ -108,20 +301,17 @@ pub fn sys_recvfrom(
 pub fn sys_bind(fd: usize, addr: *const u8, addr_len: usize) -> SysResult {
     info!("sys_bind: fd: {} addr: {:p} len: {}", fd, addr, addr_len);
     let task = get_current_task().unwrap();
-    let mut task_vm = task.vm.lock();
-    if task_vm.manually_alloc_user_str(addr, addr_len).is_err() {
-        return Err(ErrorNo::EFAULT); // 地址不合法
+    let file = get_socket(&task, fd)?;
+    if let Some(sock) = as_unix_socket(&file) {
+        return sock.bind(read_unix_addr(&task, addr, addr_len)?).map(|_| 0);
     }
-    let fd_manager = task.fd_manager.lock();
-    if let Ok(file) = fd_manager.get_file(fd) {
-        let sock = file.as_any().downcast_ref::<Socket>().unwrap().clone();
-        if let Some(_p) = sock.set_endpoint(addr, false) {
-            Ok(0)
-        } else {
-            Err(ErrorNo::EINVAL)
-        }
+    if let Some(sock) = as_udp_socket(&file) {
+        let (ip, port) = read_ip_addr(&task, addr, addr_len, sock.is_v6())?;
+        sock.bind(ip, port).map(|_| 0)
     } else {
-        return Err(ErrorNo::EBADF);
+        let sock = as_tcp_socket(&file).unwrap();
+        let (ip, port) = read_ip_addr(&task, addr, addr_len, sock.is_v6())?;
+        sock.bind(ip, port).map(|_| 0)
     }
 }
 
@@ -129,118 +319,708 @@ pub fn sys_bind(fd: usize, addr: *const u8, addr_len: usize) -> SysResult {
 pub fn sys_listen(fd: usize, backlog: usize) -> SysResult {
     info!("sys_listen: fd: {} backlog: {}", fd, backlog);
     let task = get_current_task().unwrap();
-    let fd_manager = task.fd_manager.lock();
-    if let Ok(file) = fd_manager.get_file(fd) {
-        let sock = file.as_any().downcast_ref::<Socket>().unwrap().clone();
-        sock.set_listening(true);
-        Ok(0)
+    let file = get_socket(&task, fd)?;
+    if let Some(sock) = as_unix_socket(&file) {
+        sock.listen(backlog).map(|_| 0)
+    } else if let Some(sock) = as_tcp_socket(&file) {
+        sock.listen(backlog).map(|_| 0)
     } else {
-        Err(ErrorNo::EBADF)
+        // UDP 是无连接的
+        Err(ErrorNo::EOPNOTSUPP)
     }
 }
 
-/// socket连接给的远程地址. 如完成TCP的三次握手
+/// socket连接给的远程地址. 如完成TCP的三次握手。对 UDP 来说只是设置默认的目的地
 pub fn sys_connect(fd: usize, addr: *const u8, addr_len: usize) -> SysResult {
     info!("sys_connect: fd: {} addr: {:p} len: {}", fd, addr, addr_len);
     let task = get_current_task().unwrap();
-    let mut task_vm = task.vm.lock();
-    if task_vm.manually_alloc_user_str(addr, addr_len).is_err() {
-        return Err(ErrorNo::EFAULT); // 地址不合法
+    let file = get_socket(&task, fd)?;
+    // 连接可能会阻塞，所以不能拿着 task 中的锁
+    if let Some(sock) = as_unix_socket(&file) {
+        return sock.connect(&read_unix_addr(&task, addr, addr_len)?).map(|_| 0);
     }
-
-    let fd_manager = task.fd_manager.lock();
-    if let Ok(file) = fd_manager.get_file(fd) {
-        let sock = file.as_any().downcast_ref::<Socket>().unwrap().clone();
-        let laddr = IpAddr {
-            family: 2,
-            port: 0,
-            addr: 0,
-        };
-        //设置好本地endpoint
-        if let Some(lport) = sock.set_endpoint(&laddr as *const _ as *const u8, false) {
-            // TCP submit a SYN
-            // 注意转换成Big endian
-            let laddr = IpAddr {
-                family: 2,
-                port: u16::to_be(lport),
-                addr: u32::to_be(0x7f000001),
-            };
-            let slice = unsafe {
-                core::slice::from_raw_parts(
-                    &laddr as *const IpAddr as *const u8,
-                    size_of::<IpAddr>(),
-                    )
-            };
-            //把本地地址告诉给远端, 暂用端口port+100
-            if let Some(write_len) = file.sendto(slice, 100, addr as usize) {
-                //设置好远程endpoint
-                let rport = sock.set_endpoint(addr, true).unwrap_or(0);
-                info!("sys_connect sent IpAddr {} from {} to {} len: {}", size_of::<IpAddr>(), lport, rport, write_len);
-                // 这里没loop, 直接返回成功
-                return Ok(0);
-            } else {
-                return Err(ErrorNo::ECONNREFUSED);
-            }
-        } else {
+    if let Some(sock) = as_udp_socket(&file) {
+        if addr_len < size_of::<u16>() || task.vm.lock().manually_alloc_user_str(addr, addr_len).is_err() {
             return Err(ErrorNo::EINVAL);
         }
-    } else {
-        Err(ErrorNo::EBADF)
+        // 连接到 AF_UNSPEC 表示取消连接
+        if unsafe { *(addr as *const u16) } == AF_UNSPEC {
+            return sock.connect(None).map(|_| 0);
+        }
+        return sock.connect(Some(read_ip_addr(&task, addr, addr_len, sock.is_v6())?)).map(|_| 0);
     }
+    let sock = as_tcp_socket(&file).unwrap();
+    let (ip, port) = read_ip_addr(&task, addr, addr_len, sock.is_v6())?;
+    sock.connect(ip, port).map(|_| 0)
 }
 
 /// 监听着的SOCK_STREAM类型的socket, 接受连接, 原socket不受影响，创建一个新的socket返回
 pub fn sys_accept4(fd: usize, addr: *mut u8, addr_len: *mut u32, flags: i32) -> SysResult {
     info!("sys_accept: fd: {} addr: {:p} len: {:p}", fd, addr, addr_len);
     let task = get_current_task().unwrap();
+    let file = get_socket(&task, fd)?;
+    // SOCK_NONBLOCK 和 SOCK_CLOEXEC 与 O_NONBLOCK 和 O_CLOEXEC 的值相同
+    let flags = OpenFlags::from_bits_truncate(flags as u32);
+    if let Some(sock) = as_unix_socket(&file) {
+        let new_sock = sock.accept()?;
+        new_sock.set_status(flags);
+        write_unix_addr(&task, &new_sock.peer_addr()?, addr, addr_len)?;
+        task.fd_manager.lock().push(new_sock).map_err(|_| ErrorNo::EMFILE)
+    } else if let Some(sock) = as_tcp_socket(&file) {
+        let new_sock = sock.accept()?;
+        new_sock.set_status(flags);
+        write_ip_addr(&task, new_sock.peer_addr()?, new_sock.is_v6(), addr, addr_len)?;
+        task.fd_manager.lock().push(new_sock).map_err(|_| ErrorNo::EMFILE)
+    } else {
+        // UDP 是无连接的
+        Err(ErrorNo::EOPNOTSUPP)
+    }
+}
+
+/// 创建一对互相连接的 socket，fd 写在 sv[0] 和 sv[1]。目前只支持 AF_UNIX
+pub fn sys_socketpair(domain: usize, s_type: usize, protocol: usize, sv: *mut i32) -> SysResult {
+    info!("sys_socketpair: domain {} type {:x} protocol {}", domain, s_type, protocol);
+    match Domain::try_from(domain) {
+        Ok(Domain::AF_UNIX) => {}
+        Ok(_) => return Err(ErrorNo::EOPNOTSUPP),
+        Err(_) => return Err(ErrorNo::EAFNOSUPPORT),
+    }
+    let socket_type = unix_socket_type(s_type)?;
+    let flags = socket_flags(s_type);
+    let task = get_current_task().unwrap();
+    if task.vm.lock().manually_alloc_type(sv as *const [i32; 2]).is_err() {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    let (sock1, sock2) = UnixSocket::new_pair(socket_type, flags);
+    let mut fd_manager = task.fd_manager.lock();
+    let fd1 = fd_manager.push(Arc::new(sock1)).map_err(|_| ErrorNo::EMFILE)?;
+    match fd_manager.push(Arc::new(sock2)) {
+        Ok(fd2) => {
+            unsafe {
+                *sv = fd1 as i32;
+                *sv.add(1) = fd2 as i32;
+            }
+            Ok(0)
+        }
+        Err(_) => {
+            fd_manager.remove_file(fd1).unwrap();
+            Err(ErrorNo::EMFILE)
+        }
+    }
+}
+
+/// 获取 socket 绑定的地址
+pub fn sys_getsockname(fd: usize, addr: *mut u8, addr_len: *mut u32) -> SysResult {
+    let task = get_current_task().unwrap();
+    let file = get_socket(&task, fd)?;
+    if let Some(sock) = as_unix_socket(&file) {
+        write_unix_addr(&task, &sock.local_addr(), addr, addr_len)?;
+    } else if let Some(sock) = as_tcp_socket(&file) {
+        write_ip_addr(&task, sock.local_addr(), sock.is_v6(), addr, addr_len)?;
+    } else if let Some(sock) = as_udp_socket(&file) {
+        write_ip_addr(&task, sock.local_addr(), sock.is_v6(), addr, addr_len)?;
+    }
+    Ok(0)
+}
+
+/// 获取 socket 连接的对方的地址
+pub fn sys_getpeername(fd: usize, addr: *mut u8, addr_len: *mut u32) -> SysResult {
+    let task = get_current_task().unwrap();
+    let file = get_socket(&task, fd)?;
+    if let Some(sock) = as_unix_socket(&file) {
+        write_unix_addr(&task, &sock.peer_addr()?, addr, addr_len)?;
+    } else if let Some(sock) = as_tcp_socket(&file) {
+        write_ip_addr(&task, sock.peer_addr()?, sock.is_v6(), addr, addr_len)?;
+    } else if let Some(sock) = as_udp_socket(&file) {
+        write_ip_addr(&task, sock.peer_addr()?, sock.is_v6(), addr, addr_len)?;
+    }
+    Ok(0)
+}
+
+/// 关闭连接的读、写或者读写
+pub fn sys_shutdown(fd: usize, how: usize) -> SysResult {
+    let task = get_current_task().unwrap();
+    let file = get_socket(&task, fd)?;
+    if let Some(sock) = as_unix_socket(&file) {
+        sock.shutdown(how)?;
+    } else if let Some(sock) = as_tcp_socket(&file) {
+        sock.shutdown(how)?;
+    } else if let Some(sock) = as_udp_socket(&file) {
+        sock.shutdown(how)?;
+    }
+    Ok(0)
+}
+
+/// 设置 socket 选项。支持 SOL_SOCKET 层的 SO_RCVBUF、SO_SNDBUF、SO_RCVTIMEO、SO_SNDTIMEO、SO_KEEPALIVE、SO_LINGER，
+/// 只对 UDP socket 有效的 SO_REUSEADDR、SO_REUSEPORT 和 SO_BROADCAST，只对 TCP socket 有效的 TCP_NODELAY，
+/// 以及 IPPROTO_IPV6 层的 IPV6_V6ONLY。其他选项直接忽略
+pub fn sys_setsockopt(fd: usize, level: i32, optname: i32, optval: *const u8, optlen: usize) -> SysResult {
+    info!("sys_setsockopt: fd {} level {} optname {}", fd, level, optname);
+    let task = get_current_task().unwrap();
+    let file = get_socket(&task, fd)?;
+    let opt_size = match (level, optname) {
+        (SOL_SOCKET, SO_REUSEADDR | SO_REUSEPORT | SO_BROADCAST) if as_udp_socket(&file).is_none() => return Ok(0),
+        (IPPROTO_TCP, TCP_NODELAY) if as_tcp_socket(&file).is_none() => return Ok(0),
+        (IPPROTO_IPV6, IPV6_V6ONLY) if as_unix_socket(&file).is_some() => return Ok(0),
+        (SOL_SOCKET, SO_RCVTIMEO | SO_SNDTIMEO) => size_of::<TimeVal>(),
+        (SOL_SOCKET, SO_LINGER) => size_of::<Linger>(),
+        (SOL_SOCKET, SO_REUSEADDR | SO_REUSEPORT | SO_BROADCAST | SO_RCVBUF | SO_SNDBUF | SO_KEEPALIVE)
+        | (IPPROTO_TCP, TCP_NODELAY)
+        | (IPPROTO_IPV6, IPV6_V6ONLY) => size_of::<i32>(),
+        _ => return Ok(0),
+    };
+    if optlen < opt_size {
+        return Err(ErrorNo::EINVAL);
+    }
+    if task.vm.lock().manually_alloc_user_str(optval, opt_size).is_err() {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    let value = unsafe { (optval as *const i32).read_unaligned() };
+    if level == IPPROTO_IPV6 {
+        // AF_INET 的 socket 没有 IPv6 层的选项
+        return if let Some(sock) = as_tcp_socket(&file).filter(|sock| sock.is_v6()) {
+            sock.set_v6only(value != 0).map(|_| 0)
+        } else if let Some(sock) = as_udp_socket(&file).filter(|sock| sock.is_v6()) {
+            sock.set_v6only(value != 0).map(|_| 0)
+        } else {
+            Err(ErrorNo::ENOPROTOOPT)
+        };
+    }
+    let mut options = socket_options(&file);
+    match (level, optname) {
+        (SOL_SOCKET, SO_REUSEADDR | SO_REUSEPORT) => as_udp_socket(&file).unwrap().set_reuse(value != 0),
+        (SOL_SOCKET, SO_BROADCAST) => as_udp_socket(&file).unwrap().set_broadcast(value != 0),
+        (SOL_SOCKET, SO_RCVBUF) => options.rcvbuf = SocketOptions::buffer_size(value.max(0) as usize),
+        (SOL_SOCKET, SO_SNDBUF) => options.sndbuf = SocketOptions::buffer_size(value.max(0) as usize),
+        (SOL_SOCKET, SO_RCVTIMEO) => options.rcvtimeo = unsafe { (optval as *const TimeVal).read_unaligned() }.into(),
+        (SOL_SOCKET, SO_SNDTIMEO) => options.sndtimeo = unsafe { (optval as *const TimeVal).read_unaligned() }.into(),
+        (SOL_SOCKET, SO_KEEPALIVE) => options.keepalive = value != 0,
+        (SOL_SOCKET, SO_LINGER) => {
+            let linger = unsafe { (optval as *const Linger).read_unaligned() };
+            options.linger = if linger.onoff != 0 { Some(linger.linger.max(0) as u32) } else { None };
+        }
+        _ => options.nodelay = value != 0,
+    }
+    set_socket_options(&file, options);
+    Ok(0)
+}
+
+/// 获取 socket 选项。支持 sys_setsockopt 中除 SO_REUSEADDR、SO_REUSEPORT、SO_BROADCAST 和 IPV6_V6ONLY 以外的选项，
+/// 以及只读的 SO_TYPE、SO_DOMAIN 和 SO_ERROR。
+///
+/// optlen 处原本是 buffer 的长度，返回时改为写入的长度。选项的值比 buffer 长时会被截断
+pub fn sys_getsockopt(fd: usize, level: i32, optname: i32, optval: *mut u8, optlen: *mut u32) -> SysResult {
+    info!("sys_getsockopt: fd {} level {} optname {}", fd, level, optname);
+    let task = get_current_task().unwrap();
+    let file = get_socket(&task, fd)?;
+    let options = socket_options(&file);
+    let (domain, socket_type) = if let Some(sock) = as_unix_socket(&file) {
+        (Domain::AF_UNIX, sock.socket_type())
+    } else if let Some(sock) = as_tcp_socket(&file) {
+        (if sock.is_v6() { Domain::AF_INET6 } else { Domain::AF_INET }, SocketType::SOCK_STREAM)
+    } else {
+        let sock = as_udp_socket(&file).unwrap();
+        (if sock.is_v6() { Domain::AF_INET6 } else { Domain::AF_INET }, SocketType::SOCK_DGRAM)
+    };
+    let int_value = |value: i32| value.to_ne_bytes().to_vec();
+    let value = match (level, optname) {
+        (SOL_SOCKET, SO_TYPE) => int_value(socket_type as i32),
+        (SOL_SOCKET, SO_DOMAIN) => int_value(domain as i32),
+        (SOL_SOCKET, SO_ERROR) => {
+            let error = if let Some(sock) = as_tcp_socket(&file) {
+                sock.take_error()
+            } else if let Some(sock) = as_udp_socket(&file) {
+                sock.take_error().err()
+            } else {
+                None
+            };
+            // 错误编号在内核中是负数，用户看到的是正数
+            int_value(error.map_or(0, |err| -(err as i32)))
+        }
+        (SOL_SOCKET, SO_RCVBUF) => int_value(options.rcvbuf as i32),
+        (SOL_SOCKET, SO_SNDBUF) => int_value(options.sndbuf as i32),
+        (SOL_SOCKET, SO_KEEPALIVE) => int_value(options.keepalive as i32),
+        (SOL_SOCKET, SO_RCVTIMEO | SO_SNDTIMEO) => {
+            let timeout = if optname == SO_RCVTIMEO { options.rcvtimeo } else { options.sndtimeo };
+            let time = TimeVal::from(timeout);
+            [time.sec.to_ne_bytes(), time.usec.to_ne_bytes()].concat()
+        }
+        (SOL_SOCKET, SO_LINGER) => [
+            (options.linger.is_some() as i32).to_ne_bytes(),
+            (options.linger.unwrap_or(0) as i32).to_ne_bytes(),
+        ]
+        .concat(),
+        (IPPROTO_TCP, TCP_NODELAY) if as_tcp_socket(&file).is_some() => int_value(options.nodelay as i32),
+        _ => return Err(ErrorNo::ENOPROTOOPT),
+    };
     let mut task_vm = task.vm.lock();
-    if task_vm.manually_alloc_page(addr as usize).is_err()
-        || task_vm.manually_alloc_page(addr_len as usize).is_err()
-        {
+    if task_vm.manually_alloc_type(optlen).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let len = min(unsafe { *optlen } as usize, value.len());
+    if len > 0 && task_vm.manually_alloc_user_str(optval, len).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    unsafe {
+        core::slice::from_raw_parts_mut(optval, len).copy_from_slice(&value[..len]);
+        *optlen = len as u32;
+    }
+    Ok(0)
+}
+
+/// 检查 IoVec 数组和其中的每段 buffer，返回每段 buffer 的位置和长度
+fn check_iovecs(task_vm: &mut MemorySet, iov: *const IoVec, iovlen: usize) -> Result<Vec<(*mut u8, usize)>, ErrorNo> {
+    if iovlen == 0 {
+        return Ok(Vec::new());
+    }
+    if task_vm.manually_alloc_user_str(iov as *const u8, iovlen * size_of::<IoVec>()).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let mut bufs = Vec::new();
+    for i in 0..iovlen {
+        let io_vec = unsafe { &*iov.add(i) };
+        if io_vec.len > 0 && task_vm.manually_alloc_user_str(io_vec.base, io_vec.len).is_err() {
+            return Err(ErrorNo::EFAULT);
+        }
+        bufs.push((io_vec.base, io_vec.len));
+    }
+    Ok(bufs)
+}
+
+/// 读出辅助数据中的每一段，返回 (level, type, 数据)
+fn read_control(task_vm: &mut MemorySet, control: usize, controllen: usize) -> Result<Vec<(i32, i32, Vec<u8>)>, ErrorNo> {
+    let mut cmsgs = Vec::new();
+    if control == 0 || controllen == 0 {
+        return Ok(cmsgs);
+    }
+    if task_vm.manually_alloc_user_str(control as *const u8, controllen).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let mut offset = 0;
+    while offset + size_of::<CMsgHdr>() <= controllen {
+        let hdr = unsafe { ((control + offset) as *const CMsgHdr).read_unaligned() };
+        if hdr.len < size_of::<CMsgHdr>() || offset + hdr.len > controllen {
             return Err(ErrorNo::EINVAL);
         }
-    drop(task_vm);
-    loop {
-        let mut fd_manager = task.fd_manager.lock();
-        if let Ok(file) = fd_manager.get_file(fd) {
-            //if file.ready_to_read()
-                let mut buffer = [0u8; 64];
-                //获取新连接的远端地址
-                if let Some(read_len) = file.recvfrom(&mut buffer, 100, 0, &mut 0) {
-                    info!("accept recv: {}", read_len);
-                    if read_len != size_of::<IpAddr>() {
-                        warn!("accept unknown IpAddr");
-                    }
-                    unsafe {
-                        let taddr = core::slice::from_raw_parts_mut(addr as *mut u8, read_len);
-                        taddr.copy_from_slice(&buffer[..read_len]);
-                        *addr_len = read_len as u32;
+        let data = unsafe {
+            core::slice::from_raw_parts((control + offset + size_of::<CMsgHdr>()) as *const u8, hdr.len - size_of::<CMsgHdr>())
+        };
+        cmsgs.push((hdr.level, hdr.ctype, data.to_vec()));
+        offset += CMsgHdr::align(hdr.len);
+    }
+    Ok(cmsgs)
+}
 
-                        let recv_addr = addr as *const IpAddr;
-                        info!("sys_accept got IpAddr {} family: {:?}, IP: {:x}, Port: {}",
-                              *addr_len, (*recv_addr).family, u32::from_be((*recv_addr).addr), u16::from_be((*recv_addr).port));
-                    }
+/// 发送消息。数据分散在 msg 的 IoVec 数组中。
+///
+/// unix socket 支持通过辅助数据传递 fd(SCM_RIGHTS) 和进程身份(SCM_CREDENTIALS)，其他 socket 忽略辅助数据
+pub fn sys_sendmsg(fd: usize, msg: *const MsgHdr, flags: u32) -> SysResult {
+    let task = get_current_task().unwrap();
+    let file = get_socket(&task, fd)?;
+    if task.vm.lock().manually_alloc_type(msg).is_err() {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    send_msg(&task, &file, unsafe { &*msg }, flags)
+}
+
+/// 发送一条消息，见 sys_sendmsg。msg 所在的内存已经检查过
+fn send_msg(task: &Arc<TaskControlBlock>, file: &Arc<dyn File>, msg: &MsgHdr, flags: u32) -> SysResult {
+    let mut task_vm = task.vm.lock();
+    let mut data: Vec<u8> = Vec::new();
+    for (base, len) in check_iovecs(&mut task_vm, msg.iov, msg.iovlen)? {
+        data.extend_from_slice(unsafe { core::slice::from_raw_parts(base, len) });
+    }
+    let cmsgs = read_control(&mut task_vm, msg.control, msg.controllen)?;
+    drop(task_vm);
+    let flags = MsgFlags::from_bits_truncate(flags);
+    if let Some(sock) = as_tcp_socket(file) {
+        // TCP socket 没有辅助数据，已连接时也忽略目的地址
+        return sock.send(&data, flags.contains(MsgFlags::DONTWAIT));
+    }
+    if let Some(sock) = as_udp_socket(file) {
+        // UDP socket 没有辅助数据
+        let dest = if msg.name == 0 {
+            None
+        } else {
+            Some(read_ip_addr(task, msg.name as *const u8, msg.namelen as usize, sock.is_v6())?)
+        };
+        return sock.send(&data, dest);
+    }
+    let sock = as_unix_socket(file).unwrap();
+    let dest = if msg.name == 0 {
+        None
+    } else {
+        Some(read_unix_addr(task, msg.name as *const u8, msg.namelen as usize)?)
+    };
+    let mut rights: Vec<Arc<dyn File>> = Vec::new();
+    let mut cred = None;
+    for (level, ctype, cmsg_data) in cmsgs {
+        match (level, ctype) {
+            (SOL_SOCKET, SCM_RIGHTS) => {
+                let fd_manager = task.fd_manager.lock();
+                for fd in cmsg_data.chunks_exact(size_of::<i32>()) {
+                    let fd = i32::from_ne_bytes(fd.try_into().unwrap());
+                    rights.push(fd_manager.get_file(fd as usize).map_err(|_| ErrorNo::EBADF)?);
+                }
+            }
+            (SOL_SOCKET, SCM_CREDENTIALS) if cmsg_data.len() >= size_of::<UCred>() => {
+                cred = Some(unsafe { (cmsg_data.as_ptr() as *const UCred).read_unaligned() });
+            }
+            _ => return Err(ErrorNo::EINVAL),
+        }
+    }
+    sock.send(&data, rights, cred, dest.as_ref(), flags.contains(MsgFlags::DONTWAIT))
+}
 
-                    //设置好远程endpoint
-                    let sock = file.as_any().downcast_ref::<Socket>().unwrap().clone();
-                    sock.set_endpoint(addr, true).unwrap_or(0);
+/// 接收消息，数据放到 msg 的 IoVec 数组中，并在 msg 中写入消息的地址、辅助数据和状态。
+///
+/// 通过 SCM_RIGHTS 收到的文件会放进当前进程的 fd 表。辅助数据放不下时，多出的文件会被关闭，并设置 MSG_CTRUNC
+pub fn sys_recvmsg(fd: usize, msg: *mut MsgHdr, flags: u32) -> SysResult {
+    let task = get_current_task().unwrap();
+    let file = get_socket(&task, fd)?;
+    if task.vm.lock().manually_alloc_type(msg).is_err() {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    recv_msg(&task, &file, unsafe { &mut *msg }, flags)
+}
 
-                    // New Socket
-                    if let Ok(new_fd) = fd_manager.push(Arc::new(sock.clonew())) {
-                        return Ok(new_fd);
-                    } else {
-                        return Err(ErrorNo::EMFILE);
+/// 接收一条消息，见 sys_recvmsg。msg 所在的内存已经检查过
+fn recv_msg(task: &Arc<TaskControlBlock>, file: &Arc<dyn File>, msg: &mut MsgHdr, flags: u32) -> SysResult {
+    let mut task_vm = task.vm.lock();
+    let bufs = check_iovecs(&mut task_vm, msg.iov, msg.iovlen)?;
+    if msg.name != 0 && msg.namelen > 0 && task_vm.manually_alloc_user_str(msg.name as *const u8, msg.namelen as usize).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    if msg.control != 0 && msg.controllen > 0 && task_vm.manually_alloc_user_str(msg.control as *const u8, msg.controllen).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    drop(task_vm);
+    let mut buf = vec![0u8; bufs.iter().map(|&(_, len)| len).sum()];
+    let flags = MsgFlags::from_bits_truncate(flags);
+    let mut msg_flags = MsgFlags::empty();
+    let (len, ret_len) = match as_unix_socket(file) {
+        Some(sock) => {
+            let ret = unix_recv(sock, &mut buf, flags)?;
+            if msg.name != 0 {
+                msg.namelen = unix_addr_to_user(&ret.from, msg.name as *mut u8, msg.namelen as usize) as u32;
+            }
+            if ret.msg_len > ret.len {
+                msg_flags |= MsgFlags::TRUNC;
+            }
+            // 依次写入 SCM_RIGHTS 和 SCM_CREDENTIALS
+            let mut offset = 0;
+            if !ret.rights.is_empty() {
+                let max_fds = if msg.control == 0 {
+                    0
+                } else {
+                    msg.controllen.saturating_sub(size_of::<CMsgHdr>()) / size_of::<i32>()
+                };
+                let mut fds: Vec<i32> = Vec::new();
+                let mut fd_manager = task.fd_manager.lock();
+                for file in ret.rights.iter().take(max_fds) {
+                    match fd_manager.push(file.clone()) {
+                        Ok(fd) => fds.push(fd as i32),
+                        Err(_) => break,
                     }
-                } else if let Some(fl) = OpenFlags::from_bits((flags as u32) & !SOCKET_TYPE_MASK) {
-                    if fl.contains(OpenFlags::NON_BLOCK) {
-                        info!("sys_accept flags: {:?}", fl);
-                        return Err(ErrorNo::EAGAIN);
+                    if flags.contains(MsgFlags::CMSG_CLOEXEC) {
+                        file.set_close_on_exec(true);
                     }
                 }
-        } else {
-            return Err(ErrorNo::EBADF);
+                drop(fd_manager);
+                if fds.len() < ret.rights.len() {
+                    msg_flags |= MsgFlags::CTRUNC;
+                }
+                if !fds.is_empty() {
+                    let cmsg_len = size_of::<CMsgHdr>() + fds.len() * size_of::<i32>();
+                    unsafe {
+                        (msg.control as *mut CMsgHdr).write_unaligned(CMsgHdr {
+                            len: cmsg_len,
+                            level: SOL_SOCKET,
+                            ctype: SCM_RIGHTS,
+                        });
+                        let data = (msg.control + size_of::<CMsgHdr>()) as *mut i32;
+                        for (i, &fd) in fds.iter().enumerate() {
+                            data.add(i).write_unaligned(fd);
+                        }
+                    }
+                    offset += CMsgHdr::align(cmsg_len);
+                }
+            }
+            if let Some(cred) = ret.cred {
+                let cmsg_len = size_of::<CMsgHdr>() + size_of::<UCred>();
+                if msg.control != 0 && offset + cmsg_len <= msg.controllen {
+                    unsafe {
+                        ((msg.control + offset) as *mut CMsgHdr).write_unaligned(CMsgHdr {
+                            len: cmsg_len,
+                            level: SOL_SOCKET,
+                            ctype: SCM_CREDENTIALS,
+                        });
+                        ((msg.control + offset + size_of::<CMsgHdr>()) as *mut UCred).write_unaligned(cred);
+                    }
+                    offset += CMsgHdr::align(cmsg_len);
+                } else {
+                    msg_flags |= MsgFlags::CTRUNC;
+                }
+            }
+            msg.controllen = min(offset, msg.controllen);
+            // 带 MSG_TRUNC 时返回消息的实际长度
+            (ret.len, if flags.contains(MsgFlags::TRUNC) { ret.msg_len } else { ret.len })
         }
-        drop(fd_manager);
-        suspend_current_task(); // yield
+        None => {
+            // 其他 socket 不支持辅助数据
+            msg.controllen = 0;
+            if let Some(sock) = as_tcp_socket(file) {
+                let len = tcp_recv(sock, &mut buf, flags)?;
+                if msg.name != 0 {
+                    let peer = sock.peer_addr()?;
+                    msg.namelen = inet_addr_to_user(peer, sock.is_v6(), msg.name as *mut u8, msg.namelen as usize) as u32;
+                }
+                (len, len)
+            } else {
+                let sock = as_udp_socket(file).unwrap();
+                let ret = sock.recv(&mut buf, flags.contains(MsgFlags::PEEK), flags.contains(MsgFlags::DONTWAIT))?;
+                if msg.name != 0 {
+                    msg.namelen = inet_addr_to_user(ret.from, sock.is_v6(), msg.name as *mut u8, msg.namelen as usize) as u32;
+                }
+                if ret.msg_len > ret.len {
+                    msg_flags |= MsgFlags::TRUNC;
+                }
+                (ret.len, if flags.contains(MsgFlags::TRUNC) { ret.msg_len } else { ret.len })
+            }
+        }
+    };
+    // 把数据分散写入每段 buffer
+    let mut pos = 0;
+    for (base, buf_len) in bufs {
+        let copy_len = min(buf_len, len - pos);
+        unsafe { core::slice::from_raw_parts_mut(base, copy_len) }.copy_from_slice(&buf[pos..pos + copy_len]);
+        pos += copy_len;
     }
+    msg.flags = msg_flags.bits();
+    Ok(ret_len)
+}
+
+/// 一次发送多条消息，每条消息发送的字节数写在对应 MMsgHdr 的 len 中。
+/// 返回成功发送的消息数，只有第一条消息就失败时才返回错误
+pub fn sys_sendmmsg(fd: usize, msgvec: *mut MMsgHdr, vlen: usize, flags: u32) -> SysResult {
+    let task = get_current_task().unwrap();
+    let file = get_socket(&task, fd)?;
+    let vlen = min(vlen, MAX_MMSG_COUNT);
+    if vlen > 0 && task.vm.lock().manually_alloc_user_str(msgvec as *const u8, vlen * size_of::<MMsgHdr>()).is_err() {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    let mut count = 0;
+    while count < vlen {
+        let mmsg = unsafe { &mut *msgvec.add(count) };
+        match send_msg(&task, &file, &mmsg.hdr, flags) {
+            Ok(len) => mmsg.len = len as u32,
+            Err(err) if count == 0 => return Err(err),
+            Err(_) => break,
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// 一次接收多条消息，每条消息接收的字节数写在对应 MMsgHdr 的 len 中。带 MSG_WAITFORONE 时收到第一条消息后不再阻塞。
+///
+/// timeout 不为空时，每收到一条消息后检查是否超时，超时就不再接收。和 Linux 一样，正在阻塞的接收不会因为超时而返回。
+/// 返回收到的消息数，只有第一条消息就失败时才返回错误
+pub fn sys_recvmmsg(fd: usize, msgvec: *mut MMsgHdr, vlen: usize, flags: u32, timeout: *const TimeSpec) -> SysResult {
+    let task = get_current_task().unwrap();
+    let file = get_socket(&task, fd)?;
+    let vlen = min(vlen, MAX_MMSG_COUNT);
+    let mut task_vm = task.vm.lock();
+    if vlen > 0 && task_vm.manually_alloc_user_str(msgvec as *const u8, vlen * size_of::<MMsgHdr>()).is_err() {
+        return Err(ErrorNo::EFAULT); // 地址不合法
+    }
+    let expire_us = if timeout.is_null() {
+        None
+    } else if task_vm.manually_alloc_type(timeout).is_err() {
+        return Err(ErrorNo::EFAULT);
+    } else {
+        Some(get_time_us().saturating_add(unsafe { (*timeout).to_us() }))
+    };
+    drop(task_vm);
+    let mut flags = flags;
+    let mut count = 0;
+    while count < vlen {
+        let mmsg = unsafe { &mut *msgvec.add(count) };
+        match recv_msg(&task, &file, &mut mmsg.hdr, flags) {
+            Ok(len) => mmsg.len = len as u32,
+            Err(err) if count == 0 => return Err(err),
+            Err(_) => break,
+        }
+        count += 1;
+        if MsgFlags::from_bits_truncate(flags).contains(MsgFlags::WAITFORONE) {
+            flags |= MsgFlags::DONTWAIT.bits();
+        }
+        if expire_us.map_or(false, |time| get_time_us() >= time) {
+            break;
+        }
+    }
+    Ok(count)
+}
+
+/// 一个网卡的状态
+struct IfInfo {
+    name: &'static [u8],
+    index: i32,
+    /// ip 地址，主机字节序
+    addr: u32,
+    /// 子网掩码的长度
+    prefix_len: u8,
+    mac: [u8; 6],
+    mtu: i32,
+    flags: IfFlags,
+}
+
+/// 列出所有网卡。本地回环 lo 总是存在，eth0 只在有网卡时存在
+fn interfaces() -> Vec<IfInfo> {
+    let mut ifaces = vec![IfInfo {
+        name: b"lo",
+        index: 1,
+        addr: LOCAL_LOOPBACK_ADDR,
+        prefix_len: 8,
+        mac: [0; 6],
+        mtu: LOOPBACK_MTU,
+        flags: IfFlags::UP | IfFlags::LOOPBACK | IfFlags::RUNNING,
+    }];
+    if let Some(config) = with_iface(|iface| iface.config()) {
+        let mut flags = IfFlags::BROADCAST | IfFlags::MULTICAST;
+        if config.up {
+            flags |= IfFlags::UP | IfFlags::RUNNING;
+        }
+        ifaces.push(IfInfo {
+            name: b"eth0",
+            index: 2,
+            addr: config.addr,
+            prefix_len: config.prefix_len,
+            mac: config.mac,
+            mtu: ETHER_MTU,
+            flags: flags,
+        });
+    }
+    ifaces
+}
+
+/// 子网掩码的长度转换为子网掩码
+fn prefix_len_to_mask(prefix_len: u8) -> u32 {
+    match prefix_len {
+        0 => 0,
+        len => u32::MAX << (32 - len as u32),
+    }
+}
+
+/// socket 上的 ioctl，用于查询和配置网卡，即 ifconfig 和 route 的功能。
+/// 网卡有本地回环 lo 和 eth0 两个，其中 lo 的配置不能修改。
+///
+/// fd 不是 socket 或者不是网卡相关的请求时返回 None，交给普通的 ioctl 处理
+pub fn socket_ioctl(fd: usize, request: usize, argp: usize) -> Option<SysResult> {
+    let task = get_current_task().unwrap();
+    get_socket(&task, fd).ok()?;
+    match request {
+        SIOCGIFCONF => Some(get_if_conf(&task, argp)),
+        SIOCADDRT | SIOCDELRT => Some(set_default_route(&task, request == SIOCADDRT, argp)),
+        SIOCGIFFLAGS | SIOCSIFFLAGS | SIOCGIFADDR | SIOCSIFADDR | SIOCGIFNETMASK | SIOCSIFNETMASK | SIOCGIFMTU
+        | SIOCGIFHWADDR | SIOCGIFINDEX => Some(if_ioctl(&task, request, argp)),
+        _ => None,
+    }
+}
+
+/// 针对单个网卡的请求，参数是 IfReq，用名字指定网卡
+fn if_ioctl(task: &Arc<TaskControlBlock>, request: usize, argp: usize) -> SysResult {
+    if task.vm.lock().manually_alloc_type(argp as *const IfReq).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let req = unsafe { &mut *(argp as *mut IfReq) };
+    let name_len = req.name.iter().position(|&c| c == 0).unwrap_or(req.name.len());
+    let info = interfaces()
+        .into_iter()
+        .find(|info| info.name == &req.name[..name_len])
+        .ok_or(ErrorNo::ENODEV)?;
+    let is_loopback = info.flags.contains(IfFlags::LOOPBACK);
+    match request {
+        SIOCGIFFLAGS => req.data[..2].copy_from_slice(&info.flags.bits().to_ne_bytes()),
+        SIOCGIFADDR => {
+            ip_addr_to_user(info.addr, 0, req.data.as_mut_ptr(), req.data.len());
+        }
+        SIOCGIFNETMASK => {
+            ip_addr_to_user(prefix_len_to_mask(info.prefix_len), 0, req.data.as_mut_ptr(), req.data.len());
+        }
+        SIOCGIFMTU => req.data[..4].copy_from_slice(&info.mtu.to_ne_bytes()),
+        SIOCGIFINDEX => req.data[..4].copy_from_slice(&info.index.to_ne_bytes()),
+        SIOCGIFHWADDR => {
+            // 返回的是一个 sockaddr，地址族的位置放硬件地址的类型
+            let hw_type = if is_loopback { ARPHRD_LOOPBACK } else { ARPHRD_ETHER };
+            req.data = [0; 24];
+            req.data[..2].copy_from_slice(&hw_type.to_ne_bytes());
+            req.data[2..8].copy_from_slice(&info.mac);
+        }
+        _ if is_loopback => return Err(ErrorNo::EPERM),
+        SIOCSIFFLAGS => {
+            let flags = IfFlags::from_bits_truncate(u16::from_ne_bytes([req.data[0], req.data[1]]));
+            with_iface(|iface| iface.set_up(flags.contains(IfFlags::UP)));
+        }
+        SIOCSIFADDR => {
+            let (addr, _) = ip_addr_resolution(req.data.as_ptr(), req.data.len()).ok_or(ErrorNo::EINVAL)?;
+            with_iface(|iface| iface.set_addr(addr));
+        }
+        SIOCSIFNETMASK => {
+            let (mask, _) = ip_addr_resolution(req.data.as_ptr(), req.data.len()).ok_or(ErrorNo::EINVAL)?;
+            // 子网掩码必须是连续的 1
+            if mask.leading_ones() != mask.count_ones() {
+                return Err(ErrorNo::EINVAL);
+            }
+            with_iface(|iface| iface.set_prefix_len(mask.leading_ones() as u8));
+        }
+        _ => return Err(ErrorNo::EINVAL),
+    }
+    Ok(0)
+}
+
+/// SIOCGIFCONF，把所有网卡的名字和地址写到 IfConf 给出的数组里。数组地址为 0 时只返回需要的长度
+fn get_if_conf(task: &Arc<TaskControlBlock>, argp: usize) -> SysResult {
+    let ifaces = interfaces();
+    let mut vm = task.vm.lock();
+    if vm.manually_alloc_type(argp as *const IfConf).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let conf = unsafe { &mut *(argp as *mut IfConf) };
+    if conf.buf == 0 {
+        conf.len = (ifaces.len() * size_of::<IfReq>()) as i32;
+        return Ok(0);
+    }
+    let count = min(conf.len.max(0) as usize / size_of::<IfReq>(), ifaces.len());
+    if count > 0 && vm.manually_alloc_user_str(conf.buf as *const u8, count * size_of::<IfReq>()).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let reqs = unsafe { core::slice::from_raw_parts_mut(conf.buf as *mut IfReq, count) };
+    for (req, info) in reqs.iter_mut().zip(ifaces.iter()) {
+        req.name = [0; 16];
+        req.name[..info.name.len()].copy_from_slice(info.name);
+        req.data = [0; 24];
+        ip_addr_to_user(info.addr, 0, req.data.as_mut_ptr(), req.data.len());
+    }
+    conf.len = (count * size_of::<IfReq>()) as i32;
+    Ok(0)
+}
+
+/// SIOCADDRT / SIOCDELRT。只支持默认路由，即设置或删除 eth0 的默认网关
+fn set_default_route(task: &Arc<TaskControlBlock>, add: bool, argp: usize) -> SysResult {
+    if task.vm.lock().manually_alloc_type(argp as *const RtEntry).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let entry = unsafe { &*(argp as *const RtEntry) };
+    let (dst, _) = ip_addr_resolution(entry.dst.as_ptr(), entry.dst.len()).ok_or(ErrorNo::EINVAL)?;
+    if dst != 0 {
+        return Err(ErrorNo::EINVAL);
+    }
+    let gateway = if add {
+        Some(ip_addr_resolution(entry.gateway.as_ptr(), entry.gateway.len()).ok_or(ErrorNo::EINVAL)?.0)
+    } else {
+        None
+    };
+    with_iface(|iface| iface.set_default_gateway(gateway)).ok_or(ErrorNo::ENODEV)?;
+    Ok(0)
 }
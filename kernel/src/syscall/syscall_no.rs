@@ -8,22 +8,28 @@ numeric_enum_macro::numeric_enum! {
     pub enum SyscallNo {
         UNKNOWN = usize::MAX, // 未识别的系统调用
         GETCWD = 17,
-        EPOLL_CREATE = 20,
+        EVENTFD2 = 19,
+        EPOLL_CREATE1 = 20,
         EPOLL_CTL = 21,
-        EPOLL_WAIT = 22,
+        EPOLL_PWAIT = 22,
         DUP = 23,
         DUP3 = 24,
         FCNTL64 = 25,
         IOCTL = 29,
+        MKNODAT = 33,
         MKDIR = 34,
         UNLINKAT = 35,
+        SYMLINKAT = 36,
         LINKAT = 37,
         UMOUNT = 39,
         MOUNT = 40,
         STATFS = 43,
+        FTRUNCATE = 46,
         ACCESS = 48,
         CHDIR = 49,
+        CHROOT = 51,
         CHMOD = 53,
+        FCHOWNAT = 54,
         OPEN = 56,
         CLOSE = 57,
         PIPE = 59,
@@ -37,11 +43,16 @@ numeric_enum_macro::numeric_enum! {
         SENDFILE64 = 71,
         PSELECT6 = 72,
         PPOLL = 73,
+        SIGNALFD4 = 74,
         READLINKAT = 78,
         FSTATAT = 79,
         FSTAT = 80,
+        SYNC = 81,
         FSYNC = 82,
         FDATASYNC = 83,
+        TIMERFD_CREATE = 85,
+        TIMERFD_SETTIME = 86,
+        TIMERFD_GETTIME = 87,
         UTIMENSAT = 88,
         EXIT = 93,
         EXIT_GROUP = 94,
@@ -63,6 +74,8 @@ numeric_enum_macro::numeric_enum! {
         SIGRETURN = 139,
         TIMES = 153,
         UNAME = 160,
+        GETRLIMIT = 163,
+        SETRLIMIT = 164,
         GETRUSAGE = 165,
         UMASK = 166,
         PRCTL = 167,
@@ -75,7 +88,20 @@ numeric_enum_macro::numeric_enum! {
         GETEGID = 177,
         GETTID = 178,
         SYSINFO = 179,
+        MSGGET = 186,
+        MSGCTL = 187,
+        MSGRCV = 188,
+        MSGSND = 189,
+        SEMGET = 190,
+        SEMCTL = 191,
+        SEMTIMEDOP = 192,
+        SEMOP = 193,
+        SHMGET = 194,
+        SHMCTL = 195,
+        SHMAT = 196,
+        SHMDT = 197,
         SOCKET = 198,
+        SOCKETPAIR = 199,
         BIND = 200,
         LISTEN = 201,
         ACCEPT = 202,
@@ -86,7 +112,7 @@ numeric_enum_macro::numeric_enum! {
         RECVFROM = 207,
         SETSOCKOPT = 208,
         GETSOCKOPT = 209,
-        SHUDOWN = 210,
+        SHUTDOWN = 210,
         SENDMSG = 211,
         RECVMSG = 212,
         BRK = 214,
@@ -98,8 +124,10 @@ numeric_enum_macro::numeric_enum! {
         MSYNC = 227,
         MADVISE = 233,
         ACCEPT4 = 242,
+        RECVMMSG = 243,
         WAIT4 = 260,
         PRLIMIT64 = 261,
+        SENDMMSG = 269,
         RENAMEAT2 = 276,
         MEMBARRIER = 283,
     }
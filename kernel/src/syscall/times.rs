@@ -3,12 +3,15 @@
 //#![deny(missing_docs)]
 
 use super::{ErrorNo, RUSAGE_CHILDREN, RUSAGE_SELF, RUSAGE_THREAD};
+use crate::file::{ITimerSpec, OpenFlags, TimerFd, CLOCK_BOOTTIME, CLOCK_MONOTONIC, CLOCK_REALTIME};
 use crate::task::ITimerVal;
-use crate::task::{get_current_task, suspend_current_task};
-use crate::timer::{get_time_f64, get_time_us, get_time_sec, USEC_PER_INTERRUPT};
+use crate::task::{get_current_task, get_process_count, load_avg, suspend_current_task, FSHIFT};
+use crate::memory::mem_stat;
+use crate::timer::{get_time_f64, get_time_us, get_time_sec, NSEC_PER_SEC, USEC_PER_INTERRUPT};
 use crate::timer::{TimeSpec, TimeVal};
 
 use super::{SysResult, SysInfo, TMS};
+use alloc::sync::Arc;
 
 /// 获取系统时间并存放在参数提供的数组里
 pub fn sys_get_time_of_day(time_val: *mut TimeVal) -> SysResult {
@@ -128,16 +131,80 @@ pub fn sys_settimer(
     }
 }
 
-/// 获取系统的启动时间和内存信息。
-/// 目前只支持启动时间
+/// 创建一个 timerfd，返回它的 fd。定时器创建时是未启动的
+pub fn sys_timerfd_create(clockid: usize, flags: u32) -> SysResult {
+    if clockid != CLOCK_REALTIME && clockid != CLOCK_MONOTONIC && clockid != CLOCK_BOOTTIME {
+        return Err(ErrorNo::EINVAL);
+    }
+    if flags & !(OpenFlags::NON_BLOCK.bits() | OpenFlags::CLOEXEC.bits()) != 0 {
+        return Err(ErrorNo::EINVAL);
+    }
+    let task = get_current_task().unwrap();
+    let mut fd_manager = task.fd_manager.lock();
+    fd_manager.push(Arc::new(TimerFd::new(clockid, flags))).map_err(|_| ErrorNo::EMFILE)
+}
+
+/// 设置 timerfd 的定时器。如果 old_value 不为空，则把原来的设置写入其中
+pub fn sys_timerfd_settime(fd: usize, flags: u32, new_value: *const ITimerSpec, old_value: *mut ITimerSpec) -> SysResult {
+    let task = get_current_task().unwrap();
+    let mut task_vm = task.vm.lock();
+    if task_vm.manually_alloc_type(new_value).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    if old_value as usize != 0 && task_vm.manually_alloc_type(old_value).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let file = task.fd_manager.lock().get_file(fd).map_err(|_| ErrorNo::EBADF)?;
+    let timer_fd = (*file).as_any().downcast_ref::<TimerFd>().ok_or(ErrorNo::EINVAL)?;
+    let new_value = unsafe { *new_value };
+    if new_value.it_value.tv_nsec >= NSEC_PER_SEC || new_value.it_interval.tv_nsec >= NSEC_PER_SEC {
+        return Err(ErrorNo::EINVAL);
+    }
+    let old = timer_fd.set_time(&new_value, flags);
+    if old_value as usize != 0 {
+        unsafe { *old_value = old };
+    }
+    Ok(0)
+}
+
+/// 获取 timerfd 的定时器距离下一次到期的时间和周期
+pub fn sys_timerfd_gettime(fd: usize, curr_value: *mut ITimerSpec) -> SysResult {
+    let task = get_current_task().unwrap();
+    if task.vm.lock().manually_alloc_type(curr_value).is_err() {
+        return Err(ErrorNo::EFAULT);
+    }
+    let file = task.fd_manager.lock().get_file(fd).map_err(|_| ErrorNo::EBADF)?;
+    let timer_fd = (*file).as_any().downcast_ref::<TimerFd>().ok_or(ErrorNo::EINVAL)?;
+    unsafe { *curr_value = timer_fd.get_time() };
+    Ok(0)
+}
+
+/// 获取系统的启动时间、负载和内存信息。
+/// 和 /proc/uptime、/proc/loadavg、/proc/meminfo 中的数据来源相同
 pub fn sys_sysinfo(info: *mut SysInfo) -> SysResult {
     let task = get_current_task().unwrap();
     let mut task_vm = task.vm.lock();
     if task_vm.manually_alloc_type(info).is_err() {
         return Err(ErrorNo::EFAULT);
     }
+    let mem = mem_stat();
+    // sysinfo 中的负载是 16 位小数的定点数
+    let loads = load_avg().map(|load| load << (16 - FSHIFT));
     unsafe {
-        (*info).uptime = get_time_sec() as isize;
+        *info = SysInfo {
+            uptime: get_time_sec() as isize,
+            loads: loads,
+            totalram: mem.total,
+            freeram: mem.free,
+            sharedram: 0,
+            bufferram: 0,
+            totalswap: 0,
+            freeswap: 0,
+            procs: get_process_count() as u16,
+            totalhigh: 0,
+            freehigh: 0,
+            mem_unit: 1,
+        };
     }
     Ok(0)
 }
\ No newline at end of file
@@ -0,0 +1,213 @@
+//! 运行时可调整的内核参数(sysctl)
+//!
+//! 各模块把参数定义为 Sysctl 类型的 static，初始化时用 register_sysctl 注册到一个路径下，
+//! 之后可以通过 /proc/sys/<路径> 读写，如 `echo 4096 > /proc/sys/fs/pipe_size_limit`。
+//! 写入的值会被解析并检查范围，不合法时写入失败，参数保持原值。
+//!
+//! 启动时可以通过设备树 /chosen/bootargs(qemu 的 -append)预设参数，格式和 Linux 相同，
+//! 如 `sysctl.fs.pipe_size_limit=4096`，路径中的 '.' 和 '/' 等价。
+//! 预设值在参数注册时才生效，所以和各模块的初始化顺序无关
+
+use crate::console::LogLevel;
+use crate::syscall::ErrorNo;
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lock::Mutex;
+
+/// bootargs 中 sysctl 参数的前缀
+const BOOTARGS_PREFIX: &str = "sysctl.";
+
+/// 可以通过 /proc/sys 读写的参数
+pub trait Tunable: Sync {
+    /// 当前值的文本形式，不含换行
+    fn read(&self) -> String;
+    /// 解析 value 并设置。格式错误或超出范围时返回 EINVAL
+    fn write(&self, value: &str) -> Result<(), ErrorNo>;
+}
+
+/// 可以保存在 Sysctl 中的类型，在内部都表示为 usize
+pub trait SysctlValue: Copy {
+    fn to_raw(self) -> usize;
+    fn from_raw(raw: usize) -> Self;
+    fn parse(value: &str) -> Option<Self>;
+    fn format(self) -> String;
+}
+
+impl SysctlValue for usize {
+    fn to_raw(self) -> usize {
+        self
+    }
+    fn from_raw(raw: usize) -> Self {
+        raw
+    }
+    /// 十进制，或者 0x 开头的十六进制
+    fn parse(value: &str) -> Option<Self> {
+        match value.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        }
+    }
+    fn format(self) -> String {
+        format!("{}", self)
+    }
+}
+
+impl SysctlValue for bool {
+    fn to_raw(self) -> usize {
+        self as usize
+    }
+    fn from_raw(raw: usize) -> Self {
+        raw != 0
+    }
+    /// 和 Linux 一样用 0 和 1 表示
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "0" => Some(false),
+            "1" => Some(true),
+            _ => None,
+        }
+    }
+    fn format(self) -> String {
+        format!("{}", self as usize)
+    }
+}
+
+/// 日志等级，0 为 off，5 为 trace
+impl SysctlValue for LogLevel {
+    fn to_raw(self) -> usize {
+        self as usize
+    }
+    fn from_raw(raw: usize) -> Self {
+        LogLevel::iter().nth(raw).unwrap_or(LogLevel::Trace)
+    }
+    /// 数字或者等级的名字，如 "3" 或 "warn"
+    fn parse(value: &str) -> Option<Self> {
+        match value.parse::<usize>() {
+            Ok(raw) => LogLevel::iter().nth(raw),
+            Err(_) => value.parse().ok(),
+        }
+    }
+    fn format(self) -> String {
+        format!("{}", self as usize)
+    }
+}
+
+/// 类型为 T、取值范围为 [min, max] 的参数
+pub struct Sysctl<T> {
+    value: AtomicUsize,
+    min: usize,
+    max: usize,
+    _marker: PhantomData<T>,
+}
+
+impl Sysctl<usize> {
+    /// 默认值为 default，取值范围为 [min, max] 的整数
+    pub const fn new(default: usize, min: usize, max: usize) -> Self {
+        Self {
+            value: AtomicUsize::new(default),
+            min: min,
+            max: max,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl Sysctl<bool> {
+    /// 默认值为 default 的开关
+    pub const fn new_bool(default: bool) -> Self {
+        Self {
+            value: AtomicUsize::new(default as usize),
+            min: 0,
+            max: 1,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: SysctlValue> Sysctl<T> {
+    /// 当前值
+    pub fn get(&self) -> T {
+        T::from_raw(self.value.load(Ordering::Relaxed))
+    }
+    /// 设置为 value。超出范围时返回 EINVAL
+    pub fn set(&self, value: T) -> Result<(), ErrorNo> {
+        let raw = value.to_raw();
+        if raw < self.min || raw > self.max {
+            return Err(ErrorNo::EINVAL);
+        }
+        self.value.store(raw, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl<T: SysctlValue + Sync> Tunable for Sysctl<T> {
+    fn read(&self) -> String {
+        self.get().format()
+    }
+    fn write(&self, value: &str) -> Result<(), ErrorNo> {
+        self.set(T::parse(value).ok_or(ErrorNo::EINVAL)?)
+    }
+}
+
+/// 所有参数的注册表
+struct SysctlTable {
+    /// 已注册的参数，键为 /proc/sys 下的路径，如 "fs/pipe_size_limit"
+    tunables: BTreeMap<&'static str, &'static dyn Tunable>,
+    /// bootargs 中还没有注册的参数的预设值
+    presets: BTreeMap<String, String>,
+}
+
+static SYSCTL_TABLE: Mutex<SysctlTable> = Mutex::new(SysctlTable {
+    tunables: BTreeMap::new(),
+    presets: BTreeMap::new(),
+});
+
+/// 把参数 tunable 注册到 path 下。如果 bootargs 中有它的预设值，则立即设置
+pub fn register_sysctl(path: &'static str, tunable: &'static dyn Tunable) {
+    let mut table = SYSCTL_TABLE.lock();
+    if let Some(value) = table.presets.remove(path) {
+        if tunable.write(&value).is_err() {
+            warn!("sysctl: invalid boot value {} for {}", value, path);
+        }
+    }
+    table.tunables.insert(path, tunable);
+}
+
+/// 读取 bootargs 中以 "sysctl." 开头的预设值，其他项忽略
+pub fn preset_sysctls(bootargs: &str) {
+    let mut table = SYSCTL_TABLE.lock();
+    for arg in bootargs.split_whitespace() {
+        let (path, value) = match arg.strip_prefix(BOOTARGS_PREFIX).and_then(|arg| arg.split_once('=')) {
+            Some(pair) => pair,
+            None => continue,
+        };
+        let path = path.replace('.', "/");
+        match table.tunables.get(path.as_str()) {
+            Some(tunable) => {
+                if tunable.write(value).is_err() {
+                    warn!("sysctl: invalid boot value {} for {}", value, path);
+                }
+            }
+            None => {
+                table.presets.insert(path, String::from(value));
+            }
+        }
+    }
+}
+
+/// 读取 path 处的参数
+pub fn read_sysctl(path: &str) -> Option<String> {
+    SYSCTL_TABLE.lock().tunables.get(path).map(|tunable| tunable.read())
+}
+
+/// 设置 path 处的参数。不存在时返回 ENOENT
+pub fn write_sysctl(path: &str, value: &str) -> Result<(), ErrorNo> {
+    let tunable = *SYSCTL_TABLE.lock().tunables.get(path).ok_or(ErrorNo::ENOENT)?;
+    tunable.write(value)
+}
+
+/// 所有已注册的参数的路径，按字母序排列
+pub fn sysctl_paths() -> Vec<&'static str> {
+    SYSCTL_TABLE.lock().tunables.keys().copied().collect()
+}
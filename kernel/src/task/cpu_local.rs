@@ -2,23 +2,26 @@
 
 use super::{
     TaskContext, TaskControlBlock, TaskStatus, __move_to_context, __switch,
-    fetch_task_from_scheduler, push_task_to_scheduler, ORIGIN_USER_PROC,
+    fetch_task_from_scheduler, global_logoff_task, push_task_to_scheduler, ORIGIN_USER_PROC,
+    cpu_stat::{record_task_run, set_hart_online, update_load_avg},
 };
 use crate::{
     arch::get_cpu_id,
     constants::{CPU_ID_LIMIT, IS_TEST_ENV, NO_PARENT, USER_STACK_RED_ZONE},
     error::{OSError, OSResult},
-    file::show_testcase_result,
+    file::{socket::poll_if_due, show_testcase_result, wake_expired_timers},
+    ipc::exit_sem,
     memory::{enable_kernel_page_table, PTEFlags, VirtAddr},
     signal::{
         global_logoff_signals, send_signal, SigActionDefault, SigActionFlags, SigInfo, SignalNo,
         SignalUserContext, SIG_IGN,
     },
     syscall::{clear_loop_checker, check_thread_blocked},
+    trap::handle_pending_interrupts,
 };
 use alloc::{sync::Arc, vec::Vec};
 use core::mem::size_of;
-use lock::Mutex;
+use lock::{locks_held, Mutex};
 
 /// 每个核当前正在运行的任务及上下文信息。
 /// 注意，如果一个核没有运行在任何任务上，那么它会回到 idle_task_cx 的上下文，而这里的栈就是启动时的栈。
@@ -67,7 +70,13 @@ lazy_static::lazy_static! {
 /// 开始执行用户程序
 pub fn run_tasks() -> ! {
     let cpu_id = get_cpu_id();
+    set_hart_online(cpu_id);
     loop {
+        update_load_avg();
+        wake_expired_timers();
+        poll_if_due();
+        // 所有线程都在等设备时，设备的中断只能在这里处理
+        handle_pending_interrupts();
         if let Some(task) = fetch_task_from_scheduler() {
             let tid = task.get_tid_num();
             // 如果线程正在等待，则不进入
@@ -87,8 +96,12 @@ pub fn run_tasks() -> ! {
             unsafe {
                 task.vm.lock().activate();
             }
-            // 标记内核态进入任务的时间
-            task.time.lock().switch_into_task();
+            // 标记内核态进入任务的时间，并记下任务已经用过的时间，切出时算出这次运行的时间
+            let (utime, stime) = {
+                let mut time = task.time.lock();
+                time.switch_into_task();
+                time.output_raw()
+            };
             cpu_local.current = Some(task);
             // 清空计数器
             clear_loop_checker();
@@ -102,7 +115,13 @@ pub fn run_tasks() -> ! {
             // 在其中会修改 current.task_status 和 exit_code，但任务本身还在被当前 CPU 占用，需要下面再将其插入队列或
             let mut cpu_local = CPU_CONTEXTS[cpu_id].lock();
             // 标记内核态退出任务的时间
-            cpu_local.current().unwrap().time.lock().switch_out_task();
+            let (new_utime, new_stime) = {
+                let mut time = cpu_local.current.as_ref().unwrap().time.lock();
+                time.switch_out_task();
+                time.output_raw()
+            };
+            // exec 时会清空任务的时间，所以可能比切入时小
+            record_task_run(cpu_id, new_utime.saturating_sub(utime), new_stime.saturating_sub(stime));
             // 切换回只有内核的页表。在此之后就不能再访问该任务用户空间的内容
             enable_kernel_page_table();
             // 此时已切回空闲任务
@@ -159,6 +178,18 @@ pub fn suspend_current_task() {
     }
 }
 
+/// 阻塞的系统调用每次等待时调用。
+/// 如果当前线程收到了未被屏蔽的信号，说明等待被打断，直接返回 false；否则暂停当前用户程序，之后返回 true
+pub fn suspend_current_task_interruptible() -> bool {
+    let interrupted = get_current_task().unwrap().signal_receivers.lock().has_pending_signal();
+    if interrupted {
+        false
+    } else {
+        suspend_current_task();
+        true
+    }
+}
+
 /// 终止当前用户程序，回到 idle 状态
 pub fn exit_current_task(exit_code: i32) {
     let cpu_id = get_cpu_id();
@@ -262,6 +293,11 @@ fn handle_zombie_task(_cpu_local: &mut CpuLocal, task: Arc<TaskControlBlock>) {
     }
     // 通知全局表将 signals 删除
     global_logoff_signals(task.tid.0);
+    // 主线程退出时，进程也从全局表中删除，并撤销进程对 SysV 信号量的修改
+    if task.pid == task.tid.0 {
+        global_logoff_task(task.pid);
+        exit_sem(task.pid);
+    }
     // 释放用户段占用的物理页面
     // 如果这里不释放，等僵尸进程被回收时 MemorySet 被 Drop，也可以释放这些页面
 
@@ -288,6 +324,16 @@ pub fn get_current_task() -> Option<Arc<TaskControlBlock>> {
     Some(CPU_CONTEXTS[get_cpu_id()].lock().current.as_ref()?.clone())
 }
 
+/// 等待设备时能否让出 CPU：需要在用户线程中，且当前核没有持有任何锁。
+/// 否则切换过去的线程可能会一直等这个锁，而拿着锁的线程再也不会被调度。
+/// 在调度器中(拿着 CpuLocal 的锁，比如释放退出的线程的文件时)返回 false，不会死锁
+pub fn can_suspend_current_task() -> bool {
+    locks_held() == 0
+        && CPU_CONTEXTS[get_cpu_id()]
+            .try_lock()
+            .map_or(false, |cpu_local| cpu_local.current.is_some())
+}
+
 ///从内核态进入用户态时统计时间
 pub fn timer_kernel_to_user() {
     get_current_task()
@@ -313,7 +359,11 @@ pub fn handle_signals() {
     // 如果其他线程正在向这里发送信号，则当前线程在此被阻塞
     let mut sig_inner = task.signal_receivers.lock();
     let handler = task.signal_handlers.lock();
-    if let Some(signum) = sig_inner.get_one_signal() {
+    // pselect 等系统调用的临时掩码要用来选出打断它的信号，之后再恢复原来的掩码。
+    // 这样用户的信号处理函数和 ucontext 中看到的都是原来的掩码
+    let signum = sig_inner.get_one_signal();
+    sig_inner.restore_saved_mask();
+    if let Some(signum) = signum {
         let signal = SignalNo::from(signum);
         //println!("tid {} handling signal: {:#?}", task.get_tid_num(), signal);
         // 保存成功说明当前没有在处理其他信号
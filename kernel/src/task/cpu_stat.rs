@@ -0,0 +1,113 @@
+//! 每个核的调度统计和系统的平均负载
+//!
+//! - 每个核运行任务时的用户态、内核态时间和切换进入任务的次数，在 run_tasks 中切出任务时累加。
+//!   空闲时间是启动以来的时间减去这两者
+//! - 平均负载和 Linux 一样，每 LOAD_FREQ_US 对正在运行和等待运行的任务数做一次指数平均。
+//!   内核没有开时钟中断，所以在调度和读取时检查是否到了采样时间，错过的采样点用当前的任务数补上。
+//!   等待事件的任务也在就绪队列里轮询，所以负载会比 Linux 上偏高
+//!
+//! /proc/stat、/proc/loadavg、/proc/uptime 和 sys_sysinfo 都从这里读取
+
+use super::cpu_local::CPU_CONTEXTS;
+use super::scheduler::GLOBAL_TASK_SCHEDULER;
+use crate::constants::CPU_ID_LIMIT;
+use crate::timer::get_time_us;
+use alloc::vec::Vec;
+use lock::Mutex;
+
+/// 负载的定点数表示中小数部分的位数
+pub const FSHIFT: usize = 11;
+/// 定点数的 1.0
+pub const FIXED_1: usize = 1 << FSHIFT;
+/// 两次采样的间隔，和 Linux 一样是 5 秒
+const LOAD_FREQ_US: usize = 5_000_000;
+/// 1、5、15 分钟平均的衰减系数，即 FIXED_1 / exp(5s / 1min) 等
+const EXP: [usize; 3] = [1884, 2014, 2037];
+
+/// 一个核的调度统计
+#[derive(Clone, Copy)]
+pub struct HartStat {
+    /// 这个核是否在运行用户程序
+    pub online: bool,
+    /// 运行任务的用户态时间
+    pub user_us: usize,
+    /// 运行任务的内核态时间
+    pub system_us: usize,
+    /// 切换进入任务的次数
+    pub switches: usize,
+}
+
+impl HartStat {
+    const EMPTY: Self = Self {
+        online: false,
+        user_us: 0,
+        system_us: 0,
+        switches: 0,
+    };
+}
+
+/// 所有核的统计，下标为 cpu_id
+static HART_STATS: Mutex<[HartStat; CPU_ID_LIMIT]> = Mutex::new([HartStat::EMPTY; CPU_ID_LIMIT]);
+
+/// 平均负载
+struct LoadAvg {
+    /// 1、5、15 分钟的平均负载，是 FSHIFT 位小数的定点数
+    avenrun: [usize; 3],
+    /// 下一次采样的时间
+    next_sample_us: usize,
+}
+
+static LOAD_AVG: Mutex<LoadAvg> = Mutex::new(LoadAvg {
+    avenrun: [0; 3],
+    next_sample_us: LOAD_FREQ_US,
+});
+
+/// 标记核 cpu_id 开始运行用户程序
+pub fn set_hart_online(cpu_id: usize) {
+    HART_STATS.lock()[cpu_id].online = true;
+}
+
+/// 核 cpu_id 运行了一次任务，其中用户态和内核态分别用了 user_us 和 system_us
+pub fn record_task_run(cpu_id: usize, user_us: usize, system_us: usize) {
+    let mut stats = HART_STATS.lock();
+    let stat = &mut stats[cpu_id];
+    stat.user_us += user_us;
+    stat.system_us += system_us;
+    stat.switches += 1;
+}
+
+/// 所有在运行用户程序的核的统计，按 cpu_id 排列
+pub fn hart_stats() -> Vec<HartStat> {
+    HART_STATS.lock().iter().filter(|stat| stat.online).copied().collect()
+}
+
+/// 正在运行和等待运行的任务数
+pub fn nr_running() -> usize {
+    let running = CPU_CONTEXTS.iter().filter(|cpu| cpu.lock().current().is_some()).count();
+    GLOBAL_TASK_SCHEDULER.lock().size() + running
+}
+
+/// 到了采样时间时更新平均负载。其他核正在更新时直接返回
+pub fn update_load_avg() {
+    let now = get_time_us();
+    let mut load = match LOAD_AVG.try_lock() {
+        Some(load) => load,
+        None => return,
+    };
+    if now < load.next_sample_us {
+        return;
+    }
+    let active = nr_running() * FIXED_1;
+    while load.next_sample_us <= now {
+        for i in 0..3 {
+            load.avenrun[i] = (load.avenrun[i] * EXP[i] + active * (FIXED_1 - EXP[i]) + FIXED_1 / 2) >> FSHIFT;
+        }
+        load.next_sample_us += LOAD_FREQ_US;
+    }
+}
+
+/// 1、5、15 分钟的平均负载，是 FSHIFT 位小数的定点数
+pub fn load_avg() -> [usize; 3] {
+    update_load_avg();
+    LOAD_AVG.lock().avenrun
+}
@@ -7,7 +7,10 @@
 mod clone_flags;
 mod context;
 mod cpu_local;
+mod cpu_stat;
 mod kernel_stack;
+mod pid2task;
+mod resource_limit;
 mod scheduler;
 mod switch;
 mod task;
@@ -19,11 +22,20 @@ use switch::{__move_to_context, __switch};
 
 pub use clone_flags::CloneFlags;
 pub use context::TaskContext;
+pub use cpu_stat::{hart_stats, load_avg, nr_running, FIXED_1, FSHIFT};
 pub use cpu_local::{
-    exec_new_task, exit_current_task, get_current_task, handle_signals, handle_user_page_fault,
-    run_tasks, signal_return, suspend_current_task, timer_kernel_to_user, timer_user_to_kernel,
+    can_suspend_current_task, exec_new_task, exit_current_task, get_current_task, handle_signals, handle_user_page_fault,
+    run_tasks, signal_return, suspend_current_task, suspend_current_task_interruptible,
+    timer_kernel_to_user, timer_user_to_kernel,
 };
 pub use kernel_stack::KernelStack;
+pub use pid2task::{
+    get_all_pids, get_process_count, get_task_from_pid, global_logoff_task, global_register_task,
+};
+pub use resource_limit::{
+    RLimit, ResourceLimits, RLIMIT_AS, RLIMIT_DATA, RLIMIT_FSIZE, RLIMIT_NOFILE, RLIMIT_NPROC,
+    RLIMIT_STACK, RLIM_INFINITY,
+};
 pub use scheduler::Scheduler;
 pub use scheduler::{fetch_task_from_scheduler, push_task_to_scheduler};
 pub use task::{TaskControlBlock, TaskControlBlockInner, TaskStatus};
@@ -32,7 +44,11 @@ pub use time_stat::{ITimerVal, TimeStat};
 lazy_static::lazy_static! {
     /// 第一个用户程序
     /// 任务调度器启动时会自动在队列中插入它作为第一个用户程序
-    pub static ref ORIGIN_USER_PROC: Arc<TaskControlBlock> = Arc::new(
-        TaskControlBlock::from_app_name(ROOT_DIR, 0, vec![ORIGIN_USER_PROC_NAME.into()]).unwrap()
-    );
+    pub static ref ORIGIN_USER_PROC: Arc<TaskControlBlock> = {
+        let task = Arc::new(
+            TaskControlBlock::from_app_name(ROOT_DIR, 0, vec![ORIGIN_USER_PROC_NAME.into()]).unwrap()
+        );
+        global_register_task(&task);
+        task
+    };
 }
@@ -0,0 +1,39 @@
+//! 一张全局的表，从 pid 映射到对应进程的主线程
+//!
+//! 表中只保存 Weak 指针，不影响 TCB 本身的回收
+
+use super::TaskControlBlock;
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use lock::Mutex;
+
+/// 从 pid 获取进程的主线程
+static PID2TASK: Mutex<BTreeMap<usize, Weak<TaskControlBlock>>> = Mutex::new(BTreeMap::new());
+
+/// 所有进程(而不是线程)创建时均需要加入表
+pub fn global_register_task(task: &Arc<TaskControlBlock>) {
+    PID2TASK.lock().insert(task.pid, Arc::downgrade(task)).take();
+}
+
+/// 进程的主线程退出时需要从表中删除
+pub fn global_logoff_task(pid: usize) {
+    PID2TASK.lock().remove(&pid).take();
+}
+
+/// 获取 pid 对应进程的主线程。这个函数会复制一个 Arc
+pub fn get_task_from_pid(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    PID2TASK.lock().get(&pid).and_then(|task| task.upgrade())
+}
+
+/// 当前存在的进程数
+pub fn get_process_count() -> usize {
+    PID2TASK.lock().len()
+}
+
+/// 当前存在的所有进程的 pid，从小到大排列
+pub fn get_all_pids() -> Vec<usize> {
+    PID2TASK.lock().keys().copied().collect()
+}
@@ -0,0 +1,108 @@
+//! 进程的资源限制，即 getrlimit / setrlimit / prlimit64 操作的对象
+//!
+//! 每个进程有一张资源限制表，同一进程的线程共享它。fork 时复制，exec 时保留。
+//! 表本身只负责保存和检查软/硬上限，具体的限制在各个模块中实现：
+//! - RLIMIT_CPU 由 `TimeStat` 检查，超过软上限发送 SIGXCPU，超过硬上限发送 SIGKILL
+//! - RLIMIT_FSIZE 由 `syscall/fs.rs` 中会让文件变大的 syscall(write / writev / ftruncate / sendfile)检查，超过时发送 SIGXFSZ
+//! - RLIMIT_DATA / RLIMIT_AS 由 `sys_brk` / `sys_mmap` 检查
+//! - RLIMIT_STACK 会同步到 `MemorySet` 中，限制用户栈的增长
+//! - RLIMIT_NOFILE 会同步到 `FdManager` 中
+//! - RLIMIT_NPROC 由 `sys_clone` 检查
+
+use crate::constants::{FD_LIMIT_HARD, TID_LIMIT, USER_STACK_LIMIT_DEFAULT};
+use crate::file::FD_LIMIT_ORIGIN;
+use crate::syscall::ErrorNo;
+
+/// 资源限制的一项，同时也是 sys_prlimit64 使用的数组
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RLimit {
+    /// 软上限
+    pub rlim_cur: u64,
+    /// 硬上限
+    pub rlim_max: u64,
+}
+
+/// 表示没有限制
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+// sys_prlimit64 使用的选项
+/// 进程占用的 CPU 时间(秒)
+pub const RLIMIT_CPU: i32 = 0;
+/// 可以写的文件大小
+pub const RLIMIT_FSIZE: i32 = 1;
+/// 数据段(堆和可写的私有映射)的最大大小
+pub const RLIMIT_DATA: i32 = 2;
+/// 用户栈大小
+pub const RLIMIT_STACK: i32 = 3;
+/// core dump 文件大小
+pub const RLIMIT_CORE: i32 = 4;
+/// 用户可以拥有的进程数
+pub const RLIMIT_NPROC: i32 = 6;
+/// 可以打开的 fd 数
+pub const RLIMIT_NOFILE: i32 = 7;
+/// 可以锁定在内存中的大小
+pub const RLIMIT_MEMLOCK: i32 = 8;
+/// 用户地址空间的最大大小
+pub const RLIMIT_AS: i32 = 9;
+/// 资源限制的种类数
+pub const RLIM_NLIMITS: usize = 16;
+
+/// 一个进程的资源限制表
+#[derive(Clone)]
+pub struct ResourceLimits {
+    limits: [RLimit; RLIM_NLIMITS],
+}
+
+impl ResourceLimits {
+    /// 初始进程的资源限制，默认值参考 Linux
+    pub fn new() -> Self {
+        let mut limits = [RLimit {
+            rlim_cur: RLIM_INFINITY,
+            rlim_max: RLIM_INFINITY,
+        }; RLIM_NLIMITS];
+        limits[RLIMIT_STACK as usize].rlim_cur = USER_STACK_LIMIT_DEFAULT as u64;
+        limits[RLIMIT_CORE as usize].rlim_cur = 0;
+        limits[RLIMIT_NPROC as usize] = RLimit {
+            rlim_cur: TID_LIMIT as u64,
+            rlim_max: TID_LIMIT as u64,
+        };
+        limits[RLIMIT_NOFILE as usize] = RLimit {
+            rlim_cur: FD_LIMIT_ORIGIN.get() as u64,
+            rlim_max: FD_LIMIT_HARD as u64,
+        };
+        limits[RLIMIT_MEMLOCK as usize] = RLimit {
+            rlim_cur: 0x80_0000, // 8 MB
+            rlim_max: 0x80_0000,
+        };
+        Self { limits }
+    }
+
+    /// 获取某一项资源限制，资源编号不合法时返回 None
+    pub fn get(&self, resource: i32) -> Option<RLimit> {
+        self.limits.get(resource as usize).copied()
+    }
+
+    /// 获取某一项资源的软上限。不合法的资源编号视为没有限制
+    pub fn get_cur(&self, resource: i32) -> u64 {
+        self.get(resource).map_or(RLIM_INFINITY, |limit| limit.rlim_cur)
+    }
+
+    /// 设置某一项资源限制，返回是否成功:
+    /// - 资源编号不合法或软上限大于硬上限时，返回 EINVAL
+    /// - 没有特权(privileged)时提高硬上限，或者 NOFILE 超过了内核支持的数量时，返回 EPERM
+    pub fn set(&mut self, resource: i32, new_limit: RLimit, privileged: bool) -> Result<(), ErrorNo> {
+        let old_limit = self.get(resource).ok_or(ErrorNo::EINVAL)?;
+        if new_limit.rlim_cur > new_limit.rlim_max {
+            return Err(ErrorNo::EINVAL);
+        }
+        if new_limit.rlim_max > old_limit.rlim_max && !privileged {
+            return Err(ErrorNo::EPERM);
+        }
+        if resource == RLIMIT_NOFILE && new_limit.rlim_max > FD_LIMIT_HARD as u64 {
+            return Err(ErrorNo::EPERM);
+        }
+        self.limits[resource as usize] = new_limit;
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use super::{TaskControlBlock, ORIGIN_USER_PROC};
-use crate::{arch::get_cpu_id, constants::IS_TEST_ENV, file::load_next_testcase};
+use crate::{arch::get_cpu_id, constants::IS_TEST_ENV, drivers::BLOCK_CACHE, file::load_next_testcase};
 use alloc::{collections::VecDeque, sync::Arc};
 use lock::Mutex;
 
@@ -60,6 +60,10 @@ pub fn fetch_task_from_scheduler() -> Option<Arc<TaskControlBlock>> {
             if let Some(new_tcb) = load_next_testcase() {
                 return Some(new_tcb);
             }
+            // 测例都执行完了，把块缓存写回磁盘
+            if BLOCK_CACHE.sync().is_err() {
+                warn!("failed to write back the block cache");
+            }
             info!("[cpu {}] is idle now", get_cpu_id());
             loop {}
         }
@@ -3,13 +3,16 @@
 
 //#![deny(missing_docs)]
 
-use super::{CloneFlags, KernelStack, TaskContext, TimeStat};
+use super::{
+    global_register_task, CloneFlags, KernelStack, ResourceLimits, TaskContext, TimeStat,
+    RLIMIT_AS, RLIMIT_DATA,
+};
 use crate::{
     arch::get_cpu_id,
-    constants::{NO_PARENT, USER_STACK_OFFSET},
-    file::{check_file_exists, FdManager, BackEndFile},
+    constants::NO_PARENT,
+    file::{absolute_path, check_file_exists, FdManager, BackEndFile},
     loaders::parse_user_app,
-    memory::{new_memory_set_for_task, phys_to_virt, MemorySet, PTEFlags, Tid, VirtAddr},
+    memory::{new_memory_set_for_task, phys_to_virt, MemorySet, PTEFlags, PmAreaShared, Tid, VirtAddr},
     signal::{global_register_signals, SignalHandlers, SignalReceivers, SignalUserContext},
     trap::TrapContext,
 };
@@ -18,6 +21,7 @@ use alloc::{
     sync::{Arc, Weak},
     vec::Vec,
 };
+use core::sync::atomic::AtomicUsize;
 use lock::Mutex;
 
 /// 任务控制块，包含一个用户程序的所有状态信息，但不包括与调度有关的信息。
@@ -47,8 +51,13 @@ pub struct TaskControlBlock {
     pub vm: Arc<Mutex<MemorySet>>,
     /// 管理进程的所有文件描述符
     pub fd_manager: Arc<Mutex<FdManager>>,
+    /// 进程的资源限制。同一进程的线程共享，fork 时复制
+    pub rlimits: Arc<Mutex<ResourceLimits>>,
     /// 任务的运行时间信息
     pub time: Mutex<TimeStat>,
+    /// 正在执行的程序的绝对路径，如 "/bin/busybox"，用于 /proc/<pid>/exe。
+    /// 不放在 inner 里，因为 exec 时会拿着 inner 的锁打开新程序，而打开的可能就是 /proc/self/exe
+    pub exe: Mutex<String>,
     /// 任务的状态信息
     pub inner: Arc<Mutex<TaskControlBlockInner>>,
 }
@@ -64,7 +73,7 @@ pub struct TaskControlBlockInner {
     /// - 又因为它可能会在父进程结束时被修改为初始进程，所以是可变的。
     pub ppid: usize,
     /// 用户堆的堆顶。
-    /// 用户堆和用户栈共用空间，反向增长，即从 USER_STACK_OFFSET 开始往上增加。
+    /// 用户堆从 ELF 段之后开始往上增加，和向下增长的用户栈分开。
     /// 本来不应该由内存记录的，但 brk() 系统调用要用
     pub user_heap_top: usize,
     /// 任务执行状态
@@ -123,6 +132,7 @@ impl TaskControlBlock {
         parse_user_app(app_dir, app_name, &mut vm, args)
             .map(|(user_entry, user_stack)| {
                 //println!("user MemorySet {:#x?}", vm);
+                let heap_start = vm.get_heap_start();
                 // 初始化内核栈，它包含关于进入用户程序的所有信息
                 let kernel_stack = KernelStack::new().unwrap();
                 //kernel_stack.print_info();
@@ -134,6 +144,7 @@ impl TaskControlBlock {
                 let signal_handlers = Arc::new(Mutex::new(SignalHandlers::new()));
                 let signal_receivers = Arc::new(Mutex::new(SignalReceivers::new()));
                 global_register_signals(tid.0, signal_receivers.clone());
+                let rlimits = Arc::new(Mutex::new(ResourceLimits::new()));
                 //println!("tid = {}", tid.0);
                 TaskControlBlock {
                     kernel_stack: kernel_stack,
@@ -144,11 +155,13 @@ impl TaskControlBlock {
                     signal_receivers: signal_receivers,
                     vm: Arc::new(Mutex::new(vm)),
                     fd_manager: Arc::new(Mutex::new(FdManager::new(0))), // 初始 umask 设为 0
-                    time: Mutex::new(TimeStat::new(tid_raw)),
+                    rlimits: rlimits.clone(),
+                    time: Mutex::new(TimeStat::new(tid_raw, rlimits, Arc::new(AtomicUsize::new(0)))),
+                    exe: Mutex::new(absolute_path(app_dir, app_name).unwrap_or_default()),
                     inner: Arc::new(Mutex::new(TaskControlBlockInner {
                         dir: String::from(app_dir),
                         ppid: ppid,
-                        user_heap_top: USER_STACK_OFFSET,
+                        user_heap_top: heap_start,
                         task_cx: TaskContext::goto_restore(stack_top),
                         task_status: TaskStatus::Ready,
                         parent: None,
@@ -204,6 +217,12 @@ impl TaskControlBlock {
             // 如果不共享，也要复制整个模块的值，只是不一起更新
             Arc::new(Mutex::new(self.signal_handlers.lock().clone()))
         };
+        // 同一进程的线程共享资源限制和 CPU 时间统计，新进程则复制一份限制，时间从 0 开始
+        let (rlimits, process_cpu_us) = if flags.contains(CloneFlags::CLONE_THREAD) {
+            (self.rlimits.clone(), self.time.lock().process_cpu_time())
+        } else {
+            (Arc::new(Mutex::new(self.rlimits.lock().clone())), Arc::new(AtomicUsize::new(0)))
+        };
         let tid = Tid::new().unwrap();
         let tid_raw = tid.0;
         let pid = if flags.contains(CloneFlags::CLONE_THREAD) {
@@ -287,12 +306,15 @@ impl TaskControlBlock {
             signal_receivers: signal_receivers,
             vm: vm,
             fd_manager: fd_manager,
-            time: Mutex::new(TimeStat::new(tid_raw)), // fork 出的任务不继承时间
+            rlimits: rlimits.clone(),
+            time: Mutex::new(TimeStat::new(tid_raw, rlimits, process_cpu_us)), // fork 出的任务不继承时间
+            exe: Mutex::new(self.exe.lock().clone()),
             inner: {
                 Arc::new(Mutex::new(TaskControlBlockInner {
                     dir: dir,
                     ppid: ppid,
-                    user_heap_top: USER_STACK_OFFSET,
+                    // 子任务的地址空间中复制或共享了父任务的堆
+                    user_heap_top: inner.user_heap_top,
                     task_cx: TaskContext::goto_restore(stack_top),
                     task_status: TaskStatus::Ready,
                     parent: Some(Arc::downgrade(self)),
@@ -321,6 +343,9 @@ impl TaskControlBlock {
         if !flags.contains(CloneFlags::CLONE_PARENT) {
             inner.children.push(new_tcb.clone());
         }
+        if !flags.contains(CloneFlags::CLONE_THREAD) {
+            global_register_task(&new_tcb);
+        }
         //info!("end clone");
         new_tcb
     }
@@ -338,8 +363,6 @@ impl TaskControlBlock {
         if !check_file_exists(inner.dir.as_str(), app_name) {
             return false;
         }
-        // 清空用户堆
-        inner.user_heap_top = USER_STACK_OFFSET;
         // 清空 MemorySet 中用户段的地址
         self.vm.lock().clear_user_and_save_kernel();
         // 清空信号模块
@@ -370,6 +393,8 @@ impl TaskControlBlock {
             .map(|(user_entry, user_stack)| {
                 // 修改完 MemorySet 映射后要 flush 一次
                 self_vm.flush_tlb();
+                // 用户堆从新程序的 ELF 段之后重新开始
+                inner.user_heap_top = self_vm.get_heap_start();
                 //println!("user vm {:#x?}", inner.vm);
                 // argc 和 argv 存在用户栈顶，而按用户库里的实现是需要放在 a0 和 a1 寄存器中，所以这里手动取出
                 let argc = unsafe { *(user_stack as *const usize) };
@@ -382,6 +407,7 @@ impl TaskControlBlock {
                             user_entry, user_stack, argc, argv,
                         ));
                 inner.task_cx = TaskContext::goto_restore(stack_top);
+                *self.exe.lock() = absolute_path(dir.as_str(), app_name).unwrap_or_default();
 
                 let trap_context = unsafe {*self.kernel_stack.get_first_context() };
                 debug!("sp = {:x}, entry = {:x}, sstatus = {:x}", trap_context.x[2], trap_context.sepc, trap_context.sstatus.bits());
@@ -407,6 +433,15 @@ impl TaskControlBlock {
             .push_with_backend(start, end, flags, backend, anywhere)
             .ok()
     }
+    /// 映射一段共享内存，如 MAP_SHARED 的匿名映射或者 /dev/shm 中的文件。
+    /// 不指定 anywhere 时，会先 unmap 掉和 [start, start + pma 大小) 相交的区间
+    pub fn mmap_shared(&self, start: VirtAddr, end: VirtAddr, flags: PTEFlags, pma: PmAreaShared, anywhere: bool) -> Option<usize> {
+        let mut vm = self.vm.lock();
+        if !anywhere {
+            vm.modify_overlap_areas(start, end).ok()?;
+        }
+        vm.push_shared(start, flags, pma, "from mmap", anywhere).ok()
+    }
     /// 取消一段内存地址映射
     pub fn munmap(&self, start: VirtAddr, end: VirtAddr) -> bool {
         self.vm.lock().modify_overlap_areas(start, end).is_ok()
@@ -456,16 +491,24 @@ impl TaskControlBlock {
             ppid
         }
     }
+    /// 检查地址空间再增加 len 字节后，是否仍在 RLIMIT_AS 内。
+    /// 如果 is_data，则这段地址还计入数据段，需要同时满足 RLIMIT_DATA
+    pub fn check_vm_growth(&self, len: usize, is_data: bool) -> bool {
+        let rlimits = self.rlimits.lock();
+        let vm = self.vm.lock();
+        (vm.user_size() + len) as u64 <= rlimits.get_cur(RLIMIT_AS)
+            && (!is_data || (vm.data_size() + len) as u64 <= rlimits.get_cur(RLIMIT_DATA))
+    }
     /// 获取用户堆顶地址
     pub fn get_user_heap_top(&self) -> usize {
         self.inner.lock().user_heap_top
     }
     /// 重新设置堆顶地址，如成功则返回设置后的堆顶地址，否则保持不变，并返回之前的堆顶地址。
-    /// 新地址需要在用户栈内，并且不能碰到目前的栈
+    /// 新地址不能低于堆的起始位置，且扩展的部分不能碰到其他内存段或者栈的预留空间
     pub fn set_user_heap_top(&self, new_top: usize) -> usize {
-        let user_sp = unsafe { (*self.kernel_stack.get_first_context()).get_sp() };
         let mut inner = self.inner.lock();
-        if new_top >= USER_STACK_OFFSET && new_top < user_sp {
+        let mut vm = self.vm.lock();
+        if new_top >= vm.get_heap_start() && vm.resize_heap(inner.user_heap_top, new_top).is_ok() {
             inner.user_heap_top = new_top;
             new_top
         } else {
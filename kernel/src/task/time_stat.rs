@@ -9,8 +9,15 @@
 //! > 如果在 trap 的过程中，通过其他方式退出了进程，那么内核时间统计会在 `run_tasks()` 切出时中断。
 //! > 这样统计的时间仍然是对的
 
+use super::resource_limit::{ResourceLimits, RLIMIT_CPU};
 use crate::signal::{send_signal, SignalNo};
 use crate::timer::{get_time_us, TimeVal};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lock::Mutex;
+
+/// 每秒的微秒数
+const USEC_PER_SEC: usize = 1_000_000;
 
 /// 进程的时间统计，基于 lmbench 需要，主要用于 sys_getrusage
 pub struct TimeStat {
@@ -37,6 +44,12 @@ pub struct TimeStat {
     ///
     /// 根据 timer_type 的规则不断减少，当归零时触发信号
     timer_remained_us: usize,
+    /// 所在进程的资源限制，用于检查 RLIMIT_CPU
+    rlimits: Arc<Mutex<ResourceLimits>>,
+    /// 所在进程所有线程的用户态和内核态时间之和，同一进程的线程共享。RLIMIT_CPU 限制的是这个值
+    process_cpu_us: Arc<AtomicUsize>,
+    /// 下一次发送 SIGXCPU 的时间(秒)。超过软上限后每秒发送一次
+    next_xcpu_sec: usize,
 }
 
 numeric_enum_macro::numeric_enum! {
@@ -72,8 +85,8 @@ pub struct ITimerVal {
 }
 
 impl TimeStat {
-    /// 新线程的时间记为 0
-    pub fn new(tid: usize) -> Self {
+    /// 新线程的时间记为 0。process_cpu_us 是所在进程的总时间，新进程传入新的计数
+    pub fn new(tid: usize, rlimits: Arc<Mutex<ResourceLimits>>, process_cpu_us: Arc<AtomicUsize>) -> Self {
         Self {
             tid: tid,
             utime_us: 0,
@@ -84,15 +97,20 @@ impl TimeStat {
             timer_type: TimerType::NONE,
             timer_interval_us: 0,
             timer_remained_us: 0,
+            rlimits: rlimits,
+            process_cpu_us: process_cpu_us,
+            next_xcpu_sec: 0,
         }
     }
-    /// 清空使用的时间，用于
+    /// 清空使用的时间，用于 exec。此时进程中只剩当前线程，所以进程的总时间也清空
     pub fn clear(&mut self) {
+        self.process_cpu_us.store(0, Ordering::Relaxed);
         self.utime_us = 0;
         self.stime_us = 0;
         self.user_tick = 0;
         self.kernel_tick = 0;
         self.start_tick = get_time_us();
+        self.next_xcpu_sec = 0;
     }
     /// 统计时间：从内核进入用户态时调用
     pub fn timer_kernel_to_user(&mut self) {
@@ -102,7 +120,9 @@ impl TimeStat {
             self.update_timer_and_send_signal(delta);
         }
         self.stime_us += delta;
+        self.process_cpu_us.fetch_add(delta, Ordering::Relaxed);
         self.user_tick = now;
+        self.check_cpu_limit();
     }
     /// 统计时间：从用户进入内核态时调用
     pub fn timer_user_to_kernel(&mut self) {
@@ -112,7 +132,9 @@ impl TimeStat {
             self.update_timer_and_send_signal(delta);
         }
         self.utime_us += delta;
+        self.process_cpu_us.fetch_add(delta, Ordering::Relaxed);
         self.kernel_tick = now;
+        self.check_cpu_limit();
     }
     /// 统计时间：(内核态)切换进入当前任务
     pub fn switch_into_task(&mut self) {
@@ -122,15 +144,40 @@ impl TimeStat {
     pub fn switch_out_task(&mut self) {
         let delta = get_time_us() - self.kernel_tick;
         self.stime_us += delta;
+        self.process_cpu_us.fetch_add(delta, Ordering::Relaxed);
         if self.timer_type == TimerType::REAL || self.timer_type == TimerType::PROF {
             self.update_timer_and_send_signal(delta);
         }
+        self.check_cpu_limit();
+    }
+    /// 检查 RLIMIT_CPU：进程所有线程的用户态和内核态时间之和超过软上限时每秒发送一次 SIGXCPU，超过硬上限时发送 SIGKILL
+    /// (**内部需要获取对应线程的 SignalReceivers，注意死锁**)
+    fn check_cpu_limit(&mut self) {
+        let limit = match self.rlimits.lock().get(RLIMIT_CPU) {
+            Some(limit) => limit,
+            None => return,
+        };
+        let used_sec = self.process_cpu_us.load(Ordering::Relaxed) / USEC_PER_SEC;
+        if used_sec as u64 >= limit.rlim_max {
+            send_signal(self.tid, SignalNo::SIGKILL as usize);
+        } else if used_sec as u64 >= limit.rlim_cur && used_sec >= self.next_xcpu_sec {
+            send_signal(self.tid, SignalNo::SIGXCPU as usize);
+            self.next_xcpu_sec = used_sec + 1;
+        }
     }
     /// 以 TimeVal 形式输出统计的用户态和内核态时间
     pub fn output(&self, utime: &mut TimeVal, stime: &mut TimeVal) {
         *utime = self.utime_us.into();
         *stime = self.stime_us.into();
     }
+    /// 所在进程的总时间计数，用于创建同一进程的新线程
+    pub fn process_cpu_time(&self) -> Arc<AtomicUsize> {
+        self.process_cpu_us.clone()
+    }
+    /// 开始运行(或者上次 exec)时的系统时间，单位为微秒
+    pub fn start_time_us(&self) -> usize {
+        self.start_tick
+    }
     /// 输出微秒形式的时间统计，用于调试
     pub fn output_raw(&self) -> (usize, usize) {
         (self.utime_us, self.stime_us)
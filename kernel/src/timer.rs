@@ -14,8 +14,9 @@ const USEC_PER_SEC: usize = 1_000_000;
 pub const USEC_PER_INTERRUPT: usize = USEC_PER_SEC / INTERRUPT_PER_SEC;
 /// 每秒的纳秒数
 pub const NSEC_PER_SEC: usize = 1_000_000_000;
-/// 每个时钟周期需要多少纳秒(取整)
-pub const NSEC_PER_MACHINE_TICKS: usize = NSEC_PER_SEC / CLOCK_FREQ;
+/// CLOCK_REALTIME 的系统时间比开机时间多出的微秒数。
+/// 没有 RTC，系统时间也从开机时的 1970-01-01 算起，所以目前为 0。以系统时间给出的时刻要减去它才是开机以来的时间
+pub const REALTIME_OFFSET_US: usize = 0;
 /// 当 nsec 为这个特殊值时，指示修改时间为现在
 pub const UTIME_NOW: usize = 0x3fffffff;
 /// 当 nsec 为这个特殊值时，指示不修改时间
@@ -26,11 +27,6 @@ pub fn get_time() -> usize {
     time::read()
 }
 
-/// 获取毫秒格式的时间值。注意这不一定代表进程经过的时间值
-pub fn get_time_ms() -> usize {
-    (time::read() * 1000) / CLOCK_FREQ
-}
-
 pub fn get_time_sec() -> usize {
     time::read() / CLOCK_FREQ
 }
@@ -91,10 +87,16 @@ impl TimeSpec {
             } // 设为指定时间
         }
     }
-    /// 获取时钟周期数
-    /// 考虑到 usize 有 64 位，这里应该不会溢出
-    pub fn get_ticks(&self) -> usize {
-        self.tv_sec * CLOCK_FREQ + self.tv_nsec / NSEC_PER_MACHINE_TICKS
+    /// 换算成微秒数
+    pub fn to_us(&self) -> usize {
+        self.tv_sec * USEC_PER_SEC + self.tv_nsec / 1000
+    }
+    /// 从微秒数生成 TimeSpec
+    pub fn from_us(usec: usize) -> Self {
+        Self {
+            tv_sec: usec / USEC_PER_SEC,
+            tv_nsec: usec % USEC_PER_SEC * 1000,
+        }
     }
 }
 
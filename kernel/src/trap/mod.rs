@@ -24,6 +24,7 @@ mod context;
 use crate::{
     arch::get_cpu_id,
     constants::SIGNAL_RETURN_TRAP,
+    drivers::handle_external_interrupt,
     memory::PTEFlags,
     signal::{SignalNo, send_signal},
     syscall::syscall,
@@ -67,6 +68,21 @@ pub fn enable_timer_interrupt() {
     }
 }
 
+/// 打开外部中断。内核态下全局中断仍是关闭的，只有等待设备时和空闲时才短暂打开
+pub fn enable_external_interrupt() {
+    unsafe {
+        sie::set_sext();
+    }
+}
+
+/// 短暂打开全局中断，让已经到来的中断在 trap 中被处理。只能在没有持有锁的地方调用
+pub fn handle_pending_interrupts() {
+    unsafe {
+        sstatus::set_sie();
+        sstatus::clear_sie();
+    }
+}
+
 #[no_mangle]
 /// 内核和用户Trap的共同入口
 ///
@@ -173,6 +189,9 @@ pub fn user_trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
             set_next_trigger();
             suspend_current_task();
         }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            handle_external_interrupt();
+        }
         _ => {
             panic!(
                 "[cpu {}] Unsupported trap {:?}, stval = {:#x}!",
@@ -278,6 +297,11 @@ pub fn kernel_trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
             set_next_trigger();
             //suspend_current_and_run_next();
         }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            // 等待设备的线程打开中断时进入这里，处理完就回到原来的位置
+            handle_external_interrupt();
+            return cx;
+        }
         _ => {
             panic!(
                 "[cpu {}] Unsupported trap {:?}, stval = {:#x}!",